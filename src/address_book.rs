@@ -0,0 +1,258 @@
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+
+use crate::crypto::checksum_address;
+use crate::labels::normalize_address;
+use crate::utils;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum AddressBookError {
+    #[error("The name can't be empty")]
+    EmptyName,
+    #[error("{0} is an address, so it can't be a name")]
+    NameIsAddress(String),
+    #[error("There is already an entry named {0}")]
+    DuplicateName(String),
+    #[error("{0} is not a valid address or its checksum is wrong")]
+    InvalidAddress(String),
+    #[error("There is no entry named {0}")]
+    NotFound(String),
+}
+
+/// A saved recipient
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Contact {
+    pub name: String,
+    /// The checksummed 0x-prefixed address
+    pub address: String,
+    /// What kind of address it is, if the user said
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<ContactTag>,
+    /// The smallest deposit in wei the address accepts, for an exchange deposit address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_deposit: Option<u128>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ContactTag {
+    /// An address an exchange credits deposits to. Exchanges ignore deposits that carry calldata
+    /// or arrive from a contract, and deposits below their minimum, so the funds are lost.
+    ExchangeDeposit,
+}
+
+impl Contact {
+    /// The entry as the address book lists it, with its tag and notes
+    pub fn describe(&self) -> String {
+        let mut line = format!("{}: {}", self.name, self.address);
+        if self.tag == Some(ContactTag::ExchangeDeposit) {
+            match self.min_deposit {
+                Some(min) => line += &format!(" [exchange deposit, at least {} ETH]", utils::wei_to_eth(min)),
+                None => line += " [exchange deposit]",
+            }
+        }
+        if let Some(notes) = &self.notes {
+            line += &format!(" ({})", notes);
+        }
+        line
+    }
+}
+
+/// What a send to a tagged address needs the user to know, or why it is refused
+#[derive(Debug, PartialEq)]
+pub enum DepositCheck {
+    Ok,
+    /// The amount is below the minimum deposit of the entry
+    BelowMinimum { name: String, min_deposit: u128 },
+    /// The transaction carries data, which the exchange would not credit
+    Refused(String),
+}
+
+/// Recipients saved under names, which the recipient prompt accepts in place of an address.
+/// Names are unique ignoring case, and looked up ignoring case.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(transparent)]
+pub struct AddressBook {
+    contacts: Vec<Contact>,
+}
+
+impl AddressBook {
+    pub fn is_empty(&self) -> bool {
+        self.contacts.is_empty()
+    }
+
+    /// The entries in the order they were added
+    pub fn contacts(&self) -> &[Contact] {
+        &self.contacts
+    }
+
+    /// Saves `address` as `name`, and returns the entry as saved
+    pub fn add(&mut self, name: &str, address: &str) -> Result<&Contact, AddressBookError> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(AddressBookError::EmptyName);
+        }
+        // otherwise the prompt couldn't tell the name from the address
+        if normalize_address(name).is_some() {
+            return Err(AddressBookError::NameIsAddress(String::from(name)));
+        }
+        if self.find(name).is_some() {
+            return Err(AddressBookError::DuplicateName(String::from(name)));
+        }
+        let address = address.trim();
+        let normalized = normalize_address(address).ok_or_else(|| AddressBookError::InvalidAddress(String::from(address)))?;
+        let bytes: [u8; 20] = hex::decode(&normalized[2..]).unwrap().try_into().unwrap();
+        self.contacts.push(Contact { name: String::from(name), address: checksum_address(&bytes), tag: None, min_deposit: None, notes: None });
+        Ok(self.contacts.last().unwrap())
+    }
+
+    /// Sets the tag, minimum deposit and notes of the entry named `name`, and returns it
+    pub fn annotate(&mut self, name: &str, tag: Option<ContactTag>, min_deposit: Option<u128>, notes: Option<String>) -> Result<&Contact, AddressBookError> {
+        let contact = self.contacts.iter_mut().find(|c| c.name.eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(|| AddressBookError::NotFound(String::from(name.trim())))?;
+        contact.tag = tag;
+        // only an exchange has a minimum
+        contact.min_deposit = min_deposit.filter(|_| tag == Some(ContactTag::ExchangeDeposit));
+        contact.notes = notes.filter(|n| !n.trim().is_empty());
+        Ok(contact)
+    }
+
+    /// Deletes the entry named `name`, and returns it
+    pub fn remove(&mut self, name: &str) -> Result<Contact, AddressBookError> {
+        let position = self.contacts.iter().position(|c| c.name.eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(|| AddressBookError::NotFound(String::from(name.trim())))?;
+        Ok(self.contacts.remove(position))
+    }
+
+    /// A book with only the entries at `positions` in `contacts`, in their order there
+    pub fn subset(&self, positions: &[usize]) -> AddressBook {
+        let contacts = self.contacts.iter().enumerate()
+            .filter(|(i, _)| positions.contains(i))
+            .map(|(_, c)| c.clone())
+            .collect();
+        AddressBook { contacts }
+    }
+
+    /// The entry named `name`, ignoring case
+    pub fn find(&self, name: &str) -> Option<&Contact> {
+        self.contacts.iter().find(|c| c.name.eq_ignore_ascii_case(name.trim()))
+    }
+
+    /// The first entry for `address`, however its case is written
+    pub fn find_address(&self, address: &[u8; 20]) -> Option<&Contact> {
+        let address = String::from("0x") + &hex::encode(address);
+        self.contacts.iter().find(|c| normalize_address(&c.address).as_ref() == Some(&address))
+    }
+
+    /// Checks a transaction to `to` carrying `data` and `wei_amount` against the entry for `to`,
+    /// if it is an exchange deposit address. An address typed in rather than picked by name is
+    /// checked too.
+    pub fn check_deposit(&self, to: &[u8; 20], data: &[u8], wei_amount: u128) -> DepositCheck {
+        let contact = match self.find_address(to) {
+            Some(c) if c.tag == Some(ContactTag::ExchangeDeposit) => c,
+            _ => return DepositCheck::Ok,
+        };
+        if !data.is_empty() {
+            return DepositCheck::Refused(format!("{} is an exchange deposit address, which doesn't credit transactions carrying data. Send it plain ETH instead.", contact.name));
+        }
+        match contact.min_deposit {
+            Some(min_deposit) if wei_amount < min_deposit => DepositCheck::BelowMinimum { name: contact.name.clone(), min_deposit },
+            _ => DepositCheck::Ok,
+        }
+    }
+}
+
+impl DepositCheck {
+    /// The warning shown on the confirmation screen, if any
+    pub fn warning(&self) -> Option<String> {
+        match self {
+            DepositCheck::BelowMinimum { name, min_deposit } => Some(format!("WARNING: {} only credits deposits of at least {} ETH; a smaller one may be lost",
+                name, utils::wei_to_eth(*min_deposit))),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_add() {
+        let mut book = AddressBook::default();
+        let contact = book.add(" Alice ", "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").unwrap().clone();
+        assert_eq!(contact, Contact { name: String::from("Alice"), address: String::from("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"), tag: None, min_deposit: None, notes: None });
+        assert_eq!(book.find("ALICE"), Some(&contact));
+        assert_eq!(book.find("Bob"), None);
+
+        assert_eq!(book.add("alice", "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359").unwrap_err(), AddressBookError::DuplicateName(String::from("alice")));
+        assert_eq!(book.add("Bob", "0xfb6916095CA1df60bB79Ce92cE3Ea74c37c5d359").unwrap_err(),
+            AddressBookError::InvalidAddress(String::from("0xfb6916095CA1df60bB79Ce92cE3Ea74c37c5d359")));
+        assert_eq!(book.add(" ", "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359").unwrap_err(), AddressBookError::EmptyName);
+        assert!(matches!(book.add("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed", "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"),
+            Err(AddressBookError::NameIsAddress(_))));
+        assert_eq!(book.contacts().len(), 1);
+    }
+
+    #[test]
+    fn test_subset() {
+        let mut book = AddressBook::default();
+        book.add("Alice", "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").unwrap();
+        book.add("Bob", "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359").unwrap();
+        assert_eq!(book.subset(&[1, 5]).contacts().iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), ["Bob"]);
+        assert!(book.subset(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut book = AddressBook::default();
+        book.add("Alice", "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").unwrap();
+        book.add("Bob", "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359").unwrap();
+        assert_eq!(book.remove("alice").unwrap().name, "Alice");
+        assert_eq!(book.remove("alice").unwrap_err(), AddressBookError::NotFound(String::from("alice")));
+        assert_eq!(book.contacts().iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), ["Bob"]);
+
+        // stored as a plain list
+        let json = serde_json::to_string(&book).unwrap();
+        assert_eq!(json, r#"[{"name":"Bob","address":"0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359"}]"#);
+        assert_eq!(serde_json::from_str::<AddressBook>(&json).unwrap(), book);
+    }
+
+    #[test]
+    fn test_check_deposit() {
+        let mut book = AddressBook::default();
+        book.add("Exchange", "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").unwrap();
+        book.add("Bob", "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359").unwrap();
+        let exchange: [u8; 20] = hex::decode("5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").unwrap().try_into().unwrap();
+        let bob: [u8; 20] = hex::decode("fb6916095ca1df60bb79ce92ce3ea74c37c5d359").unwrap().try_into().unwrap();
+        // untagged, anything goes
+        assert_eq!(book.check_deposit(&exchange, &[0xa9], 1), DepositCheck::Ok);
+
+        let contact = book.annotate("exchange", Some(ContactTag::ExchangeDeposit), Some(10_000_000_000_000_000), Some(String::from("memo not needed"))).unwrap();
+        assert_eq!(contact.describe(), "Exchange: 0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed [exchange deposit, at least 0.01 ETH] (memo not needed)");
+        assert!(matches!(book.check_deposit(&exchange, &[0xa9, 0x05], 20_000_000_000_000_000), DepositCheck::Refused(_)));
+        let below = book.check_deposit(&exchange, &[], 1_000);
+        assert_eq!(below, DepositCheck::BelowMinimum { name: String::from("Exchange"), min_deposit: 10_000_000_000_000_000 });
+        assert!(below.warning().unwrap().contains("at least 0.01 ETH"));
+        assert_eq!(book.check_deposit(&exchange, &[], 10_000_000_000_000_000), DepositCheck::Ok);
+        assert_eq!(book.check_deposit(&bob, &[0xa9], 1), DepositCheck::Ok);
+
+        // a minimum only applies to an exchange, and clearing the tag clears it
+        let contact = book.annotate("Exchange", None, Some(5), Some(String::from(" "))).unwrap();
+        assert_eq!((contact.min_deposit, contact.notes.clone()), (None, None));
+        assert_eq!(book.annotate("Carol", None, None, None).unwrap_err(), AddressBookError::NotFound(String::from("Carol")));
+    }
+
+    #[test]
+    fn test_tags_round_trip() {
+        let mut book = AddressBook::default();
+        book.add("Exchange", "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").unwrap();
+        book.annotate("Exchange", Some(ContactTag::ExchangeDeposit), Some(42), Some(String::from("Kraken"))).unwrap();
+        let json = serde_json::to_string(&book).unwrap();
+        assert_eq!(serde_json::from_str::<AddressBook>(&json).unwrap(), book);
+        // entries from before there were tags still load
+        let old: AddressBook = serde_json::from_str(r#"[{"name":"Bob","address":"0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359"}]"#).unwrap();
+        assert_eq!(old.contacts()[0].tag, None);
+    }
+}
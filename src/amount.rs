@@ -0,0 +1,97 @@
+use crate::utils::{eth_to_wei, wei_to_eth};
+
+/// An amount as entered at the send prompt. Relative amounts are resolved against the balance.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AmountEntry {
+    /// An absolute amount in wei, e.g. `0.5`
+    Exact(u128),
+    /// A percentage (1-100) of the balance with the fee taken out of it, e.g. `50%`. `max` is 100%.
+    Percent(u8),
+    /// Everything but the given number of wei (and the fee), e.g. `keep 0.05`
+    Keep(u128),
+}
+
+impl AmountEntry {
+    /// Parses `0.5`, `50%`, `max` or `keep 0.05`
+    pub fn parse(input: &str) -> Result<AmountEntry, String> {
+        let input = input.trim().to_lowercase();
+
+        if input == "max" {
+            return Ok(AmountEntry::Percent(100));
+        }
+        if let Some(percent) = input.strip_suffix('%') {
+            return match percent.trim().parse::<u8>() {
+                Ok(p) if (1..=100).contains(&p) => Ok(AmountEntry::Percent(p)),
+                _ => Err(String::from("Percentages must be a whole number from 1 to 100")),
+            };
+        }
+        if let Some(keep) = input.strip_prefix("keep") {
+            return Ok(AmountEntry::Keep(eth_to_wei(keep)?));
+        }
+        Ok(AmountEntry::Exact(eth_to_wei(&input)?))
+    }
+
+    /// Returns true if the amount depends on the balance
+    pub fn is_relative(&self) -> bool {
+        !matches!(self, AmountEntry::Exact(_))
+    }
+
+    /// Resolves to the absolute wei amount to send given the current balance and the fee.
+    /// Fails if a relative amount would be zero or negative.
+    pub fn resolve(&self, balance: u128, fee: u128) -> Result<u128, String> {
+        let amount = match self {
+            AmountEntry::Exact(wei) => return Ok(*wei),
+            AmountEntry::Percent(percent) => {
+                let portion = balance / 100 * (*percent as u128) + balance % 100 * (*percent as u128) / 100;
+                portion.checked_sub(fee)
+            },
+            AmountEntry::Keep(keep) => balance.checked_sub(*keep).and_then(|rest| rest.checked_sub(fee)),
+        };
+
+        match amount {
+            Some(a) if a > 0 => Ok(a),
+            _ => Err(format!(
+                "Nothing left to send: balance is {} ETH and the fee is ~{} ETH",
+                wei_to_eth(balance), wei_to_eth(fee)
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::WEI_PER_ETH;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(AmountEntry::parse("0.5").unwrap(), AmountEntry::Exact(WEI_PER_ETH / 2));
+        assert_eq!(AmountEntry::parse("50%").unwrap(), AmountEntry::Percent(50));
+        assert_eq!(AmountEntry::parse("MAX").unwrap(), AmountEntry::Percent(100));
+        assert_eq!(AmountEntry::parse("keep 0.05").unwrap(), AmountEntry::Keep(WEI_PER_ETH / 20));
+        assert!(AmountEntry::parse("0%").is_err());
+        assert!(AmountEntry::parse("101%").is_err());
+        assert!(AmountEntry::parse("keep").is_err());
+        assert!(AmountEntry::parse("lots").is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let balance = WEI_PER_ETH;
+        let fee = 21000 * 1_000_000_000;
+
+        assert_eq!(AmountEntry::Exact(5).resolve(0, fee).unwrap(), 5);
+        assert_eq!(AmountEntry::Percent(50).resolve(balance, fee).unwrap(), WEI_PER_ETH / 2 - fee);
+        assert_eq!(AmountEntry::Percent(100).resolve(balance, fee).unwrap(), balance - fee);
+        assert_eq!(AmountEntry::Keep(WEI_PER_ETH / 20).resolve(balance, fee).unwrap(), balance - WEI_PER_ETH / 20 - fee);
+        // odd balances must not lose a wei to rounding
+        assert_eq!(AmountEntry::Percent(100).resolve(12345, 0).unwrap(), 12345);
+    }
+
+    #[test]
+    fn test_resolve_non_positive() {
+        assert!(AmountEntry::Percent(50).resolve(1000, 500).is_err());
+        assert!(AmountEntry::Keep(1000).resolve(1000, 1).is_err());
+        assert!(AmountEntry::Keep(2000).resolve(1000, 0).is_err());
+    }
+}
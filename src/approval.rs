@@ -0,0 +1,230 @@
+use serde::{Serialize, Deserialize};
+
+use crate::crypto::SealedBox;
+use crate::storage::PassphraseHash;
+use crate::utils;
+
+/// Domain separation for the key that encrypts the staged transactions
+const QUEUE_KEY_CONTEXT: &[u8] = b"basic-eth staged transactions";
+
+/// How the gas price of a staged transaction is chosen once it is approved
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum FeeStrategy {
+    /// The gas price quoted when the transaction was staged
+    Fixed(u128),
+    /// The network gas price at approval time, as long as it is at most `max_gas_price`
+    NetworkPrice { max_gas_price: u128 },
+}
+
+impl FeeStrategy {
+    /// Returns the gas price to sign with given the current network gas price
+    pub fn gas_price(&self, network_price: u128) -> Result<u128, String> {
+        match self {
+            FeeStrategy::Fixed(price) => Ok(*price),
+            FeeStrategy::NetworkPrice { max_gas_price } if network_price <= *max_gas_price => Ok(network_price),
+            FeeStrategy::NetworkPrice { max_gas_price } => Err(format!(
+                "The network gas price ({} wei) is above the staged limit of {} wei",
+                network_price, max_gas_price
+            )),
+        }
+    }
+}
+
+/// A fully built transfer waiting for the approval password before it is signed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StagedTransaction {
+    pub id: u32,
+    /// Index of the sending account when it was staged. Approval finds the account by `from`,
+    /// since indices shift as accounts are added.
+    pub account: usize,
+    pub from: String,
+    pub recipient: String,
+    pub wei_amount: u128,
+    pub fee: FeeStrategy,
+    pub staged_at: u64,
+    pub expires_at: u64,
+}
+
+impl StagedTransaction {
+    pub fn describe(&self) -> String {
+        let fee = match self.fee {
+            FeeStrategy::Fixed(price) => format!("{} wei gas price", price),
+            FeeStrategy::NetworkPrice { max_gas_price } => format!("network gas price up to {} wei", max_gas_price),
+        };
+        format!(
+            "#{}: {} ETH from {} to {} ({}), staged {}, expires {}",
+            self.id, utils::wei_to_eth(self.wei_amount), self.from, self.recipient, fee,
+            utils::format_timestamp(self.staged_at), utils::format_timestamp(self.expires_at)
+        )
+    }
+}
+
+/// The decrypted list of staged transactions
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct ApprovalQueue {
+    next_id: u32,
+    pub staged: Vec<StagedTransaction>,
+}
+
+impl ApprovalQueue {
+    /// Adds a transaction, assigning it the next id. Returns the id.
+    pub fn stage(&mut self, mut transaction: StagedTransaction) -> u32 {
+        self.next_id += 1;
+        transaction.id = self.next_id;
+        self.staged.push(transaction);
+        self.next_id
+    }
+
+    pub fn get(&self, id: u32) -> Option<&StagedTransaction> {
+        self.staged.iter().find(|t| t.id == id)
+    }
+
+    pub fn remove(&mut self, id: u32) -> Option<StagedTransaction> {
+        let position = self.staged.iter().position(|t| t.id == id)?;
+        Some(self.staged.remove(position))
+    }
+
+    /// Removes and returns the transactions that expired before `now`
+    pub fn remove_expired(&mut self, now: u64) -> Vec<StagedTransaction> {
+        let (expired, pending) = self.staged.drain(..).partition(|t| t.expires_at < now);
+        self.staged = pending;
+        expired
+    }
+}
+
+/// Two-person rule: sends are only staged, and a second password that is verified independently
+/// of the wallet password must be entered to sign them
+#[derive(Serialize, Deserialize)]
+pub struct ApprovalMode {
+    /// Checked through the password KDF, since the first person can read this file
+    approver: PassphraseHash,
+    sealed: Option<SealedBox>,
+    /// The decrypted queue while the wallet is unlocked. Never persisted in plain text.
+    #[serde(skip)]
+    queue: Option<ApprovalQueue>,
+}
+
+impl ApprovalMode {
+    pub fn new(approval_password: &str) -> Self {
+        ApprovalMode {
+            approver: PassphraseHash::new(approval_password),
+            sealed: None,
+            queue: Some(ApprovalQueue::default()),
+        }
+    }
+
+    pub fn verify_approver(&mut self, password: &str) -> bool {
+        self.approver.verify(password)
+    }
+
+    /// Decrypts the stored queue with a secret only available once the wallet is unlocked
+    pub fn open(&mut self, secret: &[u8]) -> Result<(), String> {
+        let queue = match &self.sealed {
+            Some(sealed) => {
+                let plaintext = sealed.open(QUEUE_KEY_CONTEXT, secret)
+                    .ok_or_else(|| String::from("Staged transactions could not be decrypted"))?;
+                serde_json::from_slice(&plaintext).map_err(|e| e.to_string())?
+            },
+            None => ApprovalQueue::default(),
+        };
+        self.queue = Some(queue);
+        Ok(())
+    }
+
+    /// Encrypts the open queue for storage. Does nothing if the queue was never opened, so an
+    /// undecryptable queue is kept as it is.
+    pub fn seal(&mut self, secret: &[u8]) {
+        if let Some(queue) = &self.queue {
+            let plaintext = serde_json::to_vec(queue).unwrap();
+            self.sealed = Some(SealedBox::seal(QUEUE_KEY_CONTEXT, secret, &plaintext));
+        }
+    }
+
+    /// Drops the decrypted queue
+    pub fn close(&mut self) {
+        self.queue = None;
+    }
+
+    pub fn queue(&mut self) -> Option<&mut ApprovalQueue> {
+        self.queue.as_mut()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn transaction(expires_at: u64) -> StagedTransaction {
+        StagedTransaction {
+            id: 0,
+            account: 0,
+            from: String::from("0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266"),
+            recipient: String::from("0x70997970c51812dc3a010c7d01b50e0d17dc79c8"),
+            wei_amount: 5,
+            fee: FeeStrategy::Fixed(1),
+            staged_at: 0,
+            expires_at,
+        }
+    }
+
+    #[test]
+    fn test_fee_strategy() {
+        assert_eq!(FeeStrategy::Fixed(7).gas_price(100).unwrap(), 7);
+        let capped = FeeStrategy::NetworkPrice { max_gas_price: 50 };
+        assert_eq!(capped.gas_price(50).unwrap(), 50);
+        assert!(capped.gas_price(51).is_err());
+    }
+
+    #[test]
+    fn test_queue() {
+        let mut queue = ApprovalQueue::default();
+        assert_eq!(queue.stage(transaction(100)), 1);
+        assert_eq!(queue.stage(transaction(200)), 2);
+        assert_eq!(queue.get(2).unwrap().expires_at, 200);
+
+        let expired = queue.remove_expired(150);
+        assert_eq!(expired.iter().map(|t| t.id).collect::<Vec<_>>(), [1]);
+        assert_eq!(queue.remove(2).unwrap().id, 2);
+        assert!(queue.remove(2).is_none());
+        // ids are never reused
+        assert_eq!(queue.stage(transaction(300)), 3);
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let secret = [3u8; 32];
+        let mut mode = ApprovalMode::new("approver");
+        assert!(mode.verify_approver("approver"));
+        assert!(!mode.verify_approver("wallet password"));
+        mode.queue().unwrap().stage(transaction(100));
+        mode.seal(&secret);
+        mode.close();
+
+        let stored = serde_json::to_string(&mode).unwrap();
+        assert!(!stored.contains("0x70997970c51812dc3a010c7d01b50e0d17dc79c8"));
+        assert!(stored.contains(r#""kdf":"argon2id""#));
+
+        let mut loaded: ApprovalMode = serde_json::from_str(&stored).unwrap();
+        assert!(loaded.queue().is_none());
+        assert!(loaded.open(&[4u8; 32]).is_err());
+        loaded.open(&secret).unwrap();
+        assert_eq!(loaded.queue().unwrap().get(1), Some(&StagedTransaction { id: 1, ..transaction(100) }));
+    }
+
+    #[test]
+    fn test_legacy_approver() {
+        // versions before 11 stored the approval password as keccak256(salt || password)
+        let salt = [5u8; 32];
+        let legacy = serde_json::json!({
+            "approver": { "salt": salt.to_vec(), "hash": crate::crypto::salted_hash(&salt, "approver").to_vec() },
+            "sealed": null,
+        });
+        let mut mode: ApprovalMode = serde_json::from_value(legacy).unwrap();
+        assert!(!mode.verify_approver("wallet password"));
+        assert!(mode.verify_approver("approver"));
+        // and it is stored through the password KDF from then on
+        let stored = serde_json::to_string(&mode).unwrap();
+        assert!(stored.contains(r#""kdf":"argon2id""#) && !stored.contains(r#""salt":["#));
+        assert!(mode.verify_approver("approver"));
+    }
+}
@@ -0,0 +1,47 @@
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+
+use crate::{data_dir, utils};
+
+/// In the data directory
+const AUDIT_LOG_PATH: &str = "audit.log";
+
+/// Appends a timestamped event to the audit log. A failed write is reported but never stops the
+/// wallet.
+pub fn record(event: &str) {
+    let entry = format_entry(utils::now(), event);
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(data_dir::path(AUDIT_LOG_PATH))
+        .and_then(|mut file| file.write_all(entry.as_bytes()));
+
+    if let Err(e) = written {
+        eprintln!("Could not write to the audit log: {}", e);
+    }
+}
+
+fn format_entry(timestamp: u64, event: &str) -> String {
+    // keep one event per line whatever the event text contains
+    format!("{} {}\n", utils::format_timestamp(timestamp), event.replace('\n', " "))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_entry() {
+        assert_eq!(format_entry(1704272400, "Session locked"), "2024-01-03 09:00 UTC Session locked\n");
+        assert_eq!(format_entry(0, "two\nlines"), "1970-01-01 00:00 UTC two lines\n");
+    }
+
+    #[test]
+    fn test_record() {
+        let event = format!("Audit test event {}", rand::random::<u64>());
+        record(&format!("{}\nsecond line", event));
+        let log = std::fs::read_to_string(data_dir::path(AUDIT_LOG_PATH)).unwrap();
+        let line = log.lines().find(|line| line.contains(&event)).unwrap();
+        assert!(line.ends_with(&format!("UTC {} second line", event)), "{}", line);
+    }
+}
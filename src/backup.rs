@@ -0,0 +1,128 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Deserialize};
+
+use crate::crypto::keccak256;
+use crate::registry;
+use crate::utils;
+
+/// Starts every backup file, ahead of a line of JSON header and the wallet file it holds
+const MAGIC: &[u8] = b"BASIC-ETH BACKUP\n";
+const EXTENSION: &str = "backup";
+
+/// The plaintext header of a backup file
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Header {
+    /// The name of the wallet backed up
+    name: String,
+    /// When the backup was taken
+    created_at: u64,
+    /// keccak256 of the wallet file that follows, so a copy damaged in transit is refused before
+    /// it replaces anything
+    checksum: String,
+}
+
+/// A backup file that matches its checksum. The wallet file it holds is still locked.
+#[derive(Debug, PartialEq)]
+pub struct Backup {
+    pub name: String,
+    pub created_at: u64,
+    pub wallet_file: Vec<u8>,
+}
+
+/// Writes a backup of `wallet_file`, the contents of an encrypted wallet file, into `dir`.
+/// The file is named after the wallet and the time, and an existing file is never replaced.
+/// Returns its path.
+pub fn write(dir: &Path, name: &str, wallet_file: &[u8]) -> Result<PathBuf, String> {
+    if !dir.is_dir() {
+        return Err(format!("{} is not a directory", dir.display()));
+    }
+    let created_at = utils::now();
+    let data = encode(name, created_at, wallet_file);
+    for path in file_names(name, created_at).map(|file| dir.join(file)) {
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(format!("Error writing {}: {}", path.display(), e)),
+        };
+        return file.write_all(&data)
+            .and_then(|()| file.sync_all())
+            .map(|()| path.clone())
+            .map_err(|e| format!("Error writing {}: {}", path.display(), e));
+    }
+    unreachable!()
+}
+
+/// Reads and checks a backup file written by `write`
+pub fn read(path: &Path) -> Result<Backup, String> {
+    let data = std::fs::read(path).map_err(|e| format!("Error reading {}: {}", path.display(), e))?;
+    decode(&data).map_err(|e| format!("{} can't be restored: {}", path.display(), e))
+}
+
+fn encode(name: &str, created_at: u64, wallet_file: &[u8]) -> Vec<u8> {
+    let header = Header { name: String::from(name), created_at, checksum: hex::encode(keccak256(wallet_file)) };
+    let mut data = MAGIC.to_vec();
+    data.extend(serde_json::to_vec(&header).unwrap());
+    data.push(b'\n');
+    data.extend(wallet_file);
+    data
+}
+
+fn decode(data: &[u8]) -> Result<Backup, String> {
+    let rest = data.strip_prefix(MAGIC).ok_or_else(|| String::from("it isn't a wallet backup"))?;
+    let newline = rest.iter().position(|b| *b == b'\n').ok_or_else(|| String::from("the header is truncated"))?;
+    let header: Header = serde_json::from_slice(&rest[..newline]).map_err(|e| format!("malformed header: {}", e))?;
+    let wallet_file = rest[newline + 1..].to_vec();
+    if hex::encode(keccak256(&wallet_file)) != header.checksum {
+        return Err(String::from("it doesn't match its checksum, so it was damaged or cut short"));
+    }
+    Ok(Backup { name: header.name, created_at: header.created_at, wallet_file })
+}
+
+/// The file names to try in turn: the wallet's name and the time in UTC, then with a count
+/// added for backups taken within the same minute
+fn file_names(name: &str, created_at: u64) -> impl Iterator<Item = String> {
+    // "YYYY-MM-DD HH:MM UTC" as "YYYY-MM-DD-HHMM"
+    let time = utils::format_timestamp(created_at);
+    let stem = format!("{}-{}-{}", registry::file_stem(name), &time[..10], time[11..16].replace(':', ""));
+    (1..).map(move |n| match n {
+        1 => format!("{}.{}", stem, EXTENSION),
+        n => format!("{}-{}.{}", stem, n, EXTENSION),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let data = encode("Savings", 1_700_000_000, b"wallet file");
+        assert_eq!(decode(&data).unwrap(), Backup { name: String::from("Savings"), created_at: 1_700_000_000, wallet_file: b"wallet file".to_vec() });
+
+        let mut damaged = data.clone();
+        *damaged.last_mut().unwrap() ^= 1;
+        assert_eq!(decode(&damaged).unwrap_err(), "it doesn't match its checksum, so it was damaged or cut short");
+        assert!(decode(&data[..data.len() - 1]).is_err());
+        assert_eq!(decode(b"{\"pad\":[]}").unwrap_err(), "it isn't a wallet backup");
+        assert_eq!(decode(MAGIC).unwrap_err(), "the header is truncated");
+    }
+
+    #[test]
+    fn test_write() {
+        let mut names = file_names("Cold storage", 1_700_000_000);
+        assert_eq!(names.next().unwrap(), "cold-storage-2023-11-14-2213.backup");
+        assert_eq!(names.next().unwrap(), "cold-storage-2023-11-14-2213-2.backup");
+
+        let dir = std::env::temp_dir().join(format!("basic-eth-backups-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let first = write(&dir, "Savings", b"wallet file").unwrap();
+        let second = write(&dir, "Savings", b"wallet file").unwrap();
+        assert_ne!(first, second);
+        assert_eq!(read(&first).unwrap().wallet_file, b"wallet file");
+        assert!(write(&first, "Savings", b"wallet file").unwrap_err().ends_with("is not a directory"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
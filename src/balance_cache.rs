@@ -0,0 +1,87 @@
+use serde::{Serialize, Deserialize};
+
+use crate::utils;
+
+/// What the node last said about an account, kept with the wallet so it can be shown without
+/// waiting on the network
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CachedBalance {
+    pub wei_balance: u128,
+    /// The node's transaction count for the account
+    pub nonce: u64,
+    /// Unix timestamp (seconds) of the fetch
+    pub fetched_at: u64,
+    /// The network it was fetched from. A balance on another network says nothing about this one.
+    pub chain_id: u64,
+    /// Set when a refresh failed after it was fetched, so it isn't shown as current
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
+}
+
+impl CachedBalance {
+    pub fn new(wei_balance: u128, nonce: u64, chain_id: u64) -> Self {
+        CachedBalance { wei_balance, nonce, fetched_at: utils::now(), chain_id, stale: false }
+    }
+
+    /// e.g. "0.42 ETH, 3m ago", marked if it is stale
+    pub fn describe(&self, currency: &str, now: u64) -> String {
+        let described = format!("{} {}, {}", utils::wei_to_eth(self.wei_balance), currency, format_age(now.saturating_sub(self.fetched_at)));
+        match self.stale {
+            true => described + " (out of date: the node couldn't be reached since)",
+            false => described,
+        }
+    }
+}
+
+/// e.g. "0.5 ETH (0.38 pending)", with the balance once pending transactions are mined if it
+/// differs
+pub fn describe_balance(wei_balance: u128, pending: Option<u128>, currency: &str) -> String {
+    let described = format!("{} {}", utils::wei_to_eth(wei_balance), currency);
+    match pending {
+        Some(pending) if pending != wei_balance => format!("{} ({} pending)", described, utils::wei_to_eth(pending)),
+        _ => described,
+    }
+}
+
+/// The cached balance if it was fetched on the network with `chain_id`
+pub fn for_network(cache: &Option<CachedBalance>, chain_id: u64) -> Option<&CachedBalance> {
+    cache.as_ref().filter(|c| c.chain_id == chain_id)
+}
+
+/// How long ago something happened, in its largest whole unit
+pub fn format_age(secs: u64) -> String {
+    match secs {
+        0..=59 => String::from("just now"),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(format_age(59), "just now");
+        assert_eq!(format_age(180), "3m ago");
+        assert_eq!(format_age(7300), "2h ago");
+        assert_eq!(format_age(86400 * 3), "3d ago");
+
+        let mut cached = CachedBalance { wei_balance: 420_000_000_000_000_000, nonce: 3, fetched_at: 1_000, chain_id: 4, stale: false };
+        assert_eq!(cached.describe("ETH", 1_180), "0.42 ETH, 3m ago");
+        // a clock that went backwards isn't an age
+        assert_eq!(cached.describe("ETH", 0), "0.42 ETH, just now");
+        cached.stale = true;
+        assert!(cached.describe("ETH", 1_180).ends_with("(out of date: the node couldn't be reached since)"));
+
+        assert_eq!(describe_balance(500_000_000_000_000_000, Some(380_000_000_000_000_000), "ETH"), "0.5 ETH (0.38 pending)");
+        assert_eq!(describe_balance(500_000_000_000_000_000, Some(500_000_000_000_000_000), "ETH"), "0.5 ETH");
+        assert_eq!(describe_balance(42, None, "ETH"), describe_balance(42, Some(42), "ETH"));
+
+        let cache = Some(cached);
+        assert!(for_network(&cache, 4).is_some());
+        assert_eq!(for_network(&cache, 1), None);
+    }
+}
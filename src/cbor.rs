@@ -0,0 +1,191 @@
+use thiserror::Error;
+
+/// The subset of CBOR (RFC 8949) needed for UR payloads
+#[derive(Debug, PartialEq, Clone)]
+pub enum Cbor {
+    Uint(u64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Cbor>),
+    Map(Vec<(Cbor, Cbor)>),
+    Tag(u64, Box<Cbor>),
+    Bool(bool),
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum CborError {
+    #[error("Unexpected end of CBOR data")]
+    UnexpectedEnd,
+    #[error("Unsupported CBOR item (major type {0}, info {1})")]
+    Unsupported(u8, u8),
+    #[error("Invalid UTF-8 in CBOR text")]
+    InvalidText,
+    #[error("Trailing bytes after CBOR item")]
+    TrailingBytes,
+}
+
+impl Cbor {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.write(&mut out);
+        out
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Cbor::Uint(n) => write_head(out, 0, *n),
+            Cbor::Bytes(b) => {
+                write_head(out, 2, b.len() as u64);
+                out.extend_from_slice(b);
+            },
+            Cbor::Text(t) => {
+                write_head(out, 3, t.len() as u64);
+                out.extend_from_slice(t.as_bytes());
+            },
+            Cbor::Array(items) => {
+                write_head(out, 4, items.len() as u64);
+                items.iter().for_each(|i| i.write(out));
+            },
+            Cbor::Map(entries) => {
+                write_head(out, 5, entries.len() as u64);
+                for (k, v) in entries {
+                    k.write(out);
+                    v.write(out);
+                }
+            },
+            Cbor::Tag(tag, item) => {
+                write_head(out, 6, *tag);
+                item.write(out);
+            },
+            Cbor::Bool(b) => out.push(if *b { 0xf5 } else { 0xf4 }),
+        }
+    }
+
+    /// Decodes exactly one CBOR item
+    pub fn decode(data: &[u8]) -> Result<Cbor, CborError> {
+        let mut pos = 0;
+        let item = read(data, &mut pos)?;
+        if pos != data.len() {
+            return Err(CborError::TrailingBytes);
+        }
+        Ok(item)
+    }
+
+    /// Looks up an unsigned integer key in a map
+    pub fn get(&self, key: u64) -> Option<&Cbor> {
+        match self {
+            Cbor::Map(entries) => entries.iter().find(|(k, _)| *k == Cbor::Uint(key)).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Strips any tags around the item
+    pub fn untagged(&self) -> &Cbor {
+        match self {
+            Cbor::Tag(_, item) => item.untagged(),
+            _ => self,
+        }
+    }
+}
+
+fn write_head(out: &mut Vec<u8>, major: u8, n: u64) {
+    let major = major << 5;
+    match n {
+        0..=23 => out.push(major | n as u8),
+        24..=0xff => out.extend_from_slice(&[major | 24, n as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend_from_slice(&(n as u16).to_be_bytes());
+        },
+        0x10000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend_from_slice(&(n as u32).to_be_bytes());
+        },
+        _ => {
+            out.push(major | 27);
+            out.extend_from_slice(&n.to_be_bytes());
+        },
+    }
+}
+
+fn take<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], CborError> {
+    let end = pos.checked_add(len).ok_or(CborError::UnexpectedEnd)?;
+    let bytes = data.get(*pos..end).ok_or(CborError::UnexpectedEnd)?;
+    *pos = end;
+    Ok(bytes)
+}
+
+fn read(data: &[u8], pos: &mut usize) -> Result<Cbor, CborError> {
+    let initial = take(data, pos, 1)?[0];
+    let (major, info) = (initial >> 5, initial & 0x1f);
+
+    if major == 7 {
+        return match info {
+            20 => Ok(Cbor::Bool(false)),
+            21 => Ok(Cbor::Bool(true)),
+            _ => Err(CborError::Unsupported(major, info)),
+        };
+    }
+
+    let n = match info {
+        0..=23 => info as u64,
+        24 => take(data, pos, 1)?[0] as u64,
+        25 => u16::from_be_bytes(take(data, pos, 2)?.try_into().unwrap()) as u64,
+        26 => u32::from_be_bytes(take(data, pos, 4)?.try_into().unwrap()) as u64,
+        27 => u64::from_be_bytes(take(data, pos, 8)?.try_into().unwrap()),
+        _ => return Err(CborError::Unsupported(major, info)),
+    };
+
+    match major {
+        0 => Ok(Cbor::Uint(n)),
+        2 => Ok(Cbor::Bytes(take(data, pos, n as usize)?.to_vec())),
+        3 => {
+            let text = take(data, pos, n as usize)?.to_vec();
+            String::from_utf8(text).map(Cbor::Text).map_err(|_e| CborError::InvalidText)
+        },
+        4 => (0..n).map(|_| read(data, pos)).collect::<Result<_, _>>().map(Cbor::Array),
+        5 => (0..n).map(|_| Ok((read(data, pos)?, read(data, pos)?))).collect::<Result<_, _>>().map(Cbor::Map),
+        6 => Ok(Cbor::Tag(n, Box::new(read(data, pos)?))),
+        _ => Err(CborError::Unsupported(major, info)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode_heads() {
+        // examples from RFC 8949 appendix A
+        assert_eq!(Cbor::Uint(0).encode(), hex::decode("00").unwrap());
+        assert_eq!(Cbor::Uint(23).encode(), hex::decode("17").unwrap());
+        assert_eq!(Cbor::Uint(24).encode(), hex::decode("1818").unwrap());
+        assert_eq!(Cbor::Uint(1000).encode(), hex::decode("1903e8").unwrap());
+        assert_eq!(Cbor::Uint(1000000).encode(), hex::decode("1a000f4240").unwrap());
+        assert_eq!(Cbor::Uint(1000000000000).encode(), hex::decode("1b000000e8d4a51000").unwrap());
+        assert_eq!(Cbor::Text(String::from("IETF")).encode(), hex::decode("6449455446").unwrap());
+        assert_eq!(Cbor::Bool(true).encode(), hex::decode("f5").unwrap());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let item = Cbor::Map(vec![
+            (Cbor::Uint(1), Cbor::Bool(false)),
+            (Cbor::Uint(2), Cbor::Tag(304, Box::new(Cbor::Array(vec![Cbor::Uint(44), Cbor::Bool(true)])))),
+            (Cbor::Uint(3), Cbor::Bytes(vec![0xab; 33])),
+            (Cbor::Uint(4), Cbor::Text(String::from("name"))),
+        ]);
+        let decoded = Cbor::decode(&item.encode()).unwrap();
+        assert_eq!(decoded, item);
+        assert_eq!(decoded.get(4), Some(&Cbor::Text(String::from("name"))));
+        assert_eq!(decoded.get(2).unwrap().untagged(), &Cbor::Array(vec![Cbor::Uint(44), Cbor::Bool(true)]));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(Cbor::decode(&[]), Err(CborError::UnexpectedEnd));
+        assert_eq!(Cbor::decode(&hex::decode("5820ab").unwrap()), Err(CborError::UnexpectedEnd));
+        assert_eq!(Cbor::decode(&hex::decode("0000").unwrap()), Err(CborError::TrailingBytes));
+        assert!(Cbor::decode(&hex::decode("f6").unwrap()).is_err());
+    }
+}
@@ -1,20 +1,502 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use aes_gcm::Aes256Gcm;
+use argon2::{Algorithm, Argon2, Version};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use chacha20poly1305::aead::Aead;
+use hmac::{Hmac, Mac};
+use serde::{Serialize, Deserialize};
 use sha3::{Digest, Keccak256, Keccak512};
+use subtle::ConstantTimeEq;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// SLIP-39 Shamir backups of a seed
+pub mod slip39;
+
+/// Argon2id cost of new wallet passwords, OWASP's recommendation: 19 MiB and two passes
+pub const ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+pub const ARGON2_ITERATIONS: u32 = 2;
+pub const ARGON2_PARALLELISM: u32 = 1;
+/// log2 of scrypt's N for new wallet passwords. 2^15 takes 32 MiB, scrypt's interactive cost.
+pub const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+/// OWASP's recommendation for PBKDF2-HMAC-SHA512
+pub const PBKDF2_ITERATIONS: u32 = 210_000;
+/// Fewest PBKDF2 iterations a wallet password is stretched with, whatever the settings say
+pub const MIN_PBKDF2_ITERATIONS: u32 = 100_000;
+/// What the MAC subkey of a password key is derived with
+const MAC_KEY_CONTEXT: &[u8] = b"basic-eth wallet file mac";
+
+/// Compares bytes that depend on a secret, taking the same time wherever they first differ. Only
+/// the lengths are compared in variable time.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
 
 pub fn generate_eth_address(public_key: &[u8]) -> [u8; 20] {
     let result = keccak256(public_key);
     result[12..].try_into().unwrap()
 }
 
+/// Returns the EIP-55 mixed-case checksum encoding of an address, including the 0x prefix
+pub fn checksum_address(address: &[u8; 20]) -> String {
+    let lower = hex::encode(address);
+    let hash = keccak256(lower.as_bytes());
+
+    let mut result = String::from("0x");
+    for (i, c) in lower.chars().enumerate() {
+        // each hex char is checked against the corresponding nibble of the hash
+        let nibble = (hash[i / 2] >> (if i % 2 == 0 { 4 } else { 0 })) & 0x0f;
+        if nibble >= 8 {
+            result.push(c.to_ascii_uppercase());
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
 pub fn keccak256(input: &[u8]) -> [u8; 32] {
     let mut hasher = Keccak256::new();
     hasher.update(input);
-    hasher.finalize().try_into().unwrap()
+    hasher.finalize().into()
+}
+
+/// Hashes a passphrase together with a salt as keccak256(salt || passphrase)
+pub fn salted_hash(salt: &[u8], passphrase: &str) -> [u8; 32] {
+    let mut hasher = Keccak256::new();
+    hasher.update(salt);
+    hasher.update(passphrase.as_bytes());
+    hasher.finalize().into()
 }
 
 pub fn keccak512(input: &[u8]) -> [u8; 64] {
     let mut hasher = Keccak512::new();
     hasher.update(input);
-    hasher.finalize().try_into().unwrap()
+    hasher.finalize().into()
+}
+
+/// ChaCha20-Poly1305 ciphertext as stored in the wallet file. The key is
+/// keccak256(context || secret), so each use gets its own key from the same secret.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SealedBox {
+    nonce: String,
+    ciphertext: String,
+}
+
+impl SealedBox {
+    pub fn seal(context: &[u8], secret: &[u8], plaintext: &[u8]) -> Self {
+        SealedBox::seal_with_nonce(context, secret, plaintext, rand::random())
+    }
+
+    /// Seals with a caller-chosen nonce, for reproducible output. A nonce must never be reused
+    /// with the same key.
+    pub fn seal_with_nonce(context: &[u8], secret: &[u8], plaintext: &[u8], nonce: [u8; 12]) -> Self {
+        let ciphertext = cipher(context, secret).encrypt(Nonce::from_slice(&nonce), plaintext).unwrap();
+        SealedBox { nonce: hex::encode(nonce), ciphertext: hex::encode(ciphertext) }
+    }
+
+    /// Returns None if the secret is wrong or the box was tampered with
+    pub fn open(&self, context: &[u8], secret: &[u8]) -> Option<Vec<u8>> {
+        let nonce = hex::decode(&self.nonce).ok().filter(|n| n.len() == 12)?;
+        let ciphertext = hex::decode(&self.ciphertext).ok()?;
+        cipher(context, secret).decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice()).ok()
+    }
+}
+
+/// Parameters of the key derivation function named by a `PasswordBox`'s `kdf`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum PasswordKdf {
+    Argon2id(Argon2Params),
+    Scrypt(ScryptParams),
+    Pbkdf2(Pbkdf2Params),
+}
+
+impl PasswordKdf {
+    /// The name stored as the box's `kdf`
+    pub fn name(&self) -> &'static str {
+        match self {
+            PasswordKdf::Argon2id(_) => "argon2id",
+            PasswordKdf::Scrypt(_) => "scrypt",
+            PasswordKdf::Pbkdf2(_) => "pbkdf2",
+        }
+    }
+
+    fn derive_key(&self, password: &str) -> Option<Zeroizing<[u8; 32]>> {
+        match self {
+            PasswordKdf::Argon2id(params) => params.derive_key(password),
+            PasswordKdf::Scrypt(params) => params.derive_key(password),
+            PasswordKdf::Pbkdf2(params) => params.derive_key(password),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Argon2Params {
+    pub salt: String,
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Argon2Params {
+    /// Parameters with the given cost and a fresh random salt
+    pub fn new(memory_kib: u32, iterations: u32, parallelism: u32) -> Self {
+        Argon2Params::with_salt(rand::random(), memory_kib, iterations, parallelism)
+    }
+
+    pub fn with_salt(salt: [u8; 16], memory_kib: u32, iterations: u32, parallelism: u32) -> Self {
+        Argon2Params { salt: hex::encode(salt), memory_kib, iterations, parallelism }
+    }
+
+    /// Returns None for parameters Argon2 rejects, which only an edited file has
+    fn derive_key(&self, password: &str) -> Option<Zeroizing<[u8; 32]>> {
+        let salt = hex::decode(&self.salt).ok()?;
+        let params = argon2::Params::new(self.memory_kib, self.iterations, self.parallelism, Some(32)).ok()?;
+        let mut key = Zeroizing::new([0u8; 32]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params).hash_password_into(password.as_bytes(), &salt, key.as_mut_slice()).ok()?;
+        Some(key)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ScryptParams {
+    pub salt: String,
+    /// log2 of the CPU and memory cost N
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+}
+
+impl ScryptParams {
+    /// Parameters with the given cost and a fresh random salt
+    pub fn new(log_n: u8) -> Self {
+        ScryptParams { salt: hex::encode(rand::random::<[u8; 16]>()), log_n, r: SCRYPT_R, p: SCRYPT_P }
+    }
+
+    fn derive_key(&self, password: &str) -> Option<Zeroizing<[u8; 32]>> {
+        let salt = hex::decode(&self.salt).ok()?;
+        let params = scrypt::Params::new(self.log_n, self.r, self.p).ok()?;
+        let mut key = Zeroizing::new([0u8; 32]);
+        scrypt::scrypt(password.as_bytes(), &salt, &params, key.as_mut_slice()).ok()?;
+        Some(key)
+    }
+}
+
+/// PBKDF2-HMAC-SHA512
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Pbkdf2Params {
+    pub salt: String,
+    pub iterations: u32,
+}
+
+impl Pbkdf2Params {
+    /// Parameters with a fresh random salt and at least `MIN_PBKDF2_ITERATIONS` iterations
+    pub fn new(iterations: u32) -> Self {
+        Pbkdf2Params { salt: hex::encode(rand::random::<[u8; 16]>()), iterations: iterations.max(MIN_PBKDF2_ITERATIONS) }
+    }
+
+    /// Returns None below `MIN_PBKDF2_ITERATIONS`, which only an edited file has
+    fn derive_key(&self, password: &str) -> Option<Zeroizing<[u8; 32]>> {
+        if self.iterations < MIN_PBKDF2_ITERATIONS {
+            return None;
+        }
+        Some(pbkdf2_key(password, &hex::decode(&self.salt).ok()?, self.iterations))
+    }
+}
+
+fn pbkdf2_key(password: &str, salt: &[u8], iterations: u32) -> Zeroizing<[u8; 32]> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    pbkdf2::pbkdf2_hmac::<sha2::Sha512>(password.as_bytes(), salt, iterations, key.as_mut_slice());
+    key
+}
+
+/// AES-256-GCM ciphertext as stored in the wallet file, under a key derived from a password with
+/// the function named by `kdf`. Opening it checks the password, since a wrong key fails
+/// authentication.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PasswordBox {
+    kdf: String,
+    kdfparams: PasswordKdf,
+    nonce: String,
+    ciphertext: String,
+}
+
+impl PasswordBox {
+    pub fn seal(password: &str, plaintext: &[u8], params: PasswordKdf) -> Self {
+        PasswordBox::seal_with_nonce(password, plaintext, params, rand::random())
+    }
+
+    /// Seals with a caller-chosen nonce, for reproducible output. Every seal should also get a
+    /// fresh salt, so a nonce is never reused with the same key.
+    pub fn seal_with_nonce(password: &str, plaintext: &[u8], params: PasswordKdf, nonce: [u8; 12]) -> Self {
+        let key = PasswordKey::derive(password, params).expect("KDF parameters must be valid");
+        PasswordBox::seal_with_key(&key, plaintext, nonce)
+    }
+
+    /// Seals under a key that was already derived, keeping its parameters
+    pub fn seal_with_key(key: &PasswordKey, plaintext: &[u8], nonce: [u8; 12]) -> Self {
+        PasswordBox {
+            kdf: String::from(key.kdf.name()),
+            kdfparams: key.kdf.clone(),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(key.encrypt(plaintext, nonce)),
+        }
+    }
+
+    /// The parameters the key was derived with, salt included
+    pub fn kdf(&self) -> &PasswordKdf {
+        &self.kdfparams
+    }
+
+    /// The name of the function the key was derived with
+    pub fn kdf_name(&self) -> &str {
+        &self.kdf
+    }
+
+    /// Checks the nonce and ciphertext are hex of lengths a sealed box can have, so a box damaged
+    /// in the file isn't mistaken for a wrong password
+    pub fn check(&self) -> Result<(), String> {
+        match hex::decode(&self.nonce) {
+            Ok(nonce) if nonce.len() == 12 => {},
+            _ => return Err(format!("nonce is {:?}, not 24 hex digits", self.nonce)),
+        }
+        match hex::decode(&self.ciphertext) {
+            // the authentication tag alone is 16 bytes
+            Ok(ciphertext) if ciphertext.len() >= 16 => Ok(()),
+            Ok(ciphertext) => Err(format!("ciphertext is {} bytes, shorter than its authentication tag", ciphertext.len())),
+            Err(e) => Err(format!("ciphertext isn't hex: {}", e)),
+        }
+    }
+
+    /// Returns None if the password is wrong or the box was tampered with
+    pub fn open(&self, password: &str) -> Option<Zeroizing<Vec<u8>>> {
+        self.open_with_key(&PasswordKey::for_kdf(password, &self.kdf, &self.kdfparams)?)
+    }
+
+    /// Opens with a key that was already derived, or kept from an earlier derivation
+    pub fn open_with_key(&self, key: &PasswordKey) -> Option<Zeroizing<Vec<u8>>> {
+        let nonce: [u8; 12] = hex::decode(&self.nonce).ok()?.try_into().ok()?;
+        key.decrypt(&hex::decode(&self.ciphertext).ok()?, nonce)
+    }
+}
+
+/// An AES-256-GCM key derived from a password, with the parameters that derived it. Kept while a
+/// wallet is open, so its file can be encrypted again without asking for the password.
+pub struct PasswordKey {
+    kdf: PasswordKdf,
+    key: Zeroizing<[u8; 32]>,
+}
+
+impl PasswordKey {
+    /// Returns None for parameters the function rejects, which only an edited file has
+    pub fn derive(password: &str, kdf: PasswordKdf) -> Option<Self> {
+        let key = kdf.derive_key(password)?;
+        Some(PasswordKey { kdf, key })
+    }
+
+    /// Derives with stored parameters and the name of their function, which must agree
+    pub fn for_kdf(password: &str, name: &str, kdf: &PasswordKdf) -> Option<Self> {
+        // untagged parameters could be read as the wrong function's
+        if name != kdf.name() {
+            return None;
+        }
+        PasswordKey::derive(password, kdf.clone())
+    }
+
+    pub fn kdf(&self) -> &PasswordKdf {
+        &self.kdf
+    }
+
+    /// Rebuilds a key kept outside the wallet file, with the parameters it was derived with
+    pub fn from_bytes(kdf: PasswordKdf, key: Zeroizing<[u8; 32]>) -> Self {
+        PasswordKey { kdf, key }
+    }
+
+    /// The key itself, to keep outside the wallet file. Whoever has it can open the wallet.
+    pub fn to_bytes(&self) -> Zeroizing<[u8; 32]> {
+        self.key.clone()
+    }
+
+    /// A nonce must never be reused with the same key
+    pub fn encrypt(&self, plaintext: &[u8], nonce: [u8; 12]) -> Vec<u8> {
+        Aes256Gcm::new(self.key.as_slice().into()).encrypt(&nonce.into(), plaintext).unwrap()
+    }
+
+    /// Returns None if the key is wrong or the ciphertext was changed
+    pub fn decrypt(&self, ciphertext: &[u8], nonce: [u8; 12]) -> Option<Zeroizing<Vec<u8>>> {
+        Aes256Gcm::new(self.key.as_slice().into()).decrypt(&nonce.into(), ciphertext).ok().map(Zeroizing::new)
+    }
+
+    /// HMAC-SHA256 of `data`, under a subkey of this key so the encryption key is never used for
+    /// anything else
+    pub fn mac(&self, data: &[u8]) -> [u8; 32] {
+        let mut subkey = <Hmac<sha2::Sha256> as hmac::KeyInit>::new_from_slice(self.key.as_slice()).unwrap();
+        subkey.update(MAC_KEY_CONTEXT);
+        let subkey: Zeroizing<[u8; 32]> = Zeroizing::new(subkey.finalize().into_bytes().into());
+        let mut mac = <Hmac<sha2::Sha256> as hmac::KeyInit>::new_from_slice(subkey.as_slice()).unwrap();
+        mac.update(data);
+        mac.finalize().into_bytes().into()
+    }
+}
+
+/// How many locked buffers lie on each locked page, by the page's address. Locks cover whole
+/// pages and don't nest, so a page is only unlocked once the last buffer on it is dropped.
+static LOCKED_PAGES: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+/// Secret bytes held in memory the OS is asked not to page out to swap, and wiped when dropped.
+/// If the OS won't lock it (under a low RLIMIT_MEMLOCK, say) the buffer still works, and a
+/// warning is printed the first time.
+pub struct SecretBuffer {
+    bytes: Box<[u8]>,
+    locked: bool,
+}
+
+impl SecretBuffer {
+    /// Copies `secret` into a new buffer, which is locked before the copy
+    pub fn new(secret: &[u8]) -> Self {
+        let mut bytes = vec![0; secret.len()].into_boxed_slice();
+        let locked = !bytes.is_empty() && lock(&bytes);
+        bytes.copy_from_slice(secret);
+        SecretBuffer { bytes, locked }
+    }
+
+    /// Whether the OS agreed to keep the buffer out of swap
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+}
+
+impl std::ops::Deref for SecretBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Clone for SecretBuffer {
+    fn clone(&self) -> Self {
+        SecretBuffer::new(&self.bytes)
+    }
+}
+
+impl Drop for SecretBuffer {
+    fn drop(&mut self) {
+        // wiped while it is still locked, so the secret can't reach swap on the way out
+        self.bytes.zeroize();
+        if self.locked {
+            unlock(&self.bytes);
+        }
+    }
+}
+
+impl ZeroizeOnDrop for SecretBuffer {}
+
+/// The addresses of the pages `bytes` lies on
+fn pages_of(bytes: &[u8]) -> impl Iterator<Item = usize> {
+    let size = memlock::page_size();
+    let first = bytes.as_ptr() as usize / size * size;
+    let last = (bytes.as_ptr() as usize + bytes.len() - 1) / size * size;
+    (first..=last).step_by(size)
+}
+
+/// Locks the pages under `bytes`, counting it on each of them
+fn lock(bytes: &[u8]) -> bool {
+    // counted under the same lock as the call, so a page can't be unlocked in between
+    let mut pages = LOCKED_PAGES.lock().unwrap_or_else(|e| e.into_inner());
+    if let Err(reason) = memlock::lock(bytes.as_ptr(), bytes.len()) {
+        warn_unlocked(reason);
+        return false;
+    }
+    for page in pages_of(bytes) {
+        *pages.entry(page).or_default() += 1;
+    }
+    true
+}
+
+/// Uncounts `bytes` from the pages under it, unlocking those no other locked buffer lies on
+fn unlock(bytes: &[u8]) {
+    let mut pages = LOCKED_PAGES.lock().unwrap_or_else(|e| e.into_inner());
+    for page in pages_of(bytes) {
+        let Some(count) = pages.get_mut(&page) else { continue };
+        *count -= 1;
+        if *count == 0 {
+            pages.remove(&page);
+            memlock::unlock(page as *const u8, memlock::page_size());
+        }
+    }
+}
+
+#[cfg(unix)]
+mod memlock {
+    pub fn page_size() -> usize {
+        unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+    }
+
+    pub fn lock(address: *const u8, len: usize) -> Result<(), std::io::Error> {
+        match unsafe { libc::mlock(address.cast(), len) } {
+            0 => Ok(()),
+            _ => Err(std::io::Error::last_os_error()),
+        }
+    }
+
+    pub fn unlock(address: *const u8, len: usize) {
+        unsafe { libc::munlock(address.cast(), len) };
+    }
+}
+
+#[cfg(windows)]
+mod memlock {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn VirtualLock(address: *const std::ffi::c_void, size: usize) -> i32;
+        fn VirtualUnlock(address: *const std::ffi::c_void, size: usize) -> i32;
+    }
+
+    // the page size of every architecture Windows runs on
+    pub fn page_size() -> usize {
+        4096
+    }
+
+    pub fn lock(address: *const u8, len: usize) -> Result<(), std::io::Error> {
+        match unsafe { VirtualLock(address.cast(), len) } {
+            0 => Err(std::io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn unlock(address: *const u8, len: usize) {
+        unsafe { VirtualUnlock(address.cast(), len) };
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod memlock {
+    pub fn page_size() -> usize {
+        4096
+    }
+
+    pub fn lock(_address: *const u8, _len: usize) -> Result<(), &'static str> {
+        Err("this platform can't lock memory")
+    }
+
+    pub fn unlock(_address: *const u8, _len: usize) {}
+}
+
+/// Warns once per run that secrets may be written to swap
+fn warn_unlocked(reason: impl std::fmt::Display) {
+    static WARNED: std::sync::Once = std::sync::Once::new();
+    WARNED.call_once(|| println!("Warning: keys couldn't be locked in memory, so they may be written to swap ({})", reason));
+}
+
+fn cipher(context: &[u8], secret: &[u8]) -> ChaCha20Poly1305 {
+    let mut input = context.to_vec();
+    input.extend_from_slice(secret);
+    ChaCha20Poly1305::new(Key::from_slice(&keccak256(&input)))
 }
 
 #[cfg(test)]
@@ -31,4 +513,123 @@ mod test {
 
         assert_eq!(hex::encode(address), expected);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_salted_hash() {
+        let salt = [7u8; 32];
+        assert_eq!(salted_hash(&salt, "hunter2"), salted_hash(&salt, "hunter2"));
+        assert_ne!(salted_hash(&salt, "hunter2"), salted_hash(&[8u8; 32], "hunter2"));
+        assert_eq!(salted_hash(b"", "abc"), keccak256(b"abc"));
+    }
+
+    #[test]
+    fn test_ct_eq() {
+        assert!(ct_eq(b"", b""));
+        assert!(ct_eq(&[1, 2, 3], &[1, 2, 3]));
+        assert!(!ct_eq(&[1, 2, 3], &[1, 2, 4]));
+        assert!(!ct_eq(&[1, 2, 3], &[1, 2]));
+    }
+
+    #[test]
+    fn test_secret_buffer() {
+        let buffer = SecretBuffer::new(&[1, 2, 3]);
+        assert_eq!(&*buffer, [1, 2, 3]);
+        assert_eq!(&*buffer.clone(), [1, 2, 3]);
+        // nothing to lock in an empty buffer
+        assert!(!SecretBuffer::new(&[]).is_locked());
+        assert!(SecretBuffer::new(&[]).is_empty());
+
+        // two secrets on one page keep it locked until both are dropped
+        let page = memlock::page_size();
+        let count = |bytes: &[u8]| LOCKED_PAGES.lock().unwrap().get(&(bytes.as_ptr() as usize / page * page)).copied();
+        let shared = [0u8; 2];
+        let (first, second) = (&shared[..1], &shared[1..]);
+        if pages_of(first).eq(pages_of(second)) && lock(first) && lock(second) {
+            assert_eq!(count(first), Some(2));
+            unlock(first);
+            assert_eq!(count(second), Some(1));
+            unlock(second);
+            assert_eq!(count(second), None);
+        }
+    }
+
+    #[test]
+    fn test_checksum_address() {
+        // test vectors from EIP-55
+        let vectors = [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+            "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+        ];
+        for expected in vectors {
+            let bytes: [u8; 20] = hex::decode(expected[2..].to_lowercase()).unwrap().try_into().unwrap();
+            assert_eq!(checksum_address(&bytes), expected);
+        }
+    }
+
+    #[test]
+    fn test_sealed_box() {
+        let sealed = SealedBox::seal(b"context", b"secret", b"plaintext");
+        assert_eq!(sealed.open(b"context", b"secret").unwrap(), b"plaintext");
+        assert!(sealed.open(b"context", b"other secret").is_none());
+        assert!(sealed.open(b"other context", b"secret").is_none());
+        // a fresh nonce every time
+        assert_ne!(SealedBox::seal(b"context", b"secret", b"plaintext"), sealed);
+    }
+
+    #[test]
+    fn test_password_box() {
+        // a low cost keeps the test fast
+        let argon2 = || PasswordKdf::Argon2id(Argon2Params::new(64, 1, 1));
+        let sealed = PasswordBox::seal("password", b"seed", argon2());
+        assert_eq!(*sealed.open("password").unwrap(), b"seed");
+        assert!(sealed.open("wrong password").is_none());
+        assert_ne!(PasswordBox::seal("password", b"seed", argon2()).kdfparams, sealed.kdfparams);
+
+        let mut tampered = sealed.clone();
+        tampered.ciphertext.replace_range(..2, if tampered.ciphertext.starts_with("00") { "01" } else { "00" });
+        assert!(tampered.open("password").is_none());
+        let mut weakened = sealed.clone();
+        if let PasswordKdf::Argon2id(params) = &mut weakened.kdfparams {
+            params.iterations = 0;
+        }
+        assert!(weakened.open("password").is_none());
+
+        // the same salt and password always give the same key
+        let params = Argon2Params::with_salt([7; 16], 64, 1, 1);
+        assert_eq!(params.derive_key("password"), Argon2Params::with_salt([7; 16], 64, 1, 1).derive_key("password"));
+        assert_ne!(params.derive_key("password"), Argon2Params::with_salt([8; 16], 64, 1, 1).derive_key("password"));
+
+        // a key sealed with opens with a copy of it too, and its MACs depend on the key and the data
+        let key = PasswordKey::derive("password", argon2()).unwrap();
+        let sealed = PasswordBox::seal_with_key(&key, b"seed", [0; 12]);
+        assert_eq!(*sealed.open("password").unwrap(), b"seed");
+        let copied = PasswordKey::from_bytes(sealed.kdf().clone(), key.to_bytes());
+        assert_eq!(*sealed.open_with_key(&copied).unwrap(), b"seed");
+        assert_eq!(copied.mac(b"wallet"), key.mac(b"wallet"));
+        assert_ne!(key.mac(b"wallet"), key.mac(b"wallel"));
+        assert_ne!(PasswordKey::derive("password", argon2()).unwrap().mac(b"wallet"), key.mac(b"wallet"));
+    }
+
+    #[test]
+    fn test_password_kdfs() {
+        // the start of the scrypt test vector from RFC 7914
+        let scrypt = ScryptParams { salt: hex::encode(b"NaCl"), log_n: 10, r: 8, p: 16 };
+        assert_eq!(hex::encode(*scrypt.derive_key("password").unwrap()), "fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162");
+        // and of the PBKDF2-HMAC-SHA512 one with a single iteration
+        assert_eq!(hex::encode(*pbkdf2_key("password", b"salt", 1)), "867f70cf1ade02cff3752599a3a53dc4af34c7a669815ae5d513554e1c8cf252");
+
+        assert_eq!(Pbkdf2Params::new(1000).iterations, MIN_PBKDF2_ITERATIONS);
+        assert!(Pbkdf2Params { salt: String::new(), iterations: 1000 }.derive_key("password").is_none());
+
+        // each function's parameters read back as that function's
+        for kdf in [PasswordKdf::Scrypt(ScryptParams::new(4)), PasswordKdf::Pbkdf2(Pbkdf2Params::new(0))] {
+            let sealed = PasswordBox::seal("password", b"seed", kdf.clone());
+            let reparsed: PasswordBox = serde_json::from_str(&serde_json::to_string(&sealed).unwrap()).unwrap();
+            assert_eq!(reparsed.kdfparams, kdf);
+            assert_eq!(*reparsed.open("password").unwrap(), b"seed");
+            assert!(reparsed.open("wrong password").is_none());
+        }
+    }
+}
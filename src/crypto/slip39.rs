@@ -0,0 +1,448 @@
+use hmac::{Hmac, KeyInit, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use super::ct_eq;
+
+/// The 1024 SLIP-39 words, in alphabetical order
+const WORDLIST: &str = include_str!("slip39_wordlist.txt");
+const RADIX_BITS: usize = 10;
+/// Identifier, extendable flag and iteration exponent (2 words), then the group and member
+/// parameters (2 words)
+const PREFIX_WORDS: usize = 4;
+const CHECKSUM_WORDS: usize = 3;
+/// A share of a 128 bit secret
+pub const MIN_SHARE_WORDS: usize = 20;
+pub const MAX_SHARES: u8 = 16;
+/// x coordinates of the digest and the secret in the sharing polynomial
+const DIGEST_INDEX: u8 = 254;
+const SECRET_INDEX: u8 = 255;
+const DIGEST_LEN: usize = 4;
+const BASE_ITERATION_COUNT: u32 = 10000;
+const ROUND_COUNT: u8 = 4;
+/// PBKDF2 cost of new backups, 2^e times the base count. Trezor uses 1 as well.
+const ITERATION_EXPONENT: u8 = 1;
+const CHECKSUM_GENERATOR: [u32; 10] = [
+    0x00e0_e040, 0x01c1_c080, 0x0383_8100, 0x0707_0200, 0x0e0e_0009,
+    0x1c0c_2412, 0x3808_6c24, 0x3090_fc48, 0x21b1_f890, 0x03f3_f120,
+];
+
+/// Exponent and logarithm tables of GF(256) with the Rijndael polynomial, generator x + 1
+const GF_TABLES: ([u8; 255], [u8; 256]) = gf_tables();
+
+const fn gf_tables() -> ([u8; 255], [u8; 256]) {
+    let mut exp = [0u8; 255];
+    let mut log = [0u8; 256];
+    let mut poly: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = poly as u8;
+        log[poly as usize] = i as u8;
+        poly ^= poly << 1;
+        if poly & 0x100 != 0 {
+            poly ^= 0x11b;
+        }
+        i += 1;
+    }
+    (exp, log)
+}
+
+/// One share of a SLIP-39 backup
+#[derive(Clone, Debug, PartialEq)]
+pub struct Share {
+    /// Random 15 bit value shared by every share of a backup
+    identifier: u16,
+    /// Extendable backups don't mix the identifier into the encryption
+    extendable: bool,
+    iteration_exponent: u8,
+    group_index: u8,
+    group_threshold: u8,
+    group_count: u8,
+    member_index: u8,
+    member_threshold: u8,
+    value: Zeroizing<Vec<u8>>,
+}
+
+impl Share {
+    /// Parses and checks the words of a share
+    pub fn parse(mnemonic: &str) -> Result<Share, String> {
+        let words: Vec<&str> = WORDLIST.lines().collect();
+        let indices = mnemonic.split_whitespace()
+            .map(|word| {
+                let word = word.to_lowercase();
+                words.binary_search(&word.as_str()).map(|i| i as u16).map_err(|_e| format!("\"{}\" is not a SLIP-39 word", word))
+            })
+            .collect::<Result<Vec<u16>, String>>()?;
+        if indices.len() < MIN_SHARE_WORDS {
+            return Err(format!("A share has at least {} words", MIN_SHARE_WORDS));
+        }
+        let padding = padding_bits(indices.len()).ok_or_else(|| String::from("No share has that many words"))?;
+
+        let extendable = (indices[1] >> 4) & 1 == 1;
+        if checksum(extendable, &indices) != 1 {
+            return Err(String::from("The share's checksum is wrong, so a word is missing or mistyped"));
+        }
+
+        let id_exp = (indices[0] as u32) << RADIX_BITS | indices[1] as u32;
+        let params = (indices[2] as u32) << RADIX_BITS | indices[3] as u32;
+        let field = |shift: u32| ((params >> shift) & 0xf) as u8;
+        let share = Share {
+            identifier: (id_exp >> 5) as u16,
+            extendable,
+            iteration_exponent: (id_exp & 0xf) as u8,
+            group_index: field(16),
+            group_threshold: field(12) + 1,
+            group_count: field(8) + 1,
+            member_index: field(4),
+            member_threshold: field(0) + 1,
+            value: unpack(&indices[PREFIX_WORDS..indices.len() - CHECKSUM_WORDS], padding)?,
+        };
+        if share.group_threshold > share.group_count {
+            return Err(String::from("The share needs more groups than its backup has"));
+        }
+        Ok(share)
+    }
+
+    /// The words of this share
+    pub fn to_mnemonic(&self) -> Zeroizing<String> {
+        let id_exp = (self.identifier as u32) << 5 | (self.extendable as u32) << 4 | self.iteration_exponent as u32;
+        let params = (self.group_index as u32) << 16
+            | ((self.group_threshold - 1) as u32) << 12
+            | ((self.group_count - 1) as u32) << 8
+            | (self.member_index as u32) << 4
+            | (self.member_threshold - 1) as u32;
+
+        let mut indices = vec![(id_exp >> RADIX_BITS) as u16, (id_exp & 0x3ff) as u16, (params >> RADIX_BITS) as u16, (params & 0x3ff) as u16];
+        indices.extend(pack(&self.value));
+        indices.extend([0; CHECKSUM_WORDS]);
+        let polymod = checksum(self.extendable, &indices) ^ 1;
+        let length = indices.len();
+        for (i, index) in indices[length - CHECKSUM_WORDS..].iter_mut().enumerate() {
+            *index = ((polymod >> (RADIX_BITS * (CHECKSUM_WORDS - 1 - i))) & 0x3ff) as u16;
+        }
+
+        let words: Vec<&str> = WORDLIST.lines().collect();
+        let mnemonic: Vec<&str> = indices.iter().map(|i| words[*i as usize]).collect();
+        Zeroizing::new(mnemonic.join(" "))
+    }
+
+    /// Whether both shares belong to the same backup
+    pub fn same_backup(&self, other: &Share) -> bool {
+        (self.identifier, self.extendable, self.iteration_exponent, self.group_threshold, self.group_count)
+            == (other.identifier, other.extendable, other.iteration_exponent, other.group_threshold, other.group_count)
+    }
+}
+
+/// Whether a share with this many words is possible
+pub fn is_share_length(words: usize) -> bool {
+    words >= MIN_SHARE_WORDS && padding_bits(words).is_some()
+}
+
+/// The zero bits in front of the share value, None if no share has that many words
+fn padding_bits(words: usize) -> Option<usize> {
+    let bits = RADIX_BITS * words.checked_sub(PREFIX_WORDS + CHECKSUM_WORDS)?;
+    Some(bits % 16).filter(|padding| *padding <= 8)
+}
+
+/// Splits `secret` into `count` shares, any `threshold` of which recover it. The secret is
+/// encrypted with `passphrase` first, so the shares recover a different secret without it.
+pub fn split(secret: &[u8], threshold: u8, count: u8, passphrase: &str) -> Result<Vec<Zeroizing<String>>, String> {
+    if secret.len() < 16 || !secret.len().is_multiple_of(2) {
+        return Err(String::from("The secret must be at least 128 bits, in whole 16 bit words"));
+    }
+    if count == 0 || count > MAX_SHARES || threshold == 0 || threshold > count {
+        return Err(format!("There can be 1 to {} shares, and the threshold can't be more than the number of shares", MAX_SHARES));
+    }
+    if threshold == 1 && count > 1 {
+        return Err(String::from("With a threshold of 1 every share is a copy of the secret. Use a threshold of at least 2."));
+    }
+    check_passphrase(passphrase)?;
+
+    let identifier = (rand::random::<u16>()) & 0x7fff;
+    let encrypted = encrypt(secret, passphrase, ITERATION_EXPONENT, identifier, false);
+    let shares = split_secret(threshold, count, &encrypted);
+    Ok(shares.into_iter()
+        .map(|(member_index, value)| Share {
+            identifier,
+            extendable: false,
+            iteration_exponent: ITERATION_EXPONENT,
+            group_index: 0,
+            group_threshold: 1,
+            group_count: 1,
+            member_index,
+            member_threshold: threshold,
+            value,
+        }.to_mnemonic())
+        .collect())
+}
+
+/// Whether there are enough shares to try `combine`
+pub fn is_complete(shares: &[Share]) -> bool {
+    shares.first().is_some_and(|first| complete_groups(shares).is_ok_and(|groups| groups.len() >= first.group_threshold as usize))
+}
+
+/// Recovers the secret from the shares of a backup. A wrong passphrase isn't detected: it
+/// recovers a different secret.
+pub fn combine(shares: &[Share], passphrase: &str) -> Result<Zeroizing<Vec<u8>>, String> {
+    let first = shares.first().ok_or_else(|| String::from("No shares were given"))?;
+    check_passphrase(passphrase)?;
+    let groups = complete_groups(shares)?;
+    if groups.len() < first.group_threshold as usize {
+        return Err(String::from("Not enough shares to recover the secret"));
+    }
+
+    let mut group_secrets = vec![];
+    for (group_index, members) in groups.iter().take(first.group_threshold as usize) {
+        let points: Vec<(u8, &[u8])> = members.iter().map(|s| (s.member_index, s.value.as_slice())).collect();
+        group_secrets.push((*group_index, recover_secret(members[0].member_threshold, &points)?));
+    }
+    let points: Vec<(u8, &[u8])> = group_secrets.iter().map(|(i, secret)| (*i, secret.as_slice())).collect();
+    let encrypted = recover_secret(first.group_threshold, &points)?;
+    Ok(decrypt(&encrypted, passphrase, first.iteration_exponent, first.identifier, first.extendable))
+}
+
+/// The groups with at least their threshold of distinct shares, each cut down to that threshold
+fn complete_groups(shares: &[Share]) -> Result<Vec<(u8, Vec<&Share>)>, String> {
+    let first = &shares[0];
+    if shares.iter().any(|s| !s.same_backup(first)) {
+        return Err(String::from("The shares are not all from the same backup"));
+    }
+    if shares.iter().any(|s| s.value.len() != first.value.len()) {
+        return Err(String::from("The shares are not all the same length"));
+    }
+
+    let mut groups = vec![];
+    for group_index in 0..first.group_count {
+        let mut members: Vec<&Share> = vec![];
+        for share in shares.iter().filter(|s| s.group_index == group_index) {
+            match members.iter().find(|m| m.member_index == share.member_index) {
+                // the same share entered twice
+                Some(member) if member.value == share.value => {},
+                Some(_) => return Err(String::from("Two different shares have the same member index")),
+                None => members.push(share),
+            }
+        }
+        if members.iter().any(|m| m.member_threshold != members[0].member_threshold) {
+            return Err(String::from("The shares of a group disagree on its threshold"));
+        }
+        if let Some(threshold) = members.first().map(|m| m.member_threshold as usize) {
+            if members.len() >= threshold {
+                members.truncate(threshold);
+                groups.push((group_index, members));
+            }
+        }
+    }
+    Ok(groups)
+}
+
+fn check_passphrase(passphrase: &str) -> Result<(), String> {
+    match passphrase.bytes().all(|b| (32..=126).contains(&b)) {
+        true => Ok(()),
+        false => Err(String::from("A SLIP-39 passphrase can only use printable ASCII characters")),
+    }
+}
+
+/// The points of a polynomial of degree `threshold - 1` through the secret, the digest and
+/// `threshold - 2` random points, at x = 0 to count - 1
+fn split_secret(threshold: u8, count: u8, secret: &[u8]) -> Vec<(u8, Zeroizing<Vec<u8>>)> {
+    if threshold == 1 {
+        return (0..count).map(|i| (i, Zeroizing::new(secret.to_vec()))).collect();
+    }
+    let random_count = threshold - 2;
+    let mut shares: Vec<(u8, Zeroizing<Vec<u8>>)> = (0..random_count).map(|i| (i, random_bytes(secret.len()))).collect();
+    let random_part = random_bytes(secret.len() - DIGEST_LEN);
+    let mut digest = Zeroizing::new(create_digest(&random_part, secret).to_vec());
+    digest.extend_from_slice(&random_part);
+
+    let mut base: Vec<(u8, &[u8])> = shares.iter().map(|(i, value)| (*i, value.as_slice())).collect();
+    base.push((DIGEST_INDEX, &digest));
+    base.push((SECRET_INDEX, secret));
+    let derived: Vec<_> = (random_count..count).map(|i| (i, interpolate(&base, i))).collect();
+    shares.extend(derived);
+    shares
+}
+
+/// Recovers the secret from `threshold` points and checks it against the digest
+fn recover_secret(threshold: u8, points: &[(u8, &[u8])]) -> Result<Zeroizing<Vec<u8>>, String> {
+    if threshold == 1 {
+        return Ok(Zeroizing::new(points[0].1.to_vec()));
+    }
+    let secret = interpolate(points, SECRET_INDEX);
+    let digest = interpolate(points, DIGEST_INDEX);
+    if !ct_eq(&digest[..DIGEST_LEN], &create_digest(&digest[DIGEST_LEN..], &secret)) {
+        return Err(String::from("The shares don't fit together. One of them may be from another backup."));
+    }
+    Ok(secret)
+}
+
+/// Lagrange interpolation of `points` at `x`, in GF(256) byte by byte
+fn interpolate(points: &[(u8, &[u8])], x: u8) -> Zeroizing<Vec<u8>> {
+    if let Some((_, value)) = points.iter().find(|(px, _)| *px == x) {
+        return Zeroizing::new(value.to_vec());
+    }
+    let (exp, log) = &GF_TABLES;
+    let log_of = |a: u8| log[a as usize] as i64;
+    let log_product: i64 = points.iter().map(|(px, _)| log_of(px ^ x)).sum();
+
+    let mut result = Zeroizing::new(vec![0u8; points[0].1.len()]);
+    for (px, value) in points {
+        let log_others: i64 = points.iter().map(|(other, _)| log_of(px ^ other)).sum();
+        let log_basis = log_product - log_of(px ^ x) - log_others;
+        for (r, v) in result.iter_mut().zip(value.iter()) {
+            if *v != 0 {
+                *r ^= exp[(log_of(*v) + log_basis).rem_euclid(255) as usize];
+            }
+        }
+    }
+    result
+}
+
+fn create_digest(random_part: &[u8], secret: &[u8]) -> [u8; DIGEST_LEN] {
+    let mut mac = <Hmac<Sha256> as KeyInit>::new_from_slice(random_part).unwrap();
+    mac.update(secret);
+    mac.finalize().into_bytes()[..DIGEST_LEN].try_into().unwrap()
+}
+
+/// The four round Feistel network that encrypts the secret before it is split
+fn encrypt(secret: &[u8], passphrase: &str, iteration_exponent: u8, identifier: u16, extendable: bool) -> Zeroizing<Vec<u8>> {
+    feistel(secret, passphrase, iteration_exponent, identifier, extendable, &[0, 1, 2, 3])
+}
+
+fn decrypt(encrypted: &[u8], passphrase: &str, iteration_exponent: u8, identifier: u16, extendable: bool) -> Zeroizing<Vec<u8>> {
+    feistel(encrypted, passphrase, iteration_exponent, identifier, extendable, &[3, 2, 1, 0])
+}
+
+fn feistel(input: &[u8], passphrase: &str, iteration_exponent: u8, identifier: u16, extendable: bool, rounds: &[u8]) -> Zeroizing<Vec<u8>> {
+    let half = input.len() / 2;
+    let (mut left, mut right) = (Zeroizing::new(input[..half].to_vec()), Zeroizing::new(input[half..].to_vec()));
+    // holds a copy of half the secret each round
+    let mut salt = Zeroizing::new(match extendable {
+        true => vec![],
+        false => [b"shamir".as_slice(), &identifier.to_be_bytes()].concat(),
+    });
+    let salt_len = salt.len();
+    let iterations = (BASE_ITERATION_COUNT << iteration_exponent) / ROUND_COUNT as u32;
+
+    for round in rounds {
+        let password = Zeroizing::new([&[*round], passphrase.as_bytes()].concat());
+        salt.truncate(salt_len);
+        salt.extend_from_slice(&right);
+        let mut f = Zeroizing::new(vec![0u8; half]);
+        pbkdf2::pbkdf2_hmac::<Sha256>(&password, &salt, iterations, &mut f);
+        for (l, f) in left.iter_mut().zip(f.iter()) {
+            *l ^= f;
+        }
+        std::mem::swap(&mut left, &mut right);
+    }
+    let mut output = right;
+    output.extend_from_slice(&left);
+    output
+}
+
+/// The 10 bit words of `value`, with zero padding in front to fill the first word
+fn pack(value: &[u8]) -> Vec<u16> {
+    let bits = value.len() * 8;
+    let padding = (RADIX_BITS - bits % RADIX_BITS) % RADIX_BITS;
+    let stream = std::iter::repeat_n(0u16, padding).chain(value.iter().flat_map(|byte| (0..8).rev().map(move |i| (*byte as u16 >> i) & 1)));
+    let stream: Vec<u16> = stream.collect();
+    stream.chunks(RADIX_BITS).map(|chunk| chunk.iter().fold(0, |word, bit| word << 1 | bit)).collect()
+}
+
+/// The inverse of `pack`. The padding must be zero.
+fn unpack(words: &[u16], padding: usize) -> Result<Zeroizing<Vec<u8>>, String> {
+    let bits: Vec<u8> = words.iter().flat_map(|word| (0..RADIX_BITS).rev().map(move |i| ((word >> i) & 1) as u8)).collect();
+    if bits[..padding].iter().any(|bit| *bit != 0) {
+        return Err(String::from("The share's padding is invalid"));
+    }
+    Ok(Zeroizing::new(bits[padding..].chunks(8).map(|chunk| chunk.iter().fold(0, |byte, bit| byte << 1 | bit)).collect()))
+}
+
+/// The RS1024 checksum of the words, salted with the customization string. 1 means valid.
+fn checksum(extendable: bool, indices: &[u16]) -> u32 {
+    let customization: &[u8] = if extendable { b"shamir_extendable" } else { b"shamir" };
+    let values = customization.iter().map(|b| *b as u32).chain(indices.iter().map(|i| *i as u32));
+    values.fold(1, |chk, value| {
+        let top = chk >> 20;
+        let chk = (chk & 0xfffff) << 10 ^ value;
+        (0..10).filter(|i| (top >> i) & 1 == 1).fold(chk, |chk, i| chk ^ CHECKSUM_GENERATOR[i])
+    })
+}
+
+fn random_bytes(len: usize) -> Zeroizing<Vec<u8>> {
+    let mut bytes = Zeroizing::new(vec![0u8; len]);
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_all(mnemonics: &[&str]) -> Vec<Share> {
+        mnemonics.iter().map(|m| Share::parse(m).unwrap()).collect()
+    }
+
+    /// Vectors from the SLIP-39 specification, all with the passphrase "TREZOR"
+    #[test]
+    fn test_spec_vectors() {
+        let single = parse_all(&["duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision keyboard"]);
+        assert_eq!(hex::encode(&*combine(&single, "TREZOR").unwrap()), "bb54aac4b89dc868ba37d9cc21b2cece");
+        assert_eq!(*single[0].to_mnemonic(), "duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision keyboard");
+
+        let two_of_three = parse_all(&[
+            "shadow pistol academic always adequate wildlife fancy gross oasis cylinder mustang wrist rescue view short owner flip making coding armed",
+            "shadow pistol academic acid actress prayer class unknown daughter sweater depict flip twice unkind craft early superior advocate guest smoking",
+        ]);
+        assert!(!is_complete(&two_of_three[..1]));
+        assert_eq!(hex::encode(&*combine(&two_of_three, "TREZOR").unwrap()), "b43ceb7e57a0ea8766221624d01b0864");
+
+        let groups = parse_all(&[
+            "eraser senior beard romp adorn nuclear spill corner cradle style ancient family general leader ambition exchange unusual garlic promise voice",
+            "eraser senior acrobat romp bishop medical gesture pumps secret alive ultimate quarter priest subject class dictate spew material endless market",
+        ]);
+        assert_eq!(hex::encode(&*combine(&groups, "TREZOR").unwrap()), "7c3397a292a5941682d7a4ae2d898d11");
+
+        let long = parse_all(&["theory painting academic academic armed sweater year military elder discuss acne wildlife boring employer fused large satoshi bundle carbon diagnose anatomy hamster leaves tracks paces beyond phantom capital marvel lips brave detect luck"]);
+        assert_eq!(hex::encode(&*combine(&long, "TREZOR").unwrap()), "989baf9dcaad5b10ca33dfd8cc75e42477025dce88ae83e75a230086a0e00e92");
+    }
+
+    #[test]
+    fn test_invalid_shares() {
+        assert_eq!(Share::parse("duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision kidney").unwrap_err(), "The share's checksum is wrong, so a word is missing or mistyped");
+        assert_eq!(Share::parse("duckling enlarge academic academic email result length solution fridge kidney coal piece deal husband erode duke ajar music cargo fitness").unwrap_err(), "The share's padding is invalid");
+        assert_eq!(Share::parse("duckling enlarge academic").unwrap_err(), "A share has at least 20 words");
+        assert_eq!(Share::parse("abandon").unwrap_err(), "\"abandon\" is not a SLIP-39 word");
+
+        let digest_mismatch = parse_all(&[
+            "guilt walnut academic acid deliver remove equip listen vampire tactics nylon rhythm failure husband fatigue alive blind enemy teaspoon rebound",
+            "guilt walnut academic agency brave hamster hobo declare herd taste alpha slim criminal mild arcade formal romp branch pink ambition",
+        ]);
+        assert_eq!(combine(&digest_mismatch, "TREZOR").unwrap_err(), "The shares don't fit together. One of them may be from another backup.");
+
+        assert!(is_share_length(20) && is_share_length(33) && is_share_length(59));
+        assert!(!is_share_length(12) && !is_share_length(21) && !is_share_length(24));
+    }
+
+    #[test]
+    fn test_split_round_trip() {
+        let seed: Vec<u8> = (0..64).collect();
+        let mnemonics = split(&seed, 3, 5, "correct horse").unwrap();
+        assert_eq!(mnemonics.len(), 5);
+        assert!(mnemonics.iter().all(|m| m.split_whitespace().count() == 59));
+
+        let shares: Vec<Share> = mnemonics.iter().map(|m| Share::parse(m).unwrap()).collect();
+        assert!(!is_complete(&shares[..2]));
+        // any three shares, in any order
+        assert_eq!(*combine(&[shares[4].clone(), shares[0].clone(), shares[2].clone()], "correct horse").unwrap(), seed);
+        assert_eq!(*combine(&shares[1..4], "correct horse").unwrap(), seed);
+        assert_ne!(*combine(&shares[1..4], "").unwrap(), seed);
+
+        let single = split(&seed[..16], 1, 1, "").unwrap();
+        assert_eq!(*combine(&[Share::parse(&single[0]).unwrap()], "").unwrap(), seed[..16]);
+
+        assert!(split(&seed, 1, 3, "").is_err());
+        assert!(split(&seed, 4, 3, "").is_err());
+        assert!(split(&seed[..15], 2, 3, "").is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use directories::ProjectDirs;
+
+/// Environment variable that overrides the default data directory
+pub const ENV_VAR: &str = "BASIC_ETH_DATA_DIR";
+/// Files that mark a directory as holding wallet data from before the data directory existed
+const LEGACY_FILES: [&str; 2] = ["wallets.json", "userdata.txt"];
+
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Picks the data directory and creates it if it is missing. `flag` is the `--data-dir`
+/// argument, which wins over the environment variable, which wins over the platform's data
+/// directory. Call it once at startup, before anything is read.
+pub fn init(flag: Option<&str>) -> Result<&'static Path, String> {
+    let env = std::env::var(ENV_VAR).ok().filter(|dir| !dir.is_empty());
+    let default = ProjectDirs::from("", "", "basic-eth").map(|dirs| dirs.data_dir().to_path_buf());
+    let legacy_in_cwd = LEGACY_FILES.iter().any(|file| Path::new(file).exists());
+    let dir = choose(flag, env.as_deref(), default.as_deref(), legacy_in_cwd)?;
+    if dir.as_os_str() == "." && flag.is_none() && env.is_none() {
+        eprintln!("Using the wallet data in the current directory. To open it from anywhere, move it to {}, or set {}.",
+            default.as_deref().map_or_else(|| String::from("a data directory"), |d| d.display().to_string()), ENV_VAR);
+    }
+    create(&dir)?;
+    Ok(DATA_DIR.get_or_init(|| dir))
+}
+
+/// Where wallet data goes. Nothing set, or data from before the data directory existed in the
+/// working directory (and none in the default one), keeps using the working directory.
+fn choose(flag: Option<&str>, env: Option<&str>, default: Option<&Path>, legacy_in_cwd: bool) -> Result<PathBuf, String> {
+    if let Some(dir) = flag.or(env) {
+        return Ok(PathBuf::from(dir));
+    }
+    let default = default.ok_or_else(|| format!("No home directory was found. Choose a data directory with --data-dir or {}.", ENV_VAR))?;
+    if legacy_in_cwd && !LEGACY_FILES.iter().any(|file| default.join(file).exists()) {
+        return Ok(PathBuf::from("."));
+    }
+    Ok(default.to_path_buf())
+}
+
+/// Creates the directory tree, readable by this user only on unix
+fn create(dir: &Path) -> Result<(), String> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir).map_err(|e| match e.kind() {
+        std::io::ErrorKind::PermissionDenied => format!("No permission to create the data directory {}. Choose another with --data-dir or {}.", dir.display(), ENV_VAR),
+        _ => format!("Error creating the data directory {}: {}", dir.display(), e),
+    })
+}
+
+/// The data directory chosen by `init`, or the working directory if it wasn't called (library
+/// use)
+#[cfg(not(test))]
+pub fn dir() -> &'static Path {
+    DATA_DIR.get().map_or(Path::new("."), PathBuf::as_path)
+}
+
+/// Unit tests get a temporary data directory, so what they write never lands in the crate root
+#[cfg(test)]
+pub fn dir() -> &'static Path {
+    DATA_DIR.get_or_init(|| {
+        let dir = std::env::temp_dir().join(format!("basic-eth-tests-{}", std::process::id()));
+        create(&dir).unwrap();
+        dir
+    })
+}
+
+/// Resolves a file in the data directory. An absolute path stays as it is.
+pub fn path(file: &str) -> PathBuf {
+    dir().join(file)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_choose() {
+        let default = Path::new("/home/user/.local/share/basic-eth");
+        assert_eq!(choose(Some("/flag"), Some("/env"), Some(default), true).unwrap(), Path::new("/flag"));
+        assert_eq!(choose(None, Some("/env"), Some(default), true).unwrap(), Path::new("/env"));
+        assert_eq!(choose(None, None, Some(default), false).unwrap(), default);
+        // wallets left in the working directory aren't lost
+        assert_eq!(choose(None, None, Some(default), true).unwrap(), Path::new("."));
+        assert!(choose(None, None, None, false).unwrap_err().starts_with("No home directory was found"));
+    }
+
+    #[test]
+    fn test_path() {
+        assert_eq!(path("wallets.json"), dir().join("wallets.json"));
+        assert!(dir().starts_with(std::env::temp_dir()));
+        assert_eq!(path("/tmp/wallet.txt"), Path::new("/tmp/wallet.txt"));
+    }
+}
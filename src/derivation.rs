@@ -0,0 +1,187 @@
+use bip32::ChildNumber;
+use serde::{Serialize, Deserialize};
+
+/// Marks the path component that counts accounts
+const INDEX_PLACEHOLDER: &str = "{index}";
+
+/// MetaMask, MyEtherWallet and most other wallets: m/44'/60'/0'/0/x
+pub const STANDARD_TEMPLATE: &str = "m/44'/60'/0'/0/{index}";
+/// Ledger Live puts each account under its own hardened account key: m/44'/60'/x'/0/0
+pub const LEDGER_LIVE_TEMPLATE: &str = "m/44'/60'/{index}'/0/0";
+
+/// Where account keys sit in the HD tree, as a path with an `{index}` component. The key at the
+/// components before `{index}` (the root) derives every account.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct PathTemplate {
+    template: String,
+    /// Components before `{index}`
+    root: Vec<ChildNumber>,
+    /// Whether `{index}` is hardened
+    hardened: bool,
+    /// Components after `{index}`
+    tail: Vec<ChildNumber>,
+}
+
+impl PathTemplate {
+    /// Parses a template such as `m/44'/60'/{index}'/0/0`. `{index}` must appear exactly once.
+    pub fn parse(template: &str) -> Result<PathTemplate, String> {
+        let template = template.trim();
+        let mut components = template.split('/');
+        if components.next() != Some("m") {
+            return Err(String::from("A path must start with m/"));
+        }
+
+        let (mut root, mut tail) = (vec![], vec![]);
+        let mut hardened = None;
+        for component in components {
+            match component.strip_prefix(INDEX_PLACEHOLDER) {
+                Some(_) if hardened.is_some() => return Err(format!("{} may only appear once", INDEX_PLACEHOLDER)),
+                Some("'") => hardened = Some(true),
+                Some("") => hardened = Some(false),
+                _ => {
+                    let child_number = parse_component(component)?;
+                    if hardened.is_some() {
+                        tail.push(child_number);
+                    } else {
+                        root.push(child_number);
+                    }
+                },
+            }
+        }
+
+        match hardened {
+            Some(hardened) => Ok(PathTemplate { template: String::from(template), root, hardened, tail }),
+            None => Err(format!("A path must have an {} component", INDEX_PLACEHOLDER)),
+        }
+    }
+
+    pub fn standard() -> PathTemplate {
+        PathTemplate::parse(STANDARD_TEMPLATE).unwrap()
+    }
+
+    pub fn ledger_live() -> PathTemplate {
+        PathTemplate::parse(LEDGER_LIVE_TEMPLATE).unwrap()
+    }
+
+    pub fn is_standard(&self) -> bool {
+        *self == PathTemplate::standard()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.template
+    }
+
+    /// The path of the key that derives every account, e.g. m/44'/60' for Ledger Live
+    pub fn root(&self) -> String {
+        let mut path = String::from("m");
+        for child_number in &self.root {
+            path += &format!("/{}", format_component(*child_number));
+        }
+        path
+    }
+
+    /// The full path of the account at `index`
+    pub fn path(&self, index: u32) -> String {
+        let mut path = self.root();
+        for child_number in self.relative(index) {
+            path += &format!("/{}", format_component(child_number));
+        }
+        path
+    }
+
+    /// The components leading from the root to the account at `index`
+    pub fn relative(&self, index: u32) -> Vec<ChildNumber> {
+        let mut components = vec![ChildNumber::new(index, self.hardened).unwrap()];
+        components.extend_from_slice(&self.tail);
+        components
+    }
+}
+
+impl Default for PathTemplate {
+    fn default() -> Self {
+        PathTemplate::standard()
+    }
+}
+
+impl TryFrom<String> for PathTemplate {
+    type Error = String;
+
+    fn try_from(template: String) -> Result<Self, Self::Error> {
+        PathTemplate::parse(&template)
+    }
+}
+
+impl From<PathTemplate> for String {
+    fn from(template: PathTemplate) -> Self {
+        template.template
+    }
+}
+
+/// Returns the components of `path` below a key at `depth`, or None if `path` isn't that deep
+pub fn components_below(path: &str, depth: u8) -> Option<Vec<ChildNumber>> {
+    let components: Vec<&str> = path.strip_prefix("m/")?.split('/').collect();
+    if components.len() <= depth as usize {
+        return None;
+    }
+    components[depth as usize..].iter().map(|c| parse_component(c).ok()).collect()
+}
+
+fn parse_component(component: &str) -> Result<ChildNumber, String> {
+    let (number, hardened) = match component.strip_suffix('\'') {
+        Some(number) => (number, true),
+        None => (component, false),
+    };
+    number.parse::<u32>().ok()
+        .and_then(|n| ChildNumber::new(n, hardened).ok())
+        .ok_or_else(|| format!("\"{}\" is not a valid path component", component))
+}
+
+fn format_component(child_number: ChildNumber) -> String {
+    if child_number.is_hardened() {
+        format!("{}'", child_number.index())
+    } else {
+        child_number.index().to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_templates() {
+        let standard = PathTemplate::standard();
+        assert_eq!(standard.root(), "m/44'/60'/0'/0");
+        assert_eq!(standard.path(3), "m/44'/60'/0'/0/3");
+
+        let ledger = PathTemplate::ledger_live();
+        assert_eq!(ledger.root(), "m/44'/60'");
+        assert_eq!(ledger.path(3), "m/44'/60'/3'/0/0");
+        assert_eq!(ledger.relative(3), [ChildNumber::new(3, true).unwrap(), ChildNumber(0), ChildNumber(0)]);
+        assert!(!ledger.is_standard());
+
+        let custom = PathTemplate::parse(" m/44'/60'/0'/{index} ").unwrap();
+        assert_eq!(custom.path(7), "m/44'/60'/0'/7");
+        assert_eq!(serde_json::to_string(&custom).unwrap(), "\"m/44'/60'/0'/{index}\"");
+        assert_eq!(serde_json::from_str::<PathTemplate>("\"m/44'/60'/0'/{index}\"").unwrap(), custom);
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        assert_eq!(PathTemplate::parse("44'/60'/{index}").unwrap_err(), "A path must start with m/");
+        assert_eq!(PathTemplate::parse("m/44'/60'/0'/0/0").unwrap_err(), "A path must have an {index} component");
+        assert_eq!(PathTemplate::parse("m/{index}/{index}").unwrap_err(), "{index} may only appear once");
+        assert_eq!(PathTemplate::parse("m/44'/sixty/{index}").unwrap_err(), "\"sixty\" is not a valid path component");
+        assert!(PathTemplate::parse("m/{index}x").is_err());
+        assert!(serde_json::from_str::<PathTemplate>("\"m/0/0\"").is_err());
+    }
+
+    #[test]
+    fn test_components_below() {
+        assert_eq!(components_below("m/44'/60'/0'/0/5", 4).unwrap(), [ChildNumber(5)]);
+        assert_eq!(components_below("m/44'/60'/2'/0/0", 2).unwrap(), [ChildNumber::new(2, true).unwrap(), ChildNumber(0), ChildNumber(0)]);
+        assert_eq!(components_below("m/44'/60'/0'/0", 4), None);
+        assert_eq!(components_below("imported", 0), None);
+    }
+}
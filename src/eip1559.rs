@@ -0,0 +1,106 @@
+use bip32::secp256k1::ecdsa::{recoverable, signature::Signer, SigningKey};
+use rlp::RlpStream;
+
+/// The EIP-2718 type of an EIP-1559 transaction, which its envelope starts with
+const TRANSACTION_TYPE: u8 = 0x02;
+
+/// An EIP-1559 (type 2) transfer. Instead of a single gas price it caps what is paid per gas at
+/// `max_fee_per_gas`: the block's base fee is burned, and up to `max_priority_fee_per_gas` of
+/// what is left goes to the block's producer as a tip.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeMarketTransaction {
+    pub chain_id: u64,
+    pub nonce: u64,
+    pub max_priority_fee_per_gas: u128,
+    pub max_fee_per_gas: u128,
+    pub gas_limit: u128,
+    pub to: [u8; 20],
+    pub value: u128,
+    pub data: Vec<u8>,
+}
+
+impl FeeMarketTransaction {
+    /// Signs the transaction with a secp256k1 secret key and returns the signed envelope, ready
+    /// for `eth_sendRawTransaction`
+    pub fn sign(&self, secret_key: &[u8]) -> Result<Vec<u8>, String> {
+        let key = SigningKey::from_bytes(secret_key).map_err(|e| format!("Invalid private key: {}", e))?;
+        // the signature covers the keccak256 of the unsigned envelope
+        let signature: recoverable::Signature = key.sign(&self.envelope(None));
+        let bytes = signature.as_ref();
+        Ok(self.envelope(Some((bytes[64], &bytes[..32], &bytes[32..64]))))
+    }
+
+    /// The type byte followed by the RLP list of the fields, and of the y-parity, r and s of
+    /// the signature once there is one. Transfers don't use an access list, so it is empty.
+    fn envelope(&self, signature: Option<(u8, &[u8], &[u8])>) -> Vec<u8> {
+        let mut stream = RlpStream::new();
+        stream.begin_unbounded_list();
+        stream.append(&self.chain_id);
+        stream.append(&self.nonce);
+        stream.append(&self.max_priority_fee_per_gas);
+        stream.append(&self.max_fee_per_gas);
+        stream.append(&self.gas_limit);
+        stream.append(&self.to.to_vec());
+        stream.append(&self.value);
+        stream.append(&self.data);
+        stream.begin_list(0);
+        if let Some((y_parity, r, s)) = signature {
+            stream.append(&y_parity);
+            // r and s are integers, so without leading zeros
+            stream.append(&trim_leading_zeros(r));
+            stream.append(&trim_leading_zeros(s));
+        }
+        stream.finalize_unbounded_list();
+        [&[TRANSACTION_TYPE], stream.out().as_ref()].concat()
+    }
+}
+
+fn trim_leading_zeros(bytes: &[u8]) -> Vec<u8> {
+    bytes.iter().skip_while(|b| **b == 0).copied().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use bip32::secp256k1::elliptic_curve::sec1::ToEncodedPoint;
+
+    use super::*;
+    use crate::crypto::generate_eth_address;
+    use crate::utils;
+
+    #[test]
+    fn test_sign() {
+        let secret = utils::parse_private_key("0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80").unwrap();
+        let tx = FeeMarketTransaction {
+            chain_id: 11155111,
+            nonce: 7,
+            max_priority_fee_per_gas: 1_500_000_000,
+            max_fee_per_gas: 30_000_000_000,
+            gas_limit: 21000,
+            to: hex::decode("70997970c51812dc3a010c7d01b50e0d17dc79c8").unwrap().try_into().unwrap(),
+            value: 1_000_000_000_000_000,
+            data: vec![],
+        };
+        let signed = tx.sign(secret.as_slice()).unwrap();
+        assert_eq!(signed[0], TRANSACTION_TYPE);
+
+        let fields = rlp::Rlp::new(&signed[1..]);
+        assert_eq!(fields.item_count().unwrap(), 12);
+        assert_eq!(fields.val_at::<u64>(0).unwrap(), 11155111);
+        assert_eq!(fields.val_at::<u64>(1).unwrap(), 7);
+        assert_eq!(fields.val_at::<u128>(3).unwrap(), 30_000_000_000);
+        assert_eq!(fields.at(8).unwrap().item_count().unwrap(), 0);
+
+        // the signature recovers to the account that signed, over the unsigned envelope
+        let y_parity: u8 = fields.val_at(9).unwrap();
+        let pad = |bytes: Vec<u8>| [vec![0; 32 - bytes.len()], bytes].concat();
+        let bytes = [pad(fields.val_at(10).unwrap()), pad(fields.val_at(11).unwrap()), vec![y_parity]].concat();
+        let signature = recoverable::Signature::try_from(bytes.as_slice()).unwrap();
+        let key = signature.recover_verify_key(&tx.envelope(None)).unwrap();
+        let point = key.to_encoded_point(false);
+        assert_eq!(String::from("0x") + &hex::encode(generate_eth_address(&point.as_bytes()[1..])),
+            utils::secret_key_to_address(&secret).unwrap());
+
+        // signing is deterministic
+        assert_eq!(tx.sign(secret.as_slice()).unwrap(), signed);
+    }
+}
@@ -0,0 +1,109 @@
+use bip32::secp256k1::ecdsa::{recoverable, signature::Signer, SigningKey};
+use rlp::RlpStream;
+
+/// What an EIP-7702 authorization's signature covers starts with, so it can't be mistaken for a
+/// transaction
+const MAGIC: u8 = 0x05;
+
+/// An EIP-7702 authorization: the account lets the code at `address` run as its own, until it
+/// signs another. It is only valid while the account's nonce is `nonce`, and consumes it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Authorization {
+    pub chain_id: u64,
+    pub address: [u8; 20],
+    pub nonce: u64,
+}
+
+/// An authorization with the account's signature, as a type 4 transaction lists it
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedAuthorization {
+    pub authorization: Authorization,
+    pub y_parity: u8,
+    /// Without leading zeros, as the transaction encodes them
+    pub r: Vec<u8>,
+    pub s: Vec<u8>,
+}
+
+impl Authorization {
+    /// Signs the authorization with a secp256k1 secret key
+    pub fn sign(&self, secret_key: &[u8]) -> Result<SignedAuthorization, String> {
+        let key = SigningKey::from_bytes(secret_key).map_err(|e| format!("Invalid private key: {}", e))?;
+        // the signature covers the keccak256 of the magic byte and the RLP list of the fields
+        let signature: recoverable::Signature = key.sign(&self.message());
+        let bytes = signature.as_ref();
+        Ok(SignedAuthorization {
+            authorization: *self,
+            y_parity: bytes[64],
+            r: trim_leading_zeros(&bytes[..32]),
+            s: trim_leading_zeros(&bytes[32..64]),
+        })
+    }
+
+    fn message(&self) -> Vec<u8> {
+        let mut stream = RlpStream::new_list(3);
+        stream.append(&self.chain_id);
+        stream.append(&self.address.to_vec());
+        stream.append(&self.nonce);
+        [&[MAGIC], stream.out().as_ref()].concat()
+    }
+}
+
+impl SignedAuthorization {
+    /// As a relayer is given it and a node lists it in `authorizationList`
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "chainId": format!("{:#x}", self.authorization.chain_id),
+            "address": String::from("0x") + &hex::encode(self.authorization.address),
+            "nonce": format!("{:#x}", self.authorization.nonce),
+            "yParity": format!("{:#x}", self.y_parity),
+            "r": String::from("0x") + &hex::encode(&self.r),
+            "s": String::from("0x") + &hex::encode(&self.s),
+        })
+    }
+}
+
+fn trim_leading_zeros(bytes: &[u8]) -> Vec<u8> {
+    bytes.iter().skip_while(|b| **b == 0).copied().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use bip32::secp256k1::elliptic_curve::sec1::ToEncodedPoint;
+
+    use super::*;
+    use crate::crypto::generate_eth_address;
+    use crate::utils;
+
+    #[test]
+    fn test_sign() {
+        let secret = utils::parse_private_key("0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80").unwrap();
+        let authorization = Authorization {
+            chain_id: 11155111,
+            address: hex::decode("5fbdb2315678afecb367f032d93f642f64180aa3").unwrap().try_into().unwrap(),
+            nonce: 3,
+        };
+        let signed = authorization.sign(secret.as_slice()).unwrap();
+        assert_eq!(signed.authorization, authorization);
+
+        let message = authorization.message();
+        assert_eq!(message[0], MAGIC);
+        let fields = rlp::Rlp::new(&message[1..]);
+        assert_eq!(fields.val_at::<u64>(0).unwrap(), 11155111);
+        assert_eq!(fields.val_at::<Vec<u8>>(1).unwrap(), authorization.address.to_vec());
+        assert_eq!(fields.val_at::<u64>(2).unwrap(), 3);
+
+        // the signature recovers to the account that signed
+        let pad = |bytes: &[u8]| [vec![0; 32 - bytes.len()], bytes.to_vec()].concat();
+        let bytes = [pad(&signed.r), pad(&signed.s), vec![signed.y_parity]].concat();
+        let signature = recoverable::Signature::try_from(bytes.as_slice()).unwrap();
+        let key = signature.recover_verify_key(&message).unwrap();
+        let point = key.to_encoded_point(false);
+        assert_eq!(String::from("0x") + &hex::encode(generate_eth_address(&point.as_bytes()[1..])),
+            utils::secret_key_to_address(&secret).unwrap());
+
+        let json = signed.to_json();
+        assert_eq!(json["address"], "0x5fbdb2315678afecb367f032d93f642f64180aa3");
+        assert_eq!(json["nonce"], "0x3");
+        assert_eq!(json["chainId"], "0xaa36a7");
+    }
+}
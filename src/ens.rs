@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+use crate::crypto::keccak256;
+use crate::erc20::{decode_string, is_valid_name, selector};
+use crate::networks::KnownNetwork;
+use crate::rpc::EthRpc;
+
+/// The ENS registry, at the same address on every network ENS is deployed to
+const REGISTRY: &str = "0x00000000000c2e074ec69a0dfb2997ba6c7d2e1e";
+
+/// True for input that reads as a name rather than an address, like `vitalik.eth`
+pub fn is_name(input: &str) -> bool {
+    !input.starts_with("0x") && input.contains('.') && input.split('.').all(|label| !label.is_empty())
+}
+
+/// ENS is only deployed to the public networks
+pub fn is_available(chain_id: u64) -> bool {
+    KnownNetwork::from_chain_id(chain_id).is_some()
+}
+
+/// The node of a name in the registry (EIP-137): each label is hashed and folded in from the
+/// top-level domain down. Names are lowercased, and only ASCII names are taken, since others
+/// need a normalization this wallet doesn't implement.
+pub fn namehash(name: &str) -> Result<[u8; 32], String> {
+    if !name.is_ascii() {
+        return Err(format!("{} has characters outside ASCII, which this wallet can't resolve", name));
+    }
+    let name = name.to_ascii_lowercase();
+    Ok(name.rsplit('.').filter(|label| !label.is_empty()).fold([0; 32], |node, label| {
+        keccak256(&[node, keccak256(label.as_bytes())].concat())
+    }))
+}
+
+/// Looks a name up through the registry on the node's network: first the resolver the name
+/// set, then the address the resolver has for it. Fails if either is missing.
+pub fn resolve(rpc: &dyn EthRpc, name: &str) -> Result<[u8; 20], String> {
+    let node = namehash(name)?;
+    let resolver = call_for_address(rpc, REGISTRY, "resolver(bytes32)", &node)
+        .map_err(|e| format!("Could not look up the resolver of {}: {}", name, e))?
+        .ok_or_else(|| format!("{} isn't a registered ENS name, or has no resolver", name))?;
+    call_for_address(rpc, &(String::from("0x") + &hex::encode(resolver)), "addr(bytes32)", &node)
+        .map_err(|e| format!("Could not resolve {}: {}", name, e))?
+        .ok_or_else(|| format!("{} doesn't point to an address", name))
+}
+
+/// The primary name an address set for itself, if it has one and the name points back to it.
+/// Answers are kept for the session, by chain, and a failed lookup is taken as no name.
+pub fn primary_name(rpc: &dyn EthRpc, chain_id: u64, address: &str) -> Option<String> {
+    static NAMES: OnceLock<Mutex<HashMap<String, Option<String>>>> = OnceLock::new();
+    if !is_available(chain_id) {
+        return None;
+    }
+    let address = address.to_lowercase();
+    let key = format!("{}:{}", chain_id, address);
+    if let Some(name) = NAMES.get_or_init(Mutex::default).lock().unwrap().get(&key) {
+        return name.clone();
+    }
+    // a node that can't be reached now may answer later, so only answers are kept
+    let name = reverse(rpc, &address).ok()?;
+    NAMES.get_or_init(Mutex::default).lock().unwrap().insert(key, name.clone());
+    name
+}
+
+/// Looks up the name in an address's reverse record (`<address>.addr.reverse`), and checks that
+/// the name resolves back to the address, since anyone can claim any name in their own
+/// reverse record
+fn reverse(rpc: &dyn EthRpc, address: &str) -> Result<Option<String>, String> {
+    let node = namehash(&format!("{}.addr.reverse", address.trim_start_matches("0x")))?;
+    let resolver = match call_for_address(rpc, REGISTRY, "resolver(bytes32)", &node)? {
+        Some(resolver) => String::from("0x") + &hex::encode(resolver),
+        None => return Ok(None),
+    };
+    let name = match decode_string(&rpc.eth_call(&resolver, &[&selector("name(bytes32)")[..], &node].concat())?) {
+        // the name is shown beside the address, so it can't carry escape codes
+        Some(name) if is_name(&name) && is_valid_name(&name) => name,
+        _ => return Ok(None),
+    };
+    let node = match namehash(&name) {
+        Ok(node) => node,
+        Err(_) => return Ok(None),
+    };
+    let resolver = match call_for_address(rpc, REGISTRY, "resolver(bytes32)", &node)? {
+        Some(resolver) => String::from("0x") + &hex::encode(resolver),
+        None => return Ok(None),
+    };
+    let points_back = call_for_address(rpc, &resolver, "addr(bytes32)", &node)?
+        .is_some_and(|forward| hex::encode(forward) == address.trim_start_matches("0x").to_lowercase());
+    Ok(points_back.then_some(name))
+}
+
+/// Calls a contract function taking a bytes32 and returning an address. None for the zero
+/// address, which is what an unset entry, or an address without code, returns.
+fn call_for_address(rpc: &dyn EthRpc, to: &str, signature: &str, argument: &[u8; 32]) -> Result<Option<[u8; 20]>, String> {
+    let data = [&selector(signature)[..], argument].concat();
+    let result = rpc.eth_call(to, &data)?;
+    // an address is returned right-aligned in a 32 byte word
+    let address: [u8; 20] = match result.get(12..32) {
+        Some(address) => address.try_into().unwrap(),
+        None => return Ok(None),
+    };
+    Ok(Some(address).filter(|a| *a != [0; 20]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rpc::mock::MockRpc;
+
+    #[test]
+    fn test_namehash() {
+        assert_eq!(namehash("").unwrap(), [0; 32]);
+        assert_eq!(hex::encode(namehash("eth").unwrap()), "93cdeb708b7545dc668eb9280176169d1c33cfd8ed6f04690a0bcc88a93fc4ae");
+        assert_eq!(hex::encode(namehash("foo.eth").unwrap()), "de9b09fd7c5f901e23a3f19fecc54828e9c848539801e86591bd9801b019f84f");
+        assert_eq!(namehash("Foo.ETH"), namehash("foo.eth"));
+        assert!(namehash("ünicode.eth").is_err());
+
+        assert_eq!(hex::encode(selector("resolver(bytes32)")), "0178b8bf");
+        assert_eq!(hex::encode(selector("addr(bytes32)")), "3b3b57de");
+
+        assert!(is_name("vitalik.eth"));
+        assert!(is_name("pay.vitalik.eth"));
+        assert!(!is_name("0x70997970c51812dc3a010c7d01b50e0d17dc79c8"));
+        assert!(!is_name("vitalik"));
+        assert!(!is_name("vitalik..eth"));
+        assert!(is_available(1) && is_available(11155111));
+        assert!(!is_available(4));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let rpc = MockRpc::new(1);
+        let node = namehash("vitalik.eth").unwrap();
+        let resolver = "0x231b0ee14048e9dccd1d247744d114a4eb5e8e63";
+        let word = |address: &str| [vec![0; 12], hex::decode(&address[2..]).unwrap()].concat();
+        let target = "0xd8da6bf26964af9d7eed9e03e53415d37aa96045";
+
+        assert_eq!(resolve(&rpc, "vitalik.eth").unwrap_err(), "vitalik.eth isn't a registered ENS name, or has no resolver");
+        rpc.contract(REGISTRY, &[&selector("resolver(bytes32)")[..], &node].concat(), word(resolver));
+        assert_eq!(resolve(&rpc, "vitalik.eth").unwrap_err(), "vitalik.eth doesn't point to an address");
+        // a resolver without an address set returns zero
+        rpc.contract(resolver, &[&selector("addr(bytes32)")[..], &node].concat(), vec![0; 32]);
+        assert_eq!(resolve(&rpc, "vitalik.eth").unwrap_err(), "vitalik.eth doesn't point to an address");
+        rpc.contract(resolver, &[&selector("addr(bytes32)")[..], &node].concat(), word(target));
+        assert_eq!(resolve(&rpc, "VITALIK.eth").map(hex::encode), Ok(String::from(&target[2..])));
+
+        rpc.offline.set(true);
+        assert!(resolve(&rpc, "vitalik.eth").unwrap_err().starts_with("Could not look up the resolver of vitalik.eth"));
+    }
+
+    #[test]
+    fn test_primary_name() {
+        let encoded = |name: &str| {
+            let word = |n: usize| [vec![0; 24], (n as u64).to_be_bytes().to_vec()].concat();
+            let padded = [name.as_bytes().to_vec(), vec![0; 32 - name.len() % 32]].concat();
+            [word(32), word(name.len()), padded].concat()
+        };
+        assert_eq!(decode_string(&encoded("alice.eth")), Some(String::from("alice.eth")));
+        assert_eq!(decode_string(&encoded("")), Some(String::new()));
+        assert_eq!(decode_string(&encoded("alice.eth")[..40]), None);
+        assert_eq!(decode_string(&[]), None);
+
+        let rpc = MockRpc::new(11155111);
+        let address = "0x3c44cdddb6a900fa2b585dd299e03d12fa4293bc";
+        let resolver = "0x8fade66b79cc9f707ab26799354482eb93a5b7dd";
+        let word = |address: &str| [vec![0; 12], hex::decode(&address[2..]).unwrap()].concat();
+        let reverse_node = namehash(&format!("{}.addr.reverse", &address[2..])).unwrap();
+        let node = namehash("alice.eth").unwrap();
+        rpc.contract(REGISTRY, &[&selector("resolver(bytes32)")[..], &reverse_node].concat(), word(resolver));
+        rpc.contract(resolver, &[&selector("name(bytes32)")[..], &reverse_node].concat(), encoded("alice.eth"));
+
+        // a name that doesn't point back isn't the address's
+        assert_eq!(reverse(&rpc, address), Ok(None));
+        rpc.contract(REGISTRY, &[&selector("resolver(bytes32)")[..], &node].concat(), word(resolver));
+        rpc.contract(resolver, &[&selector("addr(bytes32)")[..], &node].concat(), word(address));
+        assert_eq!(reverse(&rpc, address), Ok(Some(String::from("alice.eth"))));
+
+        // a name with control characters is no name, since it would be printed
+        let escaped = "alice\x1b[2J.eth";
+        rpc.contract(resolver, &[&selector("name(bytes32)")[..], &reverse_node].concat(), encoded(escaped));
+        let escaped_node = namehash(escaped).unwrap();
+        rpc.contract(REGISTRY, &[&selector("resolver(bytes32)")[..], &escaped_node].concat(), word(resolver));
+        rpc.contract(resolver, &[&selector("addr(bytes32)")[..], &escaped_node].concat(), word(address));
+        assert_eq!(reverse(&rpc, address), Ok(None));
+        rpc.contract(resolver, &[&selector("name(bytes32)")[..], &reverse_node].concat(), encoded("alice.eth"));
+
+        // the answer is kept, so later lookups don't need the node
+        assert_eq!(primary_name(&rpc, 11155111, address), Some(String::from("alice.eth")));
+        rpc.offline.set(true);
+        assert_eq!(primary_name(&rpc, 11155111, &address.to_uppercase().replacen("0X", "0x", 1)), Some(String::from("alice.eth")));
+        assert_eq!(primary_name(&rpc, 11155111, "0x70997970c51812dc3a010c7d01b50e0d17dc79c8"), None);
+        // nor is it asked where there is no ENS
+        assert_eq!(primary_name(&rpc, 4, address), None);
+    }
+}
@@ -0,0 +1,111 @@
+use zeroize::Zeroizing;
+
+use crate::crypto::keccak256;
+
+/// Entropy lengths accepted as hex, in bytes: a 12 or a 24 word phrase
+pub const HEX_ENTROPY_BYTES: [usize; 2] = [16, 32];
+/// log2(6) scaled by 1000, the entropy of one fair die roll
+const MILLIBITS_PER_ROLL: usize = 2585;
+
+/// Parses 128 or 256 bits of entropy written as hex, with an optional 0x and any whitespace
+pub fn parse_hex(input: &str) -> Result<Zeroizing<Vec<u8>>, String> {
+    let hex: Zeroizing<String> = Zeroizing::new(input.trim().trim_start_matches("0x").split_whitespace().collect());
+    let entropy = Zeroizing::new(hex::decode(hex.as_str()).map_err(|_e| String::from("Entropy must be written in hex"))?);
+    if !HEX_ENTROPY_BYTES.contains(&entropy.len()) {
+        return Err(format!("Entropy must be 128 or 256 bits (32 or 64 hex characters), not {} bits", entropy.len() * 8));
+    }
+    Ok(entropy)
+}
+
+/// The number of die rolls that carry at least `bits` of entropy
+pub fn min_dice_rolls(bits: usize) -> usize {
+    (bits * 1000).div_ceil(MILLIBITS_PER_ROLL)
+}
+
+/// Turns a string of die rolls (1-6, spaces allowed) into `bits` of entropy by hashing them with
+/// keccak256. There must be enough rolls to carry that much entropy.
+pub fn from_dice(rolls: &str, bits: usize) -> Result<Zeroizing<Vec<u8>>, String> {
+    let rolls: Zeroizing<String> = Zeroizing::new(rolls.split_whitespace().collect());
+    if let Some(c) = rolls.chars().find(|c| !('1'..='6').contains(c)) {
+        return Err(format!("'{}' is not a die roll. Enter the rolls as digits from 1 to 6.", c));
+    }
+    let needed = min_dice_rolls(bits);
+    if rolls.len() < needed {
+        return Err(format!("{} words need at least {} rolls, but only {} were entered", bits / 32 * 3, needed, rolls.len()));
+    }
+    let hash = Zeroizing::new(keccak256(rolls.as_bytes()));
+    Ok(Zeroizing::new(hash[..bits / 8].to_vec()))
+}
+
+/// Signs that entropy wasn't random: repeated bytes or a short repeating pattern
+pub fn hex_warnings(entropy: &[u8]) -> Vec<String> {
+    let mut warnings = vec![];
+    if entropy.iter().all(|b| *b == 0) {
+        warnings.push(String::from("The entropy is all zeroes"));
+    } else if entropy.iter().all(|b| *b == entropy[0]) {
+        warnings.push(format!("Every byte of the entropy is {:02x}", entropy[0]));
+    } else if let Some(period) = (2..=entropy.len() / 2).find(|p| entropy.iter().zip(&entropy[*p..]).all(|(a, b)| a == b)) {
+        warnings.push(format!("The entropy repeats every {} bytes", period));
+    }
+    let mut distinct = entropy.to_vec();
+    distinct.sort_unstable();
+    distinct.dedup();
+    if warnings.is_empty() && distinct.len() < entropy.len() / 2 {
+        warnings.push(format!("The entropy only uses {} different byte values", distinct.len()));
+    }
+    warnings
+}
+
+/// Signs that the dice weren't really rolled: one face over and over, or faces left out
+pub fn dice_warnings(rolls: &str) -> Vec<String> {
+    let rolls: Vec<char> = rolls.chars().filter(|c| !c.is_whitespace()).collect();
+    let faces = ('1'..='6').filter(|face| rolls.contains(face)).count();
+    match faces {
+        0 => vec![],
+        1 => vec![format!("Every roll is {}", rolls[0])],
+        faces if faces < 6 => vec![format!("Only {} of the 6 faces came up", faces)],
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(*parse_hex("0x000102030405060708090a0b0c0d0e0f").unwrap(), (0..16).collect::<Vec<u8>>());
+        assert_eq!(parse_hex("00010203 04050607 08090a0b 0c0d0e0f 00010203 04050607 08090a0b 0c0d0e0f").unwrap().len(), 32);
+        assert_eq!(parse_hex("0001").unwrap_err(), "Entropy must be 128 or 256 bits (32 or 64 hex characters), not 16 bits");
+        assert_eq!(parse_hex("zz").unwrap_err(), "Entropy must be written in hex");
+    }
+
+    #[test]
+    fn test_from_dice() {
+        assert_eq!(min_dice_rolls(128), 50);
+        assert_eq!(min_dice_rolls(256), 100);
+
+        let rolls = "123456".repeat(9);
+        let entropy = from_dice(&rolls, 128).unwrap();
+        assert_eq!(*entropy, keccak256(rolls.as_bytes())[..16]);
+        // spacing doesn't change the result
+        let spaced: String = rolls.chars().flat_map(|c| [c, ' ']).collect();
+        assert_eq!(from_dice(&spaced, 128).unwrap(), entropy);
+
+        assert_eq!(from_dice(&rolls, 256).unwrap_err(), "24 words need at least 100 rolls, but only 54 were entered");
+        assert_eq!(from_dice("1237", 128).unwrap_err(), "'7' is not a die roll. Enter the rolls as digits from 1 to 6.");
+    }
+
+    #[test]
+    fn test_warnings() {
+        assert_eq!(hex_warnings(&[0; 32]), ["The entropy is all zeroes"]);
+        assert_eq!(hex_warnings(&[0xab; 16]), ["Every byte of the entropy is ab"]);
+        assert_eq!(hex_warnings(&[1, 2, 3, 1, 2, 3, 1, 2, 3, 1, 2, 3, 1, 2, 3, 1]), ["The entropy repeats every 3 bytes"]);
+        assert_eq!(hex_warnings(&[1, 2, 1, 2, 1, 2, 1, 3, 1, 2, 1, 2, 1, 2, 1, 2]), ["The entropy only uses 3 different byte values"]);
+        assert!(hex_warnings(&(0..32).collect::<Vec<u8>>()).is_empty());
+
+        assert_eq!(dice_warnings(&"6".repeat(50)), ["Every roll is 6"]);
+        assert_eq!(dice_warnings(&"12".repeat(50)), ["Only 2 of the 6 faces came up"]);
+        assert!(dice_warnings(&"123456".repeat(9)).is_empty());
+    }
+}
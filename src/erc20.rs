@@ -0,0 +1,385 @@
+use serde::{Serialize, Deserialize};
+
+use crate::crypto::keccak256;
+use crate::networks::KnownNetwork;
+use crate::rpc::EthRpc;
+
+/// The gas limit of a token transfer the node couldn't estimate. Tokens do more than an ETH
+/// transfer, and most transfers fit in this.
+pub const TRANSFER_GAS_LIMIT: u128 = 100_000;
+/// The gas limit of an approval the node couldn't estimate, which writes one allowance
+pub const APPROVE_GAS_LIMIT: u128 = 60_000;
+
+/// The most decimal places a token can have. More are taken for a broken or hostile contract, and
+/// would only make amounts unreadable.
+pub const MAX_DECIMALS: u8 = 36;
+/// The longest symbol shown, which keeps a contract from filling the screen with one
+pub const MAX_SYMBOL_LEN: usize = 12;
+
+/// How much of an owner's token a spender may move with `transferFrom`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Allowance {
+    Limited(u128),
+    /// The largest uint256, which contracts take as no limit at all. An allowance too large for
+    /// a u128 is only reached this way, and is shown as it.
+    Unlimited,
+}
+
+impl Allowance {
+    /// The allowance in whole tokens, as `format_units` shows amounts
+    pub fn format(&self, token: &Token) -> String {
+        match self {
+            Allowance::Limited(amount) => format!("{} {}", format_units(*amount, token.decimals), token.symbol),
+            Allowance::Unlimited => format!("unlimited {}", token.symbol),
+        }
+    }
+}
+
+/// A token whose balances are shown, as listed for a network
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Token {
+    pub symbol: String,
+    /// The token contract
+    pub address: String,
+    /// How many places balances are shifted by to show whole tokens, 18 for most tokens
+    pub decimals: u8,
+}
+
+impl Token {
+    fn new(symbol: &str, address: &str, decimals: u8) -> Token {
+        Token { symbol: String::from(symbol), address: String::from(address), decimals }
+    }
+}
+
+/// The tokens shown on a public network that has none of its own listed. Testnet tokens come
+/// and go, so only mainnet has any.
+pub fn known_tokens(chain_id: u64) -> Vec<Token> {
+    match KnownNetwork::from_chain_id(chain_id) {
+        Some(KnownNetwork::Mainnet) => vec![
+            Token::new("USDC", "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48", 6),
+            Token::new("USDT", "0xdac17f958d2ee523a2206206994597c13d831ec7", 6),
+            Token::new("DAI", "0x6b175474e89094c44da98b954eedeac495271d0f", 18),
+            Token::new("WETH", "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2", 18),
+        ],
+        _ => Vec::new(),
+    }
+}
+
+/// The first 4 bytes of the keccak256 of a function's signature, which picks it in a call
+pub fn selector(signature: &str) -> [u8; 4] {
+    keccak256(signature.as_bytes())[..4].try_into().unwrap()
+}
+
+/// An address as a call argument: right-aligned in a 32 byte word
+pub fn encode_address(address: &[u8; 20]) -> [u8; 32] {
+    let mut word = [0; 32];
+    word[12..].copy_from_slice(address);
+    word
+}
+
+/// The uint256 a call returned. Fails on anything shorter than a word, or too large for a u128,
+/// which no real balance is.
+pub fn decode_uint256(result: &[u8]) -> Result<u128, String> {
+    let word = result.get(..32).ok_or_else(|| format!("The call returned {} bytes, not a uint256", result.len()))?;
+    if word[..16].iter().any(|b| *b != 0) {
+        return Err(String::from("The call returned a number too large to show"));
+    }
+    Ok(u128::from_be_bytes(word[16..].try_into().unwrap()))
+}
+
+/// A string returned by a contract: the offset of its length, the length, then its bytes
+pub fn decode_string(result: &[u8]) -> Option<String> {
+    let word = |at: usize| -> Option<usize> {
+        let word = result.get(at..at.checked_add(32)?)?;
+        // anything past 8 bytes can't be a real offset or length
+        word[..24].iter().all(|b| *b == 0).then(|| u64::from_be_bytes(word[24..].try_into().unwrap()) as usize)
+    };
+    let offset = word(0)?;
+    let length = word(offset)?;
+    let bytes = result.get(offset.checked_add(32)?..offset.checked_add(32)?.checked_add(length)?)?;
+    String::from_utf8(bytes.to_vec()).ok()
+}
+
+/// Text a token returned for its symbol or name: a string, or from older tokens a bytes32
+/// padded with zeros
+pub fn decode_text(result: &[u8]) -> Option<String> {
+    decode_string(result).or_else(|| match result.len() {
+        32 => String::from_utf8(result.iter().copied().take_while(|b| *b != 0).collect()).ok(),
+        _ => None,
+    })
+}
+
+/// A symbol is 1 to `MAX_SYMBOL_LEN` printable ASCII characters
+pub fn is_valid_symbol(symbol: &str) -> bool {
+    (1..=MAX_SYMBOL_LEN).contains(&symbol.len()) && symbol.chars().all(|c| c == ' ' || c.is_ascii_graphic())
+}
+
+/// A name can be any text without control characters, which could rewrite the terminal
+pub fn is_valid_name(name: &str) -> bool {
+    !name.trim().is_empty() && !name.chars().any(char::is_control)
+}
+
+/// What a token contract says of itself. Each is None where the contract's answer was missing or
+/// invalid, so the user can enter it.
+#[derive(Debug, Default, PartialEq)]
+pub struct TokenDetails {
+    pub symbol: Option<String>,
+    pub name: Option<String>,
+    pub decimals: Option<u8>,
+}
+
+/// Asks the token contract at `address` for its symbol, name and decimals, and checks each before
+/// it is shown or used to scale amounts. The three are optional in ERC-20, and a contract may
+/// revert on any of them, so only failing on all three is an error.
+pub fn fetch_details(rpc: &dyn EthRpc, address: &str) -> Result<TokenDetails, String> {
+    let call = |signature: &str| match rpc.eth_call(address, &selector(signature)) {
+        Ok(result) if result.is_empty() => Err(format!("There is no token contract at {}", address)),
+        other => other,
+    };
+    let (decimals, symbol, name) = (call("decimals()"), call("symbol()"), call("name()"));
+    if let (Err(e), Err(_), Err(_)) = (&decimals, &symbol, &name) {
+        return Err(e.clone());
+    }
+    let details = TokenDetails {
+        decimals: decimals.ok().and_then(|r| decode_uint256(&r).ok()).and_then(|d| u8::try_from(d).ok()).filter(|d| *d <= MAX_DECIMALS),
+        symbol: symbol.ok().and_then(|r| decode_text(&r)).map(|s| String::from(s.trim())).filter(|s| is_valid_symbol(s)),
+        name: name.ok().and_then(|r| decode_text(&r)).map(|s| String::from(s.trim())).filter(|s| is_valid_name(s)),
+    };
+    match details {
+        TokenDetails { symbol: None, name: None, decimals: None } => Err(format!("The contract at {} doesn't describe itself as a token", address)),
+        details => Ok(details),
+    }
+}
+
+/// The data of a `balanceOf(owner)` call
+pub fn balance_of_data(owner: &[u8; 20]) -> Vec<u8> {
+    [&selector("balanceOf(address)")[..], &encode_address(owner)].concat()
+}
+
+/// The data of a `transfer(recipient, amount)` call
+pub fn transfer_data(recipient: &[u8; 20], amount: u128) -> Vec<u8> {
+    let mut amount_word = [0; 32];
+    amount_word[16..].copy_from_slice(&amount.to_be_bytes());
+    [&selector("transfer(address,uint256)")[..], &encode_address(recipient), &amount_word].concat()
+}
+
+/// The data of an `allowance(owner, spender)` call
+pub fn allowance_data(owner: &[u8; 20], spender: &[u8; 20]) -> Vec<u8> {
+    [&selector("allowance(address,address)")[..], &encode_address(owner), &encode_address(spender)].concat()
+}
+
+/// The data of an `approve(spender, amount)` call. Approving 0 revokes the allowance.
+pub fn approve_data(spender: &[u8; 20], allowance: Allowance) -> Vec<u8> {
+    let amount_word = match allowance {
+        Allowance::Limited(amount) => {
+            let mut word = [0; 32];
+            word[16..].copy_from_slice(&amount.to_be_bytes());
+            word
+        },
+        Allowance::Unlimited => [0xff; 32],
+    };
+    [&selector("approve(address,uint256)")[..], &encode_address(spender), &amount_word].concat()
+}
+
+/// Asks the token contract how much of `owner`'s tokens `spender` may move
+pub fn allowance(rpc: &dyn EthRpc, token: &Token, owner: &[u8; 20], spender: &[u8; 20]) -> Result<Allowance, String> {
+    let result = rpc.eth_call(&token.address, &allowance_data(owner, spender))?;
+    if result.is_empty() {
+        return Err(format!("There is no contract at {}", token.address));
+    }
+    match result.get(..16) {
+        Some(high) if high.iter().any(|b| *b != 0) => Ok(Allowance::Unlimited),
+        _ => decode_uint256(&result).map(Allowance::Limited),
+    }
+}
+
+/// Asks the token contract how much of it `owner` holds, in its smallest unit
+pub fn balance_of(rpc: &dyn EthRpc, token: &Token, owner: &[u8; 20]) -> Result<u128, String> {
+    let result = rpc.eth_call(&token.address, &balance_of_data(owner))?;
+    // a contract that doesn't exist returns nothing rather than failing
+    if result.is_empty() {
+        return Err(format!("There is no contract at {}", token.address));
+    }
+    decode_uint256(&result)
+}
+
+/// An amount in a token's smallest unit as whole tokens, like `wei_to_eth` for any number of
+/// decimals
+pub fn format_units(amount: u128, decimals: u8) -> String {
+    let decimals = decimals as usize;
+    let digits = format!("{:0>width$}", amount, width = decimals + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals);
+    match fraction.trim_end_matches('0') {
+        "" => String::from(whole),
+        fraction => format!("{}.{}", whole, fraction),
+    }
+}
+
+/// Converts an amount of whole tokens to the token's smallest unit using integer math only, like
+/// `eth_to_wei`. More decimal places than the token has are refused rather than dropped.
+pub fn parse_units(amount: &str, decimals: u8) -> Result<u128, String> {
+    let amount = amount.trim();
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return Err(String::from("Please enter a number"));
+    }
+    if !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+        return Err(String::from("Please enter a positive decimal number"));
+    }
+    if fraction.len() > decimals as usize {
+        return Err(format!("This token has {} decimal places, so the amount can't have more", decimals));
+    }
+    let digits = format!("{}{:0<width$}", whole, fraction, width = decimals as usize);
+    match digits.trim_start_matches('0') {
+        "" => Ok(0),
+        digits => digits.parse::<u128>().map_err(|_| String::from("The amount is too large")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rpc::mock::MockRpc;
+
+    #[test]
+    fn test_encoding() {
+        assert_eq!(hex::encode(selector("balanceOf(address)")), "70a08231");
+        assert_eq!(hex::encode(selector("transfer(address,uint256)")), "a9059cbb");
+
+        let owner: [u8; 20] = hex::decode("d8da6bf26964af9d7eed9e03e53415d37aa96045").unwrap().try_into().unwrap();
+        assert_eq!(hex::encode(balance_of_data(&owner)),
+            "70a08231000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa96045");
+
+        let balance = hex::decode("00000000000000000000000000000000000000000000000000000000000f4240").unwrap();
+        assert_eq!(decode_uint256(&balance), Ok(1_000_000));
+        assert_eq!(decode_uint256(&[0; 32]), Ok(0));
+        assert_eq!(decode_uint256(&[0; 31]).unwrap_err(), "The call returned 31 bytes, not a uint256");
+        assert!(decode_uint256(&[0xff; 32]).is_err());
+        // an offset past the end of memory is refused rather than overflowing
+        assert_eq!(decode_string(&[0xff; 32]), None);
+        assert_eq!(decode_string(&[vec![0; 24], vec![0xff; 8]].concat()), None);
+        assert_eq!(decode_string(&[vec![0; 24], vec![0xff; 8], vec![0xff; 32]].concat()), None);
+
+        assert_eq!(format_units(1_000_000, 6), "1");
+        assert_eq!(format_units(1_234_500, 6), "1.2345");
+        assert_eq!(format_units(5, 6), "0.000005");
+        assert_eq!(format_units(0, 18), "0");
+        assert_eq!(format_units(42, 0), "42");
+        assert_eq!(format_units(1, 40), "0.0000000000000000000000000000000000000001");
+
+        let recipient: [u8; 20] = hex::decode("70997970c51812dc3a010c7d01b50e0d17dc79c8").unwrap().try_into().unwrap();
+        assert_eq!(hex::encode(transfer_data(&recipient, 1_500_000)),
+            "a9059cbb00000000000000000000000070997970c51812dc3a010c7d01b50e0d17dc79c8\
+            000000000000000000000000000000000000000000000000000000000016e360");
+    }
+
+    #[test]
+    fn test_allowance() {
+        assert_eq!(hex::encode(selector("allowance(address,address)")), "dd62ed3e");
+        assert_eq!(hex::encode(selector("approve(address,uint256)")), "095ea7b3");
+        let (owner, spender) = ([0x11; 20], [0x22; 20]);
+        assert_eq!(hex::encode(approve_data(&spender, Allowance::Limited(1_500_000))),
+            "095ea7b30000000000000000000000002222222222222222222222222222222222222222\
+            000000000000000000000000000000000000000000000000000000000016e360");
+        assert_eq!(approve_data(&spender, Allowance::Unlimited)[36..], [0xff; 32]);
+        assert_eq!(approve_data(&spender, Allowance::Limited(0))[36..], [0; 32]);
+
+        let rpc = MockRpc::new(1);
+        let usdc = &known_tokens(1)[0];
+        assert_eq!(allowance(&rpc, usdc, &owner, &spender).unwrap_err(), format!("There is no contract at {}", usdc.address));
+        rpc.contract(&usdc.address, &allowance_data(&owner, &spender), [vec![0; 29], vec![0x16, 0xe3, 0x60]].concat());
+        assert_eq!(allowance(&rpc, usdc, &owner, &spender).map(|a| a.format(usdc)), Ok(String::from("1.5 USDC")));
+        // some tokens count an unlimited allowance down as it is spent, which stays far above a u128
+        let spent = [vec![0xff; 31], vec![0x00]].concat();
+        rpc.contract(&usdc.address, &allowance_data(&owner, &spender), spent);
+        assert_eq!(allowance(&rpc, usdc, &owner, &spender), Ok(Allowance::Unlimited));
+        assert_eq!(Allowance::Unlimited.format(usdc), "unlimited USDC");
+    }
+
+    #[test]
+    fn test_parse_units() {
+        assert_eq!(parse_units("1.5", 6), Ok(1_500_000));
+        assert_eq!(parse_units(" 0.000001 ", 6), Ok(1));
+        assert_eq!(parse_units(".25", 2), Ok(25));
+        assert_eq!(parse_units("7.", 0), Ok(7));
+        assert_eq!(parse_units("0", 18), Ok(0));
+        // exact where a float would round
+        assert_eq!(parse_units("0.1", 18), Ok(100_000_000_000_000_000));
+        assert_eq!(parse_units("123456789.123456789123456789", 18), Ok(123_456_789_123_456_789_123_456_789));
+        assert_eq!(parse_units("1.0000001", 6).unwrap_err(), "This token has 6 decimal places, so the amount can't have more");
+        assert_eq!(parse_units("1.5", 0).unwrap_err(), "This token has 0 decimal places, so the amount can't have more");
+        assert_eq!(parse_units("-1", 6).unwrap_err(), "Please enter a positive decimal number");
+        assert_eq!(parse_units("1e6", 6).unwrap_err(), "Please enter a positive decimal number");
+        assert_eq!(parse_units(".", 6).unwrap_err(), "Please enter a number");
+        assert_eq!(parse_units("1000000000000000000000", 18).unwrap_err(), "The amount is too large");
+        assert_eq!(parse_units(&format_units(123_456_789, 8), 8), Ok(123_456_789));
+    }
+
+    #[test]
+    fn test_balance_of() {
+        let rpc = MockRpc::new(1);
+        let owner = [0x11; 20];
+        let usdc = &known_tokens(1)[0];
+        assert_eq!(balance_of(&rpc, usdc, &owner).unwrap_err(), format!("There is no contract at {}", usdc.address));
+        rpc.contract(&usdc.address, &balance_of_data(&owner), [vec![0; 29], vec![0x98, 0x96, 0x80]].concat());
+        assert_eq!(balance_of(&rpc, usdc, &owner).map(|b| format_units(b, usdc.decimals)), Ok(String::from("10")));
+        assert!(known_tokens(1).iter().all(|t| t.address.len() == 42 && hex::decode(&t.address[2..]).is_ok()));
+        assert!(known_tokens(11155111).is_empty());
+    }
+
+    /// What USDC's contract returns for `symbol()`, `name()` and `decimals()`, as strings
+    const USDC_SYMBOL: &str = "0000000000000000000000000000000000000000000000000000000000000020\
+        0000000000000000000000000000000000000000000000000000000000000004\
+        5553444300000000000000000000000000000000000000000000000000000000";
+    const USDC_NAME: &str = "0000000000000000000000000000000000000000000000000000000000000020\
+        0000000000000000000000000000000000000000000000000000000000000008\
+        55534420436f696e000000000000000000000000000000000000000000000000";
+    const USDC_DECIMALS: &str = "0000000000000000000000000000000000000000000000000000000000000006";
+    /// What MKR's contract returns for them, which predates strings and answers in bytes32
+    const MKR_SYMBOL: &str = "4d4b520000000000000000000000000000000000000000000000000000000000";
+    const MKR_NAME: &str = "4d616b6572000000000000000000000000000000000000000000000000000000";
+
+    #[test]
+    fn test_fetch_details() {
+        assert_eq!(hex::encode(selector("symbol()")), "95d89b41");
+        assert_eq!(hex::encode(selector("name()")), "06fdde03");
+        assert_eq!(hex::encode(selector("decimals()")), "313ce567");
+
+        let rpc = MockRpc::new(1);
+        let fixture = |address: &str, signature: &str, result: &str| rpc.contract(address, &selector(signature), hex::decode(result).unwrap());
+        let usdc = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48";
+        assert_eq!(fetch_details(&rpc, usdc).unwrap_err(), format!("There is no token contract at {}", usdc));
+        fixture(usdc, "symbol()", USDC_SYMBOL);
+        fixture(usdc, "name()", USDC_NAME);
+        fixture(usdc, "decimals()", USDC_DECIMALS);
+        assert_eq!(fetch_details(&rpc, usdc), Ok(TokenDetails {
+            symbol: Some(String::from("USDC")), name: Some(String::from("USD Coin")), decimals: Some(6),
+        }));
+
+        let mkr = "0x9f8f72aa9304c8b593d555f12ef6589cc3a579a2";
+        fixture(mkr, "symbol()", MKR_SYMBOL);
+        fixture(mkr, "name()", MKR_NAME);
+        fixture(mkr, "decimals()", &format!("{:064x}", 18));
+        assert_eq!(fetch_details(&rpc, mkr), Ok(TokenDetails {
+            symbol: Some(String::from("MKR")), name: Some(String::from("Maker")), decimals: Some(18),
+        }));
+
+        // answers that can't be shown or used are left for the user to enter
+        let odd = "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed";
+        fixture(odd, "decimals()", &format!("{:064x}", 37));
+        fixture(odd, "symbol()", &hex::encode(b"SYMBOLTOOLONG").chars().chain(std::iter::repeat('0')).take(64).collect::<String>());
+        fixture(odd, "name()", &hex::encode(b"\x1b[2Jname").chars().chain(std::iter::repeat('0')).take(64).collect::<String>());
+        assert_eq!(fetch_details(&rpc, odd).unwrap_err(), format!("The contract at {} doesn't describe itself as a token", odd));
+        fixture(odd, "decimals()", &format!("{:064x}", 36));
+        assert_eq!(fetch_details(&rpc, odd), Ok(TokenDetails { decimals: Some(36), ..TokenDetails::default() }));
+        // reverting on one call leaves the others
+        let reverts = "0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359";
+        fixture(reverts, "symbol()", MKR_SYMBOL);
+        assert_eq!(fetch_details(&rpc, reverts), Ok(TokenDetails { symbol: Some(String::from("MKR")), ..TokenDetails::default() }));
+
+        assert!(is_valid_symbol("USDC") && is_valid_symbol("USD+") && is_valid_symbol("A B"));
+        assert!(!is_valid_symbol("") && !is_valid_symbol("SYMBOLTOOLONG") && !is_valid_symbol("ÜSD") && !is_valid_symbol("US\nDC"));
+        assert!(is_valid_name("Wrapped Ether") && is_valid_name("Ünicode Token"));
+        assert!(!is_valid_name(" ") && !is_valid_name("\x1b[2J"));
+    }
+}
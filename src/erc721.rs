@@ -0,0 +1,233 @@
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+
+use crate::crypto::checksum_address;
+use crate::erc20::{self, encode_address, selector};
+use crate::labels::normalize_address;
+use crate::rpc::EthRpc;
+
+/// The gas limit of an NFT transfer the node couldn't estimate. A safe transfer to a contract
+/// also runs the contract's check that it accepts NFTs, so this leaves room for that.
+pub const TRANSFER_GAS_LIMIT: u128 = 150_000;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum CollectionError {
+    #[error("{0} is not a valid address or its checksum is wrong")]
+    InvalidAddress(String),
+    #[error("The name can't be empty or have control characters")]
+    InvalidName,
+    #[error("{name} is already tracked at {address} on this network")]
+    Duplicate { name: String, address: String },
+    #[error("No collection is tracked at {0} on this network")]
+    NotFound(String),
+}
+
+/// An NFT collection the user follows, on the chain it was added for
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Collection {
+    pub chain_id: u64,
+    pub name: String,
+    /// The checksummed address of the collection's contract
+    pub address: String,
+}
+
+/// The collections the user follows. Each belongs to one chain, where its address is unique.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(transparent)]
+pub struct Collections {
+    collections: Vec<Collection>,
+}
+
+impl Collections {
+    pub fn is_empty(&self) -> bool {
+        self.collections.is_empty()
+    }
+
+    /// The collections tracked on `chain_id`, in the order they were added
+    pub fn for_chain(&self, chain_id: u64) -> impl Iterator<Item = &Collection> {
+        self.collections.iter().filter(move |c| c.chain_id == chain_id)
+    }
+
+    /// Tracks the collection at `address` on `chain_id`, and returns it as saved
+    pub fn add(&mut self, chain_id: u64, name: &str, address: &str) -> Result<&Collection, CollectionError> {
+        if !erc20::is_valid_name(name) {
+            return Err(CollectionError::InvalidName);
+        }
+        let address = address.trim();
+        let normalized = normalize_address(address).ok_or_else(|| CollectionError::InvalidAddress(String::from(address)))?;
+        if let Some(existing) = self.find(chain_id, &normalized) {
+            return Err(CollectionError::Duplicate { name: existing.name.clone(), address: existing.address.clone() });
+        }
+        let bytes: [u8; 20] = hex::decode(&normalized[2..]).unwrap().try_into().unwrap();
+        self.collections.push(Collection { chain_id, name: String::from(name.trim()), address: checksum_address(&bytes) });
+        Ok(self.collections.last().unwrap())
+    }
+
+    /// Stops tracking the collection at `address` on `chain_id`, and returns it
+    pub fn remove(&mut self, chain_id: u64, address: &str) -> Result<Collection, CollectionError> {
+        let position = self.collections.iter().position(|c| c.chain_id == chain_id && c.address.eq_ignore_ascii_case(address.trim()))
+            .ok_or_else(|| CollectionError::NotFound(String::from(address.trim())))?;
+        Ok(self.collections.remove(position))
+    }
+
+    /// The collection tracked at `address` on `chain_id`, ignoring case
+    pub fn find(&self, chain_id: u64, address: &str) -> Option<&Collection> {
+        self.for_chain(chain_id).find(|c| c.address.eq_ignore_ascii_case(address.trim()))
+    }
+}
+
+/// Reads a token id, in decimal or 0x-prefixed hex, as the uint256 it is passed as. Ids are often
+/// hashes, so they can take all 256 bits.
+pub fn parse_token_id(input: &str) -> Result<[u8; 32], String> {
+    let input = input.trim();
+    if let Some(digits) = input.strip_prefix("0x") {
+        if digits.is_empty() || digits.len() > 64 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(String::from("A hex token id is 0x and up to 64 hex digits"));
+        }
+        let mut id = [0; 32];
+        let bytes = hex::decode(format!("{:0>64}", digits)).unwrap();
+        id.copy_from_slice(&bytes);
+        return Ok(id);
+    }
+    if input.is_empty() || !input.chars().all(|c| c.is_ascii_digit()) {
+        return Err(String::from("Please enter the token id, a whole number"));
+    }
+    let mut id = [0u8; 32];
+    for digit in input.bytes().map(|b| (b - b'0') as u16) {
+        // id = id * 10 + digit, a byte at a time from the lowest
+        let mut carry = digit;
+        for byte in id.iter_mut().rev() {
+            let value = *byte as u16 * 10 + carry;
+            *byte = value as u8;
+            carry = value >> 8;
+        }
+        if carry != 0 {
+            return Err(String::from("The token id is too large for a uint256"));
+        }
+    }
+    Ok(id)
+}
+
+/// A token id in decimal, as collections show them
+pub fn format_token_id(id: &[u8; 32]) -> String {
+    let mut id = *id;
+    let mut digits = Vec::new();
+    while id.iter().any(|b| *b != 0) {
+        // id = id / 10, from the highest byte, keeping the remainder as the next digit
+        let mut remainder = 0u16;
+        for byte in id.iter_mut() {
+            let value = (remainder << 8) | *byte as u16;
+            *byte = (value / 10) as u8;
+            remainder = value % 10;
+        }
+        digits.push(b'0' + remainder as u8);
+    }
+    match digits.is_empty() {
+        true => String::from("0"),
+        false => digits.iter().rev().map(|d| *d as char).collect(),
+    }
+}
+
+/// Asks the collection who owns token `id`. Fails for a token that doesn't exist, which
+/// collections revert on.
+pub fn owner_of(rpc: &dyn EthRpc, collection: &str, id: &[u8; 32]) -> Result<[u8; 20], String> {
+    let result = rpc.eth_call(collection, &[&selector("ownerOf(uint256)")[..], id].concat())?;
+    match result.get(12..32) {
+        Some(owner) if owner != [0; 20] => Ok(owner.try_into().unwrap()),
+        // a contract that doesn't exist returns nothing rather than failing
+        _ if result.is_empty() => Err(format!("There is no contract at {}", collection)),
+        _ => Err(format!("Token {} has no owner in this collection", format_token_id(id))),
+    }
+}
+
+/// The metadata URI of token `id`, if the collection has one and it can be shown
+pub fn token_uri(rpc: &dyn EthRpc, collection: &str, id: &[u8; 32]) -> Option<String> {
+    let result = rpc.eth_call(collection, &[&selector("tokenURI(uint256)")[..], id].concat()).ok()?;
+    erc20::decode_string(&result).filter(|uri| erc20::is_valid_name(uri))
+}
+
+/// The name a collection gives itself, if it can be shown
+pub fn collection_name(rpc: &dyn EthRpc, collection: &str) -> Option<String> {
+    let result = rpc.eth_call(collection, &selector("name()")).ok()?;
+    erc20::decode_text(&result).map(|name| String::from(name.trim())).filter(|name| erc20::is_valid_name(name))
+}
+
+/// The data of a `safeTransferFrom(from, to, id)` call. The safe transfer refuses to send to a
+/// contract that doesn't say it can hold NFTs, where one would be stuck for good.
+pub fn safe_transfer_data(from: &[u8; 20], to: &[u8; 20], id: &[u8; 32]) -> Vec<u8> {
+    [&selector("safeTransferFrom(address,address,uint256)")[..], &encode_address(from), &encode_address(to), id].concat()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rpc::mock::MockRpc;
+
+    #[test]
+    fn test_token_id() {
+        assert_eq!(format_token_id(&parse_token_id("0").unwrap()), "0");
+        assert_eq!(parse_token_id("258").unwrap()[30..], [1, 2]);
+        assert_eq!(parse_token_id(" 0x102 "), parse_token_id("258"));
+        let max = "115792089237316195423570985008687907853269984665640564039457584007913129639935";
+        assert_eq!(parse_token_id(max), Ok([0xff; 32]));
+        assert_eq!(format_token_id(&[0xff; 32]), max);
+        assert_eq!(parse_token_id("115792089237316195423570985008687907853269984665640564039457584007913129639936").unwrap_err(),
+            "The token id is too large for a uint256");
+        let ens_id = "79233663829379634837589865448569342784712482819484549289560981379859480642508";
+        assert_eq!(format_token_id(&parse_token_id(ens_id).unwrap()), ens_id);
+        assert!(parse_token_id("-1").is_err() && parse_token_id("1.5").is_err() && parse_token_id("").is_err());
+        assert!(parse_token_id("0x").is_err() && parse_token_id(&format!("0x{}", "1".repeat(65))).is_err());
+    }
+
+    #[test]
+    fn test_calls() {
+        assert_eq!(hex::encode(selector("ownerOf(uint256)")), "6352211e");
+        assert_eq!(hex::encode(selector("tokenURI(uint256)")), "c87b56dd");
+        assert_eq!(hex::encode(selector("safeTransferFrom(address,address,uint256)")), "42842e0e");
+
+        let from: [u8; 20] = hex::decode("f39fd6e51aad88f6f4ce6ab8827279cfffb92266").unwrap().try_into().unwrap();
+        let to: [u8; 20] = hex::decode("70997970c51812dc3a010c7d01b50e0d17dc79c8").unwrap().try_into().unwrap();
+        let id = parse_token_id("1234").unwrap();
+        assert_eq!(hex::encode(safe_transfer_data(&from, &to, &id)), "42842e0e\
+            000000000000000000000000f39fd6e51aad88f6f4ce6ab8827279cfffb92266\
+            00000000000000000000000070997970c51812dc3a010c7d01b50e0d17dc79c8\
+            00000000000000000000000000000000000000000000000000000000000004d2");
+
+        let rpc = MockRpc::new(1);
+        let collection = "0xbc4ca0eda7647a8ab7c2061c2e118a18a936f13d";
+        assert_eq!(owner_of(&rpc, collection, &id).unwrap_err(), format!("There is no contract at {}", collection));
+        rpc.contract(collection, &[&selector("ownerOf(uint256)")[..], &id].concat(), encode_address(&from).to_vec());
+        assert_eq!(owner_of(&rpc, collection, &id), Ok(from));
+        rpc.contract(collection, &[&selector("ownerOf(uint256)")[..], &id].concat(), vec![0; 32]);
+        assert_eq!(owner_of(&rpc, collection, &id).unwrap_err(), "Token 1234 has no owner in this collection");
+
+        assert_eq!(token_uri(&rpc, collection, &id), None);
+        let uri = "ipfs://QmeSjSinHpPnmXmspMjwiXyN6zS4E9zccariGR3jxcaWtq/1234";
+        let word = |n: usize| [vec![0; 24], (n as u64).to_be_bytes().to_vec()].concat();
+        let encoded = [word(32), word(uri.len()), uri.as_bytes().to_vec(), vec![0; 32 - uri.len() % 32]].concat();
+        rpc.contract(collection, &[&selector("tokenURI(uint256)")[..], &id].concat(), encoded);
+        assert_eq!(token_uri(&rpc, collection, &id).as_deref(), Some(uri));
+    }
+
+    #[test]
+    fn test_collections() {
+        let mut collections = Collections::default();
+        let added = collections.add(1, " Apes ", "0xbc4ca0eda7647a8ab7c2061c2e118a18a936f13d").unwrap().clone();
+        assert_eq!((added.name.as_str(), added.address.as_str()), ("Apes", "0xBC4CA0EdA7647A8aB7C2061c2E118A18a936f13D"));
+        assert_eq!(collections.add(1, "Again", "0xBC4CA0EDA7647A8AB7C2061C2E118A18A936F13D").unwrap_err(),
+            CollectionError::Duplicate { name: String::from("Apes"), address: added.address.clone() });
+        assert_eq!(collections.add(1, "", "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").unwrap_err(), CollectionError::InvalidName);
+        assert!(matches!(collections.add(1, "Bad", "0x5aaeb6053F3E94C9b9A09f33669435E7Ef1BeAed0"), Err(CollectionError::InvalidAddress(_))));
+
+        // a mainnet collection isn't shown on Sepolia
+        collections.add(11155111, "Test apes", "0xbc4ca0eda7647a8ab7c2061c2e118a18a936f13d").unwrap();
+        assert_eq!(collections.for_chain(11155111).map(|c| c.name.as_str()).collect::<Vec<_>>(), ["Test apes"]);
+        assert_eq!(collections.remove(1, &added.address).unwrap(), added);
+        assert_eq!(collections.remove(1, &added.address).unwrap_err(), CollectionError::NotFound(added.address.clone()));
+        assert_eq!(collections.for_chain(1).count(), 0);
+
+        let json = serde_json::to_string(&collections).unwrap();
+        assert_eq!(json, r#"[{"chain_id":11155111,"name":"Test apes","address":"0xBC4CA0EdA7647A8aB7C2061c2E118A18a936f13D"}]"#);
+        assert_eq!(serde_json::from_str::<Collections>(&json).unwrap(), collections);
+    }
+}
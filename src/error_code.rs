@@ -0,0 +1,290 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::address_book::AddressBookError;
+use crate::instance::LockError;
+use crate::migrations::{self, VersionError};
+use crate::networks::ChainCheckError;
+use crate::rpc::CallError;
+use crate::storage::{LoadError, UnlockError};
+use crate::token_registry::TokenRegistryError;
+use crate::utils::AddressParseError;
+
+/// The process exit code of each class of failure. 1 is anything not in a class of its own.
+pub const EXIT_INTERNAL: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_AUTH: i32 = 3;
+pub const EXIT_NETWORK: i32 = 4;
+pub const EXIT_REJECTED_BY_NODE: i32 = 5;
+pub const EXIT_WALLET_FILE: i32 = 6;
+pub const EXIT_USER_REJECTED: i32 = 7;
+
+/// What went wrong, for scripts to branch on. A code keeps its meaning once released; a new kind
+/// of failure gets a new code rather than reusing one.
+///
+/// | Code                   | Exit | Mapped from                                                  |
+/// |------------------------|------|--------------------------------------------------------------|
+/// | `INVALID_ARGUMENT`     | 2    | the command line                                             |
+/// | `INVALID_ADDRESS`      | 2    | `AddressParseError`, the `InvalidAddress` of `AddressBookError` and `TokenRegistryError` |
+/// | `INVALID_INPUT`        | 2    | the other `AddressBookError` and `TokenRegistryError` variants |
+/// | `NOT_FOUND`            | 2    | the `NotFound` of `AddressBookError` and `TokenRegistryError` |
+/// | `BAD_PASSWORD`         | 3    | `UnlockError::IncorrectPassword`                             |
+/// | `RPC_UNREACHABLE`      | 4    | `ChainCheckError::Unreachable`, node calls that got no answer |
+/// | `WRONG_CHAIN`          | 4    | `ChainCheckError::WrongChain`                                |
+/// | `NODE_NOT_CONFIGURED`  | 4    | no network selected and no endpoint in the settings          |
+/// | `INSUFFICIENT_FUNDS`   | 5    | the node's "insufficient funds"                              |
+/// | `NONCE_TOO_LOW`        | 5    | the node's "nonce too low"                                   |
+/// | `UNDERPRICED`          | 5    | the node's "underpriced" and "replacement transaction underpriced" |
+/// | `GAS_TOO_LOW`          | 5    | the node's "gas too low"                                     |
+/// | `ALREADY_KNOWN`        | 5    | the node's "already known"                                   |
+/// | `NODE_ERROR`           | 5    | any other error the node returned                            |
+/// | `WALLET_UNREADABLE`    | 6    | `LoadError::Unreadable`, a wallet list that can't be read    |
+/// | `WALLET_CORRUPT`       | 6    | the other `LoadError` variants, `UnlockError::Corrupt` and `Modified` |
+/// | `UNSUPPORTED_VERSION`  | 6    | `VersionError`, directly or in `LoadError` and `UnlockError` |
+/// | `WALLET_IN_USE`        | 6    | `LockError::AlreadyOpen` and `AlreadyOpenElsewhere`          |
+/// | `DATA_DIR_UNAVAILABLE` | 6    | a data directory that can't be found or created              |
+/// | `USER_REJECTED`        | 7    | the user declined to go on                                   |
+/// | `INTERNAL`             | 1    | anything else, such as `LockError::Io`                       |
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    InvalidArgument,
+    InvalidAddress,
+    InvalidInput,
+    NotFound,
+    BadPassword,
+    RpcUnreachable,
+    WrongChain,
+    NodeNotConfigured,
+    InsufficientFunds,
+    NonceTooLow,
+    Underpriced,
+    GasTooLow,
+    AlreadyKnown,
+    NodeError,
+    WalletUnreadable,
+    WalletCorrupt,
+    UnsupportedVersion,
+    WalletInUse,
+    DataDirUnavailable,
+    UserRejected,
+    Internal,
+}
+
+impl ErrorCode {
+    /// The exit code of the code's class, as in the table above
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ErrorCode::InvalidArgument | ErrorCode::InvalidAddress | ErrorCode::InvalidInput | ErrorCode::NotFound => EXIT_USAGE,
+            ErrorCode::BadPassword => EXIT_AUTH,
+            ErrorCode::RpcUnreachable | ErrorCode::WrongChain | ErrorCode::NodeNotConfigured => EXIT_NETWORK,
+            ErrorCode::InsufficientFunds | ErrorCode::NonceTooLow | ErrorCode::Underpriced | ErrorCode::GasTooLow
+                | ErrorCode::AlreadyKnown | ErrorCode::NodeError => EXIT_REJECTED_BY_NODE,
+            ErrorCode::WalletUnreadable | ErrorCode::WalletCorrupt | ErrorCode::UnsupportedVersion | ErrorCode::WalletInUse
+                | ErrorCode::DataDirUnavailable => EXIT_WALLET_FILE,
+            ErrorCode::UserRejected => EXIT_USER_REJECTED,
+            ErrorCode::Internal => EXIT_INTERNAL,
+        }
+    }
+}
+
+/// A failure as JSON mode reports it: `{ "error": { "code": ..., "message": ..., "details": {...} } }`.
+/// The message is the one shown outside JSON mode, and the details hold what a script may need
+/// without parsing it, such as the address that was refused.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ErrorReport {
+    pub code: ErrorCode,
+    pub message: String,
+    pub details: Map<String, Value>,
+}
+
+impl ErrorReport {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> ErrorReport {
+        ErrorReport { code, message: message.into(), details: Map::new() }
+    }
+
+    /// Adds a detail
+    pub fn with(mut self, key: &str, value: impl Into<Value>) -> ErrorReport {
+        self.details.insert(String::from(key), value.into());
+        self
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::json!({ "error": self }).to_string()
+    }
+}
+
+impl From<VersionError> for ErrorReport {
+    fn from(e: VersionError) -> ErrorReport {
+        let report = ErrorReport::new(ErrorCode::UnsupportedVersion, e.to_string());
+        match e {
+            VersionError::TooNew(version) => report.with("version", version).with("supported", migrations::CURRENT_VERSION),
+        }
+    }
+}
+
+impl From<UnlockError> for ErrorReport {
+    fn from(e: UnlockError) -> ErrorReport {
+        let message = e.to_string();
+        match e {
+            UnlockError::IncorrectPassword => ErrorReport::new(ErrorCode::BadPassword, message),
+            UnlockError::Corrupt(reason) => ErrorReport::new(ErrorCode::WalletCorrupt, message).with("reason", reason),
+            UnlockError::Modified => ErrorReport::new(ErrorCode::WalletCorrupt, message).with("modified", true),
+            UnlockError::Version(e) => e.into(),
+        }
+    }
+}
+
+impl From<LoadError> for ErrorReport {
+    fn from(e: LoadError) -> ErrorReport {
+        let message = e.to_string();
+        match e {
+            LoadError::Unreadable(file, _) => ErrorReport::new(ErrorCode::WalletUnreadable, message).with("file", file),
+            LoadError::Version(e) => e.into(),
+            LoadError::Empty(file) | LoadError::Truncated(file, _) | LoadError::Malformed(file, _) | LoadError::Corrupt(file, _) =>
+                ErrorReport::new(ErrorCode::WalletCorrupt, message).with("file", file),
+            LoadError::MissingField(file, field) | LoadError::BadField(file, field) =>
+                ErrorReport::new(ErrorCode::WalletCorrupt, message).with("file", file).with("field", field),
+        }
+    }
+}
+
+impl From<ChainCheckError> for ErrorReport {
+    fn from(e: ChainCheckError) -> ErrorReport {
+        let message = e.to_string();
+        match e {
+            ChainCheckError::WrongChain { url, served, expected } =>
+                ErrorReport::new(ErrorCode::WrongChain, message).with("url", url).with("served", served).with("expected", expected),
+            ChainCheckError::Unreachable { url, .. } => ErrorReport::new(ErrorCode::RpcUnreachable, message).with("url", url),
+        }
+    }
+}
+
+impl From<LockError> for ErrorReport {
+    fn from(e: LockError) -> ErrorReport {
+        let message = e.to_string();
+        match e {
+            LockError::AlreadyOpen(pid) => ErrorReport::new(ErrorCode::WalletInUse, message).with("pid", pid),
+            LockError::AlreadyOpenElsewhere => ErrorReport::new(ErrorCode::WalletInUse, message),
+            LockError::Io(file, _) => ErrorReport::new(ErrorCode::Internal, message).with("file", file),
+        }
+    }
+}
+
+impl From<AddressBookError> for ErrorReport {
+    fn from(e: AddressBookError) -> ErrorReport {
+        let message = e.to_string();
+        match e {
+            AddressBookError::InvalidAddress(address) => ErrorReport::new(ErrorCode::InvalidAddress, message).with("address", address),
+            AddressBookError::NotFound(name) => ErrorReport::new(ErrorCode::NotFound, message).with("name", name),
+            AddressBookError::EmptyName | AddressBookError::NameIsAddress(_) | AddressBookError::DuplicateName(_) =>
+                ErrorReport::new(ErrorCode::InvalidInput, message),
+        }
+    }
+}
+
+impl From<TokenRegistryError> for ErrorReport {
+    fn from(e: TokenRegistryError) -> ErrorReport {
+        let message = e.to_string();
+        match e {
+            TokenRegistryError::InvalidAddress(address) => ErrorReport::new(ErrorCode::InvalidAddress, message).with("address", address),
+            TokenRegistryError::NotFound(address) => ErrorReport::new(ErrorCode::NotFound, message).with("address", address),
+            TokenRegistryError::Duplicate { symbol, address } =>
+                ErrorReport::new(ErrorCode::InvalidInput, message).with("symbol", symbol).with("address", address),
+            TokenRegistryError::EmptySymbol | TokenRegistryError::InvalidSymbol(_) | TokenRegistryError::TooManyDecimals(_)
+                | TokenRegistryError::InvalidName => ErrorReport::new(ErrorCode::InvalidInput, message),
+        }
+    }
+}
+
+impl From<CallError> for ErrorReport {
+    fn from(e: CallError) -> ErrorReport {
+        let message = e.to_string();
+        match e {
+            CallError::TimedOut { method } => ErrorReport::new(ErrorCode::RpcUnreachable, message).with("method", method).with("timed_out", true),
+            CallError::Unreachable { method, attempts, .. } =>
+                ErrorReport::new(ErrorCode::RpcUnreachable, message).with("method", method).with("attempts", attempts),
+            CallError::Node { method, error } =>
+                ErrorReport::new(error.error_code(), message).with("method", method).with("node_code", error.code).with("node_message", error.message),
+            CallError::InvalidResponse(_) => ErrorReport::new(ErrorCode::NodeError, message),
+        }
+    }
+}
+
+impl From<AddressParseError> for ErrorReport {
+    fn from(e: AddressParseError) -> ErrorReport {
+        ErrorReport::new(ErrorCode::InvalidAddress, e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Every code with its name and exit code. Scripts depend on these, so a change here is a
+    /// breaking change.
+    const PINNED: [(ErrorCode, &str, i32); 21] = [
+        (ErrorCode::InvalidArgument, "INVALID_ARGUMENT", 2),
+        (ErrorCode::InvalidAddress, "INVALID_ADDRESS", 2),
+        (ErrorCode::InvalidInput, "INVALID_INPUT", 2),
+        (ErrorCode::NotFound, "NOT_FOUND", 2),
+        (ErrorCode::BadPassword, "BAD_PASSWORD", 3),
+        (ErrorCode::RpcUnreachable, "RPC_UNREACHABLE", 4),
+        (ErrorCode::WrongChain, "WRONG_CHAIN", 4),
+        (ErrorCode::NodeNotConfigured, "NODE_NOT_CONFIGURED", 4),
+        (ErrorCode::InsufficientFunds, "INSUFFICIENT_FUNDS", 5),
+        (ErrorCode::NonceTooLow, "NONCE_TOO_LOW", 5),
+        (ErrorCode::Underpriced, "UNDERPRICED", 5),
+        (ErrorCode::GasTooLow, "GAS_TOO_LOW", 5),
+        (ErrorCode::AlreadyKnown, "ALREADY_KNOWN", 5),
+        (ErrorCode::NodeError, "NODE_ERROR", 5),
+        (ErrorCode::WalletUnreadable, "WALLET_UNREADABLE", 6),
+        (ErrorCode::WalletCorrupt, "WALLET_CORRUPT", 6),
+        (ErrorCode::UnsupportedVersion, "UNSUPPORTED_VERSION", 6),
+        (ErrorCode::WalletInUse, "WALLET_IN_USE", 6),
+        (ErrorCode::DataDirUnavailable, "DATA_DIR_UNAVAILABLE", 6),
+        (ErrorCode::UserRejected, "USER_REJECTED", 7),
+        (ErrorCode::Internal, "INTERNAL", 1),
+    ];
+
+    #[test]
+    fn test_codes_are_pinned() {
+        for (code, name, exit_code) in PINNED {
+            assert_eq!(serde_json::to_value(code).unwrap(), name);
+            assert_eq!(code.exit_code(), exit_code, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_mapping() {
+        let code = |report: ErrorReport| report.code;
+        assert_eq!(code(UnlockError::IncorrectPassword.into()), ErrorCode::BadPassword);
+        assert_eq!(code(UnlockError::Modified.into()), ErrorCode::WalletCorrupt);
+        assert_eq!(code(UnlockError::Version(VersionError::TooNew(99)).into()), ErrorCode::UnsupportedVersion);
+        assert_eq!(code(LoadError::Unreadable(String::from("w.json"), String::from("denied")).into()), ErrorCode::WalletUnreadable);
+        assert_eq!(code(LoadError::Truncated(String::from("w.json"), String::from("EOF")).into()), ErrorCode::WalletCorrupt);
+        assert_eq!(code(LockError::AlreadyOpenElsewhere.into()), ErrorCode::WalletInUse);
+        assert_eq!(code(AddressBookError::InvalidAddress(String::from("0x12")).into()), ErrorCode::InvalidAddress);
+        assert_eq!(code(AddressBookError::DuplicateName(String::from("Bob")).into()), ErrorCode::InvalidInput);
+        assert_eq!(code(TokenRegistryError::NotFound(String::from("0x12")).into()), ErrorCode::NotFound);
+        assert_eq!(code(AddressParseError::InvalidLength.into()), ErrorCode::InvalidAddress);
+
+        let wrong_chain: ErrorReport = ChainCheckError::WrongChain { url: String::from("http://node"), served: 1, expected: 4 }.into();
+        assert_eq!(wrong_chain.code, ErrorCode::WrongChain);
+        assert_eq!(wrong_chain.details["served"], 1);
+        let unreachable: ErrorReport = ChainCheckError::Unreachable { url: String::from("http://node"), error: String::from("refused") }.into();
+        assert_eq!(unreachable.code, ErrorCode::RpcUnreachable);
+    }
+
+    #[test]
+    fn test_to_json() {
+        let report: ErrorReport = VersionError::TooNew(99).into();
+        let json: Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["error"]["code"], "UNSUPPORTED_VERSION");
+        assert_eq!(json["error"]["message"], VersionError::TooNew(99).to_string());
+        assert_eq!(json["error"]["details"], serde_json::json!({ "version": 99, "supported": migrations::CURRENT_VERSION }));
+        // details are there even when there are none, so scripts needn't check
+        let json: Value = serde_json::from_str(&ErrorReport::new(ErrorCode::UserRejected, "Canceled").to_json()).unwrap();
+        assert_eq!(json["error"]["details"], serde_json::json!({}));
+    }
+}
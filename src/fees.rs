@@ -0,0 +1,465 @@
+use serde::{Serialize, Deserialize};
+
+pub const GWEI: u128 = 1_000_000_000;
+/// Gas used by a plain ETH transfer, and the least any transaction can be given
+pub const TRANSFER_GAS_LIMIT: u128 = 21000;
+/// Percent added to gas estimates unless the preferences say otherwise
+pub const ESTIMATE_MARGIN_PERCENT: u32 = 20;
+
+/// Lowest gas price worth signing with on known networks, by chain id. Below it a transaction
+/// may never be picked up, even if the node quotes less during quiet periods.
+const NETWORK_MIN_GAS_PRICES: [(u64, u128); 7] = [
+    (1, GWEI),
+    (3, GWEI / 10),
+    (4, GWEI / 10),
+    (5, GWEI / 10),
+    (42, GWEI / 10),
+    (17000, GWEI / 10),
+    (11155111, GWEI / 10),
+];
+/// Floor for chains without a preset
+const DEFAULT_MIN_GAS_PRICE: u128 = GWEI / 10;
+
+/// Lowest tip per gas worth signing an EIP-1559 transaction with on known networks, by chain
+/// id. A node with little traffic may suggest no tip at all, which block builders can ignore.
+const NETWORK_MIN_PRIORITY_FEES: [(u64, u128); 3] = [
+    (1, GWEI / 10),
+    (17000, GWEI / 100),
+    (11155111, GWEI / 100),
+];
+/// Tip floor for chains without a preset
+const DEFAULT_MIN_PRIORITY_FEE: u128 = GWEI / 100;
+
+/// Returns the preset gas price floor for the chain
+pub fn network_min_gas_price(chain_id: u64) -> u128 {
+    NETWORK_MIN_GAS_PRICES.iter()
+        .find(|(id, _)| *id == chain_id)
+        .map_or(DEFAULT_MIN_GAS_PRICE, |(_, price)| *price)
+}
+
+/// Returns the preset tip floor for the chain
+pub fn network_min_priority_fee(chain_id: u64) -> u128 {
+    NETWORK_MIN_PRIORITY_FEES.iter()
+        .find(|(id, _)| *id == chain_id)
+        .map_or(DEFAULT_MIN_PRIORITY_FEE, |(_, tip)| *tip)
+}
+
+/// A computed gas price checked against the network floor
+#[derive(Debug, PartialEq)]
+pub struct ClampedGasPrice {
+    /// The price as computed or entered
+    pub computed: u128,
+    /// The price to sign with unless the clamp is overridden
+    pub price: u128,
+}
+
+impl ClampedGasPrice {
+    pub fn raised(&self) -> bool {
+        self.price != self.computed
+    }
+
+    /// A note for the user if the price was raised
+    pub fn note(&self) -> Option<String> {
+        self.raised().then(|| format!("Raised gas price to network minimum of {} gwei", wei_to_gwei(self.price)))
+    }
+
+    /// The price to sign with. `override_floor` keeps the computed price for this send only.
+    pub fn resolve(&self, override_floor: bool) -> u128 {
+        if override_floor {
+            self.computed
+        } else {
+            self.price
+        }
+    }
+}
+
+/// Raises `computed` to `floor` if it is below it
+pub fn clamp_gas_price(computed: u128, floor: u128) -> ClampedGasPrice {
+    ClampedGasPrice { computed, price: computed.max(floor) }
+}
+
+/// The fees of an EIP-1559 send with its tip checked against the network floor
+#[derive(Debug, PartialEq)]
+pub struct ClampedTip {
+    /// The most paid per gas and the fees as computed
+    pub computed: (u128, FeeMarket),
+    /// What to sign with unless the clamp is overridden. A raised tip raises the most paid per
+    /// gas by as much, so the room left for the base fee stays the same.
+    pub clamped: (u128, FeeMarket),
+}
+
+impl ClampedTip {
+    pub fn raised(&self) -> bool {
+        self.clamped != self.computed
+    }
+
+    /// A note for the user if the tip was raised
+    pub fn note(&self) -> Option<String> {
+        self.raised().then(|| format!("Raised the tip to network minimum of {} gwei", wei_to_gwei(self.clamped.1.priority_fee)))
+    }
+
+    /// What to sign with. `override_floor` keeps the computed fees for this send only.
+    pub fn resolve(&self, override_floor: bool) -> (u128, FeeMarket) {
+        if override_floor {
+            self.computed
+        } else {
+            self.clamped
+        }
+    }
+}
+
+/// Raises the tip of a send paying at most `price` per gas on `market` to `floor`. The tip
+/// signed is the one `GasPricing::new` gives, which the price caps.
+pub fn clamp_priority_fee(price: u128, market: FeeMarket, floor: u128) -> ClampedTip {
+    let tip = market.priority_fee.min(price);
+    let raise = floor.saturating_sub(tip);
+    ClampedTip {
+        computed: (price, market),
+        clamped: (price + raise, FeeMarket { priority_fee: tip + raise, ..market }),
+    }
+}
+
+/// How the gas price of a send is chosen, before it is changed for that send
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GasPriceStrategy {
+    /// A tenth below the node's quote, for sends that can wait
+    Slow,
+    /// The price the node quotes
+    #[default]
+    Network,
+    /// A quarter above the quote, to be picked up sooner
+    Fast,
+    /// Always this many wei, without asking the node
+    Fixed(u128),
+}
+
+impl GasPriceStrategy {
+    /// The speed tiers offered at send time, slowest first
+    pub const TIERS: [GasPriceStrategy; 3] = [GasPriceStrategy::Slow, GasPriceStrategy::Network, GasPriceStrategy::Fast];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GasPriceStrategy::Slow => "Slow",
+            GasPriceStrategy::Network => "Average",
+            GasPriceStrategy::Fast => "Fast",
+            GasPriceStrategy::Fixed(_) => "Custom",
+        }
+    }
+
+    /// The percent of the node's quote a tier pays
+    fn percent(&self) -> u128 {
+        match self {
+            GasPriceStrategy::Slow => 90,
+            GasPriceStrategy::Network | GasPriceStrategy::Fixed(_) => 100,
+            GasPriceStrategy::Fast => 125,
+        }
+    }
+
+    /// The gas price the strategy gives. On a network with `market` it is the most paid per
+    /// gas, and the tiers scale the tip rather than the whole price, so the fees to sign with
+    /// are returned with it. `network_price` is only asked for by the tiers of a network
+    /// without a base fee.
+    pub fn price(&self, network_price: impl FnOnce() -> Result<u128, String>, market: Option<FeeMarket>) -> Result<(u128, Option<FeeMarket>), String> {
+        match (self, market) {
+            (GasPriceStrategy::Fixed(price), _) => Ok((*price, market)),
+            (_, Some(market)) => {
+                let market = FeeMarket { priority_fee: market.priority_fee * self.percent() / 100, ..market };
+                Ok((market.max_fee_per_gas(), Some(market)))
+            },
+            (_, None) => network_price().map(|price| (price * self.percent() / 100, None)),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            GasPriceStrategy::Slow => String::from("a tenth below the network's quote"),
+            GasPriceStrategy::Network => String::from("the network's quote"),
+            GasPriceStrategy::Fast => String::from("a quarter above the network's quote"),
+            GasPriceStrategy::Fixed(price) => format!("fixed at {} gwei", wei_to_gwei(*price)),
+        }
+    }
+}
+
+/// The gas settings a wallet's sends start from, stored with the wallet. Each can still be
+/// changed for a single send.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct GasPreferences {
+    /// The least gas limit sends are signed with. The node's estimate is used when it is higher.
+    pub gas_limit: u128,
+    /// Added to the node's gas estimate, in case the transaction uses more when it is mined
+    /// than when it was estimated. Left out at the default, so files from before it still
+    /// match their MAC.
+    #[serde(skip_serializing_if = "is_default_margin")]
+    pub estimate_margin_percent: u32,
+    pub strategy: GasPriceStrategy,
+    /// Sends priced above this, in wei, can't be confirmed unless a price is entered for them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_gas_price: Option<u128>,
+    /// Sends of at most this many wei go out without the confirmation prompt. None confirms
+    /// every send.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auto_confirm_limit: Option<u128>,
+}
+
+impl Default for GasPreferences {
+    fn default() -> Self {
+        GasPreferences {
+            gas_limit: TRANSFER_GAS_LIMIT,
+            estimate_margin_percent: ESTIMATE_MARGIN_PERCENT,
+            strategy: GasPriceStrategy::Network,
+            max_gas_price: None,
+            auto_confirm_limit: None,
+        }
+    }
+}
+
+impl GasPreferences {
+    pub fn is_default(&self) -> bool {
+        *self == GasPreferences::default()
+    }
+
+    /// `price`, or the maximum gas price if it is lower
+    pub fn bound(&self, price: u128) -> u128 {
+        self.max_gas_price.map_or(price, |max| price.min(max))
+    }
+
+    pub fn exceeds_max(&self, price: u128) -> bool {
+        self.max_gas_price.is_some_and(|max| price > max)
+    }
+
+    pub fn auto_confirms(&self, wei_amount: u128) -> bool {
+        self.auto_confirm_limit.is_some_and(|limit| wei_amount <= limit)
+    }
+
+    /// The gas limit for a transaction the node estimated at `estimate`: the estimate with the
+    /// margin, or the preferred limit if that is higher. A plain transfer to an address without
+    /// code always uses exactly 21000, so it gets no margin.
+    pub fn gas_limit_for(&self, estimate: u128) -> u128 {
+        let limit = match estimate {
+            TRANSFER_GAS_LIMIT => estimate,
+            _ => estimate + (estimate * self.estimate_margin_percent as u128).div_ceil(100),
+        };
+        limit.max(self.gas_limit)
+    }
+}
+
+fn is_default_margin(percent: &u32) -> bool {
+    *percent == ESTIMATE_MARGIN_PERCENT
+}
+
+/// The fees of a network that charges a base fee per gas (EIP-1559), as they were when asked
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeeMarket {
+    /// The base fee of the latest block, which is burned
+    pub base_fee: u128,
+    /// The tip per gas the node suggests on top of it
+    pub priority_fee: u128,
+}
+
+impl FeeMarket {
+    /// From the base fee and the node's gas price, which it quotes as the base fee plus a tip
+    pub fn new(base_fee: u128, gas_price: u128) -> Self {
+        FeeMarket { base_fee, priority_fee: gas_price.saturating_sub(base_fee) }
+    }
+
+    /// The most a transaction should offer per gas: enough for the base fee to double, which
+    /// takes at least six full blocks, plus the tip
+    pub fn max_fee_per_gas(&self) -> u128 {
+        2 * self.base_fee + self.priority_fee
+    }
+
+    /// What a transaction offering at most `max_fee_per_gas` would pay per gas at the current
+    /// base fee
+    pub fn expected_price(&self, max_fee_per_gas: u128) -> u128 {
+        (self.base_fee + self.priority_fee).min(max_fee_per_gas)
+    }
+}
+
+/// What a transaction is signed to pay per gas
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GasPricing {
+    /// A legacy transaction pays its gas price, all of it
+    Legacy(u128),
+    /// An EIP-1559 transaction pays the base fee plus a tip of up to `max_priority_fee_per_gas`,
+    /// never more than `max_fee_per_gas` in all
+    FeeMarket { max_fee_per_gas: u128, max_priority_fee_per_gas: u128 },
+}
+
+impl GasPricing {
+    /// A send priced at `price`, which on a network with `market` is the most it pays per gas
+    pub fn new(price: u128, market: Option<FeeMarket>) -> Self {
+        match market {
+            Some(market) => GasPricing::FeeMarket { max_fee_per_gas: price, max_priority_fee_per_gas: market.priority_fee.min(price) },
+            None => GasPricing::Legacy(price),
+        }
+    }
+}
+
+/// Checks a gas limit entered by the user
+pub fn parse_gas_limit(input: &str) -> Result<u128, String> {
+    match input.trim().parse::<u128>() {
+        Ok(limit) if limit >= TRANSFER_GAS_LIMIT => Ok(limit),
+        Ok(_) => Err(format!("The gas limit can't be below {}", TRANSFER_GAS_LIMIT)),
+        Err(_) => Err(format!("{} is not a whole number", input.trim())),
+    }
+}
+
+/// Parses a gas price in gwei, with up to 9 decimal places, into wei
+pub fn gwei_to_wei(input: &str) -> Result<u128, String> {
+    let input = input.trim();
+    let invalid = || format!("{} is not a gas price in gwei", input);
+    let (whole, fraction) = input.split_once('.').unwrap_or((input, ""));
+    if whole.is_empty() && fraction.is_empty() || fraction.len() > 9 || !(whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit())) {
+        return Err(invalid());
+    }
+    let whole = if whole.is_empty() { 0 } else { whole.parse::<u128>().map_err(|_| invalid())? };
+    let fraction = if fraction.is_empty() { 0 } else { format!("{:0<9}", fraction).parse::<u128>().unwrap() };
+    whole.checked_mul(GWEI).and_then(|w| w.checked_add(fraction)).ok_or_else(invalid)
+}
+
+/// Formats a wei amount in gwei, without trailing zeros
+pub fn wei_to_gwei(amount: u128) -> String {
+    let whole = amount / GWEI;
+    let fraction = amount % GWEI;
+    if fraction == 0 {
+        return whole.to_string();
+    }
+    let fraction = format!("{:09}", fraction);
+    format!("{}.{}", whole, fraction.trim_end_matches('0'))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_network_presets() {
+        assert_eq!(network_min_gas_price(1), GWEI);
+        for chain_id in [3, 4, 5, 42] {
+            assert_eq!(network_min_gas_price(chain_id), 100_000_000);
+        }
+        assert_eq!(network_min_gas_price(99), DEFAULT_MIN_GAS_PRICE);
+
+        for (chain_id, floor) in NETWORK_MIN_GAS_PRICES {
+            let tiny = clamp_gas_price(1, network_min_gas_price(chain_id));
+            assert_eq!(tiny.price, floor);
+            assert!(tiny.raised());
+            let busy = clamp_gas_price(floor * 3, network_min_gas_price(chain_id));
+            assert_eq!(busy.price, floor * 3);
+            assert_eq!(busy.note(), None);
+        }
+    }
+
+    #[test]
+    fn test_note_and_override() {
+        let clamped = clamp_gas_price(0, GWEI / 10);
+        assert_eq!(clamped.note().unwrap(), "Raised gas price to network minimum of 0.1 gwei");
+        assert_eq!(clamped.resolve(false), GWEI / 10);
+        assert_eq!(clamped.resolve(true), 0);
+
+        // a zero floor disables the clamp altogether
+        assert!(!clamp_gas_price(0, 0).raised());
+    }
+
+    #[test]
+    fn test_tip_floor() {
+        assert_eq!(network_min_priority_fee(1), GWEI / 10);
+        assert_eq!(network_min_priority_fee(99), DEFAULT_MIN_PRIORITY_FEE);
+
+        // a node quoting the base fee alone suggests no tip
+        let market = FeeMarket::new(20 * GWEI, 20 * GWEI);
+        let clamped = clamp_priority_fee(market.max_fee_per_gas(), market, GWEI / 10);
+        assert_eq!(clamped.note().unwrap(), "Raised the tip to network minimum of 0.1 gwei");
+        let (price, raised) = clamped.resolve(false);
+        assert_eq!((price, raised.priority_fee), (40 * GWEI + GWEI / 10, GWEI / 10));
+        assert_eq!(GasPricing::new(price, Some(raised)), GasPricing::FeeMarket { max_fee_per_gas: price, max_priority_fee_per_gas: GWEI / 10 });
+        assert_eq!(clamped.resolve(true), (40 * GWEI, market));
+
+        // a price below the tip caps it, and is raised with it
+        let market = FeeMarket { base_fee: GWEI, priority_fee: 2 * GWEI };
+        assert_eq!(clamp_priority_fee(GWEI / 20, market, GWEI / 10).resolve(false), (GWEI / 10, FeeMarket { priority_fee: GWEI / 10, ..market }));
+        let busy = clamp_priority_fee(4 * GWEI, market, GWEI / 10);
+        assert!(!busy.raised());
+        assert_eq!(busy.note(), None);
+        assert_eq!(busy.resolve(false), (4 * GWEI, market));
+    }
+
+    #[test]
+    fn test_gas_preferences() {
+        let mut preferences = GasPreferences::default();
+        assert!(preferences.is_default());
+        assert_eq!(GasPriceStrategy::Slow.price(|| Ok(40), None).unwrap(), (36, None));
+        assert_eq!(GasPriceStrategy::Network.price(|| Ok(40), None).unwrap(), (40, None));
+        assert_eq!(GasPriceStrategy::Fast.price(|| Ok(40), None).unwrap(), (50, None));
+        // a fixed price doesn't ask the node
+        assert_eq!(GasPriceStrategy::Fixed(7).price(|| Err(String::from("offline")), None).unwrap(), (7, None));
+
+        // with a base fee, the tiers change the tip
+        let market = FeeMarket { base_fee: 20 * GWEI, priority_fee: 2 * GWEI };
+        let fast = FeeMarket { priority_fee: 2 * GWEI + GWEI / 2, ..market };
+        assert_eq!(GasPriceStrategy::Fast.price(|| Err(String::from("unused")), Some(market)).unwrap(), (42 * GWEI + GWEI / 2, Some(fast)));
+        assert_eq!(market.expected_price(42 * GWEI), 22 * GWEI);
+        assert_eq!(market.expected_price(21 * GWEI), 21 * GWEI);
+
+        assert!(!preferences.exceeds_max(u128::MAX));
+        assert_eq!(preferences.bound(u128::MAX), u128::MAX);
+        preferences.max_gas_price = Some(50 * GWEI);
+        assert_eq!(preferences.bound(60 * GWEI), 50 * GWEI);
+        assert!(!preferences.exceeds_max(50 * GWEI));
+        assert!(preferences.exceeds_max(50 * GWEI + 1));
+        assert!(!preferences.auto_confirms(0));
+        preferences.auto_confirm_limit = Some(1000);
+        assert!(preferences.auto_confirms(1000) && !preferences.auto_confirms(1001));
+
+        // a contract's estimate gets the margin, a plain transfer's doesn't
+        assert_eq!(preferences.gas_limit_for(30_001), 36_002);
+        assert_eq!(preferences.gas_limit_for(TRANSFER_GAS_LIMIT), TRANSFER_GAS_LIMIT);
+        preferences.gas_limit = 60_000;
+        assert_eq!(preferences.gas_limit_for(30_001), 60_000);
+        preferences.estimate_margin_percent = 0;
+        assert_eq!(GasPreferences { gas_limit: TRANSFER_GAS_LIMIT, ..preferences.clone() }.gas_limit_for(30_001), 30_001);
+
+        // files from before the preferences existed get the defaults
+        assert!(serde_json::from_str::<GasPreferences>("{}").unwrap().is_default());
+        let json = serde_json::to_string(&preferences).unwrap();
+        assert_eq!(serde_json::from_str::<GasPreferences>(&json).unwrap(), preferences);
+    }
+
+    #[test]
+    fn test_fee_market() {
+        let market = FeeMarket::new(20 * GWEI, 22 * GWEI);
+        assert_eq!(market.priority_fee, 2 * GWEI);
+        assert_eq!(market.max_fee_per_gas(), 42 * GWEI);
+        // a node quoting below the base fee suggests no tip
+        assert_eq!(FeeMarket::new(20 * GWEI, GWEI).priority_fee, 0);
+
+        assert_eq!(GasPricing::new(42 * GWEI, Some(market)), GasPricing::FeeMarket { max_fee_per_gas: 42 * GWEI, max_priority_fee_per_gas: 2 * GWEI });
+        // the tip can't be more than all that is paid
+        assert_eq!(GasPricing::new(GWEI, Some(market)), GasPricing::FeeMarket { max_fee_per_gas: GWEI, max_priority_fee_per_gas: GWEI });
+        assert_eq!(GasPricing::new(GWEI, None), GasPricing::Legacy(GWEI));
+    }
+
+    #[test]
+    fn test_parse_gas_input() {
+        assert_eq!(parse_gas_limit(" 60000 ").unwrap(), 60000);
+        assert_eq!(parse_gas_limit("20999").unwrap_err(), "The gas limit can't be below 21000");
+        assert!(parse_gas_limit("lots").is_err());
+
+        assert_eq!(gwei_to_wei("12").unwrap(), 12 * GWEI);
+        assert_eq!(gwei_to_wei("1.5").unwrap(), 1_500_000_000);
+        assert_eq!(gwei_to_wei(".000000001").unwrap(), 1);
+        for invalid in ["", ".", "1.0000000001", "-1", "1e9", "1.2.3"] {
+            assert!(gwei_to_wei(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_wei_to_gwei() {
+        assert_eq!(wei_to_gwei(0), "0");
+        assert_eq!(wei_to_gwei(GWEI), "1");
+        assert_eq!(wei_to_gwei(1_500_000_000), "1.5");
+        assert_eq!(wei_to_gwei(1), "0.000000001");
+    }
+}
@@ -0,0 +1,179 @@
+use std::ops::RangeInclusive;
+
+use bip39::{Mnemonic, Language, Seed};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use crate::crypto::{self, checksum_address, salted_hash, Argon2Params, PasswordBox, PasswordKdf, PasswordKey, SealedBox};
+use crate::history::{self, TxRecord};
+use crate::journal::TransactionStatus;
+use crate::migrations;
+use crate::storage::{Wallet, PassphraseHash, RECOVERY_PHRASE_CONTEXT};
+use crate::schedule::ScheduledTransaction;
+
+/// Anvil and hardhat's default mnemonic, so fixture addresses match their funded accounts
+pub const FIXTURE_MNEMONIC: &str = "test test test test test test test test test test test junk";
+pub const FIXTURE_PASSWORD: &str = "fixture-password";
+pub const FIXTURE_PASSPHRASE: &str = "fixture-passphrase";
+
+/// Base timestamp for scheduled transactions, so fixtures don't depend on the clock
+const FIXTURE_EPOCH: u64 = 1_700_000_000;
+/// The chain id the integration tests start anvil with
+const FIXTURE_CHAIN_ID: u64 = 4;
+/// How many transactions the first account has sent
+const FIXTURE_HISTORY: u64 = 3;
+/// How many entries the address book holds
+const FIXTURE_CONTACTS: usize = 2;
+
+/// Every file version a fixture can be generated in. Legacy files have another layout, which
+/// `tests/data` holds a sample of.
+pub const FIXTURE_VERSIONS: RangeInclusive<u32> = migrations::UNVERSIONED..=migrations::CURRENT_VERSION;
+
+pub struct FixtureOptions {
+    /// Seeds every random choice in the fixture
+    pub seed: u64,
+    /// How many accounts the wallet holds, at least 1
+    pub accounts: usize,
+    /// The file version to write, one of `FIXTURE_VERSIONS`. What that version can't hold is
+    /// left out.
+    pub version: u32,
+}
+
+impl Default for FixtureOptions {
+    fn default() -> Self {
+        FixtureOptions { seed: 0, accounts: 3, version: migrations::CURRENT_VERSION }
+    }
+}
+
+/// Builds an unlocked wallet from the fixture mnemonic and password, with labeled accounts,
+/// nonces, a scheduled transaction, a protected account, backup check data and the recovery
+/// phrase, and from the versions that keep them on, a transaction history and address book
+pub fn generate(options: &FixtureOptions) -> Wallet {
+    assert!(FIXTURE_VERSIONS.contains(&options.version), "No fixture for version {}", options.version);
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let mnemonic = Mnemonic::from_phrase(FIXTURE_MNEMONIC, Language::English).unwrap();
+    let seed = Seed::new(&mnemonic, "");
+    let mut wallet = Wallet::from(String::from(FIXTURE_PASSWORD), mnemonic.clone(), "");
+    wallet.name = String::from("fixture");
+    wallet.created_at = Some(FIXTURE_EPOCH);
+    wallet.network = Some(FIXTURE_CHAIN_ID);
+    wallet.version = options.version;
+
+    let metadata = &mut wallet.accounts_metadata;
+    if let Some(backup) = &mut metadata.backup {
+        backup.created_at = FIXTURE_EPOCH;
+    }
+    for index in 1..options.accounts.max(1) {
+        metadata.create_account(index);
+    }
+    for (index, account) in metadata.accounts.iter_mut().enumerate() {
+        account.label = Some(format!("Fixture account {}", index));
+        account.nonce = rng.gen_range(0..100);
+    }
+
+    let recipient = metadata.accounts.last().unwrap().address.clone();
+    metadata.accounts[0].scheduled.push(ScheduledTransaction {
+        recipient: recipient.clone(),
+        wei_amount: rng.gen_range(1..1_000_000_000_000_000_000),
+        send_at: FIXTURE_EPOCH + rng.gen_range(0..86400),
+        presigned: None,
+    });
+
+    // version 3 added the history and version 4 the address book
+    if options.version >= 3 {
+        let sender = &mut metadata.accounts[0];
+        for nonce in 0..FIXTURE_HISTORY {
+            history::push(&mut sender.history, TxRecord {
+                hash: String::from("0x") + &hex::encode(rng.gen::<[u8; 32]>()),
+                recipient: recipient.clone(),
+                wei_amount: rng.gen_range(1..1_000_000_000_000_000_000),
+                gas_price: rng.gen_range(1_000_000_000..100_000_000_000),
+                nonce,
+                sent_at: FIXTURE_EPOCH + nonce * 3600,
+                status: if nonce + 1 < FIXTURE_HISTORY { TransactionStatus::Confirmed } else { TransactionStatus::Pending },
+                relayer: None,
+            });
+        }
+        sender.nonce = sender.nonce.max(FIXTURE_HISTORY);
+    }
+    if options.version >= 4 {
+        for index in 0..FIXTURE_CONTACTS {
+            let address = checksum_address(&rng.gen());
+            metadata.address_book.add(&format!("Fixture contact {}", index), &address).unwrap();
+        }
+    }
+
+    if metadata.accounts.len() > 1 {
+        // version 11 sealed the passphrase under the password KDF, before it was a salted hash
+        metadata.protection = Some(match options.version {
+            11.. => {
+                let kdf = Argon2Params::with_salt(rng.gen(), crypto::ARGON2_MEMORY_KIB, crypto::ARGON2_ITERATIONS, crypto::ARGON2_PARALLELISM);
+                PassphraseHash::with_kdf(FIXTURE_PASSPHRASE, PasswordKdf::Argon2id(kdf), rng.gen())
+            },
+            _ => {
+                let salt: [u8; 32] = rng.gen();
+                PassphraseHash::Legacy { salt: salt.to_vec(), hash: salted_hash(&salt, FIXTURE_PASSPHRASE).to_vec() }
+            },
+        });
+        metadata.accounts.last_mut().unwrap().protected = true;
+    }
+    // sealed again with a nonce from the fixture seed, so the output stays reproducible
+    metadata.recovery_phrase = Some(SealedBox::seal_with_nonce(RECOVERY_PHRASE_CONTEXT, seed.as_bytes(), mnemonic.entropy(), rng.gen()));
+    // and so is the seed, with a salt from it too
+    let kdf = Argon2Params::with_salt(rng.gen(), crypto::ARGON2_MEMORY_KIB, crypto::ARGON2_ITERATIONS, crypto::ARGON2_PARALLELISM);
+    let key = PasswordKey::derive(FIXTURE_PASSWORD, PasswordKdf::Argon2id(kdf)).unwrap();
+    wallet.sealed_secret = Some(PasswordBox::seal_with_key(&key, seed.as_bytes(), rng.gen()));
+    wallet.password_key = Some(key);
+
+    wallet
+}
+
+/// Returns the fixture wallet as plain JSON, the form of a file from before wallet files were
+/// encrypted, which still loads. The same options always produce byte-identical output.
+pub fn generate_json(options: &FixtureOptions) -> String {
+    String::from_utf8(generate(options).to_bytes()).unwrap()
+}
+
+/// The fixture mnemonic, parsed
+#[cfg(test)]
+pub(crate) fn fixture_mnemonic() -> Mnemonic {
+    Mnemonic::from_phrase(FIXTURE_MNEMONIC, Language::English).unwrap()
+}
+
+/// A new wallet from the fixture mnemonic with the password "password", holding its first
+/// account and nothing else
+#[cfg(test)]
+pub(crate) fn test_wallet() -> Wallet {
+    Wallet::from(String::from("password"), fixture_mnemonic(), "")
+}
+
+/// A wallet file path in the temp directory, named after `name` and unique to the test run
+#[cfg(test)]
+pub(crate) fn temp_wallet_file(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("basic-eth-{}-{}.txt", name, std::process::id()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fixture_is_reproducible() {
+        let options = FixtureOptions { seed: 42, accounts: 4, ..Default::default() };
+        assert_eq!(generate_json(&options), generate_json(&options));
+        assert_ne!(generate_json(&options), generate_json(&FixtureOptions { seed: 43, ..options }));
+    }
+
+    #[test]
+    fn test_fixture_versions() {
+        for version in FIXTURE_VERSIONS {
+            let json = generate_json(&FixtureOptions { version, ..Default::default() });
+            assert_eq!(migrations::version(json.as_bytes()), Some(version));
+            let wallet: Wallet = serde_json::from_str(&json).unwrap();
+            let metadata = &wallet.accounts_metadata;
+            assert_eq!(metadata.accounts[0].history.len() as u64, if version >= 3 { FIXTURE_HISTORY } else { 0 });
+            assert_eq!(metadata.address_book.contacts().len(), if version >= 4 { FIXTURE_CONTACTS } else { 0 });
+            assert_eq!(matches!(metadata.protection, Some(PassphraseHash::Legacy { .. })), version < 11);
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::settings::HardeningSettings;
+
+/// A problem with the environment the wallet is running in
+#[derive(Debug, PartialEq)]
+pub enum Finding {
+    RunningAsRoot,
+    /// The data directory or a directory above it
+    WorldWritableDataDir(PathBuf),
+    /// The data directory or a directory above it
+    GroupWritableDataDir(PathBuf),
+    TracerAttached(u32),
+    /// A debugger the platform doesn't give the pid of
+    DebuggerAttached,
+    CoreDumpsEnabled,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finding::RunningAsRoot => write!(f, "The wallet is running with root/Administrator privileges"),
+            Finding::WorldWritableDataDir(p) => write!(f, "The data directory or its parent {} is writable by every user", p.display()),
+            Finding::GroupWritableDataDir(p) => write!(f, "The data directory or its parent {} is writable by its group", p.display()),
+            Finding::TracerAttached(pid) => write!(f, "A debugger or tracer (pid {}) is attached to this process", pid),
+            Finding::DebuggerAttached => write!(f, "A debugger is attached to this process"),
+            Finding::CoreDumpsEnabled => write!(f, "Core dumps are enabled and could not be disabled"),
+        }
+    }
+}
+
+/// One of the startup checks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Check {
+    Root,
+    DataDirPermissions,
+    Debugger,
+    CoreDumps,
+}
+
+impl Check {
+    /// Whether settings turn the check off
+    fn is_skipped(&self, settings: &HardeningSettings) -> bool {
+        match self {
+            Check::Root => settings.skip_root_check,
+            Check::DataDirPermissions => settings.skip_world_writable_check,
+            Check::Debugger => settings.skip_debugger_check,
+            Check::CoreDumps => settings.skip_core_dump_check,
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Check::Root => write!(f, "root/Administrator"),
+            Check::DataDirPermissions => write!(f, "data directory permission"),
+            Check::Debugger => write!(f, "debugger"),
+            Check::CoreDumps => write!(f, "core dump"),
+        }
+    }
+}
+
+/// Runs every check not disabled in settings and returns what was found. Core dumps are disabled
+/// for this process as a side effect; they are only reported if that fails.
+pub fn run_startup_checks(settings: &HardeningSettings, data_dir: &Path) -> Vec<Finding> {
+    let mut findings = vec![];
+
+    if !settings.skip_root_check && platform::is_elevated() {
+        findings.push(Finding::RunningAsRoot);
+    }
+    if !settings.skip_world_writable_check {
+        // every parent as it really is, so a relative or symlinked path doesn't hide one
+        let data_dir = data_dir.canonicalize().unwrap_or_else(|_| data_dir.to_path_buf());
+        findings.extend(check_data_dir_permissions(&data_dir, platform::permission_mode));
+    }
+    if !settings.skip_debugger_check {
+        findings.extend(platform::debugger());
+    }
+    if !settings.skip_core_dump_check && platform::core_dumps_enabled() && !platform::disable_core_dumps() {
+        findings.push(Finding::CoreDumpsEnabled);
+    }
+
+    findings
+}
+
+/// The checks not disabled in settings that can't run on this platform, and pass unchecked
+pub fn unsupported_checks(settings: &HardeningSettings) -> Vec<Check> {
+    platform::UNSUPPORTED.iter().copied().filter(|check| !check.is_skipped(settings)).collect()
+}
+
+/// Checks the permissions of `data_dir` and every directory above it, as `mode_of` reports them,
+/// since whoever can write to a parent can swap the data directory for one of their own. A
+/// parent with the sticky bit set, like /tmp, only lets others add entries of their own, so it
+/// passes.
+pub fn check_data_dir_permissions(data_dir: &Path, mode_of: impl Fn(&Path) -> Option<u32>) -> Vec<Finding> {
+    data_dir.ancestors()
+        .enumerate()
+        .filter_map(|(depth, dir)| {
+            let mode = mode_of(dir)?;
+            if depth > 0 && is_sticky(mode) {
+                None
+            } else if is_world_writable(mode) {
+                Some(Finding::WorldWritableDataDir(dir.to_path_buf()))
+            } else if is_group_writable(mode) {
+                Some(Finding::GroupWritableDataDir(dir.to_path_buf()))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Returns true if the "other" write bit is set on a unix permission mode
+pub fn is_world_writable(mode: u32) -> bool {
+    mode & 0o002 != 0
+}
+
+/// Returns true if the group write bit is set on a unix permission mode
+pub fn is_group_writable(mode: u32) -> bool {
+    mode & 0o020 != 0
+}
+
+/// Returns true if the sticky bit is set on a unix permission mode
+pub fn is_sticky(mode: u32) -> bool {
+    mode & 0o1000 != 0
+}
+
+/// Extracts a non-zero TracerPid from the contents of /proc/self/status
+pub fn parse_tracer_pid(status: &str) -> Option<u32> {
+    status.lines()
+        .find_map(|line| line.strip_prefix("TracerPid:"))
+        .and_then(|pid| pid.trim().parse::<u32>().ok())
+        .filter(|pid| *pid != 0)
+}
+
+#[cfg(unix)]
+mod platform {
+    use std::path::Path;
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::{Check, Finding};
+
+    #[cfg(target_os = "linux")]
+    pub const UNSUPPORTED: &[Check] = &[];
+    // only Linux reports a tracer in /proc
+    #[cfg(not(target_os = "linux"))]
+    pub const UNSUPPORTED: &[Check] = &[Check::Debugger];
+
+    pub fn is_elevated() -> bool {
+        unsafe { libc::geteuid() == 0 }
+    }
+
+    pub fn permission_mode(path: &Path) -> Option<u32> {
+        std::fs::metadata(path).ok().map(|m| m.permissions().mode())
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn debugger() -> Option<Finding> {
+        std::fs::read_to_string("/proc/self/status").ok()
+            .and_then(|status| super::parse_tracer_pid(&status))
+            .map(Finding::TracerAttached)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn debugger() -> Option<Finding> {
+        None
+    }
+
+    pub fn core_dumps_enabled() -> bool {
+        let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+        unsafe { libc::getrlimit(libc::RLIMIT_CORE, &mut limit) == 0 && limit.rlim_cur != 0 }
+    }
+
+    pub fn disable_core_dumps() -> bool {
+        let limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+        unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) == 0 }
+    }
+}
+
+/// Windows has no permission modes, and keeps crash dumps to Windows Error Reporting, so only
+/// the Administrator and debugger checks run
+#[cfg(windows)]
+mod platform {
+    use std::path::Path;
+
+    use super::{Check, Finding};
+
+    pub const UNSUPPORTED: &[Check] = &[Check::DataDirPermissions, Check::CoreDumps];
+
+    #[link(name = "shell32")]
+    extern "system" {
+        fn IsUserAnAdmin() -> i32;
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn IsDebuggerPresent() -> i32;
+    }
+
+    pub fn is_elevated() -> bool {
+        unsafe { IsUserAnAdmin() != 0 }
+    }
+
+    pub fn permission_mode(_path: &Path) -> Option<u32> {
+        None
+    }
+
+    pub fn debugger() -> Option<Finding> {
+        match unsafe { IsDebuggerPresent() } {
+            0 => None,
+            _ => Some(Finding::DebuggerAttached),
+        }
+    }
+
+    pub fn core_dumps_enabled() -> bool {
+        false
+    }
+
+    pub fn disable_core_dumps() -> bool {
+        true
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod platform {
+    use std::path::Path;
+
+    use super::{Check, Finding};
+
+    pub const UNSUPPORTED: &[Check] = &[Check::Root, Check::DataDirPermissions, Check::Debugger, Check::CoreDumps];
+
+    pub fn is_elevated() -> bool {
+        false
+    }
+
+    pub fn permission_mode(_path: &Path) -> Option<u32> {
+        None
+    }
+
+    pub fn debugger() -> Option<Finding> {
+        None
+    }
+
+    pub fn core_dumps_enabled() -> bool {
+        false
+    }
+
+    pub fn disable_core_dumps() -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_world_writable() {
+        assert!(is_world_writable(0o40777));
+        assert!(is_world_writable(0o41777));
+        assert!(!is_world_writable(0o40755));
+        assert!(!is_world_writable(0o40770));
+        assert!(is_group_writable(0o40770));
+        assert!(!is_group_writable(0o40755));
+        assert!(is_sticky(0o41777));
+        assert!(!is_sticky(0o40777));
+    }
+
+    #[test]
+    fn test_check_data_dir_permissions() {
+        let modes = |modes: &'static [(&'static str, u32)]| move |dir: &Path| {
+            modes.iter().find(|(path, _)| Path::new(path) == dir).map(|(_, mode)| *mode)
+        };
+        let dir = Path::new("/home/user/.basic-eth");
+        assert!(check_data_dir_permissions(dir, modes(&[("/home/user/.basic-eth", 0o40700), ("/home/user", 0o40755), ("/home", 0o40755), ("/", 0o40755)])).is_empty());
+        // a parent anyone can write to could swap the data directory
+        assert_eq!(check_data_dir_permissions(dir, modes(&[("/home/user/.basic-eth", 0o40700), ("/home/user", 0o40777), ("/", 0o40775)])),
+            vec![Finding::WorldWritableDataDir(PathBuf::from("/home/user")), Finding::GroupWritableDataDir(PathBuf::from("/"))]);
+
+        // /tmp can't have entries of others replaced, but the data directory itself always counts
+        let dir = Path::new("/tmp/wallet");
+        assert!(check_data_dir_permissions(dir, modes(&[("/tmp/wallet", 0o40700), ("/tmp", 0o41777)])).is_empty());
+        assert_eq!(check_data_dir_permissions(dir, modes(&[("/tmp/wallet", 0o41777)])), vec![Finding::WorldWritableDataDir(PathBuf::from("/tmp/wallet"))]);
+    }
+
+    #[test]
+    fn test_unsupported_checks() {
+        let mut settings = HardeningSettings::default();
+        assert_eq!(unsupported_checks(&settings), platform::UNSUPPORTED);
+        settings.skip_debugger_check = true;
+        assert!(!unsupported_checks(&settings).contains(&Check::Debugger));
+    }
+
+    #[test]
+    fn test_parse_tracer_pid() {
+        let traced = "Name:\tbasic-eth\nState:\tS (sleeping)\nTracerPid:\t4242\nUid:\t1000\n";
+        let untraced = "Name:\tbasic-eth\nTracerPid:\t0\n";
+        assert_eq!(parse_tracer_pid(traced), Some(4242));
+        assert_eq!(parse_tracer_pid(untraced), None);
+        assert_eq!(parse_tracer_pid(""), None);
+    }
+
+    #[test]
+    fn test_all_checks_skippable() {
+        let settings = HardeningSettings {
+            skip_root_check: true,
+            skip_world_writable_check: true,
+            skip_debugger_check: true,
+            skip_core_dump_check: true,
+        };
+        assert!(run_startup_checks(&settings, Path::new(".")).is_empty());
+    }
+}
@@ -0,0 +1,156 @@
+use serde::{Serialize, Deserialize};
+
+use crate::journal::TransactionStatus;
+use crate::utils;
+
+/// The most transactions kept in an account's history. The oldest are dropped past it.
+pub const MAX_RECORDS: usize = 200;
+/// How many transactions the history lists at a time
+pub const PAGE_SIZE: usize = 10;
+
+/// A transaction sent from an account, kept with the wallet
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TxRecord {
+    /// The 0x-prefixed transaction hash
+    pub hash: String,
+    /// The 0x-prefixed recipient address
+    pub recipient: String,
+    /// The amount sent in wei
+    pub wei_amount: u128,
+    /// The gas price it was signed with, 0 for a relayed transaction
+    pub gas_price: u128,
+    pub nonce: u64,
+    /// Unix timestamp (seconds) of the broadcast
+    pub sent_at: u64,
+    /// URL of the relayer that broadcast it and paid its gas, None if it was sent to the node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relayer: Option<String>,
+    /// What the node said when it was last asked, pending until then
+    pub status: TransactionStatus,
+}
+
+impl TxRecord {
+    pub fn describe(&self) -> String {
+        let fee = match &self.relayer {
+            Some(url) => format!("relayed by {}", url),
+            None => format!("gas price {} wei", self.gas_price),
+        };
+        format!("{}: {} ETH to {} (nonce {}, {})\n\t\t{} ({})", utils::format_timestamp(self.sent_at),
+            utils::wei_to_eth(self.wei_amount), self.recipient, self.nonce, fee, self.hash, self.status.as_str())
+    }
+}
+
+/// Adds a transaction to a history, dropping the oldest past `MAX_RECORDS`
+pub fn push(history: &mut Vec<TxRecord>, record: TxRecord) {
+    history.push(record);
+    if history.len() > MAX_RECORDS {
+        history.drain(..history.len() - MAX_RECORDS);
+    }
+}
+
+/// The number of pages a history is listed in
+pub fn pages(history: &[TxRecord]) -> usize {
+    history.len().div_ceil(PAGE_SIZE)
+}
+
+/// Page `page` of a history, counting from 0, newest first
+pub fn page(history: &[TxRecord], page: usize) -> impl Iterator<Item = &TxRecord> {
+    history.iter().rev().skip(page * PAGE_SIZE).take(PAGE_SIZE)
+}
+
+/// Asks `status` about every transaction not yet confirmed or failed, and returns how many
+/// changed
+pub fn refresh(history: &mut [TxRecord], status: impl Fn(&str) -> TransactionStatus) -> usize {
+    let mut changed = 0;
+    for record in history.iter_mut().filter(|r| r.status.is_open()) {
+        let status = status(&record.hash);
+        // a node that can't be asked says nothing about the transaction
+        if status != record.status && status != TransactionStatus::Unknown {
+            record.status = status;
+            changed += 1;
+        }
+    }
+    changed
+}
+
+/// The nonce after the highest one of a mined transaction, which the account's next
+/// transaction can't be below
+pub fn next_mined_nonce(history: &[TxRecord]) -> Option<u64> {
+    history.iter()
+        .filter(|r| matches!(r.status, TransactionStatus::Confirmed | TransactionStatus::Failed))
+        .map(|r| r.nonce + 1)
+        .max()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(nonce: u64) -> TxRecord {
+        TxRecord {
+            hash: format!("0x{:064x}", nonce),
+            recipient: String::from("0x73363901cd60ace0df1df46111fa999416bb9bd1"),
+            wei_amount: 1_000_000_000_000_000,
+            gas_price: 1_000_000_000,
+            nonce,
+            sent_at: 1_700_000_000 + nonce,
+            status: TransactionStatus::Pending,
+            relayer: None,
+        }
+    }
+
+    #[test]
+    fn test_paging() {
+        let mut history = vec![];
+        assert_eq!(pages(&history), 0);
+        for nonce in 0..(MAX_RECORDS as u64 + 5) {
+            push(&mut history, record(nonce));
+        }
+        // the oldest are dropped
+        assert_eq!(history.len(), MAX_RECORDS);
+        assert_eq!(history[0].nonce, 5);
+        assert_eq!(pages(&history), MAX_RECORDS / PAGE_SIZE);
+
+        let first: Vec<_> = page(&history, 0).map(|r| r.nonce).collect();
+        assert_eq!(first, (MAX_RECORDS as u64 - 5..MAX_RECORDS as u64 + 5).rev().collect::<Vec<_>>());
+        history.truncate(15);
+        assert_eq!(pages(&history), 2);
+        assert_eq!(page(&history, 1).count(), 5);
+        assert_eq!(page(&history, 2).count(), 0);
+    }
+
+    #[test]
+    fn test_refresh() {
+        let mut history = vec![record(0), record(1), record(2)];
+        history[2].status = TransactionStatus::Failed;
+        let status = |hash: &str| if hash == record(0).hash { TransactionStatus::Confirmed } else { TransactionStatus::Unknown };
+        assert_eq!(refresh(&mut history, status), 1);
+        assert_eq!(history.iter().map(|r| r.status).collect::<Vec<_>>(),
+            [TransactionStatus::Confirmed, TransactionStatus::Pending, TransactionStatus::Failed]);
+        // settled transactions aren't asked about again
+        assert_eq!(refresh(&mut history, |_| TransactionStatus::Pending), 0);
+        // a failed transaction uses its nonce up too
+        assert_eq!(next_mined_nonce(&history), Some(3));
+        assert_eq!(next_mined_nonce(&history[1..2]), None);
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let json = serde_json::to_string(&record(3)).unwrap();
+        assert_eq!(serde_json::from_str::<TxRecord>(&json).unwrap(), record(3));
+        // records written before relaying was tracked load as sent to the node
+        assert!(!json.contains("relayer"));
+
+        let relayed = TxRecord { gas_price: 0, relayer: Some(String::from("https://relay.example")), ..record(4) };
+        let json = serde_json::to_string(&relayed).unwrap();
+        assert_eq!(serde_json::from_str::<TxRecord>(&json).unwrap(), relayed);
+    }
+
+    #[test]
+    fn test_describe() {
+        assert!(record(1).describe().contains("gas price 1000000000 wei"));
+        let relayed = TxRecord { gas_price: 0, relayer: Some(String::from("https://relay.example")), ..record(1) };
+        assert!(relayed.describe().contains("relayed by https://relay.example"));
+        assert!(!relayed.describe().contains("gas price"));
+    }
+}
@@ -0,0 +1,123 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use fs2::FileExt;
+use thiserror::Error;
+
+/// In the data directory. It holds the pid of the process that has the lock, and is emptied
+/// when the lock is released.
+const LOCK_FILE: &str = "basic-eth.lock";
+
+#[derive(Error, Debug, PartialEq)]
+pub enum LockError {
+    #[error("The wallet is already open (pid {0}). Quit it first, so the two don't overwrite each other's changes.")]
+    AlreadyOpen(u32),
+    #[error("The wallet is already open in another process. Quit it first, so the two don't overwrite each other's changes.")]
+    AlreadyOpenElsewhere,
+    #[error("Error locking {0}: {1}")]
+    Io(String, String),
+}
+
+/// Keeps a second copy of the app from opening the same data directory and storing over the
+/// files this one writes. The OS releases the lock if the process dies; dropping it, including
+/// while unwinding from a panic, releases it sooner.
+pub struct InstanceLock {
+    file: File,
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // the file is kept, since deleting it could let the next two processes lock different
+        // files
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
+
+/// Locks the data directory `dir` for this process. Also returns the pid of a process that
+/// held it and died without releasing it, whose lock is taken over.
+pub fn acquire(dir: &Path) -> Result<(InstanceLock, Option<u32>), LockError> {
+    let path = dir.join(LOCK_FILE);
+    let io_error = |e: std::io::Error| LockError::Io(path.display().to_string(), e.to_string());
+    let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).map_err(io_error)?;
+    match file.try_lock_exclusive() {
+        Ok(()) => {},
+        Err(e) if e.kind() == fs2::lock_contended_error().kind() => {
+            return Err(read_pid(&mut file).map_or(LockError::AlreadyOpenElsewhere, LockError::AlreadyOpen));
+        },
+        // without advisory locks, as on some network file systems, all there is to go on is the pid
+        Err(_) => {
+            if let Some(pid) = read_pid(&mut file).filter(|pid| *pid != std::process::id() && platform::is_running(*pid)) {
+                return Err(LockError::AlreadyOpen(pid));
+            }
+        },
+    }
+    // a pid left behind means its process never got to release the lock
+    let stale = read_pid(&mut file).filter(|pid| *pid != std::process::id());
+    file.set_len(0)
+        .and_then(|()| file.seek(SeekFrom::Start(0)))
+        .and_then(|_| file.write_all(std::process::id().to_string().as_bytes()))
+        .and_then(|()| file.sync_all())
+        .map_err(io_error)?;
+    Ok((InstanceLock { file }, stale))
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut contents = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+#[cfg(unix)]
+mod platform {
+    pub fn is_running(pid: u32) -> bool {
+        let Ok(pid) = libc::pid_t::try_from(pid) else {
+            return false;
+        };
+        // signal 0 only checks that the process exists; EPERM means it does, as another user's
+        let exists = unsafe { libc::kill(pid, 0) == 0 };
+        exists || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+}
+
+// TODO: check the pid on windows
+#[cfg(not(unix))]
+mod platform {
+    pub fn is_running(_pid: u32) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_acquire() {
+        let dir = std::env::temp_dir().join(format!("basic-eth-lock-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let (lock, stale) = acquire(&dir).unwrap();
+        assert_eq!(stale, None);
+        assert_eq!(std::fs::read_to_string(dir.join(LOCK_FILE)).unwrap(), std::process::id().to_string());
+        // the lock is held per open file, so a second open in this process is refused too
+        assert_eq!(acquire(&dir).err(), Some(LockError::AlreadyOpen(std::process::id())));
+        drop(lock);
+        assert_eq!(std::fs::read_to_string(dir.join(LOCK_FILE)).unwrap(), "");
+
+        // a process that was killed leaves its pid, but the OS releases its lock
+        std::fs::write(dir.join(LOCK_FILE), "4194304999").unwrap();
+        let (_lock, stale) = acquire(&dir).unwrap();
+        assert_eq!(stale, Some(4194304999));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_is_running() {
+        assert!(platform::is_running(std::process::id()));
+        #[cfg(unix)]
+        assert!(!platform::is_running(u32::MAX));
+    }
+}
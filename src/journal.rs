@@ -0,0 +1,158 @@
+use std::sync::Mutex;
+
+use serde::{Serialize, Deserialize};
+
+use crate::audit;
+use crate::utils;
+
+/// Everything done since the wallet was opened. Kept across locks so the summary covers the
+/// whole session.
+static JOURNAL: Mutex<Vec<JournalEntry>> = Mutex::new(vec![]);
+
+/// An action that changed the wallet or the chain
+#[derive(Debug, Clone, PartialEq)]
+pub enum JournalEntry {
+    /// A broadcast transaction. `fee` is the most it can cost, which for a plain transfer is
+    /// what it does cost.
+    Sent { from: String, hash: String, fee: u128 },
+    AccountCreated { address: String },
+    AccountLabeled { address: String, label: String },
+    WatchOnlyAdded { address: String },
+    KeyImported { address: String },
+}
+
+impl JournalEntry {
+    pub fn describe(&self) -> String {
+        match self {
+            JournalEntry::Sent { from, hash, fee } => format!("Transaction {} sent from {} (fee {} ETH)", hash, from, utils::wei_to_eth(*fee)),
+            JournalEntry::AccountCreated { address } => format!("Account {} created", address),
+            JournalEntry::AccountLabeled { address, label } => format!("Account {} labeled \"{}\"", address, label),
+            JournalEntry::WatchOnlyAdded { address } => format!("Watch-only account {} added", address),
+            JournalEntry::KeyImported { address } => format!("Private key for {} imported", address),
+        }
+    }
+}
+
+/// What the node knows about a sent transaction
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum TransactionStatus {
+    Confirmed,
+    Failed,
+    Pending,
+    /// The node couldn't be asked
+    Unknown,
+}
+
+impl TransactionStatus {
+    /// True if the transaction should be looked at again later
+    pub fn is_open(&self) -> bool {
+        matches!(self, TransactionStatus::Pending | TransactionStatus::Unknown)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionStatus::Confirmed => "confirmed",
+            TransactionStatus::Failed => "failed",
+            TransactionStatus::Pending => "pending",
+            TransactionStatus::Unknown => "status unknown",
+        }
+    }
+}
+
+/// Adds an entry to the session journal and the audit log
+pub fn record(entry: JournalEntry) {
+    audit::record(&entry.describe());
+    JOURNAL.lock().unwrap().push(entry);
+}
+
+/// Empties the journal, returning everything recorded this session
+pub fn take() -> Vec<JournalEntry> {
+    std::mem::take(&mut *JOURNAL.lock().unwrap())
+}
+
+/// Hashes of the transactions in the journal, in the order they were sent
+pub fn sent_hashes(entries: &[JournalEntry]) -> Vec<&str> {
+    entries.iter().filter_map(|e| match e {
+        JournalEntry::Sent { hash, .. } => Some(hash.as_str()),
+        _ => None,
+    }).collect()
+}
+
+/// Renders the summary shown when the wallet quits. `status` looks up each sent transaction.
+pub fn summary(entries: &[JournalEntry], status: impl Fn(&str) -> TransactionStatus) -> String {
+    if entries.is_empty() {
+        return String::from("Session summary: nothing was sent or changed");
+    }
+
+    let mut summary = String::from("Session summary:");
+    let mut fees = 0;
+    let mut open = 0;
+    let sent: Vec<_> = entries.iter().filter(|e| matches!(e, JournalEntry::Sent { .. })).collect();
+    if !sent.is_empty() {
+        summary += &format!("\n\tTransactions sent: {}", sent.len());
+        for entry in sent {
+            if let JournalEntry::Sent { from, hash, fee } = entry {
+                let status = status(hash);
+                open += status.is_open() as usize;
+                fees += fee;
+                summary += &format!("\n\t\t{} from {}: {}", hash, from, status.as_str());
+            }
+        }
+        summary += &format!("\n\tTotal fees: {} ETH", utils::wei_to_eth(fees));
+    }
+
+    for entry in entries {
+        match entry {
+            JournalEntry::Sent { .. } => {},
+            JournalEntry::AccountCreated { address } => summary += &format!("\n\tAccount created: {}", address),
+            JournalEntry::AccountLabeled { address, label } => summary += &format!("\n\tAccount labeled: {} ({})", address, label),
+            JournalEntry::WatchOnlyAdded { address } => summary += &format!("\n\tWatch-only account added: {}", address),
+            JournalEntry::KeyImported { address } => summary += &format!("\n\tPrivate key imported: {}", address),
+        }
+    }
+
+    if open > 0 {
+        summary += &format!("\n{} transaction(s) still pending. They will be checked on next login.", open);
+    }
+    summary
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sent(hash: &str, fee: u128) -> JournalEntry {
+        JournalEntry::Sent { from: String::from("0xf39f"), hash: String::from(hash), fee }
+    }
+
+    #[test]
+    fn test_empty_summary() {
+        assert_eq!(summary(&[], |_| unreachable!()), "Session summary: nothing was sent or changed");
+    }
+
+    #[test]
+    fn test_summary() {
+        let entries = [
+            sent("0xaa", 21000),
+            JournalEntry::AccountCreated { address: String::from("0x7099") },
+            sent("0xbb", 42000),
+            JournalEntry::AccountLabeled { address: String::from("0x7099"), label: String::from("Savings") },
+        ];
+        assert_eq!(sent_hashes(&entries), ["0xaa", "0xbb"]);
+
+        let status = |hash: &str| if hash == "0xaa" { TransactionStatus::Confirmed } else { TransactionStatus::Pending };
+        assert_eq!(summary(&entries, status), "Session summary:\
+            \n\tTransactions sent: 2\
+            \n\t\t0xaa from 0xf39f: confirmed\
+            \n\t\t0xbb from 0xf39f: pending\
+            \n\tTotal fees: 0.000000000000063 ETH\
+            \n\tAccount created: 0x7099\
+            \n\tAccount labeled: 0x7099 (Savings)\
+            \n1 transaction(s) still pending. They will be checked on next login.");
+
+        // no pending reminder once everything is settled
+        let settled = summary(&entries[..2], |_| TransactionStatus::Failed);
+        assert!(settled.contains("0xaa from 0xf39f: failed"));
+        assert!(!settled.contains("pending"));
+    }
+}
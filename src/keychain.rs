@@ -0,0 +1,64 @@
+use zeroize::Zeroizing;
+
+/// The service wallet keys are filed under, each under the absolute path of its wallet file
+#[cfg(feature = "keychain")]
+const SERVICE: &str = "basic-eth";
+
+/// Whether this build can use the system keychain (macOS Keychain, Windows Credential Manager or
+/// the Secret Service). It needs the `keychain` feature, which is off by default.
+pub const SUPPORTED: bool = cfg!(feature = "keychain");
+
+#[cfg(feature = "keychain")]
+fn entry(wallet_file: &str) -> Result<keyring::Entry, String> {
+    let path = std::fs::canonicalize(wallet_file).map_err(|e| format!("Error reading {}: {}", wallet_file, e))?;
+    keyring::Entry::new(SERVICE, &path.to_string_lossy()).map_err(|e| format!("The system keychain isn't available: {}", e))
+}
+
+/// Keeps the password key of the wallet in `wallet_file`, replacing any kept before
+#[cfg(feature = "keychain")]
+pub fn store(wallet_file: &str, key: &[u8; 32]) -> Result<(), String> {
+    entry(wallet_file)?.set_secret(key).map_err(|e| format!("Couldn't add the key to the system keychain: {}", e))
+}
+
+/// The password key kept for the wallet in `wallet_file`
+#[cfg(feature = "keychain")]
+pub fn load(wallet_file: &str) -> Result<Zeroizing<[u8; 32]>, String> {
+    let secret = Zeroizing::new(entry(wallet_file)?.get_secret().map_err(|e| format!("Couldn't read the key from the system keychain: {}", e))?);
+    let key: [u8; 32] = secret.as_slice().try_into().map_err(|_e| String::from("The key in the system keychain isn't 32 bytes"))?;
+    Ok(Zeroizing::new(key))
+}
+
+/// Whether a key is kept for the wallet in `wallet_file`
+#[cfg(feature = "keychain")]
+pub fn has_entry(wallet_file: &str) -> bool {
+    entry(wallet_file).is_ok_and(|entry| entry.get_secret().is_ok())
+}
+
+/// Removes the key kept for the wallet in `wallet_file`. There being none is fine.
+#[cfg(feature = "keychain")]
+pub fn forget(wallet_file: &str) -> Result<(), String> {
+    match entry(wallet_file)?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(format!("Couldn't remove the key from the system keychain: {}", e)),
+    }
+}
+
+#[cfg(not(feature = "keychain"))]
+pub fn store(_wallet_file: &str, _key: &[u8; 32]) -> Result<(), String> {
+    Err(String::from("This build doesn't support the system keychain"))
+}
+
+#[cfg(not(feature = "keychain"))]
+pub fn load(_wallet_file: &str) -> Result<Zeroizing<[u8; 32]>, String> {
+    Err(String::from("This build doesn't support the system keychain"))
+}
+
+#[cfg(not(feature = "keychain"))]
+pub fn has_entry(_wallet_file: &str) -> bool {
+    false
+}
+
+#[cfg(not(feature = "keychain"))]
+pub fn forget(_wallet_file: &str) -> Result<(), String> {
+    Ok(())
+}
@@ -0,0 +1,233 @@
+use aes::cipher::{KeyIvInit, StreamCipher};
+use serde::{Serialize, Deserialize};
+use zeroize::Zeroizing;
+
+use crate::crypto::{ct_eq, keccak256};
+
+type Aes128Ctr = ctr::Ctr128BE<aes::Aes128>;
+
+/// scrypt cost used by geth's standard keystores, so other wallets accept the file
+const SCRYPT_LOG_N: u8 = 18;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+const DERIVED_KEY_LEN: usize = 32;
+
+/// A Web3 Secret Storage (keystore V3) file, as written by geth and MetaMask
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Keystore {
+    pub version: u8,
+    pub id: String,
+    /// Lowercase hex address without 0x. Optional in the standard, so it isn't trusted on import.
+    #[serde(default)]
+    pub address: String,
+    pub crypto: KeystoreCrypto,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct KeystoreCrypto {
+    pub cipher: String,
+    pub cipherparams: CipherParams,
+    pub ciphertext: String,
+    pub kdf: String,
+    pub kdfparams: KdfParams,
+    pub mac: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct CipherParams {
+    pub iv: String,
+}
+
+/// Parameters of the key derivation function named by `kdf`
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum KdfParams {
+    Scrypt(ScryptParams),
+    Pbkdf2(Pbkdf2Params),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Pbkdf2Params {
+    pub dklen: usize,
+    /// Iteration count
+    pub c: u32,
+    /// Only hmac-sha256 is defined by the standard
+    pub prf: String,
+    pub salt: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ScryptParams {
+    pub dklen: usize,
+    pub n: u32,
+    pub r: u32,
+    pub p: u32,
+    pub salt: String,
+}
+
+/// Encrypts a private key with a fresh salt, IV and id
+pub fn encrypt(secret: &[u8; 32], password: &str, address: &str) -> Keystore {
+    let params = ScryptParams {
+        dklen: DERIVED_KEY_LEN,
+        n: 1 << SCRYPT_LOG_N,
+        r: SCRYPT_R,
+        p: SCRYPT_P,
+        salt: hex::encode(rand::random::<[u8; 32]>()),
+    };
+    encrypt_with(secret, password, address, params, rand::random(), &uuid_v4(rand::random()))
+}
+
+/// Encrypts with the given scrypt parameters, IV and id. `encrypt` picks them at random.
+fn encrypt_with(secret: &[u8; 32], password: &str, address: &str, params: ScryptParams, iv: [u8; 16], id: &str) -> Keystore {
+    let derived_key = scrypt_key(password, &params).unwrap();
+    let mut ciphertext = secret.to_vec();
+    Aes128Ctr::new(derived_key[..16].into(), &iv.into()).apply_keystream(&mut ciphertext);
+
+    Keystore {
+        version: 3,
+        id: String::from(id),
+        address: String::from(address.strip_prefix("0x").unwrap_or(address)).to_lowercase(),
+        crypto: KeystoreCrypto {
+            cipher: String::from("aes-128-ctr"),
+            cipherparams: CipherParams { iv: hex::encode(iv) },
+            mac: hex::encode(mac(&derived_key, &ciphertext)),
+            ciphertext: hex::encode(ciphertext),
+            kdf: String::from("scrypt"),
+            kdfparams: KdfParams::Scrypt(params),
+        },
+    }
+}
+
+/// Decrypts the private key. The MAC is checked first, so a wrong password is reported as such
+/// rather than producing a garbage key.
+pub fn decrypt(keystore: &Keystore, password: &str) -> Result<Zeroizing<[u8; 32]>, String> {
+    let crypto = &keystore.crypto;
+    if keystore.version != 3 {
+        return Err(format!("Unsupported keystore version {}", keystore.version));
+    }
+    if crypto.cipher != "aes-128-ctr" {
+        return Err(format!("Unsupported cipher {}", crypto.cipher));
+    }
+
+    let derived_key = match (crypto.kdf.as_str(), &crypto.kdfparams) {
+        ("scrypt", KdfParams::Scrypt(params)) => scrypt_key(password, params)?,
+        ("pbkdf2", KdfParams::Pbkdf2(params)) => pbkdf2_key(password, params)?,
+        (kdf, _) => return Err(format!("Unsupported key derivation function {}", kdf)),
+    };
+    let mut ciphertext = hex::decode(&crypto.ciphertext).map_err(|_e| String::from("Invalid ciphertext"))?;
+    let expected_mac = hex::decode(&crypto.mac).map_err(|_e| String::from("Invalid MAC"))?;
+    if !ct_eq(&mac(&derived_key, &ciphertext), &expected_mac) {
+        return Err(String::from("Wrong password for this keystore file"));
+    }
+
+    let iv: [u8; 16] = hex::decode(&crypto.cipherparams.iv).ok()
+        .and_then(|iv| iv.try_into().ok())
+        .ok_or_else(|| String::from("Invalid IV"))?;
+    Aes128Ctr::new(derived_key[..16].into(), &iv.into()).apply_keystream(&mut ciphertext);
+    let ciphertext = Zeroizing::new(ciphertext);
+    let secret: [u8; 32] = ciphertext.as_slice().try_into().map_err(|_e| String::from("The key must be 32 bytes"))?;
+    Ok(Zeroizing::new(secret))
+}
+
+fn pbkdf2_key(password: &str, params: &Pbkdf2Params) -> Result<Zeroizing<Vec<u8>>, String> {
+    if params.prf != "hmac-sha256" {
+        return Err(format!("Unsupported PBKDF2 function {}", params.prf));
+    }
+    if params.dklen < DERIVED_KEY_LEN {
+        return Err(String::from("Unsupported PBKDF2 parameters"));
+    }
+    let salt = hex::decode(&params.salt).map_err(|_e| String::from("Invalid PBKDF2 salt"))?;
+    let mut key = Zeroizing::new(vec![0u8; params.dklen]);
+    pbkdf2::pbkdf2_hmac::<sha2::Sha256>(password.as_bytes(), &salt, params.c, &mut key);
+    Ok(key)
+}
+
+fn scrypt_key(password: &str, params: &ScryptParams) -> Result<Zeroizing<Vec<u8>>, String> {
+    if !params.n.is_power_of_two() || params.dklen < DERIVED_KEY_LEN {
+        return Err(String::from("Unsupported scrypt parameters"));
+    }
+    let salt = hex::decode(&params.salt).map_err(|_e| String::from("Invalid scrypt salt"))?;
+    let scrypt_params = scrypt::Params::new(params.n.trailing_zeros() as u8, params.r, params.p)
+        .map_err(|_e| String::from("Unsupported scrypt parameters"))?;
+    let mut key = Zeroizing::new(vec![0u8; params.dklen]);
+    scrypt::scrypt(password.as_bytes(), &salt, &scrypt_params, &mut key).map_err(|_e| String::from("Unsupported scrypt parameters"))?;
+    Ok(key)
+}
+
+/// keccak256 of the second half of the AES key's derived bytes and the ciphertext
+fn mac(derived_key: &[u8], ciphertext: &[u8]) -> [u8; 32] {
+    let mut input = derived_key[16..32].to_vec();
+    input.extend_from_slice(ciphertext);
+    keccak256(&input)
+}
+
+/// Formats random bytes as a version 4 UUID
+fn uuid_v4(mut bytes: [u8; 16]) -> String {
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The scrypt test vector from the Web3 Secret Storage definition
+    #[test]
+    fn test_spec_vector() {
+        let secret: [u8; 32] = hex::decode("7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d").unwrap().try_into().unwrap();
+        let params = ScryptParams {
+            dklen: 32,
+            n: 262144,
+            r: 1,
+            p: 8,
+            salt: String::from("ab0c7876052600dd703518d6fc3fe8984592145b591fc8fb5c6d43190334ba19"),
+        };
+        let iv = hex::decode("83dbcc02d8ccb40e466191a123791e0e").unwrap().try_into().unwrap();
+        let keystore = encrypt_with(&secret, "testpassword", "0x008aeeda4d805471df9b2a5b0f38a0c3bcba786b", params, iv, "3198bc9c-6672-5ab3-d995-4942343ae5b6");
+
+        assert_eq!(keystore.crypto.ciphertext, "d172bf743a674da9cdad04534d56926ef8358534d458fffccd4e6ad2fbde479c");
+        assert_eq!(keystore.crypto.mac, "2103ac29920d71da29f15d75b4a16dbe95cfd7ff8faea1056c33131d846e3097");
+        assert_eq!(keystore.address, "008aeeda4d805471df9b2a5b0f38a0c3bcba786b");
+        let json = serde_json::to_value(&keystore).unwrap();
+        assert_eq!(json["crypto"]["kdfparams"]["n"], 262144);
+        assert_eq!(json["crypto"]["cipherparams"]["iv"], "83dbcc02d8ccb40e466191a123791e0e");
+
+        assert_eq!(*decrypt(&keystore, "testpassword").unwrap(), secret);
+        assert_eq!(decrypt(&keystore, "wrongpassword").unwrap_err(), "Wrong password for this keystore file");
+    }
+
+    /// The PBKDF2 test vector from the Web3 Secret Storage definition
+    #[test]
+    fn test_pbkdf2_vector() {
+        let json = r#"{
+            "crypto": {
+                "cipher": "aes-128-ctr",
+                "cipherparams": { "iv": "6087dab2f9fdbbfaddc31a909735c1e6" },
+                "ciphertext": "5318b4d5bcd28de64ee5559e671353e16f075ecae9f99c7a79a38af5f869aa46",
+                "kdf": "pbkdf2",
+                "kdfparams": {
+                    "c": 262144,
+                    "dklen": 32,
+                    "prf": "hmac-sha256",
+                    "salt": "ae3cd4e7013836a3df6bd7241b12db061dbe2c6785853cce422d148a624ce0bd"
+                },
+                "mac": "517ead924a9d0dc3124507e3393d175ce3ff7c1e96529c6c555ce9e51205e9b2"
+            },
+            "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+            "version": 3
+        }"#;
+        let mut keystore: Keystore = serde_json::from_str(json).unwrap();
+        assert_eq!(hex::encode(*decrypt(&keystore, "testpassword").unwrap()), "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d");
+
+        keystore.crypto.kdf = String::from("argon2");
+        assert_eq!(decrypt(&keystore, "testpassword").unwrap_err(), "Unsupported key derivation function argon2");
+    }
+
+    #[test]
+    fn test_uuid_v4() {
+        assert_eq!(uuid_v4([0xff; 16]), "ffffffff-ffff-4fff-bfff-ffffffffffff");
+        assert_eq!(uuid_v4([0; 16]), "00000000-0000-4000-8000-000000000000");
+    }
+}
@@ -0,0 +1,186 @@
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::crypto::checksum_address;
+
+/// How many child indices are derived when looking for the addresses named in a label export
+pub const LABEL_DISCOVERY_DEPTH: usize = 100;
+
+#[derive(Error, Debug)]
+pub enum LabelImportError {
+    #[error("File is not valid JSON: {0}")]
+    InvalidJson(String),
+    #[error("Unrecognized label export format")]
+    UnknownFormat,
+}
+
+/// An address and the label it was given in the export
+#[derive(Debug, PartialEq)]
+pub struct LabelEntry {
+    pub address: String,
+    pub label: String,
+}
+
+/// The outcome of matching a label export against the accounts of this wallet, shown to the user
+/// before anything is applied
+#[derive(Debug, Default, PartialEq)]
+pub struct LabelImportPlan {
+    /// (account index, address, label) for every address derived from this wallet's seed
+    pub matched: Vec<(usize, String, String)>,
+    /// Entries whose address is not derived from this wallet's seed
+    pub unmatched: Vec<LabelEntry>,
+    /// Entries with malformed addresses or a bad EIP-55 checksum
+    pub invalid: Vec<LabelEntry>,
+}
+
+/// Parses a label export. Accepted formats are:
+/// - MetaMask's state export, which holds an `identities` object keyed by address
+/// - a flat object mapping address to label
+/// - an array of `{ "address": ..., "name" | "label": ... }` objects
+pub fn parse_label_export(json: &str) -> Result<Vec<LabelEntry>, LabelImportError> {
+    let value: Value = serde_json::from_str(json)
+        .map_err(|e| LabelImportError::InvalidJson(e.to_string()))?;
+
+    // MetaMask nests the identities under its preferences controller
+    let identities = value.pointer("/PreferencesController/identities")
+        .or_else(|| value.pointer("/data/PreferencesController/identities"))
+        .or_else(|| value.get("identities"));
+    if let Some(identities) = identities {
+        return parse_object(identities);
+    }
+
+    match &value {
+        Value::Object(_) => parse_object(&value),
+        Value::Array(items) => items.iter().map(parse_item).collect(),
+        _ => Err(LabelImportError::UnknownFormat),
+    }
+}
+
+fn parse_object(value: &Value) -> Result<Vec<LabelEntry>, LabelImportError> {
+    let map = value.as_object().ok_or(LabelImportError::UnknownFormat)?;
+    map.iter()
+        .map(|(address, v)| match v {
+            Value::String(label) => Ok(LabelEntry { address: address.clone(), label: label.clone() }),
+            Value::Object(_) => {
+                let mut entry = parse_item(v)?;
+                if entry.address.is_empty() {
+                    entry.address = address.clone();
+                }
+                Ok(entry)
+            },
+            _ => Err(LabelImportError::UnknownFormat),
+        })
+        .collect()
+}
+
+fn parse_item(value: &Value) -> Result<LabelEntry, LabelImportError> {
+    let label = value.get("name")
+        .or_else(|| value.get("label"))
+        .and_then(Value::as_str)
+        .ok_or(LabelImportError::UnknownFormat)?;
+    let address = value.get("address").and_then(Value::as_str).unwrap_or("");
+
+    Ok(LabelEntry { address: String::from(address), label: String::from(label) })
+}
+
+/// Returns the lowercase 0x-prefixed form of an address, or None if it is malformed. Mixed-case
+/// addresses must carry a valid EIP-55 checksum, all-lowercase and all-uppercase ones are accepted as is.
+pub fn normalize_address(address: &str) -> Option<String> {
+    let raw = address.strip_prefix("0x").unwrap_or(address);
+    let bytes: [u8; 20] = hex::decode(raw).ok()?.try_into().ok()?;
+
+    let is_mixed_case = raw.chars().any(|c| c.is_ascii_lowercase()) && raw.chars().any(|c| c.is_ascii_uppercase());
+    if is_mixed_case && checksum_address(&bytes)[2..] != *raw {
+        return None;
+    }
+    Some(String::from("0x") + &hex::encode(bytes))
+}
+
+/// Matches every entry against the addresses derived at indices 0..depth. `derive_address`
+/// returns the 0x-prefixed address of the account at the given index.
+pub fn plan_label_import<F>(entries: Vec<LabelEntry>, depth: usize, derive_address: F) -> LabelImportPlan
+    where F: Fn(usize) -> String
+{
+    let mut plan = LabelImportPlan::default();
+    let mut pending = vec![];
+
+    for entry in entries {
+        match normalize_address(&entry.address) {
+            Some(address) => pending.push((address, entry)),
+            None => plan.invalid.push(entry),
+        }
+    }
+
+    // derive only as deep as needed to find every valid address
+    let mut index = 0;
+    while !pending.is_empty() && index < depth {
+        let derived = derive_address(index).to_lowercase();
+        if let Some(pos) = pending.iter().position(|(address, _)| *address == derived) {
+            let (address, entry) = pending.remove(pos);
+            plan.matched.push((index, address, entry.label));
+        }
+        index += 1;
+    }
+
+    plan.matched.sort_by_key(|m| m.0);
+    plan.unmatched = pending.into_iter().map(|(_, entry)| entry).collect();
+    plan
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ADDRESS_0: &str = "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed";
+    const ADDRESS_1: &str = "0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359";
+    const ADDRESS_2: &str = "0xdbf03b407c01e7cd3cbea99509d93f8dddc8c6fb";
+
+    fn derive(index: usize) -> String {
+        [ADDRESS_0, ADDRESS_1, ADDRESS_2].get(index).map_or(String::from("0x00"), |a| a.to_string())
+    }
+
+    #[test]
+    fn test_parse_metamask_state() {
+        let json = r#"{"PreferencesController": {"identities": {
+            "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed": {"address": "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed", "name": "Savings"}
+        }}}"#;
+        let entries = parse_label_export(json).unwrap();
+        assert_eq!(entries, vec![LabelEntry { address: String::from(ADDRESS_0), label: String::from("Savings") }]);
+    }
+
+    #[test]
+    fn test_parse_flat_map_and_array() {
+        let flat = parse_label_export(r#"{"0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed": "Savings"}"#).unwrap();
+        let array = parse_label_export(r#"[{"address": "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed", "label": "Savings"}]"#).unwrap();
+        assert_eq!(flat, array);
+        assert!(parse_label_export("42").is_err());
+    }
+
+    #[test]
+    fn test_normalize_address() {
+        assert_eq!(normalize_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed").unwrap(), ADDRESS_0);
+        assert_eq!(normalize_address("0x5AAEB6053F3E94C9B9A09F33669435E7EF1BEAED").unwrap(), ADDRESS_0);
+        // bad checksum: first letter case flipped
+        assert!(normalize_address("0x5AAeb6053F3E94C9b9A09f33669435E7Ef1BeAed").is_none());
+        assert!(normalize_address("0x1234").is_none());
+    }
+
+    #[test]
+    fn test_plan_label_import() {
+        let entries = vec![
+            LabelEntry { address: String::from("0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB"), label: String::from("Third") },
+            LabelEntry { address: String::from(ADDRESS_0), label: String::from("First") },
+            LabelEntry { address: String::from("0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb"), label: String::from("Imported") },
+            LabelEntry { address: String::from("not an address"), label: String::from("Broken") },
+        ];
+        let plan = plan_label_import(entries, 10, derive);
+
+        assert_eq!(plan.matched, vec![
+            (0, String::from(ADDRESS_0), String::from("First")),
+            (2, String::from(ADDRESS_2), String::from("Third")),
+        ]);
+        assert_eq!(plan.unmatched.len(), 1);
+        assert_eq!(plan.unmatched[0].label, "Imported");
+        assert_eq!(plan.invalid.len(), 1);
+    }
+}
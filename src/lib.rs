@@ -0,0 +1,46 @@
+pub mod crypto;
+pub mod utils;
+pub mod storage;
+pub mod labels;
+pub mod schedule;
+pub mod amount;
+pub mod relayer;
+pub mod settings;
+pub mod hardening;
+pub mod recovery;
+pub mod fixtures;
+pub mod cbor;
+pub mod ur;
+pub mod session;
+pub mod audit;
+pub mod approval;
+pub mod watch;
+pub mod fees;
+pub mod journal;
+pub mod derivation;
+pub mod keystore;
+pub mod vanity;
+pub mod registry;
+pub mod entropy;
+pub mod password;
+pub mod keychain;
+pub mod data_dir;
+pub mod migrations;
+pub mod ssz_format;
+pub mod backup;
+pub mod history;
+pub mod address_book;
+pub mod networks;
+pub mod instance;
+pub mod balance_cache;
+pub mod rpc;
+pub mod eip1559;
+pub mod eip7702;
+pub mod ens;
+pub mod erc20;
+pub mod erc721;
+pub mod token_registry;
+pub mod prices;
+pub mod portfolio;
+pub mod refresh;
+pub mod error_code;
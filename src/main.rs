@@ -1,112 +1,904 @@
-mod crypto;
-mod utils;
-mod storage;
+use zeroize::Zeroizing;
 
-use std::path::Path;
-use std::fs::File;
-use std::io::prelude::*;
+use basic_eth::{data_dir, entropy, fixtures, hardening, instance, keychain, labels, migrations, session, utils};
+use basic_eth::crypto::slip39;
+use basic_eth::utils::{read_secret_input, read_user_input};
+use basic_eth::derivation::PathTemplate;
+use basic_eth::error_code::{ErrorCode, ErrorReport};
+use basic_eth::networks;
+use basic_eth::registry::Registry;
+use basic_eth::rpc::HttpRpc;
+use basic_eth::storage::{self, LegacyWallet, LoadError, MnemonicLanguage, MnemonicStrength, UnlockError, Wallet, WalletBackup};
+use basic_eth::settings::Settings;
 
-use crate::utils::{read_user_input};
-use crate::storage::Wallet;
-use bip39::{Mnemonic, Language};
+/// Printed for a command line that can't be understood
+const USAGE: &str = "Usage: basic-eth [--data-dir <directory>] [--skip-backup-quiz] [--json] [balance <address>]";
+
+/// What the command line asks for
+struct Options {
+    data_dir: Option<String>,
+    /// For scripted use, where nobody is there to answer the backup quiz
+    skip_backup_quiz: bool,
+    /// Print the command's result, or why it failed, as JSON
+    json: bool,
+    /// `balance <address>`: print the address's balance and quit, without opening a wallet
+    balance_of: Option<String>,
+}
 
 // TODO: in case of ctrl+c, need to write data cleanly to file, or else things like nonce won't be updated
 fn main() {
-    println!("{}", "Starting Rwallet2.0, an HD wallet...");
+    // hidden dev command: gen-fixture <seed> <accounts> <output path> [version]
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("gen-fixture") {
+        return gen_fixture(&args[2..]);
+    }
+    let json = args.iter().any(|a| a == "--json");
+    let result = parse_args(&args[1..]).and_then(|options| match &options.balance_of {
+        Some(address) => print_balance(&options, address),
+        None => run(&options),
+    });
+    // everything the run held, such as the wallet lock, has been released by now
+    if let Err(report) = result {
+        match json {
+            true => println!("{}", report.to_json()),
+            false => println!("{}", report.message),
+        }
+        std::process::exit(report.code.exit_code());
+    }
+}
 
-    if !Path::new("./userdata.txt").exists() {
-        display_menu_one();
-    } else {
-        display_menu_two();
+fn parse_args(args: &[String]) -> Result<Options, ErrorReport> {
+    let usage = || ErrorReport::new(ErrorCode::InvalidArgument, USAGE);
+    let mut options = Options { data_dir: None, skip_backup_quiz: false, json: false, balance_of: None };
+    let mut positional = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--data-dir" => options.data_dir = Some(args.next().ok_or_else(usage)?.clone()),
+            "--skip-backup-quiz" => options.skip_backup_quiz = true,
+            "--json" => options.json = true,
+            flag if flag.starts_with("--") => return Err(usage().with("argument", flag)),
+            _ => positional.push(arg.as_str()),
+        }
+    }
+    match positional.as_slice() {
+        // the wallet menus can't be answered in JSON
+        [] if options.json => return Err(usage().with("argument", "--json")),
+        [] => {},
+        ["balance", address] => options.balance_of = Some(String::from(*address)),
+        _ => return Err(usage()),
     }
+    Ok(options)
 }
 
-fn display_menu_one() {
-    println!("{}", "1) Create a new wallet");
-    println!("{}", "2) Import wallet");
-    let option = read_user_input().parse::<u8>().unwrap();
+/// Runs the wallet until the user quits, or fails with why it couldn't start
+fn run(options: &Options) -> Result<(), ErrorReport> {
+    println!("Starting Rwallet2.0, an HD wallet...");
+    let dir = data_dir::init(options.data_dir.as_deref()).map_err(|e| ErrorReport::new(ErrorCode::DataDirUnavailable, e))?;
+    // rather than failing every request later
+    networks::check_active().map_err(|e| ErrorReport::new(ErrorCode::NodeNotConfigured, e))?;
+    // held until run returns, so a second instance can't store over this one's changes
+    let (_lock, stale) = instance::acquire(dir)?;
+    if let Some(pid) = stale {
+        println!("Took over the wallet lock left by pid {}, which quit without releasing it", pid);
+    }
+    session::install();
+
+    let settings = Settings::load();
+    if !acknowledge_environment(&settings) {
+        return Err(ErrorReport::new(ErrorCode::UserRejected, "Stopped, since the environment warnings weren't accepted"));
+    }
 
-    match option {
-        1 => {
-            println!("{}", "Enter New Password: ");
-            let password = read_user_input();
-            let mut wallet = Wallet::new(password);
-            wallet.run();
+    let mut registry = Registry::load().map_err(|e| ErrorReport::new(ErrorCode::WalletUnreadable, e))?;
+    match registry.scan() {
+        Ok(added) => {
+            for name in added {
+                println!("Found a wallet file that wasn't listed, added as '{}'", name);
+            }
         },
-        2 => {
-            import_and_run_wallet();
+        Err(e) => println!("{}", e),
+    }
+    if let Some(mut wallet) = pick_wallet(&mut registry, options.skip_backup_quiz) {
+        let rpc = HttpRpc::active();
+        networks::report_active_node(&rpc);
+        wallet.run(&rpc);
+    }
+    Ok(())
+}
+
+/// Prints the balance of `address` on the active network, for scripts. No wallet is opened, so
+/// neither the wallet lock nor the environment checks are needed.
+fn print_balance(options: &Options, address: &str) -> Result<(), ErrorReport> {
+    data_dir::init(options.data_dir.as_deref()).map_err(|e| ErrorReport::new(ErrorCode::DataDirUnavailable, e))?;
+    networks::check_active().map_err(|e| ErrorReport::new(ErrorCode::NodeNotConfigured, e))?;
+    let normalized = labels::normalize_address(address).ok_or_else(|| {
+        ErrorReport::new(ErrorCode::InvalidAddress, format!("{} is not a valid address or its checksum is wrong", address)).with("address", address)
+    })?;
+    let network = networks::active();
+    let rpc = HttpRpc::active();
+    network.check_chain_id(&rpc)?;
+    let wei_balance = rpc.balance(&normalized)?;
+    match options.json {
+        // as a string, since a balance can be past what JSON numbers hold exactly
+        true => println!("{}", serde_json::json!({
+            "address": normalized,
+            "network": network.name,
+            "chain_id": network.chain_id,
+            "wei_balance": wei_balance.to_string(),
+        })),
+        false => println!("{} {}", utils::wei_to_eth(wei_balance), network.currency),
+    }
+    Ok(())
+}
+
+/// Writes a deterministic test wallet to the given path, in the current file version unless
+/// another is given
+fn gen_fixture(args: &[String]) {
+    let (seed, accounts, path, version) = match args {
+        [seed, accounts, path, version @ ..] if version.len() <= 1 => {
+            let version = version.first().map_or(Ok(migrations::CURRENT_VERSION), |v| v.parse::<u32>());
+            match (seed.parse::<u64>(), accounts.parse::<usize>(), version) {
+                (Ok(s), Ok(a), Ok(v)) => (s, a, path, v),
+                _ => return println!("Seed, account count and version must be numbers"),
+            }
         },
-        _ => println!("{}", "Invalid option"),
+        _ => return println!("Usage: gen-fixture <seed> <accounts> <output path> [version]"),
+    };
+    if !fixtures::FIXTURE_VERSIONS.contains(&version) {
+        return println!("Fixtures can be generated in versions {} to {}", fixtures::FIXTURE_VERSIONS.start(), fixtures::FIXTURE_VERSIONS.end());
+    }
+
+    let json = fixtures::generate_json(&fixtures::FixtureOptions { seed, accounts, version });
+    match std::fs::write(path, json) {
+        Ok(()) => println!("Wrote fixture wallet to {}", path),
+        Err(e) => println!("Error writing fixture: {}", e),
+    }
+}
+
+/// Warns about an insecure environment and asks the user to acknowledge each problem. Returns
+/// false if the user chose not to continue.
+fn acknowledge_environment(settings: &Settings) -> bool {
+    for check in hardening::unsupported_checks(&settings.hardening) {
+        println!("Warning: the {} check isn't available on this platform, so it was skipped", check);
+    }
+    let findings = hardening::run_startup_checks(&settings.hardening, data_dir::dir());
+    if findings.is_empty() {
+        return true;
+    }
+
+    println!("WARNING: this environment may expose your keys:");
+    for finding in &findings {
+        println!("\t- {}", finding);
+    }
+    println!("These checks can be turned off individually in {}.", Settings::path().display());
+    println!("Type YES to continue anyway: ");
+    read_user_input() == "YES"
+}
+
+/// A step of the startup menu
+enum Startup {
+    /// The list of wallets, and what else can be done
+    Picker,
+    Unlock(String),
+    Create,
+    Import,
+    Restore,
+    Delete,
+    Networks,
+}
+
+/// Runs the startup menu until a wallet is unlocked, created, imported or restored, and returns
+/// it, or None if the user quits. A step that is cancelled or fails goes back to the list of
+/// wallets.
+fn pick_wallet(registry: &mut Registry, skip_backup_quiz: bool) -> Option<Wallet> {
+    let mut step = Startup::Picker;
+    loop {
+        let opened = match step {
+            Startup::Picker => {
+                step = prompt_startup(registry)?;
+                continue;
+            },
+            Startup::Unlock(name) => {
+                let wallet = unlock_wallet(registry, &name);
+                if let Some(wallet) = &wallet {
+                    record_created_at(registry, &name, wallet);
+                }
+                wallet
+            },
+            Startup::Create => create_wallet(registry, skip_backup_quiz),
+            Startup::Import => import_wallet(registry),
+            Startup::Restore => restore_wallet(registry),
+            Startup::Delete => {
+                delete_wallet(registry);
+                None
+            },
+            Startup::Networks => {
+                networks::manage();
+                None
+            },
+        };
+        if opened.is_some() {
+            return opened;
+        }
+        step = Startup::Picker;
+    }
+}
+
+/// Lists when an unlocked wallet was created if that wasn't known, as for an encrypted file
+/// copied in by hand
+fn record_created_at(registry: &mut Registry, name: &str, wallet: &Wallet) {
+    if registry.find(name).unwrap().created_at.is_some() || wallet.created_at.is_none() {
+        return;
+    }
+    if let Err(e) = registry.register(name, wallet.created_at) {
+        println!("{}", e);
+    }
+}
+
+/// Lists the wallets, whose numbers unlock them, and the other options. Returns the chosen
+/// step, or None to quit.
+fn prompt_startup(registry: &Registry) -> Option<Startup> {
+    loop {
+        if registry.is_empty() {
+            println!("There are no wallets on this computer yet");
+        } else {
+            print_wallets(registry);
+            println!("Enter a wallet's number to unlock it, or:");
+        }
+        println!("c) Create a new wallet");
+        println!("i) Import wallet");
+        println!("r) Restore a wallet from a backup file");
+        if !registry.is_empty() {
+            println!("d) Delete a wallet");
+        }
+        println!("n) Networks (using {})", networks::active().name);
+        println!("q) QUIT");
+        let input = read_user_input();
+        match input.as_str() {
+            "c" => return Some(Startup::Create),
+            "i" => return Some(Startup::Import),
+            "r" => return Some(Startup::Restore),
+            "d" if !registry.is_empty() => return Some(Startup::Delete),
+            "n" => return Some(Startup::Networks),
+            "q" => return None,
+            _ => match input.parse::<usize>() {
+                Ok(i) if (1..=registry.wallets.len()).contains(&i) => return Some(Startup::Unlock(registry.wallets[i - 1].name.clone())),
+                _ => println!("Invalid option"),
+            },
+        }
+    }
+}
+
+/// Creates a wallet with a new recovery phrase. Returns None if the user goes back.
+fn create_wallet(registry: &mut Registry, skip_backup_quiz: bool) -> Option<Wallet> {
+    let name = prompt_wallet_name(registry)?;
+    let password = utils::read_new_password("Enter New Password")?;
+    let source = prompt_phrase_source();
+    let language = prompt_mnemonic_language(&MnemonicLanguage::ALL);
+    let passphrase = prompt_bip39_passphrase();
+    let mut wallet = match source {
+        PhraseSource::Random(strength) => Wallet::new(password.clone(), strength, language, &passphrase),
+        // both prompts only return valid BIP-39 entropy lengths
+        PhraseSource::Entropy(entropy) => Wallet::from_entropy(password.clone(), &entropy, language, &passphrase).unwrap(),
+    };
+    if !register(registry, &mut wallet, &name) {
+        return None;
+    }
+    if !skip_backup_quiz {
+        if let Err(e) = wallet.confirm_new_phrase(&password) {
+            println!("{}. Check your backup with \"Verify my recovery phrase backup\" before funding the wallet.", e);
+        }
+    }
+    offer_shamir_backup(&wallet, &password);
+    Some(wallet)
+}
+
+/// Offers to split the seed of a new wallet into SLIP-39 shares, shown one at a time
+fn offer_shamir_backup(wallet: &Wallet, password: &str) {
+    println!("Also back the wallet up as SLIP-39 shares, several of which together restore it? (y/n)");
+    if read_user_input() != "y" {
+        return;
+    }
+
+    loop {
+        println!("How many shares? (2-{})", slip39::MAX_SHARES);
+        let count = read_user_input().parse::<u8>();
+        println!("How many of them should it take to restore the wallet?");
+        let threshold = read_user_input().parse::<u8>();
+        let (count, threshold) = match (count, threshold) {
+            (Ok(count), Ok(threshold)) => (count, threshold),
+            _ => {
+                println!("Enter the numbers of shares as digits");
+                continue;
+            },
+        };
+        println!("Enter a SLIP-39 passphrase (or press enter for none). Restoring needs it as well as the shares: ");
+        let passphrase = Zeroizing::new(read_secret_input());
+
+        match wallet.shamir_shares(password, threshold, count, &passphrase) {
+            Ok(shares) => {
+                for (i, share) in shares.iter().enumerate() {
+                    println!("Share {} of {} ({} of them restore the wallet):", i + 1, count, threshold);
+                    println!("{}", share.as_str());
+                    println!("Write it down, then press enter to continue");
+                    read_user_input();
+                }
+                return println!("Keep the shares in separate places, so no one place holds enough of them");
+            },
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// Collects shares, starting with `first`, until there are enough to recover the master secret
+fn restore_from_shares(first: &str) -> Option<Zeroizing<Vec<u8>>> {
+    let mut shares = match slip39::Share::parse(first) {
+        Ok(share) => vec![share],
+        Err(e) => {
+            println!("{}", e);
+            return None;
+        },
+    };
+    while !slip39::is_complete(&shares) {
+        println!("{} share(s) entered. Enter another share (or type q to return to main menu): ", shares.len());
+        let input = read_secret_input();
+        if input == "q" {
+            return None;
+        }
+        match slip39::Share::parse(&input) {
+            Ok(share) if !share.same_backup(&shares[0]) => println!("That share is from a different backup"),
+            Ok(share) => shares.push(share),
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    println!("Enter the SLIP-39 passphrase (or press enter for none): ");
+    let passphrase = Zeroizing::new(read_secret_input());
+    match slip39::combine(&shares, &passphrase) {
+        Ok(secret) => Some(secret),
+        Err(e) => {
+            println!("{}", e);
+            None
+        },
+    }
+}
+
+/// Asks for the name of a new wallet, or returns None to go back. Reusing a name replaces that
+/// wallet, which has to be confirmed.
+fn prompt_wallet_name(registry: &Registry) -> Option<String> {
+    loop {
+        println!("Enter a name for this wallet (or type q to return to main menu): ");
+        let name = read_user_input();
+        let name = name.trim();
+        if name == "q" {
+            return None;
+        }
+        if name.is_empty() {
+            println!("The name can't be empty");
+            continue;
+        }
+        if registry.find(name).is_some() {
+            println!("A wallet named '{}' already exists. Type OVERWRITE to replace it, or press enter to choose another name: ", name);
+            if read_user_input() != "OVERWRITE" {
+                continue;
+            }
+        }
+        return Some(String::from(name));
+    }
+}
+
+/// Registers a wallet under `name` and makes it store to the registered file. Returns false if
+/// the registry couldn't be written.
+fn register(registry: &mut Registry, wallet: &mut Wallet, name: &str) -> bool {
+    match registry.register(name, wallet.created_at) {
+        Ok(file) => {
+            wallet.set_location(name, file);
+            true
+        },
+        Err(e) => {
+            println!("{}", e);
+            false
+        },
+    }
+}
+
+/// Asks which wallet to use, or picks the only one. Returns its name, or None to go back.
+fn prompt_wallet(registry: &Registry) -> Option<String> {
+    match registry.wallets.as_slice() {
+        [] => {
+            println!("There are no wallets on this computer yet");
+            return None;
+        },
+        [only] => return Some(only.name.clone()),
+        _ => {},
+    }
+    loop {
+        print_wallets(registry);
+        println!("Choose a wallet (or type q to return to main menu): ");
+        let input = read_user_input();
+        if input == "q" {
+            return None;
+        }
+        match input.parse::<usize>() {
+            Ok(i) if (1..=registry.wallets.len()).contains(&i) => return Some(registry.wallets[i - 1].name.clone()),
+            _ => println!("Invalid option"),
+        }
+    }
+}
+
+fn print_wallets(registry: &Registry) {
+    println!("Wallets on this computer:");
+    for (i, wallet) in registry.wallets.iter().enumerate() {
+        match wallet.created_at {
+            Some(created_at) => println!("{}) {} (created {})", i + 1, wallet.name, utils::format_date(created_at)),
+            None => println!("{}) {}", i + 1, wallet.name),
+        }
+    }
+}
+
+/// Where the randomness behind a new recovery phrase comes from
+enum PhraseSource {
+    /// The process RNG, for a phrase of this length
+    Random(MnemonicStrength),
+    /// Entropy supplied by the user
+    Entropy(Zeroizing<Vec<u8>>),
+}
+
+fn prompt_phrase_source() -> PhraseSource {
+    loop {
+        println!("Randomness for the recovery phrase:");
+        println!("1) Generated by this computer");
+        println!("2) My own entropy, as hex");
+        println!("3) Dice rolls");
+        match read_user_input().as_str() {
+            "1" => return PhraseSource::Random(prompt_mnemonic_strength()),
+            "2" => return PhraseSource::Entropy(prompt_hex_entropy()),
+            "3" => return PhraseSource::Entropy(prompt_dice_entropy(prompt_mnemonic_strength())),
+            _ => println!("Invalid option"),
+        }
     }
 }
 
-fn display_menu_two() {
+/// Asks for 128 or 256 bits of entropy as hex
+fn prompt_hex_entropy() -> Zeroizing<Vec<u8>> {
     loop {
-        println!("1) Login");
-        println!("2) Import wallet");
-        println!("3) QUIT");
-        match read_user_input().parse::<u8>() {
-            Ok(option) => {
-                match option {
-                    1 => {
-                        let mut file = File::open("./userdata.txt").unwrap();
-                        let mut buf = String::new();
-                        file.read_to_string(&mut buf).unwrap();
-                        let mut stored_wallet: Wallet = serde_json::from_str(&buf).unwrap();
-
-                        loop {
-                            println!("Enter Password (or type q to return to main menu): ");
-                            let user_input = read_user_input();
-
-                            if user_input == "q" {
-                                break;
-                            } else {
-                                match stored_wallet.verify_password(user_input) {
-                                    true => {
-                                        stored_wallet.run();
-                                        return
-                                    },
-                                    false => println!("Incorrect password"),
-                                };
-                            }
+        println!("Enter 32 hex characters for a 12 word phrase, or 64 for a 24 word phrase: ");
+        match entropy::parse_hex(&read_secret_input()) {
+            Ok(entropy) if accept_warnings(&entropy::hex_warnings(&entropy)) => return entropy,
+            Ok(_) => {},
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// Asks for enough die rolls to fill a phrase of the given length
+fn prompt_dice_entropy(strength: MnemonicStrength) -> Zeroizing<Vec<u8>> {
+    let bits = strength.entropy_bits();
+    loop {
+        println!("Roll a die at least {} times and enter the results (1-6) in order: ", entropy::min_dice_rolls(bits));
+        let rolls = Zeroizing::new(read_user_input());
+        match entropy::from_dice(&rolls, bits) {
+            Ok(entropy) if accept_warnings(&entropy::dice_warnings(&rolls)) => return entropy,
+            Ok(_) => {},
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// Shows warnings about entropy that doesn't look random, and returns true if there were none
+/// or the user chose to use it anyway
+fn accept_warnings(warnings: &[String]) -> bool {
+    if warnings.is_empty() {
+        return true;
+    }
+    println!("WARNING: this entropy does not look random, and a phrase made from it may be easy to guess:");
+    for warning in warnings {
+        println!("\t- {}", warning);
+    }
+    println!("Type YES to use it anyway, or press enter to enter it again: ");
+    read_user_input() == "YES"
+}
+
+/// Asks for the length of a new recovery phrase
+fn prompt_mnemonic_strength() -> MnemonicStrength {
+    loop {
+        println!("Recovery phrase length:");
+        for (i, strength) in MnemonicStrength::ALL.iter().enumerate() {
+            println!("{}) {} words", i + 1, strength.word_count());
+        }
+        match read_user_input().parse::<usize>() {
+            Ok(i) if (1..=MnemonicStrength::ALL.len()).contains(&i) => return MnemonicStrength::ALL[i - 1],
+            _ => println!("Invalid option"),
+        }
+    }
+}
+
+/// Asks which of the given wordlists to use
+fn prompt_mnemonic_language(languages: &[MnemonicLanguage]) -> MnemonicLanguage {
+    loop {
+        println!("Recovery phrase language:");
+        for (i, language) in languages.iter().enumerate() {
+            println!("{}) {}", i + 1, language.name());
+        }
+        match read_user_input().parse::<usize>() {
+            Ok(i) if (1..=languages.len()).contains(&i) => return languages[i - 1],
+            _ => println!("Invalid option"),
+        }
+    }
+}
+
+/// Restores the accounts a restored wallet has used, reporting the result
+fn discover_accounts(wallet: &mut Wallet) {
+    println!("Looking for used accounts...");
+    match wallet.discover_accounts(&HttpRpc::active()) {
+        Ok(1) => println!("Found 1 account"),
+        Ok(count) => println!("Found {} accounts", count),
+        Err(e) => println!("Could not look for used accounts, so only the first one was restored: {}", e),
+    }
+}
+
+/// Asks how the accounts being restored were laid out by the wallet that created them
+fn prompt_path_template() -> PathTemplate {
+    loop {
+        println!("Account layout:");
+        println!("1) Standard (MetaMask and most wallets): m/44'/60'/0'/0/x");
+        println!("2) Ledger Live: m/44'/60'/x'/0/0");
+        println!("3) Custom path");
+        match read_user_input().as_str() {
+            "1" => return PathTemplate::standard(),
+            "2" => return PathTemplate::ledger_live(),
+            "3" => {
+                println!("Enter the path, with {{index}} in place of the account number (e.g. m/44'/60'/0'/{{index}}): ");
+                match PathTemplate::parse(&read_user_input()) {
+                    Ok(template) => return template,
+                    Err(e) => println!("{}", e),
+                }
+            },
+            _ => println!("Invalid option"),
+        }
+    }
+}
+
+/// Asks for the optional BIP-39 passphrase (the "25th word"). Enter skips it.
+fn prompt_bip39_passphrase() -> String {
+    println!("Enter a BIP-39 passphrase (or press enter for none).");
+    println!("It is never stored, and the recovery phrase restores a different wallet without it: ");
+    read_secret_input()
+}
+
+/// Unlocks the wallet registered as `name`, upgrading its file if it is old. A damaged file is
+/// recovered first, if the user agrees. Returns None if the file can't be read or the user goes
+/// back.
+fn unlock_wallet(registry: &mut Registry, name: &str) -> Option<Wallet> {
+    let file = registry.find(name).unwrap().path();
+    if let Some(legacy) = LegacyWallet::load(&file) {
+        return upgrade_legacy_wallet(&legacy, name);
+    }
+    let mut stored_wallet = match Wallet::load(&file) {
+        Ok(wallet) => wallet,
+        Err(e) if e.is_damaged() => match recover_damaged_file(registry, name, &file, &e)? {
+            Recovered::Previous(wallet) => wallet,
+            Recovered::Imported(wallet) => return Some(wallet),
+        },
+        Err(e) => {
+            println!("{}", e);
+            return None;
+        },
+    };
+    stored_wallet.adopt_name(name);
+    println!("{}", stored_wallet.banner());
+    if unlock_with_keychain(&mut stored_wallet, &file) {
+        return Some(stored_wallet);
+    }
+
+    loop {
+        println!("Enter Password (or type q to return to main menu): ");
+        let user_input = Zeroizing::new(read_secret_input());
+        if *user_input == "q" {
+            return None;
+        }
+        let unlocked = stored_wallet.login(user_input.to_string());
+        if unlocked.is_ok() {
+            if let Err(e) = stored_wallet.upgrade_encryption(&user_input) {
+                println!("Couldn't upgrade the wallet file: {}", e);
+            }
+            if let Err(e) = stored_wallet.upgrade_version() {
+                println!("Couldn't upgrade the wallet file: {}", e);
+            }
+            if !stored_wallet.is_encrypted() {
+                println!("This wallet file is stored as plain text, so anyone who can read it sees its accounts and history.");
+                println!("Encrypt it now? (y/n)");
+                if read_user_input() == "y" {
+                    match stored_wallet.encrypt_file() {
+                        Ok(()) => println!("Encrypted the wallet file"),
+                        Err(e) => println!("Couldn't encrypt the wallet file: {}", e),
+                    }
+                }
+            }
+            offer_keychain(&stored_wallet, &file);
+            return Some(stored_wallet);
+        }
+        // no password opens a damaged or modified file
+        if unlocked != Err(UnlockError::IncorrectPassword) {
+            return None;
+        }
+    }
+}
+
+/// What a damaged wallet file was recovered as
+enum Recovered {
+    /// The previous version of the file, still locked
+    Previous(Wallet),
+    /// The wallet imported again in its place
+    Imported(Wallet),
+}
+
+/// Reports what is wrong with a wallet file, then offers to restore the previous version kept
+/// next to it if that is intact, or else to import the wallet again. The damaged file is moved
+/// aside for inspection either way. Returns None if the user declines both.
+fn recover_damaged_file(registry: &mut Registry, name: &str, file: &str, error: &LoadError) -> Option<Recovered> {
+    println!("The wallet file of '{}' can't be opened: {}", name, error);
+    match Wallet::load_previous(file) {
+        Ok(_) => {
+            println!("The previous version of the file, in {}, is intact. Restoring it loses what changed since it was stored.", storage::backup_path(file));
+            println!("Restore it? The damaged file is kept for inspection. (y/n)");
+            if read_user_input() == "y" {
+                match storage::restore_previous(file) {
+                    Ok(moved) => {
+                        println!("Restored the previous version. The damaged file is now {}", moved);
+                        match Wallet::load(file) {
+                            Ok(wallet) => return Some(Recovered::Previous(wallet)),
+                            Err(e) => println!("{}", e),
                         }
                     },
-                    2 => import_and_run_wallet(),
-                    3 => return,
-                    _ => println!("{}", "Invalid option"),
+                    Err(e) => println!("{}", e),
+                }
+            }
+        },
+        Err(_) => println!("There is no intact previous version of the file to restore."),
+    }
+
+    println!("Import '{}' again from its recovery phrase, SLIP-39 shares or xprv? The damaged file is kept for inspection. (y/n)", name);
+    if read_user_input() != "y" {
+        return None;
+    }
+    let moved = match storage::quarantine(file) {
+        Ok(moved) => moved,
+        Err(e) => {
+            println!("{}", e);
+            return None;
+        },
+    };
+    match import_as(registry, name) {
+        Some(wallet) => {
+            println!("The damaged file is now {}", moved);
+            Some(Recovered::Imported(wallet))
+        },
+        None => {
+            // nothing was imported, so the wallet keeps its file, damaged as it is
+            if let Err(e) = std::fs::rename(&moved, file) {
+                println!("Couldn't move {} back: {}", moved, e);
+            }
+            None
+        },
+    }
+}
+
+/// Unlocks a wallet file in the legacy format and rewrites it in the current one. Returns None if
+/// the user goes back to the main menu.
+fn upgrade_legacy_wallet(legacy: &LegacyWallet, name: &str) -> Option<Wallet> {
+    println!("Unlocking wallet '{}'. It was created by an older version and will be upgraded once unlocked.", name);
+    loop {
+        println!("Enter Password (or type q to return to main menu): ");
+        let user_input = read_secret_input();
+        if user_input == "q" {
+            return None;
+        }
+        match legacy.upgrade(name, user_input) {
+            Ok(wallet) => {
+                println!("Wallet upgraded");
+                return Some(wallet);
+            },
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// Offers to unlock with the key kept in the system keychain, or to forget it. Returns true if
+/// the wallet was unlocked; otherwise the password is asked for as usual.
+fn unlock_with_keychain(wallet: &mut Wallet, file: &str) -> bool {
+    if !keychain::SUPPORTED || !keychain::has_entry(file) {
+        return false;
+    }
+    println!("Unlock with the key in the system keychain? (y/n, or f to forget the key)");
+    match read_user_input().as_str() {
+        "y" => match wallet.login_with_keychain() {
+            Ok(()) => {
+                if let Err(e) = wallet.upgrade_version() {
+                    println!("Couldn't upgrade the wallet file: {}", e);
                 }
+                return true;
             },
-            Err(_e) => {
-                println!("Invalid option. Please enter 1 or 2.");
+            Err(e) => println!("{}", e),
+        },
+        "f" => match keychain::forget(file) {
+            Ok(()) => println!("Removed the key from the system keychain"),
+            Err(e) => println!("{}", e),
+        },
+        _ => {},
+    }
+    false
+}
+
+/// After a password login, offers to keep the wallet's key in the system keychain if the
+/// settings allow it
+fn offer_keychain(wallet: &Wallet, file: &str) {
+    if !keychain::SUPPORTED || !Settings::load().security.keychain || keychain::has_entry(file) {
+        return;
+    }
+    println!("Keep this wallet's key in the system keychain, so it opens without the password? (y/n)");
+    println!("Anyone who can use your account on this computer could then open it.");
+    if read_user_input() == "y" {
+        match wallet.remember_in_keychain() {
+            Ok(()) => println!("Added the key to the system keychain"),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// Deletes a wallet file once the user has typed the wallet's name to confirm
+fn delete_wallet(registry: &mut Registry) {
+    let name = match prompt_wallet(registry) {
+        Some(name) => name,
+        None => return,
+    };
+    println!("Deleting '{}' removes its file for good. Without its recovery phrase the funds in it are lost.", name);
+    println!("Type the wallet's name to delete it: ");
+    if read_user_input() != name {
+        return println!("Wallet not deleted");
+    }
+    // the entry is filed under the wallet file, so it goes first
+    if let Err(e) = keychain::forget(&registry.find(&name).unwrap().path()) {
+        println!("{}", e);
+    }
+    match registry.delete(&name) {
+        Ok(()) => println!("Deleted wallet '{}'", name),
+        Err(e) => println!("{}", e),
+    }
+}
+
+/// Restores a wallet from a backup file made in the wallet menu, with its accounts' labels, nonces
+/// and history. Nothing is written until the backup matches its checksum and its password has
+/// been entered.
+fn restore_wallet(registry: &mut Registry) -> Option<Wallet> {
+    let mut backup = loop {
+        println!("Enter the path of the backup file (or type q to return to main menu): ");
+        let path = read_user_input();
+        if path == "q" {
+            return None;
+        }
+        match WalletBackup::read(path.trim()) {
+            Ok(backup) => break backup,
+            Err(e) => println!("{}", e),
+        }
+    };
+    println!("This is a backup of '{}' taken {}.", backup.name, utils::format_timestamp(backup.taken_at));
+    loop {
+        println!("Enter the password the wallet had when it was backed up (or type q to return to main menu): ");
+        let password = read_secret_input();
+        if password == "q" {
+            return None;
+        }
+        match backup.verify_password(password) {
+            Ok(()) => break,
+            Err(UnlockError::IncorrectPassword) => println!("Incorrect password"),
+            // no password opens a damaged or modified file
+            Err(e) => {
+                println!("{}", e);
+                return None;
             },
         }
+    }
+    let name = prompt_wallet_name(registry)?;
+    let file = match registry.register(&name, backup.wallet_created_at()) {
+        Ok(file) => file,
+        Err(e) => {
+            println!("{}", e);
+            return None;
+        },
     };
+    match backup.restore(&name, file.clone()) {
+        Ok(wallet) => {
+            println!("Restored wallet '{}'", name);
+            // a key kept for the wallet it replaced may not open it
+            if let Err(e) = keychain::forget(&file) {
+                println!("{}", e);
+            }
+            Some(wallet)
+        },
+        Err(e) => {
+            println!("Couldn't restore the wallet: {}", e);
+            None
+        },
+    }
+}
+
+/// Restores a wallet from a recovery phrase, SLIP-39 shares or an xprv, or watches one from an
+/// xpub. Returns None if the user goes back.
+fn import_wallet(registry: &mut Registry) -> Option<Wallet> {
+    let name = prompt_wallet_name(registry)?;
+    import_as(registry, &name)
 }
 
-fn import_and_run_wallet() {
-    println!("{}", "Enter Password (or type q to return to main menu):");
-    let password = read_user_input();
+/// Imports a wallet as `name`, replacing any wallet registered under it. Returns None if the
+/// user goes back.
+fn import_as(registry: &mut Registry, name: &str) -> Option<Wallet> {
+    let password = utils::read_new_password("Enter Password")?;
+
     loop {
-        if &password == "q" {
-            break;
-        } else {
-            loop {
-                println!("Enter your mnemonic phrase to restore your wallet (or type q to return to main menu):");
-                let phrase = utils::read_user_input();
-                if phrase != "q" {
-                    match Mnemonic::from_phrase(&phrase, Language::English) {
-                        Ok(m) => {
-                            let mut wallet = Wallet::from(password.clone(), m);
-                            wallet.run();
-                            break;
-                        },
-                        Err(_e) => println!("Bad mnemonic. Enter 12 or 24 word phrase."),
-                    };
-                } else {
-                    break;
-                };
+        println!("Enter your mnemonic phrase, a SLIP-39 share or an xprv to restore your wallet, or an xpub to watch it \
+            (or type q to return to main menu):");
+        let phrase = utils::read_secret_input();
+        if phrase.trim().starts_with("xpub") {
+            match Wallet::watch_only(password.clone(), &phrase) {
+                Ok(mut wallet) => {
+                    if register(registry, &mut wallet, name) {
+                        println!("This wallet is watch-only. It can show balances but never send.");
+                        discover_accounts(&mut wallet);
+                        return Some(wallet);
+                    }
+                    return None;
+                },
+                Err(e) => println!("{}", e),
+            }
+        } else if phrase.trim().starts_with("xprv") {
+            match Wallet::from_xprv(password.clone(), &phrase) {
+                Ok(mut wallet) => {
+                    if register(registry, &mut wallet, name) {
+                        discover_accounts(&mut wallet);
+                        return Some(wallet);
+                    }
+                    return None;
+                },
+                Err(e) => println!("{}", e),
             }
+        } else if slip39::is_share_length(phrase.split_whitespace().count()) {
+            let wallet = restore_from_shares(&phrase).map(|secret| Wallet::from_master_secret(password.clone(), &secret));
+            match wallet {
+                Some(Ok(mut wallet)) => {
+                    if register(registry, &mut wallet, name) {
+                        discover_accounts(&mut wallet);
+                        return Some(wallet);
+                    }
+                    return None;
+                },
+                Some(Err(e)) => println!("{}", e),
+                None => {},
+            }
+        } else if phrase != "q" {
+            match MnemonicLanguage::parse_phrase(&phrase) {
+                Ok(mut parsed) => {
+                    let m = if parsed.len() == 1 {
+                        parsed.remove(0).1
+                    } else {
+                        println!("This phrase is valid in more than one language.");
+                        let languages: Vec<_> = parsed.iter().map(|(l, _)| *l).collect();
+                        let language = prompt_mnemonic_language(&languages);
+                        parsed.into_iter().find(|(l, _)| *l == language).unwrap().1
+                    };
+                    let passphrase = prompt_bip39_passphrase();
+                    let template = prompt_path_template();
+                    let mut wallet = Wallet::from_with_template(password, m, &passphrase, template);
+                    if register(registry, &mut wallet, name) {
+                        discover_accounts(&mut wallet);
+                        return Some(wallet);
+                    }
+                    return None;
+                },
+                Err(e) => println!("{}", e),
+            };
+        } else {
+            return None;
         };
-        break;
     }
 }
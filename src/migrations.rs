@@ -0,0 +1,129 @@
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::ssz_format;
+use crate::storage::Wallet;
+
+/// The version of the wallet files this app writes
+pub const CURRENT_VERSION: u32 = 12;
+/// Files with a `verification_key` but no `version`, written before versions were recorded
+pub const UNVERSIONED: u32 = 1;
+/// Files from before wallets had accounts, which `LegacyWallet` reads and rebuilds
+pub const LEGACY: u32 = 0;
+
+/// Brings a wallet from version `UNVERSIONED + i` to the next, in order, once it has been checked
+/// against its MAC. Every version still deserializes as a `Wallet`, with what it lacks taking
+/// its default, since the MAC covers the wallet as it was written.
+const STEPS: [fn(&mut Wallet); (CURRENT_VERSION - UNVERSIONED) as usize] = [v1_to_v2, v2_to_v3, v3_to_v4, v4_to_v5, v5_to_v6, v6_to_v7, v7_to_v8, v8_to_v9, v9_to_v10, v10_to_v11, v11_to_v12];
+
+#[derive(Debug, Error, PartialEq)]
+pub enum VersionError {
+    #[error("The wallet file is version {0}, and this app only reads up to version {CURRENT_VERSION}. Please upgrade the app to open it.")]
+    TooNew(u32),
+}
+
+/// Just enough of a wallet file to tell its version
+#[derive(Deserialize)]
+struct Probe {
+    version: Option<u32>,
+    verification_key: Option<IgnoredAny>,
+}
+
+/// The version of a wallet file, or None if it is neither SSZ nor a JSON object
+pub fn version(data: &[u8]) -> Option<u32> {
+    if let Some(ssz) = ssz_format::strip_magic(data) {
+        return ssz_format::version(ssz);
+    }
+    let probe: Probe = serde_json::from_slice(data).ok()?;
+    Some(match (probe.version, probe.verification_key) {
+        (Some(version), _) => version,
+        (None, Some(_)) => UNVERSIONED,
+        (None, None) => LEGACY,
+    })
+}
+
+/// Refuses a file written by a newer version of the app, before parsing it fails on whatever
+/// changed
+pub fn check(data: &[u8]) -> Result<(), VersionError> {
+    match version(data) {
+        Some(version) if version > CURRENT_VERSION => Err(VersionError::TooNew(version)),
+        _ => Ok(()),
+    }
+}
+
+/// Runs the steps from the wallet's version to the current one. Returns whether there were any,
+/// in which case the file needs rewriting.
+pub fn migrate(wallet: &mut Wallet) -> bool {
+    let from = wallet.version;
+    for step in STEPS.iter().skip(from.saturating_sub(UNVERSIONED) as usize) {
+        step(wallet);
+        wallet.version += 1;
+    }
+    wallet.version != from
+}
+
+/// Version 2 records the version, and changes nothing else
+fn v1_to_v2(_wallet: &mut Wallet) {}
+
+/// Version 3 keeps a history of the transactions sent from each account, which starts empty, and
+/// writes the accounts as JSON in SSZ files
+fn v2_to_v3(_wallet: &mut Wallet) {}
+
+/// Version 4 keeps an address book, which starts empty
+fn v3_to_v4(_wallet: &mut Wallet) {}
+
+/// Version 5 keeps gas preferences, which start at the defaults
+fn v4_to_v5(_wallet: &mut Wallet) {}
+
+/// Version 6 caches the last known balance of each account, which starts unknown
+fn v5_to_v6(_wallet: &mut Wallet) {}
+
+/// Version 7 adds a margin to gas estimates, which starts at 20%
+fn v6_to_v7(_wallet: &mut Wallet) {}
+
+/// Version 8 can prefer slow gas prices, which earlier versions can't read
+fn v7_to_v8(_wallet: &mut Wallet) {}
+
+/// Version 9 keeps a registry of the tokens the user added, which starts empty
+fn v8_to_v9(_wallet: &mut Wallet) {}
+
+/// Version 10 keeps the NFT collections the user tracks, which start empty
+fn v9_to_v10(_wallet: &mut Wallet) {}
+
+/// Version 11 checks the secondary and approval passphrases through the password KDF. Their
+/// keccak256 hashes can't be rehashed without the passphrases, so each is replaced when it
+/// is next entered.
+fn v10_to_v11(_wallet: &mut Wallet) {}
+
+/// Version 12 can hold a single account exported from another wallet, which earlier versions
+/// can't read
+fn v11_to_v12(_wallet: &mut Wallet) {}
+
+/// The version of a `Wallet` deserialized without one
+pub(crate) fn unversioned() -> u32 {
+    UNVERSIONED
+}
+
+/// An unversioned wallet is written without a version, as it was, so its MAC still matches
+pub(crate) fn is_unversioned(version: &u32) -> bool {
+    *version == UNVERSIONED
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_version() {
+        assert_eq!(version(br#"{"pad":[1],"root_pub_key":[2]}"#), Some(LEGACY));
+        assert_eq!(version(br#"{"pad":[],"verification_key":[2]}"#), Some(UNVERSIONED));
+        assert_eq!(version(br#"{"version":2,"verification_key":[2]}"#), Some(2));
+        assert_eq!(version(b"not json"), None);
+
+        assert_eq!(check(br#"{"version":2}"#), Ok(()));
+        assert_eq!(check(b"not json"), Ok(()));
+        assert_eq!(check(br#"{"version":99}"#), Err(VersionError::TooNew(99)));
+        assert!(VersionError::TooNew(99).to_string().ends_with("Please upgrade the app to open it."));
+    }
+}
@@ -0,0 +1,638 @@
+use std::fs;
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use url::Url;
+
+use crate::data_dir;
+use crate::erc20::{self, Token};
+use crate::fees;
+use crate::prices;
+use crate::refresh;
+use crate::rpc::{EthRpc, HttpRpc};
+use crate::settings::{self, NetworkSettings, Settings};
+use crate::utils::read_user_input;
+
+const NETWORKS_PATH: &str = "networks.json";
+/// How long the connection test waits for a node, so a wrong address doesn't hang the menu
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A node to talk to and the chain it serves
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct NetworkConfig {
+    pub name: String,
+    /// JSON-RPC endpoint
+    pub rpc_url: String,
+    /// EIP-155 chain id transactions are signed for
+    pub chain_id: u64,
+    /// The symbol balances are shown in
+    pub currency: String,
+    /// A block explorer, whose transaction pages are at `{explorer_url}/tx/{hash}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explorer_url: Option<String>,
+    /// Lowest gas price to sign with, in wei. Defaults to the preset for the chain id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_gas_price: Option<u128>,
+    /// Lowest tip per gas to sign EIP-1559 transactions with, in wei. Defaults to the preset for
+    /// the chain id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_priority_fee: Option<u128>,
+    /// Tokens whose balances are shown. Defaults to the well-known ones for the chain id.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tokens: Vec<Token>,
+    /// The price API's id of the currency, such as "ethereum". Defaults to the preset for the
+    /// chain id, and testnets have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_id: Option<String>,
+    /// The price API's id of the chain its tokens are priced on, such as "base". Defaults to the
+    /// preset for the chain id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_platform: Option<String>,
+}
+
+impl NetworkConfig {
+    /// The network in the `network` section of the settings, used while no other is selected.
+    /// Its URL is empty if the settings have no endpoint, which `check_active` refuses at startup.
+    pub fn from_settings(network: &NetworkSettings) -> NetworkConfig {
+        NetworkConfig {
+            name: settings::network_name(network.chain_id),
+            rpc_url: network.endpoint().unwrap_or_default(),
+            chain_id: network.chain_id,
+            currency: String::from("ETH"),
+            explorer_url: None,
+            min_gas_price: network.min_gas_price,
+            min_priority_fee: None,
+            tokens: Vec::new(),
+            price_id: None,
+            price_platform: None,
+        }
+    }
+
+    pub fn min_gas_price(&self) -> u128 {
+        self.min_gas_price.unwrap_or_else(|| fees::network_min_gas_price(self.chain_id))
+    }
+
+    pub fn min_priority_fee(&self) -> u128 {
+        self.min_priority_fee.unwrap_or_else(|| fees::network_min_priority_fee(self.chain_id))
+    }
+
+    pub fn price_id(&self) -> Option<String> {
+        self.price_id.clone().or_else(|| prices::native_coin(self.chain_id).map(String::from))
+    }
+
+    pub fn price_platform(&self) -> Option<String> {
+        self.price_platform.clone().or_else(|| prices::token_platform(self.chain_id).map(String::from))
+    }
+
+    pub fn tokens(&self) -> Vec<Token> {
+        match self.tokens.is_empty() {
+            true => erc20::known_tokens(self.chain_id),
+            false => self.tokens.clone(),
+        }
+    }
+
+    /// Asks the node at the network's URL which chain it serves, and fails unless it is this
+    /// network's
+    pub fn check_node(&self) -> Result<(), String> {
+        self.check_chain_id(&HttpRpc::new(&self.rpc_url).with_timeout(CONNECT_TIMEOUT)).map_err(|e| e.to_string())
+    }
+
+    /// Fails unless `rpc` serves this network's chain. An `HttpRpc` only asks its node once a
+    /// session.
+    pub fn check_chain_id(&self, rpc: &dyn EthRpc) -> Result<(), ChainCheckError> {
+        match rpc.chain_id() {
+            Ok(chain_id) if chain_id == self.chain_id => Ok(()),
+            Ok(chain_id) => Err(ChainCheckError::WrongChain { url: self.rpc_url.clone(), served: chain_id, expected: self.chain_id }),
+            Err(e) => Err(ChainCheckError::Unreachable { url: self.rpc_url.clone(), error: e }),
+        }
+    }
+
+    /// On mainnet transactions move real funds
+    pub fn is_mainnet(&self) -> bool {
+        self.chain_id == KnownNetwork::Mainnet.chain_id()
+    }
+
+    /// The explorer page of a transaction, if there is an explorer
+    pub fn transaction_url(&self, hash: &str) -> Option<String> {
+        self.explorer_url.as_ref().map(|url| format!("{}/tx/{}", url.trim_end_matches('/'), hash))
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err(String::from("The name can't be empty"));
+        }
+        check_url(&self.rpc_url)?;
+        if self.currency.trim().is_empty() {
+            return Err(String::from("The currency symbol can't be empty"));
+        }
+        self.explorer_url.as_deref().map_or(Ok(()), check_url)
+    }
+}
+
+/// Why a node can't be used for a network. Transactions are signed for the network's chain id,
+/// so a node on another chain would reject them, or worse, mine them on a chain the user didn't
+/// mean, like mainnet for a testnet.
+#[derive(Debug, Error, PartialEq)]
+pub enum ChainCheckError {
+    #[error("The node at {url} serves chain {served}, not {expected}")]
+    WrongChain { url: String, served: u64, expected: u64 },
+    #[error("The node at {url} couldn't be reached: {error}")]
+    Unreachable { url: String, error: String },
+}
+
+/// Checks `url` is an http:// or https:// URL with a host
+pub fn check_url(url: &str) -> Result<(), String> {
+    match Url::parse(url) {
+        Ok(parsed) if !matches!(parsed.scheme(), "http" | "https") => Err(format!("{} is not an http:// or https:// URL", url)),
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("{} is not a valid URL: {}", url, e)),
+    }
+}
+
+/// Public networks that can be picked without entering their details
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KnownNetwork {
+    Mainnet,
+    Sepolia,
+    Holesky,
+}
+
+impl KnownNetwork {
+    pub const ALL: [KnownNetwork; 3] = [KnownNetwork::Mainnet, KnownNetwork::Sepolia, KnownNetwork::Holesky];
+
+    pub fn chain_id(self) -> u64 {
+        match self {
+            KnownNetwork::Mainnet => 1,
+            KnownNetwork::Sepolia => 11155111,
+            KnownNetwork::Holesky => 17000,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            KnownNetwork::Mainnet => "Mainnet",
+            KnownNetwork::Sepolia => "Sepolia",
+            KnownNetwork::Holesky => "Holesky",
+        }
+    }
+
+    /// A public node that needs no API key
+    pub fn default_rpc_url(self) -> &'static str {
+        match self {
+            KnownNetwork::Mainnet => "https://ethereum-rpc.publicnode.com",
+            KnownNetwork::Sepolia => "https://ethereum-sepolia-rpc.publicnode.com",
+            KnownNetwork::Holesky => "https://ethereum-holesky-rpc.publicnode.com",
+        }
+    }
+
+    /// The known network with `chain_id`, if it is one
+    pub fn from_chain_id(chain_id: u64) -> Option<KnownNetwork> {
+        KnownNetwork::ALL.into_iter().find(|known| known.chain_id() == chain_id)
+    }
+
+    /// Testnet coins are shown under their own symbol, so a balance can't be taken for real ETH
+    pub fn config(self) -> NetworkConfig {
+        let (currency, explorer_url) = match self {
+            KnownNetwork::Mainnet => ("ETH", "https://etherscan.io"),
+            KnownNetwork::Sepolia => ("SepoliaETH", "https://sepolia.etherscan.io"),
+            KnownNetwork::Holesky => ("HoleskyETH", "https://holesky.etherscan.io"),
+        };
+        NetworkConfig {
+            name: String::from(self.name()),
+            rpc_url: String::from(self.default_rpc_url()),
+            chain_id: self.chain_id(),
+            currency: String::from(currency),
+            explorer_url: Some(String::from(explorer_url)),
+            min_gas_price: None,
+            min_priority_fee: None,
+            tokens: Vec::new(),
+            price_id: None,
+            price_platform: None,
+        }
+    }
+}
+
+/// The networks the user added, by name, and which one the wallet talks to
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Networks {
+    pub networks: Vec<NetworkConfig>,
+    /// The name of the selected network, or None for the one in the settings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active: Option<String>,
+}
+
+impl Networks {
+    /// Loads the networks, or none if they were never saved
+    pub fn load() -> Result<Networks, String> {
+        let path = data_dir::path(NETWORKS_PATH);
+        if !path.exists() {
+            return Ok(Networks::default());
+        }
+        let data = fs::read_to_string(&path).map_err(|e| format!("Error reading {}: {}", path.display(), e))?;
+        serde_json::from_str(&data).map_err(|e| format!("Malformed {}: {}", path.display(), e))
+    }
+
+    /// Writes the networks next to the old file and renames it over, like `Registry::save`
+    pub fn save(&self) -> Result<(), String> {
+        let path = data_dir::path(NETWORKS_PATH);
+        let temp_path = data_dir::path(&format!("{}.tmp", NETWORKS_PATH));
+        fs::write(&temp_path, serde_json::to_vec_pretty(self).unwrap())
+            .and_then(|()| fs::rename(&temp_path, &path))
+            .map_err(|e| format!("Error writing {}: {}", path.display(), e))
+    }
+
+    /// The network named `name`, ignoring case
+    pub fn find(&self, name: &str) -> Option<&NetworkConfig> {
+        self.networks.iter().find(|n| n.name.eq_ignore_ascii_case(name.trim()))
+    }
+
+    fn position(&self, name: &str) -> Result<usize, String> {
+        self.networks.iter().position(|n| n.name.eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(|| format!("There is no network named {}", name.trim()))
+    }
+
+    pub fn add(&mut self, network: NetworkConfig) -> Result<(), String> {
+        network.check()?;
+        if self.find(&network.name).is_some() {
+            return Err(format!("There is already a network named {}", network.name));
+        }
+        self.networks.push(network);
+        Ok(())
+    }
+
+    /// Replaces the network named `name`, which stays selected if it was, under its new name
+    pub fn edit(&mut self, name: &str, network: NetworkConfig) -> Result<(), String> {
+        let position = self.position(name)?;
+        network.check()?;
+        if self.networks.iter().enumerate().any(|(i, n)| i != position && n.name.eq_ignore_ascii_case(&network.name)) {
+            return Err(format!("There is already a network named {}", network.name));
+        }
+        if self.is_active(&self.networks[position].name) {
+            self.active = Some(network.name.clone());
+        }
+        self.networks[position] = network;
+        Ok(())
+    }
+
+    /// Deletes the network named `name`. If it was selected, the one in the settings is used
+    /// again.
+    pub fn remove(&mut self, name: &str) -> Result<NetworkConfig, String> {
+        let position = self.position(name)?;
+        if self.is_active(&self.networks[position].name) {
+            self.active = None;
+        }
+        Ok(self.networks.remove(position))
+    }
+
+    /// Talks to the network named `name` from now on, or to the one in the settings for None
+    pub fn select(&mut self, name: Option<&str>) -> Result<(), String> {
+        self.active = match name {
+            Some(name) => Some(self.networks[self.position(name)?].name.clone()),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Selects a public network, adding it first unless there is already one under its name,
+    /// which is kept as it was in case its details were edited
+    pub fn use_known(&mut self, known: KnownNetwork) -> Result<(), String> {
+        if self.find(known.name()).is_none() {
+            self.add(known.config())?;
+        }
+        self.select(Some(known.name()))
+    }
+
+    pub fn is_active(&self, name: &str) -> bool {
+        self.active.as_ref().is_some_and(|active| active.eq_ignore_ascii_case(name))
+    }
+
+    /// The selected network, or None if it is the one in the settings. A selection whose
+    /// network is gone counts as none.
+    pub fn selected(&self) -> Option<&NetworkConfig> {
+        self.find(self.active.as_ref()?)
+    }
+}
+
+/// The network the wallet talks to: the selected one, or else the one in the settings
+pub fn active() -> NetworkConfig {
+    let networks = Networks::load().unwrap_or_else(|e| {
+        eprintln!("{}, using the network in {}", e, Settings::path().display());
+        Networks::default()
+    });
+    networks.selected().cloned().unwrap_or_else(|| NetworkConfig::from_settings(&Settings::load().network))
+}
+
+/// Every network there is to talk to: the one in the settings, if it has an endpoint, then the
+/// added ones. One the settings name again is only listed once.
+pub fn configured() -> Vec<NetworkConfig> {
+    let networks = Networks::load().unwrap_or_else(|e| {
+        eprintln!("{}, using the network in {}", e, Settings::path().display());
+        Networks::default()
+    });
+    let from_settings = Some(NetworkConfig::from_settings(&Settings::load().network))
+        .filter(|n| !n.rpc_url.is_empty())
+        .filter(|n| !networks.networks.iter().any(|added| added.chain_id == n.chain_id && added.rpc_url == n.rpc_url));
+    from_settings.into_iter().chain(networks.networks).collect()
+}
+
+/// Fails with what to configure if the wallet has no node to talk to: no network is selected,
+/// and the settings have no endpoint
+pub fn check_active() -> Result<(), String> {
+    if Networks::load().is_ok_and(|networks| networks.selected().is_some()) {
+        return Ok(());
+    }
+    Settings::load().network.endpoint().and_then(|url| check_url(&url))
+}
+
+/// Lists the networks, and selects, adds, edits or removes them until the user goes back.
+/// Every change is saved as it is made.
+pub fn manage() {
+    let mut networks = match Networks::load() {
+        Ok(networks) => networks,
+        Err(e) => return println!("{}", e),
+    };
+    loop {
+        print_networks(&networks);
+        println!("Enter a network's number to use it, or:");
+        println!("p) Use a public network ({})", KnownNetwork::ALL.map(KnownNetwork::name).join(", "));
+        println!("a) Add a network");
+        if !networks.networks.is_empty() {
+            println!("e) Edit a network");
+            println!("r) Remove a network");
+        }
+        println!("i) Refresh interval ({})", refresh::current().interval);
+        println!("b) Back");
+        let input = read_user_input();
+        let result = match input.as_str() {
+            "p" => match prompt_known_network() {
+                Some(known) => networks.use_known(known).map(|()| {
+                    println!("Using {} (chain {})", known.name(), known.chain_id());
+                    report_node(&networks);
+                }),
+                None => continue,
+            },
+            "a" => prompt_reachable_network(None).and_then(|network| networks.add(network)),
+            "e" | "r" if !networks.networks.is_empty() => {
+                let name = match prompt_network_number(&networks) {
+                    Some(name) => name,
+                    None => continue,
+                };
+                match input.as_str() {
+                    "e" => prompt_reachable_network(networks.find(&name)).and_then(|network| networks.edit(&name, network)),
+                    _ => networks.remove(&name).map(|removed| println!("Removed {}", removed.name)),
+                }
+            },
+            "i" => {
+                refresh::manage();
+                continue;
+            },
+            "b" => return,
+            _ => match input.parse::<usize>() {
+                Ok(0) => networks.select(None).map(|()| report_node(&networks)),
+                Ok(i) if (1..=networks.networks.len()).contains(&i) => {
+                    let name = networks.networks[i - 1].name.clone();
+                    networks.select(Some(&name)).map(|()| report_node(&networks))
+                },
+                _ => {
+                    println!("Invalid option");
+                    continue;
+                },
+            },
+        };
+        if let Err(e) = result.and_then(|()| networks.save()) {
+            println!("{}", e);
+        }
+    }
+}
+
+/// Lists the network in the settings as 0 and the added ones after it, marking the one in use
+fn print_networks(networks: &Networks) {
+    let from_settings = NetworkConfig::from_settings(&Settings::load().network);
+    let selected = networks.selected();
+    let listed = std::iter::once((&from_settings, selected.is_none()))
+        .chain(networks.networks.iter().map(|n| (n, selected == Some(n))));
+    println!("Networks:");
+    for (i, (network, active)) in listed.enumerate() {
+        let source = if i == 0 { ", from the settings" } else { "" };
+        let marker = if active { " (in use)" } else { "" };
+        println!("{}) {}{}: chain {}, {}, {}{}", i, network.name, marker, network.chain_id, network.currency, network.rpc_url, source);
+    }
+}
+
+/// Warns if the selected network's node is unreachable or on another chain, since nothing can be
+/// sent through it until that is fixed
+fn report_node(networks: &Networks) {
+    let network = networks.selected().cloned().unwrap_or_else(|| NetworkConfig::from_settings(&Settings::load().network));
+    if let Err(e) = network.check_node() {
+        println!("{}. Transactions won't be sent through it until it serves chain {}.", e, network.chain_id);
+    }
+}
+
+/// Warns when the wallet connects if the active network's node serves another chain, since
+/// nothing is signed while it does. One that can't be reached yet is left to fail when used.
+pub fn report_active_node(rpc: &dyn EthRpc) {
+    let network = active();
+    if let Err(e @ ChainCheckError::WrongChain { .. }) = network.check_chain_id(rpc) {
+        println!("{}. Transactions won't be signed until it serves chain {}: switch networks or fix its URL.", e, network.chain_id);
+    }
+}
+
+/// Asks which public network to use, or None to go back
+fn prompt_known_network() -> Option<KnownNetwork> {
+    for (i, known) in KnownNetwork::ALL.iter().enumerate() {
+        println!("{}) {} (chain {})", i + 1, known.name(), known.chain_id());
+    }
+    println!("Which network? (or press enter to go back)");
+    read_user_input().parse::<usize>().ok()
+        .and_then(|i| i.checked_sub(1))
+        .and_then(|i| KnownNetwork::ALL.get(i).copied())
+}
+
+/// Asks which added network to edit or remove. Returns its name, or None to go back.
+fn prompt_network_number(networks: &Networks) -> Option<String> {
+    println!("Which network? (1-{}, or press enter to go back)", networks.networks.len());
+    match read_user_input().parse::<usize>() {
+        Ok(i) if (1..=networks.networks.len()).contains(&i) => Some(networks.networks[i - 1].name.clone()),
+        _ => None,
+    }
+}
+
+/// Asks for a network and tests its node. One that can't be reached, or serves another chain,
+/// is only kept if the user says so.
+fn prompt_reachable_network(current: Option<&NetworkConfig>) -> Result<NetworkConfig, String> {
+    let network = prompt_network(current);
+    network.check()?;
+    if let Err(problem) = network.check_node() {
+        println!("{}. Save the network anyway? (y/n)", problem);
+        return match read_user_input().as_str() {
+            "y" => Ok(network),
+            _ => Err(String::from("The network wasn't saved")),
+        };
+    }
+    println!("Connected to {}, which serves chain {}", network.rpc_url, network.chain_id);
+    Ok(network)
+}
+
+/// Asks for the details of a network. When editing, enter keeps each current value.
+fn prompt_network(current: Option<&NetworkConfig>) -> NetworkConfig {
+    let name = prompt_network_field("Name", current.map(|n| n.name.as_str()));
+    let rpc_url = prompt_network_field("JSON-RPC URL", current.map(|n| n.rpc_url.as_str()));
+    let chain_id = loop {
+        match prompt_network_field("Chain id", current.map(|n| n.chain_id.to_string()).as_deref()).parse::<u64>() {
+            Ok(chain_id) => break chain_id,
+            Err(_) => println!("Enter a chain id, such as 1 for Mainnet"),
+        }
+    };
+    let currency = prompt_network_field("Currency symbol", Some(current.map_or("ETH", |n| n.currency.as_str())));
+    let explorer = prompt_network_field("Block explorer URL, or - for none", Some(current.and_then(|n| n.explorer_url.as_deref()).unwrap_or("-")));
+    NetworkConfig {
+        name,
+        rpc_url,
+        chain_id,
+        currency,
+        explorer_url: (explorer != "-").then_some(explorer),
+        min_gas_price: current.and_then(|n| n.min_gas_price),
+        min_priority_fee: current.and_then(|n| n.min_priority_fee),
+        tokens: current.map_or_else(Vec::new, |n| n.tokens.clone()),
+        price_id: current.and_then(|n| n.price_id.clone()),
+        price_platform: current.and_then(|n| n.price_platform.clone()),
+    }
+}
+
+/// Asks for one detail of a network. Enter keeps `current`, if there is one.
+fn prompt_network_field(label: &str, current: Option<&str>) -> String {
+    match current {
+        Some(current) => println!("{} (enter keeps {}): ", label, current),
+        None => println!("{}: ", label),
+    }
+    let input = read_user_input();
+    match current {
+        Some(current) if input.is_empty() => String::from(current),
+        _ => input,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn network(name: &str, chain_id: u64) -> NetworkConfig {
+        NetworkConfig {
+            name: String::from(name),
+            rpc_url: String::from("http://127.0.0.1:8545"),
+            chain_id,
+            currency: String::from("ETH"),
+            explorer_url: None,
+            min_gas_price: None,
+            min_priority_fee: None,
+            tokens: Vec::new(),
+            price_id: None,
+            price_platform: None,
+        }
+    }
+
+    #[test]
+    fn test_add_edit_remove() {
+        let mut networks = Networks::default();
+        networks.add(network("Local", 31)).unwrap();
+        assert_eq!(networks.add(network("local", 32)).unwrap_err(), "There is already a network named local");
+        assert_eq!(networks.add(NetworkConfig { rpc_url: String::from("ws://node"), ..network("Node", 1) }).unwrap_err(),
+            "ws://node is not an http:// or https:// URL");
+        assert!(networks.add(NetworkConfig { currency: String::from(" "), ..network("Node", 1) }).is_err());
+        networks.add(network("Other", 1)).unwrap();
+
+        networks.select(Some("LOCAL")).unwrap();
+        assert_eq!(networks.active.as_deref(), Some("Local"));
+        // renaming keeps the selection
+        networks.edit("local", network("Dev", 31)).unwrap();
+        assert_eq!(networks.selected().unwrap().name, "Dev");
+        assert_eq!(networks.edit("dev", network("other", 31)).unwrap_err(), "There is already a network named other");
+        assert_eq!(networks.select(Some("Gone")).unwrap_err(), "There is no network named Gone");
+
+        assert_eq!(networks.remove("other").unwrap().name, "Other");
+        assert!(networks.selected().is_some());
+        networks.remove("dev").unwrap();
+        assert_eq!(networks.active, None);
+        assert!(networks.networks.is_empty());
+    }
+
+    #[test]
+    fn test_from_settings() {
+        let network = NetworkConfig::from_settings(&NetworkSettings::default());
+        assert_eq!((network.name.as_str(), network.chain_id, network.currency.as_str()), ("Sepolia", 11155111, "ETH"));
+        assert_eq!(network.transaction_url("0x12"), None);
+
+        let network = NetworkConfig { explorer_url: Some(String::from("https://sepolia.etherscan.io/")), ..network };
+        assert_eq!(network.transaction_url("0x12").unwrap(), "https://sepolia.etherscan.io/tx/0x12");
+        assert_eq!(network.min_gas_price(), fees::network_min_gas_price(11155111));
+        assert_eq!(network.min_priority_fee(), fees::network_min_priority_fee(11155111));
+        assert_eq!(network.price_id(), None);
+        assert_eq!(NetworkConfig { price_id: Some(String::from("sepolia-eth")), ..network.clone() }.price_id().as_deref(), Some("sepolia-eth"));
+        assert_eq!(KnownNetwork::Mainnet.config().price_id().as_deref(), Some("ethereum"));
+        assert_eq!(network.price_platform(), None);
+        assert_eq!(NetworkConfig { chain_id: 8453, ..network.clone() }.price_platform().as_deref(), Some("base"));
+
+        // a selection whose network was deleted by hand falls back to the settings
+        let networks: Networks = serde_json::from_str(r#"{"networks":[],"active":"Gone"}"#).unwrap();
+        assert_eq!(networks.selected(), None);
+    }
+
+    #[test]
+    fn test_known_networks() {
+        for known in KnownNetwork::ALL {
+            assert_eq!(KnownNetwork::from_chain_id(known.chain_id()), Some(known));
+            assert_eq!(known.config().check(), Ok(()));
+            assert_eq!(settings::network_name(known.chain_id()), known.name());
+        }
+        assert_eq!(KnownNetwork::from_chain_id(4), None);
+        assert!(KnownNetwork::Mainnet.config().is_mainnet());
+        assert!(!KnownNetwork::Sepolia.config().is_mainnet());
+
+        let mut networks = Networks::default();
+        networks.use_known(KnownNetwork::Sepolia).unwrap();
+        assert_eq!(networks.selected().unwrap().chain_id, 11155111);
+        // an edited copy is used as it is
+        networks.edit("Sepolia", network("Sepolia", 11155111)).unwrap();
+        networks.select(None).unwrap();
+        networks.use_known(KnownNetwork::Sepolia).unwrap();
+        assert_eq!(networks.selected().unwrap().rpc_url, "http://127.0.0.1:8545");
+        assert_eq!(networks.networks.len(), 1);
+    }
+
+    #[test]
+    fn test_check_url() {
+        assert_eq!(check_url("http://localhost:8545"), Ok(()));
+        assert_eq!(check_url("https://sepolia.infura.io/v3/abc"), Ok(()));
+        assert_eq!(check_url("ws://localhost:8546").unwrap_err(), "ws://localhost:8546 is not an http:// or https:// URL");
+        assert!(check_url("http://").unwrap_err().starts_with("http:// is not a valid URL"));
+        assert!(check_url("localhost:8545").is_err());
+    }
+
+    /// Answers one JSON-RPC request with `result`, and returns the URL to send it to
+    fn serve_once(result: &'static str) -> String {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.read(&mut [0; 4096]);
+            let body = format!(r#"{{"jsonrpc":"2.0","id":"1","result":"{}"}}"#, result);
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        });
+        url
+    }
+
+    #[test]
+    fn test_check_node() {
+        let sepolia = NetworkConfig { rpc_url: serve_once("0xaa36a7"), ..network("Sepolia", 11155111) };
+        assert_eq!(sepolia.check_node(), Ok(()));
+
+        let arbitrum = NetworkConfig { rpc_url: serve_once("0x1"), ..network("Arbitrum", 42161) };
+        assert_eq!(arbitrum.check_node().unwrap_err(), format!("The node at {} serves chain 1, not 42161", arbitrum.rpc_url));
+
+        // nothing listens on the discard port
+        let offline = NetworkConfig { rpc_url: String::from("http://127.0.0.1:9"), ..network("Offline", 1) };
+        assert!(offline.check_node().unwrap_err().starts_with("The node at http://127.0.0.1:9 couldn't be reached"));
+    }
+}
@@ -0,0 +1,118 @@
+use crate::utils::read_user_input;
+
+/// The 10,000 most common passwords, lowercase, one per line
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+/// Lowest score a new password can have without the user accepting the risk
+pub const MIN_SCORE: u8 = 2;
+/// What the user types to keep a weak password
+const OVERRIDE_PHRASE: &str = "I understand the risk";
+
+/// A score from 0 (trivially guessed) to 4, and what would make the password stronger
+#[derive(Debug, PartialEq)]
+pub struct PasswordStrength {
+    pub score: u8,
+    pub feedback: Vec<String>,
+}
+
+/// Scores a new password by its length and mix of characters. Common passwords, also with digits
+/// or symbols tacked on the end, score 0 however long they are.
+pub fn strength(password: &str) -> PasswordStrength {
+    let length = password.chars().count();
+    let lower = password.to_lowercase();
+    let stem = lower.trim_end_matches(|c: char| c.is_ascii_digit() || c.is_ascii_punctuation());
+    if COMMON_PASSWORDS.lines().any(|common| common == lower) {
+        return PasswordStrength { score: 0, feedback: vec![String::from("It is one of the 10,000 most common passwords")] };
+    }
+    if COMMON_PASSWORDS.lines().any(|common| common == stem) {
+        return PasswordStrength { score: 0, feedback: vec![String::from("It is a common password with a few characters added")] };
+    }
+
+    let mut feedback = vec![];
+    let mut score = match length {
+        0..=7 => 0,
+        8..=11 => 1,
+        12..=15 => 2,
+        _ => 3,
+    };
+    if length < 12 {
+        feedback.push(String::from("Use at least 12 characters, or a few random words"));
+    }
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_ascii_digit()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ];
+    if classes.iter().filter(|c| **c).count() >= 3 {
+        score += 1;
+    } else if length < 16 {
+        feedback.push(String::from("Mix in capital letters, digits or symbols"));
+    }
+
+    let mut distinct: Vec<char> = password.chars().collect();
+    distinct.sort_unstable();
+    distinct.dedup();
+    if distinct.len() * 3 < length {
+        score = score.min(1);
+        feedback.push(String::from("Avoid repeating the same few characters"));
+    }
+    if length < 8 {
+        score = 0;
+    }
+    PasswordStrength { score: score.min(4), feedback }
+}
+
+/// Checks a new password, printing what is wrong with a weak one. Returns true if it is strong
+/// enough or the user accepts the risk, and false if they want to choose another.
+pub fn confirm_strength(password: &str) -> bool {
+    let strength = strength(password);
+    if strength.score >= MIN_SCORE {
+        return true;
+    }
+    println!("This password is weak ({}/4):", strength.score);
+    for line in &strength.feedback {
+        println!("- {}", line);
+    }
+    println!("Type \"{}\" to use it anyway, or press enter to choose another:", OVERRIDE_PHRASE);
+    read_user_input() == OVERRIDE_PHRASE
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_strength() {
+        let table = [
+            ("", 0),
+            ("a", 0),
+            ("password", 0),
+            ("Dragon", 0),
+            ("password123!", 0),
+            ("qwertyuiop", 0),
+            ("xk7#qm", 0),
+            ("zorblaxian", 1),
+            ("Zorblax1an", 2),
+            ("zorblaxianwomp", 2),
+            ("aaaaaaaaaaaaaaaaaaaa", 1),
+            ("abcabcabcabcabcabc", 1),
+            ("Zorblax1an!Womp", 3),
+            ("correct horse battery staple", 3),
+            ("Correct horse battery staple 9", 4),
+        ];
+        for (password, score) in table {
+            assert_eq!(strength(password).score, score, "{:?}", password);
+        }
+
+        assert_eq!(strength("letmein").feedback, ["It is one of the 10,000 most common passwords"]);
+        assert_eq!(strength("Sunshine2024!").feedback, ["It is a common password with a few characters added"]);
+        assert_eq!(strength("zorblax").feedback, ["Use at least 12 characters, or a few random words", "Mix in capital letters, digits or symbols"]);
+        assert!(strength("Correct horse battery staple 9").feedback.is_empty());
+    }
+
+    #[test]
+    fn test_common_passwords() {
+        assert_eq!(COMMON_PASSWORDS.lines().count(), 10_000);
+        assert!(COMMON_PASSWORDS.lines().all(|p| p == p.to_lowercase()));
+    }
+}
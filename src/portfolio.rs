@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::erc20::{self, Token};
+use crate::networks::NetworkConfig;
+use crate::prices::{self, Price};
+use crate::rpc::HttpRpc;
+use crate::utils;
+
+/// How long each node is given, so one that can't be reached doesn't hold up the overview
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Shown in the value column when there is nothing to count
+pub const NO_VALUE: &str = "—";
+
+/// An account's balance on one network, or why it couldn't be fetched
+#[derive(Clone)]
+pub struct NetworkBalance {
+    pub network: NetworkConfig,
+    pub wei_balance: Result<u128, String>,
+    /// Its balances of the network's tokens it holds any of, and of those that couldn't be
+    /// fetched
+    pub tokens: Vec<(Token, Result<u128, String>)>,
+}
+
+/// The prices of what `rows` hold, by coin id for the networks' currencies and by
+/// `prices::token_key` for their tokens. Each is asked for once, however many rows hold it.
+pub fn prices(rows: &[NetworkBalance]) -> HashMap<String, Price> {
+    let mut coins: Vec<String> = rows.iter().filter_map(|row| row.network.price_id()).collect();
+    coins.sort();
+    coins.dedup();
+    let mut found = prices::coin_prices(&coins.iter().map(String::as_str).collect::<Vec<_>>());
+    let mut platforms: Vec<(String, Vec<&str>)> = vec![];
+    for row in rows {
+        let platform = match row.network.price_platform() {
+            Some(platform) if !row.tokens.is_empty() => platform,
+            _ => continue,
+        };
+        let listed = match platforms.iter().position(|(p, _)| *p == platform) {
+            Some(i) => &mut platforms[i].1,
+            None => {
+                platforms.push((platform, vec![]));
+                &mut platforms.last_mut().unwrap().1
+            },
+        };
+        for (token, _) in &row.tokens {
+            if !listed.iter().any(|address| address.eq_ignore_ascii_case(&token.address)) {
+                listed.push(token.address.as_str());
+            }
+        }
+    }
+    for (platform, addresses) in platforms {
+        found.extend(prices::token_prices(&platform, &addresses));
+    }
+    found
+}
+
+/// The value in cents of `amount` of `token` on `network`, if it has a price in `prices`
+pub fn token_value(prices: &HashMap<String, Price>, network: &NetworkConfig, token: &Token, amount: u128) -> Option<u128> {
+    let platform = network.price_platform()?;
+    prices.get(&prices::token_key(&platform, &token.address))?.value_cents(amount, token.decimals)
+}
+
+/// Runs `query` for every network at once, each on its own thread, and returns what each
+/// gave in the order of `networks`
+pub fn query_all<N: Sync, T: Send>(networks: &[N], query: impl Fn(&N) -> Result<T, String> + Sync) -> Vec<Result<T, String>> {
+    let query = &query;
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = networks.iter().map(|network| scope.spawn(move || query(network))).collect();
+        handles.into_iter()
+            .map(|handle| handle.join().unwrap_or_else(|_| Err(String::from("The query stopped unexpectedly"))))
+            .collect()
+    })
+}
+
+/// A node for `network` of its own, whatever network is active, which refuses to answer for
+/// another chain
+pub fn connect(network: &NetworkConfig) -> Result<HttpRpc, String> {
+    let rpc = HttpRpc::new(&network.rpc_url).with_timeout(QUERY_TIMEOUT);
+    network.check_chain_id(&rpc).map_err(|e| e.to_string())?;
+    Ok(rpc)
+}
+
+/// The value in cents of a network's balance, and of each of its tokens, where it was fetched
+/// and has a price
+fn values(row: &NetworkBalance, prices: &HashMap<String, Price>) -> (Option<u128>, Vec<Option<u128>>) {
+    let network = &row.network;
+    let native = row.wei_balance.as_ref().ok().and_then(|wei| network.price_id()
+        .and_then(|id| prices.get(&id))
+        .and_then(|price| price.value_cents(*wei, 18)));
+    let tokens = row.tokens.iter()
+        .map(|(token, amount)| amount.as_ref().ok().and_then(|amount| token_value(prices, network, token, *amount)))
+        .collect();
+    (native, tokens)
+}
+
+/// Whether each of `rows` is counted in their total. A chain is counted once, by the first of
+/// its networks that answered, so two nodes of one chain don't count its balances twice. One
+/// that couldn't be reached is counted as missing unless another node of its chain answered.
+fn counted(rows: &[NetworkBalance]) -> Vec<bool> {
+    let answered = |chain_id| rows.iter().any(|row| row.network.chain_id == chain_id && row.wei_balance.is_ok());
+    rows.iter().enumerate().map(|(i, row)| match row.wei_balance {
+        Ok(_) => !rows[..i].iter().any(|earlier| earlier.network.chain_id == row.network.chain_id && earlier.wei_balance.is_ok()),
+        Err(_) => !answered(row.network.chain_id),
+    }).collect()
+}
+
+/// What `rows` are worth in cents, and whether any balance counted in them couldn't be fetched
+/// or has no price
+pub fn total(rows: &[NetworkBalance], prices: &HashMap<String, Price>) -> (u128, bool) {
+    let mut total = 0u128;
+    let mut uncounted = false;
+    for (row, counts) in rows.iter().zip(counted(rows)) {
+        if !counts {
+            continue;
+        }
+        let (native, tokens) = values(row, prices);
+        for value in std::iter::once(native).chain(tokens) {
+            match value {
+                Some(cents) => total = total.saturating_add(cents),
+                None => uncounted = true,
+            }
+        }
+    }
+    (total, uncounted)
+}
+
+/// Lines of `cells` in columns, the first `columns` of each
+fn columns(cells: &[[String; 3]], columns: usize) -> Vec<String> {
+    let widths: Vec<usize> = (0..columns)
+        .map(|i| cells.iter().map(|row| row[i].chars().count()).max().unwrap_or(0))
+        .collect();
+    cells.iter().map(|row| {
+        let padded: Vec<String> = (0..columns).map(|i| format!("{:<width$}", row[i], width = widths[i])).collect();
+        String::from(padded.join("  ").trim_end())
+    }).collect()
+}
+
+/// The lines of a table of each network's balance followed by its tokens', with their values in
+/// `fiat` and a total of them all, or without values for None. `last_known` describes a cached
+/// balance to show for a network that couldn't be reached, unless another node of its chain
+/// answered.
+pub fn table(rows: &[NetworkBalance], prices: &HashMap<String, Price>, fiat: Option<&str>, last_known: impl Fn(&NetworkConfig) -> Option<String>) -> Vec<String> {
+    let mut errors = vec![];
+    let mut repeated = false;
+    let answered = |chain_id| rows.iter().any(|row| row.network.chain_id == chain_id && row.wei_balance.is_ok());
+    let mut cells: Vec<[String; 3]> = vec![[String::from("Network"), String::from("Balance"), String::from("Value")]];
+    let mut push = |name: String, balance: String, value: Option<u128>| {
+        let value = fiat.zip(value).map_or_else(|| String::from(NO_VALUE), |(fiat, cents)| prices::format_cents(cents, fiat));
+        cells.push([name, balance, value]);
+    };
+    for (row, counts) in rows.iter().zip(counted(rows)) {
+        let network = &row.network;
+        let (native, token_values) = values(row, prices);
+        let balance = match &row.wei_balance {
+            Ok(wei) => {
+                repeated |= !counts;
+                format!("{} {}", utils::wei_to_eth(*wei), network.currency)
+            },
+            Err(e) => {
+                errors.push(format!("{}: {}", network.name, e));
+                match last_known(network).filter(|_| !answered(network.chain_id)) {
+                    Some(cached) => format!("unreachable, last known {}", cached),
+                    None => String::from("unreachable"),
+                }
+            },
+        };
+        push(network.name.clone(), balance, native);
+        // under the network's name, which isn't repeated
+        for ((token, amount), value) in row.tokens.iter().zip(token_values) {
+            match amount {
+                Ok(amount) => push(String::new(), format!("{} {}", erc20::format_units(*amount, token.decimals), token.symbol), value),
+                Err(e) => {
+                    errors.push(format!("{} {}: {}", network.name, token.symbol, e));
+                    push(String::new(), format!("{} couldn't be fetched", token.symbol), None);
+                },
+            }
+        }
+    }
+
+    let (total, uncounted) = total(rows, prices);
+    if let Some(fiat) = fiat {
+        cells.push([String::from("Total"), String::new(), prices::format_cents(total, fiat)]);
+    }
+    let mut lines = columns(&cells, if fiat.is_some() { 3 } else { 2 });
+    // the errors go above the total
+    let total_line = fiat.and(lines.pop());
+    lines.extend(errors);
+    if let Some(total_line) = total_line {
+        lines.push(total_line);
+        if uncounted {
+            lines.push(format!("Balances marked {} couldn't be fetched or have no price, so they aren't in the total", NO_VALUE));
+        }
+        if repeated {
+            lines.push(String::from("Networks of a chain already listed aren't counted again"));
+        }
+    }
+    lines
+}
+
+/// The lines of a table of what each account holds on every network, natively and in tokens,
+/// in `fiat`, and of their total across the wallet. `accounts` are each account's name with
+/// its balances, as `table` takes them.
+pub fn wallet_table(accounts: &[(String, Vec<NetworkBalance>)], prices: &HashMap<String, Price>, fiat: &str) -> Vec<String> {
+    let mut grand_total = 0u128;
+    let mut any_uncounted = false;
+    let mut cells: Vec<[String; 3]> = vec![[String::from("Account"), String::from("Value"), String::new()]];
+    for (name, rows) in accounts {
+        let (total, uncounted) = total(rows, prices);
+        grand_total = grand_total.saturating_add(total);
+        any_uncounted |= uncounted;
+        let marker = if uncounted { NO_VALUE } else { "" };
+        cells.push([name.clone(), prices::format_cents(total, fiat), String::from(marker)]);
+    }
+    cells.push([String::from("Wallet total"), prices::format_cents(grand_total, fiat), String::new()]);
+    let mut lines = columns(&cells, 3);
+    if any_uncounted {
+        lines.push(format!("Balances marked {} couldn't be fetched or have no price, so they aren't in the total", NO_VALUE));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::networks::KnownNetwork;
+
+    fn network(name: &str, chain_id: u64, currency: &str) -> NetworkConfig {
+        NetworkConfig { name: String::from(name), chain_id, currency: String::from(currency), ..KnownNetwork::Mainnet.config() }
+    }
+
+    #[test]
+    fn test_query_all() {
+        let networks = [network("Mainnet", 1, "ETH"), network("Base", 8453, "ETH"), network("Sepolia", 11155111, "SepoliaETH")];
+        let results = query_all(&networks, |network| match network.chain_id {
+            8453 => Err(String::from("Connection refused")),
+            chain_id => Ok(chain_id),
+        });
+        assert_eq!(results, [Ok(1), Err(String::from("Connection refused")), Ok(11155111)]);
+    }
+
+    fn token(symbol: &str, address: &str, decimals: u8) -> Token {
+        Token { symbol: String::from(symbol), address: String::from(address), decimals }
+    }
+
+    fn balance(network: NetworkConfig, wei_balance: Result<u128, String>) -> NetworkBalance {
+        NetworkBalance { network, wei_balance, tokens: vec![] }
+    }
+
+    #[test]
+    fn test_table() {
+        let usdc = token("USDC", "0xA0b86991c6218b36c1d19d4a2e9eB0cE3606eB48", 6);
+        let wbtc = token("WBTC", "0x2260fac5e5542a773aa44fbcfedf7c193bc2c599", 8);
+        let unpriced = token("FOO", "0x1111111111111111111111111111111111111111", 0);
+        let rows = vec![
+            NetworkBalance {
+                tokens: vec![(usdc.clone(), Ok(1_250_500_000)), (wbtc, Ok(1_000_000)), (unpriced, Ok(3))],
+                ..balance(network("Mainnet", 1, "ETH"), Ok(1_500_000_000_000_000_000))
+            },
+            NetworkBalance {
+                tokens: vec![(usdc, Err(String::from("execution reverted")))],
+                ..balance(network("Arbitrum", 42161, "ETH"), Ok(100_000_000_000_000_000))
+            },
+            balance(network("Sepolia", 11155111, "SepoliaETH"), Ok(2_000_000_000_000_000_000)),
+            balance(network("Base", 8453, "ETH"), Err(String::from("Connection refused"))),
+        ];
+        let prices = HashMap::from([
+            (String::from("ethereum"), Price::parse("2000").unwrap()),
+            (prices::token_key("ethereum", "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"), Price::parse("1").unwrap()),
+            (prices::token_key("ethereum", "0x2260fac5e5542a773aa44fbcfedf7c193bc2c599"), Price::parse("60000").unwrap()),
+        ]);
+        let last_known = |network: &NetworkConfig| Some(format!("0.2 {}, 3h ago", network.currency));
+        assert_eq!(table(&rows, &prices, Some("usd"), last_known), [
+            "Network   Balance                                  Value",
+            "Mainnet   1.5 ETH                                  3000.00 USD",
+            "          1250.5 USDC                              1250.50 USD",
+            "          0.01 WBTC                                600.00 USD",
+            "          3 FOO                                    —",
+            "Arbitrum  0.1 ETH                                  200.00 USD",
+            "          USDC couldn't be fetched                 —",
+            "Sepolia   2 SepoliaETH                             —",
+            "Base      unreachable, last known 0.2 ETH, 3h ago  —",
+            "Arbitrum USDC: execution reverted",
+            "Base: Connection refused",
+            "Total                                              5050.50 USD",
+            "Balances marked — couldn't be fetched or have no price, so they aren't in the total",
+        ]);
+
+        // nor for a chain another node answered for
+        let rows = vec![
+            balance(network("Mainnet", 1, "ETH"), Ok(1_500_000_000_000_000_000)),
+            balance(network("Mainnet mirror", 1, "ETH"), Err(String::from("Connection refused"))),
+        ];
+        assert_eq!(table(&rows, &prices, Some("usd"), last_known)[2], "Mainnet mirror  unreachable  —");
+
+        // two networks of one chain are only counted once
+        let mirrored = NetworkBalance {
+            tokens: vec![(token("USDC", "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48", 6), Ok(5_000_000))],
+            ..balance(network("Mainnet mirror", 1, "ETH"), Ok(1_500_000_000_000_000_000))
+        };
+        let rows = vec![
+            NetworkBalance { tokens: mirrored.tokens.clone(), ..balance(network("Mainnet", 1, "ETH"), Ok(1_500_000_000_000_000_000)) },
+            mirrored,
+        ];
+        assert_eq!(total(&rows, &prices), (300_500, false));
+        assert_eq!(table(&rows, &prices, Some("usd"), last_known)[5..], [
+            "Total                    3005.00 USD",
+            "Networks of a chain already listed aren't counted again",
+        ]);
+
+        // without a price API there is no value column
+        assert_eq!(table(&rows[..1], &HashMap::new(), None, |_| None), ["Network  Balance", "Mainnet  1.5 ETH"]);
+    }
+
+    #[test]
+    fn test_wallet_table() {
+        let usdc = token("USDC", "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48", 6);
+        let prices = HashMap::from([
+            (String::from("ethereum"), Price::parse("2000").unwrap()),
+            (prices::token_key("ethereum", &usdc.address), Price::parse("1").unwrap()),
+        ]);
+        let accounts = vec![
+            (String::from("0xaaaa (Savings)"), vec![
+                NetworkBalance { tokens: vec![(usdc.clone(), Ok(250_000_000))], ..balance(network("Mainnet", 1, "ETH"), Ok(1_000_000_000_000_000_000)) },
+                balance(network("Base", 8453, "ETH"), Ok(500_000_000_000_000_000)),
+            ]),
+            (String::from("0xbbbb"), vec![
+                NetworkBalance { tokens: vec![(usdc, Err(String::from("execution reverted")))], ..balance(network("Mainnet", 1, "ETH"), Ok(100_000_000_000_000_000)) },
+                balance(network("Base", 8453, "ETH"), Err(String::from("Connection refused"))),
+            ]),
+        ];
+        assert_eq!(wallet_table(&accounts, &prices, "usd"), [
+            "Account           Value",
+            "0xaaaa (Savings)  3250.00 USD",
+            "0xbbbb            200.00 USD   —",
+            "Wallet total      3450.00 USD",
+            "Balances marked — couldn't be fetched or have no price, so they aren't in the total",
+        ]);
+    }
+}
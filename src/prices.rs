@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use crate::data_dir;
+use crate::refresh;
+use crate::rpc;
+use crate::settings::{PriceSettings, Settings};
+use crate::utils;
+
+/// In the data directory
+const PRICES_PATH: &str = "prices.json";
+/// The price API's ids of the coins public networks pay gas in, by chain id. Testnet coins have
+/// no price.
+const NATIVE_COINS: [(u64, &str); 4] = [
+    (1, "ethereum"),
+    (10, "ethereum"),
+    (8453, "ethereum"),
+    (42161, "ethereum"),
+];
+
+/// The price API's ids of the chains public networks' tokens are priced on, by chain id
+const TOKEN_PLATFORMS: [(u64, &str); 4] = [
+    (1, "ethereum"),
+    (10, "optimistic-ethereum"),
+    (8453, "base"),
+    (42161, "arbitrum-one"),
+];
+
+/// The price API's id of the native coin of the chain with `chain_id`, if it has a price
+pub fn native_coin(chain_id: u64) -> Option<&'static str> {
+    NATIVE_COINS.iter().find(|(id, _)| *id == chain_id).map(|(_, coin)| *coin)
+}
+
+/// The price API's id of the chain with `chain_id` for pricing its tokens, if it has one
+pub fn token_platform(chain_id: u64) -> Option<&'static str> {
+    TOKEN_PLATFORMS.iter().find(|(id, _)| *id == chain_id).map(|(_, platform)| *platform)
+}
+
+/// What the price of a token contract is found by, among coin ids, which never have a colon
+pub fn token_key(platform: &str, address: &str) -> String {
+    format!("{}:{}", platform, address.to_lowercase())
+}
+
+/// A price in fiat per whole coin or token, kept as the exact decimal the price API gave:
+/// `digits` × 10^-`scale`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Price {
+    digits: u128,
+    scale: u32,
+}
+
+impl Price {
+    /// Parses a decimal such as "3456.78", or one with an exponent such as "1.5e-7" as JSON
+    /// numbers may be written
+    pub fn parse(text: &str) -> Option<Price> {
+        let (mantissa, exponent) = match text.split_once(['e', 'E']) {
+            Some((mantissa, exponent)) => (mantissa, exponent.parse::<i32>().ok()?),
+            None => (text, 0),
+        };
+        let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if whole.is_empty() && fraction.is_empty() || !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let digits = match format!("{}{}", whole, fraction).trim_start_matches('0') {
+            "" => 0,
+            digits => digits.parse::<u128>().ok()?,
+        };
+        let scale = i32::try_from(fraction.len()).ok()?.checked_sub(exponent)?;
+        match u32::try_from(scale) {
+            Ok(scale) => Some(Price { digits, scale }),
+            // a positive exponent beyond the fraction, as in "2e3"
+            Err(_) => Some(Price { digits: digits.checked_mul(10u128.checked_pow(scale.unsigned_abs())?)?, scale: 0 }),
+        }
+    }
+
+    /// The value of `amount` in the smallest unit of a coin or token with `decimals` places, in
+    /// hundredths of the fiat currency rounded down. None if it is too large to count.
+    pub fn value_cents(&self, amount: u128, decimals: u8) -> Option<u128> {
+        let shift = i64::from(decimals) + i64::from(self.scale) - 2;
+        match u32::try_from(shift) {
+            Ok(shift) => scaled_product(amount, self.digits, shift),
+            Err(_) => amount.checked_mul(self.digits)?.checked_mul(10u128.checked_pow(shift.unsigned_abs() as u32)?),
+        }
+    }
+}
+
+impl fmt::Display for Price {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scale = self.scale as usize;
+        let digits = format!("{:0>width$}", self.digits, width = scale + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - scale);
+        match fraction {
+            "" => write!(f, "{}", whole),
+            fraction => write!(f, "{}.{}", whole, fraction),
+        }
+    }
+}
+
+impl TryFrom<String> for Price {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        Price::parse(&text).ok_or_else(|| format!("{} is not a price", text))
+    }
+}
+
+impl From<Price> for String {
+    fn from(price: Price) -> String {
+        price.to_string()
+    }
+}
+
+/// `a` × `b` / 10^`shift`, rounded down, or None if it doesn't fit a u128. The product is kept
+/// in four 64-bit limbs, least significant first, so it can't overflow before it is divided.
+fn scaled_product(a: u128, b: u128, shift: u32) -> Option<u128> {
+    let (a, b) = ([a as u64, (a >> 64) as u64], [b as u64, (b >> 64) as u64]);
+    let mut limbs = [0u64; 4];
+    for i in 0..2 {
+        let mut carry = 0u128;
+        for j in 0..2 {
+            let sum = limbs[i + j] as u128 + a[i] as u128 * b[j] as u128 + carry;
+            limbs[i + j] = sum as u64;
+            carry = sum >> 64;
+        }
+        limbs[i + 2] = carry as u64;
+    }
+    for _ in 0..shift {
+        let mut remainder = 0u128;
+        for limb in limbs.iter_mut().rev() {
+            let current = (remainder << 64) | *limb as u128;
+            *limb = (current / 10) as u64;
+            remainder = current % 10;
+        }
+    }
+    match limbs[2..] {
+        [0, 0] => Some(limbs[0] as u128 | (limbs[1] as u128) << 64),
+        _ => None,
+    }
+}
+
+/// e.g. "1234.56 USD" for 123456 cents
+pub fn format_cents(cents: u128, currency: &str) -> String {
+    format!("{}.{:02} {}", cents / 100, cents % 100, currency.to_uppercase())
+}
+
+/// Prices looked up before, so views shown in a row don't ask the price API each time
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+struct PriceCache {
+    prices: Vec<CachedPrice>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct CachedPrice {
+    /// The price API's id of the coin, or the `token_key` of a token
+    coin: String,
+    /// The fiat currency it is in, such as "usd"
+    currency: String,
+    price: Price,
+    /// Unix timestamp (seconds) of the lookup
+    fetched_at: u64,
+}
+
+impl PriceCache {
+    /// Loads the cache, or an empty one if there is none or it can't be read
+    fn load() -> PriceCache {
+        fs::read_to_string(data_dir::path(PRICES_PATH)).ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        if let Err(e) = fs::write(data_dir::path(PRICES_PATH), serde_json::to_vec_pretty(self).unwrap()) {
+            println!("Error writing {}: {}", PRICES_PATH, e);
+        }
+    }
+
+    fn find(&self, coin: &str, currency: &str) -> Option<&CachedPrice> {
+        self.prices.iter().find(|p| p.coin == coin && p.currency == currency)
+    }
+
+    /// The cached price of `coin` if it was fetched less than `max_age_secs` before `now`
+    fn fresh(&self, coin: &str, currency: &str, now: u64, max_age_secs: u64) -> Option<Price> {
+        self.find(coin, currency).filter(|p| now.saturating_sub(p.fetched_at) < max_age_secs).map(|p| p.price)
+    }
+
+    fn insert(&mut self, coin: &str, currency: &str, price: Price, now: u64) {
+        self.prices.retain(|p| p.coin != coin || p.currency != currency);
+        self.prices.push(CachedPrice { coin: String::from(coin), currency: String::from(currency), price, fetched_at: now });
+    }
+}
+
+/// The prices of `coins` in the configured fiat currency, by id. Those cached within the refresh
+/// interval are used as they are, and the rest are asked of the price API at once. If it can't be
+/// reached, older cached prices are used instead. Nothing is looked up unless a price API is
+/// configured, and a coin without a price is left out.
+pub fn coin_prices(coins: &[&str]) -> HashMap<String, Price> {
+    lookup(coins, fetch_coin_prices)
+}
+
+/// The prices of the token contracts at `addresses` on `platform`, by `token_key`, cached and
+/// looked up as `coin_prices` does
+pub fn token_prices(platform: &str, addresses: &[&str]) -> HashMap<String, Price> {
+    let keys: Vec<String> = addresses.iter().map(|address| token_key(platform, address)).collect();
+    lookup(&keys.iter().map(String::as_str).collect::<Vec<_>>(), |api_url, settings, keys| {
+        let addresses: Vec<&str> = keys.iter().map(|key| &key[platform.len() + 1..]).collect();
+        let fetched = fetch_token_prices(api_url, settings, platform, &addresses)?;
+        Ok(fetched.into_iter().map(|(address, price)| (token_key(platform, &address), price)).collect())
+    })
+}
+
+/// The prices of `coins` from the cache and, for those missing from it, `fetch`
+fn lookup(coins: &[&str], fetch: impl FnOnce(&str, &PriceSettings, &[&str]) -> Result<HashMap<String, Price>, String>) -> HashMap<String, Price> {
+    let settings = Settings::load().prices;
+    let api_url = match &settings.api_url {
+        Some(url) if !url.trim().is_empty() => url.trim_end_matches('/'),
+        _ => return HashMap::new(),
+    };
+    let now = utils::now();
+    let mut cache = PriceCache::load();
+    let mut prices = HashMap::new();
+    let mut missing = vec![];
+    for coin in coins {
+        match cache.fresh(coin, &settings.currency, now, refresh::current().interval.max_age_secs()) {
+            Some(price) => {
+                prices.insert(String::from(*coin), price);
+            },
+            None if !missing.contains(coin) => missing.push(*coin),
+            None => {},
+        }
+    }
+    if missing.is_empty() {
+        return prices;
+    }
+
+    match fetch(api_url, &settings, &missing) {
+        Ok(fetched) => {
+            for (coin, price) in fetched {
+                cache.insert(&coin, &settings.currency, price, now);
+                prices.insert(coin, price);
+            }
+            cache.save();
+        },
+        Err(e) => {
+            println!("Prices couldn't be fetched, so those last fetched are shown: {}", e);
+            for coin in missing {
+                if let Some(cached) = cache.find(coin, &settings.currency) {
+                    prices.insert(String::from(coin), cached.price);
+                }
+            }
+        },
+    }
+    prices
+}
+
+/// Asks the price API for the prices of `coins`.
+///
+/// The API is CoinGecko's, or one that answers like it:
+/// - `GET <url>/simple/price?ids=<id>,<id>&vs_currencies=<currency>`
+/// - it answers `{ "<id>": { "<currency>": <price> } }`, leaving out the coins it doesn't know
+fn fetch_coin_prices(api_url: &str, settings: &PriceSettings, coins: &[&str]) -> Result<HashMap<String, Price>, String> {
+    let network = Settings::load().network;
+    let agent = rpc::agent(Duration::from_secs(network.connect_timeout_secs), Duration::from_secs(network.read_timeout_secs));
+    let response = agent.get(&format!("{}/simple/price", api_url))
+        .query("ids", &coins.join(","))
+        .query("vs_currencies", &settings.currency)
+        .call();
+    match response {
+        Ok(r) => {
+            let body: Value = r.into_json().map_err(|e| format!("The price API returned invalid JSON: {}", e))?;
+            Ok(parse_prices(&body, &settings.currency))
+        },
+        Err(ureq::Error::Status(code, _)) => Err(format!("The price API refused the request ({})", code)),
+        Err(e) => Err(format!("Could not reach the price API: {}", e)),
+    }
+}
+
+/// Asks the price API for the prices of the token contracts at `addresses` on `platform`.
+///
+/// As with coins, the API is CoinGecko's or one that answers like it:
+/// - `GET <url>/simple/token_price/<platform>?contract_addresses=<address>,<address>&vs_currencies=<currency>`
+/// - it answers `{ "<address>": { "<currency>": <price> } }`, leaving out the tokens it doesn't know
+fn fetch_token_prices(api_url: &str, settings: &PriceSettings, platform: &str, addresses: &[&str]) -> Result<HashMap<String, Price>, String> {
+    let network = Settings::load().network;
+    let agent = rpc::agent(Duration::from_secs(network.connect_timeout_secs), Duration::from_secs(network.read_timeout_secs));
+    let response = agent.get(&format!("{}/simple/token_price/{}", api_url, platform))
+        .query("contract_addresses", &addresses.join(","))
+        .query("vs_currencies", &settings.currency)
+        .call();
+    match response {
+        Ok(r) => {
+            let body: Value = r.into_json().map_err(|e| format!("The price API returned invalid JSON: {}", e))?;
+            Ok(parse_prices(&body, &settings.currency))
+        },
+        Err(ureq::Error::Status(code, _)) => Err(format!("The price API refused the request ({})", code)),
+        Err(e) => Err(format!("Could not reach the price API: {}", e)),
+    }
+}
+
+/// The prices in a `{ "<id>": { "<currency>": <price> } }` response. Entries without a price
+/// in `currency`, or with one that isn't a number, are left out.
+fn parse_prices(body: &Value, currency: &str) -> HashMap<String, Price> {
+    let entries = match body.as_object() {
+        Some(entries) => entries,
+        None => return HashMap::new(),
+    };
+    entries.iter()
+        .filter_map(|(id, prices)| {
+            let price = match &prices[currency] {
+                Value::Number(number) => Price::parse(&number.to_string())?,
+                _ => return None,
+            };
+            Some((id.to_lowercase(), price))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let price = |text| Price::parse(text).map(|p| p.to_string());
+        assert_eq!(price("3456.78").unwrap(), "3456.78");
+        assert_eq!(price("0.000012").unwrap(), "0.000012");
+        assert_eq!(price("1.5e-7").unwrap(), "0.00000015");
+        assert_eq!(price("2E3").unwrap(), "2000");
+        assert_eq!(price("12").unwrap(), "12");
+        assert_eq!(price(".5").unwrap(), "0.5");
+        for invalid in ["", ".", "-1", "1.2.3", "abc", "1e", "0x10"] {
+            assert_eq!(Price::parse(invalid), None, "{}", invalid);
+        }
+
+        let json = serde_json::to_string(&Price::parse("3456.78").unwrap()).unwrap();
+        assert_eq!(json, r#""3456.78""#);
+        assert_eq!(serde_json::from_str::<Price>(&json).unwrap(), Price::parse("3456.78").unwrap());
+    }
+
+    #[test]
+    fn test_value_cents() {
+        let eth = Price::parse("3456.78").unwrap();
+        assert_eq!(eth.value_cents(1_500_000_000_000_000_000, 18), Some(518517));
+        assert_eq!(eth.value_cents(1, 18), Some(0));
+        assert_eq!(format_cents(518517, "usd"), "5185.17 USD");
+        assert_eq!(format_cents(5, "eur"), "0.05 EUR");
+
+        // a product past a u128 still comes out exact
+        let whale = Price::parse("123456789.123456789").unwrap();
+        assert_eq!(whale.value_cents(1_000_000_000 * 10u128.pow(18), 18), Some(12_345_678_912_345_678_900));
+        assert_eq!(Price::parse("1e30").unwrap().value_cents(u128::MAX, 0), None);
+        assert_eq!(scaled_product(u128::MAX, 10, 1), Some(u128::MAX));
+
+        // tokens carry their own decimals: 8 as WBTC has, and none at all
+        let wbtc = Price::parse("65432.1").unwrap();
+        assert_eq!(wbtc.value_cents(150_000_000, 8), Some(9_814_815));
+        assert_eq!(wbtc.value_cents(1, 8), Some(0));
+        let ticket = Price::parse("0.25").unwrap();
+        assert_eq!(ticket.value_cents(3, 0), Some(75));
+        assert_eq!(Price::parse("12.345").unwrap().value_cents(7, 0), Some(8641));
+    }
+
+    #[test]
+    fn test_parse_prices() {
+        let body = serde_json::json!({
+            "ethereum": { "usd": 3456.78, "eur": 3100 },
+            "worthless": { "eur": 1 },
+            "broken": { "usd": "a lot" },
+        });
+        let prices = parse_prices(&body, "usd");
+        assert_eq!(prices.len(), 1);
+        assert_eq!(prices["ethereum"].to_string(), "3456.78");
+        assert_eq!(parse_prices(&body, "eur")["ethereum"].to_string(), "3100");
+        assert!(parse_prices(&Value::Null, "usd").is_empty());
+
+        // token prices come by contract, however the API writes it
+        let body = serde_json::json!({ "0xA0b86991c6218b36c1d19d4a2e9eB0cE3606eB48": { "usd": 0.9998 } });
+        assert_eq!(parse_prices(&body, "usd")["0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"].to_string(), "0.9998");
+    }
+
+    #[test]
+    fn test_cache() {
+        let mut cache = PriceCache::default();
+        let price = Price::parse("2").unwrap();
+        cache.insert("ethereum", "usd", price, 1_000);
+        assert_eq!(cache.fresh("ethereum", "usd", 1_299, 300), Some(price));
+        assert_eq!(cache.fresh("ethereum", "usd", 1_300, 300), None);
+        assert_eq!(cache.fresh("ethereum", "eur", 1_000, 300), None);
+
+        // a newer lookup replaces the old one
+        cache.insert("ethereum", "usd", Price::parse("3").unwrap(), 2_000);
+        assert_eq!(cache.prices.len(), 1);
+        assert_eq!(cache.fresh("ethereum", "usd", 2_000, 300), Price::parse("3"));
+        assert_eq!(native_coin(1), Some("ethereum"));
+        assert_eq!(native_coin(11155111), None);
+
+        // tokens share the cache, by platform and contract
+        let usdc = token_key("ethereum", "0xA0b86991c6218b36c1d19d4a2e9eB0cE3606eB48");
+        assert_eq!(usdc, "ethereum:0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48");
+        cache.insert(&usdc, "usd", Price::parse("1").unwrap(), 2_000);
+        assert_eq!(cache.fresh(&usdc, "usd", 2_000, 300), Price::parse("1"));
+        assert_eq!(cache.fresh("ethereum", "usd", 2_000, 300), Price::parse("3"));
+        assert_eq!(token_platform(10), Some("optimistic-ethereum"));
+        assert_eq!(token_platform(11155111), None);
+    }
+}
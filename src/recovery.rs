@@ -0,0 +1,116 @@
+use rand::seq::index::sample;
+use serde::{Serialize, Deserialize};
+
+use crate::crypto::{ct_eq, salted_hash};
+
+/// Number of words asked in a backup quiz
+pub const QUIZ_WORDS: usize = 3;
+const SECONDS_PER_MONTH: u64 = 30 * 86400;
+
+/// Lets the user prove they still have their recovery phrase without the phrase being stored.
+/// Each word is kept as a hash salted with a secret that is only available once the wallet is
+/// unlocked, so the file alone can't be used to guess words.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BackupCheck {
+    /// keccak256(secret || position || word) for every word of the phrase
+    word_hashes: Vec<Vec<u8>>,
+    /// When the check data was created
+    pub created_at: u64,
+    /// When the user last passed a backup quiz
+    pub last_verified: Option<u64>,
+    /// When the user last dismissed a reminder
+    pub dismissed_at: Option<u64>,
+}
+
+impl BackupCheck {
+    pub fn new(phrase: &str, secret: &[u8], now: u64) -> Self {
+        BackupCheck {
+            word_hashes: phrase.split_whitespace()
+                .enumerate()
+                .map(|(i, word)| word_hash(secret, i, word).to_vec())
+                .collect(),
+            created_at: now,
+            last_verified: None,
+            dismissed_at: None,
+        }
+    }
+
+    pub fn word_count(&self) -> usize {
+        self.word_hashes.len()
+    }
+
+    /// Checks the word at the zero-based position. Case and surrounding whitespace are ignored.
+    pub fn check_word(&self, secret: &[u8], position: usize, word: &str) -> bool {
+        match self.word_hashes.get(position) {
+            Some(hash) => ct_eq(&word_hash(secret, position, &word.trim().to_lowercase()), hash),
+            None => false,
+        }
+    }
+
+    /// Returns true if a reminder should be shown: more than `interval_months` have passed since
+    /// the last successful check, dismissal, or creation if neither happened.
+    pub fn reminder_due(&self, now: u64, interval_months: u32) -> bool {
+        let last = [Some(self.created_at), self.last_verified, self.dismissed_at]
+            .iter()
+            .flatten()
+            .copied()
+            .max()
+            .unwrap();
+        now.saturating_sub(last) > interval_months as u64 * SECONDS_PER_MONTH
+    }
+}
+
+/// Picks distinct random zero-based word positions for a quiz, in ascending order
+pub fn quiz_positions(word_count: usize) -> Vec<usize> {
+    let mut positions = sample(&mut rand::thread_rng(), word_count, QUIZ_WORDS.min(word_count)).into_vec();
+    positions.sort_unstable();
+    positions
+}
+
+fn word_hash(secret: &[u8], position: usize, word: &str) -> [u8; 32] {
+    let mut salt = secret.to_vec();
+    salt.extend_from_slice(&(position as u32).to_be_bytes());
+    salted_hash(&salt, word)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PHRASE: &str = "test test test test test test test test test test test junk";
+    const SECRET: [u8; 32] = [9u8; 32];
+
+    #[test]
+    fn test_check_word() {
+        let check = BackupCheck::new(PHRASE, &SECRET, 0);
+        assert_eq!(check.word_count(), 12);
+        assert!(check.check_word(&SECRET, 0, "test"));
+        assert!(check.check_word(&SECRET, 11, " JUNK "));
+        assert!(!check.check_word(&SECRET, 11, "test"));
+        assert!(!check.check_word(&SECRET, 12, "junk"));
+        // the wrong secret must not verify anything
+        assert!(!check.check_word(&[0u8; 32], 0, "test"));
+    }
+
+    #[test]
+    fn test_reminder_due() {
+        let mut check = BackupCheck::new(PHRASE, &SECRET, 1000);
+        let six_months = 6 * SECONDS_PER_MONTH;
+        assert!(!check.reminder_due(1000 + six_months, 6));
+        assert!(check.reminder_due(1001 + six_months, 6));
+
+        check.last_verified = Some(5000);
+        assert!(!check.reminder_due(1001 + six_months, 6));
+        check.dismissed_at = Some(9000);
+        assert!(!check.reminder_due(5001 + six_months, 6));
+        assert!(check.reminder_due(9001 + six_months, 6));
+    }
+
+    #[test]
+    fn test_quiz_positions() {
+        let positions = quiz_positions(12);
+        assert_eq!(positions.len(), QUIZ_WORDS);
+        assert!(positions.windows(2).all(|w| w[0] < w[1]));
+        assert!(positions.iter().all(|p| *p < 12));
+    }
+}
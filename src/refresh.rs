@@ -0,0 +1,304 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde::{Serialize, Deserialize};
+
+use crate::rpc::{EthRpc, HttpRpc};
+use crate::settings::{RefreshSettings, Settings};
+use crate::utils::read_user_input;
+
+/// The shortest interval, so a typo can't hammer a public node
+pub const MIN_INTERVAL_SECS: u64 = 5;
+/// The time between blocks on Ethereum and its testnets. Without a websocket subscription to new
+/// blocks, refreshing every block polls for them this often.
+pub const BLOCK_SECS: u64 = 12;
+/// How often the block watcher looks for a change to the settings
+const TICK: Duration = Duration::from_secs(1);
+/// How often the receipt of a transaction being waited on is asked for when nothing else is set.
+/// Waiting is always asked for, so it polls even when refreshing is manual.
+const DEFAULT_RECEIPT_POLL: Duration = Duration::from_secs(4);
+/// The intervals offered in the menu
+const CHOICES: [RefreshInterval; 5] = [
+    RefreshInterval::Manual,
+    RefreshInterval::EveryBlock,
+    RefreshInterval::Every(15),
+    RefreshInterval::Every(30),
+    RefreshInterval::Every(60),
+];
+
+/// When each refresh was last tried this session, by what it refreshes, so one that fails isn't
+/// tried again every time a menu is shown
+static ATTEMPTS: Mutex<Vec<(String, u64)>> = Mutex::new(Vec::new());
+/// The block each refresh was last tried at, by what it refreshes
+static BLOCKS_SEEN: Mutex<Vec<(String, u64)>> = Mutex::new(Vec::new());
+/// The refresh settings in use: read from the settings file when first needed, and changed by
+/// `manage`
+static CURRENT: Mutex<Option<RefreshSettings>> = Mutex::new(None);
+/// Counts the changes to `CURRENT`, so the block watcher knows to start over
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+/// The newest block the block watcher saw on the active network, or 0 before it saw one
+static LATEST_BLOCK: AtomicU64 = AtomicU64::new(0);
+
+/// How often live data, such as the balance shown above the menu, is fetched again without
+/// being asked for
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(try_from = "String", into = "String")]
+pub enum RefreshInterval {
+    /// Only when the user asks, as by viewing the balance
+    #[default]
+    Manual,
+    /// Once the data is this many seconds old, when a menu is next shown
+    Every(u64),
+    /// Once a block was mined since the data was fetched, when a menu is next shown
+    EveryBlock,
+}
+
+impl RefreshInterval {
+    /// "manual", "block", or a number of seconds such as "30" or "30s", of at least
+    /// `MIN_INTERVAL_SECS`
+    pub fn parse(text: &str) -> Result<RefreshInterval, String> {
+        let text = text.trim();
+        if text.eq_ignore_ascii_case("manual") {
+            return Ok(RefreshInterval::Manual);
+        }
+        if text.eq_ignore_ascii_case("block") {
+            return Ok(RefreshInterval::EveryBlock);
+        }
+        match text.strip_suffix('s').unwrap_or(text).parse::<u64>() {
+            Ok(secs) if secs >= MIN_INTERVAL_SECS => Ok(RefreshInterval::Every(secs)),
+            Ok(_) => Err(format!("Refreshing can't be more often than every {} seconds", MIN_INTERVAL_SECS)),
+            Err(_) => Err(format!("{} is not \"manual\", \"block\" or a number of seconds", text)),
+        }
+    }
+
+    /// How long fetched data is used before a view that needs it fetches it again. With manual
+    /// refreshing every view asks again, since asking is the refresh.
+    pub fn max_age_secs(&self) -> u64 {
+        match self {
+            RefreshInterval::Manual => 0,
+            RefreshInterval::Every(secs) => *secs,
+            RefreshInterval::EveryBlock => BLOCK_SECS,
+        }
+    }
+}
+
+impl fmt::Display for RefreshInterval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RefreshInterval::Manual => write!(f, "manual only"),
+            RefreshInterval::Every(secs) => write!(f, "every {} seconds", secs),
+            RefreshInterval::EveryBlock => write!(f, "every block"),
+        }
+    }
+}
+
+impl TryFrom<String> for RefreshInterval {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        RefreshInterval::parse(&text)
+    }
+}
+
+impl From<RefreshInterval> for String {
+    fn from(interval: RefreshInterval) -> String {
+        match interval {
+            RefreshInterval::Manual => String::from("manual"),
+            RefreshInterval::Every(secs) => secs.to_string(),
+            RefreshInterval::EveryBlock => String::from("block"),
+        }
+    }
+}
+
+impl RefreshSettings {
+    /// How often the receipt of a transaction being waited on is asked for: the override, or
+    /// else the interval
+    pub fn receipt_poll_interval(&self) -> Duration {
+        match (self.receipt_poll_secs, self.interval) {
+            (Some(secs), _) => Duration::from_secs(secs.max(1)),
+            (None, RefreshInterval::Every(secs)) => Duration::from_secs(secs),
+            (None, RefreshInterval::EveryBlock) => Duration::from_secs(BLOCK_SECS),
+            (None, RefreshInterval::Manual) => DEFAULT_RECEIPT_POLL,
+        }
+    }
+}
+
+/// The refresh settings in use. They are read from the settings file once, and `manage` changes
+/// them for the rest of the run as well as in the file.
+pub fn current() -> RefreshSettings {
+    *CURRENT.lock().unwrap_or_else(|e| e.into_inner()).get_or_insert_with(|| Settings::load().refresh)
+}
+
+/// Makes `settings` the ones in use, restarting the block watcher
+fn set(settings: RefreshSettings) {
+    *CURRENT.lock().unwrap_or_else(|e| e.into_inner()) = Some(settings);
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Whether `key`, last fetched at `fetched_at`, is due to be refreshed at `now` under
+/// `interval`. A refresh tried within the interval counts as one, whether or not it worked, and
+/// a due one is recorded as tried. Refreshing every block goes by the blocks the block watcher
+/// saw, or by `BLOCK_SECS` while it hasn't seen one.
+pub fn take_due(key: &str, fetched_at: Option<u64>, now: u64, interval: RefreshInterval) -> bool {
+    let secs = match interval {
+        RefreshInterval::Manual => return false,
+        RefreshInterval::Every(secs) => secs,
+        RefreshInterval::EveryBlock => match LATEST_BLOCK.load(Ordering::SeqCst) {
+            0 => BLOCK_SECS,
+            block => return take_new_block(key, block),
+        },
+    };
+    let mut attempts = ATTEMPTS.lock().unwrap_or_else(|e| e.into_inner());
+    let tried = attempts.iter().find(|(k, _)| k == key).map(|(_, at)| *at);
+    let last = fetched_at.max(tried);
+    if last.is_some_and(|last| now.saturating_sub(last) < secs) {
+        return false;
+    }
+    attempts.retain(|(k, _)| k != key);
+    attempts.push((String::from(key), now));
+    true
+}
+
+/// Whether `key` wasn't refreshed at `block` yet, recording that it is
+fn take_new_block(key: &str, block: u64) -> bool {
+    let mut seen = BLOCKS_SEEN.lock().unwrap_or_else(|e| e.into_inner());
+    if seen.iter().any(|(k, at)| k == key && *at == block) {
+        return false;
+    }
+    seen.retain(|(k, _)| k != key);
+    seen.push((String::from(key), block));
+    true
+}
+
+/// Asks the active network's node for its newest block every `BLOCK_SECS` while refreshing every
+/// block is chosen, for `take_due`. It starts over whenever the settings in use change, and stops
+/// when dropped.
+pub struct BlockWatcher {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl BlockWatcher {
+    pub fn start() -> BlockWatcher {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let mut generation = None;
+                let mut next_poll = Instant::now();
+                while !stop.load(Ordering::SeqCst) {
+                    let changed = GENERATION.load(Ordering::SeqCst);
+                    if generation != Some(changed) {
+                        generation = Some(changed);
+                        next_poll = Instant::now();
+                        LATEST_BLOCK.store(0, Ordering::SeqCst);
+                    }
+                    if current().interval == RefreshInterval::EveryBlock && Instant::now() >= next_poll {
+                        // a node that doesn't answer leaves refreshing to the clock
+                        LATEST_BLOCK.store(HttpRpc::active().block_number().unwrap_or(0), Ordering::SeqCst);
+                        next_poll = Instant::now() + Duration::from_secs(BLOCK_SECS);
+                    }
+                    thread::park_timeout(TICK);
+                }
+            })
+        };
+        BlockWatcher { stop, thread: Some(thread) }
+    }
+}
+
+impl Drop for BlockWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+        LATEST_BLOCK.store(0, Ordering::SeqCst);
+    }
+}
+
+/// Shows the refresh interval and lets the user pick another, which is saved to the settings
+pub fn manage() {
+    let settings = current();
+    println!("Live data is refreshed {}", settings.interval);
+    println!("Transactions being waited on are checked every {} seconds", settings.receipt_poll_interval().as_secs());
+    for (i, choice) in CHOICES.iter().enumerate() {
+        println!("{}) Refresh {}", i + 1, choice);
+    }
+    println!("Enter a choice, or a number of seconds of at least {} (or press enter to go back): ", MIN_INTERVAL_SECS);
+    let input = read_user_input();
+    let interval = match input.parse::<usize>() {
+        _ if input.is_empty() => return,
+        Ok(i) if (1..=CHOICES.len()).contains(&i) => CHOICES[i - 1],
+        _ => match RefreshInterval::parse(&input) {
+            Ok(interval) => interval,
+            Err(e) => return println!("{}", e),
+        },
+    };
+    let settings = RefreshSettings { interval, ..settings };
+    match Settings::save_section("refresh", &settings) {
+        Ok(()) => println!("Live data is now refreshed {}", interval),
+        Err(e) => println!("{}. It is refreshed {} until the wallet is closed.", e, interval),
+    }
+    set(settings);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(RefreshInterval::parse("manual"), Ok(RefreshInterval::Manual));
+        assert_eq!(RefreshInterval::parse(" 30s "), Ok(RefreshInterval::Every(30)));
+        assert_eq!(RefreshInterval::parse("60"), Ok(RefreshInterval::Every(60)));
+        assert_eq!(RefreshInterval::parse("1").unwrap_err(), "Refreshing can't be more often than every 5 seconds");
+        assert!(RefreshInterval::parse("often").is_err());
+        assert_eq!(RefreshInterval::parse("Block"), Ok(RefreshInterval::EveryBlock));
+        assert_eq!(serde_json::to_string(&RefreshInterval::EveryBlock).unwrap(), r#""block""#);
+
+        let settings: RefreshSettings = serde_json::from_str(r#"{"interval":"15"}"#).unwrap();
+        assert_eq!(settings.interval, RefreshInterval::Every(15));
+        assert_eq!(serde_json::to_string(&RefreshInterval::Manual).unwrap(), r#""manual""#);
+        assert!(serde_json::from_str::<RefreshSettings>(r#"{"interval":"2"}"#).is_err());
+    }
+
+    #[test]
+    fn test_receipt_poll_interval() {
+        let manual = RefreshSettings::default();
+        assert_eq!(manual.interval, RefreshInterval::Manual);
+        assert_eq!(manual.receipt_poll_interval(), DEFAULT_RECEIPT_POLL);
+        let every = RefreshSettings { interval: RefreshInterval::Every(15), receipt_poll_secs: None };
+        assert_eq!(every.receipt_poll_interval(), Duration::from_secs(15));
+        // the poller's own setting wins
+        assert_eq!(RefreshSettings { receipt_poll_secs: Some(2), ..every }.receipt_poll_interval(), Duration::from_secs(2));
+        assert_eq!(RefreshInterval::Manual.max_age_secs(), 0);
+        assert_eq!(RefreshInterval::Every(30).max_age_secs(), 30);
+        let every_block = RefreshSettings { interval: RefreshInterval::EveryBlock, receipt_poll_secs: None };
+        assert_eq!(every_block.receipt_poll_interval(), Duration::from_secs(BLOCK_SECS));
+    }
+
+    #[test]
+    fn test_take_due() {
+        let every = RefreshInterval::Every(30);
+        assert!(!take_due("test manual", None, 1_000, RefreshInterval::Manual));
+
+        // never fetched, so due at once, and then not until the interval has passed
+        assert!(take_due("test never", None, 1_000, every));
+        assert!(!take_due("test never", None, 1_029, every));
+        assert!(take_due("test never", None, 1_030, every));
+
+        // measured from the fetch, or from the last try if that was later
+        assert!(!take_due("test fetched", Some(990), 1_000, every));
+        assert!(take_due("test fetched", Some(900), 1_000, every));
+        assert!(!take_due("test fetched", Some(900), 1_010, every));
+
+        // once per block the watcher saw
+        assert!(take_new_block("test block", 100));
+        assert!(!take_new_block("test block", 100));
+        assert!(take_new_block("test block", 101));
+    }
+}
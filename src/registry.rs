@@ -0,0 +1,232 @@
+use std::fs;
+
+use serde::{Serialize, Deserialize};
+
+use crate::data_dir;
+use crate::storage::{backup_path, Wallet};
+
+const REGISTRY_PATH: &str = "wallets.json";
+/// Where the files of wallets created since the registry was added are kept
+const WALLET_DIR: &str = "wallets";
+/// The single wallet file written before the registry existed
+const LEGACY_WALLET_FILE: &str = "userdata.txt";
+/// The name given to a wallet found in the legacy file
+const LEGACY_WALLET_NAME: &str = "default";
+
+/// The wallets on this computer, by name
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Registry {
+    pub wallets: Vec<WalletEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct WalletEntry {
+    pub name: String,
+    /// The wallet file, relative to the data directory
+    pub file: String,
+    /// When the wallet was created, if that is known. An encrypted file only tells once it is
+    /// unlocked, so it is recorded here.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+}
+
+impl Registry {
+    /// Loads the registry. Without one, a wallet in the legacy userdata.txt is registered as
+    /// "default" so it can still be opened.
+    pub fn load() -> Result<Registry, String> {
+        let path = data_dir::path(REGISTRY_PATH);
+        if !path.exists() {
+            let registry = Registry::legacy(data_dir::path(LEGACY_WALLET_FILE).exists());
+            if !registry.is_empty() {
+                registry.save()?;
+            }
+            return Ok(registry);
+        }
+        let data = fs::read_to_string(&path).map_err(|e| format!("Error reading {}: {}", path.display(), e))?;
+        serde_json::from_str(&data).map_err(|e| format!("Malformed {}: {}", path.display(), e))
+    }
+
+    /// The registry to start with when there is none, given whether the legacy file exists
+    fn legacy(legacy_file_exists: bool) -> Registry {
+        let mut registry = Registry::default();
+        if legacy_file_exists {
+            registry.wallets.push(WalletEntry { name: String::from(LEGACY_WALLET_NAME), file: String::from(LEGACY_WALLET_FILE), created_at: None });
+        }
+        registry
+    }
+
+    /// Writes the registry next to the old one and renames it over, like `Wallet::store`
+    fn save(&self) -> Result<(), String> {
+        let path = data_dir::path(REGISTRY_PATH);
+        let temp_path = data_dir::path(&format!("{}.tmp", REGISTRY_PATH));
+        fs::write(&temp_path, serde_json::to_vec_pretty(self).unwrap())
+            .and_then(|()| fs::rename(&temp_path, &path))
+            .map_err(|e| format!("Error writing {}: {}", path.display(), e))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.wallets.is_empty()
+    }
+
+    pub fn find(&self, name: &str) -> Option<&WalletEntry> {
+        self.wallets.iter().find(|w| w.name == name)
+    }
+
+    /// Returns the path a wallet with this name should be stored at, registering the name if it
+    /// is new, along with when the wallet was created. An existing name keeps its file, so the
+    /// caller must confirm replacing it first.
+    pub fn register(&mut self, name: &str, created_at: Option<u64>) -> Result<String, String> {
+        if self.find(name).is_none() {
+            let wallet_dir = data_dir::path(WALLET_DIR);
+            fs::create_dir_all(&wallet_dir).map_err(|e| format!("Error creating {}: {}", wallet_dir.display(), e))?;
+            self.add(name, |file| data_dir::path(file).exists());
+        }
+        let entry = self.wallets.iter_mut().find(|w| w.name == name).unwrap();
+        entry.created_at = created_at;
+        let path = entry.path();
+        self.save()?;
+        Ok(path)
+    }
+
+    /// Lists wallet files in the data directory that aren't listed yet, such as ones copied in
+    /// by hand, under the names of their files, and fills in the creation dates that can be read
+    /// from the files. Returns the names of the wallets added.
+    pub fn scan(&mut self) -> Result<Vec<String>, String> {
+        let wallet_dir = data_dir::path(WALLET_DIR);
+        let mut files: Vec<String> = match fs::read_dir(&wallet_dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .filter(|file| file.ends_with(".txt"))
+                .map(|file| format!("{}/{}", WALLET_DIR, file))
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(format!("Error reading {}: {}", wallet_dir.display(), e)),
+        };
+        files.sort();
+        if data_dir::path(LEGACY_WALLET_FILE).exists() {
+            files.insert(0, String::from(LEGACY_WALLET_FILE));
+        }
+        let added = self.adopt(&files);
+        let mut changed = !added.is_empty();
+        for entry in self.wallets.iter_mut().filter(|w| w.created_at.is_none()) {
+            entry.created_at = Wallet::created_at_of(&entry.path());
+            changed |= entry.created_at.is_some();
+        }
+        if changed {
+            self.save()?;
+        }
+        Ok(added)
+    }
+
+    /// Adds the files no wallet is registered with, named after them. Returns the names given.
+    fn adopt(&mut self, files: &[String]) -> Vec<String> {
+        let mut added = vec![];
+        for file in files {
+            if self.wallets.iter().any(|w| w.file == *file) {
+                continue;
+            }
+            let stem = match file.as_str() {
+                LEGACY_WALLET_FILE => String::from(LEGACY_WALLET_NAME),
+                file => std::path::Path::new(file).file_stem().unwrap().to_string_lossy().into_owned(),
+            };
+            let name = (1..)
+                .map(|n| match n {
+                    1 => stem.clone(),
+                    n => format!("{} {}", stem, n),
+                })
+                .find(|name| self.find(name).is_none())
+                .unwrap();
+            self.wallets.push(WalletEntry { name: name.clone(), file: file.clone(), created_at: None });
+            added.push(name);
+        }
+        added
+    }
+
+    /// Adds a new name with a file no other wallet uses. `exists` tells whether a file is already
+    /// on disk, so a stray file is never overwritten either.
+    fn add(&mut self, name: &str, exists: impl Fn(&str) -> bool) -> String {
+        let stem = file_stem(name);
+        let taken = |file: &str| exists(file) || self.wallets.iter().any(|w| w.file == file);
+        let file = (1..)
+            .map(|n| match n {
+                1 => format!("{}/{}.txt", WALLET_DIR, stem),
+                n => format!("{}/{}-{}.txt", WALLET_DIR, stem, n),
+            })
+            .find(|file| !taken(file))
+            .unwrap();
+        self.wallets.push(WalletEntry { name: String::from(name), file: file.clone(), created_at: None });
+        file
+    }
+
+    /// Deletes a wallet's file and its backup, and forgets its name
+    pub fn delete(&mut self, name: &str) -> Result<(), String> {
+        let index = self.wallets.iter().position(|w| w.name == name).ok_or_else(|| format!("There is no wallet named '{}'", name))?;
+        let path = self.wallets[index].path();
+        for path in [backup_path(&path), path] {
+            match fs::remove_file(&path) {
+                Ok(()) => {},
+                // already gone, so only the name is left to remove
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+                Err(e) => return Err(format!("Error deleting {}: {}", path, e)),
+            }
+        }
+        self.wallets.remove(index);
+        self.save()
+    }
+}
+
+impl WalletEntry {
+    /// Where the wallet file is, in the data directory
+    pub fn path(&self) -> String {
+        data_dir::path(&self.file).to_string_lossy().into_owned()
+    }
+}
+
+/// A file name for a wallet name: lowercase letters and digits, with anything else as dashes
+pub(crate) fn file_stem(name: &str) -> String {
+    let stem: String = name.trim().chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .collect();
+    let stem = stem.trim_matches('-');
+    if stem.is_empty() { String::from("wallet") } else { String::from(stem) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_add() {
+        let mut registry = Registry::legacy(true);
+        assert_eq!(registry.find("default").unwrap().file, "userdata.txt");
+
+        assert_eq!(registry.add("Savings", |_| false), "wallets/savings.txt");
+        // a name that maps to a used file, and one whose file is already on disk
+        assert_eq!(registry.add("savings!", |_| false), "wallets/savings-2.txt");
+        assert_eq!(registry.add("Day to day", |file| file == "wallets/day-to-day.txt"), "wallets/day-to-day-2.txt");
+        assert_eq!(registry.wallets.len(), 4);
+        assert_eq!(registry.find("savings!").unwrap().file, "wallets/savings-2.txt");
+        assert!(Registry::legacy(false).is_empty());
+    }
+
+    #[test]
+    fn test_adopt() {
+        let mut registry = Registry::default();
+        registry.add("Savings", |_| false);
+        registry.add("wallet", |_| false);
+        let files = ["userdata.txt", "wallets/savings.txt", "wallets/cold.txt", "wallets/wallet-2.txt", "wallets/wallet.txt"].map(String::from);
+        assert_eq!(registry.adopt(&files), ["default", "cold", "wallet-2"]);
+        assert_eq!(registry.find("cold").unwrap().file, "wallets/cold.txt");
+        // a name already taken gets a number
+        assert_eq!(registry.adopt(&[String::from("wallets/sub/cold.txt")]), ["cold 2"]);
+        assert!(registry.adopt(&files).is_empty());
+    }
+
+    #[test]
+    fn test_file_stem() {
+        assert_eq!(file_stem(" Cold storage "), "cold-storage");
+        assert_eq!(file_stem("../../etc"), "etc");
+        assert_eq!(file_stem("Ümlaut"), "mlaut");
+        assert_eq!(file_stem("***"), "wallet");
+    }
+}
@@ -0,0 +1,296 @@
+use std::time::Duration;
+
+use bip32::secp256k1::ecdsa::{recoverable, signature::Signer, SigningKey};
+use serde_json::Value;
+
+use crate::eip7702::SignedAuthorization;
+use crate::erc20::{self, decode_uint256, encode_address};
+use crate::rpc::{self, EthRpc, TransactionFields};
+use crate::settings::Settings;
+
+/// Marks what a sponsored call's signature covers as EIP-191 data for an intended validator, the
+/// account itself, so it can't be replayed as a transaction or for another account
+const CALL_PREFIX: [u8; 2] = [0x19, 0x00];
+
+/// A call the account signs for a relayer to make on its behalf. The relayer sends a type 4
+/// (EIP-7702) transaction to the account, which carries the account's authorization for the
+/// delegate contract from the settings and calls its
+/// `execute(address to, uint256 value, bytes data, uint256 nonce, bytes signature)`. The delegate
+/// checks the signature against the account and makes the call from it, so the relayer pays the
+/// gas and can change nothing that was signed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SponsoredCall {
+    pub chain_id: u64,
+    /// The account the call is made from
+    pub account: [u8; 20],
+    pub to: [u8; 20],
+    pub value: u128,
+    pub data: Vec<u8>,
+    /// The delegate's count of the account's sponsored calls, which keeps each from being made
+    /// twice
+    pub nonce: u128,
+}
+
+impl SponsoredCall {
+    /// Signs the call with the account's secp256k1 secret key, as `r || s || v` for `ecrecover`
+    pub fn sign(&self, secret_key: &[u8]) -> Result<Vec<u8>, String> {
+        let key = SigningKey::from_bytes(secret_key).map_err(|e| format!("Invalid private key: {}", e))?;
+        let signature: recoverable::Signature = key.sign(&self.message());
+        let bytes = signature.as_ref();
+        Ok([&bytes[..64], &[27 + bytes[64]]].concat())
+    }
+
+    /// The prefix and the account, then the chain id, nonce, target, value and keccak256 of the
+    /// data as words, which the signature covers the keccak256 of
+    fn message(&self) -> Vec<u8> {
+        [
+            &CALL_PREFIX[..],
+            &self.account,
+            &uint_word(self.chain_id as u128),
+            &uint_word(self.nonce),
+            &encode_address(&self.to),
+            &uint_word(self.value),
+            &crate::crypto::keccak256(&self.data),
+        ].concat()
+    }
+
+    /// The call data of the delegate's `execute` with this call and its signature, which is what
+    /// the relayer's transaction must carry
+    pub fn execute_data(&self, signature: &[u8]) -> Vec<u8> {
+        // five head words, then the data and the signature, each as its length and padded bytes
+        let data_offset = 5 * 32;
+        let signature_offset = data_offset + 32 + padded_len(self.data.len());
+        [
+            &erc20::selector("execute(address,uint256,bytes,uint256,bytes)")[..],
+            &encode_address(&self.to),
+            &uint_word(self.value),
+            &uint_word(data_offset as u128),
+            &uint_word(self.nonce),
+            &uint_word(signature_offset as u128),
+            &encode_bytes(&self.data),
+            &encode_bytes(signature),
+        ].concat()
+    }
+}
+
+/// The delegate's nonce for the account's next sponsored call: its `nonce()`, or 0 while the
+/// account has no code, as before its first call
+pub fn delegate_nonce(rpc: &dyn EthRpc, account: &str) -> Result<u128, String> {
+    match rpc.eth_call(account, &erc20::selector("nonce()"))? {
+        result if result.is_empty() => Ok(0),
+        result => decode_uint256(&result).map_err(|e| format!("Invalid nonce from the delegate: {}", e)),
+    }
+}
+
+/// Checks that the transaction the relayer broadcast is the one the account signed for: sent to
+/// the account with no value of its own, carrying its authorization, and calling `execute` with
+/// the signed call. Its `from` is the relayer, which paid for it, so it must not be the account.
+pub fn verify(fields: &TransactionFields, call: &SponsoredCall, authorization: &SignedAuthorization, signature: &[u8]) -> Result<(), String> {
+    if fields.from == call.account {
+        return Err(String::from("it was sent by this account rather than the relayer"));
+    }
+    if fields.to != Some(call.account) {
+        return Err(String::from("it isn't sent to this account"));
+    }
+    if fields.value != 0 {
+        return Err(format!("it sends {} wei of the relayer's own", fields.value));
+    }
+    if !fields.authorization_list.contains(authorization) {
+        return Err(format!("it doesn't carry this account's authorization for nonce {}", authorization.authorization.nonce));
+    }
+    if fields.input != call.execute_data(signature) {
+        return Err(String::from("it doesn't make the call that was signed"));
+    }
+    Ok(())
+}
+
+fn uint_word(value: u128) -> [u8; 32] {
+    let mut word = [0; 32];
+    word[16..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+fn padded_len(len: usize) -> usize {
+    len.div_ceil(32) * 32
+}
+
+/// A `bytes` argument: its length as a word, then the bytes padded to whole words
+fn encode_bytes(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = uint_word(bytes.len() as u128).to_vec();
+    encoded.extend_from_slice(bytes);
+    encoded.resize(32 + padded_len(bytes.len()), 0);
+    encoded
+}
+
+/// Submits a signed sponsored call to a relayer that pays its gas.
+///
+/// The relayer API is a single endpoint:
+/// - `POST <url>` with header `X-Api-Key: <key>` and body
+///   `{ "chainId": <id>, "from": "0x..", "authorizationList": [<authorization>], "data": "0x.." }`,
+///   asking for a type 4 transaction to `from` with the authorization and call data
+/// - it answers with the hash of the transaction it broadcast, as `{ "txHash": "0x.." }`
+///   (`hash` and a JSON-RPC style `result` are accepted too)
+///
+/// Returns the transaction hash claimed by the relayer. It is given as long to answer as the node
+/// is in the network settings.
+pub fn submit(url: &str, api_key: &str, from: &str, authorization: &SignedAuthorization, data: &[u8]) -> Result<String, String> {
+    let network = Settings::load().network;
+    let agent = rpc::agent(Duration::from_secs(network.connect_timeout_secs), Duration::from_secs(network.read_timeout_secs));
+    let resp = agent.post(url)
+        .set("Content-Type", "application/json")
+        .set("X-Api-Key", api_key)
+        .send_json(ureq::json!({
+            "chainId": authorization.authorization.chain_id,
+            "from": from,
+            "authorizationList": [authorization.to_json()],
+            "data": String::from("0x") + &hex::encode(data),
+        }));
+
+    match resp {
+        Ok(r) => {
+            let body: Value = r.into_json().map_err(|e| format!("Relayer returned invalid JSON: {}", e))?;
+            parse_submit_response(&body)
+        },
+        Err(ureq::Error::Status(code, r)) => {
+            Err(format!("Relayer rejected the request ({}): {}", code, r.into_string().unwrap_or_default()))
+        },
+        Err(e) => Err(format!("Could not reach the relayer: {}", e)),
+    }
+}
+
+/// Extracts and validates the transaction hash from a relayer response
+pub fn parse_submit_response(body: &Value) -> Result<String, String> {
+    if let Some(error) = body.get("error") {
+        return Err(format!("Relayer error: {}", error));
+    }
+
+    let hash = ["txHash", "hash", "result"].iter()
+        .find_map(|key| body.get(key).and_then(Value::as_str))
+        .ok_or_else(|| String::from("Relayer response has no transaction hash"))?;
+
+    if is_valid_tx_hash(hash) {
+        Ok(hash.to_lowercase())
+    } else {
+        Err(format!("Relayer returned a malformed transaction hash: {}", hash))
+    }
+}
+
+/// Returns true for a 0x-prefixed 32 byte hex string
+pub fn is_valid_tx_hash(hash: &str) -> bool {
+    match hash.strip_prefix("0x") {
+        Some(h) => h.len() == 64 && h.chars().all(|c| c.is_ascii_hexdigit()),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::eip7702::Authorization;
+    use crate::rpc::mock::MockRpc;
+    use crate::utils;
+
+    const HASH: &str = "0x88df016429689c079f3b2f6ad39fa052532c56795b733da78a91ebe6a713944b";
+
+    #[test]
+    fn test_parse_submit_response() {
+        assert_eq!(parse_submit_response(&ureq::json!({ "txHash": HASH })).unwrap(), HASH);
+        assert_eq!(parse_submit_response(&ureq::json!({ "hash": HASH.to_uppercase().replace("0X", "0x") })).unwrap(), HASH);
+        assert_eq!(parse_submit_response(&ureq::json!({ "jsonrpc": "2.0", "result": HASH })).unwrap(), HASH);
+    }
+
+    #[test]
+    fn test_parse_submit_response_failures() {
+        assert!(parse_submit_response(&ureq::json!({ "error": "out of credits" })).is_err());
+        assert!(parse_submit_response(&ureq::json!({ "status": "queued" })).is_err());
+        assert!(parse_submit_response(&ureq::json!({ "txHash": "0x1234" })).is_err());
+    }
+
+    fn call() -> SponsoredCall {
+        SponsoredCall {
+            chain_id: 11155111,
+            account: hex::decode("f39fd6e51aad88f6f4ce6ab8827279cfffb92266").unwrap().try_into().unwrap(),
+            to: hex::decode("70997970c51812dc3a010c7d01b50e0d17dc79c8").unwrap().try_into().unwrap(),
+            value: 1_000_000_000_000_000,
+            data: vec![],
+            nonce: 2,
+        }
+    }
+
+    #[test]
+    fn test_execute_data() {
+        let call = SponsoredCall { data: vec![0xab; 33], ..call() };
+        let data = call.execute_data(&[0xcd; 65]);
+        assert_eq!(data[..4], erc20::selector("execute(address,uint256,bytes,uint256,bytes)"));
+        let word = |i: usize| &data[4 + 32 * i..4 + 32 * (i + 1)];
+        assert_eq!(word(0), encode_address(&call.to));
+        assert_eq!(word(1), uint_word(call.value));
+        assert_eq!(word(2), uint_word(160));
+        assert_eq!(word(3), uint_word(2));
+        // the data takes a length word and two padded words, so the signature starts after them
+        assert_eq!(word(4), uint_word(160 + 96));
+        assert_eq!(word(5), uint_word(33));
+        assert_eq!(word(7)[..1], [0xab]);
+        assert_eq!(word(7)[1..], [0; 31]);
+        assert_eq!(word(8), uint_word(65));
+        // and 65 signature bytes take three words
+        assert_eq!(data.len(), 4 + 32 * 12);
+    }
+
+    #[test]
+    fn test_sign_call() {
+        let secret = utils::parse_private_key("0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80").unwrap();
+        let signature = call().sign(secret.as_slice()).unwrap();
+        assert_eq!(signature.len(), 65);
+        assert!(signature[64] == 27 || signature[64] == 28);
+        // anything signed changes the signature
+        assert_ne!(SponsoredCall { nonce: 3, ..call() }.sign(secret.as_slice()).unwrap(), signature);
+        assert_ne!(SponsoredCall { chain_id: 1, ..call() }.sign(secret.as_slice()).unwrap(), signature);
+    }
+
+    #[test]
+    fn test_delegate_nonce() {
+        let rpc = MockRpc::new(1);
+        let account = "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266";
+        assert_eq!(delegate_nonce(&rpc, account), Ok(0));
+        rpc.contract(account, &erc20::selector("nonce()"), uint_word(4).to_vec());
+        assert_eq!(delegate_nonce(&rpc, account), Ok(4));
+        rpc.contract(account, &erc20::selector("nonce()"), vec![1]);
+        assert!(delegate_nonce(&rpc, account).is_err());
+    }
+
+    #[test]
+    fn test_verify() {
+        let secret = utils::parse_private_key("0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80").unwrap();
+        let call = call();
+        let signature = call.sign(secret.as_slice()).unwrap();
+        let authorization = Authorization { chain_id: call.chain_id, address: [0x5f; 20], nonce: 7 }.sign(secret.as_slice()).unwrap();
+        let relayed = TransactionFields {
+            from: [0x11; 20],
+            to: Some(call.account),
+            nonce: 40,
+            value: 0,
+            input: call.execute_data(&signature),
+            authorization_list: vec![authorization.clone()],
+        };
+        assert_eq!(verify(&relayed, &call, &authorization, &signature), Ok(()));
+
+        let fails = |fields: TransactionFields| verify(&fields, &call, &authorization, &signature).unwrap_err();
+        assert!(fails(TransactionFields { from: call.account, ..relayed.clone() }).contains("sent by this account"));
+        assert!(fails(TransactionFields { to: Some(call.to), ..relayed.clone() }).contains("isn't sent to this account"));
+        assert!(fails(TransactionFields { value: 1, ..relayed.clone() }).contains("relayer's own"));
+        assert!(fails(TransactionFields { authorization_list: vec![], ..relayed.clone() }).contains("nonce 7"));
+        // a relayer that sends the funds elsewhere, or replays an older call, is caught
+        let elsewhere = SponsoredCall { to: [0x22; 20], ..call.clone() }.execute_data(&signature);
+        assert!(fails(TransactionFields { input: elsewhere, ..relayed.clone() }).contains("call that was signed"));
+        let replayed = SponsoredCall { nonce: 1, ..call.clone() }.execute_data(&signature);
+        assert!(fails(TransactionFields { input: replayed, ..relayed }).contains("call that was signed"));
+    }
+
+    #[test]
+    fn test_is_valid_tx_hash() {
+        assert!(is_valid_tx_hash(HASH));
+        assert!(!is_valid_tx_hash(&HASH[2..]));
+        assert!(!is_valid_tx_hash("0xzz8df016429689c079f3b2f6ad39fa052532c56795b733da78a91ebe6a71394"));
+    }
+}
@@ -0,0 +1,1155 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::audit;
+use crate::eip7702::{Authorization, SignedAuthorization};
+use crate::error_code::ErrorCode;
+use crate::fees::{FeeMarket, TRANSFER_GAS_LIMIT};
+use crate::journal::TransactionStatus;
+use crate::networks;
+use crate::refresh;
+use crate::settings::Settings;
+
+/// In the error of a call the node didn't answer in time
+const TIMED_OUT: &str = "the request timed out, check your connection or RPC endpoint";
+/// How many of the latest blocks fee suggestions are drawn from
+const FEE_HISTORY_BLOCKS: u64 = 20;
+/// The percentiles of each block's tips that the slow, average and fast suggestions follow
+const REWARD_PERCENTILES: [f64; 3] = [10.0, 50.0, 90.0];
+/// Between the explanation of a well-known node error and the node's own message
+const NODE_SAID: &str = " (the node said: ";
+/// What the errors nodes commonly return for a transaction mean, their error code, and what to do
+/// about them, by a part of their message. Nodes return most of them with the generic code
+/// -32000, so the message is all there is to go on.
+const EXPLANATIONS: [(&str, ErrorCode, &str); 6] = [
+    ("nonce too low", ErrorCode::NonceTooLow, "A transaction with this nonce was already mined. Query the balance to bring the account's nonce up to date, then send again."),
+    ("insufficient funds", ErrorCode::InsufficientFunds, "The account can't cover the amount plus the most the gas can cost. Send less, or lower the gas price or limit."),
+    ("replacement transaction underpriced", ErrorCode::Underpriced, "A pending transaction has the same nonce, and this one doesn't pay enough more to replace it. Raise the gas price by at least 10%, or wait for the pending one to be mined."),
+    ("gas too low", ErrorCode::GasTooLow, "The gas limit is below what the transaction needs. Raise the gas limit and send again."),
+    ("already known", ErrorCode::AlreadyKnown, "The node already has this transaction. It will be mined without sending it again."),
+    ("underpriced", ErrorCode::Underpriced, "The gas price is below what the node accepts. Raise the gas price and send again."),
+];
+
+/// The error object of a JSON-RPC response
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    /// What the error means and how to get past it, if it is a well-known one
+    pub fn explanation(&self) -> Option<&'static str> {
+        self.well_known().map(|(_, _, explanation)| *explanation)
+    }
+
+    /// The error code of a well-known error, or else `NODE_ERROR`
+    pub fn error_code(&self) -> ErrorCode {
+        self.well_known().map_or(ErrorCode::NodeError, |(_, code, _)| *code)
+    }
+
+    fn well_known(&self) -> Option<&'static (&'static str, ErrorCode, &'static str)> {
+        let message = self.message.to_lowercase();
+        EXPLANATIONS.iter().find(|(part, ..)| message.contains(part))
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.explanation() {
+            Some(explanation) => write!(f, "{}{}{})", explanation, NODE_SAID, self.message),
+            None => write!(f, "{} (code {})", self.message, self.code),
+        }
+    }
+}
+
+/// Why a call to the node failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum CallError {
+    /// The node didn't answer in time
+    TimedOut { method: String },
+    /// The request didn't get through, after `attempts` tries
+    Unreachable { method: String, attempts: u32, reason: String },
+    /// The node answered with an error
+    Node { method: String, error: RpcError },
+    /// The node's answer doesn't make sense
+    InvalidResponse(String),
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CallError::TimedOut { method } => write!(f, "Error occurred in calling {}: {}", method, TIMED_OUT),
+            CallError::Unreachable { method, attempts: 1, reason } => write!(f, "Error occurred in calling {}: {}", method, reason),
+            CallError::Unreachable { method, attempts, reason } => write!(f, "Error occurred in calling {} (gave up after {} attempts): {}", method, attempts, reason),
+            CallError::Node { method, error } => write!(f, "Error occurred in calling {}: {}", method, error),
+            CallError::InvalidResponse(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<CallError> for String {
+    fn from(e: CallError) -> String {
+        e.to_string()
+    }
+}
+
+/// A JSON-RPC response has a result or an error
+#[derive(Deserialize)]
+struct Response {
+    #[serde(default)]
+    result: Value,
+    error: Option<RpcError>,
+}
+
+/// What `eth_feeHistory` says about the latest blocks, oldest first
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeHistory {
+    /// The base fee of each block, followed by the base fee of the block after them
+    pub base_fees: Vec<u128>,
+    /// The share of its gas limit each block used
+    pub gas_used_ratios: Vec<f64>,
+    /// The tips paid in each block at each of the percentiles asked for, weighted by gas used
+    pub rewards: Vec<Vec<u128>>,
+}
+
+impl FeeHistory {
+    fn parse(history: &Value) -> Result<FeeHistory, String> {
+        let invalid = || format!("Invalid eth_feeHistory result: {}", history);
+        let quantities = |values: &Value| -> Result<Vec<u128>, String> {
+            values.as_array().ok_or_else(invalid)?.iter().map(|value| parse_quantity("eth_feeHistory", value)).collect()
+        };
+        Ok(FeeHistory {
+            base_fees: quantities(&history["baseFeePerGas"])?,
+            gas_used_ratios: history["gasUsedRatio"].as_array().ok_or_else(invalid)?
+                .iter().map(|ratio| ratio.as_f64().ok_or_else(invalid)).collect::<Result<_, _>>()?,
+            rewards: match &history["reward"] {
+                Value::Null => vec![],
+                rewards => rewards.as_array().ok_or_else(invalid)?.iter().map(quantities).collect::<Result<_, _>>()?,
+            },
+        })
+    }
+
+    /// The fees to offer to be mined slowly, on average and fast: each tip is the median across
+    /// the blocks that had transactions of their tips at `REWARD_PERCENTILES`, on top of the
+    /// base fee of the next block. None if none of the blocks had any.
+    pub fn suggestions(&self) -> Option<[FeeMarket; 3]> {
+        let base_fee = *self.base_fees.last()?;
+        // an empty block reports tips of 0, which says nothing about what gets mined
+        let rewards: Vec<_> = self.rewards.iter().zip(&self.gas_used_ratios)
+            .filter(|(rewards, ratio)| **ratio > 0.0 && rewards.len() == REWARD_PERCENTILES.len())
+            .map(|(rewards, _)| rewards)
+            .collect();
+        if rewards.is_empty() {
+            return None;
+        }
+        Some([0, 1, 2].map(|i| {
+            let tips: Vec<u128> = rewards.iter().map(|rewards| rewards[i]).collect();
+            FeeMarket { base_fee, priority_fee: percentile(&tips, 50) }
+        }))
+    }
+}
+
+/// The smallest of `values` that at least `percent`% of them are at or below (the nearest rank)
+pub fn percentile(values: &[u128], percent: u8) -> u128 {
+    let mut sorted = values.to_vec();
+    sorted.sort_unstable();
+    let rank = (sorted.len() * percent as usize).div_ceil(100);
+    sorted.get(rank.saturating_sub(1)).copied().unwrap_or(0)
+}
+
+/// What a plain transfer is expected to cost now: at the average suggested fee where the node
+/// has fee history, or else at its gas price
+pub fn typical_transfer_fee(rpc: &dyn EthRpc) -> Result<u128, String> {
+    let price = match suggest_fees(rpc) {
+        Ok([_, average, _]) => average.base_fee + average.priority_fee,
+        Err(_) => rpc.gas_price()?,
+    };
+    Ok(price * TRANSFER_GAS_LIMIT)
+}
+
+/// Suggested fees for sends that can wait, for the average send and for sends in a hurry, from
+/// the tips paid in the latest blocks. Errs on a node without `eth_feeHistory`.
+pub fn suggest_fees(rpc: &dyn EthRpc) -> Result<[FeeMarket; 3], String> {
+    rpc.fee_history(FEE_HISTORY_BLOCKS, &REWARD_PERCENTILES)?
+        .suggestions()
+        .ok_or_else(|| String::from("None of the latest blocks had transactions to go by"))
+}
+
+/// The JSON-RPC calls the wallet makes to an Ethereum node
+pub trait EthRpc {
+    /// The chain the node serves (`eth_chainId`)
+    fn chain_id(&self) -> Result<u64, String>;
+    /// The latest balance of an address in wei
+    fn get_balance(&self, address: &str) -> Result<u128, String>;
+    /// The latest balances of several addresses, in order. One that can't be fetched doesn't
+    /// keep the others from being returned.
+    fn get_balances(&self, addresses: &[&str]) -> Result<Vec<Result<u128, String>>, String> {
+        Ok(addresses.iter().map(|address| self.get_balance(address)).collect())
+    }
+    /// The balance of an address in wei once its pending transactions are mined
+    fn get_pending_balance(&self, address: &str) -> Result<u128, String>;
+    /// The number of transactions sent from an address
+    fn get_transaction_count(&self, address: &str) -> Result<u64, String>;
+    /// The number of transactions sent from an address, counting those still pending, which is
+    /// the nonce of the next one
+    fn get_pending_transaction_count(&self, address: &str) -> Result<u64, String>;
+    /// The current gas price in wei
+    fn gas_price(&self) -> Result<u128, String>;
+    /// The base fee per gas of the latest block, or None on a network without EIP-1559 fees
+    fn base_fee_per_gas(&self) -> Result<Option<u128>, String>;
+    /// The base fees and the tips at `reward_percentiles` of the latest `block_count` blocks
+    fn fee_history(&self, block_count: u64, reward_percentiles: &[f64]) -> Result<FeeHistory, String>;
+    /// The gas a transaction would use if it were sent now (`eth_estimateGas`)
+    fn estimate_gas(&self, from: &str, to: &str, wei_amount: u128, data: &[u8]) -> Result<u128, String>;
+    /// What calling a contract with `data` returns, without sending a transaction (`eth_call`)
+    fn eth_call(&self, to: &str, data: &[u8]) -> Result<Vec<u8>, String>;
+    /// Submits a signed transaction and returns its hash
+    fn send_raw_transaction(&self, signed_tx: &str) -> Result<String, String>;
+    /// Looks up the receipt of a sent transaction
+    fn transaction_status(&self, hash: &str) -> TransactionStatus;
+    /// The receipt of a sent transaction, or None while it is pending
+    fn transaction_receipt(&self, hash: &str) -> Result<Option<Receipt>, String>;
+    /// Whether the node knows about the transaction, pending or mined
+    fn transaction_exists(&self, hash: &str) -> Result<bool, String>;
+    /// The transaction with a hash, or None if the node doesn't know it
+    fn get_transaction(&self, hash: &str) -> Result<Option<TransactionInfo>, String>;
+    /// What a transaction the node knows was signed with
+    fn get_transaction_fields(&self, hash: &str) -> Result<Option<TransactionFields>, String>;
+    /// The number of the latest block
+    fn block_number(&self) -> Result<u64, String>;
+}
+
+/// Talks to a node over HTTP
+pub struct HttpRpc {
+    /// None for the node of whichever network is active when each call is made, so switching
+    /// networks takes effect at once
+    url: Option<String>,
+    agent: ureq::Agent,
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+}
+
+/// Shared by every `HttpRpc`, so connections to the node are reused. It waits on the node as
+/// long as the network settings allow.
+fn shared_agent() -> ureq::Agent {
+    static AGENT: OnceLock<ureq::Agent> = OnceLock::new();
+    AGENT.get_or_init(|| {
+        let network = Settings::load().network;
+        agent(Duration::from_secs(network.connect_timeout_secs), Duration::from_secs(network.read_timeout_secs))
+    }).clone()
+}
+
+pub(crate) fn agent(connect_timeout: Duration, read_timeout: Duration) -> ureq::Agent {
+    ureq::AgentBuilder::new().timeout_connect(connect_timeout).timeout_read(read_timeout).build()
+}
+
+/// How calls that fail for a reason that may pass, like a dropped connection or a busy node, are
+/// retried
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Including the first
+    pub attempts: u32,
+    /// The delay before the first retry, doubled for each one after
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy { attempts: 3, base_delay: Duration::from_millis(250), max_delay: Duration::from_secs(2) }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay before retry number `retry` (1 for the first). Up to half of it is random,
+    /// weighted by `jitter` in [0, 1), so clients that failed together don't retry together.
+    pub fn delay(&self, retry: u32, jitter: f64) -> Duration {
+        let delay = self.base_delay.saturating_mul(1 << retry.saturating_sub(1).min(16)).min(self.max_delay);
+        delay.mul_f64(1.0 - jitter.clamp(0.0, 1.0) / 2.0)
+    }
+}
+
+/// Whether a failed call is worth making again. A call that submits a transaction is only made
+/// again if it never reached the node: once it has, the node may have accepted the transaction
+/// whatever it answered, and submitting it twice would leave it unclear which one was sent.
+fn is_retryable(error: &ureq::Error, submits: bool) -> bool {
+    // the user is offered to retry those rather than made to wait several times over
+    if is_timeout(error) {
+        return false;
+    }
+    match error {
+        ureq::Error::Status(code, _) => !submits && (*code == 429 || (500..600).contains(code)),
+        ureq::Error::Transport(transport) => match transport.kind() {
+            ureq::ErrorKind::Dns | ureq::ErrorKind::ConnectionFailed => true,
+            ureq::ErrorKind::Io => !submits,
+            _ => false,
+        },
+    }
+}
+
+fn is_timeout(error: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(error);
+    while let Some(error) = source {
+        if let Some(e) = error.downcast_ref::<std::io::Error>() {
+            return matches!(e.kind(), std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock);
+        }
+        source = error.source();
+    }
+    false
+}
+
+/// Whether an error from a node call is because the node didn't answer in time, so making the
+/// call again may work
+pub fn timed_out(error: &str) -> bool {
+    error.contains(TIMED_OUT)
+}
+
+impl HttpRpc {
+    /// The node of the active network
+    pub fn active() -> HttpRpc {
+        HttpRpc { url: None, agent: shared_agent(), timeout: None, retry: RetryPolicy::default() }
+    }
+
+    pub fn new(url: &str) -> HttpRpc {
+        HttpRpc { url: Some(String::from(url)), agent: shared_agent(), timeout: None, retry: RetryPolicy::default() }
+    }
+
+    /// Gives up on calls that take longer than `timeout`
+    pub fn with_timeout(self, timeout: Duration) -> HttpRpc {
+        HttpRpc { timeout: Some(timeout), ..self }
+    }
+
+    /// Waits `connect_timeout` for a connection and `read_timeout` for each read, instead of
+    /// what the network settings say
+    pub fn with_timeouts(self, connect_timeout: Duration, read_timeout: Duration) -> HttpRpc {
+        HttpRpc { agent: agent(connect_timeout, read_timeout), ..self }
+    }
+
+    pub fn with_retry(self, retry: RetryPolicy) -> HttpRpc {
+        HttpRpc { retry, ..self }
+    }
+
+    /// Makes a call and returns its result, retrying it as `retry` allows. Errors the node
+    /// returns are recorded in the audit log as they came.
+    fn call(&self, method: &str, params: Value) -> Result<Value, CallError> {
+        let body = ureq::json!({
+            "jsonrpc": "2.0",
+            "id": "1",
+            "method": method,
+            "params": params,
+        });
+        let response = self.post(method, body, method == "eth_sendRawTransaction")?;
+        if !response["error"].is_null() {
+            audit::record(&format!("Node error in {}: {}", method, response["error"]));
+        }
+        parse_response(method, response)
+    }
+
+    /// Makes a call for each of `params` in one batch request. A call that fails doesn't fail
+    /// the others, only the request as a whole can.
+    fn batch(&self, method: &str, params: &[Value]) -> Result<Vec<Result<Value, String>>, CallError> {
+        let body: Vec<Value> = params.iter().enumerate().map(|(id, params)| ureq::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        })).collect();
+        let results = parse_batch(method, params.len(), self.post(method, Value::Array(body), false)?)?;
+        for error in results.iter().filter_map(|r| r.as_ref().err()) {
+            audit::record(&format!("Node error in batched {}: {}", method, error));
+        }
+        Ok(results)
+    }
+
+    fn url(&self) -> String {
+        self.url.clone().unwrap_or_else(|| networks::active().rpc_url)
+    }
+
+    /// Posts a request body, retrying what may pass, and returns the response
+    fn post(&self, method: &str, body: Value, submits: bool) -> Result<Value, CallError> {
+        let url = self.url();
+        let mut attempt = 1;
+        let response = loop {
+            let mut request = self.agent.post(&url).set("Content-Type", "application/json");
+            if let Some(timeout) = self.timeout {
+                request = request.timeout(timeout);
+            }
+            match request.send_json(body.clone()) {
+                Ok(response) => break response,
+                Err(e) if attempt < self.retry.attempts && is_retryable(&e, submits) => {
+                    std::thread::sleep(self.retry.delay(attempt, rand::thread_rng().gen()));
+                    attempt += 1;
+                },
+                Err(e) if is_timeout(&e) => return Err(CallError::TimedOut { method: String::from(method) }),
+                Err(e) => return Err(CallError::Unreachable { method: String::from(method), attempts: attempt, reason: e.to_string() }),
+            }
+        };
+        response.into_json().map_err(|e| match is_timeout(&e) {
+            true => CallError::TimedOut { method: String::from(method) },
+            false => CallError::InvalidResponse(format!("Invalid response: {}", e)),
+        })
+    }
+
+    /// Makes a call that returns a hex quantity
+    fn quantity(&self, method: &str, params: Value) -> Result<u128, CallError> {
+        parse_quantity(method, &self.call(method, params)?).map_err(CallError::InvalidResponse)
+    }
+
+    /// The balance of an address, failing with why the node couldn't give it
+    pub fn balance(&self, address: &str) -> Result<u128, CallError> {
+        self.quantity("eth_getBalance", ureq::json!([address, "latest"]))
+    }
+}
+
+impl EthRpc for HttpRpc {
+    fn chain_id(&self) -> Result<u64, String> {
+        // the chain is checked before every send, and a node doesn't change chains, so each URL
+        // is only asked once a session
+        static CHAIN_IDS: OnceLock<Mutex<HashMap<String, u64>>> = OnceLock::new();
+        let url = self.url();
+        if let Some(chain_id) = CHAIN_IDS.get_or_init(Mutex::default).lock().unwrap().get(&url) {
+            return Ok(*chain_id);
+        }
+        let chain_id = self.quantity("eth_chainId", ureq::json!([]))?;
+        let chain_id = u64::try_from(chain_id).map_err(|e| format!("Invalid eth_chainId result: {}", e))?;
+        CHAIN_IDS.get_or_init(Mutex::default).lock().unwrap().insert(url, chain_id);
+        Ok(chain_id)
+    }
+
+    fn get_balance(&self, address: &str) -> Result<u128, String> {
+        Ok(self.balance(address)?)
+    }
+
+    // one request for all of them, rather than one for each address
+    fn get_balances(&self, addresses: &[&str]) -> Result<Vec<Result<u128, String>>, String> {
+        let params: Vec<Value> = addresses.iter().map(|address| ureq::json!([address, "latest"])).collect();
+        Ok(self.batch("eth_getBalance", &params)?.into_iter()
+            .map(|result| result.and_then(|balance| parse_quantity("eth_getBalance", &balance)))
+            .collect())
+    }
+
+    fn get_pending_balance(&self, address: &str) -> Result<u128, String> {
+        Ok(self.quantity("eth_getBalance", ureq::json!([address, "pending"]))?)
+    }
+
+    fn get_transaction_count(&self, address: &str) -> Result<u64, String> {
+        Ok(self.quantity("eth_getTransactionCount", ureq::json!([address, "latest"]))? as u64)
+    }
+
+    fn get_pending_transaction_count(&self, address: &str) -> Result<u64, String> {
+        Ok(self.quantity("eth_getTransactionCount", ureq::json!([address, "pending"]))? as u64)
+    }
+
+    fn gas_price(&self) -> Result<u128, String> {
+        Ok(self.quantity("eth_gasPrice", ureq::json!([]))?)
+    }
+
+    fn base_fee_per_gas(&self) -> Result<Option<u128>, String> {
+        let block = self.call("eth_getBlockByNumber", ureq::json!(["latest", false]))?;
+        match &block["baseFeePerGas"] {
+            Value::Null => Ok(None),
+            base_fee => parse_quantity("eth_getBlockByNumber", base_fee).map(Some),
+        }
+    }
+
+    fn fee_history(&self, block_count: u64, reward_percentiles: &[f64]) -> Result<FeeHistory, String> {
+        let history = self.call("eth_feeHistory", ureq::json!([format!("{:#x}", block_count), "latest", reward_percentiles]))?;
+        FeeHistory::parse(&history)
+    }
+
+    fn estimate_gas(&self, from: &str, to: &str, wei_amount: u128, data: &[u8]) -> Result<u128, String> {
+        Ok(self.quantity("eth_estimateGas", ureq::json!([{
+            "from": from,
+            "to": to,
+            "value": format!("{:#x}", wei_amount),
+            "data": String::from("0x") + &hex::encode(data),
+        }]))?)
+    }
+
+    fn eth_call(&self, to: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+        let result = self.call("eth_call", ureq::json!([{ "to": to, "data": String::from("0x") + &hex::encode(data) }, "latest"]))?;
+        result.as_str().and_then(|s| s.strip_prefix("0x")).and_then(|s| hex::decode(s).ok())
+            .ok_or_else(|| format!("Invalid eth_call result: {}", result))
+    }
+
+    fn send_raw_transaction(&self, signed_tx: &str) -> Result<String, String> {
+        let result = self.call("eth_sendRawTransaction", ureq::json!([signed_tx]))
+            .map_err(|e| e.to_string().replacen("Error occurred in calling eth_sendRawTransaction", "The transaction wasn't sent", 1))?;
+        match result.as_str() {
+            Some("0x0") => Err(String::from("Transaction not yet available")),
+            Some(hash) => Ok(String::from(hash)),
+            None => Err(format!("Invalid eth_sendRawTransaction result: {}", result)),
+        }
+    }
+
+    fn transaction_status(&self, hash: &str) -> TransactionStatus {
+        match self.call("eth_getTransactionReceipt", ureq::json!([hash])) {
+            Ok(receipt) => parse_receipt(&receipt),
+            Err(_) => TransactionStatus::Unknown,
+        }
+    }
+
+    fn transaction_receipt(&self, hash: &str) -> Result<Option<Receipt>, String> {
+        Receipt::parse(&self.call("eth_getTransactionReceipt", ureq::json!([hash]))?)
+    }
+
+    fn transaction_exists(&self, hash: &str) -> Result<bool, String> {
+        let transaction = self.call("eth_getTransactionByHash", ureq::json!([hash]))
+            .map_err(|e| format!("Could not look up transaction: {}", e))?;
+        Ok(!transaction.is_null())
+    }
+
+    fn get_transaction(&self, hash: &str) -> Result<Option<TransactionInfo>, String> {
+        TransactionInfo::parse(&self.call("eth_getTransactionByHash", ureq::json!([hash]))?)
+    }
+
+    fn get_transaction_fields(&self, hash: &str) -> Result<Option<TransactionFields>, String> {
+        TransactionFields::parse(&self.call("eth_getTransactionByHash", ureq::json!([hash]))?)
+    }
+
+    fn block_number(&self) -> Result<u64, String> {
+        Ok(self.quantity("eth_blockNumber", ureq::json!([]))? as u64)
+    }
+}
+
+/// The result of a response, or the node's error
+fn parse_response(method: &str, response: Value) -> Result<Value, CallError> {
+    let response: Response = serde_json::from_value(response).map_err(|e| CallError::InvalidResponse(format!("Invalid response to {}: {}", method, e)))?;
+    match response.error {
+        Some(error) => Err(CallError::Node { method: String::from(method), error }),
+        None => Ok(response.result),
+    }
+}
+
+/// The responses to a batch of `count` calls, in the order they were made. The node may answer
+/// them in any order, so they are matched up by id.
+fn parse_batch(method: &str, count: usize, response: Value) -> Result<Vec<Result<Value, String>>, CallError> {
+    let responses = match response {
+        Value::Array(responses) => responses,
+        // a node that doesn't take batches answers with a single error
+        response => return parse_response(method, response).and_then(|_| Err(CallError::InvalidResponse(format!("Invalid response to batched {}", method)))),
+    };
+    let mut results: Vec<Result<Value, String>> = vec![Err(format!("The node didn't answer this {} call", method)); count];
+    for response in responses {
+        match response["id"].as_u64().map(|id| id as usize).filter(|id| *id < count) {
+            Some(id) => results[id] = parse_response(method, response).map_err(String::from),
+            None => return Err(CallError::InvalidResponse(format!("Invalid response to batched {}: unexpected id {}", method, response["id"]))),
+        }
+    }
+    Ok(results)
+}
+
+/// A hex quantity result
+fn parse_quantity(method: &str, result: &Value) -> Result<u128, String> {
+    match result.as_str().and_then(|s| s.strip_prefix("0x")) {
+        Some(v) => u128::from_str_radix(v, 16).map_err(|e| format!("Invalid {} result: {}", method, e)),
+        None => Err(format!("Invalid {} result: {}", method, result)),
+    }
+}
+
+/// A missing receipt means the transaction is still pending
+fn parse_receipt(receipt: &Value) -> TransactionStatus {
+    match receipt {
+        Value::Null => TransactionStatus::Pending,
+        receipt => match receipt["status"].as_str() {
+            Some("0x1") => TransactionStatus::Confirmed,
+            Some("0x0") => TransactionStatus::Failed,
+            _ => TransactionStatus::Unknown,
+        },
+    }
+}
+
+/// What the receipt of a mined transaction says about it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Receipt {
+    /// Confirmed, or Failed if it reverted
+    pub status: TransactionStatus,
+    pub block_number: u64,
+    pub gas_used: u128,
+    /// What was paid per gas. Nodes from before EIP-1559 don't say, as it is the gas price.
+    pub effective_gas_price: Option<u128>,
+}
+
+impl Receipt {
+    fn parse(receipt: &Value) -> Result<Option<Receipt>, String> {
+        let method = "eth_getTransactionReceipt";
+        let status = match parse_receipt(receipt) {
+            TransactionStatus::Pending => return Ok(None),
+            TransactionStatus::Unknown => return Err(format!("Invalid {} result: {}", method, receipt)),
+            status => status,
+        };
+        Ok(Some(Receipt {
+            status,
+            block_number: parse_quantity(method, &receipt["blockNumber"])? as u64,
+            gas_used: parse_quantity(method, &receipt["gasUsed"])?,
+            effective_gas_price: match &receipt["effectiveGasPrice"] {
+                Value::Null => None,
+                price => Some(parse_quantity(method, price)?),
+            },
+        }))
+    }
+
+    /// The fee the transaction paid, given the gas price it was signed with
+    pub fn fee(&self, gas_price: u128) -> u128 {
+        self.gas_used * self.effective_gas_price.unwrap_or(gas_price)
+    }
+}
+
+/// What the node says about a transaction it knows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransactionInfo {
+    /// None while it is pending
+    pub block_number: Option<u64>,
+    /// The gas price of a legacy transaction, or what a mined EIP-1559 one paid per gas
+    pub gas_price: Option<u128>,
+    /// The most an EIP-1559 transaction pays per gas
+    pub max_fee_per_gas: Option<u128>,
+}
+
+impl TransactionInfo {
+    fn parse(transaction: &Value) -> Result<Option<TransactionInfo>, String> {
+        let method = "eth_getTransactionByHash";
+        let optional = |field: &str| match &transaction[field] {
+            Value::Null => Ok(None),
+            value => parse_quantity(method, value).map(Some),
+        };
+        if transaction.is_null() {
+            return Ok(None);
+        }
+        Ok(Some(TransactionInfo {
+            block_number: optional("blockNumber")?.map(|n| n as u64),
+            gas_price: optional("gasPrice")?,
+            max_fee_per_gas: optional("maxFeePerGas")?,
+        }))
+    }
+}
+
+/// What a transaction was signed with, as the node returns it
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionFields {
+    pub from: [u8; 20],
+    /// None for a contract creation
+    pub to: Option<[u8; 20]>,
+    pub nonce: u64,
+    pub value: u128,
+    pub input: Vec<u8>,
+    /// The EIP-7702 authorizations of a type 4 transaction, empty for any other
+    pub authorization_list: Vec<SignedAuthorization>,
+}
+
+impl TransactionFields {
+    fn parse(transaction: &Value) -> Result<Option<TransactionFields>, String> {
+        let method = "eth_getTransactionByHash";
+        let invalid = |field: &str, value: &Value| format!("Invalid {} result: {} is {}", method, field, value);
+        let bytes = |field: &str, value: &Value| value.as_str().and_then(|s| s.strip_prefix("0x"))
+            .and_then(|s| hex::decode(if s.len() % 2 == 1 { format!("0{}", s) } else { String::from(s) }).ok())
+            .ok_or_else(|| invalid(field, value));
+        let address = |field: &str, value: &Value| bytes(field, value)?.try_into().map_err(|_| invalid(field, value));
+        // r and s are quantities, so without leading zeros
+        let integer = |field: &str, value: &Value| bytes(field, value).map(|b| b.into_iter().skip_while(|b| *b == 0).collect::<Vec<u8>>());
+        if transaction.is_null() {
+            return Ok(None);
+        }
+        let authorization_list = match &transaction["authorizationList"] {
+            Value::Null => Vec::new(),
+            Value::Array(list) => list.iter().map(|entry| Ok(SignedAuthorization {
+                authorization: Authorization {
+                    chain_id: parse_quantity(method, &entry["chainId"])? as u64,
+                    address: address("authorization address", &entry["address"])?,
+                    nonce: parse_quantity(method, &entry["nonce"])? as u64,
+                },
+                y_parity: parse_quantity(method, &entry["yParity"])? as u8,
+                r: integer("authorization r", &entry["r"])?,
+                s: integer("authorization s", &entry["s"])?,
+            })).collect::<Result<_, String>>()?,
+            list => return Err(invalid("authorizationList", list)),
+        };
+        Ok(Some(TransactionFields {
+            from: address("from", &transaction["from"])?,
+            to: match &transaction["to"] {
+                Value::Null => None,
+                to => Some(address("to", to)?),
+            },
+            nonce: parse_quantity(method, &transaction["nonce"])? as u64,
+            value: parse_quantity(method, &transaction["value"])?,
+            input: bytes("input", &transaction["input"])?,
+            authorization_list,
+        }))
+    }
+}
+
+/// Asks for the receipt of a transaction until it is mined, `timeout` passes or `stop` is set.
+/// A call that fails is made again at the next poll, since the transaction is mined regardless.
+/// The poll interval is read from the settings each time, so a change takes effect at once.
+pub fn wait_for_receipt(rpc: &dyn EthRpc, hash: &str, timeout: Duration, stop: &AtomicBool) -> Option<Receipt> {
+    let deadline = Instant::now() + timeout;
+    while !stop.load(Ordering::SeqCst) {
+        if let Ok(Some(receipt)) = rpc.transaction_receipt(hash) {
+            return Some(receipt);
+        }
+        let next_poll = Instant::now() + refresh::current().receipt_poll_interval();
+        if next_poll > deadline {
+            return None;
+        }
+        // in short steps, so stopping takes effect at once
+        while Instant::now() < next_poll && !stop.load(Ordering::SeqCst) {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+    None
+}
+
+/// A node in memory, for tests of code that would otherwise need the network
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+
+    use super::*;
+
+    pub struct MockRpc {
+        pub chain_id: u64,
+        pub balances: RefCell<HashMap<String, u128>>,
+        /// Balances that pending transactions change, where they differ from `balances`
+        pub pending_balances: RefCell<HashMap<String, u128>>,
+        pub gas_price: Cell<u128>,
+        /// None for a network without EIP-1559 fees
+        pub base_fee: Cell<Option<u128>>,
+        /// None for a node without `eth_feeHistory`
+        pub fee_history: RefCell<Option<FeeHistory>>,
+        /// What every transaction is estimated to use
+        pub gas_estimate: Cell<u128>,
+        /// What contract calls return, by address and hex call data. Others return nothing, as
+        /// a call to an address without code does.
+        pub contracts: RefCell<HashMap<String, Vec<u8>>>,
+        /// Signed transactions submitted, in order
+        pub sent: RefCell<Vec<String>>,
+        /// Every call fails, as if the node couldn't be reached
+        pub offline: Cell<bool>,
+        /// Sent transactions are never mined
+        pub pending: Cell<bool>,
+        /// The number of the latest block. Sent transactions are mined in block 1.
+        pub head: Cell<u64>,
+        /// What transactions that weren't sent through the mock were signed with, by hash, as
+        /// those a relayer broadcasts
+        pub transactions: RefCell<HashMap<String, TransactionFields>>,
+    }
+
+    impl MockRpc {
+        pub fn new(chain_id: u64) -> MockRpc {
+            MockRpc {
+                chain_id,
+                balances: RefCell::new(HashMap::new()),
+                pending_balances: RefCell::new(HashMap::new()),
+                gas_price: Cell::new(2_000_000_000),
+                base_fee: Cell::new(None),
+                fee_history: RefCell::new(None),
+                gas_estimate: Cell::new(21_000),
+                contracts: RefCell::new(HashMap::new()),
+                sent: RefCell::new(vec![]),
+                offline: Cell::new(false),
+                pending: Cell::new(false),
+                head: Cell::new(1),
+                transactions: RefCell::new(HashMap::new()),
+            }
+        }
+
+        pub fn fund(&self, address: &str, wei: u128) {
+            self.balances.borrow_mut().insert(address.to_lowercase(), wei);
+        }
+
+        pub fn contract(&self, address: &str, data: &[u8], result: Vec<u8>) {
+            self.contracts.borrow_mut().insert(MockRpc::call_key(address, data), result);
+        }
+
+        fn call_key(address: &str, data: &[u8]) -> String {
+            format!("{}:{}", address.to_lowercase(), hex::encode(data))
+        }
+
+        fn reachable(&self) -> Result<(), String> {
+            match self.offline.get() {
+                true => Err(String::from("Connection refused")),
+                false => Ok(()),
+            }
+        }
+
+        fn hash(signed_tx: &str) -> String {
+            String::from("0x") + &hex::encode(crate::crypto::keccak256(&hex::decode(&signed_tx[2..]).unwrap()))
+        }
+    }
+
+    impl EthRpc for MockRpc {
+        fn chain_id(&self) -> Result<u64, String> {
+            self.reachable()?;
+            Ok(self.chain_id)
+        }
+
+        fn get_balance(&self, address: &str) -> Result<u128, String> {
+            self.reachable()?;
+            Ok(self.balances.borrow().get(&address.to_lowercase()).copied().unwrap_or(0))
+        }
+
+        fn get_pending_balance(&self, address: &str) -> Result<u128, String> {
+            match self.pending_balances.borrow().get(&address.to_lowercase()) {
+                Some(balance) => self.reachable().map(|_| *balance),
+                None => self.get_balance(address),
+            }
+        }
+
+        fn get_transaction_count(&self, _address: &str) -> Result<u64, String> {
+            self.reachable()?;
+            Ok(self.sent.borrow().len() as u64)
+        }
+
+        fn get_pending_transaction_count(&self, address: &str) -> Result<u64, String> {
+            self.get_transaction_count(address)
+        }
+
+        fn gas_price(&self) -> Result<u128, String> {
+            self.reachable()?;
+            Ok(self.gas_price.get())
+        }
+
+        fn base_fee_per_gas(&self) -> Result<Option<u128>, String> {
+            self.reachable()?;
+            Ok(self.base_fee.get())
+        }
+
+        fn fee_history(&self, _block_count: u64, _reward_percentiles: &[f64]) -> Result<FeeHistory, String> {
+            self.reachable()?;
+            self.fee_history.borrow().clone().ok_or_else(|| String::from("Error occurred in calling eth_feeHistory: the method eth_feeHistory does not exist/is not available (code -32601)"))
+        }
+
+        fn estimate_gas(&self, _from: &str, _to: &str, _wei_amount: u128, _data: &[u8]) -> Result<u128, String> {
+            self.reachable()?;
+            Ok(self.gas_estimate.get())
+        }
+
+        fn eth_call(&self, to: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+            self.reachable()?;
+            Ok(self.contracts.borrow().get(&MockRpc::call_key(to, data)).cloned().unwrap_or_default())
+        }
+
+        fn send_raw_transaction(&self, signed_tx: &str) -> Result<String, String> {
+            self.reachable()?;
+            self.sent.borrow_mut().push(String::from(signed_tx));
+            Ok(MockRpc::hash(signed_tx))
+        }
+
+        fn transaction_status(&self, hash: &str) -> TransactionStatus {
+            match self.sent.borrow().iter().any(|tx| MockRpc::hash(tx) == hash) {
+                true if self.pending.get() => TransactionStatus::Pending,
+                true => TransactionStatus::Confirmed,
+                false => TransactionStatus::Unknown,
+            }
+        }
+
+        fn transaction_receipt(&self, hash: &str) -> Result<Option<Receipt>, String> {
+            self.reachable()?;
+            Ok(match self.transaction_status(hash) {
+                TransactionStatus::Confirmed => Some(Receipt {
+                    status: TransactionStatus::Confirmed,
+                    block_number: 1,
+                    gas_used: 21_000,
+                    effective_gas_price: Some(self.gas_price.get()),
+                }),
+                _ => None,
+            })
+        }
+
+        fn transaction_exists(&self, hash: &str) -> Result<bool, String> {
+            self.reachable()?;
+            Ok(self.sent.borrow().iter().any(|tx| MockRpc::hash(tx) == hash))
+        }
+
+        fn get_transaction(&self, hash: &str) -> Result<Option<TransactionInfo>, String> {
+            Ok(self.transaction_exists(hash)?.then(|| TransactionInfo {
+                block_number: (!self.pending.get()).then_some(1),
+                gas_price: Some(self.gas_price.get()),
+                max_fee_per_gas: None,
+            }))
+        }
+
+        fn get_transaction_fields(&self, hash: &str) -> Result<Option<TransactionFields>, String> {
+            self.reachable()?;
+            Ok(self.transactions.borrow().get(hash).cloned())
+        }
+
+        fn block_number(&self) -> Result<u64, String> {
+            self.reachable()?;
+            Ok(self.head.get())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error_code::ErrorReport;
+
+    #[test]
+    fn test_parse_responses() {
+        assert_eq!(parse_response("eth_gasPrice", ureq::json!({ "result": "0x77359400" })), Ok(ureq::json!("0x77359400")));
+        assert_eq!(parse_response("eth_gasPrice", ureq::json!({ "error": { "code": -32601, "message": "method not found" } })).unwrap_err().to_string(),
+            "Error occurred in calling eth_gasPrice: method not found (code -32601)");
+        assert!(parse_response("eth_gasPrice", ureq::json!({ "error": "busy" })).unwrap_err().to_string().starts_with("Invalid response to eth_gasPrice"));
+        // a pending transaction has no receipt
+        assert_eq!(parse_response("eth_getTransactionReceipt", ureq::json!({ "jsonrpc": "2.0", "id": "1", "result": null })), Ok(Value::Null));
+
+        assert_eq!(parse_quantity("eth_gasPrice", &ureq::json!("0x77359400")), Ok(2_000_000_000));
+        assert!(parse_quantity("eth_gasPrice", &ureq::json!("0xzz")).is_err());
+        assert!(parse_quantity("eth_gasPrice", &Value::Null).is_err());
+
+        assert_eq!(parse_receipt(&Value::Null), TransactionStatus::Pending);
+        assert_eq!(parse_receipt(&ureq::json!({ "status": "0x1" })), TransactionStatus::Confirmed);
+        assert_eq!(parse_receipt(&ureq::json!({ "status": "0x0" })), TransactionStatus::Failed);
+        assert_eq!(parse_receipt(&ureq::json!({})), TransactionStatus::Unknown);
+
+        // nothing listens on the discard port
+        assert!(HttpRpc::new("http://127.0.0.1:9").gas_price().unwrap_err().starts_with("Error occurred in calling eth_gasPrice"));
+    }
+
+    #[test]
+    fn test_fee_suggestions() {
+        let tips = [5, 1, 4, 2, 3];
+        assert_eq!(percentile(&tips, 10), 1);
+        assert_eq!(percentile(&tips, 50), 3);
+        assert_eq!(percentile(&tips, 90), 5);
+        assert_eq!(percentile(&tips, 100), 5);
+        assert_eq!(percentile(&[7, 9], 50), 7);
+        assert_eq!(percentile(&[], 50), 0);
+
+        let history = FeeHistory::parse(&ureq::json!({
+            "oldestBlock": "0x10",
+            "baseFeePerGas": ["0x3b9aca00", "0x3b9aca00", "0x3b9aca00", "0x3b9aca00", "0x4a817c80"],
+            "gasUsedRatio": [0.5, 0.0, 0.9, 0.3],
+            "reward": [["0x1", "0x5", "0x9"], ["0x0", "0x0", "0x0"], ["0x3", "0x6", "0x14"], ["0x2", "0x4", "0x7"]],
+        })).unwrap();
+        assert_eq!(history.base_fees.len(), 5);
+        // the empty block is left out, and the next block's base fee is used
+        let suggested = history.suggestions().unwrap();
+        assert_eq!(suggested.map(|m| m.priority_fee), [2, 5, 9]);
+        assert_eq!(suggested[2], FeeMarket { base_fee: 1_250_000_000, priority_fee: 9 });
+        assert_eq!(suggested[2].max_fee_per_gas(), 2_500_000_009);
+
+        let empty = FeeHistory { base_fees: vec![1, 1], gas_used_ratios: vec![0.0], rewards: vec![vec![0, 0, 0]] };
+        assert_eq!(empty.suggestions(), None);
+        assert!(FeeHistory::parse(&ureq::json!({ "baseFeePerGas": "0x1" })).is_err());
+
+        let rpc = mock::MockRpc::new(1);
+        assert!(suggest_fees(&rpc).unwrap_err().contains("does not exist"));
+        // without fee history a transfer is expected to cost the gas price
+        assert_eq!(typical_transfer_fee(&rpc), Ok(2_000_000_000 * 21_000));
+        rpc.fee_history.replace(Some(history));
+        assert_eq!(suggest_fees(&rpc), Ok(suggested));
+        assert_eq!(typical_transfer_fee(&rpc), Ok((1_250_000_000 + 5) * 21_000));
+    }
+
+    #[test]
+    fn test_receipts() {
+        let receipt = |status: &str| ureq::json!({ "status": status, "blockNumber": "0x10", "gasUsed": "0x5208", "effectiveGasPrice": "0x3b9aca00" });
+        let mined = Receipt { status: TransactionStatus::Confirmed, block_number: 16, gas_used: 21_000, effective_gas_price: Some(1_000_000_000) };
+        assert_eq!(Receipt::parse(&receipt("0x1")), Ok(Some(mined)));
+        assert_eq!(Receipt::parse(&receipt("0x0")).unwrap().unwrap().status, TransactionStatus::Failed);
+        assert_eq!(Receipt::parse(&Value::Null), Ok(None));
+        assert!(Receipt::parse(&ureq::json!({ "status": "0x1" })).is_err());
+        // a node from before EIP-1559 leaves the price out
+        let legacy = Receipt::parse(&ureq::json!({ "status": "0x1", "blockNumber": "0x10", "gasUsed": "0x5208" })).unwrap().unwrap();
+        assert_eq!(legacy.effective_gas_price, None);
+        assert_eq!(legacy.fee(2), 42_000);
+        assert_eq!(mined.fee(2), 21_000_000_000_000);
+
+        let pending = TransactionInfo { block_number: None, gas_price: Some(30), max_fee_per_gas: Some(40) };
+        assert_eq!(TransactionInfo::parse(&ureq::json!({ "blockNumber": null, "gasPrice": "0x1e", "maxFeePerGas": "0x28" })), Ok(Some(pending)));
+        assert_eq!(TransactionInfo::parse(&ureq::json!({ "blockNumber": "0x10", "gasPrice": "0x1e" })).unwrap().unwrap().block_number, Some(16));
+        assert_eq!(TransactionInfo::parse(&Value::Null), Ok(None));
+        assert!(TransactionInfo::parse(&ureq::json!({ "gasPrice": 30 })).is_err());
+
+        let relayed = ureq::json!({
+            "type": "0x4",
+            "from": "0x70997970c51812dc3a010c7d01b50e0d17dc79c8",
+            "to": "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266",
+            "nonce": "0x9",
+            "value": "0x0",
+            "input": "0xabcd",
+            "authorizationList": [{ "chainId": "0x1", "address": "0x5fbdb2315678afecb367f032d93f642f64180aa3", "nonce": "0x3", "yParity": "0x1", "r": "0xa0b", "s": "0x0c" }],
+        });
+        let fields = TransactionFields::parse(&relayed).unwrap().unwrap();
+        assert_eq!(hex::encode(fields.from), "70997970c51812dc3a010c7d01b50e0d17dc79c8");
+        assert_eq!((fields.nonce, fields.value, fields.input.as_slice()), (9, 0, &[0xab, 0xcd][..]));
+        let authorization = &fields.authorization_list[0];
+        assert_eq!((authorization.authorization.chain_id, authorization.authorization.nonce, authorization.y_parity), (1, 3, 1));
+        assert_eq!((authorization.r.as_slice(), authorization.s.as_slice()), (&[0x0a, 0x0b][..], &[0x0c][..]));
+        let legacy = TransactionFields::parse(&ureq::json!({ "from": "0x70997970c51812dc3a010c7d01b50e0d17dc79c8", "to": null, "nonce": "0x0", "value": "0x1", "input": "0x" })).unwrap().unwrap();
+        assert_eq!((legacy.to, legacy.authorization_list.len()), (None, 0));
+        assert_eq!(TransactionFields::parse(&Value::Null), Ok(None));
+        assert!(TransactionFields::parse(&ureq::json!({ "from": "0x1234", "nonce": "0x0", "value": "0x0", "input": "0x" })).is_err());
+
+        let rpc = mock::MockRpc::new(1);
+        let hash = rpc.send_raw_transaction("0x1234").unwrap();
+        let never = AtomicBool::new(false);
+        assert_eq!(wait_for_receipt(&rpc, &hash, Duration::ZERO, &never).map(|r| r.status), Some(TransactionStatus::Confirmed));
+        rpc.pending.set(true);
+        assert_eq!(wait_for_receipt(&rpc, &hash, Duration::ZERO, &never), None);
+        assert_eq!(wait_for_receipt(&rpc, &hash, Duration::from_secs(60), &AtomicBool::new(true)), None);
+    }
+
+    #[test]
+    fn test_batch() {
+        // answered out of order, with one error
+        let results = parse_batch("eth_getBalance", 3, ureq::json!([
+            { "jsonrpc": "2.0", "id": 2, "result": "0x2" },
+            { "jsonrpc": "2.0", "id": 0, "result": "0x0" },
+            { "jsonrpc": "2.0", "id": 1, "error": { "code": -32602, "message": "invalid address" } },
+        ])).unwrap();
+        assert_eq!(results[0], Ok(ureq::json!("0x0")));
+        assert!(results[1].as_ref().unwrap_err().contains("invalid address"));
+        assert_eq!(results[2], Ok(ureq::json!("0x2")));
+
+        // a call left unanswered fails alone
+        let results = parse_batch("eth_getBalance", 2, ureq::json!([{ "id": 1, "result": "0x1" }])).unwrap();
+        assert!(results[0].is_err() && results[1].is_ok());
+        assert!(parse_batch("eth_getBalance", 1, ureq::json!([{ "id": 5, "result": "0x1" }])).is_err());
+        assert!(parse_batch("eth_getBalance", 1, ureq::json!({ "error": { "code": -32600, "message": "batch not supported" } }))
+            .unwrap_err().to_string().contains("batch not supported"));
+
+        let rpc = mock::MockRpc::new(1);
+        rpc.fund("0xAB", 7);
+        assert_eq!(rpc.get_balances(&["0xab", "0xcd"]).unwrap(), vec![Ok(7), Ok(0)]);
+    }
+
+    #[test]
+    fn test_explain_errors() {
+        let error = |message: &str| RpcError { code: -32000, message: String::from(message) };
+        assert!(error("nonce too low").explanation().unwrap().contains("nonce up to date"));
+        assert!(error("insufficient funds for gas * price + value").explanation().unwrap().starts_with("The account can't cover"));
+        assert!(error("replacement transaction underpriced").explanation().unwrap().contains("at least 10%"));
+        assert!(error("intrinsic gas too low").explanation().unwrap().contains("Raise the gas limit"));
+        assert!(error("transaction underpriced").explanation().unwrap().contains("Raise the gas price"));
+        assert_eq!(error("execution reverted").explanation(), None);
+
+        assert_eq!(parse_response("eth_sendRawTransaction", ureq::json!({ "error": { "code": -32000, "message": "Nonce too low" } })).unwrap_err().to_string(),
+            format!("Error occurred in calling eth_sendRawTransaction: {} (the node said: Nonce too low)", EXPLANATIONS[0].2));
+    }
+
+    #[test]
+    fn test_error_code() {
+        let returned = |message: &str| parse_response("eth_sendRawTransaction", ureq::json!({ "error": { "code": -32000, "message": message } })).unwrap_err();
+        let code = |error: CallError| ErrorReport::from(error).code;
+        assert_eq!(code(returned("insufficient funds for gas * price + value")), ErrorCode::InsufficientFunds);
+        assert_eq!(code(returned("Nonce too low")), ErrorCode::NonceTooLow);
+        assert_eq!(code(returned("replacement transaction underpriced")), ErrorCode::Underpriced);
+        assert_eq!(code(returned("intrinsic gas too low")), ErrorCode::GasTooLow);
+        assert_eq!(code(returned("already known")), ErrorCode::AlreadyKnown);
+        assert_eq!(code(returned("execution reverted")), ErrorCode::NodeError);
+        assert_eq!(code(parse_response("eth_getBalance", ureq::json!("expected value")).unwrap_err()), ErrorCode::NodeError);
+        let report = ErrorReport::from(returned("execution reverted"));
+        assert_eq!((&report.details["node_code"], &report.details["node_message"]), (&ureq::json!(-32000), &ureq::json!("execution reverted")));
+
+        assert_eq!(code(CallError::TimedOut { method: String::from("eth_getBalance") }), ErrorCode::RpcUnreachable);
+        let refused = HttpRpc::new("http://127.0.0.1:9").with_retry(RetryPolicy { attempts: 1, ..RetryPolicy::default() }).balance("0x00").unwrap_err();
+        assert!(matches!(refused, CallError::Unreachable { attempts: 1, .. }));
+        assert_eq!(code(refused), ErrorCode::RpcUnreachable);
+    }
+
+    /// Answers each request with the next of `statuses` and a gas price, and returns the url and
+    /// the number of requests answered
+    fn serve(statuses: &'static [u16]) -> (String, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+        use std::io::{Read, Write};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let served = std::sync::Arc::new(AtomicUsize::new(0));
+        let counter = served.clone();
+        std::thread::spawn(move || {
+            for status in statuses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let _ = stream.read(&mut [0; 4096]);
+                let body = r#"{"jsonrpc":"2.0","id":"1","result":"0x77359400"}"#;
+                write!(stream, "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body).unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        (url, served)
+    }
+
+    #[test]
+    fn test_chain_id_cached() {
+        use std::sync::atomic::Ordering;
+        let (url, served) = serve(&[200]);
+        let rpc = HttpRpc::new(&url);
+        assert_eq!(rpc.chain_id(), Ok(2_000_000_000));
+        // the node would no longer answer, so the second comes from the cache
+        assert_eq!(rpc.chain_id(), Ok(2_000_000_000));
+        assert_eq!(HttpRpc::new(&url).chain_id(), Ok(2_000_000_000));
+        assert_eq!(served.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_retry() {
+        use std::sync::atomic::Ordering;
+        let policy = RetryPolicy { attempts: 3, base_delay: Duration::from_millis(1), max_delay: Duration::from_millis(4) };
+        assert_eq!(policy.delay(1, 0.0), Duration::from_millis(1));
+        assert_eq!(policy.delay(2, 0.0), Duration::from_millis(2));
+        assert_eq!(policy.delay(3, 0.5), Duration::from_micros(3000));
+        assert_eq!(policy.delay(10, 0.0), Duration::from_millis(4));
+
+        let (url, served) = serve(&[503, 429, 200]);
+        assert_eq!(HttpRpc::new(&url).with_retry(policy).gas_price(), Ok(2_000_000_000));
+        assert_eq!(served.load(Ordering::SeqCst), 3);
+
+        let (url, _) = serve(&[502, 502, 502]);
+        assert!(HttpRpc::new(&url).with_retry(policy).gas_price().unwrap_err()
+            .starts_with("Error occurred in calling eth_gasPrice (gave up after 3 attempts)"));
+
+        // a client error won't go away by asking again
+        let (url, served) = serve(&[400, 200]);
+        assert!(HttpRpc::new(&url).with_retry(policy).gas_price().is_err());
+        assert_eq!(served.load(Ordering::SeqCst), 1);
+
+        // the node may have taken a transaction it answered with an error, so it isn't sent again
+        let (url, served) = serve(&[503, 200]);
+        assert!(HttpRpc::new(&url).with_retry(policy).send_raw_transaction("0x00").is_err());
+        assert_eq!(served.load(Ordering::SeqCst), 1);
+
+        // but one that never reached the node is
+        assert!(HttpRpc::new("http://127.0.0.1:9").with_retry(policy).send_raw_transaction("0x00").unwrap_err()
+            .contains("gave up after 3 attempts"));
+    }
+
+    #[test]
+    fn test_timeout() {
+        use std::io::Read;
+        // reads each request and never answers it
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let _ = stream.read(&mut [0; 4096]);
+                std::thread::spawn(move || {
+                    std::thread::sleep(Duration::from_secs(5));
+                    drop(stream);
+                });
+            }
+        });
+
+        let rpc = HttpRpc::new(&url).with_timeouts(Duration::from_millis(200), Duration::from_millis(200));
+        let started = std::time::Instant::now();
+        let balance = rpc.get_balance("0x0000000000000000000000000000000000000000").unwrap_err();
+        let send = rpc.send_raw_transaction("0x00").unwrap_err();
+        assert_eq!(balance, format!("Error occurred in calling eth_getBalance: {}", TIMED_OUT));
+        assert!(timed_out(&send));
+        // neither is retried without asking
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(!timed_out(&HttpRpc::new("http://127.0.0.1:9").gas_price().unwrap_err()));
+    }
+}
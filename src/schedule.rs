@@ -0,0 +1,162 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
+
+use crate::utils;
+
+/// How often the timer looks at the clock
+const TICK: Duration = Duration::from_secs(1);
+
+/// A transfer prepared now and broadcast once `send_at` has passed, only ever from an unlocked session
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ScheduledTransaction {
+    /// The 0x-prefixed recipient address
+    pub recipient: String,
+    /// The amount to send in wei
+    pub wei_amount: u128,
+    /// Unix timestamp (seconds) after which the transaction is sent
+    pub send_at: u64,
+    /// Set if the transaction was signed when it was scheduled. Otherwise it is signed with the
+    /// gas price and nonce current at send time.
+    pub presigned: Option<PresignedTransaction>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PresignedTransaction {
+    /// The 0x-prefixed RLP encoded signed transaction
+    pub raw: String,
+    pub nonce: u64,
+    pub gas_price: u128,
+}
+
+/// The result of re-checking a pre-signed transaction right before it is broadcast
+#[derive(Debug, PartialEq)]
+pub enum PresignedCheck {
+    Ok,
+    /// The account's nonce moved on, so the signed transaction would be rejected
+    StaleNonce { signed: u64, current: u64 },
+    /// Gas got more expensive since signing, so the transaction may sit unmined
+    Underpriced { signed: u128, current: u128 },
+}
+
+impl ScheduledTransaction {
+    pub fn is_due(&self, now: u64) -> bool {
+        self.send_at <= now
+    }
+
+    /// Sanity-checks the pre-signed transaction, if any, against the current nonce and gas price
+    pub fn check_presigned(&self, current_nonce: u64, current_gas_price: u128) -> PresignedCheck {
+        match &self.presigned {
+            Some(p) if p.nonce != current_nonce => PresignedCheck::StaleNonce { signed: p.nonce, current: current_nonce },
+            Some(p) if p.gas_price < current_gas_price => PresignedCheck::Underpriced { signed: p.gas_price, current: current_gas_price },
+            _ => PresignedCheck::Ok,
+        }
+    }
+}
+
+/// Calls `wake` from a thread of its own once the time it was set to has passed, so a scheduled
+/// transaction goes out on time, and revealed accounts are hidden on time, while the user sits
+/// at a prompt. It stops when dropped.
+pub struct DueTimer {
+    /// Unix timestamp (seconds) to wake at, or 0 for never
+    due_at: Arc<AtomicU64>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DueTimer {
+    pub fn start(wake: fn()) -> DueTimer {
+        let due_at = Arc::new(AtomicU64::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let (due_at, stop) = (due_at.clone(), stop.clone());
+            thread::spawn(move || while !stop.load(Ordering::SeqCst) {
+                let at = due_at.load(Ordering::SeqCst);
+                // wakes once per time set, unless it was set again meanwhile
+                if at != 0 && at <= utils::now() && due_at.compare_exchange(at, 0, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                    wake();
+                }
+                thread::park_timeout(TICK);
+            })
+        };
+        DueTimer { due_at, stop, thread: Some(thread) }
+    }
+
+    /// Sets when to wake next, replacing the time set before. None stops it waking.
+    pub fn set(&self, at: Option<u64>) {
+        self.due_at.store(at.unwrap_or(0), Ordering::SeqCst);
+    }
+}
+
+impl Drop for DueTimer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn scheduled(presigned: Option<PresignedTransaction>) -> ScheduledTransaction {
+        ScheduledTransaction {
+            recipient: String::from("0x73363901cd60ace0df1df46111fa999416bb9bd1"),
+            wei_amount: 1_000_000_000_000_000_000_000_000,
+            send_at: 1000,
+            presigned,
+        }
+    }
+
+    #[test]
+    fn test_is_due() {
+        let tx = scheduled(None);
+        assert!(!tx.is_due(999));
+        assert!(tx.is_due(1000));
+        assert!(tx.is_due(5000));
+    }
+
+    #[test]
+    fn test_check_presigned() {
+        assert_eq!(scheduled(None).check_presigned(7, 100), PresignedCheck::Ok);
+
+        let tx = scheduled(Some(PresignedTransaction { raw: String::from("0x00"), nonce: 3, gas_price: 100 }));
+        assert_eq!(tx.check_presigned(3, 100), PresignedCheck::Ok);
+        assert_eq!(tx.check_presigned(3, 90), PresignedCheck::Ok);
+        assert_eq!(tx.check_presigned(4, 100), PresignedCheck::StaleNonce { signed: 3, current: 4 });
+        assert_eq!(tx.check_presigned(3, 150), PresignedCheck::Underpriced { signed: 100, current: 150 });
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let tx = scheduled(Some(PresignedTransaction { raw: String::from("0x00"), nonce: 3, gas_price: 100 }));
+        let json = serde_json::to_string(&tx).unwrap();
+        assert_eq!(serde_json::from_str::<ScheduledTransaction>(&json).unwrap(), tx);
+    }
+
+    #[test]
+    fn test_due_timer() {
+        static WOKEN: AtomicU64 = AtomicU64::new(0);
+        fn wake() {
+            WOKEN.fetch_add(1, Ordering::SeqCst);
+        }
+
+        let timer = DueTimer::start(wake);
+        timer.set(Some(utils::now() + 3600));
+        thread::sleep(TICK * 2);
+        assert_eq!(WOKEN.load(Ordering::SeqCst), 0);
+
+        // a time that has passed wakes it once
+        timer.set(Some(utils::now()));
+        thread::sleep(TICK * 3);
+        assert_eq!(WOKEN.load(Ordering::SeqCst), 1);
+        timer.set(None);
+        drop(timer);
+    }
+}
@@ -0,0 +1,353 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Typed at any prompt of an unlocked wallet to lock it
+pub const LOCK_COMMAND: &str = "lock";
+
+/// Set by SIGUSR1, consumed by the next lock check
+static LOCK_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// True while a wallet is unlocked, so locking outside a session is ignored
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+/// Set by SIGINT inside `run_interruptible`
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// Set when a scheduled transaction is due, consumed by the next due check
+static DUE: AtomicBool = AtomicBool::new(false);
+/// True inside `run_until_due`, so a due transaction only ends what can be gone back to
+static WATCHING_DUE: AtomicBool = AtomicBool::new(false);
+
+/// Unwinding payload used to abandon whatever the user was in the middle of when the wallet is
+/// locked. Nothing is signed or written while it unwinds, so no half-finished action survives.
+struct Locked;
+
+/// Unwinding payload used like `Locked` to leave a prompt when a scheduled transaction is due
+struct Due;
+
+/// Installs the SIGUSR1 handler that locks the wallet
+pub fn install() {
+    platform::install_signal_handler();
+}
+
+/// Asks for the wallet to be locked. Only touches an atomic, so it's safe in a signal handler.
+pub fn request_lock() {
+    LOCK_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Returns true if a lock was requested since the last check
+fn lock_requested() -> bool {
+    LOCK_REQUESTED.swap(false, Ordering::SeqCst)
+}
+
+/// Asks for the scheduled transactions that are due to be sent, waking the prompt the user sits
+/// at. Called from the timer's thread.
+pub fn request_due() {
+    DUE.store(true, Ordering::SeqCst);
+    if WATCHING_DUE.load(Ordering::SeqCst) {
+        platform::wake_reader();
+    }
+}
+
+/// Returns true if a due transaction is waiting and can be gone back to
+fn due_requested() -> bool {
+    WATCHING_DUE.load(Ordering::SeqCst) && DUE.swap(false, Ordering::SeqCst)
+}
+
+/// Unwinds to the enclosing `run_until_locked` if the input is the lock command or a lock was
+/// requested, or to the enclosing `run_until_due` if a scheduled transaction is due. Called on
+/// every line of user input.
+pub fn check(input: &str) {
+    if !ACTIVE.load(Ordering::SeqCst) {
+        return;
+    }
+    if input.trim().eq_ignore_ascii_case(LOCK_COMMAND) || lock_requested() {
+        panic::resume_unwind(Box::new(Locked));
+    }
+    if due_requested() {
+        panic::resume_unwind(Box::new(Due));
+    }
+}
+
+/// Runs an unlocked session. Returns `None` if the session was locked before `f` finished, in
+/// which case every stack frame inside `f`, and the secrets they held, has been dropped.
+pub fn run_until_locked<T>(f: impl FnOnce() -> T) -> Option<T> {
+    lock_requested();
+    ACTIVE.store(true, Ordering::SeqCst);
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    ACTIVE.store(false, Ordering::SeqCst);
+
+    match result {
+        Ok(value) => Some(value),
+        Err(payload) if payload.is::<Locked>() => None,
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+/// Runs the menus of an unlocked session. Returns `None` if a scheduled transaction became due
+/// before `f` finished, in which case `f` was left at a prompt, as locking leaves it, and the
+/// caller sends the transaction before going back.
+pub fn run_until_due<T>(f: impl FnOnce() -> T) -> Option<T> {
+    DUE.store(false, Ordering::SeqCst);
+    WATCHING_DUE.store(true, Ordering::SeqCst);
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    WATCHING_DUE.store(false, Ordering::SeqCst);
+
+    match result {
+        Ok(value) => Some(value),
+        Err(payload) if payload.is::<Due>() => None,
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+/// Runs a long computation that Ctrl-C should stop without ending the program. `f` is given a
+/// flag that Ctrl-C sets; the usual Ctrl-C behaviour is restored afterwards.
+pub fn run_interruptible<T>(f: impl FnOnce(&AtomicBool) -> T) -> T {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    let previous = platform::catch_interrupt();
+    let result = f(&INTERRUPTED);
+    platform::restore_interrupt(previous);
+    result
+}
+
+/// Reads a line from stdin without the trailing newline. On unix, a lock signal or a due
+/// scheduled transaction interrupts the read, so they don't wait for the user to type something.
+pub fn read_line() -> String {
+    platform::read_line()
+}
+
+/// Reads a line like `read_line`, without echoing what is typed when stdin is a terminal. Piped
+/// input is read as it is.
+pub fn read_hidden_line() -> String {
+    platform::read_hidden_line()
+}
+
+#[cfg(unix)]
+mod platform {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    /// The thread blocked in `read_line`, as a `pthread_t`
+    static READER: AtomicUsize = AtomicUsize::new(0);
+    /// True while `READER` is blocked in `read_line`, so waking it can't interrupt anything else
+    static READING: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn on_sigusr1(_signal: libc::c_int) {
+        super::request_lock();
+    }
+
+    // the flag is set before the signal is sent, so interrupting the read is all that's left
+    extern "C" fn on_sigusr2(_signal: libc::c_int) {}
+
+    extern "C" fn on_sigint(_signal: libc::c_int) {
+        super::INTERRUPTED.store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    /// Sends SIGINT to `on_sigint`, returning the handler it replaced
+    pub fn catch_interrupt() -> libc::sigaction {
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_sigint as *const () as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            libc::sigaction(libc::SIGINT, &action, &mut previous);
+            previous
+        }
+    }
+
+    pub fn restore_interrupt(previous: libc::sigaction) {
+        unsafe {
+            libc::sigaction(libc::SIGINT, &previous, std::ptr::null_mut());
+        }
+    }
+
+    pub fn install_signal_handler() {
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_sigusr1 as *const () as libc::sighandler_t;
+            // no SA_RESTART, so a blocked read returns EINTR
+            action.sa_flags = 0;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut());
+            action.sa_sigaction = on_sigusr2 as *const () as libc::sighandler_t;
+            libc::sigaction(libc::SIGUSR2, &action, std::ptr::null_mut());
+        }
+    }
+
+    /// Interrupts the read of the thread sitting at a prompt, if there is one
+    pub fn wake_reader() {
+        if READING.load(Ordering::SeqCst) {
+            unsafe { libc::pthread_kill(READER.load(Ordering::SeqCst) as libc::pthread_t, libc::SIGUSR2) };
+        }
+    }
+
+    // reads byte by byte from the descriptor so nothing is buffered past the line, which keeps
+    // input typed ahead of a lock from being replayed after it
+    pub fn read_line() -> String {
+        let mut line = vec![];
+        READER.store(unsafe { libc::pthread_self() } as usize, Ordering::SeqCst);
+        READING.store(true, Ordering::SeqCst);
+        loop {
+            let mut byte = 0u8;
+            let read = unsafe { libc::read(libc::STDIN_FILENO, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+            match read {
+                1 if byte == b'\n' => break,
+                1 => line.push(byte),
+                0 => break,
+                _ => {
+                    let interrupted = std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted;
+                    let due = super::WATCHING_DUE.load(Ordering::SeqCst) && super::DUE.load(Ordering::SeqCst);
+                    if !interrupted || super::LOCK_REQUESTED.load(Ordering::SeqCst) || due {
+                        break;
+                    }
+                },
+            }
+        }
+        READING.store(false, Ordering::SeqCst);
+        String::from_utf8_lossy(&line).into_owned()
+    }
+
+    pub fn read_hidden_line() -> String {
+        let _echo_off = EchoOff::new(libc::STDIN_FILENO);
+        read_line()
+    }
+
+    /// Stops a terminal echoing input, other than the newline, until dropped
+    pub struct EchoOff {
+        fd: libc::c_int,
+        saved: libc::termios,
+    }
+
+    impl EchoOff {
+        /// Returns None if `fd` isn't a terminal
+        pub fn new(fd: libc::c_int) -> Option<Self> {
+            unsafe {
+                let mut saved: libc::termios = std::mem::zeroed();
+                if libc::isatty(fd) == 0 || libc::tcgetattr(fd, &mut saved) != 0 {
+                    return None;
+                }
+                let mut hidden = saved;
+                hidden.c_lflag &= !libc::ECHO;
+                hidden.c_lflag |= libc::ECHONL;
+                libc::tcsetattr(fd, libc::TCSANOW, &hidden);
+                Some(EchoOff { fd, saved })
+            }
+        }
+    }
+
+    impl Drop for EchoOff {
+        fn drop(&mut self) {
+            unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.saved) };
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod platform {
+    pub fn install_signal_handler() {}
+
+    // a due transaction waits for the next line typed
+    pub fn wake_reader() {}
+
+    // Ctrl-C keeps ending the program
+    pub fn catch_interrupt() {}
+
+    pub fn restore_interrupt(_previous: ()) {}
+
+    pub fn read_line() -> String {
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input).unwrap();
+        if let Some('\n') = input.chars().next_back() {
+            input.pop();
+        }
+        input
+    }
+
+    #[cfg(windows)]
+    pub fn read_hidden_line() -> String {
+        use std::ffi::c_void;
+
+        #[link(name = "kernel32")]
+        extern "system" {
+            fn GetStdHandle(handle: u32) -> *mut c_void;
+            fn GetConsoleMode(console: *mut c_void, mode: *mut u32) -> i32;
+            fn SetConsoleMode(console: *mut c_void, mode: u32) -> i32;
+        }
+        const STD_INPUT_HANDLE: u32 = -10i32 as u32;
+        const ENABLE_ECHO_INPUT: u32 = 0x4;
+
+        let console = unsafe { GetStdHandle(STD_INPUT_HANDLE) };
+        let mut mode = 0;
+        // fails when stdin isn't a console
+        if unsafe { GetConsoleMode(console, &mut mode) } == 0 {
+            return read_line();
+        }
+        unsafe { SetConsoleMode(console, mode & !ENABLE_ECHO_INPUT) };
+        let line = read_line();
+        unsafe { SetConsoleMode(console, mode) };
+        println!();
+        line
+    }
+
+    #[cfg(not(windows))]
+    pub fn read_hidden_line() -> String {
+        read_line()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // one test, since the lock state is global
+    #[test]
+    fn test_run_until_locked() {
+        // locking outside a session is ignored
+        check(LOCK_COMMAND);
+        request_lock();
+        check("1");
+        // and the request left over doesn't lock the next session
+        assert_eq!(run_until_locked(|| { check("1"); 5 }), Some(5));
+
+        assert_eq!(run_until_locked(|| { check(" LOCK "); 5 }), None);
+        assert_eq!(run_until_locked(|| { request_lock(); check("1"); 5 }), None);
+
+        // a due transaction only leaves what `run_until_due` can go back to
+        assert_eq!(run_until_locked(|| { request_due(); check("1"); 5 }), Some(5));
+        assert_eq!(run_until_locked(|| run_until_due(|| { request_due(); check("1"); 5 })), Some(None));
+        assert_eq!(run_until_locked(|| run_until_due(|| { check("1"); 5 })), Some(Some(5)));
+        // locking still goes through it
+        assert_eq!(run_until_locked(|| run_until_due(|| { check(LOCK_COMMAND); 5 })), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_echo_off() {
+        let echoes = |fd| unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            assert_eq!(libc::tcgetattr(fd, &mut termios), 0);
+            termios.c_lflag & libc::ECHO != 0
+        };
+
+        // piped input isn't a terminal, so it is read as it is
+        let mut pipe = [0; 2];
+        assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+        assert!(platform::EchoOff::new(pipe[0]).is_none());
+
+        // a terminal stops echoing until the guard is dropped
+        let terminal = unsafe {
+            let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            assert!(master >= 0);
+            assert_eq!(libc::grantpt(master), 0);
+            assert_eq!(libc::unlockpt(master), 0);
+            let slave = libc::open(libc::ptsname(master), libc::O_RDWR | libc::O_NOCTTY);
+            assert!(slave >= 0);
+            [master, slave]
+        };
+        assert!(echoes(terminal[1]));
+        let echo_off = platform::EchoOff::new(terminal[1]).unwrap();
+        assert!(!echoes(terminal[1]));
+        drop(echo_off);
+        assert!(echoes(terminal[1]));
+
+        for fd in pipe.into_iter().chain(terminal) {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
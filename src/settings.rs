@@ -0,0 +1,379 @@
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Serialize, Deserialize};
+
+use crate::crypto::{self, Argon2Params, PasswordKdf, Pbkdf2Params, ScryptParams};
+use crate::data_dir;
+use crate::refresh::RefreshInterval;
+use crate::ssz_format::StorageFormat;
+
+/// In the data directory
+const SETTINGS_PATH: &str = "settings.json";
+/// Environment variable that overrides `network.project_id`
+pub const PROJECT_ID_VAR: &str = "BASIC_ETH_PROJECT_ID";
+/// Environment variable that overrides `network.provider_url`
+pub const PROVIDER_URL_VAR: &str = "BASIC_ETH_PROVIDER_URL";
+/// Names of well-known networks, by chain id
+const NETWORK_NAMES: [(u64, &str); 7] = [
+    (1, "Mainnet"),
+    (3, "Ropsten"),
+    (4, "Rinkeby"),
+    (5, "Goerli"),
+    (42, "Kovan"),
+    (17000, "Holesky"),
+    (11155111, "Sepolia"),
+];
+
+/// User settings that apply before any wallet is unlocked. Missing fields take their default value,
+/// so the file only needs to contain what the user wants to change.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Settings {
+    /// Startup environment checks
+    pub hardening: HardeningSettings,
+    /// The gas-sponsoring relayer used for relayed sends
+    pub relayer: RelayerSettings,
+    /// Reminders to check the recovery phrase backup
+    pub backup_reminder: BackupReminderSettings,
+    /// Two-person approval of outgoing transactions
+    pub approvals: ApprovalSettings,
+    /// The node the wallet talks to
+    pub network: NetworkSettings,
+    /// Finding used accounts when a wallet is restored
+    pub discovery: DiscoverySettings,
+    /// How wallet passwords are protected
+    pub security: SecuritySettings,
+    /// How wallet files are written
+    pub storage: StorageSettings,
+    /// Where fiat values of balances come from
+    pub prices: PriceSettings,
+    /// How often live data is fetched again
+    pub refresh: RefreshSettings,
+}
+
+/// Each flag disables one of the startup checks in the hardening module
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct HardeningSettings {
+    pub skip_root_check: bool,
+    pub skip_world_writable_check: bool,
+    pub skip_debugger_check: bool,
+    pub skip_core_dump_check: bool,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct RelayerSettings {
+    pub url: Option<String>,
+    pub api_key: Option<String>,
+    /// The EIP-7702 delegate contract relayed calls are made through, which the account
+    /// authorizes for each call
+    pub delegate: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupReminderSettings {
+    pub enabled: bool,
+    /// Months without a backup check before a reminder is shown at login
+    pub interval_months: u32,
+}
+
+impl Default for BackupReminderSettings {
+    fn default() -> Self {
+        BackupReminderSettings { enabled: true, interval_months: 6 }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApprovalSettings {
+    /// Hours a staged transaction waits for approval before it is discarded
+    pub expiry_hours: u64,
+}
+
+impl Default for ApprovalSettings {
+    fn default() -> Self {
+        ApprovalSettings { expiry_hours: 72 }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiscoverySettings {
+    /// Unused accounts in a row after which the scan stops. 0 turns discovery off.
+    pub gap_limit: usize,
+}
+
+impl Default for DiscoverySettings {
+    fn default() -> Self {
+        DiscoverySettings { gap_limit: 5 }
+    }
+}
+
+/// Key derivation for new and changed wallet passwords. Existing files keep the function and
+/// cost they were written with until the password is changed.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct SecuritySettings {
+    /// "argon2id", "scrypt" or "pbkdf2"
+    pub kdf: String,
+    pub argon2_memory_kib: u32,
+    pub argon2_iterations: u32,
+    pub argon2_parallelism: u32,
+    pub scrypt_log_n: u8,
+    /// Values below 100000 are raised to it
+    pub pbkdf2_iterations: u32,
+    /// Offer to keep each wallet's password key in the system keychain after a password login,
+    /// so it opens without the password. Needs a build with the keychain feature.
+    pub keychain: bool,
+    /// Minutes protected accounts stay revealed before they are hidden again
+    pub reveal_timeout_mins: u64,
+}
+
+impl Default for SecuritySettings {
+    fn default() -> Self {
+        SecuritySettings {
+            kdf: String::from("argon2id"),
+            argon2_memory_kib: crypto::ARGON2_MEMORY_KIB,
+            argon2_iterations: crypto::ARGON2_ITERATIONS,
+            argon2_parallelism: crypto::ARGON2_PARALLELISM,
+            scrypt_log_n: crypto::SCRYPT_LOG_N,
+            pbkdf2_iterations: crypto::PBKDF2_ITERATIONS,
+            keychain: false,
+            reveal_timeout_mins: 10,
+        }
+    }
+}
+
+impl SecuritySettings {
+    /// Fresh parameters for the configured function, or for Argon2id if it isn't known
+    pub fn password_kdf(&self) -> PasswordKdf {
+        match self.kdf.as_str() {
+            "scrypt" => PasswordKdf::Scrypt(ScryptParams::new(self.scrypt_log_n)),
+            "pbkdf2" => PasswordKdf::Pbkdf2(Pbkdf2Params::new(self.pbkdf2_iterations)),
+            kdf => {
+                if kdf != "argon2id" {
+                    eprintln!("Unknown key derivation function '{}' in {}, using argon2id", kdf, SETTINGS_PATH);
+                }
+                PasswordKdf::Argon2id(Argon2Params::new(self.argon2_memory_kib, self.argon2_iterations, self.argon2_parallelism))
+            },
+        }
+    }
+}
+
+/// Wallet files are read in either format, so changing it takes effect the next time each one
+/// is stored
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageSettings {
+    /// "json" or "ssz"
+    pub format: String,
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        StorageSettings { format: String::from("json") }
+    }
+}
+
+impl StorageSettings {
+    /// The configured format, or JSON if it isn't known
+    pub fn format(&self) -> StorageFormat {
+        match self.format.as_str() {
+            "ssz" => StorageFormat::Ssz,
+            format => {
+                if format != "json" {
+                    eprintln!("Unknown storage format '{}' in {}, using json", format, SETTINGS_PATH);
+                }
+                StorageFormat::Json
+            },
+        }
+    }
+}
+
+/// Nothing is looked up until `api_url` is set, since each lookup tells the price service which
+/// coins the wallet holds
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct PriceSettings {
+    /// A CoinGecko-compatible API, such as https://api.coingecko.com/api/v3
+    pub api_url: Option<String>,
+    /// The fiat currency values are shown in, as the API names it. Prices are looked up again
+    /// as often as `refresh.interval` says.
+    pub currency: String,
+}
+
+impl Default for PriceSettings {
+    fn default() -> Self {
+        PriceSettings { api_url: None, currency: String::from("usd") }
+    }
+}
+
+/// Changed from the networks menu as well as in the file
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(default)]
+pub struct RefreshSettings {
+    /// "manual", "block", or seconds after which the balance shown with the account menu,
+    /// pending transactions and prices are fetched again
+    pub interval: RefreshInterval,
+    /// Seconds between checks on a transaction being waited on. Defaults to the interval, or 4
+    /// with manual refreshing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receipt_poll_secs: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkSettings {
+    /// JSON-RPC endpoint. When set, the provider URL and project id aren't used.
+    pub rpc_url: Option<String>,
+    /// Base URL of a node provider such as Infura, whose endpoint is `{provider_url}/{project_id}`
+    pub provider_url: String,
+    /// The project id (API key) to reach the provider with
+    pub project_id: Option<String>,
+    /// EIP-155 chain id transactions are signed for
+    pub chain_id: u64,
+    /// Lowest gas price to sign with, in wei. Defaults to the preset for the chain id.
+    pub min_gas_price: Option<u128>,
+    /// Seconds to wait for a connection to the node
+    pub connect_timeout_secs: u64,
+    /// Seconds to wait for the node to send each part of its response
+    pub read_timeout_secs: u64,
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        NetworkSettings {
+            rpc_url: None,
+            provider_url: String::from("https://sepolia.infura.io/v3"),
+            project_id: None,
+            chain_id: 11155111,
+            min_gas_price: None,
+            connect_timeout_secs: 5,
+            read_timeout_secs: 15,
+        }
+    }
+}
+
+impl NetworkSettings {
+    /// The JSON-RPC endpoint: `rpc_url`, or else the provider URL joined with the project id.
+    /// `BASIC_ETH_PROVIDER_URL` and `BASIC_ETH_PROJECT_ID` override those two.
+    pub fn endpoint(&self) -> Result<String, String> {
+        let env = |var| std::env::var(var).ok().filter(|value: &String| !value.trim().is_empty());
+        self.endpoint_with(env(PROVIDER_URL_VAR), env(PROJECT_ID_VAR))
+    }
+
+    fn endpoint_with(&self, provider_url: Option<String>, project_id: Option<String>) -> Result<String, String> {
+        if let Some(rpc_url) = self.rpc_url.as_ref().filter(|url| !url.trim().is_empty()) {
+            return Ok(rpc_url.clone());
+        }
+        let provider_url = provider_url.unwrap_or_else(|| self.provider_url.clone());
+        match project_id.or_else(|| self.project_id.clone()).filter(|id| !id.trim().is_empty()) {
+            Some(project_id) => Ok(format!("{}/{}", provider_url.trim_end_matches('/'), project_id.trim())),
+            None => Err(format!(
+                "No node is configured. Set {} to your project id for {}, or set network.project_id or network.rpc_url in {}.",
+                PROJECT_ID_VAR, provider_url, Settings::path().display()
+            )),
+        }
+    }
+}
+
+/// The name of a network for display, e.g. "Sepolia" or "chain 1337"
+pub fn network_name(chain_id: u64) -> String {
+    NETWORK_NAMES.iter()
+        .find(|(id, _)| *id == chain_id)
+        .map_or_else(|| format!("chain {}", chain_id), |(_, name)| String::from(*name))
+}
+
+impl Settings {
+    /// Where the settings file is, in the data directory
+    pub fn path() -> PathBuf {
+        data_dir::path(SETTINGS_PATH)
+    }
+
+    /// Loads the settings file, falling back to defaults if it doesn't exist or can't be parsed
+    pub fn load() -> Settings {
+        let path = Settings::path();
+        if !path.exists() {
+            return Settings::default();
+        }
+
+        match fs::read_to_string(&path).map(|s| serde_json::from_str(&s)) {
+            Ok(Ok(settings)) => settings,
+            Ok(Err(e)) => {
+                eprintln!("Ignoring malformed {}: {}", path.display(), e);
+                Settings::default()
+            },
+            Err(e) => {
+                eprintln!("Could not read {}: {}", path.display(), e);
+                Settings::default()
+            },
+        }
+    }
+
+    /// Writes `value` as the `key` section of the settings file, keeping everything else in it as
+    /// it was written, and renames it over like `Networks::save`
+    pub fn save_section<T: Serialize>(key: &str, value: &T) -> Result<(), String> {
+        let path = Settings::path();
+        let existing = match path.exists() {
+            true => Some(fs::read_to_string(&path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?),
+            false => None,
+        };
+        let merged = merge_section(existing.as_deref(), key, value).map_err(|e| format!("{} was left as it is: {}", path.display(), e))?;
+        let temp_path = data_dir::path(&format!("{}.tmp", SETTINGS_PATH));
+        fs::write(&temp_path, merged)
+            .and_then(|()| fs::rename(&temp_path, &path))
+            .map_err(|e| format!("Error writing {}: {}", path.display(), e))
+    }
+}
+
+/// The settings file `existing` with `key` set to `value`. A file that isn't a JSON object isn't
+/// overwritten, since what the user meant by it would be lost.
+fn merge_section<T: Serialize>(existing: Option<&str>, key: &str, value: &T) -> Result<String, String> {
+    let mut settings = match existing {
+        Some(text) => serde_json::from_str(text).map_err(|e| e.to_string())?,
+        None => serde_json::Value::Object(Default::default()),
+    };
+    let object = settings.as_object_mut().ok_or_else(|| String::from("it isn't a JSON object"))?;
+    object.insert(String::from(key), serde_json::to_value(value).map_err(|e| e.to_string())?);
+    Ok(serde_json::to_string_pretty(&settings).unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_endpoint() {
+        let mut network = NetworkSettings::default();
+        assert!(network.endpoint_with(None, None).unwrap_err().starts_with("No node is configured. Set BASIC_ETH_PROJECT_ID"));
+        assert_eq!(network.endpoint_with(None, Some(String::from("abc"))).unwrap(), "https://sepolia.infura.io/v3/abc");
+
+        network.project_id = Some(String::from("def"));
+        assert_eq!(network.endpoint_with(None, None).unwrap(), "https://sepolia.infura.io/v3/def");
+        // the environment wins over the file
+        assert_eq!(network.endpoint_with(Some(String::from("https://holesky.infura.io/v3/")), Some(String::from("abc"))).unwrap(),
+            "https://holesky.infura.io/v3/abc");
+
+        // a full URL needs neither
+        network.rpc_url = Some(String::from("http://127.0.0.1:8545"));
+        assert_eq!(network.endpoint_with(None, Some(String::from("abc"))).unwrap(), "http://127.0.0.1:8545");
+    }
+
+    #[test]
+    fn test_merge_section() {
+        let refresh = RefreshSettings { interval: RefreshInterval::Every(30), receipt_poll_secs: None };
+        let existing = r#"{"network":{"chain_id":1,"unknown":true},"refresh":{"interval":"manual","receipt_poll_secs":2}}"#;
+        let merged: serde_json::Value = serde_json::from_str(&merge_section(Some(existing), "refresh", &refresh).unwrap()).unwrap();
+        // the other sections are kept as written, even what this version doesn't know
+        assert_eq!(merged["network"], serde_json::json!({"chain_id": 1, "unknown": true}));
+        assert_eq!(merged["refresh"], serde_json::json!({"interval": "30"}));
+
+        let settings: Settings = serde_json::from_str(&merge_section(None, "refresh", &refresh).unwrap()).unwrap();
+        assert_eq!(settings.refresh.interval, RefreshInterval::Every(30));
+        assert!(merge_section(Some("[1]"), "refresh", &refresh).is_err());
+        assert!(merge_section(Some("{"), "refresh", &refresh).is_err());
+    }
+}
@@ -0,0 +1,244 @@
+use serde::de::DeserializeOwned;
+use ssz::{Decode, DecodeError, Encode};
+
+/// Starts a wallet file, or the decrypted body of one, written as SSZ rather than JSON
+pub const MAGIC: &[u8] = b"BASIC-ETH SSZ\n";
+
+/// How `store` writes wallet files. Either is read whatever the settings say.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StorageFormat {
+    Json,
+    Ssz,
+}
+
+/// Prefixes SSZ bytes with `MAGIC`
+pub fn with_magic(ssz: Vec<u8>) -> Vec<u8> {
+    [MAGIC.to_vec(), ssz].concat()
+}
+
+/// The SSZ bytes of data written with `with_magic`, or None for JSON
+pub fn strip_magic(data: &[u8]) -> Option<&[u8]> {
+    data.strip_prefix(MAGIC)
+}
+
+/// A `String` as its UTF-8 bytes
+pub mod text {
+    pub mod encode {
+        pub fn is_ssz_fixed_len() -> bool {
+            false
+        }
+
+        pub fn ssz_fixed_len() -> usize {
+            ssz::BYTES_PER_LENGTH_OFFSET
+        }
+
+        pub fn ssz_bytes_len(text: &str) -> usize {
+            text.len()
+        }
+
+        pub fn ssz_append(text: &str, buf: &mut Vec<u8>) {
+            buf.extend_from_slice(text.as_bytes());
+        }
+    }
+
+    pub mod decode {
+        use ssz::DecodeError;
+
+        pub use super::encode::{is_ssz_fixed_len, ssz_fixed_len};
+
+        pub fn from_ssz_bytes(bytes: &[u8]) -> Result<String, DecodeError> {
+            String::from_utf8(bytes.to_vec()).map_err(|e| DecodeError::BytesInvalid(e.to_string()))
+        }
+    }
+}
+
+/// An `Option` as the SSZ union of nothing and the value: a selector byte, 0 for None and 1 for
+/// Some, followed by the value
+pub mod optional {
+    pub mod encode {
+        use ssz::Encode;
+
+        pub fn is_ssz_fixed_len() -> bool {
+            false
+        }
+
+        pub fn ssz_fixed_len() -> usize {
+            ssz::BYTES_PER_LENGTH_OFFSET
+        }
+
+        pub fn ssz_bytes_len<T: Encode>(value: &Option<T>) -> usize {
+            ssz::BYTES_PER_UNION_SELECTOR + value.as_ref().map_or(0, T::ssz_bytes_len)
+        }
+
+        pub fn ssz_append<T: Encode>(value: &Option<T>, buf: &mut Vec<u8>) {
+            buf.push(value.is_some() as u8);
+            if let Some(value) = value {
+                value.ssz_append(buf);
+            }
+        }
+    }
+
+    pub mod decode {
+        use ssz::{Decode, DecodeError};
+
+        pub use super::encode::{is_ssz_fixed_len, ssz_fixed_len};
+
+        pub fn from_ssz_bytes<T: Decode>(bytes: &[u8]) -> Result<Option<T>, DecodeError> {
+            super::super::split_union(bytes, T::from_ssz_bytes)
+        }
+    }
+}
+
+/// An `Option<String>`, like `optional`
+pub mod optional_text {
+    pub mod encode {
+        pub use super::super::optional::encode::{is_ssz_fixed_len, ssz_fixed_len};
+
+        pub fn ssz_bytes_len(text: &Option<String>) -> usize {
+            ssz::BYTES_PER_UNION_SELECTOR + text.as_ref().map_or(0, String::len)
+        }
+
+        pub fn ssz_append(text: &Option<String>, buf: &mut Vec<u8>) {
+            buf.push(text.is_some() as u8);
+            if let Some(text) = text {
+                buf.extend_from_slice(text.as_bytes());
+            }
+        }
+    }
+
+    pub mod decode {
+        use ssz::DecodeError;
+
+        pub use super::super::optional::encode::{is_ssz_fixed_len, ssz_fixed_len};
+
+        pub fn from_ssz_bytes(bytes: &[u8]) -> Result<Option<String>, DecodeError> {
+            super::super::split_union(bytes, super::super::text::decode::from_ssz_bytes)
+        }
+    }
+}
+
+/// Any other field as its JSON bytes. The nested types have layouts of their own that change
+/// with them, and serde already reads every version of those.
+pub mod json {
+    pub mod encode {
+        use serde::Serialize;
+
+        pub use super::super::text::encode::{is_ssz_fixed_len, ssz_fixed_len};
+
+        pub fn ssz_bytes_len<T: Serialize>(value: &T) -> usize {
+            serde_json::to_vec(value).unwrap().len()
+        }
+
+        pub fn ssz_append<T: Serialize>(value: &T, buf: &mut Vec<u8>) {
+            serde_json::to_writer(buf, value).unwrap();
+        }
+    }
+
+    pub mod decode {
+        pub use super::super::text::encode::{is_ssz_fixed_len, ssz_fixed_len};
+
+        pub fn from_ssz_bytes<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<T, ssz::DecodeError> {
+            super::super::from_json(bytes)
+        }
+    }
+}
+
+/// Like `json`, for a field that version 2 files wrote as an SSZ container. A container starts
+/// with an offset or a fixed-size field, whose first byte for these types is never the `{` that
+/// starts the JSON.
+pub mod json_or_container {
+    pub mod encode {
+        pub use super::super::json::encode::*;
+    }
+
+    pub mod decode {
+        use serde::de::DeserializeOwned;
+        use ssz::{Decode, DecodeError};
+
+        pub use super::super::text::encode::{is_ssz_fixed_len, ssz_fixed_len};
+
+        pub fn from_ssz_bytes<T: DeserializeOwned + Decode>(bytes: &[u8]) -> Result<T, DecodeError> {
+            match bytes.first() {
+                Some(b'{') => super::super::from_json(bytes),
+                _ => T::from_ssz_bytes(bytes),
+            }
+        }
+    }
+}
+
+fn split_union<T>(bytes: &[u8], decode: impl FnOnce(&[u8]) -> Result<T, DecodeError>) -> Result<Option<T>, DecodeError> {
+    match bytes.split_first() {
+        Some((0, [])) => Ok(None),
+        Some((1, value)) => decode(value).map(Some),
+        Some((selector, _)) => Err(DecodeError::UnionSelectorInvalid(*selector)),
+        None => Err(DecodeError::InvalidByteLength { len: 0, expected: ssz::BYTES_PER_UNION_SELECTOR }),
+    }
+}
+
+fn from_json<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, DecodeError> {
+    serde_json::from_slice(bytes).map_err(|e| DecodeError::BytesInvalid(e.to_string()))
+}
+
+/// The version field that leads an SSZ wallet, read without decoding the rest
+pub fn version(ssz: &[u8]) -> Option<u32> {
+    ssz.get(..<u32 as Decode>::ssz_fixed_len()).and_then(|bytes| u32::from_ssz_bytes(bytes).ok())
+}
+
+/// Encodes a value, such as a wallet, with `MAGIC` in front
+pub fn encode<T: Encode>(value: &T) -> Vec<u8> {
+    with_magic(value.as_ssz_bytes())
+}
+
+/// Decodes what `encode` wrote, describing what is wrong with bytes that don't decode
+pub fn decode<T: Decode>(ssz: &[u8]) -> Result<T, String> {
+    T::from_ssz_bytes(ssz).map_err(|e| match e {
+        DecodeError::BytesInvalid(e) => e,
+        e => format!("{:?}", e),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_union() {
+        let mut buf = vec![];
+        optional::encode::ssz_append(&Some(7u64), &mut buf);
+        assert_eq!(buf, [1, 7, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(optional::encode::ssz_bytes_len(&Some(7u64)), buf.len());
+        assert_eq!(optional::decode::from_ssz_bytes::<u64>(&buf), Ok(Some(7)));
+        assert_eq!(optional::decode::from_ssz_bytes::<u64>(&[0]), Ok(None));
+
+        // an empty string isn't nothing
+        for text in [None, Some(String::new()), Some(String::from("Savings ✓"))] {
+            let mut buf = vec![];
+            optional_text::encode::ssz_append(&text, &mut buf);
+            assert_eq!(optional_text::encode::ssz_bytes_len(&text), buf.len());
+            assert_eq!(optional_text::decode::from_ssz_bytes(&buf), Ok(text));
+        }
+
+        assert_eq!(optional::decode::from_ssz_bytes::<u64>(&[2]), Err(DecodeError::UnionSelectorInvalid(2)));
+        assert!(optional::decode::from_ssz_bytes::<u64>(&[0, 1]).is_err());
+        assert!(optional::decode::from_ssz_bytes::<u64>(&[]).is_err());
+        assert!(text::decode::from_ssz_bytes(&[0xff]).is_err());
+    }
+
+    #[test]
+    fn test_json_or_container() {
+        #[derive(serde::Serialize, serde::Deserialize, ssz_derive::Encode, ssz_derive::Decode, Debug, PartialEq)]
+        struct Container {
+            nonce: u64,
+            data: Vec<u8>,
+        }
+
+        let value = Container { nonce: 3, data: vec![1, 2] };
+        let mut buf = vec![];
+        json_or_container::encode::ssz_append(&value, &mut buf);
+        assert_eq!(buf, br#"{"nonce":3,"data":[1,2]}"#);
+        assert_eq!(json_or_container::decode::from_ssz_bytes::<Container>(&buf), Ok(value));
+        // as version 2 wrote it
+        let value = Container { nonce: 3, data: vec![1, 2] };
+        assert_eq!(json_or_container::decode::from_ssz_bytes::<Container>(&value.as_ssz_bytes()), Ok(value));
+    }
+}
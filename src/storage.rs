@@ -1,372 +1,6659 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
+use std::str::FromStr;
 
 use bip39::{Mnemonic, MnemonicType, Language, Seed};
-use bip32::{XPrv, ChildNumber, PrivateKeyBytes};
-use bip32::secp256k1::elliptic_curve::sec1::ToEncodedPoint;
+use bip32::{XPrv, XPub, ChildNumber, ExtendedKey, ExtendedKeyAttrs, PrivateKeyBytes, Prefix};
+use qrcode::{QrCode, EcLevel};
+use qrcode::render::unicode::Dense1x2;
 use serde::{Serialize, Deserialize};
-use serde_json::Value;
-use hex;
+use ssz_derive::{Decode, Encode};
 use ethereum_tx_sign::RawTransaction;
+use thiserror::Error;
+use zeroize::Zeroizing;
 
-use crate::crypto::{generate_eth_address, keccak512};
-use crate::{read_user_input, utils};
+use crate::crypto::{checksum_address, ct_eq, keccak256, keccak512, salted_hash, slip39, PasswordBox, PasswordKdf, PasswordKey, SealedBox, SecretBuffer};
+use crate::derivation::{self, PathTemplate};
+use crate::labels;
+use crate::amount::AmountEntry;
+use crate::address_book::{AddressBook, ContactTag, DepositCheck};
+use crate::approval::{ApprovalMode, FeeStrategy, StagedTransaction};
+use crate::audit;
+use crate::balance_cache::{self, CachedBalance};
+use crate::backup;
+use crate::eip1559::FeeMarketTransaction;
+use crate::eip7702::Authorization;
+use crate::ens;
+use crate::erc20::{self, Allowance, Token};
+use crate::erc721::{self, Collection, Collections};
+use crate::fees::{self, ClampedGasPrice, FeeMarket, GasPreferences, GasPriceStrategy, GasPricing, TRANSFER_GAS_LIMIT};
+use crate::history::{self, TxRecord};
+use crate::journal::{self, JournalEntry, TransactionStatus};
+use crate::keychain;
+use crate::keystore::{self, Keystore};
+use crate::migrations::{self, VersionError};
+use crate::networks::{self, ChainCheckError, NetworkConfig};
+use crate::session;
+use crate::ssz_format::{self, json, json_or_container, optional, optional_text, text, StorageFormat};
+use crate::settings::{self, Settings};
+use crate::token_registry::TokenRegistry;
+use crate::refresh::{self, RefreshInterval};
+use crate::relayer::{self, SponsoredCall};
+use crate::portfolio::{self, NetworkBalance};
+use crate::prices;
+use crate::rpc::{self, EthRpc};
+use crate::recovery::{self, BackupCheck};
+use crate::schedule::{DueTimer, ScheduledTransaction, PresignedTransaction, PresignedCheck};
+use crate::ur::{HdKey, KeyOrigin, COIN_TYPE_ETH};
+use crate::utils::{self, read_secret_input, read_user_input, MnemonicError};
+use crate::vanity::{self, VanityPattern};
+use crate::watch;
 
-const RINKEBY_CHAIN_ID: u8 = 4;
-const ETH_DERIVE_KEY_PATH: &str = "m/44'/60'/0'/0";
+const ACCOUNT_KEY_PATH: &str = "m/44'/60'/0'";
+const ACCOUNT_KEY_DEPTH: u8 = 3;
+/// The path of the key that early wallet files checked the password against
+const LEGACY_ROOT_PATH: &str = "m/0'";
+/// How many addresses to show for a key imported from another device
+const IMPORTED_KEY_PREVIEW: u32 = 5;
+/// Path recorded for accounts whose key was imported rather than derived
+const IMPORTED_KEY_PATH: &str = "imported";
+/// Domain separation for the key that encrypts imported private keys
+const IMPORTED_KEY_CONTEXT: &[u8] = b"basic-eth imported key";
+/// Key derivation context for the recovery phrase entropy
+pub(crate) const RECOVERY_PHRASE_CONTEXT: &[u8] = b"basic-eth recovery phrase";
+/// Returned by `Account::run` when the account changed and the wallet should be saved
+const CHANGED: u8 = 0;
+/// How long to look for a relayed transaction on chain, as often as receipts are polled for
+const RELAY_WAIT_SECS: u64 = 120;
+/// How long to wait for a transaction just sent to be mined
+const RECEIPT_TIMEOUT_SECS: u64 = 300;
+/// How long a scheduled transaction that couldn't be sent waits before it is tried again
+const SCHEDULED_RETRY_SECS: u64 = 60;
+/// Failed logins in a row before each further attempt waits, starting at a second and doubling
+const FREE_LOGIN_ATTEMPTS: u32 = 3;
+const MAX_LOGIN_DELAY_SECS: u64 = 300;
+/// The start of an encrypted wallet file, followed by the format version
+const FILE_MAGIC: &[u8] = b"BASIC-ETH WALLET";
+const FILE_VERSION: u8 = 1;
+/// A compressed secp256k1 public key, which the verification key of a seed or xprv wallet is
+const VERIFICATION_KEY_LEN: usize = 33;
+/// keccak512, which the pad of a file from before `sealed_secret` is xored with
+const PAD_MAX_LEN: usize = 64;
 
-#[derive(Serialize, Deserialize)]
+/// Why a password didn't unlock a wallet
+#[derive(Error, Debug, PartialEq)]
+pub enum UnlockError {
+    #[error("Incorrect password")]
+    IncorrectPassword,
+    /// What the password is checked against can't be right whatever the password
+    #[error("The wallet file is corrupt: {0}")]
+    Corrupt(String),
+    /// The password is right, but the file doesn't match its MAC
+    #[error("The wallet file has been modified since it was stored, so it won't be opened")]
+    Modified,
+    /// The decrypted file was written by a newer version of the app
+    #[error(transparent)]
+    Version(#[from] VersionError),
+}
+
+/// Why a wallet file couldn't be loaded
+#[derive(Error, Debug, PartialEq)]
+pub enum LoadError {
+    #[error("Error reading {0}: {1}")]
+    Unreadable(String, String),
+    #[error("{0} is empty")]
+    Empty(String),
+    #[error("{0} ends partway through, as if it was cut short ({1})")]
+    Truncated(String, String),
+    #[error("{0} is missing its {1} field")]
+    MissingField(String, String),
+    /// A field that parses but can't be what `store` wrote
+    #[error("{0} has a damaged {1}")]
+    BadField(String, String),
+    #[error("Malformed {0}: {1}")]
+    Malformed(String, String),
+    /// The header of an encrypted file doesn't hold together
+    #[error("{0} is corrupt: {1}")]
+    Corrupt(String, String),
+    #[error(transparent)]
+    Version(#[from] VersionError),
+}
+
+impl LoadError {
+    /// Whether the file itself is damaged, rather than unreadable or from a newer app, so
+    /// restoring or re-importing it is worth offering
+    pub fn is_damaged(&self) -> bool {
+        !matches!(self, LoadError::Unreadable(..) | LoadError::Version(_))
+    }
+
+    fn from_json(file: &str, e: serde_json::Error) -> LoadError {
+        let message = e.to_string();
+        match e.classify() {
+            serde_json::error::Category::Eof => LoadError::Truncated(String::from(file), message),
+            // serde names the field between backticks: "missing field `pad` at line 1 column 9"
+            serde_json::error::Category::Data if message.starts_with("missing field") => {
+                LoadError::MissingField(String::from(file), message.split('`').nth(1).unwrap_or_default().to_string())
+            },
+            _ => LoadError::Malformed(String::from(file), message),
+        }
+    }
+}
+
+/// Length of a new recovery phrase. Longer phrases carry more entropy; the seed is 64 bytes either way.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MnemonicStrength {
+    Words12,
+    Words15,
+    Words18,
+    Words21,
+    Words24,
+}
+
+impl MnemonicStrength {
+    pub const ALL: [MnemonicStrength; 5] = [
+        MnemonicStrength::Words12,
+        MnemonicStrength::Words15,
+        MnemonicStrength::Words18,
+        MnemonicStrength::Words21,
+        MnemonicStrength::Words24,
+    ];
+
+    pub fn word_count(&self) -> usize {
+        self.mnemonic_type().word_count()
+    }
+
+    pub fn entropy_bits(&self) -> usize {
+        self.mnemonic_type().entropy_bits()
+    }
+
+    fn mnemonic_type(&self) -> MnemonicType {
+        match self {
+            MnemonicStrength::Words12 => MnemonicType::Words12,
+            MnemonicStrength::Words15 => MnemonicType::Words15,
+            MnemonicStrength::Words18 => MnemonicType::Words18,
+            MnemonicStrength::Words21 => MnemonicType::Words21,
+            MnemonicStrength::Words24 => MnemonicType::Words24,
+        }
+    }
+}
+
+/// The BIP-39 wordlist a recovery phrase is written in
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum MnemonicLanguage {
+    #[default]
+    English,
+    ChineseSimplified,
+    ChineseTraditional,
+    French,
+    Italian,
+    Japanese,
+    Korean,
+    Spanish,
+}
+
+impl MnemonicLanguage {
+    pub const ALL: [MnemonicLanguage; 8] = [
+        MnemonicLanguage::English,
+        MnemonicLanguage::ChineseSimplified,
+        MnemonicLanguage::ChineseTraditional,
+        MnemonicLanguage::French,
+        MnemonicLanguage::Italian,
+        MnemonicLanguage::Japanese,
+        MnemonicLanguage::Korean,
+        MnemonicLanguage::Spanish,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MnemonicLanguage::English => "English",
+            MnemonicLanguage::ChineseSimplified => "Chinese (Simplified)",
+            MnemonicLanguage::ChineseTraditional => "Chinese (Traditional)",
+            MnemonicLanguage::French => "French",
+            MnemonicLanguage::Italian => "Italian",
+            MnemonicLanguage::Japanese => "Japanese",
+            MnemonicLanguage::Korean => "Korean",
+            MnemonicLanguage::Spanish => "Spanish",
+        }
+    }
+
+    fn language(&self) -> Language {
+        match self {
+            MnemonicLanguage::English => Language::English,
+            MnemonicLanguage::ChineseSimplified => Language::ChineseSimplified,
+            MnemonicLanguage::ChineseTraditional => Language::ChineseTraditional,
+            MnemonicLanguage::French => Language::French,
+            MnemonicLanguage::Italian => Language::Italian,
+            MnemonicLanguage::Japanese => Language::Japanese,
+            MnemonicLanguage::Korean => Language::Korean,
+            MnemonicLanguage::Spanish => Language::Spanish,
+        }
+    }
+
+    fn of(mnemonic: &Mnemonic) -> Self {
+        *MnemonicLanguage::ALL.iter().find(|l| l.language() == mnemonic.language()).unwrap()
+    }
+
+    /// Parses a phrase against every wordlist and returns each reading that is valid. There can
+    /// be more than one, since the two Chinese wordlists share characters.
+    /// If none is valid, the error is the one from the wordlist closest to the phrase.
+    pub fn parse_phrase(phrase: &str) -> Result<Vec<(MnemonicLanguage, Mnemonic)>, MnemonicError> {
+        let mut errors = vec![];
+        let mut parsed = vec![];
+        for language in MnemonicLanguage::ALL {
+            match utils::parse_mnemonic(phrase, language.language()) {
+                Ok(m) => parsed.push((language, m)),
+                Err(e) => errors.push(e),
+            }
+        }
+        if !parsed.is_empty() {
+            return Ok(parsed);
+        }
+
+        // a checksum failure means every word matched that wordlist
+        Err(errors.into_iter().min_by_key(|e| match e {
+            MnemonicError::UnknownWords(words) => words.len(),
+            MnemonicError::WordCount(_) | MnemonicError::Checksum => 0,
+        }).unwrap())
+    }
+}
+
+/// What the wallet encrypts, which depends on how the wallet was created
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum KeySource {
+    /// The BIP-39 seed
+    #[default]
+    Seed,
+    /// The private key and chain code of a deriving key imported as an xprv. There is no seed.
+    ExtendedKey,
+    /// Nothing: a watch-only wallet holds an account-level xpub and no private key. An empty
+    /// secret is encrypted, so the password still guards the view. In older files the pad is a
+    /// salt and the verification key a salted hash of the password.
+    WatchOnly,
+    /// The private key and chain code of a random deriving key, as for `ExtendedKey`. It derives
+    /// no accounts and only seals the one imported key of a wallet exported from a single
+    /// account of another.
+    ExportedAccount,
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode)]
 pub struct Wallet {
-    /// Encoded wallet seed
+    /// The version of the file's layout, brought up to date by `upgrade_version`. Files from
+    /// before it was recorded are written without it until then.
+    #[serde(default = "migrations::unversioned", skip_serializing_if = "migrations::is_unversioned")]
+    pub version: u32,
+    /// The secret xored with keccak512 of the password, in files from before `sealed_secret`.
+    /// Empty in newer ones.
     pub pad: Vec<u8>,
-    /// The public key used to verify logins
+    /// The public key at m/44'/60'/0', or of the imported deriving key. Files from before
+    /// `sealed_secret` check logins against it.
     pub verification_key: Vec<u8>,
+    /// The secret, encrypted with a key derived from the password and a random salt. Every wallet
+    /// gets its own salt, and a new one whenever the password changes, so wallets sharing a
+    /// password don't share a key. Older files have `pad` instead, until the next login rewrites
+    /// them.
+    #[serde(default)]
+    #[ssz(with = "json")]
+    pub sealed_secret: Option<PasswordBox>,
+    /// The wordlist of the recovery phrase
+    #[serde(default)]
+    #[ssz(with = "json")]
+    pub language: MnemonicLanguage,
+    /// What the pad encrypts
+    #[serde(default)]
+    #[ssz(with = "json")]
+    pub source: KeySource,
+    /// The name the wallet was registered under. Empty in files from before wallets had names.
+    #[serde(default)]
+    #[ssz(with = "text")]
+    pub name: String,
+    /// When the wallet was created or restored, unless that was before this was recorded
+    #[serde(default)]
+    #[ssz(with = "optional")]
+    pub created_at: Option<u64>,
+    /// Chain id of the network the wallet was created for
+    #[serde(default)]
+    #[ssz(with = "optional")]
+    pub network: Option<u64>,
+    /// Failed logins since the last successful one, kept in the file so restarting doesn't reset
+    /// the delay they add
+    #[serde(default)]
+    pub failed_logins: u32,
     /// Accounts associated with this wallet
-    accounts_metadata: AccountMetadata,
+    #[ssz(with = "json_or_container")]
+    pub(crate) accounts_metadata: AccountMetadata,
+    /// HMAC of everything above but `failed_logins`, keyed from the password key that opens
+    /// `sealed_secret`, so a file edited without the password is refused. Every file with a
+    /// sealed secret has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[ssz(with = "optional_text")]
+    mac: Option<String>,
+    /// The key derived from the password that seals `sealed_secret`, kept from the last time it
+    /// was opened or sealed. It also keys `mac` and encrypts the file, and is what the system
+    /// keychain keeps.
+    #[serde(skip)]
+    #[ssz(skip_serializing, skip_deserializing)]
+    pub(crate) password_key: Option<PasswordKey>,
+    /// Whether `store` encrypts the whole file. False for a file loaded as plain JSON, which stays
+    /// that way until the user agrees to encrypt it.
+    #[serde(skip)]
+    #[ssz(skip_serializing, skip_deserializing)]
+    encrypt: bool,
+    /// The file as loaded, until the password decrypts it. Every other field holds a
+    /// placeholder until then.
+    #[serde(skip)]
+    #[ssz(skip_serializing, skip_deserializing)]
+    encrypted_file: Option<EncryptedFile>,
+    /// The file `store` writes to, set by `load` or `set_location`
+    #[serde(skip)]
+    #[ssz(skip_serializing, skip_deserializing)]
+    file: String,
 }
 
 impl Wallet {
-    /// Creates a new wallet with the given password
-    pub fn new(password: String) -> Wallet {
-        let mnemonic = Mnemonic::new(MnemonicType::Words12, Language::English);
-        let phrase = mnemonic.phrase();
-        let seed = Seed::new(&mnemonic, "");
-        println!("Here is your secret recovery phrase: {}", phrase);
+    /// Creates a new wallet with the given password and a recovery phrase of the given length.
+    /// `passphrase` is the optional BIP-39 passphrase; an empty one means none.
+    pub fn new(password: String, strength: MnemonicStrength, language: MnemonicLanguage, passphrase: &str) -> Wallet {
+        let mnemonic = Mnemonic::new(strength.mnemonic_type(), language.language());
+        println!("Here is your secret recovery phrase: {}", mnemonic.phrase());
+        Wallet::from(password, mnemonic, passphrase)
+    }
 
-        Wallet::generate_wallet(seed.as_bytes(), password)
+    /// Creates a new wallet whose recovery phrase encodes entropy supplied by the user, rather
+    /// than entropy from the process RNG
+    pub fn from_entropy(password: String, entropy: &[u8], language: MnemonicLanguage, passphrase: &str) -> Result<Wallet, String> {
+        let mnemonic = Mnemonic::from_entropy(entropy, language.language()).map_err(|e| e.to_string())?;
+        println!("Here is your secret recovery phrase: {}", mnemonic.phrase());
+        Ok(Wallet::from(password, mnemonic, passphrase))
     }
 
-    /// Recreates a wallet with the given seed phrase and new password
-    pub fn from(password: String, mnemonic: Mnemonic) -> Wallet {
-        let seed = Seed::new(&mnemonic, "");
-        Wallet::generate_wallet(seed.as_bytes(), password)
+    /// Recreates a wallet with the given seed phrase, BIP-39 passphrase and new password. Only the
+    /// seed is kept, so the passphrase is never written to disk.
+    pub fn from(password: String, mnemonic: Mnemonic, passphrase: &str) -> Wallet {
+        Wallet::from_with_template(password, mnemonic, passphrase, PathTemplate::standard())
+    }
+
+    /// Like `from`, with accounts laid out by `template` instead of the standard layout
+    pub fn from_with_template(password: String, mnemonic: Mnemonic, passphrase: &str, template: PathTemplate) -> Wallet {
+        let seed = Seed::new(&mnemonic, passphrase);
+        let mut wallet = Wallet::generate_wallet(seed.as_bytes(), password, template);
+        wallet.language = MnemonicLanguage::of(&mnemonic);
+        wallet.accounts_metadata.set_backup_check(mnemonic.phrase());
+        wallet.accounts_metadata.recovery_phrase = Some(SealedBox::seal(RECOVERY_PHRASE_CONTEXT, seed.as_bytes(), mnemonic.entropy()));
+        wallet
     }
 
-    /// Utility function to generate a fresh wallet instance
-    fn generate_wallet(seed: &[u8], password: String) -> Wallet {
-        let pad = utils::xor(seed, &keccak512(password.as_bytes())).unwrap();
-        let (_, verification_key) = utils::create_keys_from_path(seed, "m/44'/60'/0'");
-        let (parent_derive_xprv, _) = utils::create_keys_from_path(seed, ETH_DERIVE_KEY_PATH);
+    /// Restores a wallet from the master secret of a SLIP-39 backup, which is used as the seed.
+    /// There is no recovery phrase, so backup checks aren't available.
+    pub fn from_master_secret(password: String, secret: &[u8]) -> Result<Wallet, String> {
+        // the longest seed BIP-32 takes
+        if secret.len() > 64 {
+            return Err(String::from("Master secrets longer than 512 bits are not supported"));
+        }
+        Ok(Wallet::generate_wallet(secret, password, PathTemplate::standard()))
+    }
+
+    /// Utility function to generate a fresh wallet instance. The seed may be shorter than 64
+    /// bytes, from a SLIP-39 backup.
+    fn generate_wallet(seed: &[u8], password: String, template: PathTemplate) -> Wallet {
+        let (_, verification_key) = utils::create_keys_from_path(seed, ACCOUNT_KEY_PATH);
+        let (parent_derive_xprv, _) = utils::create_keys_from_path(seed, &template.root());
+        let (sealed_secret, password_key) = seal_secret(&password, seed);
 
         Wallet {
-            pad,
+            version: migrations::CURRENT_VERSION,
+            pad: vec![],
             verification_key: verification_key.to_bytes().to_vec(),
-            accounts_metadata: AccountMetadata::new(parent_derive_xprv),
+            sealed_secret: Some(sealed_secret),
+            language: MnemonicLanguage::English,
+            source: KeySource::Seed,
+            name: String::new(),
+            created_at: Some(utils::now()),
+            network: Some(networks::active().chain_id),
+            failed_logins: 0,
+            accounts_metadata: AccountMetadata::new(parent_derive_xprv, template),
+            mac: None,
+            password_key: Some(password_key),
+            encrypt: true,
+            encrypted_file: None,
+            file: String::new(),
         }
     }
 
-    /// Stores the key user data that is necessary for logging in again
-    pub fn store(&mut self) -> Result<(), String> {
-        let mut file = File::create("userdata.txt").unwrap();
+    /// Restores a wallet from a Base58 xprv, used as the parent at m/44'/60'/0'/0 that derives
+    /// every account. The key itself is encrypted, since there is no seed.
+    pub fn from_xprv(password: String, xprv: &str) -> Result<Wallet, String> {
+        let imported = XPrv::from_str(xprv.trim()).map_err(|_e| String::from("Not a valid xprv"))?;
+        let mut secret = Zeroizing::new(imported.to_bytes().to_vec());
+        secret.extend_from_slice(&imported.attrs().chain_code);
+        // rebuilt as it will be on every login, at the depth of m/44'/60'/0'/0
+        let deriving_key = xprv_from_parts(&secret).unwrap();
+        let (sealed_secret, password_key) = seal_secret(&password, &secret);
 
-        // clear all sensitive data
-        self.accounts_metadata.deriving_key = None;
-        for account in &mut self.accounts_metadata.accounts {
-            account.prv_key = None;
+        Ok(Wallet {
+            version: migrations::CURRENT_VERSION,
+            pad: vec![],
+            verification_key: deriving_key.public_key().to_bytes().to_vec(),
+            sealed_secret: Some(sealed_secret),
+            language: MnemonicLanguage::English,
+            source: KeySource::ExtendedKey,
+            name: String::new(),
+            created_at: Some(utils::now()),
+            network: Some(networks::active().chain_id),
+            failed_logins: 0,
+            accounts_metadata: AccountMetadata::new(deriving_key, PathTemplate::standard()),
+            mac: None,
+            password_key: Some(password_key),
+            encrypt: true,
+            encrypted_file: None,
+            file: String::new(),
+        })
+    }
+
+    /// Creates a watch-only wallet from the Base58 xpub at m/44'/60'/0', as exported by a wallet on
+    /// an air-gapped machine. Accounts are derived from it with public derivation only, so the
+    /// wallet can show balances but never sign.
+    pub fn watch_only(password: String, xpub: &str) -> Result<Wallet, String> {
+        let account_key = XPub::from_str(xpub.trim()).map_err(|_e| String::from("Not a valid xpub"))?;
+        let depth = account_key.attrs().depth;
+        if depth != ACCOUNT_KEY_DEPTH {
+            return Err(format!("Expected the account-level xpub at {}, not a key at depth {}", ACCOUNT_KEY_PATH, depth));
         }
+        let (sealed_secret, password_key) = seal_secret(&password, &[]);
+        Ok(Wallet {
+            version: migrations::CURRENT_VERSION,
+            pad: vec![],
+            verification_key: vec![],
+            sealed_secret: Some(sealed_secret),
+            language: MnemonicLanguage::English,
+            source: KeySource::WatchOnly,
+            name: String::new(),
+            created_at: Some(utils::now()),
+            network: Some(networks::active().chain_id),
+            failed_logins: 0,
+            accounts_metadata: AccountMetadata::watching(&account_key),
+            mac: None,
+            password_key: Some(password_key),
+            encrypt: true,
+            encrypted_file: None,
+            file: String::new(),
+        })
+    }
 
-        let data_bytes = serde_json::to_vec(self).unwrap();
+    /// Creates a wallet holding nothing but the account with the private key `secret`, as an
+    /// imported account labelled `label`, and `address_book`, to hand that one account to someone
+    /// else. Its deriving key is random, and only seals the imported key.
+    pub(crate) fn from_exported_account(password: String, secret: &[u8; 32], label: Option<String>, address_book: AddressBook) -> Wallet {
+        let (deriving_secret, deriving_key) = loop {
+            let secret = Zeroizing::new([rand::random::<[u8; 32]>(), rand::random()].concat());
+            // almost every 32 bytes are a valid key
+            if let Some(key) = xprv_from_parts(&secret) {
+                break (secret, key);
+            }
+        };
+        let (sealed_secret, password_key) = seal_secret(&password, &deriving_secret);
 
-        match file.write_all(&data_bytes) {
-            Ok(()) => Ok(()),
-            Err(e) => Err(format!("Error writing to file: {}", e)),
+        let mut accounts_metadata = AccountMetadata::new(deriving_key.clone(), PathTemplate::standard());
+        accounts_metadata.accounts.clear();
+        let address = utils::secret_key_to_address(secret).unwrap();
+        let sealed = SealedBox::seal(IMPORTED_KEY_CONTEXT, &*key_bytes(&deriving_key), secret.as_slice());
+        accounts_metadata.external.push(Account { label, ..Account::imported(address, sealed) });
+        accounts_metadata.address_book = address_book;
+
+        Wallet {
+            version: migrations::CURRENT_VERSION,
+            pad: vec![],
+            verification_key: deriving_key.public_key().to_bytes().to_vec(),
+            sealed_secret: Some(sealed_secret),
+            language: MnemonicLanguage::English,
+            source: KeySource::ExportedAccount,
+            name: String::new(),
+            created_at: Some(utils::now()),
+            network: Some(networks::active().chain_id),
+            failed_logins: 0,
+            accounts_metadata,
+            mac: None,
+            password_key: Some(password_key),
+            encrypt: true,
+            encrypted_file: None,
+            file: String::new(),
         }
     }
 
-    pub fn verify_password(&mut self, password: String) -> bool {
-        let password_hash = keccak512(password.as_bytes());
-        let seed = utils::xor(&password_hash, &self.pad).unwrap();
-        let (_, xpub) = utils::create_keys_from_path(&seed, "m/44'/60'/0'");
+    /// Splits the seed into `count` SLIP-39 shares, any `threshold` of which restore the wallet
+    /// with `from_master_secret`
+    pub fn shamir_shares(&self, password: &str, threshold: u8, count: u8, passphrase: &str) -> Result<Vec<Zeroizing<String>>, String> {
+        match self.source {
+            KeySource::Seed => {},
+            KeySource::ExtendedKey => return Err(String::from("This wallet was restored from an xprv, so it has no seed to split")),
+            KeySource::WatchOnly => return Err(String::from("This wallet is watch-only, so it has no seed to split")),
+            KeySource::ExportedAccount => return Err(String::from("This wallet holds a single exported account, so it has no seed to split")),
+        }
+        let seed = self.unlock_secret(password).map_err(|e| e.to_string())?;
+        slip39::split(&seed, threshold, count, passphrase)
+    }
 
-        if xpub.to_bytes().to_vec() == self.verification_key {
-            // set the deriving key
-            let (parent_derive_xprv, _) = utils::create_keys_from_path(&seed, ETH_DERIVE_KEY_PATH);
-            self.accounts_metadata.deriving_key = Some(parent_derive_xprv);
+    /// Quizzes the user on a few words of a newly created recovery phrase, and only returns once
+    /// they answer correctly. After a wrong answer the phrase can be shown again.
+    /// Fails without quizzing, and leaves the wallet as it was, if the phrase or the words to
+    /// check it against can't be recovered with `password`.
+    pub fn confirm_new_phrase(&mut self, password: &str) -> Result<(), String> {
+        let seed = self.unlock_secret(password).map_err(|e| e.to_string())?;
+        let phrase = self.accounts_metadata.recovery_phrase(&seed, self.language)
+            .ok_or("The recovery phrase couldn't be decrypted")?;
+        let secret = key_bytes(self.accounts_metadata.deriving_key.as_ref().ok_or("The wallet is locked")?);
+        let backup = self.accounts_metadata.backup.as_mut().ok_or("The wallet holds nothing to check the phrase against")?;
 
-            true
-        } else {
-            false
+        println!("Write the recovery phrase down now. To make sure you have, a few words will be asked.");
+        while !backup_quiz_passes(backup, &*secret) {
+            println!("That word doesn't match. Type s to show the phrase again, or press enter to retry:");
+            if utils::read_user_input() == "s" {
+                println!("Here is your secret recovery phrase: {}", phrase.as_str());
+            }
         }
+        backup.last_verified = Some(utils::now());
+        println!("Your recovery phrase backup checks out");
+        Ok(())
     }
 
-    /// Starts the wallet with the default account
-    pub fn run(&mut self) {
-        // fetch the deriving key
-        let deriving_key = match &self.accounts_metadata.deriving_key {
-            Some(k) => k.clone(),
-            None => unreachable!("Deriving key must've been created if wallet was created"),
-        };
+    /// Loads the wallet stored by `store` in `file`. It stays locked until `verify_password`
+    /// succeeds, and an encrypted file shows nothing but its failed logins until then. A file
+    /// that doesn't match its checksum is reported as corrupt here, so a wallet that fails to
+    /// unlock afterwards was given the wrong password.
+    /// A damaged file is reported as it is; `load_previous` reads the version kept before it.
+    pub fn load(file: &str) -> Result<Wallet, LoadError> {
+        let mut wallet = Wallet::parse_file(file)?;
+        wallet.file = String::from(file);
+        Ok(wallet)
+    }
 
-        // start account actions
-        match self.accounts_metadata.run(deriving_key) {
-            5 => {
-                match self.store() {
-                    Ok(()) => println!("Stored wallet data safely"),
-                    Err(e) => println!("{}", e),
+    /// Loads the previous version of `file` that `store` kept, to see whether it is intact before
+    /// `restore_previous` puts it back
+    pub fn load_previous(file: &str) -> Result<Wallet, LoadError> {
+        Wallet::parse_file(&backup_path(file))
+    }
+
+    fn parse_file(file: &str) -> Result<Wallet, LoadError> {
+        let data = std::fs::read(file).map_err(|e| LoadError::Unreadable(String::from(file), e.to_string()))?;
+        Wallet::from_file_bytes(&data, file)
+    }
+
+    /// Parses the contents of a wallet file, read from `file`
+    fn from_file_bytes(data: &[u8], file: &str) -> Result<Wallet, LoadError> {
+        if data.iter().all(u8::is_ascii_whitespace) {
+            return Err(LoadError::Empty(String::from(file)));
+        }
+        match EncryptedFile::parse(data) {
+            Some(encrypted) => Ok(Wallet::encrypted(encrypted.map_err(|e| LoadError::Corrupt(String::from(file), e))?)),
+            None => {
+                migrations::check(data)?;
+                let wallet = match ssz_format::strip_magic(data) {
+                    Some(ssz) => ssz_format::decode(ssz).map_err(|e| LoadError::Malformed(String::from(file), e))?,
+                    None => serde_json::from_slice::<Wallet>(data).map_err(|e| LoadError::from_json(file, e))?,
                 };
+                wallet.check_fields().map_err(|field| LoadError::BadField(String::from(file), field))?;
+                Ok(wallet)
             },
-            _ => unreachable!("Code should only return quit flag (5)"),
+        }
+    }
+
+    /// Checks the hex fields, whose damage would otherwise only show as a wrong password or a
+    /// modified file
+    fn check_fields(&self) -> Result<(), String> {
+        if let Some(sealed_secret) = &self.sealed_secret {
+            sealed_secret.check().map_err(|e| format!("sealed secret: its {}", e))?;
+        }
+        match &self.mac {
+            Some(mac) if mac.len() != 64 || hex::decode(mac).is_err() => Err(format!("MAC: it is {:?}, not 64 hex digits", mac)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Parses a wallet as `store` serialized it, in SSZ if it starts with `ssz_format::MAGIC`
+    /// and in JSON otherwise
+    fn parse(data: &[u8]) -> Result<Wallet, String> {
+        match ssz_format::strip_magic(data) {
+            Some(ssz) => ssz_format::decode(ssz),
+            None => serde_json::from_slice(data).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// When the wallet stored in `file` was created, if that is readable without the password
+    pub fn created_at_of(file: &str) -> Option<u64> {
+        Wallet::parse_file(file).ok()?.created_at
+    }
+
+    /// The placeholder for an encrypted file until its password is entered
+    fn encrypted(encrypted_file: EncryptedFile) -> Wallet {
+        Wallet {
+            version: migrations::CURRENT_VERSION,
+            pad: vec![],
+            verification_key: vec![],
+            sealed_secret: None,
+            language: MnemonicLanguage::English,
+            source: KeySource::Seed,
+            name: String::new(),
+            created_at: None,
+            network: None,
+            failed_logins: encrypted_file.header.failed_logins,
+            accounts_metadata: AccountMetadata::default(),
+            mac: None,
+            password_key: None,
+            encrypt: true,
+            encrypted_file: Some(encrypted_file),
+            file: String::new(),
+        }
+    }
+
+    /// Decrypts the file of a wallet loaded encrypted, replacing the placeholder with what it
+    /// holds
+    fn decrypt_file(&mut self, key: &PasswordKey) -> Result<(), UnlockError> {
+        let body = self.encrypted_file.as_ref().and_then(|e| e.open(key)).ok_or(UnlockError::IncorrectPassword)?;
+        migrations::check(&body)?;
+        let mut wallet = Wallet::parse(&body)
+            .map_err(|e| UnlockError::Corrupt(format!("it decrypted, but what it holds is malformed: {}", e)))?;
+        wallet.adopt_name(&self.name);
+        wallet.failed_logins = self.failed_logins;
+        wallet.encrypt = true;
+        wallet.file = std::mem::take(&mut self.file);
+        *self = wallet;
+        Ok(())
+    }
+
+    /// Whether `store` encrypts the whole file
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypt || self.encrypted_file.is_some()
+    }
+
+    /// Encrypts the file of a wallet loaded as plain JSON, once its password has been verified
+    pub fn encrypt_file(&mut self) -> Result<(), String> {
+        self.encrypt = true;
+        let stored = self.store();
+        if stored.is_err() {
+            self.encrypt = false;
+        }
+        stored
+    }
+
+    /// Names a new wallet and sets the file it is stored in, as handed out by
+    /// `Registry::register`
+    pub fn set_location(&mut self, name: &str, file: String) {
+        self.name = String::from(name);
+        self.file = file;
+    }
+
+    /// Names a wallet stored before wallets had names, so the name is written on the next store
+    pub fn adopt_name(&mut self, name: &str) {
+        if self.name.is_empty() {
+            self.name = String::from(name);
+        }
+    }
+
+    /// The line shown before the password prompt, e.g.
+    /// "Unlocking wallet 'savings' (created 2024-01-03, Sepolia)"
+    pub fn banner(&self) -> String {
+        let details: Vec<String> = self.created_at.map(|t| format!("created {}", utils::format_date(t))).into_iter()
+            .chain(self.network.map(settings::network_name))
+            .collect();
+        match details.is_empty() {
+            true => format!("Unlocking wallet '{}'", self.name),
+            false => format!("Unlocking wallet '{}' ({})", self.name, details.join(", ")),
+        }
+    }
+
+    /// Stores the key user data that is necessary for logging in again. The file is written
+    /// next to the old one and renamed over it, so a failed write never leaves it half written.
+    /// The wallet stays unlocked, so it can be stored after every change.
+    pub fn store(&mut self) -> Result<(), String> {
+        self.store_as(Settings::load().storage.format())
+    }
+
+    /// Like `store`, serializing the wallet as `format` instead of the configured format
+    fn store_as(&mut self, format: StorageFormat) -> Result<(), String> {
+        if self.encrypted_file.is_some() {
+            return Err(String::from("The wallet file can't be stored before it is decrypted"));
+        }
+        let data_bytes = self.serialize(format);
+        let data_bytes = match (self.encrypt, &self.password_key) {
+            (true, Some(key)) => EncryptedFile::seal(key, &data_bytes, self.failed_logins).to_bytes(),
+            (true, None) => return Err(String::from("The wallet file can't be encrypted before its secret is sealed")),
+            (false, _) => data_bytes,
         };
+        write_file(&self.file, &data_bytes)
     }
-}
 
-#[derive(Serialize, Deserialize)]
-struct AccountMetadata {
-    /// The parent private key deriving all accounts
-    #[serde(skip)]
-    pub deriving_key: Option<XPrv>,
-    /// A vector of derived accounts
-    pub accounts: Vec<Account>,
-}
+    /// Stores the wallet and scrubs its secrets, so it needs the password again
+    fn lock(&mut self) -> Result<(), String> {
+        let stored = self.store();
+        self.scrub_secrets();
+        stored
+    }
 
-impl AccountMetadata {
-    /// Creates AccountMetadata with the private deriving key and a default account
-    pub fn new(deriving_key: XPrv) -> Self {
-        AccountMetadata {
-            deriving_key: Some(deriving_key.clone()),
-            accounts: vec![Account::new(&deriving_key, 0)]
+    /// Stores the wallet with the accounts the menus took out of it, and puts them back
+    fn save_accounts(&mut self, metadata: &mut AccountMetadata) {
+        std::mem::swap(&mut self.accounts_metadata, metadata);
+        let stored = self.store();
+        std::mem::swap(&mut self.accounts_metadata, metadata);
+        if let Err(e) = stored {
+            println!("Couldn't save the wallet: {}", e);
         }
     }
 
-    /// Creates a new account with specified index and returns a reference to it
-    pub fn create_account(&mut self, index: usize) -> &mut Account {
-        match &self.deriving_key {
-            Some(k) => {
-                let account = Account::new(k, index);
-                self.accounts.push(account);
-                self.get_account(index)
+    /// Writes an encrypted copy of the wallet file to a backup file in `dir`, named after the
+    /// wallet and the time, and returns its path. Unlike the recovery phrase it keeps the
+    /// accounts' labels, nonces and history. It opens with the current password. The wallet
+    /// must be unlocked.
+    pub fn back_up(&mut self, dir: &str) -> Result<std::path::PathBuf, String> {
+        if self.password_key.is_none() {
+            return Err(String::from("This wallet file is too old to back up. Log in again to upgrade it first."));
+        }
+        let body = self.serialize(Settings::load().storage.format());
+        let data = EncryptedFile::seal(self.password_key.as_ref().unwrap(), &body, 0).to_bytes();
+        backup::write(std::path::Path::new(dir), &self.name, &data)
+    }
+
+    /// Sets the failed login count in the stored file and nothing else. The wallet is locked
+    /// whenever it changes, so storing all of it would write back whatever was loaded. An
+    /// encrypted file keeps the count in its header, which changes without the password.
+    fn store_failed_logins(&self) -> Result<(), String> {
+        let data = std::fs::read(&self.file).map_err(|e| format!("Error reading {}: {}", self.file, e))?;
+        let data = match EncryptedFile::parse(&data) {
+            Some(encrypted) => {
+                let mut encrypted = encrypted.map_err(|e| format!("{} is corrupt: {}", self.file, e))?;
+                encrypted.header.failed_logins = self.failed_logins;
+                encrypted.to_bytes()
+            },
+            // read as a wallet, since a JSON value would turn amounts too large for a u64 into floats
+            None => {
+                let mut wallet = Wallet::parse(&data).map_err(|e| format!("Malformed {}: {}", self.file, e))?;
+                wallet.failed_logins = self.failed_logins;
+                match ssz_format::strip_magic(&data) {
+                    Some(_) => ssz_format::encode(&wallet),
+                    None => serde_json::to_vec(&wallet).unwrap(),
+                }
             },
-            None => unreachable!(),
+        };
+        write_file(&self.file, &data)
+    }
+
+    /// Serializes the wallet as `format`
+    fn serialize(&mut self, format: StorageFormat) -> Vec<u8> {
+        let data_bytes = self.to_bytes();
+        match format {
+            StorageFormat::Json => data_bytes,
+            StorageFormat::Ssz => ssz_format::encode(self),
         }
     }
 
-    /// Returns the first account of the accounts vector
-    pub fn default_account(&mut self) -> &mut Account {
-        &mut self.accounts[0]
+    /// Serializes the wallet as it is written to disk. The deriving key is never written, and
+    /// stays in memory until the wallet is locked.
+    pub fn to_bytes(&mut self) -> Vec<u8> {
+        let metadata = &mut self.accounts_metadata;
+        if let (Some(approval), Some(deriving_key)) = (&mut metadata.approval, &metadata.deriving_key) {
+            approval.seal(&*key_bytes(deriving_key));
+        }
+        if let Some(key) = self.password_key.take() {
+            self.mac = Some(self.file_mac(&key));
+            self.password_key = Some(key);
+        }
+        serde_json::to_vec(self).unwrap()
+    }
+
+    /// The MAC of the wallet as serialized without the MAC itself and with no failed logins, which
+    /// change without the password. Serializing is deterministic, since nothing stored is a map.
+    fn file_mac(&mut self, key: &PasswordKey) -> String {
+        let mac = self.mac.take();
+        let failed_logins = std::mem::take(&mut self.failed_logins);
+        let data = serde_json::to_vec(self).unwrap();
+        self.mac = mac;
+        self.failed_logins = failed_logins;
+        hex::encode(key.mac(&data))
     }
 
-    /// Prints all the created accounts in the wallet
-    pub fn print_accounts(&self) {
-        for (index, acc) in self.accounts.iter().enumerate() {
-            println!("{}) {}", index, acc.address);
+    /// Checks a loaded file against its MAC, under the key that opened its sealed secret
+    fn check_mac(&mut self, key: &PasswordKey) -> Result<(), UnlockError> {
+        let expected = self.file_mac(key);
+        let mac = self.mac.as_ref().and_then(|mac| hex::decode(mac).ok()).unwrap_or_default();
+        if !ct_eq(&mac, &hex::decode(expected).unwrap()) {
+            return Err(UnlockError::Modified);
         }
+        Ok(())
     }
 
-    /// Returns the account with given index
-    pub fn get_account(&mut self, index: usize) -> &mut Account {
-        &mut self.accounts[index]
+    /// Zeroizes the deriving key, and hides protected accounts again
+    fn scrub_secrets(&mut self) {
+        // XPrv zeroizes its key when dropped
+        self.accounts_metadata.deriving_key = None;
+        self.accounts_metadata.elevated = false;
+        if let Some(approval) = &mut self.accounts_metadata.approval {
+            approval.close();
+        }
     }
 
-    /// Runs an account, allowing for creation of new accounts and switching between accounts when user opts to do so.
-    pub fn run(&mut self, deriving_key: XPrv) -> u8 {
-        let mut account = self.default_account();
+    /// Verifies a login password after the delay earned by earlier failures. A wrong password is
+    /// counted in the file, and a success clears the count once it has been reported.
+    pub fn login(&mut self, password: String) -> Result<(), UnlockError> {
+        let delay = login_delay(self.failed_logins);
+        if !delay.is_zero() {
+            println!("Waiting {} seconds after {} failed attempts...", delay.as_secs(), self.failed_logins);
+            std::thread::sleep(delay);
+        }
+        let unlocked = self.verify_password(password);
+        self.record_login(&unlocked);
+        unlocked
+    }
 
-        loop {
-            match account.run(&deriving_key) {
-                3 => {
-                    let index = self.accounts.len();
-                    account = self.create_account(index);
-                },
-                4 => {
-                    self.print_accounts();
-                    // switch to user selected account
-                    let option = utils::read_user_input().parse::<usize>().unwrap();
-                    account = self.get_account(option);
-                },
-                5 => {
-                    return 5;
-                },
-                _ => print!("Invalid option"),
-            }
+    /// Unlocks with the password key kept in the system keychain, which counts as a login
+    pub fn login_with_keychain(&mut self) -> Result<(), String> {
+        let key = keychain::load(&self.file)?;
+        let unlocked = self.unlock_with_key(key);
+        if unlocked.is_ok() {
+            self.record_login(&unlocked);
         }
+        unlocked.map_err(|e| match e {
+            UnlockError::IncorrectPassword => String::from("The key in the system keychain doesn't open this wallet. The password may have changed since it was added."),
+            e => e.to_string(),
+        })
     }
-}
 
-#[derive(Serialize, Deserialize, Clone)]
-struct Account {
-    /// The number of confirmed transactions sent from this account
-    pub nonce: u64,
-    /// The full HD derivation path of this account
-    pub path: String,
-    /// The address of this account
-    pub address: String,
-    /// The private key of the account
-    prv_key: Option<PrivateKeyBytes>,
-}
+    /// Keeps the password key in the system keychain, so the wallet opens without the password.
+    /// The wallet must be unlocked.
+    pub fn remember_in_keychain(&self) -> Result<(), String> {
+        let key = self.password_key.as_ref().ok_or_else(|| String::from("This wallet file is too old to keep its key in the keychain"))?;
+        keychain::store(&self.file, &key.to_bytes())
+    }
 
-impl Account {
-    /// Creates a new account with nonce as 0 and private_key set to none. Private key can later be
-    /// instantiated when needed for signing a transaction.
-    /// deriving_key - the parent key with path m/44'/60'/0'/0, used to derive all child accounts
-    /// index - the index of the child account
-    ///
-    /// The returned key has path: m/44'/60'/0'/0/x, where x = 0,1,2,3...
-    pub fn new(deriving_key: &XPrv, index: usize) -> Self {
-        let child_number = ChildNumber::new(index as u32, false).unwrap();
-        let child_xprv = deriving_key.derive_child(child_number).unwrap();
-        let child_xpub = child_xprv.public_key();
+    /// Counts a failed login in the file, or reports and clears the count after a success. A
+    /// damaged or modified file isn't a guess at the password, so it isn't counted.
+    fn record_login(&mut self, unlocked: &Result<(), UnlockError>) {
+        let failed_logins = match unlocked {
+            Ok(()) => {
+                if self.failed_logins > 0 {
+                    println!("Warning: {} failed login attempts since the last successful login", self.failed_logins);
+                }
+                0
+            },
+            Err(UnlockError::IncorrectPassword) => {
+                let failed_logins = self.failed_logins.saturating_add(1);
+                println!("Incorrect password ({} failed attempts)", failed_logins);
+                failed_logins
+            },
+            Err(e) => {
+                println!("{}", e);
+                return;
+            },
+        };
+        if failed_logins != self.failed_logins {
+            self.failed_logins = failed_logins;
+            if let Err(e) = self.store_failed_logins() {
+                println!("{}", e);
+            }
+        }
+    }
 
-        // Convert default acct pub_key to Ethereum address by taking hash of UNCOMPRESSED point
-        let pub_key: [u8; 65] = child_xpub.public_key().to_encoded_point(false).as_bytes().try_into().unwrap();
-        // only hash last 64B of pub_key because we want to leave out the prefix 0x04
-        let addr_bytes = generate_eth_address(&pub_key[1..]);
-        let address = String::from("0x") + &hex::encode(addr_bytes);
+    /// Recovers the secret, returning it only if the password is correct
+    fn unlock_secret(&self, password: &str) -> Result<Zeroizing<Vec<u8>>, UnlockError> {
+        unlock_secret(self.source, self.sealed_secret.as_ref(), &self.pad, &self.verification_key, password)
+    }
 
-        let mut path = String::from("m/44'/60'/0'/0/");
-        path.push_str(&index.to_string());
+    /// Checks the password, decrypting the file and checking it against its MAC first, and sets
+    /// the deriving key
+    pub fn verify_password(&mut self, password: String) -> Result<(), UnlockError> {
+        let password = Zeroizing::new(password);
+        self.unlock(Credential::Password(&password))
+    }
 
-        Account {
-            nonce: 0,
-            path,
-            prv_key: None,
-            address,
+    /// Unlocks the wallet like `verify_password`, with the password key kept outside the file
+    pub fn unlock_with_key(&mut self, key: Zeroizing<[u8; 32]>) -> Result<(), UnlockError> {
+        self.unlock(Credential::Key(key))
+    }
+
+    fn unlock(&mut self, credential: Credential) -> Result<(), UnlockError> {
+        let mut file_key = None;
+        if let Some(encrypted) = &self.encrypted_file {
+            let key = credential.key(&encrypted.header.kdf, &encrypted.header.kdfparams).ok_or(UnlockError::IncorrectPassword)?;
+            self.decrypt_file(&key)?;
+            file_key = Some(key);
+        }
+        let secret = match &self.sealed_secret {
+            Some(sealed) => {
+                // the file is encrypted under the same key, unless it was stored before they were one
+                let key = match file_key {
+                    Some(key) if key.kdf() == sealed.kdf() => key,
+                    _ => credential.key(sealed.kdf_name(), sealed.kdf()).ok_or(UnlockError::IncorrectPassword)?,
+                };
+                let secret = SecretBuffer::new(&sealed.open_with_key(&key).ok_or(UnlockError::IncorrectPassword)?);
+                // a wallet that has the key already was checked when it was loaded, or created here
+                if self.password_key.is_none() {
+                    self.check_mac(&key)?;
+                }
+                self.password_key = Some(key);
+                secret
+            },
+            // files from before `sealed_secret` check the password itself
+            None => {
+                let password = credential.password().ok_or(UnlockError::IncorrectPassword)?;
+                match self.source {
+                    KeySource::WatchOnly => watch_password_matches(None, &self.pad, &self.verification_key, password).map(|()| SecretBuffer::new(&[]))?,
+                    _ => SecretBuffer::new(&self.unlock_secret(password)?),
+                }
+            },
+        };
+        // there is no secret to recover, and the wallet stays without a deriving key
+        if self.source == KeySource::WatchOnly {
+            return Ok(());
+        }
+        let parent_derive_xprv = deriving_key_from_secret(self.source, &self.accounts_metadata.path_template, &secret);
+        if let Some(approval) = &mut self.accounts_metadata.approval {
+            if let Err(e) = approval.open(&*key_bytes(&parent_derive_xprv)) {
+                println!("{}", e);
+            }
         }
+        self.accounts_metadata.deriving_key = Some(parent_derive_xprv);
+        Ok(())
+    }
+
+    /// Addresses of all accounts, in derivation order
+    pub fn addresses(&self) -> Vec<String> {
+        self.accounts_metadata.accounts.iter().map(|a| a.address.clone()).collect()
+    }
+
+    /// The number of transactions sent from the account at `index`
+    pub fn nonce(&self, index: usize) -> Option<u64> {
+        self.accounts_metadata.accounts.get(index).map(|a| a.nonce)
     }
 
-    pub fn run(&mut self, deriving_key: &XPrv) -> u8 {
-        println!("CURRENT ACCOUNT ADDRESS: {}", &self.address);
+    /// Scans the chain for used accounts after a restore, up to the configured gap limit, and
+    /// returns how many accounts the wallet has afterwards. The wallet must be unlocked.
+    pub fn discover_accounts(&mut self, rpc: &dyn EthRpc) -> Result<usize, String> {
+        let gap_limit = Settings::load().discovery.gap_limit;
+        self.accounts_metadata.discover_accounts(gap_limit, |address| fetch_account_activity(rpc, address))
+    }
+
+    /// Derives the next account and returns its address. The wallet must be unlocked.
+    pub fn add_account(&mut self) -> String {
+        let index = self.accounts_metadata.accounts.len();
+        self.accounts_metadata.create_account(index).address.clone()
+    }
+
+    /// Sends a plain transfer from the account at `index` at the network gas price, without any
+    /// prompts, and returns the transaction hash. The wallet must be unlocked.
+    pub fn send(&mut self, rpc: &dyn EthRpc, index: usize, recipient: &str, wei_amount: u128) -> Result<String, String> {
+        if self.source == KeySource::WatchOnly {
+            return Err(String::from("A watch-only wallet can't send"));
+        }
+        if self.accounts_metadata.approval.is_some() {
+            return Err(String::from("Transactions need approval in this wallet"));
+        }
+        let deriving_key = self.accounts_metadata.deriving_key.clone().ok_or_else(|| String::from("The wallet is locked"))?;
+        let recipient_bytes: [u8; 20] = recipient.strip_prefix("0x")
+            .and_then(|r| hex::decode(r).ok())
+            .and_then(|b| b.try_into().ok())
+            .ok_or_else(|| format!("Invalid recipient address {}", recipient))?;
+        let account = self.accounts_metadata.accounts.get_mut(index).ok_or_else(|| format!("No account {}", index))?;
+        if account.watch_only {
+            return Err(String::from("Watch-only accounts can't send"));
+        }
+
+        let price = floored_gas_price(rpc)?;
+        account.sync_nonce(rpc)?;
+        let final_txn = account.sign_transfer(&deriving_key, recipient_bytes, wei_amount, GasPricing::Legacy(price), TRANSFER_GAS_LIMIT);
+        let hash = send_raw_transaction(rpc, &final_txn)?;
+        account.record_sent(&hash, recipient_bytes, wei_amount, price, None);
+        account.nonce += 1;
+        journal::record(JournalEntry::Sent { from: account.address.clone(), hash: hash.clone(), fee: price * TRANSFER_GAS_LIMIT });
+        Ok(hash)
+    }
 
+    /// Starts the wallet with the default account. If the session is locked, the secrets are
+    /// scrubbed and the wallet resumes at the account menu once the password is entered again.
+    pub fn run(&mut self, rpc: &dyn EthRpc) {
         loop {
-            // TODO: remove manual query of account balance in place of automatic fetch
-            let user_input = loop {
-                println!("{}", "1) View account balance");
-                println!("{}", "2) Send a transaction");
-                println!("{}", "3) Create another account");
-                println!("{}", "4) Switch account");
-                println!("{}", "5) QUIT");
+            // the menus hold the accounts, and hand them back to be saved after every change
+            let mut metadata = std::mem::take(&mut self.accounts_metadata);
+            let quit = if self.source == KeySource::WatchOnly {
+                let mut save = |metadata: &mut AccountMetadata| self.save_accounts(metadata);
+                session::run_until_locked(|| metadata.run_watch_only(rpc, &mut save))
+            } else {
+                // fetch the deriving key. Its signing key zeroizes itself when the clone is dropped.
+                let deriving_key = match &metadata.deriving_key {
+                    Some(k) => k.clone(),
+                    None => unreachable!("Deriving key must've been created if wallet was created"),
+                };
 
-                match utils::read_user_input().parse::<u8>() {
-                    Ok(option) => break option,
-                    Err(_e) => {
-                        println!("Invalid option");
-                    }
-                }
+                // start account actions
+                let (source, language, sealed_secret, pad, verification_key) =
+                    (self.source, self.language, self.sealed_secret.clone(), self.pad.clone(), self.verification_key.clone());
+                let unlock = |password: &str| unlock_secret(source, sealed_secret.as_ref(), &pad, &verification_key, password).ok();
+                let mut save = |metadata: &mut AccountMetadata| self.save_accounts(metadata);
+                session::run_until_locked(|| metadata.run(rpc, deriving_key, &unlock, &mut save, source, language))
             };
-
-            match user_input {
-                1 => {
-                    self.query_balance();
-                },
-                2 => {
-                    // if prv_key is non-existent, derive it and set it. Then send transaction.
-                    if let None = self.prv_key {
-                        let index = self.path.split("/")
-                            .into_iter()
-                            .last().unwrap()
-                            .parse::<u32>().unwrap();
-                        self.prv_key = Some(utils::derive_child_secret_key(deriving_key, index));
+            self.accounts_metadata = metadata;
+            match quit {
+                Some(22) => self.change_password_prompt(),
+                Some(23) => self.back_up_prompt(),
+                Some(25) => break,
+                // the lock option, or the lock command or signal, which unwind out of the session
+                Some(24) | None => {
+                    // persist anything changed since the last save, e.g. before a lock signal
+                    if let Err(e) = self.lock() {
+                        println!("{}", e);
+                    }
+                    audit::record("Session locked");
+                    println!("Wallet locked");
+                    if !self.unlock_again() {
+                        break;
                     }
-                    self.send_transaction();
                 },
-                3 => return 3,
-                4 => return 4,
-                5 => return 5,
-                _ => println!("{}", "Invalid option"),
+                Some(_) => unreachable!("Code should only return the password (22), backup (23), lock (24) or quit (25) flag"),
             }
         }
-    }
 
-    fn query_balance(&self) {
-        let resp: Value = ureq::post("https://rinkeby.infura.io/v3/39f702e71cd84987bd1ec2550a54375e")
-            .set("Content-Type", "application/json")
-            .send_json(ureq::json!({
-                        "jsonrpc": "2.0",
-                        "id": 1,
-                        "method": "eth_getBalance",
-                        "params": [self.address, "latest"]
-                    })).unwrap()
-            .into_json().unwrap();
+        let entries = journal::take();
+        let statuses: Vec<_> = journal::sent_hashes(&entries).into_iter()
+            .map(|hash| (String::from(hash), rpc.transaction_status(hash)))
+            .collect();
+        let metadata = &mut self.accounts_metadata;
+        metadata.pending.extend(statuses.iter().filter(|(_, status)| status.is_open()).map(|(hash, _)| hash.clone()));
 
-        match resp["result"].as_str() {
-            Some(s) => {
-                match s.strip_prefix("0x") {
-                    Some(v) => {
-                        let balance = u128::from_str_radix(v, 16).unwrap();
-                        println!("Balance: {} ETH", utils::wei_to_eth(balance));
-                    },
-                    None => println!("String doesn't start with 0x"),
-                }
-            },
-            None => println!("Value is not a string"),
+        println!("{}", journal::summary(&entries, |hash| {
+            statuses.iter().find(|(h, _)| h == hash).map_or(TransactionStatus::Unknown, |(_, status)| *status)
+        }));
+        match self.store() {
+            Ok(()) => println!("Stored wallet data safely"),
+            Err(e) => println!("{}", e),
         };
     }
 
-    fn send_transaction(&mut self) {
-        let (recipient, recipient_bytes) = match utils::get_valid_address_bytes() {
-            Ok(r) => (r.0, r.1),
-            Err(_e) => return,
+    /// Replaces the password once the current one is entered, and stores the wallet right away
+    pub fn change_password(&mut self, current: &str, new: &str) -> Result<(), String> {
+        if new.is_empty() {
+            return Err(String::from("The new password can't be empty"));
+        }
+        self.replace_password(current, new)?;
+        // a key kept for the old password no longer opens the wallet
+        if keychain::has_entry(&self.file) {
+            self.remember_in_keychain()?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites a file of an older version in the current one once the wallet is unlocked, so the
+    /// file was checked against its MAC as it was written. Does nothing for current files, and
+    /// for files from before `sealed_secret` until `upgrade_encryption` has run.
+    pub fn upgrade_version(&mut self) -> Result<(), String> {
+        if self.password_key.is_none() || self.version >= migrations::CURRENT_VERSION {
+            return Ok(());
+        }
+        let from = self.version;
+        migrations::migrate(self);
+        self.store()?;
+        println!("Upgraded the wallet file from version {} to {}", from, self.version);
+        Ok(())
+    }
+
+    /// Rewrites a file from before `sealed_secret` once its password has been verified, so the
+    /// secret is encrypted instead of padded. Does nothing for newer files.
+    pub fn upgrade_encryption(&mut self, password: &str) -> Result<(), String> {
+        if self.sealed_secret.is_some() {
+            return Ok(());
+        }
+        self.replace_password(password, password)?;
+        println!("Upgraded the wallet file to authenticated encryption");
+        Ok(())
+    }
+
+    /// Encrypts the secret, and the file if it is encrypted, under `new` with a fresh salt,
+    /// dropping any pad, and stores the wallet.
+    /// The file is renamed over the old one, so if storing fails the old file still opens with
+    /// the old password, and so does the wallet in memory.
+    fn replace_password(&mut self, current: &str, new: &str) -> Result<(), String> {
+        let secret = match self.source {
+            KeySource::WatchOnly => watch_password_matches(self.sealed_secret.as_ref(), &self.pad, &self.verification_key, current)
+                .map(|()| Zeroizing::new(vec![])),
+            _ => self.unlock_secret(current),
+        }.map_err(|e| e.to_string())?;
+        let old_pad = std::mem::take(&mut self.pad);
+        let (sealed_secret, password_key) = seal_secret(new, &secret);
+        let old_sealed_secret = self.sealed_secret.replace(sealed_secret);
+        let old_password_key = self.password_key.replace(password_key);
+        let old_mac = self.mac.clone();
+
+        let stored = self.store();
+        if stored.is_err() {
+            self.pad = old_pad;
+            self.sealed_secret = old_sealed_secret;
+            self.password_key = old_password_key;
+            self.mac = old_mac;
+        }
+        stored
+    }
+
+    /// Asks for the current password and a new one entered twice, and changes it
+    fn change_password_prompt(&mut self) {
+        println!("Enter the current password: ");
+        let current = Zeroizing::new(read_secret_input());
+        let new = match utils::read_new_password("Enter the new password") {
+            Some(new) => Zeroizing::new(new),
+            None => return,
         };
 
-        // TODO: check that amount is less than the current balance
-        let eth_amount: f64 =  loop {
-            println!("Enter ETH amount to send: ");
-            match utils::read_user_input().parse::<f64>() {
-                Ok(v) => break v,
-                Err(_e) => println!("Please enter a number"),
-            }
-        };
-        let wei_amount: u128 = utils::eth_to_wei(eth_amount);
-
-        // estimate the gas price
-        let resp: Value = ureq::post("https://rinkeby.infura.io/v3/39f702e71cd84987bd1ec2550a54375e")
-            .set("Content-Type", "application/json")
-            .send_json(ureq::json!({
-                "jsonrpc": "2.0",
-                "id": "1",
-                "method": "eth_gasPrice",
-                "params": []
-            })).unwrap()
-            .into_json().unwrap();
-        let gas_price = resp["result"].as_str().unwrap().strip_prefix("0x").unwrap();
-        let price = u128::from_str_radix(gas_price, 16).unwrap();
-
-        // create and sign transaction
-        let tx = RawTransaction::new(
-            self.nonce as u128,
-            recipient_bytes,
-            wei_amount,
-            price,
-            21000,
-            vec![]
-        );
-        let rlp_bytes = tx.sign(&self.prv_key.unwrap(), &RINKEBY_CHAIN_ID);
-        let mut final_txn = String::from("0x");
-        final_txn.push_str(&hex::encode(rlp_bytes));
+        match self.change_password(&current, &new) {
+            Ok(()) => {
+                audit::record("Password changed");
+                println!("Password changed");
+            },
+            Err(e) => println!("{}", e),
+        }
+    }
 
-        println!("Transaction details:\n\tTO: {:?}\n\tAMOUNT: {} ETH\n\tGAS PRICE: {} wei\n\t", recipient, eth_amount, price);
-        println!("Press 1 to CONFIRM");
-        println!("Press any other number to CANCEL");
-        let user_option = loop {
-            match read_user_input().parse::<u8>() {
-                Ok(v) => break v,
-                Err(_e) => println!("Please enter a number"),
-            }
-        };
-
-        match user_option {
-            1 => {
-                let resp: Value = ureq::post("https://rinkeby.infura.io/v3/39f702e71cd84987bd1ec2550a54375e")
-                    .set("Content-Type", "application/json")
-                    .send_json(ureq::json!({
-                        "jsonrpc": "2.0",
-                        "id": "1",
-                        "method": "eth_sendRawTransaction",
-                        "params": [final_txn],
-                    })).unwrap()
-                    .into_json().unwrap();
-
-                if let Some(s) = resp["result"].as_str() {
-                    if s != "0x0" {
-                        self.nonce += 1;
-                        println!("Transaction {} successfully sent", s);
-                    } else {
-                        println!("Transaction not yet available");
-                    }
-                } else {
-                    println!("Error occurred in sending transaction");
-                }
+    fn back_up_prompt(&mut self) {
+        println!("Enter the folder to save the backup in (or type q to cancel): ");
+        let dir = read_user_input();
+        if dir == "q" {
+            return;
+        }
+        match self.back_up(&dir) {
+            Ok(path) => {
+                audit::record(&format!("Wallet backed up to {}", path.display()));
+                println!("Saved the backup to {}. It opens with the current password.", path.display());
             },
-            _ => println!("Transaction canceled")
-        };
+            Err(e) => println!("{}", e),
+        }
     }
-}
\ No newline at end of file
+
+    /// Asks for the password after a lock. Returns false if the user chose to quit instead.
+    fn unlock_again(&mut self) -> bool {
+        loop {
+            println!("Enter Password to unlock (or type q to quit): ");
+            let password = read_secret_input();
+            if password == "q" {
+                return false;
+            }
+            match self.login(password) {
+                Ok(()) => {
+                    audit::record("Session unlocked");
+                    return true;
+                },
+                Err(UnlockError::IncorrectPassword) => {},
+                Err(_) => return false,
+            }
+        }
+    }
+}
+
+impl Drop for Wallet {
+    /// Wipes the secrets of a wallet that is dropped without being stored, e.g. after a panic
+    fn drop(&mut self) {
+        self.scrub_secrets();
+    }
+}
+
+/// Asks for a few random words of the recovery phrase, stopping at the first wrong one
+fn backup_quiz_passes(backup: &BackupCheck, secret: &[u8]) -> bool {
+    recovery::quiz_positions(backup.word_count()).into_iter().all(|position| {
+        println!("What is word #{} of your recovery phrase?", position + 1);
+        backup.check_word(secret, position, &utils::read_user_input())
+    })
+}
+
+/// Exports the account's private key once the wallet password is entered again, either printed,
+/// as a keystore file or as a wallet file of its own. A printed key is never stored, and is
+/// zeroized afterwards.
+fn export_private_key_prompt(account: &Account, deriving_key: &XPrv, book: &AddressBook, unlock: &dyn Fn(&str) -> Option<Zeroizing<Vec<u8>>>) {
+    println!("1) Show the private key");
+    println!("2) Save it as an encrypted keystore file (geth, MetaMask)");
+    println!("3) Save it as a wallet file of its own, to hand this account to someone else");
+    println!("4) Back");
+    let option = utils::read_user_input();
+    if !["1", "2", "3"].contains(&option.as_str()) {
+        return;
+    }
+
+    println!("Enter the wallet password: ");
+    if unlock(&utils::read_secret_input()).is_none() {
+        return println!("Incorrect password");
+    }
+
+    if option == "2" {
+        return save_keystore(account, deriving_key);
+    }
+    if option == "3" {
+        return save_account_wallet(account, deriving_key, book);
+    }
+    println!("WARNING: anyone who sees this key can take every asset in {}.", account.address);
+    println!("Never share it, and make sure nobody is watching your screen.");
+    println!("Private key: {}", account.export_private_key(deriving_key).as_str());
+    audit::record(&format!("Private key exported for {}", account.address));
+}
+
+/// Writes the account's key to a new keystore file under a password of the user's choice
+fn save_keystore(account: &Account, deriving_key: &XPrv) {
+    println!("Enter the path of the keystore file to create: ");
+    let path = utils::read_user_input();
+    if std::path::Path::new(&path).exists() {
+        return println!("{} already exists", path);
+    }
+    println!("Enter a password for the keystore file: ");
+    let password = Zeroizing::new(utils::read_secret_input());
+    println!("Enter it again: ");
+    if *utils::read_secret_input() != *password {
+        return println!("The passwords don't match");
+    }
+
+    println!("Encrypting...");
+    let json = serde_json::to_vec_pretty(&account.export_keystore(deriving_key, &password)).unwrap();
+    let written = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(&json));
+    match written {
+        Ok(()) => {
+            println!("Saved the keystore for {} to {}", account.address, path);
+            audit::record(&format!("Keystore file exported for {}", account.address));
+        },
+        Err(e) => println!("Error writing keystore file: {}", e),
+    }
+}
+
+/// Writes a new wallet file holding only the account, with the address book entries the user
+/// picks, under a password of their choice. The file can be copied into another data directory's
+/// wallets folder, where it is listed on the next start.
+fn save_account_wallet(account: &Account, deriving_key: &XPrv, book: &AddressBook) {
+    println!("WARNING: this wallet's recovery phrase or file can still recreate the key of {}.", account.address);
+    println!("Whoever gets the new file shares control of the account with you, and you can't take it back.");
+    println!("Only hand over an account you will never use again, and move anything you keep out of it first.");
+    println!("Press 1 to CONTINUE");
+    if read_user_input() != "1" {
+        return;
+    }
+
+    let mut positions = vec![];
+    if !book.is_empty() {
+        for (i, contact) in book.contacts().iter().enumerate() {
+            println!("{}) {}", i + 1, contact.describe());
+        }
+        println!("Enter the numbers of the contacts to include, separated by commas (or press enter for none): ");
+        for entry in read_user_input().split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match entry.parse::<usize>() {
+                Ok(n) if (1..=book.contacts().len()).contains(&n) => positions.push(n - 1),
+                _ => return println!("{} is not one of the contacts", entry),
+            }
+        }
+    }
+
+    println!("Enter the path of the wallet file to create: ");
+    let path = utils::read_user_input();
+    if std::path::Path::new(&path).exists() {
+        return println!("{} already exists", path);
+    }
+    let password = match utils::read_new_password("Enter a password for the new wallet") {
+        Some(password) => password,
+        None => return,
+    };
+
+    println!("Encrypting...");
+    let mut wallet = Wallet::from_exported_account(password, &account.secret_key(deriving_key), account.label.clone(), book.subset(&positions));
+    let name = std::path::Path::new(&path).file_stem().map_or_else(|| account.address.clone(), |s| s.to_string_lossy().into_owned());
+    wallet.set_location(&name, path.clone());
+    match wallet.store() {
+        Ok(()) => {
+            println!("Saved a wallet holding {} to {}", account.address, path);
+            println!("Copy it into the wallets folder of the recipient's data directory and it will be listed on the next start");
+            audit::record(&format!("Account {} exported as a wallet file", account.address));
+        },
+        Err(e) => println!("Error writing wallet file: {}", e),
+    }
+}
+
+/// A wallet file written before wallets had accounts: the padded seed and the public key at
+/// m/0' that once verified the password
+#[derive(Deserialize)]
+pub struct LegacyWallet {
+    pad: Vec<u8>,
+    root_pub_key: Vec<u8>,
+    #[serde(skip)]
+    file: String,
+}
+
+impl LegacyWallet {
+    /// Reads `file` if it is in the legacy format, which lacks a `verification_key`
+    pub fn load(file: &str) -> Option<LegacyWallet> {
+        let data = std::fs::read_to_string(file).ok()?;
+        let mut legacy = LegacyWallet::parse(&data)?;
+        legacy.file = String::from(file);
+        Some(legacy)
+    }
+
+    fn parse(data: &str) -> Option<LegacyWallet> {
+        if migrations::version(data.as_bytes())? != migrations::LEGACY {
+            return None;
+        }
+        serde_json::from_str(data).ok()
+    }
+
+    /// Checks the password against the old m/0' key, then rebuilds the wallet with the standard
+    /// account layout and rewrites its file. Returns the unlocked wallet.
+    pub fn upgrade(&self, name: &str, password: String) -> Result<Wallet, String> {
+        let password_hash = password_hash(&password);
+        let hash = password_hash.get(..self.pad.len()).ok_or_else(|| String::from("Incorrect password"))?;
+        let seed = Zeroizing::new(utils::xor(hash, &self.pad).unwrap());
+        let (_, root_pub_key) = utils::create_keys_from_path(&seed, LEGACY_ROOT_PATH);
+        if !ct_eq(&root_pub_key.to_bytes(), &self.root_pub_key) {
+            return Err(String::from("Incorrect password"));
+        }
+
+        let mut wallet = Wallet::generate_wallet(&seed, password, PathTemplate::standard());
+        // neither was recorded in the old format
+        wallet.created_at = None;
+        wallet.network = None;
+        wallet.set_location(name, self.file.clone());
+        wallet.store()?;
+        Ok(wallet)
+    }
+}
+
+/// A wallet read from a backup file, which is written over a wallet file only once its password
+/// has been entered
+pub struct WalletBackup {
+    /// The name of the wallet backed up
+    pub name: String,
+    /// When the backup was taken
+    pub taken_at: u64,
+    wallet: Wallet,
+}
+
+impl WalletBackup {
+    /// Reads a backup written by `Wallet::back_up`, checking it against its checksum
+    pub fn read(path: &str) -> Result<WalletBackup, String> {
+        let backup = backup::read(std::path::Path::new(path))?;
+        let wallet = Wallet::from_file_bytes(&backup.wallet_file, path).map_err(|e| e.to_string())?;
+        Ok(WalletBackup { name: backup.name, taken_at: backup.created_at, wallet })
+    }
+
+    /// Checks the password of the wallet in the backup, and the wallet against its MAC
+    pub fn verify_password(&mut self, password: String) -> Result<(), UnlockError> {
+        self.wallet.verify_password(password)
+    }
+
+    /// When the wallet in the backup was created, once its password has been entered
+    pub fn wallet_created_at(&self) -> Option<u64> {
+        self.wallet.created_at
+    }
+
+    /// Stores the wallet in the backup as `name` in `file`, replacing the file there, and returns
+    /// it unlocked. The file is only replaced once the password has been verified, and renamed
+    /// over, so it is kept if storing fails.
+    pub fn restore(mut self, name: &str, file: String) -> Result<Wallet, String> {
+        if self.wallet.password_key.is_none() {
+            return Err(String::from("The backup can't be restored before its password is entered"));
+        }
+        self.wallet.set_location(name, file);
+        self.wallet.store()?;
+        audit::record(&format!("Wallet restored from a backup taken {}", utils::format_timestamp(self.taken_at)));
+        Ok(self.wallet)
+    }
+}
+
+/// The plaintext header of an encrypted wallet file, a line of JSON after the magic and version
+#[derive(Serialize, Deserialize)]
+struct FileHeader {
+    kdf: String,
+    kdfparams: PasswordKdf,
+    nonce: String,
+    /// keccak256 of the ciphertext. A file that matches it but doesn't decrypt was opened with the
+    /// wrong password rather than damaged.
+    checksum: String,
+    /// Kept out of the ciphertext, so a failed login can be counted without the password
+    failed_logins: u32,
+}
+
+/// A wallet file whose JSON is encrypted with AES-256-GCM under a key derived from the password
+struct EncryptedFile {
+    header: FileHeader,
+    ciphertext: Vec<u8>,
+}
+
+impl EncryptedFile {
+    /// Encrypts the wallet JSON with a fresh nonce
+    fn seal(key: &PasswordKey, plaintext: &[u8], failed_logins: u32) -> EncryptedFile {
+        let nonce: [u8; 12] = rand::random();
+        let ciphertext = key.encrypt(plaintext, nonce);
+        let header = FileHeader {
+            kdf: String::from(key.kdf().name()),
+            kdfparams: key.kdf().clone(),
+            nonce: hex::encode(nonce),
+            checksum: hex::encode(keccak256(&ciphertext)),
+            failed_logins,
+        };
+        EncryptedFile { header, ciphertext }
+    }
+
+    /// Returns None for a file that doesn't start with `FILE_MAGIC`, such as one from before
+    /// files were encrypted, and an error for an encrypted file that is damaged
+    fn parse(data: &[u8]) -> Option<Result<EncryptedFile, String>> {
+        let rest = data.strip_prefix(FILE_MAGIC)?;
+        Some(EncryptedFile::parse_versioned(rest))
+    }
+
+    fn parse_versioned(data: &[u8]) -> Result<EncryptedFile, String> {
+        let (version, rest) = data.split_first().ok_or_else(|| String::from("the file is truncated"))?;
+        if *version != FILE_VERSION {
+            return Err(format!("unknown format version {}", version));
+        }
+        let newline = rest.iter().position(|b| *b == b'\n').ok_or_else(|| String::from("the header is truncated"))?;
+        let header: FileHeader = serde_json::from_slice(&rest[..newline]).map_err(|e| format!("malformed header: {}", e))?;
+        let ciphertext = rest[newline + 1..].to_vec();
+        if hex::encode(keccak256(&ciphertext)) != header.checksum {
+            return Err(String::from("the contents don't match the checksum"));
+        }
+        Ok(EncryptedFile { header, ciphertext })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = FILE_MAGIC.to_vec();
+        data.push(FILE_VERSION);
+        data.extend(serde_json::to_vec(&self.header).unwrap());
+        data.push(b'\n');
+        data.extend(&self.ciphertext);
+        data
+    }
+
+    /// Returns the wallet JSON, or None if the key is wrong
+    fn open(&self, key: &PasswordKey) -> Option<Zeroizing<Vec<u8>>> {
+        let nonce: [u8; 12] = hex::decode(&self.header.nonce).ok()?.try_into().ok()?;
+        key.decrypt(&self.ciphertext, nonce)
+    }
+}
+
+/// Where the previous version of a wallet file is kept
+pub fn backup_path(path: &str) -> String {
+    format!("{}.bak", path)
+}
+
+/// Moves a damaged wallet file aside, to a name with the time added that no wallet is looked for
+/// under, so it can be inspected and a wallet stored in its place. Returns where it went.
+pub fn quarantine(path: &str) -> Result<String, String> {
+    let now = utils::now();
+    let quarantined = (0..).map(|n| match n {
+        0 => format!("{}.damaged-{}", path, now),
+        n => format!("{}.damaged-{}-{}", path, now, n),
+    }).find(|candidate| !std::path::Path::new(candidate).exists()).unwrap();
+    std::fs::rename(path, &quarantined).map_err(|e| format!("Error moving {} aside: {}", path, e))?;
+    Ok(quarantined)
+}
+
+/// Puts the previous version kept by `store` in place of a damaged wallet file, which is moved
+/// aside by `quarantine`. Returns where the damaged file went.
+pub fn restore_previous(path: &str) -> Result<String, String> {
+    let quarantined = quarantine(path)?;
+    if let Err(e) = std::fs::copy(backup_path(path), path) {
+        // leave things as they were rather than without a wallet file
+        let _ = std::fs::rename(&quarantined, path);
+        return Err(format!("Error restoring {} from its previous version: {}", path, e));
+    }
+    Ok(quarantined)
+}
+
+/// Writes a wallet file next to the old one, syncs it and renames it over, so a crash never
+/// leaves it half written. The old one is copied to `backup_path` first.
+fn write_file(path: &str, data: &[u8]) -> Result<(), String> {
+    let temp_path = format!("{}.tmp", path);
+    let mut file = File::create(&temp_path).map_err(|e| format!("Error writing to file: {}", e))?;
+    file.write_all(data).and_then(|()| file.sync_all()).map_err(|e| format!("Error writing to file: {}", e))?;
+    if std::path::Path::new(path).exists() {
+        std::fs::copy(path, backup_path(path))
+            .and_then(|_| File::open(backup_path(path))?.sync_all())
+            .map_err(|e| format!("Error backing up {}: {}", path, e))?;
+    }
+    std::fs::rename(&temp_path, path).map_err(|e| format!("Error writing to file: {}", e))?;
+    sync_parent_dir(path);
+    Ok(())
+}
+
+/// Makes a rename in the directory of `path` survive a power cut. Windows can't open a directory
+/// to sync it, so there it is left to the file system.
+fn sync_parent_dir(path: &str) {
+    #[cfg(unix)]
+    {
+        let dir = match std::path::Path::new(path).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => std::path::Path::new("."),
+        };
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+/// How long a login waits after `failed_logins` failures in a row
+fn login_delay(failed_logins: u32) -> std::time::Duration {
+    let secs = match failed_logins.checked_sub(FREE_LOGIN_ATTEMPTS) {
+        None => 0,
+        Some(extra) => 1u64.checked_shl(extra).unwrap_or(u64::MAX).min(MAX_LOGIN_DELAY_SECS),
+    };
+    std::time::Duration::from_secs(secs)
+}
+
+/// The hash of the password that the pad is xored with
+fn password_hash(password: &str) -> Zeroizing<[u8; 64]> {
+    Zeroizing::new(keccak512(password.as_bytes()))
+}
+
+/// KDF parameters for a new or changed password, with the function and cost from the settings
+fn new_password_kdf() -> PasswordKdf {
+    Settings::load().security.password_kdf()
+}
+
+/// Seals the secret under a new or changed password, returning the key too for the file
+fn seal_secret(password: &str, secret: &[u8]) -> (PasswordBox, PasswordKey) {
+    let key = PasswordKey::derive(password, new_password_kdf()).expect("KDF parameters from the settings are valid");
+    (PasswordBox::seal_with_key(&key, secret, rand::random()), key)
+}
+
+/// What a wallet is unlocked with: the password, or the key it derives
+enum Credential<'a> {
+    Password(&'a str),
+    Key(Zeroizing<[u8; 32]>),
+}
+
+impl Credential<'_> {
+    /// The key for the parameters stored under the function `name`
+    fn key(&self, name: &str, kdf: &PasswordKdf) -> Option<PasswordKey> {
+        match self {
+            Credential::Password(password) => PasswordKey::for_kdf(password, name, kdf),
+            Credential::Key(key) if name == kdf.name() => Some(PasswordKey::from_bytes(kdf.clone(), key.clone())),
+            Credential::Key(_) => None,
+        }
+    }
+
+    fn password(&self) -> Option<&str> {
+        match self {
+            Credential::Password(password) => Some(password),
+            Credential::Key(_) => None,
+        }
+    }
+}
+
+/// Checks the password of a watch-only wallet, which has only an empty secret to decrypt
+fn watch_password_matches(sealed_secret: Option<&PasswordBox>, pad: &[u8], verification_key: &[u8], password: &str) -> Result<(), UnlockError> {
+    let matches = match sealed_secret {
+        Some(sealed_secret) => sealed_secret.open(password).is_some(),
+        None => {
+            check_length("verification key", verification_key, 32)?;
+            ct_eq(&salted_hash(pad, password), verification_key)
+        },
+    };
+    if matches { Ok(()) } else { Err(UnlockError::IncorrectPassword) }
+}
+
+/// A field of a file from before `sealed_secret` that isn't the length it must be is damaged, and
+/// no password would match it
+fn check_length(field: &str, value: &[u8], expected: usize) -> Result<(), UnlockError> {
+    if value.len() != expected {
+        return Err(UnlockError::Corrupt(format!("the {} is {} bytes, not {}", field, value.len(), expected)));
+    }
+    Ok(())
+}
+
+/// The private key of `key`, which seals what is kept under the deriving key, wiped once dropped
+fn key_bytes(key: &XPrv) -> Zeroizing<PrivateKeyBytes> {
+    Zeroizing::new(key.to_bytes())
+}
+
+/// Recovers the secret, returning it only if the password is correct. The secret is the seed, or
+/// the deriving key for a wallet restored from an xprv. A sealed secret is checked by its
+/// authentication tag; a padded one by the public key it derives.
+fn unlock_secret(source: KeySource, sealed_secret: Option<&PasswordBox>, pad: &[u8], verification_key: &[u8], password: &str) -> Result<Zeroizing<Vec<u8>>, UnlockError> {
+    match (source, sealed_secret) {
+        (KeySource::WatchOnly, _) => return Err(UnlockError::IncorrectPassword),
+        (_, Some(sealed_secret)) => return sealed_secret.open(password).ok_or(UnlockError::IncorrectPassword),
+        (_, None) => {},
+    }
+    check_length("verification key", verification_key, VERIFICATION_KEY_LEN)?;
+    if pad.is_empty() || pad.len() > PAD_MAX_LEN {
+        return Err(UnlockError::Corrupt(format!("the pad is {} bytes, not 1 to {}", pad.len(), PAD_MAX_LEN)));
+    }
+    let password_hash = password_hash(password);
+    let secret = Zeroizing::new(utils::xor(&password_hash[..pad.len()], pad).unwrap());
+    let public_key = match source {
+        KeySource::Seed => utils::create_keys_from_path(&secret, ACCOUNT_KEY_PATH).1.to_bytes(),
+        // a wrong password can also give bytes that aren't a valid key at all
+        KeySource::ExtendedKey | KeySource::ExportedAccount => xprv_from_parts(&secret).ok_or(UnlockError::IncorrectPassword)?.public_key().to_bytes(),
+        KeySource::WatchOnly => return Err(UnlockError::IncorrectPassword),
+    };
+
+    if ct_eq(&public_key, verification_key) {
+        Ok(secret)
+    } else {
+        Err(UnlockError::IncorrectPassword)
+    }
+}
+
+/// Derives the descendant of `key` along `components`
+fn derive_along(key: &XPrv, components: &[ChildNumber]) -> XPrv {
+    components.iter().fold(key.clone(), |key, child_number| key.derive_child(*child_number).unwrap())
+}
+
+/// Returns the deriving key, the root of `template`, for a secret returned by `unlock_secret`.
+/// A wallet restored from an xprv always uses the standard template.
+fn deriving_key_from_secret(source: KeySource, template: &PathTemplate, secret: &[u8]) -> XPrv {
+    match source {
+        KeySource::Seed => utils::create_keys_from_path(secret, &template.root()).0,
+        KeySource::ExtendedKey | KeySource::ExportedAccount => xprv_from_parts(secret).unwrap(),
+        KeySource::WatchOnly => unreachable!("A watch-only wallet has no secret"),
+    }
+}
+
+/// Rebuilds an imported deriving key from its private key and chain code. Its depth and parent
+/// aren't stored; they don't affect the keys it derives.
+fn xprv_from_parts(secret: &[u8]) -> Option<XPrv> {
+    let (private_key, chain_code) = secret.split_at(32);
+    let mut key_bytes = Zeroizing::new([0u8; 33]);
+    key_bytes[1..].copy_from_slice(private_key);
+    let extended_key = ExtendedKey {
+        prefix: Prefix::XPRV,
+        attrs: ExtendedKeyAttrs {
+            depth: 4,
+            parent_fingerprint: [0; 4],
+            child_number: ChildNumber(0),
+            chain_code: chain_code.try_into().ok()?,
+        },
+        key_bytes: *key_bytes,
+    };
+    XPrv::try_from(extended_key).ok()
+}
+
+/// What a secondary passphrase is checked against. The wallet file is readable to whoever can
+/// unlock it, so a guess has to cost a run of the password KDF, as one at the wallet password does.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum PassphraseHash {
+    /// Nothing, sealed under the key the passphrase derives. Only the right passphrase opens it.
+    Sealed(PasswordBox),
+    /// keccak256(salt || passphrase), as files before version 11 stored it. It is sealed as
+    /// above once the passphrase is next entered.
+    Legacy { salt: Vec<u8>, hash: Vec<u8> },
+}
+
+impl PassphraseHash {
+    /// Seals with fresh parameters for the password KDF in the settings
+    pub(crate) fn new(passphrase: &str) -> Self {
+        PassphraseHash::with_kdf(passphrase, new_password_kdf(), rand::random())
+    }
+
+    /// Seals with the given parameters and nonce, for reproducible output
+    pub(crate) fn with_kdf(passphrase: &str, kdf: PasswordKdf, nonce: [u8; 12]) -> Self {
+        PassphraseHash::Sealed(PasswordBox::seal_with_nonce(passphrase, &[], kdf, nonce))
+    }
+
+    /// Checks the passphrase, and seals a legacy hash it matches
+    pub(crate) fn verify(&mut self, passphrase: &str) -> bool {
+        let verified = match self {
+            PassphraseHash::Sealed(sealed) => sealed.open(passphrase).is_some(),
+            PassphraseHash::Legacy { salt, hash } => ct_eq(&salted_hash(salt, passphrase), hash),
+        };
+        if verified && matches!(self, PassphraseHash::Legacy { .. }) {
+            *self = PassphraseHash::new(passphrase);
+        }
+        verified
+    }
+}
+
+/// The accounts of a wallet. SSZ files write them as JSON; the SSZ layout is the one version 2
+/// files wrote, and only reads those, so fields added since skip it.
+#[derive(Serialize, Deserialize, Decode, Default)]
+pub(crate) struct AccountMetadata {
+    /// The parent private key deriving all accounts
+    #[serde(skip)]
+    #[ssz(skip_serializing, skip_deserializing)]
+    pub deriving_key: Option<XPrv>,
+    /// A vector of derived accounts
+    pub accounts: Vec<Account>,
+    /// The secondary passphrase that reveals protected accounts
+    #[serde(default)]
+    #[ssz(with = "json")]
+    pub(crate) protection: Option<PassphraseHash>,
+    /// Whether protected accounts are currently revealed. Never persisted.
+    #[serde(skip)]
+    #[ssz(skip_serializing, skip_deserializing)]
+    elevated: bool,
+    /// Unix timestamp (seconds) at which revealed accounts are hidden again. Never persisted.
+    #[serde(skip)]
+    #[ssz(skip_serializing, skip_deserializing)]
+    elevated_until: u64,
+    /// Hashes of the recovery phrase words for backup checks
+    #[serde(default)]
+    #[ssz(with = "json")]
+    pub(crate) backup: Option<BackupCheck>,
+    /// Set when outgoing transactions need a second approval password
+    #[serde(default)]
+    #[ssz(with = "json")]
+    pub(crate) approval: Option<ApprovalMode>,
+    /// Accounts not derived in sequence: imported keys, watch-only addresses and derived vanity
+    /// accounts found further along. Listed after the derived accounts and kept apart so the
+    /// position of a derived account stays its derivation index.
+    #[serde(default)]
+    pub(crate) external: Vec<Account>,
+    /// Hashes of transactions that weren't confirmed when the last session ended
+    #[serde(default)]
+    #[ssz(with = "json")]
+    pub(crate) pending: Vec<String>,
+    /// The layout of derived account paths. The deriving key sits at its root.
+    #[serde(default)]
+    #[ssz(with = "json")]
+    pub(crate) path_template: PathTemplate,
+    /// The entropy of the recovery phrase, encrypted under the seed. Wallets created before it
+    /// was kept and wallets restored from an xprv have none.
+    #[serde(default)]
+    #[ssz(with = "json")]
+    pub(crate) recovery_phrase: Option<SealedBox>,
+    /// The account-level xpub of a watch-only wallet, which derives accounts in place of the
+    /// deriving key
+    #[serde(default)]
+    #[ssz(with = "optional_text")]
+    pub(crate) watch_key: Option<String>,
+    /// Recipients saved under names. Left out while empty, so files from before it still match
+    /// their MAC.
+    #[serde(default, skip_serializing_if = "AddressBook::is_empty")]
+    #[ssz(skip_deserializing)]
+    pub(crate) address_book: AddressBook,
+    /// The gas limit and price sends start from. Left out while they are the defaults.
+    #[serde(default, skip_serializing_if = "GasPreferences::is_default")]
+    #[ssz(skip_deserializing)]
+    pub(crate) gas: GasPreferences,
+    /// Tokens the user added to those listed for the networks. Left out while empty.
+    #[serde(default, skip_serializing_if = "TokenRegistry::is_empty")]
+    #[ssz(skip_deserializing)]
+    pub(crate) tokens: TokenRegistry,
+    /// NFT collections the user tracks. Left out while empty.
+    #[serde(default, skip_serializing_if = "Collections::is_empty")]
+    #[ssz(skip_deserializing)]
+    pub(crate) collections: Collections,
+}
+
+impl AccountMetadata {
+    /// Creates AccountMetadata with the private deriving key and a default account
+    pub fn new(deriving_key: XPrv, path_template: PathTemplate) -> Self {
+        AccountMetadata {
+            deriving_key: Some(deriving_key.clone()),
+            accounts: vec![Account::new(&deriving_key, &path_template, 0)],
+            protection: None,
+            elevated: false,
+            elevated_until: 0,
+            backup: None,
+            approval: None,
+            external: vec![],
+            pending: vec![],
+            path_template,
+            recovery_phrase: None,
+            watch_key: None,
+            address_book: AddressBook::default(),
+            gas: GasPreferences::default(),
+            tokens: TokenRegistry::default(),
+            collections: Collections::default(),
+        }
+    }
+
+    /// Creates AccountMetadata for a watch-only wallet with the xpub at m/44'/60'/0' and a default
+    /// account. Watch-only wallets always use the standard layout.
+    fn watching(account_key: &XPub) -> Self {
+        let path_template = PathTemplate::standard();
+        AccountMetadata {
+            deriving_key: None,
+            accounts: vec![Account::watched(account_key, &path_template, 0)],
+            protection: None,
+            elevated: false,
+            elevated_until: 0,
+            backup: None,
+            approval: None,
+            external: vec![],
+            pending: vec![],
+            path_template,
+            recovery_phrase: None,
+            watch_key: Some(account_key.to_string(Prefix::XPUB)),
+            address_book: AddressBook::default(),
+            gas: GasPreferences::default(),
+            tokens: TokenRegistry::default(),
+            collections: Collections::default(),
+        }
+    }
+
+    /// Derives the account at `index`, from the deriving key or the xpub of a watch-only wallet
+    fn derive_account(&self, index: usize) -> Account {
+        match (&self.deriving_key, &self.watch_key) {
+            (Some(k), _) => Account::new(k, &self.path_template, index),
+            (None, Some(xpub)) => Account::watched(&XPub::from_str(xpub).unwrap(), &self.path_template, index),
+            (None, None) => unreachable!(),
+        }
+    }
+
+    /// Records the hashes needed to check the recovery phrase later. Requires the deriving key.
+    fn set_backup_check(&mut self, phrase: &str) {
+        let secret = key_bytes(self.deriving_key.as_ref().unwrap());
+        self.backup = Some(BackupCheck::new(phrase, &*secret, utils::now()));
+    }
+
+    /// Shows a one-line reminder at login if the backup hasn't been checked in a while
+    fn remind_backup_check(&mut self, now: u64) {
+        let settings = Settings::load().backup_reminder;
+        let backup = match &mut self.backup {
+            Some(b) if settings.enabled && b.reminder_due(now, settings.interval_months) => b,
+            _ => return,
+        };
+
+        println!("Reminder: you haven't checked your recovery phrase backup in over {} months. \
+            Use \"Verify my recovery phrase backup\" from the menu, or type d to dismiss.", settings.interval_months);
+        if utils::read_user_input() == "d" {
+            backup.dismissed_at = Some(now);
+        }
+    }
+
+    /// Quizzes the user on a few words of their recovery phrase. The phrase is never displayed.
+    fn verify_backup(&mut self, deriving_key: &XPrv) {
+        let backup = match &mut self.backup {
+            Some(b) => b,
+            None => return println!("There is no recovery phrase on record for this wallet, so there is nothing to check against"),
+        };
+
+        if !backup_quiz_passes(backup, &*key_bytes(deriving_key)) {
+            return println!("That word doesn't match. Please find your backup and try again.");
+        }
+        backup.last_verified = Some(utils::now());
+        println!("Your recovery phrase backup checks out");
+    }
+
+    /// Rebuilds the recovery phrase from the stored entropy. `seed` is what `unlock` returns.
+    pub fn recovery_phrase(&self, seed: &[u8], language: MnemonicLanguage) -> Option<Zeroizing<String>> {
+        let entropy = Zeroizing::new(self.recovery_phrase.as_ref()?.open(RECOVERY_PHRASE_CONTEXT, seed)?);
+        let mnemonic = Mnemonic::from_entropy(&entropy, language.language()).ok()?;
+        Some(Zeroizing::new(String::from(mnemonic.phrase())))
+    }
+
+    /// Shows the recovery phrase once the wallet password is entered again
+    fn show_recovery_phrase(&self, unlock: &dyn Fn(&str) -> Option<Zeroizing<Vec<u8>>>, language: MnemonicLanguage) {
+        if self.recovery_phrase.is_none() {
+            return println!("This wallet has no recovery phrase on record, so it can't be shown");
+        }
+        println!("Enter the wallet password: ");
+        let seed = match unlock(&utils::read_secret_input()) {
+            Some(seed) => seed,
+            None => return println!("Incorrect password"),
+        };
+
+        match self.recovery_phrase(&seed, language) {
+            Some(phrase) => {
+                println!("WARNING: anyone who sees this phrase can take every asset in this wallet.");
+                println!("Never share it, and make sure nobody is watching your screen.");
+                println!("Your secret recovery phrase: {}", phrase.as_str());
+                audit::record("Recovery phrase shown");
+            },
+            None => println!("The stored recovery phrase could not be decrypted"),
+        }
+    }
+
+    /// Derives accounts in order until `gap_limit` in a row have neither a balance nor sent
+    /// transactions, and keeps every account up to the last used one. `activity` returns the
+    /// balance and transaction count of an address. Nonces are set from the chain.
+    fn discover_accounts(&mut self, gap_limit: usize, activity: impl Fn(&str) -> Result<(u128, u64), String>) -> Result<usize, String> {
+        let mut scanned = vec![];
+        let mut unused = 0;
+        while unused < gap_limit {
+            let index = scanned.len();
+            let mut account = match self.accounts.get(index) {
+                Some(account) => account.clone(),
+                None => self.derive_account(index),
+            };
+            let (balance, nonce) = activity(&account.address)?;
+            account.nonce = nonce;
+            unused = if balance > 0 || nonce > 0 { 0 } else { unused + 1 };
+            scanned.push(account);
+        }
+
+        let used = scanned.len() - unused;
+        for (index, account) in scanned.into_iter().take(used).enumerate() {
+            match self.accounts.get_mut(index) {
+                Some(existing) => existing.nonce = account.nonce,
+                None => self.accounts.push(account),
+            }
+        }
+        Ok(self.accounts.len())
+    }
+
+    /// Creates a new account with specified index and returns a reference to it
+    pub fn create_account(&mut self, index: usize) -> &mut Account {
+        let account = self.derive_account(index);
+        // a vanity search may have found this index already
+        match self.external.iter().position(|a| a.address == account.address) {
+            Some(found) => {
+                let account = self.external.remove(found);
+                self.accounts.push(account);
+            },
+            None => {
+                journal::record(JournalEntry::AccountCreated { address: account.address.clone() });
+                self.accounts.push(account);
+            },
+        }
+        self.get_account(index)
+    }
+
+    /// Returns the first derived account, or the imported one of a wallet that derives none
+    pub fn default_account(&mut self) -> &mut Account {
+        self.get_account(0)
+    }
+
+    /// False for a wallet exported from a single account, which has no derived accounts and
+    /// must not derive any from its random deriving key, since nothing could restore them
+    fn derives_accounts(&self) -> bool {
+        !self.accounts.is_empty()
+    }
+
+    /// The number of derived and watch-only accounts
+    fn account_count(&self) -> usize {
+        self.accounts.len() + self.external.len()
+    }
+
+    /// Returns true if the account can be listed and used in the current session
+    fn is_visible(&self, index: usize) -> bool {
+        match self.accounts.get(index) {
+            Some(account) => !account.protected || self.is_elevated(),
+            None => true,
+        }
+    }
+
+    /// Whether protected accounts are revealed and their time hasn't run out
+    fn is_elevated(&self) -> bool {
+        self.elevated && utils::now() < self.elevated_until
+    }
+
+    /// Hides protected accounts again once their time ran out at `now`. Returns true if they were
+    /// revealed until then.
+    fn expire_elevation(&mut self, now: u64) -> bool {
+        if !self.elevated || now < self.elevated_until {
+            return false;
+        }
+        self.elevated = false;
+        true
+    }
+
+    /// `current` if it is visible, or else the first account that is
+    fn visible_or_first(&self, current: usize) -> usize {
+        if self.is_visible(current) {
+            current
+        } else {
+            (0..self.accounts.len()).find(|i| self.is_visible(*i)).unwrap()
+        }
+    }
+
+    /// Prints all the created accounts in the wallet, leaving out protected accounts unless elevated.
+    /// With `rpc`, their balances are fetched in one request along with their primary ENS names,
+    /// and otherwise the last known balances are shown.
+    pub fn print_accounts(&self, rpc: Option<&dyn EthRpc>) {
+        let network = networks::active();
+        let now = utils::now();
+        let visible: Vec<_> = self.accounts.iter().chain(&self.external).enumerate()
+            .filter(|(index, _)| self.is_visible(*index))
+            .collect();
+        let balances = match rpc.map(|rpc| rpc.get_balances(&visible.iter().map(|(_, acc)| acc.address.as_str()).collect::<Vec<_>>())) {
+            Some(Ok(balances)) => balances.into_iter().map(Some).collect(),
+            Some(Err(e)) => {
+                println!("Could not fetch the balances, so the last known ones are shown: {}", e);
+                vec![None; visible.len()]
+            },
+            None => vec![None; visible.len()],
+        };
+        for ((index, acc), fetched) in visible.into_iter().zip(balances) {
+            let tag = match (acc.watch_only, acc.imported_key.is_some()) {
+                (true, _) => String::from(" [watch]"),
+                (false, true) => String::from(" [imported]"),
+                // a vanity account found out of sequence
+                (false, false) if index >= self.accounts.len() => format!(" [{}]", acc.path),
+                (false, false) => String::new(),
+            };
+            let cached = || balance_cache::for_network(&acc.balance_cache, network.chain_id)
+                .map_or(String::new(), |cached| format!(" - {}", cached.describe(&network.currency, now)));
+            let balance = match fetched {
+                Some(Ok(wei)) => format!(" - {} {}", utils::wei_to_eth(wei), network.currency),
+                // one that failed still shows what was last known of it
+                Some(Err(_)) => format!("{} (couldn't be fetched)", cached()),
+                None => cached(),
+            };
+            let address = match rpc.and_then(|rpc| ens::primary_name(rpc, network.chain_id, &acc.address)) {
+                Some(name) => format!("{} {}", acc.address, name),
+                None => acc.address.clone(),
+            };
+            match &acc.label {
+                Some(label) => println!("{}) {}{} ({}){}", index, address, tag, label, balance),
+                None => println!("{}) {}{}{}", index, address, tag, balance),
+            }
+        }
+    }
+
+    /// Returns the account with given index. External accounts follow the derived accounts.
+    pub fn get_account(&mut self, index: usize) -> &mut Account {
+        match index.checked_sub(self.accounts.len()) {
+            Some(external) => &mut self.external[external],
+            None => &mut self.accounts[index],
+        }
+    }
+
+    /// Returns the index of the account with the given address, as used by `get_account`
+    fn position_of(&self, address: &str) -> usize {
+        self.find_position(address).unwrap()
+    }
+
+    /// The index of the account with the given address, ignoring case, if it is in the wallet
+    fn find_position(&self, address: &str) -> Option<usize> {
+        self.accounts.iter().chain(&self.external).position(|a| a.address.eq_ignore_ascii_case(address))
+    }
+
+    /// Lists the accounts with their balances and returns the one the user picks, or `current`
+    /// for an invalid choice
+    fn prompt_switch(&self, rpc: &dyn EthRpc, current: usize) -> usize {
+        self.print_accounts(Some(rpc));
+        match utils::read_user_input().parse::<usize>() {
+            Ok(i) if i < self.account_count() && self.is_visible(i) => i,
+            _ => {
+                println!("Invalid account");
+                current
+            },
+        }
+    }
+
+    /// Asks which kind of import to run. Keys can't be imported without a deriving key to
+    /// encrypt them under, as in a watch-only wallet.
+    fn import(&mut self, deriving_key: Option<&XPrv>) {
+        println!("1) Account labels from a MetaMask export");
+        println!("2) Watch-only addresses from a text file");
+        println!("3) A single address to watch");
+        println!("4) A private key");
+        println!("5) A keystore file (geth, MetaMask)");
+        println!("6) Back");
+
+        match utils::read_user_input().parse::<u8>() {
+            Ok(1) if deriving_key.is_some() && !self.derives_accounts() => println!("This wallet derives no accounts to label"),
+            Ok(1) => self.import_labels(),
+            Ok(2) => self.import_watched(),
+            Ok(3) => self.watch_address(),
+            Ok(4 | 5) if deriving_key.is_none() => println!("A watch-only wallet can't hold private keys"),
+            Ok(4) => self.import_private_key(deriving_key.unwrap()),
+            Ok(5) => self.import_keystore(deriving_key.unwrap()),
+            Ok(6) => {},
+            _ => println!("Invalid option"),
+        }
+    }
+
+    /// Reads a file of addresses, one per line and optionally followed by `,label`, previews what
+    /// would be added and adds every new address as a watch-only account once the user confirms
+    fn import_watched(&mut self) {
+        println!("Enter the path of the address file (or type q to cancel): ");
+        let path = utils::read_user_input();
+        if path == "q" {
+            return;
+        }
+
+        let text = match std::fs::read_to_string(&path) {
+            Ok(t) => t,
+            Err(e) => return println!("Error reading file: {}", e),
+        };
+        let existing: Vec<String> = self.accounts.iter().chain(&self.external).map(|a| a.address.clone()).collect();
+        let plan = watch::plan_watch_import(&text, &existing);
+
+        println!("Watch-only accounts to add:");
+        for (address, label) in &plan.added {
+            match label {
+                Some(label) => println!("\t{} ({})", address, label),
+                None => println!("\t{}", address),
+            }
+        }
+        if !plan.duplicates.is_empty() {
+            println!("Already in the wallet, skipped:");
+            for (line, address) in &plan.duplicates {
+                println!("\tline {}: {}", line, address);
+            }
+        }
+        if !plan.invalid.is_empty() {
+            println!("Invalid addresses or checksums:");
+            for (line, text) in &plan.invalid {
+                println!("\tline {}: {}", line, text);
+            }
+        }
+        println!("{} to add, {} skipped, {} invalid", plan.added.len(), plan.duplicates.len(), plan.invalid.len());
+        if plan.added.is_empty() {
+            return println!("No accounts to add");
+        }
+
+        println!("Press 1 to ADD");
+        println!("Press any other key to CANCEL");
+        if utils::read_user_input() != "1" {
+            return println!("Watch-only import canceled");
+        }
+
+        let added = plan.added.len();
+        for (address, label) in plan.added {
+            journal::record(JournalEntry::WatchOnlyAdded { address: address.clone() });
+            self.external.push(Account::watch(address, label));
+        }
+        println!("Added {} watch-only accounts", added);
+    }
+
+    /// Adds a watch-only account for one address typed in, such as a friend's or an exchange's,
+    /// with an optional label
+    fn watch_address(&mut self) {
+        println!("Enter the address to watch (or type q to cancel): ");
+        let input = utils::read_user_input();
+        if input == "q" {
+            return;
+        }
+        let existing: Vec<String> = self.accounts.iter().chain(&self.external).map(|a| a.address.clone()).collect();
+        let address = match watch::parse_watch_address(&input, &existing) {
+            Ok(a) => a,
+            Err(e) => return println!("{}", e),
+        };
+        println!("Enter a label for it (or press enter for none): ");
+        let label = utils::read_user_input();
+        let label = Some(String::from(label.trim())).filter(|l| !l.is_empty());
+
+        journal::record(JournalEntry::WatchOnlyAdded { address: address.clone() });
+        self.external.push(Account::watch(address.clone(), label));
+        println!("Watching {}", address);
+    }
+
+    /// Reads a MetaMask-style label export, previews which accounts it matches and applies the
+    /// labels once the user confirms. Accounts up to the highest matched index are created, and
+    /// addresses not derived from the seed can be added as labelled watch-only accounts.
+    fn import_labels(&mut self) {
+        println!("Enter the path of the label export file (or type q to cancel): ");
+        let path = utils::read_user_input();
+        if path == "q" {
+            return;
+        }
+
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(json) => match labels::parse_label_export(&json) {
+                Ok(e) => e,
+                Err(e) => return println!("{}", e),
+            },
+            Err(e) => return println!("Error reading file: {}", e),
+        };
+        let plan = labels::plan_label_import(entries, labels::LABEL_DISCOVERY_DEPTH, |index| {
+            match self.accounts.get(index) {
+                Some(account) => account.address.clone(),
+                None => self.derive_account(index).address,
+            }
+        });
+
+        println!("Labels to apply:");
+        for (index, address, label) in &plan.matched {
+            let status = if *index < self.accounts.len() { "existing" } else { "new" };
+            println!("\t{}) {} -> {} [{} account]", index, address, label, status);
+        }
+        let existing: Vec<String> = self.accounts.iter().chain(&self.external).map(|a| a.address.clone()).collect();
+        let mut watched = Vec::new();
+        if !plan.unmatched.is_empty() {
+            println!("Addresses not derived from this wallet's seed (probably imported keys):");
+            for entry in &plan.unmatched {
+                match watch::parse_watch_address(&entry.address, &existing) {
+                    Ok(address) => {
+                        println!("\t{} ({})", address, entry.label);
+                        watched.push((address, entry.label.clone()));
+                    },
+                    Err(_) => println!("\t{} ({}) [already in the wallet, skipped]", entry.address, entry.label),
+                }
+            }
+        }
+        if !plan.invalid.is_empty() {
+            println!("Invalid addresses or checksums:");
+            for entry in &plan.invalid {
+                println!("\t{} ({})", entry.address, entry.label);
+            }
+        }
+        if plan.matched.is_empty() && watched.is_empty() {
+            return println!("No labels to apply");
+        }
+
+        if !plan.matched.is_empty() {
+            println!("Press 1 to APPLY");
+            println!("Press any other key to CANCEL");
+            if utils::read_user_input() != "1" {
+                return println!("Label import canceled");
+            }
+
+            for (index, _, label) in plan.matched {
+                while self.accounts.len() <= index {
+                    let next = self.accounts.len();
+                    self.create_account(next);
+                }
+                journal::record(JournalEntry::AccountLabeled { address: self.accounts[index].address.clone(), label: label.clone() });
+                self.accounts[index].label = Some(label);
+            }
+            println!("Labels applied");
+        }
+
+        if !watched.is_empty() {
+            println!("Add the {} addresses not derived from this seed as watch-only accounts with their labels?", watched.len());
+            println!("Press 1 to ADD");
+            println!("Press any other key to SKIP");
+            if utils::read_user_input() != "1" {
+                return println!("Addresses not derived from this seed skipped");
+            }
+
+            let added = watched.len();
+            for (address, label) in watched {
+                journal::record(JournalEntry::WatchOnlyAdded { address: address.clone() });
+                self.external.push(Account::watch(address, Some(label)));
+            }
+            println!("Added {} watch-only accounts", added);
+        }
+    }
+
+    /// Adds an account for a raw private key, such as one exported from MetaMask. The key can't be
+    /// derived again, so it is stored encrypted under the deriving key, which only the wallet
+    /// password unlocks. A watch-only account for the same address becomes a full account.
+    fn import_private_key(&mut self, deriving_key: &XPrv) {
+        println!("Enter the private key as 64 hex characters (or type q to cancel): ");
+        let input = Zeroizing::new(utils::read_secret_input());
+        if *input == "q" {
+            return;
+        }
+
+        match utils::parse_private_key(input.trim()) {
+            Ok(secret) => self.add_imported_key(deriving_key, &secret),
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    /// Reads a keystore V3 file and imports the key it holds once its password is entered
+    fn import_keystore(&mut self, deriving_key: &XPrv) {
+        println!("Enter the path of the keystore file (or type q to cancel): ");
+        let path = utils::read_user_input();
+        if path == "q" {
+            return;
+        }
+
+        let keystore: Keystore = match std::fs::read_to_string(&path) {
+            Ok(json) => match serde_json::from_str(&json) {
+                Ok(k) => k,
+                Err(e) => return println!("Not a keystore file: {}", e),
+            },
+            Err(e) => return println!("Error reading file: {}", e),
+        };
+        println!("Enter the keystore file's password: ");
+        let password = Zeroizing::new(utils::read_secret_input());
+        println!("Decrypting...");
+        match keystore::decrypt(&keystore, &password) {
+            Ok(secret) => self.add_imported_key(deriving_key, &secret),
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    /// Adds a non-HD account for a private key, or lets a watch-only account for the same address
+    /// send
+    fn add_imported_key(&mut self, deriving_key: &XPrv, secret: &[u8; 32]) {
+        let address = utils::secret_key_to_address(secret).unwrap();
+        let existing = self.accounts.iter().chain(&self.external).position(|a| a.address == address);
+        let sealed = SealedBox::seal(IMPORTED_KEY_CONTEXT, &*key_bytes(deriving_key), secret.as_slice());
+
+        match existing.map(|i| self.get_account(i)) {
+            Some(account) if account.watch_only => {
+                account.watch_only = false;
+                account.path = String::from(IMPORTED_KEY_PATH);
+                account.imported_key = Some(sealed);
+                println!("Watch-only account {} can now send", address);
+            },
+            Some(_) => return println!("Account {} is already in the wallet", address),
+            None => {
+                self.external.push(Account::imported(address.clone(), sealed));
+                println!("Imported account {}", address);
+            },
+        }
+        journal::record(JournalEntry::KeyImported { address });
+    }
+
+    /// Searches for an address with a chosen prefix and suffix, among the derived accounts after
+    /// the existing ones or among random keys, and adds the first match
+    fn vanity_account(&mut self, deriving_key: &XPrv) {
+        println!("1) Derive accounts from this wallet (the recovery phrase restores them)");
+        println!("2) Random keys (imported, so only this wallet file or an export restores them)");
+        println!("3) Back");
+        let derived = match utils::read_user_input().parse::<u8>() {
+            Ok(1) if !self.derives_accounts() => return println!("This wallet holds a single exported account, and can't derive others"),
+            Ok(1) => true,
+            Ok(2) => false,
+            Ok(3) => return,
+            _ => return println!("Invalid option"),
+        };
+
+        println!("Enter the prefix (or press enter for none): ");
+        let prefix = read_user_input();
+        println!("Enter the suffix (or press enter for none): ");
+        let suffix = read_user_input();
+        let pattern = match VanityPattern::parse(&prefix, &suffix) {
+            Ok(pattern) => pattern,
+            Err(e) => return println!("{}", e),
+        };
+
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        println!("Searching on {} threads, about {} attempts on average. Press Ctrl-C to cancel.", threads, pattern.expected_attempts());
+        let progress = |attempts, rate| println!("{} attempts ({}/s)", attempts, rate);
+
+        if derived {
+            let existing: Vec<String> = self.accounts.iter().chain(&self.external).map(|a| a.address.clone()).collect();
+            let template = &self.path_template;
+            let candidate = |n: u64| {
+                let index = usize::try_from(n).ok().filter(|i| *i < ChildNumber::HARDENED_FLAG as usize)?;
+                let account = Account::new(deriving_key, template, index);
+                let address = account.address.clone();
+                (!existing.contains(&address)).then_some((account, address))
+            };
+            let first = self.accounts.len() as u64;
+            match session::run_interruptible(|cancel| vanity::search(threads, first, &pattern, candidate, cancel, progress)) {
+                Some((index, account)) => {
+                    println!("Found account {} at {}", account.address, account.path);
+                    println!("Note the index {}: restoring from the recovery phrase only finds accounts in sequence", index);
+                    journal::record(JournalEntry::AccountCreated { address: account.address.clone() });
+                    self.external.push(account);
+                },
+                None => println!("Search cancelled"),
+            }
+        } else {
+            let candidate = |_n: u64| {
+                let secret = Zeroizing::new(rand::random::<[u8; 32]>());
+                let address = utils::secret_key_to_address(&secret)?;
+                Some((secret, address))
+            };
+            match session::run_interruptible(|cancel| vanity::search(threads, 0, &pattern, candidate, cancel, progress)) {
+                Some((_attempt, secret)) => self.add_imported_key(deriving_key, &secret),
+                None => println!("Search cancelled"),
+            }
+        }
+    }
+
+    /// Reports on the transactions left pending by earlier sessions, keeping those still pending
+    fn check_pending(&mut self, rpc: &dyn EthRpc) {
+        self.pending.retain(|hash| match rpc.transaction_status(hash) {
+            TransactionStatus::Confirmed => {
+                println!("Transaction {} from an earlier session was confirmed", hash);
+                false
+            },
+            TransactionStatus::Failed => {
+                println!("Transaction {} from an earlier session failed", hash);
+                false
+            },
+            TransactionStatus::Pending | TransactionStatus::Unknown => true,
+        });
+        if !self.pending.is_empty() {
+            println!("{} transaction(s) from earlier sessions are still pending", self.pending.len());
+        }
+    }
+
+    /// When the next scheduled transaction this session sends is due: the first one scheduled for
+    /// `not_before` or later, of an account that can sign. One that was due when sending was
+    /// last tried, at `tried_at`, is tried again `SCHEDULED_RETRY_SECS` later.
+    fn next_scheduled(&self, not_before: u64, tried_at: u64) -> Option<u64> {
+        self.accounts.iter()
+            .filter(|a| !a.protected || self.is_elevated())
+            .flat_map(|a| &a.scheduled)
+            .filter(|tx| tx.send_at >= not_before)
+            .map(|tx| if tx.is_due(tried_at) { tried_at + SCHEDULED_RETRY_SECS } else { tx.send_at })
+            .min()
+    }
+
+    /// Sends the scheduled transactions due at `now` of every account that can sign, leaving
+    /// those scheduled before `not_before`
+    fn send_due_scheduled(&mut self, rpc: &dyn EthRpc, deriving_key: &XPrv, now: u64, not_before: u64) {
+        let elevated = self.is_elevated();
+        for account in self.accounts.iter_mut().filter(|a| !a.protected || elevated) {
+            account.send_due_scheduled(rpc, deriving_key, now, not_before);
+        }
+    }
+
+    /// Offers to send every scheduled transaction whose time passed while the wallet was closed
+    fn send_overdue_scheduled(&mut self, rpc: &dyn EthRpc, deriving_key: &XPrv, now: u64) {
+        // protected accounts can't sign until revealed, so their transactions wait
+        let overdue = self.accounts.iter()
+            .filter(|a| !a.protected)
+            .flat_map(|a| &a.scheduled)
+            .filter(|tx| tx.is_due(now))
+            .count();
+        if overdue == 0 {
+            return;
+        }
+
+        println!("{} scheduled transaction(s) are overdue. Send now? (y/n)", overdue);
+        if utils::read_user_input() != "y" {
+            return println!("Overdue transactions were kept. Send or cancel them from the scheduled transactions menu.");
+        }
+        for account in self.accounts.iter_mut().filter(|a| !a.protected) {
+            account.send_due_scheduled(rpc, deriving_key, now, 0);
+        }
+    }
+
+    /// Menu for revealing protected accounts and changing which accounts are protected.
+    /// Returns the index of an account that may stay selected afterwards.
+    fn manage_protected(&mut self, current: usize, unlock: &dyn Fn(&str) -> Option<Zeroizing<Vec<u8>>>) -> usize {
+        loop {
+            println!("1) Reveal protected accounts");
+            println!("2) Hide protected accounts");
+            println!("3) Set the secondary passphrase");
+            println!("4) Protect or unprotect an account");
+            println!("5) Back");
+
+            match utils::read_user_input().parse::<u8>() {
+                Ok(1) => match &mut self.protection {
+                    Some(protection) => {
+                        println!("Enter the secondary passphrase: ");
+                        if protection.verify(&utils::read_secret_input()) {
+                            let minutes = Settings::load().security.reveal_timeout_mins;
+                            self.elevated = true;
+                            self.elevated_until = utils::now() + minutes * 60;
+                            println!("Protected accounts revealed for {} minutes", minutes);
+                        } else {
+                            println!("Incorrect passphrase");
+                        }
+                    },
+                    None => println!("No secondary passphrase has been set"),
+                },
+                Ok(2) => {
+                    self.elevated = false;
+                    println!("Protected accounts hidden");
+                },
+                Ok(3) => {
+                    if !self.authorize_protection_change(unlock, true) {
+                        continue;
+                    }
+                    println!("Enter the new secondary passphrase: ");
+                    let passphrase = utils::read_secret_input();
+                    if passphrase.is_empty() {
+                        println!("Passphrase must not be empty");
+                        continue;
+                    }
+                    self.protection = Some(PassphraseHash::new(&passphrase));
+                    println!("Secondary passphrase set");
+                },
+                Ok(4) => {
+                    if self.protection.is_none() {
+                        println!("Set a secondary passphrase first");
+                        continue;
+                    }
+                    if !self.authorize_protection_change(unlock, false) {
+                        continue;
+                    }
+                    // revealed to list them, and only as long as that takes
+                    let revealed = (self.elevated, self.elevated_until);
+                    (self.elevated, self.elevated_until) = (true, u64::MAX);
+                    self.print_accounts(None);
+                    (self.elevated, self.elevated_until) = revealed;
+                    println!("Enter the number of the account to protect or unprotect: ");
+                    match utils::read_user_input().parse::<usize>() {
+                        Ok(i) if i < self.accounts.len() => {
+                            let visible = self.accounts.iter().filter(|a| !a.protected).count();
+                            if !self.accounts[i].protected && visible == 1 {
+                                println!("At least one account must stay unprotected");
+                                continue;
+                            }
+                            self.accounts[i].protected = !self.accounts[i].protected;
+                            let state = if self.accounts[i].protected { "protected" } else { "unprotected" };
+                            println!("Account {} is now {}", i, state);
+                        },
+                        _ => println!("Invalid account"),
+                    }
+                },
+                Ok(5) => break,
+                _ => println!("Invalid option"),
+            }
+        }
+
+        // never leave a hidden account selected
+        self.visible_or_first(current)
+    }
+
+    /// Asks for the main password and, if one is set, the current secondary passphrase.
+    /// `allow_unset` permits the passphrase check to be skipped when there is no passphrase yet.
+    fn authorize_protection_change(&mut self, unlock: &dyn Fn(&str) -> Option<Zeroizing<Vec<u8>>>, allow_unset: bool) -> bool {
+        println!("Enter the wallet password: ");
+        if unlock(&utils::read_secret_input()).is_none() {
+            println!("Incorrect password");
+            return false;
+        }
+        match &mut self.protection {
+            Some(protection) => {
+                println!("Enter the current secondary passphrase: ");
+                if !protection.verify(&utils::read_secret_input()) {
+                    println!("Incorrect passphrase");
+                    return false;
+                }
+                true
+            },
+            None => allow_unset,
+        }
+    }
+
+    /// Discards staged transactions that waited too long for approval
+    fn expire_staged(&mut self, now: u64) {
+        if let Some(queue) = self.approval.as_mut().and_then(ApprovalMode::queue) {
+            for transaction in queue.remove_expired(now) {
+                println!("Staged transaction expired: {}", transaction.describe());
+                audit::record(&format!("Staged transaction expired: {}", transaction.describe()));
+            }
+        }
+    }
+
+    fn stage_transaction(&mut self, transaction: StagedTransaction) {
+        match self.approval.as_mut().and_then(ApprovalMode::queue) {
+            Some(queue) => {
+                let id = queue.stage(transaction);
+                let description = queue.get(id).unwrap().describe();
+                println!("Waiting for approval: {}", description);
+                audit::record(&format!("Transaction staged: {}", description));
+            },
+            None => println!("The staged transactions could not be decrypted, so nothing was staged"),
+        }
+    }
+
+    /// Menu for the two-person rule: staging sends and approving or rejecting them
+    fn manage_approvals(&mut self, rpc: &dyn EthRpc, deriving_key: &XPrv, unlock: &dyn Fn(&str) -> Option<Zeroizing<Vec<u8>>>) {
+        if self.approval.is_none() {
+            return self.enable_approvals(unlock);
+        }
+
+        loop {
+            self.expire_staged(utils::now());
+            println!("1) List staged transactions");
+            println!("2) Approve and send a staged transaction");
+            println!("3) Reject a staged transaction");
+            println!("4) Stop requiring approval");
+            println!("5) Back");
+
+            match utils::read_user_input().parse::<u8>() {
+                Ok(1) => self.print_staged(),
+                Ok(2) => self.approve_staged(rpc, deriving_key),
+                Ok(3) => {
+                    let queue = match self.approval.as_mut().and_then(ApprovalMode::queue) {
+                        Some(q) => q,
+                        None => continue,
+                    };
+                    println!("Enter the number of the transaction to reject: ");
+                    match utils::read_user_input().trim_start_matches('#').parse::<u32>().ok().and_then(|id| queue.remove(id)) {
+                        Some(transaction) => {
+                            println!("Rejected");
+                            audit::record(&format!("Staged transaction rejected: {}", transaction.describe()));
+                        },
+                        None => println!("No such staged transaction"),
+                    }
+                },
+                Ok(4) => {
+                    if self.disable_approvals(unlock) {
+                        break;
+                    }
+                },
+                Ok(5) => break,
+                _ => println!("Invalid option"),
+            }
+        }
+    }
+
+    fn enable_approvals(&mut self, unlock: &dyn Fn(&str) -> Option<Zeroizing<Vec<u8>>>) {
+        println!("Approval mode: every send is staged until a second, different password approves it.");
+        if self.accounts.iter().any(|a| !a.scheduled.is_empty()) {
+            return println!("Cancel all scheduled transactions first, they would be sent without approval");
+        }
+        println!("Enter the wallet password: ");
+        if unlock(&utils::read_secret_input()).is_none() {
+            return println!("Incorrect password");
+        }
+        println!("Enter the approval password for the second person: ");
+        let approval_password = utils::read_secret_input();
+        if approval_password.is_empty() {
+            return println!("The approval password must not be empty");
+        }
+        if unlock(&approval_password).is_some() {
+            return println!("The approval password must differ from the wallet password");
+        }
+
+        self.approval = Some(ApprovalMode::new(&approval_password));
+        println!("Outgoing transactions now need approval");
+        audit::record("Approval mode enabled");
+    }
+
+    /// Turns approval mode off after both passwords are entered. Staged transactions are rejected.
+    /// Returns true if it was turned off.
+    fn disable_approvals(&mut self, unlock: &dyn Fn(&str) -> Option<Zeroizing<Vec<u8>>>) -> bool {
+        println!("Enter the wallet password: ");
+        if unlock(&utils::read_secret_input()).is_none() {
+            println!("Incorrect password");
+            return false;
+        }
+        println!("Enter the approval password: ");
+        let approved = self.approval.as_mut().map(|a| a.verify_approver(&utils::read_secret_input())).unwrap_or(false);
+        if !approved {
+            println!("Incorrect approval password");
+            audit::record("Attempt to disable approval mode refused: incorrect approval password");
+            return false;
+        }
+
+        if let Some(queue) = self.approval.as_mut().and_then(ApprovalMode::queue) {
+            for transaction in queue.staged.drain(..) {
+                audit::record(&format!("Staged transaction rejected: {}", transaction.describe()));
+            }
+        }
+        self.approval = None;
+        println!("Outgoing transactions no longer need approval");
+        audit::record("Approval mode disabled");
+        true
+    }
+
+    fn print_staged(&mut self) {
+        match self.approval.as_mut().and_then(ApprovalMode::queue) {
+            Some(queue) if queue.staged.is_empty() => println!("No staged transactions"),
+            Some(queue) => queue.staged.iter().for_each(|t| println!("{}", t.describe())),
+            None => println!("The staged transactions could not be decrypted"),
+        }
+    }
+
+    /// Asks for the approval password, then signs and broadcasts a staged transaction. It stays
+    /// staged if sending fails.
+    fn approve_staged(&mut self, rpc: &dyn EthRpc, deriving_key: &XPrv) {
+        self.print_staged();
+        let approval = match &mut self.approval {
+            Some(a) => a,
+            None => return,
+        };
+        println!("Enter the number of the transaction to approve: ");
+        let id = utils::read_user_input().trim_start_matches('#').parse::<u32>().unwrap_or_default();
+        let transaction = match approval.queue().and_then(|q| q.get(id)) {
+            Some(t) => t.clone(),
+            None => return println!("No such staged transaction"),
+        };
+
+        println!("Enter the approval password: ");
+        if !approval.verify_approver(&utils::read_secret_input()) {
+            println!("Incorrect approval password");
+            return audit::record(&format!("Approval refused, incorrect approval password: {}", transaction.describe()));
+        }
+
+        if self.send_approved(rpc, deriving_key, &transaction) {
+            audit::record(&format!("Staged transaction approved and sent: {}", transaction.describe()));
+        } else {
+            println!("The transaction stays staged");
+            audit::record(&format!("Staged transaction approved but not sent: {}", transaction.describe()));
+        }
+    }
+
+    /// Signs and sends an approved staged transaction from the account that staged it, and takes
+    /// it off the queue once sent. The account is found by its address, since indices shift as
+    /// accounts are added, and a transaction whose account is gone or can't sign is refused.
+    fn send_approved(&mut self, rpc: &dyn EthRpc, deriving_key: &XPrv, transaction: &StagedTransaction) -> bool {
+        let account = match self.find_position(&transaction.from) {
+            Some(index) => self.get_account(index),
+            None => {
+                println!("{} is no longer in this wallet, so the transaction can't be signed", transaction.from);
+                return false;
+            },
+        };
+        if account.watch_only {
+            println!("{} is watch-only now, so the transaction can't be signed", transaction.from);
+            return false;
+        }
+        if !account.send_staged(rpc, deriving_key, transaction) {
+            return false;
+        }
+        if let Some(queue) = self.approval.as_mut().and_then(ApprovalMode::queue) {
+            queue.remove(transaction.id);
+        }
+        true
+    }
+
+    /// Runs an account, allowing for creation of new accounts and switching between accounts when user opts to do so.
+    /// `unlock` recovers the seed from the wallet password, for actions that need more than the
+    /// deriving key.
+    /// `source` tells what `unlock` returns, and `language` is the wordlist of the recovery phrase.
+    /// `save` stores the wallet with these accounts, and is called after every action that
+    /// changes them.
+    /// Scheduled transactions that fall due while it runs are sent when they do, and revealed
+    /// protected accounts are hidden once their time runs out, leaving any prompt the user was
+    /// at. Older scheduled transactions are left to the overdue prompt shown at login.
+    pub fn run(&mut self, rpc: &dyn EthRpc, deriving_key: XPrv, unlock: &dyn Fn(&str) -> Option<Zeroizing<Vec<u8>>>, save: &mut dyn FnMut(&mut AccountMetadata), source: KeySource, language: MnemonicLanguage) -> u8 {
+        let unlocked_at = utils::now();
+        self.remind_backup_check(unlocked_at);
+        self.check_pending(rpc);
+        self.send_overdue_scheduled(rpc, &deriving_key, unlocked_at);
+        self.expire_staged(unlocked_at);
+        save(self);
+        let expiry_secs = Settings::load().approvals.expiry_hours * 3600;
+        let mut approvals_required = self.approval.is_some();
+        let mut book = self.address_book.clone();
+        let mut gas = self.gas.clone();
+        let derives_accounts = self.derives_accounts();
+        let timer = DueTimer::start(session::request_due);
+        let _block_watcher = refresh::BlockWatcher::start();
+        let mut tried_at = 0;
+        let mut current = self.default_account().address.clone();
+
+        loop {
+            // a scheduled transaction falling due, or revealed accounts being hidden, leaves the
+            // prompt the user is at, like locking does, to be handled here
+            let code = session::run_until_due(|| {
+                let index = self.position_of(&current);
+                let mut account = self.get_account(index);
+                loop {
+                    current.clone_from(&account.address);
+                    if self.expire_elevation(utils::now()) {
+                        println!("Protected accounts are hidden again");
+                    }
+                    let revealed_until = Some(self.elevated_until).filter(|_| self.elevated);
+                    timer.set(self.next_scheduled(unlocked_at, tried_at).into_iter().chain(revealed_until).min());
+                    let index = self.visible_or_first(self.position_of(&current));
+                    account = self.get_account(index);
+                    match account.run(rpc, &deriving_key, approvals_required, &book, &mut gas) {
+                        CHANGED => {
+                            let address = account.address.clone();
+                            let index = self.position_of(&address);
+                            self.gas.strategy = gas.strategy;
+                            save(self);
+                            account = self.get_account(index);
+                        },
+                        2 => {
+                            let address = account.address.clone();
+                            let index = self.position_of(&address);
+                            account = self.get_account(index);
+                            if let Some(transaction) = account.prompt_staged_transfer(rpc, index, expiry_secs, &book) {
+                                self.stage_transaction(transaction);
+                                save(self);
+                            }
+                            account = self.get_account(index);
+                        },
+                        3 if !derives_accounts => println!("This wallet holds a single exported account, and can't create others"),
+                        3 => {
+                            let index = self.accounts.len();
+                            self.create_account(index);
+                            save(self);
+                            account = self.get_account(index);
+                        },
+                        4 => {
+                            let address = account.address.clone();
+                            let current = self.position_of(&address);
+                            // switch to user selected account
+                            let option = self.prompt_switch(rpc, current);
+                            account = self.get_account(option);
+                        },
+                        5 => {
+                            let address = account.address.clone();
+                            self.import(Some(&deriving_key));
+                            save(self);
+                            let index = self.position_of(&address);
+                            account = self.get_account(index);
+                        },
+                        7 => {
+                            let address = account.address.clone();
+                            let current = self.position_of(&address);
+                            let index = self.manage_protected(current, unlock);
+                            save(self);
+                            account = self.get_account(index);
+                        },
+                        9 => {
+                            let address = account.address.clone();
+                            let index = self.position_of(&address);
+                            self.verify_backup(&deriving_key);
+                            save(self);
+                            account = self.get_account(index);
+                        },
+                        10 => {
+                            let address = account.address.clone();
+                            let index = self.position_of(&address);
+                            air_gapped_signer(unlock, source, self);
+                            save(self);
+                            account = self.get_account(index);
+                        },
+                        11 => {
+                            let address = account.address.clone();
+                            let index = self.position_of(&address);
+                            self.manage_approvals(rpc, &deriving_key, unlock);
+                            save(self);
+                            approvals_required = self.approval.is_some();
+                            account = self.get_account(index);
+                        },
+                        12 => export_private_key_prompt(account, &deriving_key, &book, unlock),
+                        13 => {
+                            let address = account.address.clone();
+                            let index = self.position_of(&address);
+                            self.show_recovery_phrase(unlock, language);
+                            account = self.get_account(index);
+                        },
+                        14 => {
+                            let address = account.address.clone();
+                            self.vanity_account(&deriving_key);
+                            save(self);
+                            let index = self.position_of(&address);
+                            account = self.get_account(index);
+                        },
+                        16 => {
+                            let address = account.address.clone();
+                            let index = self.position_of(&address);
+                            self.manage_address_book(save);
+                            book = self.address_book.clone();
+                            account = self.get_account(index);
+                        },
+                        17 => {
+                            let address = account.address.clone();
+                            let index = self.position_of(&address);
+                            self.manage_gas_preferences(save);
+                            gas = self.gas.clone();
+                            account = self.get_account(index);
+                        },
+                        19 => {
+                            let address = account.address.clone();
+                            let index = self.position_of(&address);
+                            self.manage_tokens(rpc, &deriving_key, index, approvals_required, save);
+                            gas = self.gas.clone();
+                            account = self.get_account(index);
+                        },
+                        20 => {
+                            let address = account.address.clone();
+                            let index = self.position_of(&address);
+                            self.manage_nfts(rpc, &deriving_key, index, approvals_required, save);
+                            gas = self.gas.clone();
+                            account = self.get_account(index);
+                        },
+                        21 => {
+                            let address = account.address.clone();
+                            let index = self.position_of(&address);
+                            self.view_balances_across_networks(index, save);
+                            account = self.get_account(index);
+                        },
+                        // changing the password, backing up and locking store the wallet, which only the
+                        // wallet can do
+                        22 => return 22,
+                        23 => return 23,
+                        24 => return 24,
+                        25 => return 25,
+                        _ => print!("Invalid option"),
+                    }
+                }
+            });
+            match code {
+                Some(code) => return code,
+                None => {
+                    println!();
+                    if self.expire_elevation(utils::now()) {
+                        println!("Protected accounts are hidden again, so what you were doing was left without sending anything");
+                    } else {
+                        println!("A scheduled transaction is due, so what you were doing was left without sending anything");
+                    }
+                    tried_at = utils::now();
+                    self.send_due_scheduled(rpc, &deriving_key, tried_at, unlocked_at);
+                    save(self);
+                },
+            }
+        }
+    }
+
+    /// The tokens of the active network: those listed for it, then those the user registered
+    fn token_list(&self) -> Vec<Token> {
+        self.token_list_for(&networks::active())
+    }
+
+    /// The tokens listed for `network`, then those the user registered on its chain
+    fn token_list_for(&self, network: &NetworkConfig) -> Vec<Token> {
+        let mut tokens = network.tokens();
+        for registered in self.tokens.for_chain(network.chain_id) {
+            if !tokens.iter().any(|t| t.address.eq_ignore_ascii_case(&registered.token.address)) {
+                tokens.push(registered.token.clone());
+            }
+        }
+        tokens
+    }
+
+    /// Shows the balances of the account at `index` on every configured network, with the tokens
+    /// of each, saving if the active network's balance was cached. The total of every account
+    /// can be shown after it.
+    fn view_balances_across_networks(&mut self, index: usize, save: &mut dyn FnMut(&mut AccountMetadata)) {
+        let configured: Vec<(NetworkConfig, Vec<Token>)> = networks::configured().into_iter()
+            .map(|network| {
+                let tokens = self.token_list_for(&network);
+                (network, tokens)
+            })
+            .collect();
+        if self.get_account(index).view_balances_across_networks(&configured) {
+            save(self);
+        }
+        let settings = Settings::load().prices;
+        let visible = (0..self.account_count()).filter(|i| self.is_visible(*i)).count();
+        if configured.is_empty() || settings.api_url.is_none() || visible < 2 {
+            return;
+        }
+        println!("Enter w to total every account in the wallet, or press enter to go back");
+        if read_user_input() == "w" {
+            self.view_wallet_total(&configured, &settings.currency);
+        }
+    }
+
+    /// Prints what each account shown in the wallet holds across `configured`, natively and in
+    /// tokens, in `fiat`, and their total. Protected accounts are left out unless elevated.
+    fn view_wallet_total(&self, configured: &[(NetworkConfig, Vec<Token>)], fiat: &str) {
+        let accounts: Vec<&Account> = self.accounts.iter().chain(&self.external).enumerate()
+            .filter(|(index, _)| self.is_visible(*index))
+            .map(|(_, account)| account)
+            .collect();
+        let owners: Vec<[u8; 20]> = match accounts.iter().map(|account| account.address_bytes()).collect() {
+            Ok(owners) => owners,
+            Err(e) => return println!("{}", e),
+        };
+        println!("Asking {} networks about {} accounts...", configured.len(), accounts.len());
+        let fetched = portfolio::query_all(configured, |(network, tokens)| {
+            let rpc = portfolio::connect(network)?;
+            let addresses: Vec<&str> = accounts.iter().map(|account| account.address.as_str()).collect();
+            let balances = rpc.get_balances(&addresses)?;
+            Ok(balances.into_iter().zip(&owners).map(|(balance, owner)| {
+                let held: Vec<_> = tokens.iter()
+                    .map(|token| (token.clone(), erc20::balance_of(&rpc, token, owner)))
+                    .filter(|(_, balance)| *balance != Ok(0))
+                    .collect();
+                (balance, held)
+            }).collect::<Vec<_>>())
+        });
+
+        let rows: Vec<(String, Vec<NetworkBalance>)> = accounts.iter().enumerate().map(|(i, account)| {
+            let name = match &account.label {
+                Some(label) => format!("{} ({})", account.address, label),
+                None => account.address.clone(),
+            };
+            let balances = configured.iter().zip(&fetched).map(|((network, _), fetched)| match fetched {
+                Ok(accounts) => NetworkBalance { network: network.clone(), wei_balance: accounts[i].0.clone(), tokens: accounts[i].1.clone() },
+                Err(e) => NetworkBalance { network: network.clone(), wei_balance: Err(e.clone()), tokens: vec![] },
+            }).collect();
+            (name, balances)
+        }).collect();
+        for ((network, _), fetched) in configured.iter().zip(&fetched) {
+            if let Err(e) = fetched {
+                println!("{}: {}", network.name, e);
+            }
+        }
+        let all: Vec<NetworkBalance> = rows.iter().flat_map(|(_, balances)| balances.iter().cloned()).collect();
+        let prices = portfolio::prices(&all);
+        for line in portfolio::wallet_table(&rows, &prices, fiat) {
+            println!("{}", line);
+        }
+    }
+
+    /// Whether a token at the normalized `address` is listed on the active network
+    fn is_token_listed(&self, address: &str) -> bool {
+        self.token_list().iter().any(|t| labels::normalize_address(&t.address).as_deref() == Some(address))
+    }
+
+    /// Shows and sends the tokens of the account at `index`, and registers and removes tokens on
+    /// the active network, saving after each change
+    fn manage_tokens(&mut self, rpc: &dyn EthRpc, deriving_key: &XPrv, index: usize, approvals_required: bool, save: &mut dyn FnMut(&mut AccountMetadata)) {
+        loop {
+            let network = networks::active();
+            println!("Tokens on {}:", network.name);
+            println!("1) View token balances");
+            println!("2) Send tokens");
+            println!("3) List tokens");
+            println!("4) Register a token");
+            println!("5) Remove a registered token");
+            println!("6) Check an allowance");
+            println!("7) Approve a spender");
+            println!("8) Revoke an approval");
+            println!("9) Back");
+
+            match utils::read_user_input().parse::<u8>() {
+                Ok(1) => {
+                    let tokens = self.token_list();
+                    self.get_account(index).view_token_balances(rpc, &tokens);
+                },
+                Ok(2) if self.get_account(index).watch_only => println!("This is a watch-only account, so it can't send transactions"),
+                Ok(2) if approvals_required => println!("Only approved transactions can be sent while approvals are required"),
+                Ok(2) => {
+                    let (tokens, book, mut gas) = (self.token_list(), self.address_book.clone(), self.gas.clone());
+                    self.get_account(index).send_tokens(rpc, deriving_key, &tokens, &book, &mut gas);
+                    self.gas.strategy = gas.strategy;
+                    save(self);
+                },
+                Ok(3) => {
+                    let listed = network.tokens();
+                    let registered: Vec<_> = self.tokens.for_chain(network.chain_id).collect();
+                    if listed.is_empty() && registered.is_empty() {
+                        println!("\t(none)");
+                    }
+                    for token in &listed {
+                        println!("\t{}: {}, {} decimals (listed for {})", token.symbol, token.address, token.decimals, network.name);
+                    }
+                    for registered in registered {
+                        let token = &registered.token;
+                        println!("\t{}: {}, {} decimals ({})", token.symbol, token.address, token.decimals, registered.name);
+                    }
+                },
+                Ok(4) => {
+                    if self.register_token(rpc, network.chain_id) {
+                        save(self);
+                    }
+                },
+                Ok(5) => {
+                    println!("Enter the contract address of the token to remove: ");
+                    match self.tokens.remove(network.chain_id, &read_user_input()) {
+                        Ok(removed) => {
+                            println!("Removed {} ({})", removed.token.symbol, removed.token.address);
+                            save(self);
+                        },
+                        Err(e) => println!("{}", e),
+                    }
+                },
+                Ok(6) => {
+                    let (tokens, book) = (self.token_list(), self.address_book.clone());
+                    self.get_account(index).view_allowance(rpc, &tokens, &book);
+                },
+                Ok(7..=8) if self.get_account(index).watch_only => println!("This is a watch-only account, so it can't send transactions"),
+                Ok(7..=8) if approvals_required => println!("Only approved transactions can be sent while approvals are required"),
+                Ok(option @ 7..=8) => {
+                    let (tokens, book, mut gas) = (self.token_list(), self.address_book.clone(), self.gas.clone());
+                    self.get_account(index).approve_spender(rpc, deriving_key, &tokens, &book, &mut gas, option == 8);
+                    self.gas.strategy = gas.strategy;
+                    save(self);
+                },
+                Ok(9) => return,
+                _ => println!("Invalid option"),
+            }
+        }
+    }
+
+    /// Registers a token by its contract address on `chain_id`, with the symbol, name and
+    /// decimals the contract reports filled in for the user to keep or change. What it doesn't
+    /// report, or reports wrongly, is entered, and everything is checked as it is. Returns
+    /// whether one was registered.
+    fn register_token(&mut self, rpc: &dyn EthRpc, chain_id: u64) -> bool {
+        println!("Enter the token's contract address: ");
+        let entered = read_user_input();
+        let address = match labels::normalize_address(entered.trim()) {
+            Some(address) => address,
+            None => {
+                println!("{} is not a valid address or its checksum is wrong", entered);
+                return false;
+            },
+        };
+        if self.is_token_listed(&address) {
+            println!("A token at {} is already listed on this network", address);
+            return false;
+        }
+        let details = match erc20::fetch_details(rpc, &address) {
+            Ok(details) => details,
+            Err(e) => {
+                println!("Could not read the token's details from its contract, so they need entering: {}", e);
+                erc20::TokenDetails::default()
+            },
+        };
+        let prompt = |label: &str, current: Option<&str>| {
+            match current {
+                Some(current) => println!("{} (enter keeps {}): ", label, current),
+                None => println!("{}: ", label),
+            }
+            match (read_user_input(), current) {
+                (input, Some(current)) if input.is_empty() => String::from(current),
+                (input, _) => input,
+            }
+        };
+        let symbol = loop {
+            match prompt("Symbol", details.symbol.as_deref()) {
+                symbol if erc20::is_valid_symbol(&symbol) => break symbol,
+                _ => println!("Enter up to {} printable ASCII characters, such as USDC", erc20::MAX_SYMBOL_LEN),
+            }
+        };
+        let name = loop {
+            match prompt("Name", details.name.as_deref()) {
+                name if erc20::is_valid_name(&name) => break name,
+                _ => println!("Enter a name, such as USD Coin"),
+            }
+        };
+        let decimals = loop {
+            match prompt("Decimals", details.decimals.map(|d| d.to_string()).as_deref()).parse::<u8>() {
+                Ok(decimals) if decimals <= erc20::MAX_DECIMALS => break decimals,
+                _ => println!("Enter the number of decimal places, up to {}, such as 18", erc20::MAX_DECIMALS),
+            }
+        };
+        match self.tokens.add(chain_id, &name, Token { symbol, address, decimals }) {
+            Ok(added) => {
+                println!("Registered {} ({}) at {}", added.token.symbol, added.name, added.token.address);
+                true
+            },
+            Err(e) => {
+                println!("{}", e);
+                false
+            },
+        }
+    }
+
+    /// Checks and sends the NFTs of the account at `index`, and tracks collections on the active
+    /// network, saving after each change
+    fn manage_nfts(&mut self, rpc: &dyn EthRpc, deriving_key: &XPrv, index: usize, approvals_required: bool, save: &mut dyn FnMut(&mut AccountMetadata)) {
+        loop {
+            let network = networks::active();
+            println!("NFTs on {}:", network.name);
+            println!("1) Check who owns an NFT");
+            println!("2) Send an NFT");
+            println!("3) List tracked collections");
+            println!("4) Track a collection");
+            println!("5) Stop tracking a collection");
+            println!("6) Back");
+
+            match utils::read_user_input().parse::<u8>() {
+                Ok(1) => {
+                    if let Some(collection) = self.prompt_collection(rpc) {
+                        let id = prompt_token_id();
+                        print_nft(rpc, &collection, &id, &self.get_account(index).address);
+                    }
+                },
+                Ok(2) if self.get_account(index).watch_only => println!("This is a watch-only account, so it can't send transactions"),
+                Ok(2) if approvals_required => println!("Only approved transactions can be sent while approvals are required"),
+                Ok(2) => {
+                    if let Some(collection) = self.prompt_collection(rpc) {
+                        let (book, mut gas) = (self.address_book.clone(), self.gas.clone());
+                        self.get_account(index).send_nft(rpc, deriving_key, &collection, &book, &mut gas);
+                        self.gas.strategy = gas.strategy;
+                        save(self);
+                    }
+                },
+                Ok(3) => {
+                    let tracked: Vec<_> = self.collections.for_chain(network.chain_id).collect();
+                    if tracked.is_empty() {
+                        println!("\t(none)");
+                    }
+                    for collection in tracked {
+                        println!("\t{}: {}", collection.name, collection.address);
+                    }
+                },
+                Ok(4) => {
+                    println!("Enter the collection's contract address: ");
+                    let address = read_user_input();
+                    if labels::normalize_address(&address).is_none() {
+                        println!("{} is not a valid address or its checksum is wrong", address);
+                        continue;
+                    }
+                    let fetched = erc721::collection_name(rpc, &address);
+                    let name = loop {
+                        match &fetched {
+                            Some(fetched) => println!("Name (enter keeps {}): ", fetched),
+                            None => println!("Name: "),
+                        }
+                        let name = match (read_user_input(), &fetched) {
+                            (input, Some(fetched)) if input.is_empty() => fetched.clone(),
+                            (input, _) => input,
+                        };
+                        match erc20::is_valid_name(&name) {
+                            true => break name,
+                            false => println!("Enter a name, such as the collection's"),
+                        }
+                    };
+                    match self.collections.add(network.chain_id, &name, &address) {
+                        Ok(added) => {
+                            println!("Tracking {} at {}", added.name, added.address);
+                            save(self);
+                        },
+                        Err(e) => println!("{}", e),
+                    }
+                },
+                Ok(5) => {
+                    println!("Enter the contract address of the collection to stop tracking: ");
+                    match self.collections.remove(network.chain_id, &read_user_input()) {
+                        Ok(removed) => {
+                            println!("Stopped tracking {} ({})", removed.name, removed.address);
+                            save(self);
+                        },
+                        Err(e) => println!("{}", e),
+                    }
+                },
+                Ok(6) => return,
+                _ => println!("Invalid option"),
+            }
+        }
+    }
+
+    /// Asks for a collection, by its number among those tracked on the active network or by
+    /// address. An untracked one is named as it names itself.
+    fn prompt_collection(&self, rpc: &dyn EthRpc) -> Option<Collection> {
+        let chain_id = networks::active().chain_id;
+        let tracked: Vec<_> = self.collections.for_chain(chain_id).collect();
+        for (i, collection) in tracked.iter().enumerate() {
+            println!("{}) {} ({})", i + 1, collection.name, collection.address);
+        }
+        match tracked.is_empty() {
+            true => println!("Enter the collection's contract address: "),
+            false => println!("Enter the number of a collection, or the address of another: "),
+        }
+        let input = read_user_input();
+        if let Ok(i) = input.parse::<usize>() {
+            return match tracked.get(i.wrapping_sub(1)) {
+                Some(collection) => Some((*collection).clone()),
+                None => {
+                    println!("Invalid collection");
+                    None
+                },
+            };
+        }
+        match labels::normalize_address(&input) {
+            Some(address) => {
+                let bytes: [u8; 20] = hex::decode(&address[2..]).unwrap().try_into().unwrap();
+                let name = erc721::collection_name(rpc, &address).unwrap_or_else(|| String::from("Untracked collection"));
+                Some(Collection { chain_id, name, address: checksum_address(&bytes) })
+            },
+            None => {
+                println!("{} is not a valid address or its checksum is wrong", input);
+                None
+            },
+        }
+    }
+
+    /// Lists the address book and lets the user add, delete and tag entries, saving after each change
+    fn manage_address_book(&mut self, save: &mut dyn FnMut(&mut AccountMetadata)) {
+        loop {
+            println!("Address book:");
+            if self.address_book.is_empty() {
+                println!("\t(empty)");
+            }
+            for contact in self.address_book.contacts() {
+                println!("\t{}", contact.describe());
+            }
+            println!("1) Add an entry");
+            println!("2) Delete an entry");
+            println!("3) Tag an entry as an exchange deposit address, or edit its notes");
+            println!("4) Back");
+
+            match utils::read_user_input().parse::<u8>() {
+                Ok(1) => {
+                    println!("Enter a name for the address: ");
+                    let name = read_user_input();
+                    println!("Enter the address: ");
+                    let address = read_user_input();
+                    match self.address_book.add(&name, &address) {
+                        Ok(contact) => println!("Saved {} as {}", contact.address, contact.name),
+                        Err(e) => {
+                            println!("{}", e);
+                            continue;
+                        },
+                    }
+                    save(self);
+                },
+                Ok(2) => {
+                    println!("Enter the name of the entry to delete: ");
+                    match self.address_book.remove(&read_user_input()) {
+                        Ok(contact) => println!("Deleted {}", contact.name),
+                        Err(e) => {
+                            println!("{}", e);
+                            continue;
+                        },
+                    }
+                    save(self);
+                },
+                Ok(3) => {
+                    println!("Enter the name of the entry to edit: ");
+                    let name = read_user_input();
+                    if self.address_book.find(&name).is_none() {
+                        println!("There is no entry named {}", name.trim());
+                        continue;
+                    }
+                    println!("Is it an exchange deposit address? Sends to it then can't carry data, and warn below its minimum deposit (y/n)");
+                    let tag = (read_user_input() == "y").then_some(ContactTag::ExchangeDeposit);
+                    let min_deposit = match tag {
+                        Some(_) => loop {
+                            println!("Enter the exchange's minimum deposit in ETH (or press enter for none): ");
+                            match read_user_input().as_str() {
+                                "" => break None,
+                                input => match utils::eth_to_wei(input) {
+                                    Ok(wei) => break Some(wei),
+                                    Err(e) => println!("{}", e),
+                                },
+                            }
+                        },
+                        None => None,
+                    };
+                    println!("Enter notes for the entry (or press enter for none): ");
+                    let notes = Some(read_user_input());
+                    match self.address_book.annotate(&name, tag, min_deposit, notes) {
+                        Ok(contact) => println!("Saved {}", contact.describe()),
+                        Err(e) => {
+                            println!("{}", e);
+                            continue;
+                        },
+                    }
+                    save(self);
+                },
+                Ok(4) => return,
+                _ => println!("Invalid option"),
+            }
+        }
+    }
+
+    /// Shows the gas preferences and lets the user change them, saving after each change
+    fn manage_gas_preferences(&mut self, save: &mut dyn FnMut(&mut AccountMetadata)) {
+        loop {
+            println!("Gas preferences:");
+            println!("\tGas limit: the node's estimate plus {}%, and at least {}", self.gas.estimate_margin_percent, self.gas.gas_limit);
+            println!("\tGas price: {}", self.gas.strategy.describe());
+            match self.gas.max_gas_price {
+                Some(max) => println!("\tMaximum gas price: {} gwei", fees::wei_to_gwei(max)),
+                None => println!("\tMaximum gas price: none"),
+            }
+            match self.gas.auto_confirm_limit {
+                Some(limit) => println!("\tSends of up to {} ETH go out without confirming", utils::wei_to_eth(limit)),
+                None => println!("\tEvery send is confirmed"),
+            }
+            println!("1) Change the least gas limit");
+            println!("2) Change how the gas price is chosen");
+            println!("3) Change the maximum gas price");
+            println!("4) Change the auto-confirm limit");
+            println!("5) Change the margin added to gas estimates");
+            println!("6) Back");
+
+            match utils::read_user_input().parse::<u8>() {
+                Ok(1) => {
+                    println!("Enter the least gas limit for sends ({} for a plain transfer). The node's estimate is used when it is higher: ", TRANSFER_GAS_LIMIT);
+                    match fees::parse_gas_limit(&read_user_input()) {
+                        Ok(limit) => self.gas.gas_limit = limit,
+                        Err(e) => {
+                            println!("{}", e);
+                            continue;
+                        },
+                    }
+                },
+                Ok(2) => {
+                    println!("1) Use {}", GasPriceStrategy::Slow.describe());
+                    println!("2) Use {}", GasPriceStrategy::Network.describe());
+                    println!("3) Use {}", GasPriceStrategy::Fast.describe());
+                    println!("4) Use a fixed gas price");
+                    self.gas.strategy = match read_user_input().as_str() {
+                        "1" => GasPriceStrategy::Slow,
+                        "2" => GasPriceStrategy::Network,
+                        "3" => GasPriceStrategy::Fast,
+                        "4" => {
+                            println!("Enter the gas price in gwei: ");
+                            match fees::gwei_to_wei(&read_user_input()) {
+                                Ok(price) => GasPriceStrategy::Fixed(price),
+                                Err(e) => {
+                                    println!("{}", e);
+                                    continue;
+                                },
+                            }
+                        },
+                        _ => {
+                            println!("Invalid option");
+                            continue;
+                        },
+                    };
+                },
+                Ok(3) => {
+                    println!("Enter the most gas price to accept without entering it, in gwei (or press enter for no maximum): ");
+                    self.gas.max_gas_price = match read_user_input().as_str() {
+                        "" => None,
+                        input => match fees::gwei_to_wei(input) {
+                            Ok(max) => Some(max),
+                            Err(e) => {
+                                println!("{}", e);
+                                continue;
+                            },
+                        },
+                    };
+                },
+                Ok(4) => {
+                    println!("Enter the most ETH to send without confirming (or press enter to confirm every send): ");
+                    self.gas.auto_confirm_limit = match read_user_input().as_str() {
+                        "" => None,
+                        input => match utils::eth_to_wei(input) {
+                            Ok(limit) => Some(limit),
+                            Err(e) => {
+                                println!("{}", e);
+                                continue;
+                            },
+                        },
+                    };
+                },
+                Ok(5) => {
+                    println!("Enter the percent to add to the node's gas estimates ({} by default): ", fees::ESTIMATE_MARGIN_PERCENT);
+                    match read_user_input().trim().parse::<u32>() {
+                        Ok(percent) if percent <= 100 => self.gas.estimate_margin_percent = percent,
+                        _ => {
+                            println!("Enter a whole number from 0 to 100");
+                            continue;
+                        },
+                    }
+                },
+                Ok(6) => return,
+                _ => {
+                    println!("Invalid option");
+                    continue;
+                },
+            }
+            save(self);
+        }
+    }
+
+    /// The menu of a watch-only wallet, numbered like the full menu. Balances can be viewed and
+    /// accounts derived from the xpub, but nothing can be signed.
+    fn run_watch_only(&mut self, rpc: &dyn EthRpc, save: &mut dyn FnMut(&mut AccountMetadata)) -> u8 {
+        let mut index = 0;
+        print_current_address(rpc, &self.get_account(index).address);
+
+        loop {
+            println!("1) View account balance");
+            println!("2) Send a transaction");
+            println!("3) Create another account");
+            println!("4) Switch account");
+            println!("5) Import labels or watch-only addresses");
+            println!("18) Networks (using {})", networks::active().name);
+            println!("19) Token balances");
+            println!("21) Balances across networks");
+            println!("22) Change the wallet password");
+            println!("23) Back up the wallet file");
+            println!("24) Lock the wallet");
+            println!("25) QUIT");
+
+            match utils::read_user_input().parse::<u8>() {
+                Ok(1) => {
+                    if self.get_account(index).query_balance(rpc) {
+                        save(self);
+                    }
+                },
+                Ok(2) => println!("This is a watch-only wallet. It only holds the xpub, so it can't sign transactions. \
+                    Sign them on the device that holds the seed."),
+                Ok(3) => {
+                    index = self.accounts.len();
+                    print_current_address(rpc, &self.create_account(index).address);
+                    save(self);
+                },
+                Ok(4) => {
+                    index = self.prompt_switch(rpc, index);
+                    print_current_address(rpc, &self.get_account(index).address);
+                },
+                Ok(5) => {
+                    let address = self.get_account(index).address.clone();
+                    self.import(None);
+                    save(self);
+                    index = self.position_of(&address);
+                },
+                Ok(18) => networks::manage(),
+                Ok(19) => {
+                    let tokens = self.token_list();
+                    self.get_account(index).view_token_balances(rpc, &tokens);
+                },
+                Ok(21) => self.view_balances_across_networks(index, save),
+                Ok(22) => return 22,
+                Ok(23) => return 23,
+                Ok(24) => return 24,
+                Ok(25) => return 25,
+                Ok(6..=17) | Ok(20) => println!("This isn't available in a watch-only wallet"),
+                _ => println!("Invalid option"),
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Decode, Clone)]
+pub(crate) struct Account {
+    /// The number of confirmed transactions sent from this account
+    pub nonce: u64,
+    /// The full HD derivation path of this account
+    #[ssz(with = "text")]
+    pub path: String,
+    /// The address of this account
+    #[ssz(with = "text")]
+    pub address: String,
+    /// A user-facing name for this account
+    #[serde(default)]
+    #[ssz(with = "optional_text")]
+    pub label: Option<String>,
+    /// Transfers waiting to be broadcast at a later time
+    #[serde(default)]
+    #[ssz(with = "json")]
+    pub scheduled: Vec<ScheduledTransaction>,
+    /// Protected accounts are hidden until the secondary passphrase is entered
+    #[serde(default)]
+    pub protected: bool,
+    /// Watch-only accounts track an address this wallet holds no key for
+    #[serde(default)]
+    pub watch_only: bool,
+    /// The private key of a non-HD account, encrypted under the deriving key
+    #[serde(default)]
+    #[ssz(with = "json")]
+    pub(crate) imported_key: Option<SealedBox>,
+    /// The transactions sent from this account, oldest first, up to `history::MAX_RECORDS`.
+    /// Left out while empty, so files from before it still match their MAC.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[ssz(skip_deserializing)]
+    pub history: Vec<TxRecord>,
+    /// What the node last reported for this account, shown before it is fetched again. Dropped
+    /// after a send, which changes both.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[ssz(skip_deserializing)]
+    pub balance_cache: Option<CachedBalance>,
+}
+
+impl Account {
+    /// Creates a new account with nonce as 0. Its private key isn't kept; it is derived again each
+    /// time a transaction is signed.
+    /// deriving_key - the key at the root of the template, used to derive all child accounts
+    /// template - the layout of account paths
+    /// index - the index of the child account
+    ///
+    /// With the standard template the returned key has path: m/44'/60'/0'/0/x, where x = 0,1,2,3...
+    pub fn new(deriving_key: &XPrv, template: &PathTemplate, index: usize) -> Self {
+        let child_xprv = derive_along(deriving_key, &template.relative(index as u32));
+        let address = utils::xpub_to_address(&child_xprv.public_key());
+        let path = template.path(index as u32);
+
+        Account {
+            nonce: 0,
+            path,
+            address,
+            label: None,
+            scheduled: vec![],
+            protected: false,
+            watch_only: false,
+            imported_key: None,
+            history: vec![],
+            balance_cache: None,
+        }
+    }
+
+    /// Creates an account for an imported private key, sealed with `SealedBox::seal`
+    fn imported(address: String, imported_key: SealedBox) -> Self {
+        Account {
+            nonce: 0,
+            path: String::from(IMPORTED_KEY_PATH),
+            address,
+            label: None,
+            scheduled: vec![],
+            protected: false,
+            watch_only: false,
+            imported_key: Some(imported_key),
+            history: vec![],
+            balance_cache: None,
+        }
+    }
+
+    /// Derives the account at `index` from an account-level xpub. Only public derivation is used,
+    /// so the account is watch-only.
+    fn watched(account_key: &XPub, template: &PathTemplate, index: usize) -> Self {
+        let path = template.path(index as u32);
+        let components = derivation::components_below(&path, account_key.attrs().depth).unwrap();
+        let child_xpub = components.iter().fold(account_key.clone(), |key, child_number| key.derive_child(*child_number).unwrap());
+        Account { path, ..Account::watch(utils::xpub_to_address(&child_xpub), None) }
+    }
+
+    /// Creates a watch-only account for an address with no key in this wallet
+    pub fn watch(address: String, label: Option<String>) -> Self {
+        Account {
+            nonce: 0,
+            path: String::new(),
+            address,
+            label,
+            scheduled: vec![],
+            protected: false,
+            watch_only: true,
+            imported_key: None,
+            history: vec![],
+            balance_cache: None,
+        }
+    }
+
+    /// Runs the account menu.
+    /// With `approvals_required`, sends are handed back to be staged and other ways of sending
+    /// are disabled.
+    /// Returns `CHANGED` after a send, a change to the scheduled transactions or a refreshed
+    /// balance or history, for the wallet to be saved before the menu is shown again.
+    /// Recipients can be entered as names from `book`, and a gas speed chosen at send time is
+    /// remembered in `gas`, which also returns `CHANGED`.
+    pub fn run(&mut self, rpc: &dyn EthRpc, deriving_key: &XPrv, approvals_required: bool, book: &AddressBook, gas: &mut GasPreferences) -> u8 {
+        let network = networks::active();
+        println!("NETWORK: {} (chain {}){}", network.name, network.chain_id,
+            if network.is_mainnet() { " - transactions move real funds" } else { "" });
+        print_current_address(rpc, &self.address);
+        self.print_cached_balance();
+
+        loop {
+            let sent = self.sent_state();
+            if self.auto_refresh(rpc, refresh::current().interval) {
+                return CHANGED;
+            }
+
+            let user_input = loop {
+                println!("1) View account balance");
+                println!("2) Send a transaction");
+                println!("3) Create another account");
+                println!("4) Switch account");
+                println!("5) Import labels or watch-only addresses");
+                println!("6) Scheduled transactions");
+                println!("7) Protected accounts");
+                println!("8) Send a relayed transaction (gas paid by a relayer)");
+                println!("9) Verify my recovery phrase backup");
+                println!("10) Air-gapped signer QR (export/import)");
+                println!("11) Transaction approvals (two-person rule)");
+                println!("12) Export private key");
+                println!("13) Show recovery phrase");
+                println!("14) Find a vanity address");
+                println!("15) Transaction history");
+                println!("16) Address book");
+                println!("17) Gas preferences");
+                println!("18) Networks (using {})", networks::active().name);
+                println!("19) Tokens");
+                println!("20) NFTs");
+                println!("21) Balances across networks");
+                println!("22) Change the wallet password");
+                println!("23) Back up the wallet file");
+                println!("24) Lock the wallet");
+                println!("25) QUIT");
+
+                match utils::read_user_input().parse::<u8>() {
+                    Ok(option) => break option,
+                    Err(_e) => {
+                        println!("Invalid option");
+                    }
+                }
+            };
+
+            match user_input {
+                1 => {
+                    if self.query_balance(rpc) {
+                        return CHANGED;
+                    }
+                },
+                2 | 6 | 8 if self.watch_only => println!("This is a watch-only account, so it can't send transactions"),
+                12 if self.watch_only => println!("This is a watch-only account, so it has no private key"),
+                2 if approvals_required => return 2,
+                2 => {
+                    let strategy = gas.strategy;
+                    self.send_transaction(rpc, deriving_key, book, gas);
+                    if gas.strategy != strategy {
+                        return CHANGED;
+                    }
+                },
+                3 => return 3,
+                4 => return 4,
+                5 => return 5,
+                6 | 8 if approvals_required => println!("Only approved transactions can be sent while approvals are required"),
+                6 => {
+                    self.manage_scheduled(rpc, deriving_key, book);
+                    return CHANGED;
+                },
+                7 => return 7,
+                8 => self.send_relayed_transaction(rpc, deriving_key, book),
+                9 => return 9,
+                10 => return 10,
+                11 => return 11,
+                // revealing a key would let one person send without approval
+                12 if approvals_required => println!("Private keys can't be exported while approvals are required"),
+                12 => return 12,
+                13 if approvals_required => println!("The recovery phrase can't be shown while approvals are required"),
+                13 => return 13,
+                14 => return 14,
+                15 => {
+                    if self.view_history(rpc) {
+                        return CHANGED;
+                    }
+                },
+                16 => return 16,
+                17 => return 17,
+                18 => networks::manage(),
+                19 => return 19,
+                20 => return 20,
+                21 => return 21,
+                22 => return 22,
+                23 => return 23,
+                24 => return 24,
+                25 => return 25,
+                _ => println!("Invalid option"),
+            }
+            if self.sent_state() != sent {
+                return CHANGED;
+            }
+        }
+    }
+
+    /// What sending a transaction, or one being mined, changes
+    fn sent_state(&self) -> (u64, Option<TxRecord>) {
+        (self.nonce, self.history.last().cloned())
+    }
+
+    /// The account's address as bytes, which only fails if the wallet file was damaged
+    pub fn address_bytes(&self) -> Result<[u8; 20], String> {
+        utils::sanitize_address(&self.address).map_err(|e| format!("The account address {} is damaged: {}", self.address, e))
+    }
+
+    /// Derives the private key, or decrypts it for an imported account
+    fn secret_key(&self, deriving_key: &XPrv) -> Zeroizing<PrivateKeyBytes> {
+        Zeroizing::new(match &self.imported_key {
+            Some(sealed) => {
+                let secret = Zeroizing::new(sealed.open(IMPORTED_KEY_CONTEXT, &*key_bytes(deriving_key))
+                    .expect("Imported key could not be decrypted"));
+                secret.as_slice().try_into().unwrap()
+            },
+            None => {
+                // the deriving key's depth tells where it sits on the account's path
+                let components = derivation::components_below(&self.path, deriving_key.attrs().depth).unwrap();
+                derive_along(deriving_key, &components).to_bytes()
+            },
+        })
+    }
+
+    /// Encrypts the private key as a keystore V3 file, for geth and MetaMask
+    pub fn export_keystore(&self, deriving_key: &XPrv, password: &str) -> Keystore {
+        keystore::encrypt(&self.secret_key(deriving_key), password, &self.address)
+    }
+
+    /// Returns the private key as 0x-prefixed hex, for use in other tools
+    pub fn export_private_key(&self, deriving_key: &XPrv) -> Zeroizing<String> {
+        Zeroizing::new(String::from("0x") + &hex::encode(*self.secret_key(deriving_key)))
+    }
+
+    /// Prints the last known balance on the active network, if there is one
+    fn print_cached_balance(&self) {
+        let network = networks::active();
+        if let Some(cached) = balance_cache::for_network(&self.balance_cache, network.chain_id) {
+            println!("Last known balance: {}", cached.describe(&network.currency, utils::now()));
+        }
+    }
+
+    /// Shows the last known balance while the current one is fetched, along with what it will be
+    /// once pending transactions are mined, and catches the nonce up with the node's. If the node
+    /// can't be reached, the last known balance is marked out of date. Returns true if the cache
+    /// changed.
+    fn query_balance(&mut self, rpc: &dyn EthRpc) -> bool {
+        let network = networks::active();
+        self.print_cached_balance();
+        match offer_retry(|| fetch_account_activity(rpc, &self.address)) {
+            Ok((balance, nonce)) => {
+                // a node that can't tell the pending balance still has the latest one to show
+                let pending = rpc.get_pending_balance(&self.address).ok();
+                println!("Balance: {}", balance_cache::describe_balance(balance, pending, &network.currency));
+                match self.history.iter().filter(|r| r.status == TransactionStatus::Pending).count() {
+                    0 => {},
+                    1 => println!("1 transaction sent from this account is still pending"),
+                    n => println!("{} transactions sent from this account are still pending", n),
+                }
+                if let Ok(price) = rpc.gas_price() {
+                    // what is left to spend is what pending transactions leave
+                    let transfers = utils::affordable_transfers(pending.unwrap_or(balance), price * TRANSFER_GAS_LIMIT);
+                    println!("Enough {} for ~{} transfers at the current gas price", network.currency, transfers);
+                }
+                // transactions sent from elsewhere use up nonces too. Pending ones from here aren't
+                // counted yet, so the nonce is never lowered.
+                self.nonce = self.nonce.max(nonce);
+                self.balance_cache = Some(CachedBalance::new(balance, nonce, network.chain_id));
+                true
+            },
+            Err(e) => {
+                println!("{}", e);
+                match self.balance_cache.as_mut().filter(|c| c.chain_id == network.chain_id) {
+                    Some(cached) => {
+                        println!("The last known balance is out of date until the node can be reached again");
+                        !std::mem::replace(&mut cached.stale, true)
+                    },
+                    None => false,
+                }
+            },
+        }
+    }
+
+    /// Fetches the balance, nonce and pending transactions' statuses again once `interval` has
+    /// passed since the balance was fetched, when the menu is next shown. A failed
+    /// refresh waits the interval too, rather than being tried before every menu. Returns true if
+    /// anything changed.
+    fn auto_refresh(&mut self, rpc: &dyn EthRpc, interval: RefreshInterval) -> bool {
+        let network = networks::active();
+        let fetched_at = balance_cache::for_network(&self.balance_cache, network.chain_id).map(|c| c.fetched_at);
+        let key = format!("balance:{}:{}", network.chain_id, self.address.to_lowercase());
+        if !refresh::take_due(&key, fetched_at, utils::now(), interval) {
+            return false;
+        }
+        let mut changed = false;
+        if self.history.iter().any(|r| r.status == TransactionStatus::Pending) {
+            changed |= history::refresh(&mut self.history, |hash| rpc.transaction_status(hash)) > 0;
+        }
+        match fetch_account_activity(rpc, &self.address) {
+            Ok((balance, nonce)) => {
+                self.nonce = self.nonce.max(nonce);
+                // shown with the menu, which is printed again for the wallet to be saved
+                self.balance_cache = Some(CachedBalance::new(balance, nonce, network.chain_id));
+                true
+            },
+            Err(e) => {
+                println!("The balance couldn't be refreshed: {}", e);
+                match self.balance_cache.as_mut().filter(|c| c.chain_id == network.chain_id) {
+                    Some(cached) => !std::mem::replace(&mut cached.stale, true) || changed,
+                    None => changed,
+                }
+            },
+        }
+    }
+
+    /// Shows the account's balance on each of the `configured` networks and of the tokens listed
+    /// with it, asked of each network's node at once, with their values where they have a price.
+    /// A balance from the active network's node is cached as `query_balance` caches it. Returns
+    /// true if the cache changed.
+    fn view_balances_across_networks(&mut self, configured: &[(NetworkConfig, Vec<Token>)]) -> bool {
+        if configured.is_empty() {
+            println!("No networks are configured");
+            return false;
+        }
+        println!("Asking {} networks...", configured.len());
+        let address = self.address.clone();
+        let owner = match self.address_bytes() {
+            Ok(owner) => owner,
+            Err(e) => {
+                println!("{}", e);
+                return false;
+            },
+        };
+        let activity = portfolio::query_all(configured, |(network, tokens)| {
+            let rpc = portfolio::connect(network)?;
+            let activity = fetch_account_activity(&rpc, &address)?;
+            // tokens the account holds none of would only crowd the table
+            let held: Vec<_> = tokens.iter()
+                .map(|token| (token.clone(), erc20::balance_of(&rpc, token, &owner)))
+                .filter(|(_, balance)| *balance != Ok(0))
+                .collect();
+            let fee = rpc::typical_transfer_fee(&rpc).ok();
+            Ok((activity, held, fee))
+        });
+        let active = networks::active();
+        let mut changed = false;
+        if let Some(Ok(((balance, nonce), ..))) = configured.iter().zip(&activity).find(|((n, _), _)| *n == active).map(|(_, a)| a) {
+            self.nonce = self.nonce.max(*nonce);
+            self.balance_cache = Some(CachedBalance::new(*balance, *nonce, active.chain_id));
+            changed = true;
+        }
+
+        // what gas each network's balance pays for, at the fee a transfer is expected to cost there
+        let gas: Vec<String> = configured.iter().zip(&activity)
+            .filter_map(|((network, _), activity)| match activity {
+                Ok(((balance, _), _, Some(fee))) => Some(describe_gas(&network.name, &network.currency, *balance, *fee)),
+                _ => None,
+            })
+            .collect();
+        let rows: Vec<NetworkBalance> = configured.iter().zip(activity)
+            .map(|((network, _), activity)| match activity {
+                Ok(((balance, _), tokens, _)) => NetworkBalance { network: network.clone(), wei_balance: Ok(balance), tokens },
+                Err(e) => NetworkBalance { network: network.clone(), wei_balance: Err(e), tokens: vec![] },
+            })
+            .collect();
+        let prices = portfolio::prices(&rows);
+        let settings = Settings::load().prices;
+        let fiat = settings.api_url.is_some().then_some(settings.currency.as_str());
+        let now = utils::now();
+        let last_known = |network: &NetworkConfig| balance_cache::for_network(&self.balance_cache, network.chain_id)
+            .map(|cached| cached.describe(&network.currency, now));
+        for line in portfolio::table(&rows, &prices, fiat, last_known).iter().chain(&gas) {
+            println!("{}", line);
+        }
+        if fiat.is_none() {
+            println!("Set prices.api_url in {} to see their value", Settings::path().display());
+        }
+        changed
+    }
+
+    /// Prints the account's balance of each token listed for the network, in whole tokens, with
+    /// its value and their total where a price API is configured. Those it holds none of are
+    /// left out unless the user asks to see them all.
+    fn view_token_balances(&self, rpc: &dyn EthRpc, tokens: &[Token]) {
+        let network = networks::active();
+        if tokens.is_empty() {
+            return println!("No tokens are listed for {}. Register one to see its balance.", network.name);
+        }
+        let owner = match self.address_bytes() {
+            Ok(owner) => owner,
+            Err(e) => return println!("{}", e),
+        };
+        let balances: Vec<_> = tokens.iter().map(|token| erc20::balance_of(rpc, token, &owner)).collect();
+        let settings = Settings::load().prices;
+        let fiat = settings.api_url.is_some().then_some(settings.currency.as_str());
+        let held: Vec<&str> = tokens.iter().zip(&balances)
+            .filter(|(_, balance)| matches!(balance, Ok(amount) if *amount > 0))
+            .map(|(token, _)| token.address.as_str())
+            .collect();
+        let prices = match network.price_platform() {
+            Some(platform) if fiat.is_some() && !held.is_empty() => prices::token_prices(&platform, &held),
+            _ => HashMap::new(),
+        };
+        let value = |token: &Token, amount: u128| portfolio::token_value(&prices, &network, token, amount);
+        let print = |show_all: bool| {
+            for (token, balance) in tokens.iter().zip(&balances) {
+                let amount = match balance {
+                    Ok(0) if !show_all => continue,
+                    Ok(amount) => format!("{} {}", erc20::format_units(*amount, token.decimals), token.symbol),
+                    Err(e) => {
+                        println!("{}: couldn't be fetched: {}", token.symbol, e);
+                        continue;
+                    },
+                };
+                match (fiat, balance) {
+                    (Some(fiat), Ok(units)) => println!("{} ({})", amount, value(token, *units)
+                        .map_or_else(|| String::from(portfolio::NO_VALUE), |cents| prices::format_cents(cents, fiat))),
+                    _ => println!("{}", amount),
+                }
+            }
+        };
+        print(false);
+        if let Some(fiat) = fiat.filter(|_| !held.is_empty()) {
+            let values: Vec<_> = tokens.iter().zip(&balances)
+                .filter_map(|(token, balance)| balance.as_ref().ok().filter(|amount| **amount > 0).map(|amount| value(token, *amount)))
+                .collect();
+            println!("Total: {}", prices::format_cents(values.iter().flatten().sum(), fiat));
+            if values.contains(&None) {
+                println!("Tokens marked {} have no price, so they aren't in the total", portfolio::NO_VALUE);
+            }
+        }
+        let empty = balances.iter().filter(|balance| **balance == Ok(0)).count();
+        if empty == 0 {
+            return;
+        }
+        match empty == tokens.len() {
+            true => println!("This account holds none of the {} tokens listed for {}", empty, network.name),
+            false => println!("{} more tokens listed for {} have no balance", empty, network.name),
+        }
+        println!("Enter a to show them all, or press enter to go back");
+        if read_user_input() == "a" {
+            print(true);
+        }
+    }
+
+    /// Sends a transfer at the speed tier chosen for it, which becomes the default in `gas`, and
+    /// with the gas limit the node estimates. Either can be changed for this send before
+    /// confirming it.
+    fn send_transaction(&mut self, rpc: &dyn EthRpc, deriving_key: &XPrv, book: &AddressBook, gas: &mut GasPreferences) {
+        let (recipient, recipient_bytes) = match utils::get_valid_address_bytes(book, Some(&|name: &str| resolve_recipient(rpc, name))) {
+            Ok(r) => (r.0, r.1),
+            Err(_e) => return,
+        };
+
+        let entry = prompt_amount_entry();
+
+        // a relative amount isn't known until the fee is, so the estimate is made without it
+        let estimated_amount = match entry {
+            AmountEntry::Exact(wei) => wei,
+            _ => 0,
+        };
+        let estimate = rpc.estimate_gas(&self.address, &recipient, estimated_amount, &[]);
+        let mut gas_limit = match &estimate {
+            Ok(estimate) => gas.gas_limit_for(*estimate),
+            Err(e) => {
+                println!("Could not estimate the gas, so the gas limit of {} is used: {}", gas.gas_limit, e);
+                gas.gas_limit
+            },
+        };
+
+        // where there is a base fee the price is the most paid per gas, which leaves room for
+        // the base fee to rise before the transaction is mined
+        let (price, market, mut price_entered) = match prompt_speed_tier(rpc, gas, fetch_fee_market(rpc), gas_limit) {
+            Some(tier) => tier,
+            None => return println!("Transaction canceled"),
+        };
+        let (mut price, market) = prompt_price_floor(price, market);
+
+        loop {
+            let fee = price * gas_limit;
+            let wei_amount = match self.resolve_amount(rpc, entry, fee) {
+                Ok(a) => a,
+                Err(e) => {
+                    println!("{}", e);
+                    return println!("Transaction canceled");
+                },
+            };
+
+            // make sure the fee is covered before signing anything
+            if !self.covers_fee(rpc, wei_amount, fee, None) {
+                return println!("Transaction canceled");
+            }
+
+            let too_expensive = !price_entered && gas.exceeds_max(price);
+            // a deposit below an exchange's minimum is always confirmed
+            let deposit_warning = book.check_deposit(&recipient_bytes, &[], wei_amount).warning();
+            if !too_expensive && deposit_warning.is_none() && gas.auto_confirms(wei_amount) {
+                println!("Sending {} ETH to {} without confirming, as it is within the auto-confirm limit", utils::wei_to_eth(wei_amount), checksum_address(&recipient_bytes));
+            } else {
+                let network = networks::active();
+                println!("Transaction details:\n\tNETWORK: {} (chain {})\n\tTO: {}\n\tAMOUNT: {} ETH\n\t{}\n\tGAS LIMIT: {}{}\n\tMOST IT CAN COST IN FEES: {} ETH\n\t",
+                    network.name, network.chain_id, checksum_address(&recipient_bytes), utils::wei_to_eth(wei_amount), describe_pricing(price, market), gas_limit, describe_estimate(&estimate), utils::wei_to_eth(fee));
+                if let Some(warning) = &deposit_warning {
+                    println!("{}", warning);
+                }
+                match too_expensive {
+                    true => println!("The gas price is above the maximum of {} gwei. Enter a gas price for this send to go ahead.", fees::wei_to_gwei(gas.max_gas_price.unwrap())),
+                    false => println!("Press 1 to CONFIRM"),
+                }
+                println!("Press 2 to change the gas price or gas limit for this send");
+                println!("Press any other number to CANCEL");
+                let user_option = loop {
+                    match read_user_input().parse::<u8>() {
+                        Ok(v) => break v,
+                        Err(_e) => println!("Please enter a number"),
+                    }
+                };
+
+                match user_option {
+                    1 if !too_expensive => {
+                        // relative amounts are re-resolved in case the balance moved while confirming
+                        if entry.is_relative() {
+                            match self.resolve_amount(rpc, entry, fee) {
+                                Ok(a) if a == wei_amount => {},
+                                Ok(a) => {
+                                    println!("The balance changed; the amount would now be {} ETH", utils::wei_to_eth(a));
+                                    return println!("Transaction canceled");
+                                },
+                                Err(e) => {
+                                    println!("{}", e);
+                                    return println!("Transaction canceled");
+                                },
+                            }
+                        }
+                    },
+                    2 => {
+                        (price, gas_limit) = prompt_gas_override(price, gas_limit);
+                        price_entered = true;
+                        continue;
+                    },
+                    _ => return println!("Transaction canceled"),
+                }
+            }
+
+            // create and sign transaction
+            if let Err(e) = self.sync_nonce(rpc) {
+                return println!("{}", e);
+            }
+            let final_txn = self.sign_transfer(deriving_key, recipient_bytes, wei_amount, GasPricing::new(price, market), gas_limit);
+            self.broadcast(rpc, &final_txn, recipient_bytes, wei_amount, price, gas_limit);
+            return;
+        }
+    }
+
+    /// Sends one of `tokens`, those of the network: a call to its contract's `transfer`
+    fn send_tokens(&mut self, rpc: &dyn EthRpc, deriving_key: &XPrv, tokens: &[Token], book: &AddressBook, gas: &mut GasPreferences) {
+        let (token, contract_bytes) = match pick_token(tokens, "send") {
+            Some(picked) => picked,
+            None => return,
+        };
+
+        let recipient_bytes = match utils::get_valid_address_bytes(book, Some(&|name: &str| resolve_recipient(rpc, name))) {
+            Ok(r) => r.1,
+            Err(_e) => return,
+        };
+        let amount = loop {
+            println!("Enter {} amount to send: ", token.symbol);
+            match erc20::parse_units(&read_user_input(), token.decimals) {
+                Ok(0) => println!("Please enter an amount above 0"),
+                Ok(amount) => break amount,
+                Err(e) => println!("{}", e),
+            }
+        };
+        let owner = match self.address_bytes() {
+            Ok(owner) => owner,
+            Err(e) => return println!("{}", e),
+        };
+        let held = erc20::balance_of(rpc, token, &owner);
+        match &held {
+            Ok(held) if *held < amount => {
+                println!("This account only holds {} {}", erc20::format_units(*held, token.decimals), token.symbol);
+                return println!("Transaction canceled");
+            },
+            Ok(_) => {},
+            Err(e) => println!("Could not check the {} balance before sending: {}", token.symbol, e),
+        }
+
+        self.send_contract_call(rpc, deriving_key, book, gas, ContractCall {
+            title: "Token transfer details",
+            contract: contract_bytes,
+            data: erc20::transfer_data(&recipient_bytes, amount),
+            fallback_gas_limit: erc20::TRANSFER_GAS_LIMIT,
+            details: vec![
+                format!("TO: {}", checksum_address(&recipient_bytes)),
+                format!("AMOUNT: {} {}", erc20::format_units(amount, token.decimals), token.symbol),
+                format!("TOKEN CONTRACT: {}", token.address),
+            ],
+            warning: None,
+            token: Some(TokenSend { symbol: token.symbol.clone(), held }),
+        });
+    }
+
+    /// Shows how much of one of `tokens` a spender may move from this account
+    fn view_allowance(&self, rpc: &dyn EthRpc, tokens: &[Token], book: &AddressBook) {
+        let (token, _) = match pick_token(tokens, "check") {
+            Some(picked) => picked,
+            None => return,
+        };
+        let (spender, spender_bytes) = match prompt_spender(book) {
+            Some(spender) => spender,
+            None => return,
+        };
+        let owner = match self.address_bytes() {
+            Ok(owner) => owner,
+            Err(e) => return println!("{}", e),
+        };
+        match erc20::allowance(rpc, token, &owner, &spender_bytes) {
+            Ok(allowance) => println!("{} may spend {} of this account's", spender, allowance.format(token)),
+            Err(e) => println!("Could not check the allowance: {}", e),
+        }
+    }
+
+    /// Sends an `approve` call for one of `tokens`, letting a spender move up to an amount of
+    /// it from this account, or, with `revoke`, nothing at all. An unlimited approval has to
+    /// be typed out a second time.
+    fn approve_spender(&mut self, rpc: &dyn EthRpc, deriving_key: &XPrv, tokens: &[Token], book: &AddressBook, gas: &mut GasPreferences, revoke: bool) {
+        let (token, contract_bytes) = match pick_token(tokens, if revoke { "revoke an approval of" } else { "approve" }) {
+            Some(picked) => picked,
+            None => return,
+        };
+        let (spender, spender_bytes) = match prompt_spender(book) {
+            Some(spender) => spender,
+            None => return,
+        };
+        let owner = match self.address_bytes() {
+            Ok(owner) => owner,
+            Err(e) => return println!("{}", e),
+        };
+        let current = erc20::allowance(rpc, token, &owner, &spender_bytes);
+        match &current {
+            Ok(Allowance::Limited(0)) if revoke => return println!("{} can't spend any {} of this account's, so there is nothing to revoke", spender, token.symbol),
+            Ok(allowance) => println!("{} may spend {} of this account's now", spender, allowance.format(token)),
+            Err(e) => println!("Could not check the current allowance: {}", e),
+        }
+
+        let allowance = match revoke {
+            true => Allowance::Limited(0),
+            false => loop {
+                println!("Enter the {} amount to approve, or \"unlimited\": ", token.symbol);
+                let input = read_user_input();
+                if input.eq_ignore_ascii_case("unlimited") {
+                    println!("An unlimited approval lets {} spend all of this account's {}, including any it receives later, until it is revoked.", spender, token.symbol);
+                    println!("Type UNLIMITED to approve it: ");
+                    match read_user_input() == "UNLIMITED" {
+                        true => break Allowance::Unlimited,
+                        false => return println!("Transaction canceled"),
+                    }
+                }
+                match erc20::parse_units(&input, token.decimals) {
+                    Ok(amount) => break Allowance::Limited(amount),
+                    Err(e) => println!("{}", e),
+                }
+            },
+        };
+        let warning = match allowance {
+            Allowance::Limited(amount) if amount > 0 => match erc20::balance_of(rpc, token, &owner) {
+                Ok(held) if held < amount => Some(format!("This is more than the {} {} this account holds. {} can spend what it receives later, up to the allowance.",
+                    erc20::format_units(held, token.decimals), token.symbol, spender)),
+                Ok(_) => None,
+                Err(e) => Some(format!("Could not check the {} balance: {}", token.symbol, e)),
+            },
+            Allowance::Unlimited => Some(format!("{} will be able to spend all of this account's {}.", spender, token.symbol)),
+            Allowance::Limited(_) => None,
+        };
+
+        self.send_contract_call(rpc, deriving_key, book, gas, ContractCall {
+            title: if revoke { "Revocation details" } else { "Approval details" },
+            contract: contract_bytes,
+            data: erc20::approve_data(&spender_bytes, allowance),
+            fallback_gas_limit: erc20::APPROVE_GAS_LIMIT,
+            details: vec![
+                format!("SPENDER: {}", spender),
+                format!("ALLOWANCE: {}", allowance.format(token)),
+                format!("TOKEN CONTRACT: {}", token.address),
+            ],
+            warning,
+            token: None,
+        });
+    }
+
+    /// Sends an NFT of `collection` with `safeTransferFrom`, once the collection says this
+    /// account owns it
+    fn send_nft(&mut self, rpc: &dyn EthRpc, deriving_key: &XPrv, collection: &Collection, book: &AddressBook, gas: &mut GasPreferences) {
+        let id = prompt_token_id();
+        let owner = match self.address_bytes() {
+            Ok(owner) => owner,
+            Err(e) => return println!("{}", e),
+        };
+        match erc721::owner_of(rpc, &collection.address, &id) {
+            Ok(holder) if holder == owner => {},
+            Ok(holder) => {
+                println!("Token {} is owned by {}, not this account", erc721::format_token_id(&id), checksum_address(&holder));
+                return println!("Transaction canceled");
+            },
+            Err(e) => {
+                println!("Could not check who owns it: {}", e);
+                return println!("Transaction canceled");
+            },
+        }
+        let contract_bytes = match utils::sanitize_address(&collection.address) {
+            Ok(bytes) => bytes,
+            Err(e) => return println!("The collection address {} is damaged: {}", collection.address, e),
+        };
+
+        let (recipient, recipient_bytes) = match utils::get_valid_address_bytes(book, Some(&|name: &str| resolve_recipient(rpc, name))) {
+            Ok(r) => (r.0, r.1),
+            Err(_e) => return,
+        };
+        self.send_contract_call(rpc, deriving_key, book, gas, ContractCall {
+            title: "NFT transfer details",
+            contract: contract_bytes,
+            data: erc721::safe_transfer_data(&owner, &recipient_bytes, &id),
+            fallback_gas_limit: erc721::TRANSFER_GAS_LIMIT,
+            details: vec![
+                format!("TO: {}", checksum_address(&recipient_bytes)),
+                format!("COLLECTION: {} ({})", collection.name, collection.address),
+                format!("TOKEN ID: {}", erc721::format_token_id(&id)),
+            ],
+            warning: Some(format!("Once sent, {} owns this NFT for good. Only they can send it back.", recipient)),
+            token: None,
+        });
+    }
+
+    /// Sends `call` with no ETH, at the node's gas estimate and the speed tier chosen for it,
+    /// which becomes the default in `gas`. It is signed with the same nonce and fees as a
+    /// transfer, and always asks for confirmation, since the auto-confirm limit is in ETH. A
+    /// call to an exchange deposit address in `book` is refused.
+    fn send_contract_call(&mut self, rpc: &dyn EthRpc, deriving_key: &XPrv, book: &AddressBook, gas: &mut GasPreferences, call: ContractCall) {
+        if let DepositCheck::Refused(reason) = book.check_deposit(&call.contract, &call.data, 0) {
+            println!("{}", reason);
+            return println!("Transaction canceled");
+        }
+        let network = networks::active();
+        let contract = String::from("0x") + &hex::encode(call.contract);
+        let estimate = rpc.estimate_gas(&self.address, &contract, 0, &call.data);
+        let mut gas_limit = match &estimate {
+            Ok(estimate) => gas.gas_limit_for(*estimate),
+            Err(e) => {
+                println!("Could not estimate the gas, so the gas limit of {} is used: {}", call.fallback_gas_limit, e);
+                call.fallback_gas_limit
+            },
+        };
+        let (price, market, mut price_entered) = match prompt_speed_tier(rpc, gas, fetch_fee_market(rpc), gas_limit) {
+            Some(tier) => tier,
+            None => return println!("Transaction canceled"),
+        };
+        let (mut price, market) = prompt_price_floor(price, market);
+
+        loop {
+            let fee = price * gas_limit;
+            if !self.covers_fee(rpc, 0, fee, call.token.as_ref()) {
+                return println!("Transaction canceled");
+            }
+            let too_expensive = !price_entered && gas.exceeds_max(price);
+            println!("{}:\n\tNETWORK: {} (chain {})\n\t{}\n\t{}\n\tGAS LIMIT: {}{}\n\tMOST IT CAN COST IN FEES: {} ETH\n\t",
+                call.title, network.name, network.chain_id, call.details.join("\n\t"),
+                describe_pricing(price, market), gas_limit, describe_estimate(&estimate), utils::wei_to_eth(fee));
+            if let Some(warning) = &call.warning {
+                println!("{}", warning);
+            }
+            match too_expensive {
+                true => println!("The gas price is above the maximum of {} gwei. Enter a gas price for this send to go ahead.", fees::wei_to_gwei(gas.max_gas_price.unwrap())),
+                false => println!("Press 1 to CONFIRM"),
+            }
+            println!("Press 2 to change the gas price or gas limit for this send");
+            println!("Press any other number to CANCEL");
+            let user_option = loop {
+                match read_user_input().parse::<u8>() {
+                    Ok(v) => break v,
+                    Err(_e) => println!("Please enter a number"),
+                }
+            };
+            match user_option {
+                1 if !too_expensive => {},
+                2 => {
+                    (price, gas_limit) = prompt_gas_override(price, gas_limit);
+                    price_entered = true;
+                    continue;
+                },
+                _ => return println!("Transaction canceled"),
+            }
+
+            if let Err(e) = self.sync_nonce(rpc) {
+                return println!("{}", e);
+            }
+            let final_txn = self.sign_call(deriving_key, call.contract, 0, call.data, GasPricing::new(price, market), gas_limit);
+            self.broadcast(rpc, &final_txn, call.contract, 0, price, gas_limit);
+            return;
+        }
+    }
+
+    /// Has the relayer from settings make a transfer from this account and pay its gas. The
+    /// account signs an EIP-7702 authorization for the delegate contract and the transfer for the
+    /// delegate to make, which the relayer wraps in a transaction of its own. Success is only
+    /// reported once that transaction is on chain and carries exactly what was signed.
+    fn send_relayed_transaction(&mut self, rpc: &dyn EthRpc, deriving_key: &XPrv, book: &AddressBook) {
+        let settings = Settings::load().relayer;
+        let (url, api_key, delegate) = match (settings.url, settings.api_key, settings.delegate) {
+            (Some(url), Some(key), Some(delegate)) => (url, key, delegate),
+            _ => return println!("No relayer configured. Set relayer.url, relayer.api_key and relayer.delegate in {}.", Settings::path().display()),
+        };
+        let delegate_bytes: [u8; 20] = match delegate.strip_prefix("0x").and_then(|d| hex::decode(d).ok()).and_then(|d| d.try_into().ok()) {
+            Some(bytes) => bytes,
+            None => return println!("relayer.delegate {} is not an address", delegate),
+        };
+
+        let recipient_bytes = match utils::get_valid_address_bytes(book, None) {
+            Ok(r) => r.1,
+            Err(_e) => return,
+        };
+        let wei_amount = prompt_wei_amount();
+
+        if let Some(warning) = book.check_deposit(&recipient_bytes, &[], wei_amount).warning() {
+            println!("{}", warning);
+        }
+        println!("Relayed transaction details:\n\tTO: {}\n\tAMOUNT: {} ETH\n\tRELAYER: {}\n\tDELEGATE: {}\n\t", checksum_address(&recipient_bytes), utils::wei_to_eth(wei_amount), url, checksum_address(&delegate_bytes));
+        println!("Press 1 to CONFIRM");
+        println!("Press any other key to CANCEL");
+        if read_user_input() != "1" {
+            return println!("Transaction canceled");
+        }
+
+        if let Err(e) = self.sync_nonce(rpc) {
+            return println!("{}", e);
+        }
+        let call_nonce = match relayer::delegate_nonce(rpc, &self.address) {
+            Ok(nonce) => nonce,
+            Err(e) => return println!("{}", e),
+        };
+        let account = match self.address_bytes() {
+            Ok(account) => account,
+            Err(e) => return println!("{}", e),
+        };
+        let call = SponsoredCall {
+            chain_id: chain_id(),
+            account,
+            to: recipient_bytes,
+            value: wei_amount,
+            data: vec![],
+            nonce: call_nonce,
+        };
+        // the authorization is only valid at the account's nonce, which it then consumes
+        let authorization = Authorization { chain_id: chain_id(), address: delegate_bytes, nonce: self.nonce };
+        let secret = SecretBuffer::new(&*self.secret_key(deriving_key));
+        let signed = authorization.sign(&secret).and_then(|a| call.sign(&secret).map(|signature| (a, signature)));
+        drop(secret);
+        let (authorization, signature) = match signed {
+            Ok(signed) => signed,
+            Err(e) => return println!("{}", e),
+        };
+        let hash = match relayer::submit(&url, &api_key, &self.address, &authorization, &call.execute_data(&signature)) {
+            Ok(h) => h,
+            Err(e) => return println!("{}", e),
+        };
+        println!("Relayer accepted transaction {}, waiting for it to appear on chain...", hash);
+
+        // relayers can silently drop requests or broadcast something else under the hash, so
+        // don't trust it until the node has the transaction and it is the one signed
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(RELAY_WAIT_SECS);
+        while std::time::Instant::now() < deadline {
+            match rpc.get_transaction_fields(&hash) {
+                Ok(Some(fields)) => return match relayer::verify(&fields, &call, &authorization, &signature) {
+                    Ok(()) => {
+                        self.record_sent(&hash, recipient_bytes, wei_amount, 0, Some(&url));
+                        self.nonce += 1;
+                        println!("Relayed transaction {} successfully sent", hash);
+                        // the relayer pays the gas
+                        journal::record(JournalEntry::Sent { from: self.address.clone(), hash, fee: 0 })
+                    },
+                    Err(e) => println!("Relayed transaction {} is not the one that was signed: {}. It wasn't recorded.", hash, e),
+                },
+                Ok(None) => {},
+                Err(e) => println!("{}", e),
+            }
+            std::thread::sleep(refresh::current().receipt_poll_interval());
+        }
+        println!("Relayed transaction {} never appeared on chain; the relayer may have dropped it", hash);
+    }
+
+    /// Builds a transfer for the approval queue. Nothing is signed until it is approved.
+    fn prompt_staged_transfer(&self, rpc: &dyn EthRpc, index: usize, expiry_secs: u64, book: &AddressBook) -> Option<StagedTransaction> {
+        let (_, recipient_bytes) = utils::get_valid_address_bytes(book, None).ok()?;
+        let wei_amount = prompt_wei_amount();
+        if let Some(warning) = book.check_deposit(&recipient_bytes, &[], wei_amount).warning() {
+            println!("{}", warning);
+        }
+
+        let price = match floored_gas_price(rpc) {
+            Ok(p) => p,
+            Err(e) => {
+                println!("{}", e);
+                return None;
+            },
+        };
+        if !self.covers_fee(rpc, wei_amount, price * TRANSFER_GAS_LIMIT, None) {
+            println!("Transaction canceled");
+            return None;
+        }
+        let max_gas_price = price + price / 4;
+        println!("Let the gas price follow the network until approval, up to {} wei? (y/n, n keeps {} wei)", max_gas_price, price);
+        let fee = match read_user_input().as_str() {
+            "y" => FeeStrategy::NetworkPrice { max_gas_price },
+            _ => FeeStrategy::Fixed(price),
+        };
+
+        let now = utils::now();
+        let transaction = StagedTransaction {
+            id: 0,
+            account: index,
+            from: self.address.clone(),
+            recipient: String::from("0x") + &hex::encode(recipient_bytes),
+            wei_amount,
+            fee,
+            staged_at: now,
+            expires_at: now + expiry_secs,
+        };
+        println!("Transaction details:\n\tTO: {}\n\tAMOUNT: {} ETH\n\t", transaction.recipient, utils::wei_to_eth(wei_amount));
+        println!("Press 1 to STAGE for approval");
+        println!("Press any other key to CANCEL");
+        if read_user_input() != "1" {
+            println!("Transaction canceled");
+            return None;
+        }
+        Some(transaction)
+    }
+
+    /// Signs and broadcasts an approved transaction with the gas price its fee strategy allows
+    fn send_staged(&mut self, rpc: &dyn EthRpc, deriving_key: &XPrv, transaction: &StagedTransaction) -> bool {
+        let price = match floored_gas_price(rpc).and_then(|p| transaction.fee.gas_price(p)) {
+            Ok(p) => p,
+            Err(e) => {
+                println!("{}", e);
+                return false;
+            },
+        };
+        if !self.covers_fee(rpc, transaction.wei_amount, price * TRANSFER_GAS_LIMIT, None) {
+            return false;
+        }
+
+        let recipient_bytes = match utils::sanitize_address(&transaction.recipient) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                println!("The recipient {} is damaged: {}", transaction.recipient, e);
+                return false;
+            },
+        };
+        if let Err(e) = self.sync_nonce(rpc) {
+            println!("{}", e);
+            return false;
+        }
+        let final_txn = self.sign_transfer(deriving_key, recipient_bytes, transaction.wei_amount, GasPricing::Legacy(price), TRANSFER_GAS_LIMIT);
+        self.broadcast(rpc, &final_txn, recipient_bytes, transaction.wei_amount, price, TRANSFER_GAS_LIMIT)
+    }
+
+    /// Resolves an amount entry to wei, fetching the balance if the entry is relative to it
+    fn resolve_amount(&self, rpc: &dyn EthRpc, entry: AmountEntry, fee: u128) -> Result<u128, String> {
+        if !entry.is_relative() {
+            return entry.resolve(0, fee);
+        }
+        let balance = rpc.get_balance(&self.address)?;
+        entry.resolve(balance, fee)
+    }
+
+    /// Checks the balance covers the amount plus fee, printing the shortfall if it doesn't. For
+    /// a `token` transfer it says whether the tokens are there and only the gas is missing. If
+    /// the balance can't be fetched the send is allowed to go ahead.
+    fn covers_fee(&self, rpc: &dyn EthRpc, wei_amount: u128, fee: u128, token: Option<&TokenSend>) -> bool {
+        match rpc.get_balance(&self.address) {
+            Ok(balance) => {
+                if let Some(shortfall) = utils::gas_shortfall(balance, wei_amount, fee) {
+                    println!("{}", describe_shortfall(balance, wei_amount + fee, shortfall, token));
+                    return false;
+                }
+                true
+            },
+            Err(e) => {
+                println!("Could not check the balance before sending: {}", e);
+                true
+            },
+        }
+    }
+
+    /// Takes the nonce to sign with from the node, which counts transactions sent from the
+    /// account by other wallets and those still pending. If the node can't be reached, the
+    /// wallet's own count is kept, for transactions signed now and sent later. Fails if the node
+    /// serves another chain than the one transactions are signed for, so nothing is signed.
+    fn sync_nonce(&mut self, rpc: &dyn EthRpc) -> Result<(), String> {
+        let fetched = match networks::active().check_chain_id(rpc) {
+            Ok(()) => rpc.get_pending_transaction_count(&self.address),
+            Err(e @ ChainCheckError::WrongChain { .. }) => return Err(format!("{}, so the transaction wasn't signed", e)),
+            Err(e) => Err(e.to_string()),
+        };
+        match fetched {
+            Ok(nonce) if nonce != self.nonce => {
+                println!("The node has this account at nonce {}, and this wallet had it at {}; signing with {}", nonce, self.nonce, nonce);
+                self.nonce = nonce;
+            },
+            Ok(_) => {},
+            Err(e) => println!("Could not fetch the nonce, so this wallet's count of {} is used: {}", self.nonce, e),
+        }
+        Ok(())
+    }
+
+    /// Signs a plain ETH transfer with the account's nonce, as a legacy or an EIP-1559
+    /// transaction as `pricing` says, and returns it as 0x-prefixed hex
+    fn sign_transfer(&self, deriving_key: &XPrv, recipient_bytes: [u8; 20], wei_amount: u128, pricing: GasPricing, gas_limit: u128) -> String {
+        self.sign_call(deriving_key, recipient_bytes, wei_amount, vec![], pricing, gas_limit)
+    }
+
+    /// Signs a transaction that calls a contract with `data`, like a token transfer, the way
+    /// `sign_transfer` signs a transfer. The private key is derived for this signature only, and
+    /// wiped as soon as it is made.
+    fn sign_call(&self, deriving_key: &XPrv, to: [u8; 20], wei_amount: u128, data: Vec<u8>, pricing: GasPricing, gas_limit: u128) -> String {
+        let nonce = self.nonce;
+        let secret = SecretBuffer::new(&*self.secret_key(deriving_key));
+        let rlp_bytes = match pricing {
+            GasPricing::Legacy(price) => RawTransaction::new(
+                nonce as u128,
+                to,
+                wei_amount,
+                price,
+                gas_limit,
+                data
+            ).sign(&secret, &chain_id()),
+            GasPricing::FeeMarket { max_fee_per_gas, max_priority_fee_per_gas } => FeeMarketTransaction {
+                chain_id: chain_id(),
+                nonce,
+                max_priority_fee_per_gas,
+                max_fee_per_gas,
+                gas_limit,
+                to,
+                value: wei_amount,
+                data,
+            }.sign(&secret).expect("Derived private key is invalid"),
+        };
+        let mut final_txn = String::from("0x");
+        final_txn.push_str(&hex::encode(rlp_bytes));
+        final_txn
+    }
+
+    /// Broadcasts a signed transaction. If it was accepted, records it in the history and bumps
+    /// the nonce.
+    fn broadcast(&mut self, rpc: &dyn EthRpc, final_txn: &str, recipient_bytes: [u8; 20], wei_amount: u128, price: u128, gas_limit: u128) -> bool {
+        // the same signed transaction can only be mined once, so sending it again is safe even if
+        // the node got it the first time
+        match offer_retry(|| send_raw_transaction(rpc, final_txn)) {
+            Ok(hash) => {
+                self.record_sent(&hash, recipient_bytes, wei_amount, price, None);
+                println!("Transaction {} successfully sent", hash);
+                if let Some(url) = networks::active().transaction_url(&hash) {
+                    println!("View it at {}", url);
+                }
+                journal::record(JournalEntry::Sent { from: self.address.clone(), hash: hash.clone(), fee: price * gas_limit });
+                self.await_receipt(rpc, &hash, price);
+                true
+            },
+            Err(e) => {
+                println!("{}", e);
+                false
+            },
+        }
+    }
+
+    /// Waits for a transaction just broadcast to be mined and reports how it went. Mined, even if
+    /// it reverted, it used up its nonce, so only then does the account's nonce move past it.
+    /// Otherwise the nonce catches up when the node is next asked, or the history refreshed.
+    fn await_receipt(&mut self, rpc: &dyn EthRpc, hash: &str, gas_price: u128) {
+        println!("Waiting for it to be mined (Ctrl-C to stop waiting)...");
+        let timeout = std::time::Duration::from_secs(RECEIPT_TIMEOUT_SECS);
+        let receipt = match session::run_interruptible(|stop| rpc::wait_for_receipt(rpc, hash, timeout, stop)) {
+            Some(receipt) => receipt,
+            None => return println!("Transaction {} isn't mined yet. Refresh the transaction history to see when it is.", hash),
+        };
+
+        let paid = match receipt.effective_gas_price {
+            Some(price) => format!(" at {} wei per gas", price),
+            None => String::new(),
+        };
+        match receipt.status {
+            TransactionStatus::Failed => {
+                println!("TRANSACTION REVERTED in block {}: nothing was sent, but it used {} gas{} (fee {} ETH)",
+                    receipt.block_number, receipt.gas_used, paid, utils::wei_to_eth(receipt.fee(gas_price)));
+            },
+            _ => println!("Mined in block {}: used {} gas{} (fee {} ETH)",
+                receipt.block_number, receipt.gas_used, paid, utils::wei_to_eth(receipt.fee(gas_price))),
+        }
+        if let Some(record) = self.history.iter_mut().rev().find(|r| r.hash == hash) {
+            record.status = receipt.status;
+            self.nonce = self.nonce.max(record.nonce + 1);
+        }
+    }
+
+    /// Adds a transaction just broadcast with the current nonce to the history, and drops the
+    /// cached balance it made out of date
+    fn record_sent(&mut self, hash: &str, recipient_bytes: [u8; 20], wei_amount: u128, gas_price: u128, relayer: Option<&str>) {
+        self.balance_cache = None;
+        history::push(&mut self.history, TxRecord {
+            hash: String::from(hash),
+            recipient: String::from("0x") + &hex::encode(recipient_bytes),
+            wei_amount,
+            gas_price,
+            nonce: self.nonce,
+            sent_at: utils::now(),
+            status: TransactionStatus::Pending,
+            relayer: relayer.map(String::from),
+        });
+    }
+
+    /// Lists the transactions sent from this account, newest first, a page at a time, and looks
+    /// up any transaction by hash. Returns true if refreshing their statuses or a lookup changed
+    /// any.
+    fn view_history(&mut self, rpc: &dyn EthRpc) -> bool {
+        let mut page = 0;
+        let mut changed = false;
+        loop {
+            let pages = history::pages(&self.history);
+            match pages {
+                0 => println!("No transactions have been sent from this account"),
+                _ => println!("Transactions sent from this account, newest first (page {} of {}):", page + 1, pages),
+            }
+            for record in history::page(&self.history, page) {
+                println!("\t{}", record.describe());
+            }
+            println!("1) Older transactions");
+            println!("2) Newer transactions");
+            println!("3) Refresh their statuses");
+            println!("4) Look up a transaction by hash");
+            println!("5) Back");
+
+            match utils::read_user_input().parse::<u8>() {
+                Ok(1) if page + 1 < pages => page += 1,
+                Ok(2) if page > 0 => page -= 1,
+                Ok(1 | 2) => println!("There are no more transactions that way"),
+                Ok(3) => {
+                    let updated = history::refresh(&mut self.history, |hash| rpc.transaction_status(hash));
+                    changed |= updated > 0;
+                    if let Some(nonce) = history::next_mined_nonce(&self.history).filter(|n| *n > self.nonce) {
+                        self.nonce = nonce;
+                        changed = true;
+                    }
+                    println!("{} status(es) changed", updated);
+                },
+                Ok(4) => {
+                    println!("Enter the transaction hash: ");
+                    let hash = read_user_input().to_lowercase();
+                    // a malformed hash isn't worth asking the node about
+                    match relayer::is_valid_tx_hash(&hash) {
+                        true => changed |= self.look_up_transaction(rpc, &hash),
+                        false => println!("A transaction hash is 0x followed by 64 hex characters"),
+                    }
+                },
+                Ok(5) => return changed,
+                _ => println!("Invalid option"),
+            }
+        }
+    }
+
+    /// Prints whether the node knows a transaction, and whether it is pending or mined. If it is
+    /// one of this account's, its record is brought up to date, which returns true.
+    fn look_up_transaction(&mut self, rpc: &dyn EthRpc, hash: &str) -> bool {
+        let found = rpc.get_transaction(hash).and_then(|tx| match tx {
+            Some(tx) => rpc.transaction_receipt(hash).map(|receipt| Some((tx, receipt))),
+            None => Ok(None),
+        });
+        let status = match found {
+            Err(e) => {
+                println!("{}", e);
+                return false;
+            },
+            Ok(None) => {
+                println!("The node doesn't know transaction {}. It may have been dropped, or not reached the node yet.", hash);
+                return false;
+            },
+            Ok(Some((tx, None))) => {
+                let offered = match (tx.max_fee_per_gas, tx.gas_price) {
+                    (Some(max_fee), _) => format!("at most {} gwei per gas", fees::wei_to_gwei(max_fee)),
+                    (None, Some(price)) => format!("{} gwei per gas", fees::wei_to_gwei(price)),
+                    (None, None) => String::from("an unknown gas price"),
+                };
+                match rpc.gas_price() {
+                    Ok(network) => println!("Transaction {} is pending, offering {}, and the network's gas price is {} gwei", hash, offered, fees::wei_to_gwei(network)),
+                    Err(_) => println!("Transaction {} is pending, offering {}", hash, offered),
+                }
+                TransactionStatus::Pending
+            },
+            Ok(Some((tx, Some(receipt)))) => {
+                let confirmations = match rpc.block_number() {
+                    Ok(head) => format!(", {} confirmation(s)", head.saturating_sub(receipt.block_number) + 1),
+                    Err(_) => String::new(),
+                };
+                let outcome = match receipt.status {
+                    TransactionStatus::Failed => "REVERTED",
+                    _ => "succeeded",
+                };
+                println!("Transaction {} was mined in block {}{} and {}, paying {} ETH in fees", hash, receipt.block_number, confirmations,
+                    outcome, utils::wei_to_eth(receipt.fee(tx.gas_price.unwrap_or(0))));
+                receipt.status
+            },
+        };
+
+        let record = match self.history.iter_mut().find(|r| r.hash == hash) {
+            Some(record) => record,
+            None => return false,
+        };
+        let mut changed = std::mem::replace(&mut record.status, status) != status;
+        if status != TransactionStatus::Pending && record.nonce + 1 > self.nonce {
+            self.nonce = record.nonce + 1;
+            changed = true;
+        }
+        if changed {
+            println!("It was sent from this account, and its status is now {}", status.as_str());
+        }
+        changed
+    }
+
+    /// Lists the scheduled transactions of this account and lets the user add, edit or cancel them
+    fn manage_scheduled(&mut self, rpc: &dyn EthRpc, deriving_key: &XPrv, book: &AddressBook) {
+        loop {
+            println!("Scheduled transactions:");
+            if self.scheduled.is_empty() {
+                println!("\t(none)");
+            }
+            for (i, tx) in self.scheduled.iter().enumerate() {
+                let signed = if tx.presigned.is_some() { "pre-signed" } else { "signed at send time" };
+                println!("\t{}) {} ETH to {} at {} ({})", i, utils::wei_to_eth(tx.wei_amount), tx.recipient, utils::format_timestamp(tx.send_at), signed);
+            }
+            println!("1) Schedule a transaction");
+            println!("2) Change the time of a scheduled transaction");
+            println!("3) Cancel a scheduled transaction");
+            println!("4) Send due transactions now");
+            println!("5) Back");
+
+            match utils::read_user_input().parse::<u8>() {
+                Ok(1) => self.schedule_transaction(rpc, deriving_key, book),
+                Ok(2) => {
+                    if let Some(i) = self.prompt_scheduled_index() {
+                        println!("Enter the new send time (YYYY-MM-DD HH:MM in UTC, or +30m / +2h / +1d): ");
+                        match utils::parse_time(&utils::read_user_input(), utils::now()) {
+                            Ok(t) => self.scheduled[i].send_at = t,
+                            Err(e) => println!("{}", e),
+                        }
+                    }
+                },
+                Ok(3) => {
+                    if let Some(i) = self.prompt_scheduled_index() {
+                        self.scheduled.remove(i);
+                        println!("Scheduled transaction canceled");
+                    }
+                },
+                Ok(4) => self.send_due_scheduled(rpc, deriving_key, utils::now(), 0),
+                Ok(5) => return,
+                _ => println!("Invalid option"),
+            }
+        }
+    }
+
+    fn prompt_scheduled_index(&self) -> Option<usize> {
+        println!("Enter the number of the scheduled transaction: ");
+        match utils::read_user_input().parse::<usize>() {
+            Ok(i) if i < self.scheduled.len() => Some(i),
+            _ => {
+                println!("No such scheduled transaction");
+                None
+            },
+        }
+    }
+
+    fn schedule_transaction(&mut self, rpc: &dyn EthRpc, deriving_key: &XPrv, book: &AddressBook) {
+        let (recipient, recipient_bytes) = match utils::get_valid_address_bytes(book, None) {
+            Ok(r) => (r.0, r.1),
+            Err(_e) => return,
+        };
+        let wei_amount = prompt_wei_amount();
+        if let Some(warning) = book.check_deposit(&recipient_bytes, &[], wei_amount).warning() {
+            println!("{}", warning);
+        }
+
+        let send_at = loop {
+            println!("Enter the send time (YYYY-MM-DD HH:MM in UTC, or +30m / +2h / +1d): ");
+            match utils::parse_time(&utils::read_user_input(), utils::now()) {
+                Ok(t) => break t,
+                Err(e) => println!("{}", e),
+            }
+        };
+
+        println!("Sign now with the current gas price and nonce? Otherwise it is signed at send time (y/n)");
+        let presigned = if utils::read_user_input() == "y" {
+            let price = match floored_gas_price(rpc) {
+                Ok(p) => p,
+                Err(e) => return println!("{}", e),
+            };
+            println!("NOTE: sending any other transaction from this account before then invalidates the signature; it will be re-signed at send time.");
+            if let Err(e) = self.sync_nonce(rpc) {
+                return println!("{}", e);
+            }
+            Some(PresignedTransaction {
+                raw: self.sign_transfer(deriving_key, recipient_bytes, wei_amount, GasPricing::Legacy(price), TRANSFER_GAS_LIMIT),
+                nonce: self.nonce,
+                gas_price: price,
+            })
+        } else {
+            None
+        };
+
+        self.scheduled.push(ScheduledTransaction { recipient, wei_amount, send_at, presigned });
+        println!("Transaction scheduled for {}", utils::format_timestamp(send_at));
+    }
+
+    /// Sends every scheduled transaction due at `now` that was scheduled for after `not_before`.
+    /// Pre-signed transactions are re-checked first and re-signed if their nonce went stale.
+    fn send_due_scheduled(&mut self, rpc: &dyn EthRpc, deriving_key: &XPrv, now: u64, not_before: u64) {
+        let (due, rest): (Vec<_>, Vec<_>) = self.scheduled.drain(..)
+            .partition(|tx| tx.is_due(now) && tx.send_at >= not_before);
+        self.scheduled = rest;
+        if due.is_empty() {
+            return;
+        }
+
+        for tx in due {
+            println!("Sending scheduled transaction of {} ETH to {}", utils::wei_to_eth(tx.wei_amount), tx.recipient);
+            let price = match floored_gas_price(rpc) {
+                Ok(p) => p,
+                Err(e) => {
+                    println!("{}. It will be retried later.", e);
+                    self.scheduled.push(tx);
+                    continue;
+                },
+            };
+            if !self.covers_fee(rpc, tx.wei_amount, price * TRANSFER_GAS_LIMIT, None) {
+                println!("Scheduled transaction kept for later");
+                self.scheduled.push(tx);
+                continue;
+            }
+
+            let recipient_bytes = match utils::sanitize_address(&tx.recipient) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    println!("The recipient {} is damaged: {}. Cancel the scheduled transaction and schedule it again.", tx.recipient, e);
+                    self.scheduled.push(tx);
+                    continue;
+                },
+            };
+            if let Err(e) = self.sync_nonce(rpc) {
+                println!("{}. Scheduled transaction kept for later.", e);
+                self.scheduled.push(tx);
+                continue;
+            }
+            let (raw, signed_price) = match (tx.check_presigned(self.nonce, price), &tx.presigned) {
+                (PresignedCheck::Ok, Some(p)) => (p.raw.clone(), p.gas_price),
+                (PresignedCheck::Underpriced { signed, current }, Some(p)) => {
+                    println!("Gas price rose from {} to {} wei since signing; the transaction may take longer to be mined", signed, current);
+                    (p.raw.clone(), p.gas_price)
+                },
+                (PresignedCheck::StaleNonce { signed, current }, _) => {
+                    println!("Signed with nonce {} but the account is at nonce {}; re-signing", signed, current);
+                    (self.sign_transfer(deriving_key, recipient_bytes, tx.wei_amount, GasPricing::Legacy(price), TRANSFER_GAS_LIMIT), price)
+                },
+                (_, None) => (self.sign_transfer(deriving_key, recipient_bytes, tx.wei_amount, GasPricing::Legacy(price), TRANSFER_GAS_LIMIT), price),
+            };
+
+            if !self.broadcast(rpc, &raw, recipient_bytes, tx.wei_amount, signed_price, TRANSFER_GAS_LIMIT) {
+                println!("Scheduled transaction kept for later");
+                self.scheduled.push(tx);
+            }
+        }
+    }
+}
+
+/// Menu for pairing with QR-based air-gapped signers through `crypto-hdkey` URs. Accounts of an
+/// imported key are added to `metadata` as watch-only accounts.
+fn air_gapped_signer(unlock: &dyn Fn(&str) -> Option<Zeroizing<Vec<u8>>>, source: KeySource, metadata: &mut AccountMetadata) {
+    let template = metadata.path_template.clone();
+    loop {
+        println!("1) Show this wallet's account key as a QR code");
+        println!("2) Import an account key from another device");
+        println!("3) Back");
+
+        match utils::read_user_input().parse::<u8>() {
+            // the exported key only covers accounts at m/44'/60'/0'/0/x
+            Ok(1) if !template.is_standard() => println!("Only wallets using the standard account layout can be exported"),
+            Ok(1) => {
+                println!("Enter the wallet password: ");
+                match unlock(&utils::read_secret_input()) {
+                    Some(secret) if source == KeySource::Seed => export_account_key(&secret),
+                    Some(secret) => export_deriving_key(&deriving_key_from_secret(source, &template, &secret)),
+                    None => println!("Incorrect password"),
+                }
+            },
+            Ok(2) => import_account_key(metadata),
+            Ok(3) => break,
+            _ => println!("Invalid option"),
+        }
+    }
+}
+
+/// Prints the account-level xpub with its fingerprint and path as a single-part UR and QR code
+fn export_account_key(seed: &[u8]) {
+    let (_, xpub) = utils::create_keys_from_path(seed, ACCOUNT_KEY_PATH);
+    let master_fingerprint = XPrv::new(seed).unwrap().public_key().fingerprint();
+    let origin = KeyOrigin {
+        components: vec![(44, true), (60, true), (0, true)],
+        source_fingerprint: Some(u32::from_be_bytes(master_fingerprint)),
+        depth: Some(3),
+    };
+    print_hdkey(&HdKey::from_xpub(&xpub, origin, Some(String::from("basic-eth"))));
+    println!("Master key fingerprint: {}", hex::encode(master_fingerprint));
+}
+
+/// Exports a wallet restored from an xprv. Without a seed the account-level key is unknown, so
+/// the imported key at m/44'/60'/0'/0 is shown instead, with no master fingerprint.
+fn export_deriving_key(deriving_key: &XPrv) {
+    let origin = KeyOrigin {
+        components: vec![(44, true), (60, true), (0, true), (0, false)],
+        source_fingerprint: None,
+        depth: Some(4),
+    };
+    print_hdkey(&HdKey::from_xpub(&deriving_key.public_key(), origin, Some(String::from("basic-eth"))));
+    println!("This wallet was restored from an xprv, so the key shown is m/44'/60'/0'/0 and has no master fingerprint");
+}
+
+fn print_hdkey(hdkey: &HdKey) {
+    let ur = hdkey.to_ur();
+    // QR codes hold upper case text more compactly
+    let qr = QrCode::with_error_correction_level(ur.to_uppercase(), EcLevel::L).unwrap();
+    println!("{}", qr.render::<Dense1x2>().dark_color(Dense1x2::Light).light_color(Dense1x2::Dark).build());
+    println!("{}", ur);
+}
+
+/// Reads a UR scanned from another device, shows the addresses it derives and adds those not in
+/// the wallet as watch-only accounts once the user confirms
+fn import_account_key(metadata: &mut AccountMetadata) {
+    println!("Enter the ur:crypto-hdkey/... text from the other device: ");
+    let key = match HdKey::from_ur(&utils::read_user_input()) {
+        Ok(k) => k,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        },
+    };
+    if key.coin_type.unwrap_or(COIN_TYPE_ETH) != COIN_TYPE_ETH {
+        println!("This key is not an Ethereum key");
+        return;
+    }
+    let xpub = match key.to_xpub() {
+        Ok(x) => x,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        },
+    };
+
+    let path = key.origin.as_ref().map(KeyOrigin::path).unwrap_or_else(|| String::from("m"));
+    if let Some(name) = &key.name {
+        println!("Key name: {}", name);
+    }
+    if let Some(fingerprint) = key.origin.as_ref().and_then(|o| o.source_fingerprint) {
+        println!("Master key fingerprint: {:08x}", fingerprint);
+    }
+    let receiving = xpub.derive_child(ChildNumber::new(0, false).unwrap()).unwrap();
+    let mut added = Vec::new();
+    for index in 0..IMPORTED_KEY_PREVIEW {
+        let child = receiving.derive_child(ChildNumber::new(index, false).unwrap()).unwrap();
+        let (child_path, address) = (format!("{}/0/{}", path, index), utils::xpub_to_address(&child));
+        match metadata.find_position(&address) {
+            Some(_) => println!("{}: {} (already in this wallet)", child_path, address),
+            None => {
+                println!("{}: {}", child_path, address);
+                added.push(Account { path: child_path, ..Account::watch(address, key.name.clone()) });
+            },
+        }
+    }
+    if added.is_empty() {
+        return println!("No accounts to add");
+    }
+
+    println!("Press 1 to ADD the {} new addresses as watch-only accounts", added.len());
+    println!("Press any other key to CANCEL");
+    if utils::read_user_input() != "1" {
+        return println!("Watch-only import canceled");
+    }
+    let count = added.len();
+    for account in added {
+        journal::record(JournalEntry::WatchOnlyAdded { address: account.address.clone() });
+        metadata.external.push(account);
+    }
+    println!("Added {} watch-only accounts", count);
+}
+
+/// Prompts until the user enters a valid amount. Besides ETH amounts, `max`, a percentage of the
+/// balance (`50%`) and `keep <ETH amount>` are accepted.
+fn prompt_amount_entry() -> AmountEntry {
+    loop {
+        println!("Enter ETH amount to send (or max, 50%, keep 0.05): ");
+        match AmountEntry::parse(&utils::read_user_input()) {
+            Ok(v) => break v,
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// Prompts until the user enters a valid ETH amount and returns it in wei
+fn prompt_wei_amount() -> u128 {
+    loop {
+        println!("Enter ETH amount to send: ");
+        match utils::eth_to_wei(&utils::read_user_input()) {
+            Ok(v) => break v,
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// Asks for the gas price and gas limit of a single send. Enter keeps each current value.
+fn prompt_gas_override(price: u128, gas_limit: u128) -> (u128, u128) {
+    let price = loop {
+        println!("Gas price in gwei (enter keeps {}): ", fees::wei_to_gwei(price));
+        match read_user_input().as_str() {
+            "" => break price,
+            input => match fees::gwei_to_wei(input) {
+                Ok(p) => break p,
+                Err(e) => println!("{}", e),
+            },
+        }
+    };
+    let gas_limit = loop {
+        println!("Gas limit (enter keeps {}): ", gas_limit);
+        match read_user_input().as_str() {
+            "" => break gas_limit,
+            input => match fees::parse_gas_limit(input) {
+                Ok(l) => break l,
+                Err(e) => println!("{}", e),
+            },
+        }
+    };
+    (price, gas_limit)
+}
+
+fn chain_id() -> u64 {
+    networks::active().chain_id
+}
+
+/// Looks up a name entered as a recipient, on networks with ENS
+fn resolve_recipient(rpc: &dyn EthRpc, name: &str) -> Result<[u8; 20], String> {
+    let network = networks::active();
+    match ens::is_available(network.chain_id) {
+        true => ens::resolve(rpc, name),
+        false => Err(format!("ENS isn't deployed on {} (chain {}), so names can't be sent to. Enter the 0x address.", network.name, network.chain_id)),
+    }
+}
+
+/// Raises a price below the network's floor to it, and on a network with `market` a tip below
+/// the network's tip floor to that, unless the user overrides each for this send
+fn prompt_price_floor(price: u128, market: Option<FeeMarket>) -> (u128, Option<FeeMarket>) {
+    let network = networks::active();
+    let clamped = fees::clamp_gas_price(price, network.min_gas_price());
+    let mut override_floor = false;
+    if let Some(note) = clamped.note() {
+        println!("{}", note);
+        println!("Type override to sign with the computed {} wei for this send only, or press enter to continue", clamped.computed);
+        override_floor = read_user_input() == "override";
+    }
+    let price = clamped.resolve(override_floor);
+    let market = match market {
+        Some(market) => market,
+        None => return (price, None),
+    };
+
+    let clamped = fees::clamp_priority_fee(price, market, network.min_priority_fee());
+    let mut override_floor = false;
+    if let Some(note) = clamped.note() {
+        println!("{}", note);
+        println!("Type override to sign with the computed tip of {} wei for this send only, or press enter to continue", clamped.computed.1.priority_fee.min(price));
+        override_floor = read_user_input() == "override";
+    }
+    let (price, market) = clamped.resolve(override_floor);
+    (price, Some(market))
+}
+
+/// The price line of a confirmation screen
+fn describe_pricing(price: u128, market: Option<FeeMarket>) -> String {
+    match GasPricing::new(price, market) {
+        GasPricing::Legacy(price) => format!("GAS PRICE: {} wei", price),
+        GasPricing::FeeMarket { max_fee_per_gas, max_priority_fee_per_gas } => format!(
+            "MAX FEE PER GAS: {} wei (base fee now {} wei, tip {} wei)", max_fee_per_gas, market.unwrap().base_fee, max_priority_fee_per_gas),
+    }
+}
+
+/// What follows the gas limit on a confirmation screen
+fn describe_estimate(estimate: &Result<u128, String>) -> String {
+    match estimate {
+        Ok(estimate) => format!(" (estimated at {})", estimate),
+        Err(_) => String::from(" (not estimated)"),
+    }
+}
+
+/// A call to a contract sent with no ETH, like a token transfer, and what its confirmation screen
+/// shows of it
+struct ContractCall {
+    /// Heads the confirmation screen
+    title: &'static str,
+    contract: [u8; 20],
+    data: Vec<u8>,
+    /// The gas limit if the node can't estimate one
+    fallback_gas_limit: u128,
+    /// Lines of the confirmation screen between the network and the fees
+    details: Vec<String>,
+    /// Shown under the details, before asking to confirm
+    warning: Option<String>,
+    /// The token it sends, if it is a token transfer
+    token: Option<TokenSend>,
+}
+
+/// The token a transfer sends and the account's balance of it, which tells a fee that can't be
+/// paid apart from tokens that aren't there
+struct TokenSend {
+    symbol: String,
+    held: Result<u128, String>,
+}
+
+/// How many typical transfers costing `fee` a `balance` on `network` pays the gas of
+fn describe_gas(network: &str, currency: &str, balance: u128, fee: u128) -> String {
+    match utils::affordable_transfers(balance, fee) {
+        0 => format!("Gas on {}: not enough {} for a transfer (~{} {} each)", network, currency, utils::wei_to_eth(fee), currency),
+        transfers => format!("Gas on {}: can afford ~{} typical transfers", network, transfers),
+    }
+}
+
+/// Why a send that needs `needed` wei can't go ahead from a `balance` that is `shortfall` short
+fn describe_shortfall(balance: u128, needed: u128, shortfall: u128, token: Option<&TokenSend>) -> String {
+    let (needed, balance) = (utils::wei_to_eth(needed), utils::wei_to_eth(balance));
+    match token {
+        // the token balance was checked to cover the amount before this
+        Some(TokenSend { symbol, held: Ok(_) }) =>
+            format!("You have enough {} but not enough ETH for gas (need ~{} ETH, have {} ETH)", symbol, needed, balance),
+        Some(TokenSend { symbol, held: Err(_) }) =>
+            format!("Not enough ETH for the gas to send {} (need ~{} ETH, have {} ETH)", symbol, needed, balance),
+        None => format!("Not enough ETH for this transfer and its gas fee (need ~{} ETH, have {} ETH, short {} ETH)", needed, balance, utils::wei_to_eth(shortfall)),
+    }
+}
+
+/// Asks which of `tokens` to `action`, and returns it with its contract address as bytes
+fn pick_token<'a>(tokens: &'a [Token], action: &str) -> Option<(&'a Token, [u8; 20])> {
+    if tokens.is_empty() {
+        println!("No tokens are listed for {}. Register one to {} it.", networks::active().name, action);
+        return None;
+    }
+    for (i, token) in tokens.iter().enumerate() {
+        println!("{}) {} ({})", i + 1, token.symbol, token.address);
+    }
+    println!("Enter the number of the token to {}: ", action);
+    let token = match read_user_input().parse::<usize>() {
+        Ok(i) if (1..=tokens.len()).contains(&i) => &tokens[i - 1],
+        _ => {
+            println!("Invalid token");
+            return None;
+        },
+    };
+    match hex::decode(token.address.trim_start_matches("0x")).ok().and_then(|b| b.try_into().ok()) {
+        Some(bytes) => Some((token, bytes)),
+        None => {
+            println!("{} isn't a valid contract address for {}", token.address, token.symbol);
+            None
+        },
+    }
+}
+
+/// Asks for the contract or account an allowance is for, by address or address book name
+fn prompt_spender(book: &AddressBook) -> Option<(String, [u8; 20])> {
+    loop {
+        match book.is_empty() {
+            true => println!("Enter the spender's address (or press q to cancel): "),
+            false => println!("Enter the spender's address or address book name (or press q to cancel): "),
+        }
+        let input = read_user_input();
+        if input == "q" {
+            return None;
+        }
+        let address = match book.find(&input) {
+            Some(contact) => contact.address.clone(),
+            None => input,
+        };
+        match labels::normalize_address(&address) {
+            Some(normalized) => {
+                let bytes: [u8; 20] = hex::decode(&normalized[2..]).unwrap().try_into().unwrap();
+                return Some((checksum_address(&bytes), bytes));
+            },
+            None => println!("{} is not a valid address or its checksum is wrong", address),
+        }
+    }
+}
+
+/// Prompts until the user enters a token id
+fn prompt_token_id() -> [u8; 32] {
+    loop {
+        println!("Enter the token id: ");
+        match erc721::parse_token_id(&read_user_input()) {
+            Ok(id) => break id,
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// Shows who owns token `id` of `collection`, marking `address` as this account, and the
+/// token's metadata URI if it has one
+fn print_nft(rpc: &dyn EthRpc, collection: &Collection, id: &[u8; 32], address: &str) {
+    println!("Token {} of {} ({}):", erc721::format_token_id(id), collection.name, collection.address);
+    match erc721::owner_of(rpc, &collection.address, id) {
+        Ok(owner) if hex::encode(owner) == address[2..].to_lowercase() => println!("\tOwner: {} (this account)", address),
+        Ok(owner) => println!("\tOwner: {}", checksum_address(&owner)),
+        Err(e) => println!("\tCould not check who owns it: {}", e),
+    }
+    if let Some(uri) = erc721::token_uri(rpc, &collection.address, id) {
+        println!("\tMetadata: {}", uri);
+    }
+}
+
+/// The banner above an account's menu, with the account's primary ENS name when it has one
+fn print_current_address(rpc: &dyn EthRpc, address: &str) {
+    match ens::primary_name(rpc, chain_id(), address) {
+        Some(name) => println!("CURRENT ACCOUNT ADDRESS: {} ({})", address, name),
+        None => println!("CURRENT ACCOUNT ADDRESS: {}", address),
+    }
+}
+
+/// Submits a signed transaction and returns its hash. It is only submitted to a node that
+/// serves the chain it was signed for.
+fn send_raw_transaction(rpc: &dyn EthRpc, final_txn: &str) -> Result<String, String> {
+    networks::active().check_chain_id(rpc).map_err(|e| format!("{}, so the transaction wasn't sent", e))?;
+    rpc.send_raw_transaction(final_txn)
+}
+
+/// Offers the speed tiers with about what each would cost at `gas_limit`, and a custom price.
+/// A tier chosen becomes the default in `gas`. Returns the price, the fees to sign with on a
+/// network with `market` and whether the price was entered, which lets it go above the
+/// maximum. None if there is no price to send at.
+fn prompt_speed_tier(rpc: &dyn EthRpc, gas: &mut GasPreferences, market: Option<FeeMarket>, gas_limit: u128) -> Option<(u128, Option<FeeMarket>, bool)> {
+    // on a network with a base fee the tiers follow the tips of the latest blocks, or scale the
+    // node's tip where it can't tell them, so don't need the quote
+    let suggested = market.and_then(|_| rpc::suggest_fees(rpc).map_err(|e| {
+        println!("Could not get fee suggestions from the latest blocks, so the node's tip is scaled: {}", e);
+    }).ok());
+    let quote = match market {
+        Some(market) => Ok(market.base_fee + market.priority_fee),
+        None => rpc.gas_price(),
+    };
+    let tiers: Vec<_> = match (suggested, &quote) {
+        (Some(suggested), _) => GasPriceStrategy::TIERS.iter().zip(suggested)
+            .map(|(tier, market)| (*tier, market.max_fee_per_gas(), Some(market)))
+            .collect(),
+        (None, Ok(_)) => GasPriceStrategy::TIERS.iter()
+            .filter_map(|tier| tier.price(|| quote.clone(), market).ok().map(|(price, market)| (*tier, price, market)))
+            .collect(),
+        (None, Err(e)) => {
+            println!("Could not fetch the gas price, so only a custom one can be used: {}", e);
+            vec![]
+        },
+    };
+
+    println!("Gas speed:");
+    for (i, (tier, price, tier_market)) in tiers.iter().enumerate() {
+        let bounded = gas.bound(*price);
+        let expected = tier_market.map_or(bounded, |m| m.expected_price(bounded));
+        println!("{}) {}: {} gwei, about {} ETH{}{}", i + 1, tier.name(), fees::wei_to_gwei(bounded), utils::wei_to_eth(expected * gas_limit),
+            if bounded < *price { " (kept to your maximum gas price)" } else { "" },
+            if *tier == gas.strategy { " - the default" } else { "" });
+    }
+    println!("{}) Custom gas price", tiers.len() + 1);
+    let default = match gas.strategy {
+        GasPriceStrategy::Fixed(price) => Some((price, market, format!("the fixed {} gwei", fees::wei_to_gwei(price)))),
+        strategy => tiers.iter()
+            .find(|(tier, _, _)| *tier == strategy)
+            .map(|(tier, price, market)| (gas.bound(*price), *market, String::from(tier.name()))),
+    };
+    match &default {
+        Some((_, _, name)) => println!("Press enter for {}, or q to cancel", name),
+        None => println!("Press q to cancel"),
+    }
+
+    loop {
+        let input = read_user_input();
+        match input.parse::<usize>() {
+            _ if input == "q" => return None,
+            _ if input.is_empty() && default.is_some() => return default.map(|(price, market, _)| (price, market, false)),
+            Ok(i) if (1..=tiers.len()).contains(&i) => {
+                let (tier, price, tier_market) = tiers[i - 1];
+                if tier != gas.strategy {
+                    gas.strategy = tier;
+                    println!("{} is now the default gas speed", tier.name());
+                }
+                return Some((gas.bound(price), tier_market, false));
+            },
+            Ok(i) if i == tiers.len() + 1 => {
+                println!("Gas price in gwei{}: ", if market.is_some() { ", the most to pay per gas" } else { "" });
+                match fees::gwei_to_wei(&read_user_input()) {
+                    Ok(price) => return Some((price, market, true)),
+                    Err(e) => println!("{}", e),
+                }
+            },
+            _ => println!("Invalid option"),
+        }
+    }
+}
+
+/// The fees of the active network if it charges a base fee, in which case sends are EIP-1559
+/// transactions. Otherwise, or if the node can't say, they are legacy ones, which every
+/// network takes.
+fn fetch_fee_market(rpc: &dyn EthRpc) -> Option<FeeMarket> {
+    let market = rpc.base_fee_per_gas().and_then(|base_fee| match base_fee {
+        Some(base_fee) => rpc.gas_price().map(|price| Some(FeeMarket::new(base_fee, price))),
+        None => Ok(None),
+    });
+    market.unwrap_or_else(|e| {
+        println!("Could not fetch the network's base fee, so a legacy transaction is sent: {}", e);
+        None
+    })
+}
+
+/// Makes a node call, and offers to make it again for as long as it times out
+fn offer_retry<T>(mut call: impl FnMut() -> Result<T, String>) -> Result<T, String> {
+    loop {
+        match call() {
+            Err(e) if rpc::timed_out(&e) => {
+                println!("The node didn't answer in time. Try again? (y/n)");
+                if read_user_input() != "y" {
+                    return Err(e);
+                }
+            },
+            result => return result,
+        }
+    }
+}
+
+/// Returns the balance and transaction count of an address
+fn fetch_account_activity(rpc: &dyn EthRpc, address: &str) -> Result<(u128, u64), String> {
+    Ok((rpc.get_balance(address)?, rpc.get_transaction_count(address)?))
+}
+
+/// Fetches the current gas price and checks it against the configured floor
+fn fetch_clamped_gas_price(rpc: &dyn EthRpc) -> Result<ClampedGasPrice, String> {
+    let floor = networks::active().min_gas_price();
+    Ok(fees::clamp_gas_price(rpc.gas_price()?, floor))
+}
+
+/// Fetches the gas price to sign with, raised to the configured floor with a note if needed
+fn floored_gas_price(rpc: &dyn EthRpc) -> Result<u128, String> {
+    let clamped = fetch_clamped_gas_price(rpc)?;
+    if let Some(note) = clamped.note() {
+        println!("{}", note);
+    }
+    Ok(clamped.price)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::fixtures;
+    use crate::rpc::mock::MockRpc;
+    use crate::utils::WEI_PER_ETH;
+
+    /// Removes a wallet file a test stored, and the backup of it a second store leaves
+    fn remove_wallet_file(file: impl AsRef<std::path::Path>) {
+        let file = file.as_ref();
+        std::fs::remove_file(file).unwrap();
+        let _ = std::fs::remove_file(backup_path(file.to_str().unwrap()));
+    }
+
+    #[test]
+    fn test_strengths() {
+        for strength in MnemonicStrength::ALL {
+            let mnemonic = Mnemonic::new(strength.mnemonic_type(), Language::English);
+            assert_eq!(mnemonic.phrase().split(' ').count(), strength.word_count());
+            // 11 bits per word, of which one in 33 is checksum
+            assert_eq!(mnemonic.entropy().len(), strength.word_count() * 11 * 32 / 33 / 8);
+
+            let seed = Seed::new(&mnemonic, "");
+            assert_eq!(seed.as_bytes().len(), 64);
+            let key = keccak512(b"password");
+            let pad = utils::xor(seed.as_bytes(), &key).unwrap();
+            assert_eq!(utils::xor(&pad, &key).unwrap(), seed.as_bytes());
+
+            // import picks up the length from the phrase
+            let imported = utils::parse_mnemonic(mnemonic.phrase(), Language::English).unwrap();
+            assert_eq!(Seed::new(&imported, "").as_bytes(), seed.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_24_word_round_trip() {
+        let created = Wallet::new(String::from("first password"), MnemonicStrength::Words24, MnemonicLanguage::English, "");
+        // the phrase is only printed, so check its length through the backup check
+        assert_eq!(created.accounts_metadata.backup.as_ref().unwrap().word_count(), 24);
+
+        let mnemonic = Mnemonic::new(MnemonicType::Words24, Language::English);
+        let phrase = String::from(mnemonic.phrase());
+        let first = Wallet::from(String::from("first password"), mnemonic, "");
+        let imported = Wallet::from(String::from("second password"), utils::parse_mnemonic(&phrase, Language::English).unwrap(), "");
+        assert_eq!(first.verification_key, imported.verification_key);
+        assert_eq!(first.addresses(), imported.addresses());
+    }
+
+    #[test]
+    fn test_bip39_passphrase() {
+        let plain = fixtures::test_wallet();
+        // the well-known first address of this phrase without a passphrase
+        assert_eq!(plain.addresses()[0], "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266");
+
+        let mut protected = Wallet::from(String::from("password"), fixtures::fixture_mnemonic(), "TREZOR");
+        assert_ne!(protected.verification_key, plain.verification_key);
+        assert_ne!(protected.addresses(), plain.addresses());
+        assert_eq!(Wallet::from(String::from("other"), fixtures::fixture_mnemonic(), "TREZOR").addresses(), protected.addresses());
+
+        let stored = String::from_utf8(protected.to_bytes()).unwrap();
+        assert!(!stored.contains("TREZOR"));
+        let mut loaded: Wallet = serde_json::from_str(&stored).unwrap();
+        loaded.verify_password(String::from("password")).unwrap();
+    }
+
+    #[test]
+    fn test_languages() {
+        for language in MnemonicLanguage::ALL {
+            let mnemonic = Mnemonic::new(MnemonicType::Words12, language.language());
+            let phrase = String::from(mnemonic.phrase());
+            let parsed = MnemonicLanguage::parse_phrase(&phrase).unwrap();
+            assert!(parsed.iter().any(|(l, _)| *l == language), "{} not detected", language.name());
+
+            let wallet = Wallet::from(String::from("password"), mnemonic, "");
+            assert_eq!(wallet.language, language);
+            // same words, same seed
+            let (_, reparsed) = parsed.into_iter().find(|(l, _)| *l == language).unwrap();
+            assert_eq!(Wallet::from(String::from("password"), reparsed, "").verification_key, wallet.verification_key);
+        }
+
+        assert_eq!(MnemonicLanguage::parse_phrase("one two three").unwrap_err(), MnemonicError::WordCount(3));
+        assert_eq!(MnemonicLanguage::parse_phrase(&"test ".repeat(12)).unwrap_err(), MnemonicError::Checksum);
+        // the typo is reported against English, not against a wordlist where nothing matches
+        let typo = fixtures::FIXTURE_MNEMONIC.replace("junk", "junque");
+        assert_eq!(MnemonicLanguage::parse_phrase(&typo).unwrap_err(), MnemonicError::UnknownWords(vec![(12, String::from("junque"))]));
+    }
+
+    #[test]
+    fn test_language_defaults_to_english() {
+        let mut wallet = Wallet::from(String::from("password"), Mnemonic::new(MnemonicType::Words12, Language::French), "");
+        let mut stored: Value = serde_json::from_slice(&wallet.to_bytes()).unwrap();
+        assert_eq!(stored["language"], "French");
+        // wallets stored before languages were recorded
+        stored.as_object_mut().unwrap().remove("language");
+        let loaded: Wallet = serde_json::from_value(stored).unwrap();
+        assert_eq!(loaded.language, MnemonicLanguage::English);
+    }
+
+    #[test]
+    fn test_imported_key() {
+        let key = "59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d";
+        let mut wallet = Wallet::from(String::from("password"), Mnemonic::new(MnemonicType::Words12, Language::English), "");
+        let deriving_key = wallet.accounts_metadata.deriving_key.clone().unwrap();
+        let secret = utils::parse_private_key(key).unwrap();
+        let sealed = SealedBox::seal(IMPORTED_KEY_CONTEXT, &deriving_key.to_bytes(), secret.as_slice());
+        wallet.accounts_metadata.external.push(Account::imported(utils::secret_key_to_address(&secret).unwrap(), sealed));
+
+        let stored = String::from_utf8(wallet.to_bytes()).unwrap();
+        assert!(!stored.contains(key));
+        let mut loaded: Wallet = serde_json::from_str(&stored).unwrap();
+        loaded.verify_password(String::from("password")).unwrap();
+
+        let deriving_key = loaded.accounts_metadata.deriving_key.clone().unwrap();
+        let account = loaded.accounts_metadata.get_account(1);
+        assert_eq!(account.address, "0x70997970c51812dc3a010c7d01b50e0d17dc79c8");
+        assert_eq!(account.path, IMPORTED_KEY_PATH);
+        assert_eq!(*account.secret_key(&deriving_key), *secret);
+    }
+
+    #[test]
+    fn test_from_xprv() {
+        let mnemonic = fixtures::fixture_mnemonic();
+        let seed = Seed::new(&mnemonic, "");
+        let (deriving_key, _) = utils::create_keys_from_path(seed.as_bytes(), &PathTemplate::standard().root());
+        let xprv = deriving_key.to_string(Prefix::XPRV);
+
+        let mut restored = Wallet::from_xprv(String::from("password"), &xprv).unwrap();
+        restored.add_account();
+        let mut from_seed = Wallet::from(String::from("password"), mnemonic, "");
+        from_seed.add_account();
+        assert_eq!(restored.addresses(), from_seed.addresses());
+
+        let stored = String::from_utf8(restored.to_bytes()).unwrap();
+        assert!(!stored.contains(xprv.as_str()));
+        let mut loaded: Wallet = serde_json::from_str(&stored).unwrap();
+        assert_eq!(loaded.source, KeySource::ExtendedKey);
+        assert_eq!(loaded.verify_password(String::from("wrong password")), Err(UnlockError::IncorrectPassword));
+        loaded.verify_password(String::from("password")).unwrap();
+        assert_eq!(loaded.accounts_metadata.deriving_key.as_ref().unwrap().to_bytes(), deriving_key.to_bytes());
+
+        assert_eq!(Wallet::from_xprv(String::from("password"), "xprv123").err().unwrap(), "Not a valid xprv");
+    }
+
+    #[test]
+    fn test_from_exported_account() {
+        let mut wallet = fixtures::test_wallet();
+        wallet.add_account();
+        wallet.accounts_metadata.address_book.add("Alice", "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").unwrap();
+        wallet.accounts_metadata.address_book.add("Bob", "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359").unwrap();
+        let deriving_key = wallet.accounts_metadata.deriving_key.clone().unwrap();
+        let account = wallet.accounts_metadata.get_account(1).clone();
+        let secret = account.secret_key(&deriving_key);
+
+        let book = wallet.accounts_metadata.address_book.subset(&[1]);
+        let mut exported = Wallet::from_exported_account(String::from("their password"), &secret, Some(String::from("Savings")), book);
+        let file = fixtures::temp_wallet_file("exported");
+        let path = file.to_str().unwrap();
+        exported.set_location("exported", String::from(path));
+        exported.store().unwrap();
+        let mut loaded = Wallet::load(path).unwrap();
+        remove_wallet_file(&file);
+
+        assert_eq!(loaded.verify_password(String::from("password")), Err(UnlockError::IncorrectPassword));
+        loaded.verify_password(String::from("their password")).unwrap();
+        assert_eq!(loaded.source, KeySource::ExportedAccount);
+        assert!(loaded.addresses().is_empty());
+        assert!(!loaded.accounts_metadata.derives_accounts());
+        assert!(loaded.accounts_metadata.backup.is_none());
+        assert!(loaded.shamir_shares("their password", 2, 3, "").is_err());
+        assert_eq!(loaded.accounts_metadata.address_book.contacts().iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), ["Bob"]);
+
+        let deriving_key = loaded.accounts_metadata.deriving_key.clone().unwrap();
+        let only = loaded.accounts_metadata.default_account();
+        assert_eq!(only.address, account.address);
+        assert_eq!(only.label.as_deref(), Some("Savings"));
+        assert_eq!(*only.secret_key(&deriving_key), *secret);
+    }
+
+    #[test]
+    fn test_watch_only() {
+        let mnemonic = fixtures::fixture_mnemonic();
+        let seed = Seed::new(&mnemonic, "");
+        let (_, account_key) = utils::create_keys_from_path(seed.as_bytes(), ACCOUNT_KEY_PATH);
+        let xpub = account_key.to_string(Prefix::XPUB);
+
+        let mut watching = Wallet::watch_only(String::from("password"), &xpub).unwrap();
+        watching.add_account();
+        let mut from_seed = Wallet::from(String::from("password"), mnemonic, "");
+        from_seed.add_account();
+        assert_eq!(watching.addresses(), from_seed.addresses());
+        assert_eq!(watching.accounts_metadata.get_account(1).path, "m/44'/60'/0'/0/1");
+        assert!(watching.accounts_metadata.accounts.iter().all(|a| a.watch_only));
+        let rpc = MockRpc::new(networks::active().chain_id);
+        assert_eq!(watching.send(&rpc, 0, "0x70997970c51812dc3a010c7d01b50e0d17dc79c8", 1).unwrap_err(), "A watch-only wallet can't send");
+
+        let stored = String::from_utf8(watching.to_bytes()).unwrap();
+        assert!(!stored.contains("\"password\""));
+        let mut loaded: Wallet = serde_json::from_str(&stored).unwrap();
+        assert_eq!(loaded.source, KeySource::WatchOnly);
+        assert_eq!(loaded.verify_password(String::from("wrong password")), Err(UnlockError::IncorrectPassword));
+        loaded.verify_password(String::from("password")).unwrap();
+        assert!(loaded.accounts_metadata.deriving_key.is_none());
+        assert!(loaded.unlock_secret("password").is_err());
+        assert_eq!(loaded.add_account(), from_seed.add_account());
+
+        let (_, deriving_xpub) = utils::create_keys_from_path(seed.as_bytes(), &PathTemplate::standard().root());
+        assert_eq!(Wallet::watch_only(String::from("password"), &deriving_xpub.to_string(Prefix::XPUB)).err().unwrap(),
+            "Expected the account-level xpub at m/44'/60'/0', not a key at depth 4");
+        assert_eq!(Wallet::watch_only(String::from("password"), "xpub123").err().unwrap(), "Not a valid xpub");
+    }
+
+    #[test]
+    fn test_passphrase_hash() {
+        let mut hash = PassphraseHash::new("passphrase");
+        assert!(!hash.verify("Passphrase"));
+        assert!(hash.verify("passphrase"));
+        // the check runs the password KDF, whose parameters are stored with it
+        let json = serde_json::to_string(&hash).unwrap();
+        assert!(json.contains(r#""kdf":"argon2id""#), "{}", json);
+
+        // a keccak256 hash from before version 11 still verifies, and is sealed once it does
+        let salt = [9u8; 32];
+        let legacy = serde_json::json!({ "salt": salt.to_vec(), "hash": salted_hash(&salt, "passphrase").to_vec() }).to_string();
+        let mut hash: PassphraseHash = serde_json::from_str(&legacy).unwrap();
+        assert!(!hash.verify("wrong"));
+        assert!(matches!(hash, PassphraseHash::Legacy { .. }));
+        assert!(hash.verify("passphrase"));
+        assert!(matches!(hash, PassphraseHash::Sealed(_)));
+        assert!(hash.verify("passphrase"));
+    }
+
+    #[test]
+    fn test_elevation_expires() {
+        let mut wallet = fixtures::test_wallet();
+        wallet.add_account();
+        let metadata = &mut wallet.accounts_metadata;
+        metadata.accounts[1].protected = true;
+        assert!(!metadata.is_visible(1));
+        assert_eq!(metadata.visible_or_first(1), 0);
+
+        let now = utils::now();
+        (metadata.elevated, metadata.elevated_until) = (true, now + 60);
+        assert!(metadata.is_visible(1));
+        assert!(!metadata.expire_elevation(now));
+        // hidden again once the time runs out
+        metadata.elevated_until = now;
+        assert!(!metadata.is_visible(1));
+        assert!(metadata.expire_elevation(now));
+        assert!(!metadata.elevated);
+        assert!(!metadata.expire_elevation(now));
+    }
+
+    #[test]
+    fn test_salts_are_per_wallet() {
+        let mut first = fixtures::test_wallet();
+        let second = fixtures::test_wallet();
+        let kdf = |wallet: &Wallet| wallet.sealed_secret.as_ref().unwrap().kdf().clone();
+        assert_ne!(kdf(&first), kdf(&second));
+
+        // and a changed password gets a new one, even if it is the same password
+        let file = fixtures::temp_wallet_file("salts");
+        first.set_location("salts", String::from(file.to_str().unwrap()));
+        let before = kdf(&first);
+        first.change_password("password", "password").unwrap();
+        remove_wallet_file(&file);
+        assert_ne!(kdf(&first), before);
+    }
+
+    #[test]
+    fn test_unlock_with_key() {
+        let mut wallet = fixtures::test_wallet();
+        let file = fixtures::temp_wallet_file("key");
+        let path = file.to_str().unwrap();
+        wallet.set_location("key", String::from(path));
+        wallet.store().unwrap();
+        let key = wallet.password_key.as_ref().unwrap().to_bytes();
+
+        // the key kept in a keychain opens the file without the password
+        let mut stored = Wallet::load(path).unwrap();
+        assert_eq!(stored.unlock_with_key(Zeroizing::new([7; 32])), Err(UnlockError::IncorrectPassword));
+        stored.unlock_with_key(key.clone()).unwrap();
+        assert_eq!(stored.addresses(), wallet.addresses());
+        assert!(stored.accounts_metadata.deriving_key.is_some());
+
+        // and no longer once the password has changed
+        wallet.change_password("password", "new password").unwrap();
+        let mut stored = Wallet::load(path).unwrap();
+        remove_wallet_file(&file);
+        assert_eq!(stored.unlock_with_key(key), Err(UnlockError::IncorrectPassword));
+    }
+
+    #[test]
+    fn test_change_password() {
+        let mnemonic = fixtures::fixture_mnemonic();
+        let mut wallet = Wallet::from(String::from("old password"), mnemonic, "");
+        let file = fixtures::temp_wallet_file("password");
+        wallet.set_location("test", String::from(file.to_str().unwrap()));
+
+        assert_eq!(wallet.change_password("old password", "").unwrap_err(), "The new password can't be empty");
+        assert_eq!(wallet.change_password("wrong password", "new password").unwrap_err(), "Incorrect password");
+        wallet.change_password("old password", "new password").unwrap();
+        // still unlocked with the same keys
+        assert!(wallet.accounts_metadata.deriving_key.is_some());
+
+        let mut loaded = Wallet::load(file.to_str().unwrap()).unwrap();
+        remove_wallet_file(&file);
+        assert_eq!(loaded.verify_password(String::from("old password")), Err(UnlockError::IncorrectPassword));
+        loaded.verify_password(String::from("new password")).unwrap();
+        assert_eq!(loaded.addresses(), wallet.addresses());
+        let seed = loaded.unlock_secret("new password").unwrap();
+        assert_eq!(loaded.accounts_metadata.recovery_phrase(&seed, loaded.language).unwrap().as_str(), fixtures::FIXTURE_MNEMONIC);
+
+        // a failed write keeps the old password
+        loaded.set_location("test", String::from("/nonexistent/wallet.txt"));
+        assert!(loaded.change_password("new password", "newer password").is_err());
+        loaded.verify_password(String::from("new password")).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_verification_data() {
+        let wallet = fixtures::test_wallet();
+        let seed = wallet.unlock_secret("password").unwrap();
+        let pad = utils::xor(&seed, &keccak512(b"password")).unwrap();
+        let key = &wallet.verification_key;
+        assert_eq!(*unlock_secret(KeySource::Seed, None, &pad, key, "password").unwrap(), *seed);
+        assert_eq!(unlock_secret(KeySource::Seed, None, &pad, key, "wrong").unwrap_err(), UnlockError::IncorrectPassword);
+
+        // no password matches a key or pad of the wrong length, so the file is reported as damaged
+        assert_eq!(unlock_secret(KeySource::Seed, None, &pad, &key[..20], "password").unwrap_err(),
+            UnlockError::Corrupt(String::from("the verification key is 20 bytes, not 33")));
+        assert_eq!(unlock_secret(KeySource::Seed, None, &[pad.clone(), vec![0]].concat(), key, "password").unwrap_err(),
+            UnlockError::Corrupt(String::from("the pad is 65 bytes, not 1 to 64")));
+        assert_eq!(unlock_secret(KeySource::ExtendedKey, None, &[], key, "password").unwrap_err(),
+            UnlockError::Corrupt(String::from("the pad is 0 bytes, not 1 to 64")));
+        assert_eq!(watch_password_matches(None, &[1; 32], &[2; 31], "password").unwrap_err(),
+            UnlockError::Corrupt(String::from("the verification key is 31 bytes, not 32")));
+        assert_eq!(watch_password_matches(None, &[1; 32], &salted_hash(&[1; 32], "password"), "password"), Ok(()));
+    }
+
+    #[test]
+    fn test_upgrade_encryption() {
+        let mut wallet = fixtures::test_wallet();
+        let seed = wallet.unlock_secret("password").unwrap();
+        // a file from before the seed was sealed, with the seed xored with keccak512(password)
+        let mut json: Value = serde_json::from_slice(&wallet.to_bytes()).unwrap();
+        json.as_object_mut().unwrap().remove("sealed_secret");
+        json["pad"] = serde_json::json!(utils::xor(&seed, &keccak512(b"password")).unwrap());
+        let file = fixtures::temp_wallet_file("upgrade");
+        std::fs::write(&file, json.to_string()).unwrap();
+
+        let mut old = Wallet::load(file.to_str().unwrap()).unwrap();
+        assert_eq!(old.verify_password(String::from("wrong password")), Err(UnlockError::IncorrectPassword));
+        old.verify_password(String::from("password")).unwrap();
+        old.upgrade_encryption("password").unwrap();
+        assert!(old.accounts_metadata.deriving_key.is_some());
+        assert!(!old.is_encrypted());
+
+        let mut upgraded = Wallet::load(file.to_str().unwrap()).unwrap();
+        remove_wallet_file(&file);
+        assert!(upgraded.pad.is_empty());
+        assert!(upgraded.sealed_secret.is_some());
+        assert_eq!(upgraded.verify_password(String::from("wrong password")), Err(UnlockError::IncorrectPassword));
+        upgraded.verify_password(String::from("password")).unwrap();
+        assert_eq!(upgraded.unlock_secret("password").unwrap(), seed);
+        assert_eq!(upgraded.addresses(), wallet.addresses());
+
+        // a sealed secret that was tampered with doesn't open
+        let mut json: Value = serde_json::from_slice(&upgraded.to_bytes()).unwrap();
+        let ciphertext = json["sealed_secret"]["ciphertext"].as_str().unwrap();
+        let flipped = format!("{}{}", if ciphertext.starts_with('0') { '1' } else { '0' }, &ciphertext[1..]);
+        json["sealed_secret"]["ciphertext"] = Value::String(flipped);
+        let mut tampered: Wallet = serde_json::from_value(json).unwrap();
+        assert_eq!(tampered.verify_password(String::from("password")), Err(UnlockError::IncorrectPassword));
+    }
+
+    #[test]
+    fn test_failed_logins() {
+        assert_eq!(login_delay(0).as_secs(), 0);
+        assert_eq!(login_delay(2).as_secs(), 0);
+        assert_eq!(login_delay(3).as_secs(), 1);
+        assert_eq!(login_delay(5).as_secs(), 4);
+        assert_eq!(login_delay(12).as_secs(), MAX_LOGIN_DELAY_SECS);
+        assert_eq!(login_delay(u32::MAX).as_secs(), MAX_LOGIN_DELAY_SECS);
+
+        let mut wallet = fixtures::test_wallet();
+        let file = fixtures::temp_wallet_file("logins");
+        wallet.set_location("logins", String::from(file.to_str().unwrap()));
+        wallet.store().unwrap();
+
+        let mut locked = Wallet::load(file.to_str().unwrap()).unwrap();
+        assert_eq!(locked.login(String::from("wrong")), Err(UnlockError::IncorrectPassword));
+        assert_eq!(locked.login(String::from("wrong again")), Err(UnlockError::IncorrectPassword));
+        // a restart picks up the count, and the rest of the file is untouched
+        let mut restarted = Wallet::load(file.to_str().unwrap()).unwrap();
+        assert_eq!(restarted.failed_logins, 2);
+        restarted.login(String::from("password")).unwrap();
+        assert_eq!(restarted.name, "logins");
+        assert_eq!(restarted.sealed_secret, wallet.sealed_secret);
+        assert_eq!(Wallet::load(file.to_str().unwrap()).unwrap().failed_logins, 0);
+
+        // a plain file keeps amounts too large for a u64
+        wallet.encrypt = false;
+        wallet.accounts_metadata.get_account(0).scheduled.push(ScheduledTransaction {
+            recipient: String::from("0x70997970c51812dc3a010c7d01b50e0d17dc79c8"), wei_amount: u128::MAX, send_at: 100, presigned: None });
+        wallet.store().unwrap();
+        let mut locked = Wallet::load(file.to_str().unwrap()).unwrap();
+        assert_eq!(locked.login(String::from("wrong")), Err(UnlockError::IncorrectPassword));
+        let mut restarted = Wallet::load(file.to_str().unwrap()).unwrap();
+        assert_eq!(restarted.failed_logins, 1);
+        restarted.login(String::from("password")).unwrap();
+        assert_eq!(restarted.accounts_metadata.get_account(0).scheduled[0].wei_amount, u128::MAX);
+        remove_wallet_file(&file);
+    }
+
+    #[test]
+    fn test_modified_file() {
+        let mut wallet = fixtures::test_wallet();
+        wallet.add_account();
+        // the MAC matters for plain JSON files, since an encrypted one can't be edited unseen
+        wallet.encrypt = false;
+        let file = fixtures::temp_wallet_file("modified");
+        let path = file.to_str().unwrap();
+        wallet.set_location("modified", String::from(path));
+        wallet.store().unwrap();
+        let data = std::fs::read(&file).unwrap();
+        let detected = |data: &[u8]| {
+            std::fs::write(&file, data).unwrap();
+            Wallet::load(path).map_or(true, |mut wallet| wallet.verify_password(String::from("password")).is_err())
+        };
+        assert!(!detected(&data));
+
+        // an account address swapped for another, the verification key changed, or the MAC removed
+        let text = String::from_utf8(data.clone()).unwrap();
+        let addresses = wallet.addresses();
+        assert!(detected(text.replacen(&addresses[1], &addresses[0], 1).as_bytes()));
+        let mut json: Value = serde_json::from_str(&text).unwrap();
+        json["verification_key"][5] = Value::from(json["verification_key"][5].as_u64().unwrap() ^ 1);
+        assert!(detected(&serde_json::to_vec(&json).unwrap()));
+        let mut json: Value = serde_json::from_str(&text).unwrap();
+        json.as_object_mut().unwrap().remove("mac");
+        assert!(detected(&serde_json::to_vec(&json).unwrap()));
+        let mut modified = Wallet::load(path).unwrap();
+        assert_eq!(modified.login(String::from("password")), Err(UnlockError::Modified));
+        // a modified file isn't counted as a wrong guess
+        assert_eq!(Wallet::load(path).unwrap().failed_logins, 0);
+
+        // single bytes flipped across the file, unless the file still reads the same, as when the
+        // name of a field left at its default is changed
+        let reads_as = |data: &[u8]| serde_json::from_slice::<Wallet>(data).ok().map(|wallet| serde_json::to_vec(&wallet).unwrap());
+        for index in (0..data.len()).step_by(data.len() / 32) {
+            let mut flipped = data.clone();
+            flipped[index] ^= 0x01;
+            assert!(detected(&flipped) || reads_as(&flipped) == reads_as(&data), "byte {} flipped", index);
+        }
+
+        // failed logins change without the password, so they aren't covered
+        json = serde_json::from_str(&text).unwrap();
+        json["failed_logins"] = Value::from(2);
+        assert!(!detected(&serde_json::to_vec(&json).unwrap()));
+        remove_wallet_file(&file);
+    }
+
+    #[test]
+    fn test_backup_file() {
+        let mut wallet = fixtures::test_wallet();
+        let file = fixtures::temp_wallet_file("backup");
+        let path = file.to_str().unwrap();
+        let backup = backup_path(path);
+        wallet.set_location("backup", String::from(path));
+        wallet.store().unwrap();
+        // nothing to back up the first time
+        assert!(!std::path::Path::new(&backup).exists());
+        wallet.verify_password(String::from("password")).unwrap();
+        wallet.store().unwrap();
+        assert_eq!(std::fs::read(&backup).unwrap().len(), std::fs::read(&file).unwrap().len());
+
+        // a primary cut short by a crash is reported, and the backup can be put back in its place
+        let data = std::fs::read(&file).unwrap();
+        std::fs::write(&file, &data[..data.len() / 2]).unwrap();
+        assert!(Wallet::load(path).err().unwrap().is_damaged());
+        assert!(Wallet::load_previous(path).is_ok());
+        let moved = restore_previous(path).unwrap();
+        assert_eq!(std::fs::read(&moved).unwrap(), &data[..data.len() / 2]);
+        let mut loaded = Wallet::load(path).unwrap();
+        assert_eq!(loaded.file, path);
+        loaded.verify_password(String::from("password")).unwrap();
+        assert_eq!(loaded.addresses(), wallet.addresses());
+        std::fs::remove_file(&moved).unwrap();
+
+        std::fs::write(&backup, b"").unwrap();
+        assert_eq!(Wallet::load_previous(path).err().unwrap(), LoadError::Empty(backup.clone()));
+        remove_wallet_file(&file);
+        // with nothing to restore, the damaged file stays where it was
+        assert!(restore_previous(path).is_err());
+    }
+
+    #[test]
+    fn test_damaged_files() {
+        let file = fixtures::temp_wallet_file("damaged");
+        let path = file.to_str().unwrap();
+        let json = fixtures::generate_json(&Default::default());
+        let load = |data: &[u8]| {
+            std::fs::write(&file, data).unwrap();
+            Wallet::load(path).err()
+        };
+        assert_eq!(load(json.as_bytes()), None);
+
+        assert_eq!(load(b""), Some(LoadError::Empty(String::from(path))));
+        assert_eq!(load(b" \n"), Some(LoadError::Empty(String::from(path))));
+        assert!(matches!(load(&json.as_bytes()[..json.len() - 10]), Some(LoadError::Truncated(..))));
+
+        let mangle = |f: &dyn Fn(&mut Value)| {
+            let mut value: Value = serde_json::from_str(&json).unwrap();
+            f(&mut value);
+            load(&serde_json::to_vec(&value).unwrap())
+        };
+        assert_eq!(mangle(&|v| { v.as_object_mut().unwrap().remove("verification_key"); }),
+            Some(LoadError::MissingField(String::from(path), String::from("verification_key"))));
+        assert!(matches!(mangle(&|v| v["pad"] = Value::from("not bytes")), Some(LoadError::Malformed(..))));
+        assert_eq!(mangle(&|v| v["mac"] = Value::from("abcd")).unwrap().to_string(),
+            format!("{} has a damaged MAC: it is \"abcd\", not 64 hex digits", path));
+        assert!(matches!(mangle(&|v| v["sealed_secret"]["nonce"] = Value::from("00ff")), Some(LoadError::BadField(..))));
+        assert!(matches!(mangle(&|v| v["sealed_secret"]["ciphertext"] = Value::from("xyz")), Some(LoadError::BadField(..))));
+        // a file from a newer app isn't damaged
+        assert!(!mangle(&|v| v["version"] = Value::from(99)).unwrap().is_damaged());
+
+        let moved = quarantine(path).unwrap();
+        assert!(!file.exists());
+        assert!(moved.starts_with(&format!("{}.damaged-", path)));
+        std::fs::remove_file(&moved).unwrap();
+        assert!(Wallet::load(path).err().unwrap().to_string().starts_with("Error reading"));
+    }
+
+    #[test]
+    fn test_back_up() {
+        let mut wallet = fixtures::generate(&fixtures::FixtureOptions::default());
+        let dir = std::env::temp_dir().join(format!("basic-eth-back-up-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("restored.txt");
+        let path = file.to_str().unwrap();
+        let backup_file = wallet.back_up(dir.to_str().unwrap()).unwrap();
+        // still unlocked, and the backup is encrypted
+        assert!(wallet.accounts_metadata.deriving_key.is_some());
+        assert!(backup_file.file_name().unwrap().to_str().unwrap().starts_with("fixture-"));
+        assert!(!String::from_utf8_lossy(&std::fs::read(&backup_file).unwrap()).contains("Fixture account"));
+
+        let mut backup = WalletBackup::read(backup_file.to_str().unwrap()).unwrap();
+        assert_eq!(backup.name, "fixture");
+        std::fs::write(&file, b"the live wallet").unwrap();
+        assert!(WalletBackup::read(backup_file.to_str().unwrap()).unwrap().restore("restored", String::from(path)).is_err());
+        assert_eq!(backup.verify_password(String::from("wrong")), Err(UnlockError::IncorrectPassword));
+        assert_eq!(std::fs::read(&file).unwrap(), b"the live wallet");
+        backup.verify_password(String::from(fixtures::FIXTURE_PASSWORD)).unwrap();
+        let restored = backup.restore("restored", String::from(path)).unwrap();
+        assert!(restored.accounts_metadata.deriving_key.is_some());
+
+        // labels and nonces come back with it
+        let mut loaded = Wallet::load(path).unwrap();
+        loaded.verify_password(String::from(fixtures::FIXTURE_PASSWORD)).unwrap();
+        assert_eq!(loaded.name, "restored");
+        for (loaded, account) in loaded.accounts_metadata.accounts.iter().zip(&wallet.accounts_metadata.accounts) {
+            assert_eq!((&loaded.label, loaded.nonce), (&account.label, account.nonce));
+        }
+        assert_eq!(std::fs::read(backup_path(path)).unwrap(), b"the live wallet");
+
+        // a damaged backup is refused before its password is asked for
+        let mut damaged = std::fs::read(&backup_file).unwrap();
+        damaged.truncate(damaged.len() - 1);
+        std::fs::write(&backup_file, &damaged).unwrap();
+        assert!(WalletBackup::read(backup_file.to_str().unwrap()).err().unwrap().ends_with("it doesn't match its checksum, so it was damaged or cut short"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_version() {
+        let file = fixtures::temp_wallet_file("version");
+        let path = file.to_str().unwrap();
+        for version in fixtures::FIXTURE_VERSIONS {
+            let options = fixtures::FixtureOptions { version, ..Default::default() };
+            let fixture = fixtures::generate(&options);
+            // written as that version of the app wrote it
+            std::fs::write(&file, fixtures::generate_json(&options)).unwrap();
+            assert_eq!(migrations::version(&std::fs::read(&file).unwrap()), Some(version));
+
+            let mut loaded = Wallet::load(path).unwrap();
+            assert_eq!(loaded.version, version);
+            // the MAC of an old file still matches
+            loaded.verify_password(String::from(fixtures::FIXTURE_PASSWORD)).unwrap();
+            loaded.upgrade_version().unwrap();
+            assert_eq!(loaded.version, migrations::CURRENT_VERSION);
+            assert!(loaded.accounts_metadata.deriving_key.is_some());
+            assert_eq!(migrations::version(&std::fs::read(&file).unwrap()), Some(migrations::CURRENT_VERSION));
+
+            let mut reloaded = Wallet::load(path).unwrap();
+            reloaded.verify_password(String::from(fixtures::FIXTURE_PASSWORD)).unwrap();
+            assert_eq!(reloaded.addresses(), fixture.addresses());
+            let (metadata, expected) = (&mut reloaded.accounts_metadata, &fixture.accounts_metadata);
+            assert_eq!(metadata.accounts[0].history, expected.accounts[0].history);
+            assert_eq!(metadata.address_book, expected.address_book);
+            // a legacy passphrase hash still checks out, and is sealed once it has
+            let protection = metadata.protection.as_mut().unwrap();
+            assert!(protection.verify(fixtures::FIXTURE_PASSPHRASE));
+            assert!(matches!(protection, PassphraseHash::Sealed(_)));
+        }
+
+        let mut reloaded = Wallet::load(path).unwrap();
+        reloaded.verify_password(String::from(fixtures::FIXTURE_PASSWORD)).unwrap();
+        // a newer file asks for a newer app, plain or encrypted
+        let newer = migrations::CURRENT_VERSION + 1;
+        let mut value: Value = serde_json::from_slice(&std::fs::read(&file).unwrap()).unwrap();
+        value["version"] = Value::from(newer);
+        std::fs::write(&file, serde_json::to_vec(&value).unwrap()).unwrap();
+        assert_eq!(Wallet::parse_file(path).err().unwrap(), LoadError::Version(VersionError::TooNew(newer)));
+        reloaded.version = newer;
+        reloaded.encrypt = true;
+        reloaded.store().unwrap();
+        let mut encrypted = Wallet::load(path).unwrap();
+        assert_eq!(encrypted.verify_password(String::from(fixtures::FIXTURE_PASSWORD)), Err(UnlockError::Version(VersionError::TooNew(newer))));
+        remove_wallet_file(&file);
+    }
+
+    /// Serializes a wallet as JSON, and again after it has been through SSZ
+    fn json_via_ssz(wallet: &mut Wallet) -> (Vec<u8>, Vec<u8>) {
+        let json = wallet.to_bytes();
+        let ssz = ssz_format::encode(&Wallet::parse(&json).unwrap());
+        let mut decoded = Wallet::parse(&ssz).unwrap();
+        assert_eq!(ssz_format::encode(&decoded), ssz);
+        let round_trip = decoded.to_bytes();
+        (json, round_trip)
+    }
+
+    #[test]
+    fn test_ssz_round_trip() {
+        for (seed, accounts) in [(0, 1), (1, 3), (2, 8)] {
+            let mut wallet = fixtures::generate(&fixtures::FixtureOptions { seed, accounts, ..Default::default() });
+            let metadata = &mut wallet.accounts_metadata;
+            let mut external = metadata.accounts[0].clone();
+            external.label = Some(String::new());
+            external.watch_only = true;
+            external.imported_key = Some(SealedBox::seal(b"context", b"secret", b"key"));
+            external.balance_cache = Some(CachedBalance { stale: true, ..CachedBalance::new(42, 3, 4) });
+            metadata.external.push(external);
+            metadata.accounts[0].balance_cache = Some(CachedBalance::new(42, 3, 4));
+            metadata.accounts[0].record_sent("0xabcd", [0x11; 20], 5, 7, None);
+            // a send changes the balance, so the cached one is dropped
+            assert_eq!(metadata.accounts[0].balance_cache, None);
+            metadata.address_book.add("Exchange", "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").unwrap();
+            metadata.gas.strategy = GasPriceStrategy::Fixed(3_000_000_000);
+            metadata.pending.push(String::from("0x1234"));
+            metadata.watch_key = Some(String::from("xpub"));
+            let (json, round_trip) = json_via_ssz(&mut wallet);
+            assert_eq!(String::from_utf8(round_trip).unwrap(), String::from_utf8(json).unwrap(), "seed {}", seed);
+        }
+
+        let mnemonic = fixtures::fixture_mnemonic();
+        let seed = Seed::new(&mnemonic, "");
+        let (deriving_key, _) = utils::create_keys_from_path(seed.as_bytes(), &PathTemplate::standard().root());
+        let (_, account_key) = utils::create_keys_from_path(seed.as_bytes(), ACCOUNT_KEY_PATH);
+        let mut restored = Wallet::from_xprv(String::from("password"), &deriving_key.to_string(Prefix::XPRV)).unwrap();
+        let mut watching = Wallet::watch_only(String::from("password"), &account_key.to_string(Prefix::XPUB)).unwrap();
+        // nothing optional set, and a file from before versions were recorded
+        watching.created_at = None;
+        watching.network = None;
+        watching.version = migrations::UNVERSIONED;
+        for wallet in [&mut restored, &mut watching] {
+            let (json, round_trip) = json_via_ssz(wallet);
+            assert_eq!(round_trip, json);
+        }
+        assert!(Wallet::parse(&ssz_format::with_magic(vec![2, 0, 0, 0])).is_err());
+    }
+
+    #[test]
+    fn test_ssz_file() {
+        let mut wallet = fixtures::test_wallet();
+        let file = fixtures::temp_wallet_file("ssz");
+        let path = file.to_str().unwrap();
+        wallet.set_location("ssz", String::from(path));
+        wallet.encrypt = false;
+        wallet.store_as(StorageFormat::Ssz).unwrap();
+        let data = std::fs::read(&file).unwrap();
+        assert!(ssz_format::strip_magic(&data).is_some());
+        assert_eq!(migrations::version(&data), Some(migrations::CURRENT_VERSION));
+
+        // it is detected on load, and checked against the same MAC as JSON
+        let mut loaded = Wallet::load(path).unwrap();
+        assert_eq!(loaded.login(String::from("wrong")), Err(UnlockError::IncorrectPassword));
+        let mut loaded = Wallet::load(path).unwrap();
+        assert_eq!(loaded.failed_logins, 1);
+        loaded.verify_password(String::from("password")).unwrap();
+        assert_eq!(loaded.addresses(), wallet.addresses());
+
+        // inside an encrypted file too, and a JSON setting writes JSON again
+        loaded.encrypt = true;
+        loaded.store_as(StorageFormat::Ssz).unwrap();
+        let mut encrypted = Wallet::load(path).unwrap();
+        encrypted.verify_password(String::from("password")).unwrap();
+        assert_eq!(encrypted.addresses(), wallet.addresses());
+        encrypted.encrypt = false;
+        encrypted.store_as(StorageFormat::Json).unwrap();
+        assert!(serde_json::from_slice::<Value>(&std::fs::read(&file).unwrap()).is_ok());
+
+        // a newer version is refused before the rest is decoded
+        let mut newer = data.clone();
+        newer[ssz_format::MAGIC.len()] = 99;
+        std::fs::write(&file, &newer).unwrap();
+        assert_eq!(Wallet::parse_file(path).err().unwrap(), LoadError::Version(VersionError::TooNew(99)));
+        remove_wallet_file(&file);
+    }
+
+    #[test]
+    fn test_encrypted_file() {
+        let mut wallet = fixtures::test_wallet();
+        let file = fixtures::temp_wallet_file("encrypted");
+        let path = file.to_str().unwrap();
+        wallet.set_location("encrypted", String::from(path));
+        wallet.store().unwrap();
+
+        // nothing but the header is readable
+        let data = std::fs::read(&file).unwrap();
+        assert!(data.starts_with(FILE_MAGIC));
+        assert!(!String::from_utf8_lossy(&data).contains("encrypted"));
+        let mut stored = Wallet::load(path).unwrap();
+        assert!(stored.is_encrypted());
+        assert!(stored.store().is_err());
+        assert_eq!(stored.verify_password(String::from("wrong")), Err(UnlockError::IncorrectPassword));
+        stored.verify_password(String::from("password")).unwrap();
+        assert_eq!(stored.name, "encrypted");
+        assert_eq!(stored.addresses(), wallet.addresses());
+
+        // a damaged file is reported when it loads, not as a wrong password
+        let mut damaged = data.clone();
+        *damaged.last_mut().unwrap() ^= 1;
+        std::fs::write(&file, &damaged).unwrap();
+        assert!(Wallet::load(path).err().unwrap().to_string().ends_with("is corrupt: the contents don't match the checksum"));
+        let mut truncated = FILE_MAGIC.to_vec();
+        truncated.push(FILE_VERSION);
+        std::fs::write(&file, &truncated).unwrap();
+        assert!(Wallet::load(path).err().unwrap().to_string().ends_with("is corrupt: the header is truncated"));
+
+        // a plain JSON file loads as it is, and is encrypted once the user agrees
+        std::fs::write(&file, wallet.to_bytes()).unwrap();
+        let mut plain = Wallet::load(path).unwrap();
+        assert!(!plain.is_encrypted());
+        assert_eq!(plain.name, "encrypted");
+        plain.verify_password(String::from("password")).unwrap();
+        plain.encrypt_file().unwrap();
+        assert!(std::fs::read(&file).unwrap().starts_with(FILE_MAGIC));
+        Wallet::load(path).unwrap().verify_password(String::from("password")).unwrap();
+        remove_wallet_file(&file);
+    }
+
+    #[test]
+    fn test_secrets_zeroize_on_drop() {
+        fn wiped_on_drop<T: zeroize::ZeroizeOnDrop>(_: &T) {}
+
+        let mut wallet = fixtures::test_wallet();
+        let deriving_key = wallet.accounts_metadata.deriving_key.clone().unwrap();
+        wiped_on_drop(&wallet.accounts_metadata.get_account(0).secret_key(&deriving_key));
+        wiped_on_drop(&wallet.unlock_secret("password").unwrap());
+        wiped_on_drop(&password_hash("password"));
+        wiped_on_drop(&key_bytes(&deriving_key));
+
+        // the deriving key is never written out
+        let stored = String::from_utf8(wallet.to_bytes()).unwrap();
+        assert!(!stored.contains(deriving_key.to_string(Prefix::XPRV).as_str()));
+    }
+
+    #[test]
+    fn test_save_accounts() {
+        let mut wallet = fixtures::test_wallet();
+        let file = fixtures::temp_wallet_file("save");
+        let path = file.to_str().unwrap();
+        wallet.set_location("save", String::from(path));
+
+        // the menus save the accounts they hold without locking the wallet
+        let mut metadata = std::mem::take(&mut wallet.accounts_metadata);
+        metadata.create_account(1);
+        metadata.get_account(0).nonce = 3;
+        wallet.save_accounts(&mut metadata);
+        assert!(metadata.deriving_key.is_some());
+        assert!(wallet.accounts_metadata.accounts.is_empty());
+        wallet.accounts_metadata = metadata;
+
+        let mut saved = Wallet::load(path).unwrap();
+        saved.verify_password(String::from("password")).unwrap();
+        assert_eq!(saved.addresses(), wallet.addresses());
+        assert_eq!(saved.nonce(0), Some(3));
+
+        // locking stores the wallet and scrubs the deriving key
+        wallet.add_account();
+        wallet.lock().unwrap();
+        assert!(wallet.accounts_metadata.deriving_key.is_none());
+        let mut saved = Wallet::load(path).unwrap();
+        remove_wallet_file(&file);
+        saved.verify_password(String::from("password")).unwrap();
+        assert_eq!(saved.addresses().len(), 3);
+    }
+
+    #[test]
+    fn test_watched_address_survives_store() {
+        let mut wallet = fixtures::test_wallet();
+        let address = watch::parse_watch_address("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed", &wallet.addresses()).unwrap();
+        wallet.accounts_metadata.external.push(Account::watch(address, Some(String::from("Exchange"))));
+
+        let loaded: Wallet = serde_json::from_slice(&wallet.to_bytes()).unwrap();
+        let watched = &loaded.accounts_metadata.external[0];
+        assert!(watched.watch_only);
+        assert_eq!(watched.address, "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed");
+        assert_eq!(watched.label.as_deref(), Some("Exchange"));
+        assert!(watched.path.is_empty() && watched.imported_key.is_none());
+    }
+
+    #[test]
+    fn test_address_book_survives_store() {
+        let mut wallet = fixtures::test_wallet();
+        let file = fixtures::temp_wallet_file("book");
+        let path = file.to_str().unwrap();
+        wallet.set_location("book", String::from(path));
+        wallet.accounts_metadata.address_book.add("Exchange", "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").unwrap();
+        wallet.store().unwrap();
+
+        let mut loaded = Wallet::load(path).unwrap();
+        remove_wallet_file(&file);
+        loaded.verify_password(String::from("password")).unwrap();
+        let contact = loaded.accounts_metadata.address_book.find("exchange").unwrap();
+        assert_eq!(contact.address, "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed");
+    }
+
+    #[test]
+    fn test_sync_nonce() {
+        let mut wallet = fixtures::test_wallet();
+        let rpc = MockRpc::new(networks::active().chain_id);
+        // two transactions sent from another machine
+        rpc.sent.borrow_mut().extend([String::from("0x01"), String::from("0x02")]);
+
+        let hash = wallet.send(&rpc, 0, "0x70997970c51812dc3a010c7d01b50e0d17dc79c8", 1000).unwrap();
+        assert_eq!(wallet.nonce(0), Some(3));
+        let account = wallet.accounts_metadata.get_account(0);
+        assert_eq!((account.history[0].hash.as_str(), account.history[0].nonce), (hash.as_str(), 2));
+
+        // offline, the wallet's own count is used
+        rpc.offline.set(true);
+        assert_eq!(account.sync_nonce(&rpc), Ok(()));
+        assert_eq!(account.nonce, 3);
+
+        // but nothing is signed for a node on another chain
+        let other_chain = MockRpc::new(networks::active().chain_id + 1);
+        assert!(account.sync_nonce(&other_chain).unwrap_err().starts_with("The node at"));
+        assert_eq!(account.nonce, 3);
+    }
+
+    #[test]
+    fn test_send_through_rpc() {
+        let mut wallet = fixtures::test_wallet();
+        let sender = wallet.addresses()[0].clone();
+        let recipient = "0x70997970c51812dc3a010c7d01b50e0d17dc79c8";
+        let rpc = MockRpc::new(networks::active().chain_id);
+
+        let hash = wallet.send(&rpc, 0, recipient, 1000).unwrap();
+        assert_eq!(rpc.sent.borrow().len(), 1);
+        assert_eq!(wallet.nonce(0), Some(1));
+        let account = wallet.accounts_metadata.get_account(0);
+        assert_eq!((account.history[0].hash.as_str(), account.history[0].nonce, account.history[0].gas_price), (hash.as_str(), 0, 2_000_000_000));
+
+        // nothing is sent to a node on another chain, or one that can't be reached
+        let other_chain = MockRpc::new(networks::active().chain_id + 1);
+        assert!(wallet.send(&other_chain, 0, recipient, 1000).unwrap_err().ends_with("so the transaction wasn't signed"));
+        assert!(other_chain.sent.borrow().is_empty());
+        rpc.offline.set(true);
+        assert!(wallet.send(&rpc, 0, recipient, 1000).is_err());
+        assert_eq!(wallet.nonce(0), Some(1));
+        rpc.offline.set(false);
+
+        // a due scheduled transfer goes out once the balance covers it and its fee
+        let deriving_key = wallet.accounts_metadata.deriving_key.clone().unwrap();
+        let account = wallet.accounts_metadata.get_account(0);
+        account.scheduled.push(ScheduledTransaction { recipient: String::from(recipient), wei_amount: 5, send_at: 100, presigned: None });
+        account.send_due_scheduled(&rpc, &deriving_key, 200, 0);
+        assert_eq!((account.scheduled.len(), account.nonce), (1, 1));
+        rpc.fund(&sender, WEI_PER_ETH);
+        account.send_due_scheduled(&rpc, &deriving_key, 200, 0);
+        assert_eq!((account.scheduled.len(), account.nonce), (0, 2));
+        assert_eq!(rpc.sent.borrow().len(), 2);
+        // the scheduled one was waited on until mined, and the one sent without prompts wasn't
+        assert_eq!(account.history.iter().map(|r| r.status).collect::<Vec<_>>(), [TransactionStatus::Pending, TransactionStatus::Confirmed]);
+        assert_eq!(history::refresh(&mut account.history, |hash| rpc.transaction_status(hash)), 1);
+
+        // the session's timer is set for the next one, or for a retry of one that is late
+        account.scheduled.push(ScheduledTransaction { recipient: String::from(recipient), wei_amount: 5, send_at: 300, presigned: None });
+        let metadata = &wallet.accounts_metadata;
+        assert_eq!(metadata.next_scheduled(0, 0), Some(300));
+        assert_eq!(metadata.next_scheduled(0, 310), Some(310 + SCHEDULED_RETRY_SECS));
+        // one scheduled before unlocking is left to the overdue prompt
+        assert_eq!(metadata.next_scheduled(400, 0), None);
+    }
+
+    #[test]
+    fn test_approve_staged_from_imported_key() {
+        let mut wallet = fixtures::test_wallet();
+        let deriving_key = wallet.accounts_metadata.deriving_key.clone().unwrap();
+        let metadata = &mut wallet.accounts_metadata;
+        let secret = utils::parse_private_key("4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318").unwrap();
+        metadata.add_imported_key(&deriving_key, &secret);
+        let imported = String::from("0x2c7536e3605d9c16a7a3d7b1898e529396a65c23");
+        let index = metadata.position_of(&imported);
+        assert_eq!(index, 1);
+
+        metadata.approval = Some(ApprovalMode::new("approver"));
+        let staged = StagedTransaction {
+            id: 0,
+            account: index,
+            from: imported.clone(),
+            recipient: String::from("0x3c44cdddb6a900fa2b585dd299e03d12fa4293bc"),
+            wei_amount: 1000,
+            fee: FeeStrategy::Fixed(2_000_000_000),
+            staged_at: 0,
+            expires_at: u64::MAX,
+        };
+        let id = metadata.approval.as_mut().unwrap().queue().unwrap().stage(staged);
+        // a derived account added since takes the index the send was staged with
+        let derived = metadata.create_account(1).address.clone();
+        assert_eq!(metadata.get_account(index).address, derived);
+
+        let rpc = MockRpc::new(networks::active().chain_id);
+        rpc.fund(&imported, WEI_PER_ETH);
+        let transaction = metadata.approval.as_mut().unwrap().queue().unwrap().get(id).unwrap().clone();
+        assert!(metadata.send_approved(&rpc, &deriving_key, &transaction));
+        assert!(metadata.approval.as_mut().unwrap().queue().unwrap().get(id).is_none());
+        let sender = metadata.get_account(metadata.position_of(&imported));
+        assert_eq!((sender.nonce, sender.history.len()), (1, 1));
+        assert!(metadata.accounts[1].history.is_empty());
+        assert_eq!(rpc.sent.borrow().len(), 1);
+
+        // nothing is signed for an account that left the wallet
+        let gone = StagedTransaction { from: String::from("0x90f79bf6eb2c4f870365e785982e1f101e93b906"), ..transaction };
+        assert!(!metadata.send_approved(&rpc, &deriving_key, &gone));
+        assert_eq!(rpc.sent.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_tokens_survive_store() {
+        let mut wallet = fixtures::test_wallet();
+        let file = fixtures::temp_wallet_file("tokens");
+        let path = file.to_str().unwrap();
+        wallet.set_location("tokens", String::from(path));
+        let token = Token { symbol: String::from("TKN"), address: String::from("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"), decimals: 6 };
+        wallet.accounts_metadata.tokens.add(11155111, "Token", token).unwrap();
+        wallet.accounts_metadata.collections.add(11155111, "Collection", "0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359").unwrap();
+        wallet.store().unwrap();
+
+        let mut loaded = Wallet::load(path).unwrap();
+        remove_wallet_file(&file);
+        loaded.verify_password(String::from("password")).unwrap();
+        assert_eq!(loaded.accounts_metadata.tokens, wallet.accounts_metadata.tokens);
+        assert_eq!(loaded.accounts_metadata.tokens.for_chain(11155111).next().unwrap().token.decimals, 6);
+        assert_eq!(loaded.accounts_metadata.collections, wallet.accounts_metadata.collections);
+        assert_eq!(loaded.accounts_metadata.collections.for_chain(11155111).next().unwrap().name, "Collection");
+    }
+
+    #[test]
+    fn test_is_token_listed() {
+        let mut wallet = fixtures::test_wallet();
+        let chain_id = networks::active().chain_id;
+        let token = Token { symbol: String::from("TKN"), address: String::from("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"), decimals: 6 };
+        wallet.accounts_metadata.tokens.add(chain_id, "Token", token).unwrap();
+
+        // however the address was typed
+        for entered in ["0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed", "5AAEB6053F3E94C9B9A09F33669435E7EF1BEAED"] {
+            let address = labels::normalize_address(entered).unwrap();
+            assert!(wallet.accounts_metadata.is_token_listed(&address));
+        }
+        assert!(!wallet.accounts_metadata.is_token_listed("0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359"));
+    }
+
+    #[test]
+    fn test_sign_token_transfer() {
+        let mut wallet = fixtures::test_wallet();
+        let deriving_key = wallet.accounts_metadata.deriving_key.clone().unwrap();
+        let account = wallet.accounts_metadata.get_account(0);
+        let token = [0xaa; 20];
+        let data = erc20::transfer_data(&[0x11; 20], 1_500_000);
+
+        // the call goes to the token with no ETH, and carries the transfer
+        for pricing in [GasPricing::Legacy(2_000_000_000), GasPricing::new(3_000_000_000, Some(FeeMarket { base_fee: 1_000_000_000, priority_fee: 1_000_000_000 }))] {
+            let signed = account.sign_call(&deriving_key, token, 0, data.clone(), pricing, 60_000);
+            assert!(signed.contains(&hex::encode(token)) && signed.contains(&hex::encode(&data)));
+            assert_ne!(signed, account.sign_transfer(&deriving_key, token, 0, pricing, 60_000));
+        }
+    }
+
+    #[test]
+    fn test_describe_shortfall() {
+        let eth = 1_000_000_000_000_000_000;
+        let usdc = |held| TokenSend { symbol: String::from("USDC"), held };
+        assert_eq!(describe_shortfall(eth / 1000, eth / 100, eth / 100 - eth / 1000, Some(&usdc(Ok(5_000_000)))),
+            "You have enough USDC but not enough ETH for gas (need ~0.01 ETH, have 0.001 ETH)");
+        assert_eq!(describe_shortfall(0, eth / 100, eth / 100, Some(&usdc(Err(String::from("execution reverted"))))),
+            "Not enough ETH for the gas to send USDC (need ~0.01 ETH, have 0 ETH)");
+        assert_eq!(describe_shortfall(eth, 2 * eth, eth, None), "Not enough ETH for this transfer and its gas fee (need ~2 ETH, have 1 ETH, short 1 ETH)");
+
+        assert_eq!(describe_gas("Mainnet", "ETH", eth / 100, eth / 1000), "Gas on Mainnet: can afford ~10 typical transfers");
+        assert_eq!(describe_gas("Base", "ETH", 0, 42_000_000_000_000), "Gas on Base: not enough ETH for a transfer (~0.000042 ETH each)");
+    }
+
+    #[test]
+    fn test_look_up_transaction() {
+        let mut wallet = fixtures::test_wallet();
+        let rpc = MockRpc::new(networks::active().chain_id);
+        rpc.pending.set(true);
+        let hash = wallet.send(&rpc, 0, "0x70997970c51812dc3a010c7d01b50e0d17dc79c8", 1000).unwrap();
+        let account = wallet.accounts_metadata.get_account(0);
+
+        // still pending, as recorded
+        assert!(!account.look_up_transaction(&rpc, &hash));
+        assert_eq!(account.history[0].status, TransactionStatus::Pending);
+        rpc.pending.set(false);
+        rpc.head.set(5);
+        assert!(account.look_up_transaction(&rpc, &hash));
+        assert_eq!(account.history[0].status, TransactionStatus::Confirmed);
+        assert!(!account.look_up_transaction(&rpc, &hash));
+
+        // a transaction that isn't this account's is only reported
+        assert!(!account.look_up_transaction(&rpc, &format!("0x{:064x}", 1)));
+        rpc.offline.set(true);
+        assert!(!account.look_up_transaction(&rpc, &hash));
+    }
+
+    #[test]
+    fn test_balance_cache() {
+        let mut wallet = fixtures::test_wallet();
+        let chain_id = networks::active().chain_id;
+        let rpc = MockRpc::new(chain_id);
+        let account = wallet.accounts_metadata.get_account(0);
+        rpc.fund(&account.address, 42);
+
+        assert!(account.query_balance(&rpc));
+        let cached = account.balance_cache.clone().unwrap();
+        assert_eq!((cached.wei_balance, cached.nonce, cached.chain_id, cached.stale), (42, 0, chain_id, false));
+        // a transaction sent with the same key from another wallet
+        rpc.sent.borrow_mut().push(String::from("0x00"));
+        rpc.pending_balances.borrow_mut().insert(account.address.to_lowercase(), 40);
+        assert!(account.query_balance(&rpc));
+        assert_eq!(account.nonce, 1);
+        // the cache keeps the latest balance, not the pending one
+        assert_eq!(account.balance_cache.as_ref().unwrap().wei_balance, 42);
+
+        // the node going away marks the cache stale, once
+        rpc.offline.set(true);
+        assert!(account.query_balance(&rpc));
+        assert!(account.balance_cache.as_ref().unwrap().stale);
+        assert!(!account.query_balance(&rpc));
+        assert_eq!(account.balance_cache.as_ref().unwrap().wei_balance, 42);
+    }
+
+    #[test]
+    fn test_auto_refresh() {
+        let mut wallet = fixtures::test_wallet();
+        let chain_id = networks::active().chain_id;
+        let rpc = MockRpc::new(chain_id);
+        let account = wallet.accounts_metadata.get_account(0);
+        rpc.fund(&account.address, 42);
+        assert!(!account.auto_refresh(&rpc, RefreshInterval::Manual));
+        assert_eq!(account.balance_cache, None);
+
+        assert!(account.auto_refresh(&rpc, RefreshInterval::Every(60)));
+        assert_eq!(account.balance_cache.as_ref().unwrap().wei_balance, 42);
+        // fetched just now, so not again until the interval passes
+        rpc.fund(&account.address, 50);
+        assert!(!account.auto_refresh(&rpc, RefreshInterval::Every(60)));
+        assert_eq!(account.balance_cache.as_ref().unwrap().wei_balance, 42);
+    }
+
+    #[test]
+    fn test_gas_preferences_survive_store() {
+        let mut wallet = fixtures::test_wallet();
+        // the defaults aren't written, as in files from before there were preferences
+        assert!(!serde_json::to_string(&wallet.accounts_metadata).unwrap().contains("gas"));
+
+        let file = fixtures::temp_wallet_file("gas");
+        let path = file.to_str().unwrap();
+        wallet.set_location("gas", String::from(path));
+        let gas = GasPreferences {
+            gas_limit: 60000,
+            estimate_margin_percent: 50,
+            strategy: GasPriceStrategy::Fast,
+            max_gas_price: Some(80 * fees::GWEI),
+            auto_confirm_limit: Some(1000),
+        };
+        wallet.accounts_metadata.gas = gas.clone();
+        wallet.store().unwrap();
+
+        let mut loaded = Wallet::load(path).unwrap();
+        remove_wallet_file(&file);
+        loaded.verify_password(String::from("password")).unwrap();
+        assert_eq!(loaded.accounts_metadata.gas, gas);
+    }
+
+    #[test]
+    fn test_export_private_key() {
+        let mut wallet = fixtures::test_wallet();
+        let deriving_key = wallet.accounts_metadata.deriving_key.clone().unwrap();
+        let account = wallet.accounts_metadata.get_account(0);
+        assert_eq!(account.export_private_key(&deriving_key).as_str(), "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80");
+
+        // an imported key comes back out as it went in
+        let key = "0x59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d";
+        let secret = utils::parse_private_key(key).unwrap();
+        let sealed = SealedBox::seal(IMPORTED_KEY_CONTEXT, &deriving_key.to_bytes(), secret.as_slice());
+        wallet.accounts_metadata.external.push(Account::imported(utils::secret_key_to_address(&secret).unwrap(), sealed));
+        assert_eq!(wallet.accounts_metadata.get_account(1).export_private_key(&deriving_key).as_str(), key);
+    }
+
+    #[test]
+    fn test_recovery_phrase() {
+        let mut wallet = fixtures::test_wallet();
+        let stored = wallet.to_bytes();
+        let mut loaded: Wallet = serde_json::from_slice(&stored).unwrap();
+        loaded.verify_password(String::from("password")).unwrap();
+
+        let seed = loaded.unlock_secret("password").unwrap();
+        let phrase = loaded.accounts_metadata.recovery_phrase(&seed, loaded.language).unwrap();
+        assert_eq!(phrase.as_str(), fixtures::FIXTURE_MNEMONIC);
+
+        // wallet files from before the entropy was kept still load, with nothing to show
+        let mut old: serde_json::Value = serde_json::from_slice(&stored).unwrap();
+        old["accounts_metadata"].as_object_mut().unwrap().remove("recovery_phrase");
+        let mut old: Wallet = serde_json::from_value(old).unwrap();
+        assert!(old.accounts_metadata.recovery_phrase(&seed, old.language).is_none());
+
+        // the backup quiz reports what it can't recover instead of quizzing
+        assert_eq!(loaded.confirm_new_phrase("wrong"), Err(UnlockError::IncorrectPassword.to_string()));
+        let verified = old.accounts_metadata.backup.as_ref().map(|b| b.last_verified);
+        assert_eq!(old.confirm_new_phrase("password").unwrap_err(), "The recovery phrase couldn't be decrypted");
+        assert_eq!(old.accounts_metadata.backup.as_ref().map(|b| b.last_verified), verified);
+    }
+
+    #[test]
+    fn test_ledger_live_layout() {
+        let seed = Seed::new(&fixtures::fixture_mnemonic(), "");
+        let mut wallet = Wallet::from_with_template(String::from("password"), fixtures::fixture_mnemonic(), "", PathTemplate::ledger_live());
+        wallet.add_account();
+        assert_eq!(wallet.accounts_metadata.accounts[1].path, "m/44'/60'/1'/0/0");
+        let expected = utils::create_keys_from_path(seed.as_bytes(), "m/44'/60'/1'/0/0");
+        assert_eq!(wallet.addresses()[1], utils::xpub_to_address(&expected.1));
+        // account 0 is the same in both layouts
+        assert_eq!(wallet.addresses()[0], fixtures::test_wallet().addresses()[0]);
+
+        // signing keys follow the stored layout after a reload
+        let stored = wallet.to_bytes();
+        let mut loaded: Wallet = serde_json::from_slice(&stored).unwrap();
+        loaded.verify_password(String::from("password")).unwrap();
+        let deriving_key = loaded.accounts_metadata.deriving_key.clone().unwrap();
+        let account = loaded.accounts_metadata.get_account(1);
+        assert_eq!(*account.secret_key(&deriving_key), expected.0.to_bytes());
+    }
+
+    #[test]
+    fn test_discover_accounts() {
+        let mut wallet = fixtures::test_wallet();
+        let addresses: Vec<String> = (0..10).map(|i| Account::new(wallet.accounts_metadata.deriving_key.as_ref().unwrap(), &PathTemplate::standard(), i).address).collect();
+
+        // index 8 is past a gap of 5 unused accounts, so it isn't found
+        let activity = |address: &str| Ok(match addresses.iter().position(|a| a == address) {
+            Some(0) => (0, 3),
+            Some(2) => (1, 0),
+            Some(8) => (1, 1),
+            _ => (0, 0),
+        });
+        assert_eq!(wallet.accounts_metadata.discover_accounts(5, activity), Ok(3));
+        assert_eq!(wallet.addresses(), addresses[..3]);
+        assert_eq!((wallet.nonce(0), wallet.nonce(1), wallet.nonce(2)), (Some(3), Some(0), Some(0)));
+
+        // the scan stops at the first error and changes nothing
+        let mut fresh = fixtures::test_wallet();
+        assert_eq!(fresh.accounts_metadata.discover_accounts(5, |_| Err(String::from("offline"))), Err(String::from("offline")));
+        assert_eq!(fresh.addresses().len(), 1);
+        // a gap limit of 0 turns discovery off
+        assert_eq!(fresh.accounts_metadata.discover_accounts(0, |_| unreachable!()), Ok(1));
+    }
+
+    #[test]
+    fn test_keystore_round_trip() {
+        let mut source = fixtures::test_wallet();
+        let source_key = source.accounts_metadata.deriving_key.clone().unwrap();
+        let exported = source.accounts_metadata.get_account(0).export_keystore(&source_key, "keystore password");
+        assert_eq!(exported.address, "f39fd6e51aad88f6f4ce6ab8827279cfffb92266");
+
+        // the file goes through JSON like it would on disk, and imports as a key that signs
+        let json = serde_json::to_string(&exported).unwrap();
+        let secret = keystore::decrypt(&serde_json::from_str(&json).unwrap(), "keystore password").unwrap();
+        let mut wallet = Wallet::from(String::from("password"), Mnemonic::new(MnemonicType::Words12, Language::English), "");
+        let deriving_key = wallet.accounts_metadata.deriving_key.clone().unwrap();
+        wallet.accounts_metadata.add_imported_key(&deriving_key, &secret);
+        let account = wallet.accounts_metadata.get_account(1);
+        assert_eq!(account.address, "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266");
+        assert_eq!(*account.secret_key(&deriving_key), *source.accounts_metadata.get_account(0).secret_key(&source_key));
+    }
+
+    #[test]
+    fn test_vanity_account_in_sequence() {
+        let mut wallet = fixtures::test_wallet();
+        let metadata = &mut wallet.accounts_metadata;
+        let deriving_key = metadata.deriving_key.clone().unwrap();
+        // a vanity search found account 2 before account 1 was created
+        metadata.external.push(Account::new(&deriving_key, &PathTemplate::standard(), 2));
+        metadata.create_account(1);
+        assert_eq!(metadata.external.len(), 1);
+
+        let address = metadata.create_account(2).address.clone();
+        assert!(metadata.external.is_empty());
+        assert_eq!(metadata.accounts.len(), 3);
+        assert_eq!(metadata.position_of(&address), 2);
+    }
+
+    #[test]
+    fn test_banner() {
+        let mut wallet = fixtures::generate(&Default::default());
+        wallet.name = String::from("savings");
+        wallet.network = Some(11155111);
+        wallet.created_at = Some(1704272400);
+        assert_eq!(wallet.banner(), "Unlocking wallet 'savings' (created 2024-01-03, Sepolia)");
+
+        // wallets stored before they had names and creation details
+        let mut stored: Value = serde_json::from_slice(&wallet.to_bytes()).unwrap();
+        for field in ["name", "created_at", "network"] {
+            stored.as_object_mut().unwrap().remove(field);
+        }
+        let mut loaded: Wallet = serde_json::from_value(stored).unwrap();
+        loaded.adopt_name("default");
+        assert_eq!(loaded.banner(), "Unlocking wallet 'default'");
+        let upgraded: Value = serde_json::from_slice(&loaded.to_bytes()).unwrap();
+        assert_eq!(upgraded["name"], "default");
+
+        loaded.network = Some(1337);
+        assert_eq!(loaded.banner(), "Unlocking wallet 'default' (chain 1337)");
+    }
+
+    #[test]
+    fn test_shamir_shares() {
+        let wallet = fixtures::test_wallet();
+        let mnemonics = wallet.shamir_shares("password", 2, 3, "").unwrap();
+        assert_eq!(wallet.shamir_shares("wrong", 2, 3, "").unwrap_err(), "Incorrect password");
+
+        let shares: Vec<slip39::Share> = mnemonics[1..].iter().map(|m| slip39::Share::parse(m).unwrap()).collect();
+        let seed = slip39::combine(&shares, "").unwrap();
+        let mut restored = Wallet::from_master_secret(String::from("new password"), &seed).unwrap();
+        assert_eq!(restored.addresses(), wallet.addresses());
+        restored.verify_password(String::from("new password")).unwrap();
+
+        // a 128 bit master secret from another wallet
+        let secret = hex::decode("bb54aac4b89dc868ba37d9cc21b2cece").unwrap();
+        let mut short = Wallet::from_master_secret(String::from("password"), &secret).unwrap();
+        assert!(short.pad.is_empty());
+        short.verify_password(String::from("password")).unwrap();
+        assert_eq!(short.verify_password(String::from("wrong")), Err(UnlockError::IncorrectPassword));
+    }
+
+    #[test]
+    fn test_upgrade_legacy_wallet() {
+        let legacy_file = include_str!("../tests/data/legacy_userdata.txt");
+        assert!(LegacyWallet::parse(legacy_file).is_some());
+        assert!(LegacyWallet::parse(&fixtures::generate_json(&Default::default())).is_none());
+
+        let file = fixtures::temp_wallet_file("legacy");
+        let file = file.to_str().unwrap();
+        std::fs::write(file, legacy_file).unwrap();
+        let legacy = LegacyWallet::load(file).unwrap();
+        assert_eq!(legacy.upgrade("old", String::from("wrong password")).err().unwrap(), "Incorrect password");
+        // a failed attempt leaves the file alone
+        assert_eq!(std::fs::read_to_string(file).unwrap(), legacy_file);
+
+        let wallet = legacy.upgrade("old", String::from(fixtures::FIXTURE_PASSWORD)).unwrap();
+        assert!(wallet.accounts_metadata.deriving_key.is_some());
+        assert_eq!(wallet.addresses(), ["0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266"]);
+
+        assert!(LegacyWallet::load(file).is_none());
+        let mut stored = Wallet::load(file).unwrap();
+        remove_wallet_file(file);
+        stored.verify_password(String::from(fixtures::FIXTURE_PASSWORD)).unwrap();
+        assert_eq!(stored.name, "old");
+        assert_eq!(stored.addresses(), wallet.addresses());
+    }
+}
@@ -0,0 +1,143 @@
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+
+use crate::crypto::checksum_address;
+use crate::erc20::{self, Token, MAX_DECIMALS, MAX_SYMBOL_LEN};
+use crate::labels::normalize_address;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum TokenRegistryError {
+    #[error("The symbol can't be empty")]
+    EmptySymbol,
+    #[error("{0} can't be a symbol, which is up to {MAX_SYMBOL_LEN} printable ASCII characters")]
+    InvalidSymbol(String),
+    #[error("A token has at most {MAX_DECIMALS} decimal places, not {0}")]
+    TooManyDecimals(u8),
+    #[error("The name can't be empty or have control characters")]
+    InvalidName,
+    #[error("{0} is not a valid address or its checksum is wrong")]
+    InvalidAddress(String),
+    #[error("{symbol} is already registered at {address} on this network")]
+    Duplicate { symbol: String, address: String },
+    #[error("No token is registered at {0} on this network")]
+    NotFound(String),
+}
+
+/// A token the user added, on the chain it was added for
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RegisteredToken {
+    pub chain_id: u64,
+    pub name: String,
+    #[serde(flatten)]
+    pub token: Token,
+}
+
+/// Tokens added to those listed for the networks. Each belongs to one chain, where its address
+/// is unique.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(transparent)]
+pub struct TokenRegistry {
+    tokens: Vec<RegisteredToken>,
+}
+
+impl TokenRegistry {
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// The tokens registered on `chain_id`, in the order they were added
+    pub fn for_chain(&self, chain_id: u64) -> impl Iterator<Item = &RegisteredToken> {
+        self.tokens.iter().filter(move |t| t.chain_id == chain_id)
+    }
+
+    /// Registers `token` on `chain_id` under its checksummed address, and returns it as saved
+    pub fn add(&mut self, chain_id: u64, name: &str, token: Token) -> Result<&RegisteredToken, TokenRegistryError> {
+        let symbol = token.symbol.trim();
+        if symbol.is_empty() {
+            return Err(TokenRegistryError::EmptySymbol);
+        }
+        if !erc20::is_valid_symbol(symbol) {
+            return Err(TokenRegistryError::InvalidSymbol(String::from(symbol)));
+        }
+        if token.decimals > MAX_DECIMALS {
+            return Err(TokenRegistryError::TooManyDecimals(token.decimals));
+        }
+        if !erc20::is_valid_name(name) {
+            return Err(TokenRegistryError::InvalidName);
+        }
+        let address = token.address.trim();
+        let normalized = normalize_address(address).ok_or_else(|| TokenRegistryError::InvalidAddress(String::from(address)))?;
+        if let Some(existing) = self.find(chain_id, &normalized) {
+            return Err(TokenRegistryError::Duplicate { symbol: existing.token.symbol.clone(), address: existing.token.address.clone() });
+        }
+        let bytes: [u8; 20] = hex::decode(&normalized[2..]).unwrap().try_into().unwrap();
+        self.tokens.push(RegisteredToken {
+            chain_id,
+            name: String::from(name.trim()),
+            token: Token { symbol: String::from(symbol), address: checksum_address(&bytes), decimals: token.decimals },
+        });
+        Ok(self.tokens.last().unwrap())
+    }
+
+    /// Unregisters the token at `address` on `chain_id`, and returns it
+    pub fn remove(&mut self, chain_id: u64, address: &str) -> Result<RegisteredToken, TokenRegistryError> {
+        let position = self.tokens.iter().position(|t| t.chain_id == chain_id && t.token.address.eq_ignore_ascii_case(address.trim()))
+            .ok_or_else(|| TokenRegistryError::NotFound(String::from(address.trim())))?;
+        Ok(self.tokens.remove(position))
+    }
+
+    /// The token registered at `address` on `chain_id`, ignoring case
+    pub fn find(&self, chain_id: u64, address: &str) -> Option<&RegisteredToken> {
+        self.for_chain(chain_id).find(|t| t.token.address.eq_ignore_ascii_case(address.trim()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn token(symbol: &str, address: &str) -> Token {
+        Token { symbol: String::from(symbol), address: String::from(address), decimals: 18 }
+    }
+
+    #[test]
+    fn test_add() {
+        let mut registry = TokenRegistry::default();
+        let added = registry.add(1, " Token ", token(" TKN ", "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed")).unwrap().clone();
+        assert_eq!(added.token, token("TKN", "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"));
+        assert_eq!(added.name, "Token");
+        assert_eq!(registry.find(1, "0x5AAEB6053F3E94C9B9A09F33669435E7EF1BEAED"), Some(&added));
+
+        assert_eq!(registry.add(1, "Again", token("TKN2", "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed")).unwrap_err(),
+            TokenRegistryError::Duplicate { symbol: String::from("TKN"), address: String::from("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed") });
+        assert_eq!(registry.add(1, "", token(" ", "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359")).unwrap_err(), TokenRegistryError::EmptySymbol);
+        assert_eq!(registry.add(1, "Long", token("SYMBOLTOOLONG", "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359")).unwrap_err(),
+            TokenRegistryError::InvalidSymbol(String::from("SYMBOLTOOLONG")));
+        let precise = Token { decimals: 37, ..token("PRC", "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359") };
+        assert_eq!(registry.add(1, "Precise", precise).unwrap_err(), TokenRegistryError::TooManyDecimals(37));
+        assert_eq!(registry.add(1, "\x1b[2J", token("ESC", "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359")).unwrap_err(), TokenRegistryError::InvalidName);
+        assert_eq!(registry.add(1, "Bad", token("BAD", "0xfb6916095CA1df60bB79Ce92cE3Ea74c37c5d359")).unwrap_err(),
+            TokenRegistryError::InvalidAddress(String::from("0xfb6916095CA1df60bB79Ce92cE3Ea74c37c5d359")));
+
+        // the same address on another chain is another token
+        registry.add(11155111, "Test token", token("TKN", "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed")).unwrap();
+        assert_eq!(registry.for_chain(1).count(), 1);
+        assert_eq!(registry.for_chain(11155111).map(|t| t.name.as_str()).collect::<Vec<_>>(), ["Test token"]);
+        assert_eq!(registry.for_chain(17000).count(), 0);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut registry = TokenRegistry::default();
+        registry.add(1, "Token", token("TKN", "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed")).unwrap();
+        registry.add(11155111, "Token", token("TKN", "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed")).unwrap();
+        assert_eq!(registry.remove(1, "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").unwrap().chain_id, 1);
+        assert_eq!(registry.remove(1, "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").unwrap_err(),
+            TokenRegistryError::NotFound(String::from("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed")));
+
+        // stored as a plain list, the token's fields beside the chain's
+        let json = serde_json::to_string(&registry).unwrap();
+        assert_eq!(json, r#"[{"chain_id":11155111,"name":"Token","symbol":"TKN","address":"0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed","decimals":18}]"#);
+        assert_eq!(serde_json::from_str::<TokenRegistry>(&json).unwrap(), registry);
+    }
+}
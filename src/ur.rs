@@ -0,0 +1,401 @@
+use bip32::{ChildNumber, ExtendedKey, ExtendedKeyAttrs, Prefix, XPub};
+use thiserror::Error;
+
+use crate::cbor::{Cbor, CborError};
+
+/// UR type for a single BIP32 key (BCR-2020-007)
+pub const HDKEY_UR_TYPE: &str = "crypto-hdkey";
+const TAG_KEYPATH: u64 = 304;
+const TAG_COININFO: u64 = 305;
+/// SLIP-44 coin type for Ethereum
+pub const COIN_TYPE_ETH: u64 = 60;
+
+/// The bytewords word list (BCR-2020-012). Minimal bytewords use each word's first and last letter.
+const BYTEWORDS: [&str; 256] = [
+    "able", "acid", "also", "apex", "aqua", "arch", "atom", "aunt", "away", "axis", "back", "bald", "barn", "belt", "beta", "bias",
+    "blue", "body", "brag", "brew", "bulb", "buzz", "calm", "cash", "cats", "chef", "city", "claw", "code", "cola", "cook", "cost",
+    "crux", "curl", "cusp", "cyan", "dark", "data", "days", "deli", "dice", "diet", "door", "down", "draw", "drop", "drum", "dull",
+    "duty", "each", "easy", "echo", "edge", "epic", "even", "exam", "exit", "eyes", "fact", "fair", "fern", "figs", "film", "fish",
+    "fizz", "flap", "flew", "flux", "foxy", "free", "frog", "fuel", "fund", "gala", "game", "gear", "gems", "gift", "girl", "glow",
+    "good", "gray", "grim", "guru", "gush", "gyro", "half", "hang", "hard", "hawk", "heat", "help", "high", "hill", "holy", "hope",
+    "horn", "huts", "iced", "idea", "idle", "inch", "inky", "into", "iris", "iron", "item", "jade", "jazz", "join", "jolt", "jowl",
+    "judo", "jugs", "jump", "junk", "jury", "keep", "keno", "kept", "keys", "kick", "kiln", "king", "kite", "kiwi", "knob", "lamb",
+    "lava", "lazy", "leaf", "legs", "liar", "limp", "lion", "list", "logo", "loud", "love", "luau", "luck", "lung", "main", "many",
+    "math", "maze", "memo", "menu", "meow", "mild", "mint", "miss", "monk", "nail", "navy", "need", "news", "next", "noon", "note",
+    "numb", "obey", "oboe", "omit", "onyx", "open", "oval", "owls", "paid", "part", "peck", "play", "plus", "poem", "pool", "pose",
+    "puff", "puma", "purr", "quad", "quiz", "race", "ramp", "real", "redo", "rich", "road", "rock", "roof", "ruby", "ruin", "runs",
+    "rust", "safe", "saga", "scar", "sets", "silk", "skew", "slot", "soap", "solo", "song", "stub", "surf", "swan", "taco", "task",
+    "taxi", "tent", "tied", "time", "tiny", "toil", "tomb", "toys", "trip", "tuna", "twin", "ugly", "undo", "unit", "urge", "user",
+    "vast", "very", "veto", "vial", "vibe", "view", "visa", "void", "vows", "wall", "wand", "warm", "wasp", "wave", "waxy", "webs",
+    "what", "when", "whiz", "wolf", "work", "yank", "yawn", "yell", "yoga", "yurt", "zaps", "zero", "zest", "zinc", "zone", "zoom",
+];
+
+#[derive(Error, Debug, PartialEq)]
+pub enum UrError {
+    #[error("Not a UR: it should start with \"ur:\"")]
+    MissingScheme,
+    #[error("Expected a {expected} UR but got {found}")]
+    WrongType { expected: String, found: String },
+    #[error("Multi-part URs aren't supported, show the key as a single QR code")]
+    MultiPart,
+    #[error("Invalid byteword \"{0}\"")]
+    InvalidByteword(String),
+    #[error("Checksum mismatch, the UR was not scanned correctly")]
+    ChecksumMismatch,
+    #[error("Malformed CBOR: {0}")]
+    Cbor(#[from] CborError),
+    #[error("Malformed {0}")]
+    InvalidField(&'static str),
+    #[error("Expected a public key with a chain code")]
+    NotAnExtendedPublicKey,
+}
+
+/// A derivation path as carried in a `crypto-keypath`
+#[derive(Debug, PartialEq, Clone)]
+pub struct KeyOrigin {
+    /// (index, hardened) for every path component
+    pub components: Vec<(u32, bool)>,
+    pub source_fingerprint: Option<u32>,
+    pub depth: Option<u8>,
+}
+
+impl KeyOrigin {
+    /// Formats the path as `m/44'/60'/0'`
+    pub fn path(&self) -> String {
+        let mut path = String::from("m");
+        for (index, hardened) in &self.components {
+            path.push_str(&format!("/{}{}", index, if *hardened { "'" } else { "" }));
+        }
+        path
+    }
+
+    fn to_cbor(&self) -> Cbor {
+        let components = self.components.iter()
+            .flat_map(|(index, hardened)| [Cbor::Uint(*index as u64), Cbor::Bool(*hardened)])
+            .collect();
+        let mut map = vec![(Cbor::Uint(1), Cbor::Array(components))];
+        if let Some(fingerprint) = self.source_fingerprint {
+            map.push((Cbor::Uint(2), Cbor::Uint(fingerprint as u64)));
+        }
+        if let Some(depth) = self.depth {
+            map.push((Cbor::Uint(3), Cbor::Uint(depth as u64)));
+        }
+        Cbor::Tag(TAG_KEYPATH, Box::new(Cbor::Map(map)))
+    }
+
+    fn from_cbor(item: &Cbor) -> Result<Self, UrError> {
+        let item = item.untagged();
+        let components = match item.get(1) {
+            Some(Cbor::Array(items)) if items.len().is_multiple_of(2) => items.chunks(2)
+                .map(|pair| match pair {
+                    [Cbor::Uint(index), Cbor::Bool(hardened)] if *index < 1 << 31 => Ok((*index as u32, *hardened)),
+                    _ => Err(UrError::InvalidField("key path component")),
+                })
+                .collect::<Result<_, _>>()?,
+            _ => return Err(UrError::InvalidField("key path")),
+        };
+        Ok(KeyOrigin {
+            components,
+            source_fingerprint: optional_u32(item.get(2), "source fingerprint")?,
+            depth: match item.get(3) {
+                Some(Cbor::Uint(d)) if *d <= u8::MAX as u64 => Some(*d as u8),
+                Some(_) => return Err(UrError::InvalidField("depth")),
+                None => None,
+            },
+        })
+    }
+}
+
+/// A `crypto-hdkey`: an extended key with its origin, as exchanged with QR-based signers
+#[derive(Debug, PartialEq, Clone)]
+pub struct HdKey {
+    pub is_master: bool,
+    pub is_private: bool,
+    /// 33 byte compressed public key, or 0x00 followed by the private key
+    pub key_data: Vec<u8>,
+    pub chain_code: Option<[u8; 32]>,
+    /// SLIP-44 coin type the key is meant for
+    pub coin_type: Option<u64>,
+    pub origin: Option<KeyOrigin>,
+    pub parent_fingerprint: Option<u32>,
+    pub name: Option<String>,
+}
+
+impl HdKey {
+    pub fn to_cbor(&self) -> Cbor {
+        let mut map = vec![];
+        if self.is_master {
+            map.push((Cbor::Uint(1), Cbor::Bool(true)));
+        }
+        if self.is_private {
+            map.push((Cbor::Uint(2), Cbor::Bool(true)));
+        }
+        map.push((Cbor::Uint(3), Cbor::Bytes(self.key_data.clone())));
+        if let Some(chain_code) = self.chain_code {
+            map.push((Cbor::Uint(4), Cbor::Bytes(chain_code.to_vec())));
+        }
+        if let Some(coin_type) = self.coin_type {
+            let info = Cbor::Map(vec![(Cbor::Uint(1), Cbor::Uint(coin_type))]);
+            map.push((Cbor::Uint(5), Cbor::Tag(TAG_COININFO, Box::new(info))));
+        }
+        if let Some(origin) = &self.origin {
+            map.push((Cbor::Uint(6), origin.to_cbor()));
+        }
+        if let Some(fingerprint) = self.parent_fingerprint {
+            map.push((Cbor::Uint(8), Cbor::Uint(fingerprint as u64)));
+        }
+        if let Some(name) = &self.name {
+            map.push((Cbor::Uint(9), Cbor::Text(name.clone())));
+        }
+        Cbor::Map(map)
+    }
+
+    pub fn from_cbor(item: &Cbor) -> Result<Self, UrError> {
+        let item = item.untagged();
+        let flag = |key, field| match item.get(key) {
+            Some(Cbor::Bool(b)) => Ok(*b),
+            Some(_) => Err(UrError::InvalidField(field)),
+            None => Ok(false),
+        };
+
+        let key_data = match item.get(3) {
+            Some(Cbor::Bytes(b)) if b.len() == 33 => b.clone(),
+            _ => return Err(UrError::InvalidField("key data")),
+        };
+        let chain_code = match item.get(4) {
+            Some(Cbor::Bytes(b)) => Some(b.as_slice().try_into().map_err(|_e| UrError::InvalidField("chain code"))?),
+            Some(_) => return Err(UrError::InvalidField("chain code")),
+            None => None,
+        };
+        let coin_type = match item.get(5).map(Cbor::untagged) {
+            Some(info) => match info.get(1) {
+                Some(Cbor::Uint(t)) => Some(*t),
+                None => None,
+                Some(_) => return Err(UrError::InvalidField("coin info")),
+            },
+            None => None,
+        };
+        let name = match item.get(9) {
+            Some(Cbor::Text(t)) => Some(t.clone()),
+            Some(_) => return Err(UrError::InvalidField("name")),
+            None => None,
+        };
+
+        Ok(HdKey {
+            is_master: flag(1, "master flag")?,
+            is_private: flag(2, "private flag")? || key_data[0] == 0,
+            key_data,
+            chain_code,
+            coin_type,
+            origin: item.get(6).map(KeyOrigin::from_cbor).transpose()?,
+            parent_fingerprint: optional_u32(item.get(8), "parent fingerprint")?,
+            name,
+        })
+    }
+
+    /// Describes an extended public key derived at `origin`
+    pub fn from_xpub(xpub: &XPub, origin: KeyOrigin, name: Option<String>) -> Self {
+        let attrs = xpub.attrs();
+        HdKey {
+            is_master: false,
+            is_private: false,
+            key_data: xpub.to_bytes().to_vec(),
+            chain_code: Some(attrs.chain_code),
+            coin_type: Some(COIN_TYPE_ETH),
+            origin: Some(origin),
+            parent_fingerprint: Some(u32::from_be_bytes(attrs.parent_fingerprint)),
+            name,
+        }
+    }
+
+    /// Rebuilds the extended public key so child addresses can be derived from it
+    pub fn to_xpub(&self) -> Result<XPub, UrError> {
+        let chain_code = match self.chain_code {
+            Some(c) if !self.is_private => c,
+            _ => return Err(UrError::NotAnExtendedPublicKey),
+        };
+        let components = self.origin.as_ref().map(|o| o.components.as_slice()).unwrap_or_default();
+        let child_number = match components.last() {
+            Some((index, hardened)) => ChildNumber::new(*index, *hardened).map_err(|_e| UrError::InvalidField("key path"))?,
+            None => ChildNumber::default(),
+        };
+        let depth = self.origin.as_ref().and_then(|o| o.depth).unwrap_or(components.len() as u8);
+
+        let key = ExtendedKey {
+            prefix: Prefix::XPUB,
+            attrs: ExtendedKeyAttrs {
+                depth,
+                parent_fingerprint: self.parent_fingerprint.unwrap_or_default().to_be_bytes(),
+                child_number,
+                chain_code,
+            },
+            key_bytes: self.key_data.as_slice().try_into().unwrap(),
+        };
+        XPub::try_from(key).map_err(|_e| UrError::InvalidField("public key"))
+    }
+
+    /// Encodes the key as a single-part `ur:crypto-hdkey/...` string
+    pub fn to_ur(&self) -> String {
+        encode_ur(HDKEY_UR_TYPE, &self.to_cbor().encode())
+    }
+
+    pub fn from_ur(ur: &str) -> Result<Self, UrError> {
+        HdKey::from_cbor(&Cbor::decode(&decode_ur(HDKEY_UR_TYPE, ur)?)?)
+    }
+}
+
+fn optional_u32(item: Option<&Cbor>, field: &'static str) -> Result<Option<u32>, UrError> {
+    match item {
+        Some(Cbor::Uint(n)) => u32::try_from(*n).map(Some).map_err(|_e| UrError::InvalidField(field)),
+        Some(_) => Err(UrError::InvalidField(field)),
+        None => Ok(None),
+    }
+}
+
+/// Builds a single-part UR from a type and its CBOR payload
+pub fn encode_ur(ur_type: &str, cbor: &[u8]) -> String {
+    format!("ur:{}/{}", ur_type, encode_minimal_bytewords(cbor))
+}
+
+/// Parses a single-part UR of the expected type and returns its CBOR payload. Case-insensitive,
+/// since QR codes carry URs in upper case.
+pub fn decode_ur(expected_type: &str, ur: &str) -> Result<Vec<u8>, UrError> {
+    let ur = ur.trim().to_lowercase();
+    let rest = ur.strip_prefix("ur:").ok_or(UrError::MissingScheme)?;
+    let mut parts = rest.split('/');
+    let ur_type = parts.next().unwrap_or_default();
+    if ur_type != expected_type {
+        return Err(UrError::WrongType { expected: String::from(expected_type), found: String::from(ur_type) });
+    }
+    match (parts.next(), parts.next()) {
+        (Some(payload), None) => decode_minimal_bytewords(payload),
+        _ => Err(UrError::MultiPart),
+    }
+}
+
+/// Encodes bytes as minimal bytewords with the CRC32 checksum appended
+pub fn encode_minimal_bytewords(data: &[u8]) -> String {
+    let mut data = data.to_vec();
+    data.extend_from_slice(&crc32(&data).to_be_bytes());
+    data.iter()
+        .map(|b| {
+            let word = BYTEWORDS[*b as usize].as_bytes();
+            format!("{}{}", word[0] as char, word[3] as char)
+        })
+        .collect()
+}
+
+/// Decodes minimal bytewords and checks and strips the CRC32 checksum
+pub fn decode_minimal_bytewords(text: &str) -> Result<Vec<u8>, UrError> {
+    let text = text.as_bytes();
+    if !text.len().is_multiple_of(2) {
+        return Err(UrError::InvalidByteword(String::from_utf8_lossy(&text[text.len() - 1..]).into_owned()));
+    }
+    let mut data = text.chunks(2)
+        .map(|pair| BYTEWORDS.iter()
+            .position(|w| w.as_bytes()[0] == pair[0] && w.as_bytes()[3] == pair[1])
+            .map(|i| i as u8)
+            .ok_or_else(|| UrError::InvalidByteword(String::from_utf8_lossy(pair).into_owned())))
+        .collect::<Result<Vec<u8>, _>>()?;
+
+    if data.len() < 4 {
+        return Err(UrError::ChecksumMismatch);
+    }
+    let checksum = data.split_off(data.len() - 4);
+    if crc32(&data).to_be_bytes() != checksum.as_slice() {
+        return Err(UrError::ChecksumMismatch);
+    }
+    Ok(data)
+}
+
+/// CRC-32 as used by bytewords (IEEE 802.3, reflected)
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // master key example from the crypto-hdkey specification (BCR-2020-007)
+    const MASTER_KEY_CBOR: &str = "a301f503582100e8f32e723decf4051aefac8e2c93c9c5b214313817cdb01a1494b917c8436b35045820873dff81c02f525623fd1fe5167eac3a55a049de3d314bb42ee227ffed37d508";
+    const MASTER_KEY_UR: &str = "ur:crypto-hdkey/otadykaxhdclaevswfdmjpfswpwkahcywspsmndwmusoskprbbehetchsnpfcybbmwrhchspfxjeecaahdcxltfszmlyrtdlgmhfcnzcctvwcmkbpsftgonbgauefsehgrqzdmvodizmweemtlaybakiylat";
+
+    #[test]
+    fn test_crc32() {
+        // test vector from the bytewords specification
+        assert_eq!(crc32(b"Wolf"), 0x598c84dc);
+    }
+
+    #[test]
+    fn test_bytewords() {
+        // test vector from the bytewords specification
+        let data = [0, 1, 2, 128, 255];
+        assert_eq!(encode_minimal_bytewords(&data), "aeadaolazmjendeoti");
+        assert_eq!(decode_minimal_bytewords("aeadaolazmjendeoti").unwrap(), data);
+        assert_eq!(decode_minimal_bytewords("aeadaolazmjendeote"), Err(UrError::ChecksumMismatch));
+        assert_eq!(decode_minimal_bytewords("aeadaolazmjendeotx"), Err(UrError::InvalidByteword(String::from("tx"))));
+    }
+
+    #[test]
+    fn test_master_key_vector() {
+        let cbor = hex::decode(MASTER_KEY_CBOR).unwrap();
+        assert_eq!(encode_ur(HDKEY_UR_TYPE, &cbor), MASTER_KEY_UR);
+
+        let key = HdKey::from_ur(&MASTER_KEY_UR.to_uppercase()).unwrap();
+        assert!(key.is_master);
+        assert!(key.is_private);
+        assert_eq!(hex::encode(&key.key_data), "00e8f32e723decf4051aefac8e2c93c9c5b214313817cdb01a1494b917c8436b35");
+        assert_eq!(hex::encode(key.chain_code.unwrap()), "873dff81c02f525623fd1fe5167eac3a55a049de3d314bb42ee227ffed37d508");
+        assert_eq!(key.origin, None);
+        // explicit private flags are not written back, everything else round-trips byte for byte
+        assert_eq!(HdKey { is_private: false, ..key }.to_cbor().encode(), cbor);
+    }
+
+    #[test]
+    fn test_account_key_round_trip() {
+        let key = HdKey {
+            is_master: false,
+            is_private: false,
+            key_data: vec![2; 33],
+            chain_code: Some([7; 32]),
+            coin_type: Some(COIN_TYPE_ETH),
+            origin: Some(KeyOrigin {
+                components: vec![(44, true), (60, true), (0, true)],
+                source_fingerprint: Some(0x1234_5678),
+                depth: Some(3),
+            }),
+            parent_fingerprint: Some(0xdead_beef),
+            name: Some(String::from("basic-eth")),
+        };
+        assert_eq!(key.origin.as_ref().unwrap().path(), "m/44'/60'/0'");
+        assert_eq!(HdKey::from_ur(&key.to_ur()).unwrap(), key);
+    }
+
+    #[test]
+    fn test_xpub_round_trip() {
+        let seed = [1u8; 64];
+        let (_, xpub) = crate::utils::create_keys_from_path(&seed, "m/44'/60'/0'");
+        let origin = KeyOrigin { components: vec![(44, true), (60, true), (0, true)], source_fingerprint: None, depth: Some(3) };
+        let key = HdKey::from_ur(&HdKey::from_xpub(&xpub, origin, None).to_ur()).unwrap();
+        assert_eq!(key.to_xpub().unwrap(), xpub);
+
+        let master = HdKey::from_ur(MASTER_KEY_UR).unwrap();
+        assert_eq!(master.to_xpub(), Err(UrError::NotAnExtendedPublicKey));
+    }
+
+    #[test]
+    fn test_decode_ur_errors() {
+        assert_eq!(decode_ur(HDKEY_UR_TYPE, "crypto-hdkey/aeadaolazmjendeoti"), Err(UrError::MissingScheme));
+        assert!(matches!(decode_ur(HDKEY_UR_TYPE, "ur:bytes/aeadaolazmjendeoti"), Err(UrError::WrongType { .. })));
+        assert_eq!(decode_ur(HDKEY_UR_TYPE, "ur:crypto-hdkey/1-3/aeadaolazmjendeoti"), Err(UrError::MultiPart));
+        assert_eq!(decode_ur(HDKEY_UR_TYPE, "UR:CRYPTO-HDKEY/AEADAOLAZMJENDEOTI").unwrap(), [0, 1, 2, 128, 255]);
+    }
+}
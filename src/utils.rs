@@ -1,10 +1,20 @@
-use std::io;
 use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use thiserror::Error;
+use bip39::{Mnemonic, MnemonicType, Language};
 use bip32::{ChildNumber, XPrv, XPub, DerivationPath};
+use bip32::secp256k1::ecdsa::SigningKey;
+use bip32::secp256k1::elliptic_curve::sec1::ToEncodedPoint;
+use zeroize::Zeroizing;
 
-#[derive(Error, Debug)]
+use crate::address_book::AddressBook;
+use crate::crypto::generate_eth_address;
+use crate::ens;
+use crate::password;
+use crate::session;
+
+#[derive(Error, Debug, PartialEq)]
 pub enum AddressParseError {
     #[error("Invalid hex character")]
     InvalidHexCharacter,
@@ -12,25 +22,206 @@ pub enum AddressParseError {
     InvalidLength,
 }
 
-/// Returns clean (no newline) user input
+#[derive(Error, Debug, PartialEq)]
+pub enum MnemonicError {
+    #[error("A recovery phrase has 12, 15, 18, 21 or 24 words, but this one has {0}")]
+    WordCount(usize),
+    #[error("Not in the wordlist: {}", describe_unknown_words(.0))]
+    UnknownWords(Vec<(usize, String)>),
+    #[error("Every word is valid but the checksum doesn't match. Check for a wrong word or words out of order.")]
+    Checksum,
+}
+
+fn describe_unknown_words(words: &[(usize, String)]) -> String {
+    words.iter().map(|(position, word)| format!("word #{} (\"{}\")", position, word)).collect::<Vec<_>>().join(", ")
+}
+
+/// Returns clean (no newline) user input. Typing the lock command, or a pending lock request,
+/// locks an unlocked wallet instead of returning.
 pub fn read_user_input() -> String {
-    let mut input = String::new();
-    io::stdin().read_line(&mut input).unwrap();
+    let input = session::read_line();
+    session::check(&input);
+    input
+}
 
-    if let Some('\n') = input.chars().next_back() {
-        input.pop();
-    }
+/// Reads a password, passphrase or recovery phrase like `read_user_input`, without echoing it to
+/// the terminal. Piped input is read as it is.
+pub fn read_secret_input() -> String {
+    let input = session::read_hidden_line();
+    session::check(&input);
     input
 }
 
+/// Asks for a new password, then for it again so a typo can't lock the wallet, until the two match
+/// and the password is strong enough or the user accepts the risk. Returns None if the user types
+/// q instead.
+pub fn read_new_password(prompt: &str) -> Option<String> {
+    loop {
+        println!("{} (or type q to cancel): ", prompt);
+        let password = read_secret_input();
+        if password == "q" {
+            return None;
+        }
+        if !password::confirm_strength(&password) {
+            continue;
+        }
+        println!("Enter it again to confirm: ");
+        if read_secret_input() == password {
+            return Some(password);
+        }
+        println!("The passwords don't match, try again");
+    }
+}
+
+/// Parses a recovery phrase of any BIP-39 length in the given wordlist. Case and extra whitespace
+/// are ignored. The word count is checked first, then each word (positions are 1-based), then the
+/// checksum.
+pub fn parse_mnemonic(phrase: &str, language: Language) -> Result<Mnemonic, MnemonicError> {
+    let phrase = phrase.to_lowercase();
+    let words: Vec<&str> = phrase.split_whitespace().collect();
+    if MnemonicType::for_word_count(words.len()).is_err() {
+        return Err(MnemonicError::WordCount(words.len()));
+    }
+
+    let wordmap = language.wordmap();
+    let unknown: Vec<(usize, String)> = words.iter().enumerate()
+        .filter(|(_, word)| wordmap.get_bits(word).is_err())
+        .map(|(i, word)| (i + 1, String::from(*word)))
+        .collect();
+    if !unknown.is_empty() {
+        return Err(MnemonicError::UnknownWords(unknown));
+    }
+
+    // the count and the words were checked, so only the checksum can fail
+    Mnemonic::from_phrase(&words.join(" "), language).map_err(|_e| MnemonicError::Checksum)
+}
+
+/// Number of wei in one ETH
+pub const WEI_PER_ETH: u128 = 1_000_000_000_000_000_000;
+
+/// Formats a wei amount as an exact decimal ETH amount without trailing zeroes
 pub fn wei_to_eth(amount: u128) -> String {
-    (amount as f64 / 10_f64.powf(18 as f64)).to_string()
+    let whole = amount / WEI_PER_ETH;
+    let fraction = amount % WEI_PER_ETH;
+    if fraction == 0 {
+        return whole.to_string();
+    }
+    let fraction = format!("{:018}", fraction);
+    format!("{}.{}", whole, fraction.trim_end_matches('0'))
 }
 
-/// Converts an ETH amount to corresponding wei amount.
+/// Converts a decimal ETH amount to the corresponding wei amount using integer math only.
 /// NOTE: any wei amount less than 1 is invalid and will truncate to 0
-pub fn eth_to_wei(amount: f64) -> u128 {
-    (amount * 10_f64.powf(18 as f64)) as u128
+pub fn eth_to_wei(amount: &str) -> Result<u128, String> {
+    let amount = amount.trim();
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return Err(String::from("Please enter a number"));
+    }
+    if !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+        return Err(String::from("Please enter a positive decimal number"));
+    }
+
+    // digits past the 18th decimal are less than 1 wei and get truncated
+    let mut fraction = String::from(&fraction[..fraction.len().min(18)]);
+    while fraction.len() < 18 {
+        fraction.push('0');
+    }
+
+    let whole = if whole.is_empty() { 0 } else { whole.parse::<u128>().map_err(|_e| String::from("Amount too large"))? };
+    whole.checked_mul(WEI_PER_ETH)
+        .and_then(|w| w.checked_add(fraction.parse::<u128>().unwrap()))
+        .ok_or_else(|| String::from("Amount too large"))
+}
+
+/// Returns the current unix time in seconds
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Formats a unix timestamp as "YYYY-MM-DD HH:MM UTC"
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+
+    // civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, seconds / 3600, (seconds % 3600) / 60)
+}
+
+/// Formats a unix timestamp as "YYYY-MM-DD"
+pub fn format_date(timestamp: u64) -> String {
+    let mut formatted = format_timestamp(timestamp);
+    formatted.truncate(10);
+    formatted
+}
+
+/// Parses a point in time entered by the user, either relative to `now` ("+30m", "+2h", "+1d")
+/// or as an absolute UTC time ("YYYY-MM-DD HH:MM"). Returns a unix timestamp in seconds.
+pub fn parse_time(input: &str, now: u64) -> Result<u64, String> {
+    let input = input.trim();
+    if let Some(relative) = input.strip_prefix('+') {
+        let (amount, unit) = relative.split_at(relative.len().saturating_sub(1));
+        let amount = amount.parse::<u64>().map_err(|_e| String::from("Invalid relative time"))?;
+        let multiplier = match unit {
+            "m" => 60,
+            "h" => 3600,
+            "d" => 86400,
+            _ => return Err(String::from("Relative times must end in m, h or d")),
+        };
+        return Ok(now + amount * multiplier);
+    }
+
+    let parts: Vec<i64> = input.split(['-', ' ', ':'])
+        .map(|p| p.parse::<i64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_e| String::from("Expected YYYY-MM-DD HH:MM"))?;
+    if parts.len() != 5 {
+        return Err(String::from("Expected YYYY-MM-DD HH:MM"));
+    }
+    let (year, month, day, hour, minute) = (parts[0], parts[1], parts[2], parts[3], parts[4]);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || !(0..24).contains(&hour) || !(0..60).contains(&minute) {
+        return Err(String::from("Date or time out of range"));
+    }
+
+    // days-from-civil, the inverse of the computation in format_timestamp
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let timestamp = days * 86400 + hour * 3600 + minute * 60;
+    u64::try_from(timestamp).map_err(|_e| String::from("Time is before 1970"))
+}
+
+/// Returns how many transfers costing `fee` wei the balance can pay for
+pub fn affordable_transfers(balance: u128, fee: u128) -> u128 {
+    if fee == 0 {
+        return u128::MAX;
+    }
+    balance / fee
+}
+
+/// Returns how many wei are missing to send `amount` and pay `fee`, or None if the balance covers both
+pub fn gas_shortfall(balance: u128, amount: u128, fee: u128) -> Option<u128> {
+    let required = amount.saturating_add(fee);
+    if balance >= required {
+        None
+    } else {
+        Some(required - balance)
+    }
 }
 
 /// Returns the XOR of two byte arrays. The byte arrays must be the same length
@@ -56,32 +247,82 @@ pub fn create_keys_from_path(seed: &[u8], path: &str) -> (XPrv, XPub) {
     (child_xprv, child_xpub)
 }
 
-pub fn derive_child_secret_key(parent_key: &XPrv, index: u32) -> [u8; 32] {
+pub fn derive_child_secret_key(parent_key: &XPrv, index: u32) -> Zeroizing<[u8; 32]> {
     let child = parent_key.derive_child(ChildNumber::new(index, false).unwrap()).unwrap();
-    child.to_bytes()
+    Zeroizing::new(child.to_bytes())
+}
+
+/// Returns the lowercase 0x-prefixed Ethereum address of an extended public key
+pub fn xpub_to_address(xpub: &XPub) -> String {
+    // the address is the hash of the UNCOMPRESSED point, leaving out the 0x04 prefix
+    let pub_key: [u8; 65] = xpub.public_key().to_encoded_point(false).as_bytes().try_into().unwrap();
+    String::from("0x") + &hex::encode(generate_eth_address(&pub_key[1..]))
+}
+
+/// Parses a hex private key, with or without 0x, checking it is a valid secp256k1 key
+pub fn parse_private_key(input: &str) -> Result<Zeroizing<[u8; 32]>, String> {
+    let raw = input.strip_prefix("0x").unwrap_or(input);
+    let bytes = Zeroizing::new(hex::decode(raw).map_err(|_e| String::from("A private key must be hex"))?);
+    let secret: [u8; 32] = bytes.as_slice().try_into().map_err(|_e| String::from("A private key is 32 bytes (64 hex characters)"))?;
+    let secret = Zeroizing::new(secret);
+    if SigningKey::from_bytes(secret.as_slice()).is_err() {
+        return Err(String::from("Not a valid secp256k1 private key"));
+    }
+    Ok(secret)
+}
+
+/// Returns the address of a raw private key, or None if it isn't a valid secp256k1 key
+pub fn secret_key_to_address(secret: &[u8; 32]) -> Option<String> {
+    let point = SigningKey::from_bytes(secret).ok()?.verifying_key().to_encoded_point(false);
+    Some(String::from("0x") + &hex::encode(generate_eth_address(&point.as_bytes()[1..])))
 }
 
-pub fn get_valid_address_bytes() -> Result<(String, [u8; 20]), String> {
+/// Looks up the address a name like `vitalik.eth` points to
+pub type NameResolver<'a> = &'a dyn Fn(&str) -> Result<[u8; 20], String>;
+
+/// Prompts for a recipient address, or the name of one saved in `book`. Names like `vitalik.eth`
+/// are taken if there is a `resolve` to look them up.
+pub fn get_valid_address_bytes(book: &AddressBook, resolve: Option<NameResolver>) -> Result<(String, [u8; 20]), String> {
     loop {
-        println!("Enter recipient address (or press q to cancel transaction): ");
+        let names = if resolve.is_some() { ", ENS name" } else { "" };
+        match book.is_empty() {
+            true => println!("Enter recipient address{} (or press q to cancel transaction): ", names),
+            false => println!("Enter recipient address{} or address book name (or press q to cancel transaction): ", names),
+        }
         let recipient = read_user_input();
 
-        if recipient != "q" {
-            match sanitize_address(recipient.clone()) {
-                Ok(recipient_bytes) => return Ok((recipient, recipient_bytes)),
-                Err(_e) => println!("Invalid address"),
+        if recipient == "q" {
+            return Err(String::from("User cancel"));
+        }
+        if let Some(contact) = book.find(&recipient) {
+            println!("Sending to {}: {}", contact.name, contact.address);
+            let recipient_bytes = sanitize_address(&contact.address).unwrap();
+            return Ok((contact.address.clone(), recipient_bytes));
+        }
+        if ens::is_name(&recipient) {
+            match resolve.map(|resolve| resolve(&recipient)) {
+                Some(Ok(recipient_bytes)) => {
+                    let address = String::from("0x") + &hex::encode(recipient_bytes);
+                    println!("{} resolves to {}", recipient, address);
+                    return Ok((address, recipient_bytes));
+                },
+                Some(Err(e)) => println!("{}", e),
+                None => println!("Names can't be used here, enter the 0x address"),
             }
-        } else {
-            return Err(format!("User cancel"));
+            continue;
+        }
+        match sanitize_address(&recipient) {
+            Ok(recipient_bytes) => return Ok((recipient, recipient_bytes)),
+            Err(_e) => println!("Invalid address"),
         }
     }
 }
 
-// TODO: figure out a cleaner way to do this
-fn sanitize_address(address: String) -> Result<[u8; 20], AddressParseError> {
+/// Parses a hex address, with or without the 0x prefix
+pub fn sanitize_address(address: &str) -> Result<[u8; 20], AddressParseError> {
     let raw_address = match address.strip_prefix("0x") {
         Some(r) => r,
-        None => &address,
+        None => address,
     };
 
     match hex::decode(raw_address) {
@@ -97,54 +338,129 @@ fn sanitize_address(address: String) -> Result<[u8; 20], AddressParseError> {
 
 fn vec_to_array<T, const N: usize>(v: Vec<T>) -> Result<[T; N], String> {
     v.try_into()
-        .map_err(|_e| format!("Invalid length"))
+        .map_err(|_e| String::from("Invalid length"))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn test_parse_private_key() {
+        let key = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+        let secret = parse_private_key(key).unwrap();
+        assert_eq!(*parse_private_key(&format!("0x{}", key)).unwrap(), *secret);
+        assert_eq!(secret_key_to_address(&secret).unwrap(), "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266");
+
+        assert_eq!(parse_private_key("zz").unwrap_err(), "A private key must be hex");
+        assert_eq!(parse_private_key(&key[2..]).unwrap_err(), "A private key is 32 bytes (64 hex characters)");
+        assert_eq!(parse_private_key(&"00".repeat(32)).unwrap_err(), "Not a valid secp256k1 private key");
+    }
+
+    #[test]
+    fn test_parse_mnemonic() {
+        let phrase = "test test test test test test test test test test test junk";
+        let english = Language::English;
+        let messy = format!("  {}\t", phrase.replace(' ', "  ").replace("junk", "JuNk"));
+        assert_eq!(parse_mnemonic(&messy, english).unwrap().phrase(), phrase);
+
+        let error = parse_mnemonic("test test test", english).unwrap_err();
+        assert_eq!(error, MnemonicError::WordCount(3));
+        assert_eq!(error.to_string(), "A recovery phrase has 12, 15, 18, 21 or 24 words, but this one has 3");
+
+        let typos = parse_mnemonic(&phrase.replacen("test", "tset", 1).replace("junk", "junky"), english).unwrap_err();
+        assert_eq!(typos, MnemonicError::UnknownWords(vec![(1, String::from("tset")), (12, String::from("junky"))]));
+        assert_eq!(typos.to_string(), "Not in the wordlist: word #1 (\"tset\"), word #12 (\"junky\")");
+
+        // every word is in the list but the last one no longer matches the checksum
+        assert_eq!(parse_mnemonic(&"test ".repeat(12), english).unwrap_err(), MnemonicError::Checksum);
+    }
+
     #[test]
 /*    #[test_case(1.0 => 1000000000000000000 ; "a whole number eth amount")]
     #[test_case(1.35 => 1350000000000000000 ; "a fractional eth amount")]
     #[test_case(0.00000000000000000001 => 0 ; "an eth amount smaller than 1 wei")]*/
     fn test_eth_to_wei_1() {
-        let amount = 1.0;
-        let result = eth_to_wei(amount);
+        let amount = "1.0";
+        let result = eth_to_wei(amount).unwrap();
         let expected: u128 = 1000000000000000000;
         assert_eq!(result, expected);
     }
 
     #[test]
     fn test_eth_to_wei_2() {
-        let amount = 1.35;
-        let result = eth_to_wei(amount);
+        let amount = "1.35";
+        let result = eth_to_wei(amount).unwrap();
         let expected: u128 = 1350000000000000000;
         assert_eq!(result, expected);
     }
 
     #[test]
     fn test_eth_to_wei_3() {
-        let amount = 0.00000000000000000001;
-        let result = eth_to_wei(amount);
+        let amount = "0.00000000000000000001";
+        let result = eth_to_wei(amount).unwrap();
         let expected: u128 = 0;
         assert_eq!(result, expected);
     }
 
     #[test]
     fn test_eth_to_wei_4() {
-        let amount = 0.00000000000000000099;
-        let result = eth_to_wei(amount);
+        let amount = "0.00000000000000000099";
+        let result = eth_to_wei(amount).unwrap();
         let expected: u128 = 0;
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn test_eth_to_wei_invalid() {
+        assert!(eth_to_wei("").is_err());
+        assert!(eth_to_wei(".").is_err());
+        assert!(eth_to_wei("-1").is_err());
+        assert!(eth_to_wei("1e5").is_err());
+        assert!(eth_to_wei("999999999999999999999999").is_err());
+        assert_eq!(eth_to_wei(".5").unwrap(), 500000000000000000);
+    }
+
+    #[test]
+    fn test_wei_to_eth() {
+        assert_eq!(wei_to_eth(0), "0");
+        assert_eq!(wei_to_eth(1350000000000000000), "1.35");
+        assert_eq!(wei_to_eth(1), "0.000000000000000001");
+        assert_eq!(wei_to_eth(eth_to_wei("123.456").unwrap()), "123.456");
+    }
+
+    #[test]
+    fn test_gas_shortfall() {
+        assert_eq!(gas_shortfall(1000, 900, 100), None);
+        assert_eq!(gas_shortfall(1000, 900, 101), Some(1));
+        assert_eq!(gas_shortfall(0, 0, 21000), Some(21000));
+        assert_eq!(affordable_transfers(100_000, 21000), 4);
+    }
+
+    #[test]
+    fn test_parse_and_format_time() {
+        let timestamp = parse_time("2024-01-03 09:00", 0).unwrap();
+        assert_eq!(timestamp, 1704272400);
+        assert_eq!(format_timestamp(timestamp), "2024-01-03 09:00 UTC");
+        assert_eq!(format_timestamp(parse_time("2000-02-29 23:59", 0).unwrap()), "2000-02-29 23:59 UTC");
+        assert_eq!(format_date(timestamp), "2024-01-03");
+
+        assert_eq!(parse_time("+30m", 1000).unwrap(), 2800);
+        assert_eq!(parse_time("+2h", 0).unwrap(), 7200);
+        assert!(parse_time("+2y", 0).is_err());
+        assert!(parse_time("2024-13-01 00:00", 0).is_err());
+        assert!(parse_time("tomorrow", 0).is_err());
+    }
+
     #[test]
     fn test_sanitize_address() {
         let address = String::from("0x73363901CD60Ace0Df1df46111fA999416Bb9Bd1");
-        let result = sanitize_address(address).unwrap();
+        let result = sanitize_address(&address).unwrap();
         let expected: [u8; 20] = hex::decode("73363901CD60Ace0Df1df46111fA999416Bb9Bd1").unwrap().try_into().unwrap();
         assert_eq!(result, expected);
+        assert_eq!(sanitize_address(&address[2..]), Ok(expected));
+        assert_eq!(sanitize_address("0x7336"), Err(AddressParseError::InvalidLength));
+        assert_eq!(sanitize_address("0x7336390zcd60ace0df1df46111fa999416bb9bd1"), Err(AddressParseError::InvalidHexCharacter));
     }
 
     #[test]
@@ -171,6 +487,16 @@ mod test {
     fn test_xor_failure() {
         let a = [0u8; 32];
         let b = [0u8; 31];
-        let c = xor(&a, &b).unwrap();
+        let _c = xor(&a, &b).unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_xpub_to_address() {
+        use bip39::{Mnemonic, Language, Seed};
+
+        // anvil's first default account
+        let mnemonic = Mnemonic::from_phrase(crate::fixtures::FIXTURE_MNEMONIC, Language::English).unwrap();
+        let (_, xpub) = create_keys_from_path(Seed::new(&mnemonic, "").as_bytes(), "m/44'/60'/0'/0/0");
+        assert_eq!(xpub_to_address(&xpub), "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266");
+    }
+}
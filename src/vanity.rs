@@ -0,0 +1,132 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Longest prefix and suffix together. Each extra character multiplies the search by 16, and
+/// seven already takes hours on a laptop.
+pub const MAX_PATTERN_LEN: usize = 6;
+/// How often the search reports progress
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The hex characters a vanity address must start and end with
+#[derive(Debug, PartialEq)]
+pub struct VanityPattern {
+    prefix: String,
+    suffix: String,
+}
+
+impl VanityPattern {
+    /// Parses a prefix and suffix of hex characters, either of which may be empty. A leading 0x
+    /// on the prefix is ignored. Case doesn't matter, since it isn't what is compared.
+    pub fn parse(prefix: &str, suffix: &str) -> Result<VanityPattern, String> {
+        let prefix = prefix.trim();
+        let prefix = prefix.strip_prefix("0x").unwrap_or(prefix).to_lowercase();
+        let suffix = suffix.trim().to_lowercase();
+        if !prefix.chars().chain(suffix.chars()).all(|c| c.is_ascii_hexdigit()) {
+            return Err(String::from("A vanity pattern can only use the hex characters 0-9 and a-f"));
+        }
+        match prefix.len() + suffix.len() {
+            0 => Err(String::from("Enter a prefix, a suffix or both")),
+            n if n > MAX_PATTERN_LEN => Err(format!("The prefix and suffix can be at most {} characters together", MAX_PATTERN_LEN)),
+            _ => Ok(VanityPattern { prefix, suffix }),
+        }
+    }
+
+    /// Checks a 0x-prefixed address
+    pub fn matches(&self, address: &str) -> bool {
+        let address = address.strip_prefix("0x").unwrap_or(address).to_lowercase();
+        address.starts_with(&self.prefix) && address.ends_with(&self.suffix)
+    }
+
+    /// The number of candidates a search tries on average
+    pub fn expected_attempts(&self) -> u64 {
+        16u64.pow((self.prefix.len() + self.suffix.len()) as u32)
+    }
+}
+
+/// Tries candidates on `threads` threads until one matches or `cancel` is set. `candidate` turns
+/// an attempt number into a value and its address, or None to skip the attempt. Attempt numbers
+/// start at `first` and each is handed out once, so a deterministic candidate finds the same
+/// match every time. `progress` is called with the attempts so far and the rate per second.
+pub fn search<T: Send>(
+    threads: usize,
+    first: u64,
+    pattern: &VanityPattern,
+    candidate: impl Fn(u64) -> Option<(T, String)> + Sync,
+    cancel: &AtomicBool,
+    mut progress: impl FnMut(u64, u64),
+) -> Option<(u64, T)> {
+    let next = AtomicU64::new(first);
+    let found: Mutex<Option<(u64, T)>> = Mutex::new(None);
+    let done = AtomicBool::new(false);
+    let started = Instant::now();
+
+    std::thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| {
+                while !done.load(Ordering::Relaxed) && !cancel.load(Ordering::Relaxed) {
+                    let attempt = next.fetch_add(1, Ordering::Relaxed);
+                    match candidate(attempt) {
+                        Some((value, address)) if pattern.matches(&address) => {
+                            let mut found = found.lock().unwrap();
+                            // keep the lowest match, in case two threads found one
+                            if found.as_ref().is_none_or(|(a, _)| attempt < *a) {
+                                *found = Some((attempt, value));
+                            }
+                            done.store(true, Ordering::Relaxed);
+                        },
+                        _ => {},
+                    }
+                }
+            });
+        }
+
+        let mut last_report = Instant::now();
+        while !done.load(Ordering::Relaxed) && !cancel.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(50));
+            if last_report.elapsed() >= PROGRESS_INTERVAL {
+                let attempts = next.load(Ordering::Relaxed) - first;
+                let secs = started.elapsed().as_secs().max(1);
+                progress(attempts, attempts / secs);
+                last_report = Instant::now();
+            }
+        }
+    });
+    found.into_inner().unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pattern() {
+        let pattern = VanityPattern::parse("0xBEEF", "").unwrap();
+        assert!(pattern.matches("0xbeEF00000000000000000000000000000000cafe"));
+        assert!(!pattern.matches("0x00ef00000000000000000000000000000000beef"));
+        assert_eq!(pattern.expected_attempts(), 65536);
+
+        let both = VanityPattern::parse("0", "Af").unwrap();
+        assert!(both.matches("0x0000000000000000000000000000000000000aaf"));
+        assert!(!both.matches("0x1000000000000000000000000000000000000aaf"));
+
+        assert_eq!(VanityPattern::parse("", " ").unwrap_err(), "Enter a prefix, a suffix or both");
+        assert_eq!(VanityPattern::parse("dead", "beef").unwrap_err(), "The prefix and suffix can be at most 6 characters together");
+        assert_eq!(VanityPattern::parse("xyz", "").unwrap_err(), "A vanity pattern can only use the hex characters 0-9 and a-f");
+    }
+
+    #[test]
+    fn test_search() {
+        let pattern = VanityPattern::parse("", "7").unwrap();
+        let address = |n: u64| Some((n, format!("0x{:040x}", n)));
+        // every thread finishes its attempt, and the lowest match wins
+        assert_eq!(search(4, 10, &pattern, address, &AtomicBool::new(false), |_, _| {}), Some((23, 23)));
+
+        // skipped attempts never match
+        let skip_low = |n: u64| if n < 30 { None } else { address(n) };
+        assert_eq!(search(2, 0, &pattern, skip_low, &AtomicBool::new(false), |_, _| {}), Some((39, 39)));
+
+        let never = VanityPattern::parse("fffff", "").unwrap();
+        assert_eq!(search(2, 0, &never, address, &AtomicBool::new(true), |_, _| {}), None);
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::HashSet;
+
+use crate::crypto::checksum_address;
+use crate::labels::normalize_address;
+
+/// What a watch-only address file would add, with 1-based line numbers for the report
+#[derive(Debug, Default, PartialEq)]
+pub struct WatchImportPlan {
+    /// (checksummed address, label) for every new address
+    pub added: Vec<(String, Option<String>)>,
+    /// (line, address) for addresses already in the wallet or earlier in the file
+    pub duplicates: Vec<(usize, String)>,
+    /// (line, text) for lines that aren't a valid address
+    pub invalid: Vec<(usize, String)>,
+}
+
+/// Parses a newline-separated list of addresses, each optionally followed by `,label`. Blank lines
+/// and lines starting with `#` are ignored. `existing` holds the addresses already in the wallet.
+pub fn plan_watch_import(text: &str, existing: &[String]) -> WatchImportPlan {
+    let mut plan = WatchImportPlan::default();
+    let mut seen: HashSet<String> = existing.iter().filter_map(|a| normalize_address(a)).collect();
+
+    for (number, line) in text.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (address, label) = match line.split_once(',') {
+            Some((address, label)) => (address.trim(), Some(label.trim()).filter(|l| !l.is_empty())),
+            None => (line, None),
+        };
+
+        let normalized = match normalize_address(address) {
+            Some(a) => a,
+            None => {
+                plan.invalid.push((number, String::from(line)));
+                continue;
+            },
+        };
+        if !seen.insert(normalized.clone()) {
+            plan.duplicates.push((number, String::from(address)));
+            continue;
+        }
+
+        let bytes: [u8; 20] = hex::decode(&normalized[2..]).unwrap().try_into().unwrap();
+        plan.added.push((checksum_address(&bytes), label.map(String::from)));
+    }
+    plan
+}
+
+/// Parses one address to watch and returns it checksummed, unless it is invalid or already among
+/// `existing`
+pub fn parse_watch_address(input: &str, existing: &[String]) -> Result<String, String> {
+    let address = input.trim();
+    let normalized = normalize_address(address).ok_or_else(|| format!("{} is not a valid address or its checksum is wrong", address))?;
+    if existing.iter().any(|a| normalize_address(a).as_ref() == Some(&normalized)) {
+        return Err(format!("{} is already in the wallet", address));
+    }
+    let bytes: [u8; 20] = hex::decode(&normalized[2..]).unwrap().try_into().unwrap();
+    Ok(checksum_address(&bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_plan_watch_import() {
+        let text = "\
+# cold storage
+0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed
+0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359, Exchange
+
+0x5AAEB6053F3E94C9B9A09F33669435E7EF1BEAED,again
+0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266
+0xfb6916095CA1df60bB79Ce92cE3Ea74c37c5d359
+not an address,label
+";
+        let existing = [String::from("0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266")];
+        let plan = plan_watch_import(text, &existing);
+
+        assert_eq!(plan.added, [
+            (String::from("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"), None),
+            (String::from("0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359"), Some(String::from("Exchange"))),
+        ]);
+        assert_eq!(plan.duplicates, [
+            (5, String::from("0x5AAEB6053F3E94C9B9A09F33669435E7EF1BEAED")),
+            (6, String::from("0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266")),
+        ]);
+        // a broken checksum is invalid, not a duplicate
+        assert_eq!(plan.invalid, [
+            (7, String::from("0xfb6916095CA1df60bB79Ce92cE3Ea74c37c5d359")),
+            (8, String::from("not an address,label")),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_watch_address() {
+        let existing = [String::from("0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266")];
+        assert_eq!(parse_watch_address(" 0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed ", &existing).unwrap(), "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed");
+        assert_eq!(parse_watch_address("0xF39FD6E51AAD88F6F4CE6AB8827279CFFFB92266", &existing).unwrap_err(),
+            "0xF39FD6E51AAD88F6F4CE6AB8827279CFFFB92266 is already in the wallet");
+        assert_eq!(parse_watch_address("0xfb6916095CA1df60bB79Ce92cE3Ea74c37c5d359", &existing).unwrap_err(),
+            "0xfb6916095CA1df60bB79Ce92cE3Ea74c37c5d359 is not a valid address or its checksum is wrong");
+    }
+}
@@ -0,0 +1,136 @@
+// End-to-end test against a local node. Run with `cargo test --features integration-tests`;
+// it spawns `anvil` from PATH unless `ANVIL_URL` points at a fresh node started with
+// `--chain-id 31337 --mnemonic "test test test test test test test test test test test junk"`.
+#![cfg(feature = "integration-tests")]
+
+use std::net::TcpListener;
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::Duration;
+
+use basic_eth::fixtures::{FIXTURE_MNEMONIC, FIXTURE_PASSWORD};
+use basic_eth::rpc::HttpRpc;
+use basic_eth::storage::Wallet;
+use basic_eth::utils::WEI_PER_ETH;
+use bip39::{Mnemonic, Language};
+use serde_json::Value;
+
+/// Anvil's default, which is too big for a u8 like the chain ids the wallet used to sign with
+const CHAIN_ID: u64 = 31337;
+
+/// Kills the spawned node when the test ends, pass or fail
+struct Node {
+    url: String,
+    process: Option<Child>,
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        if let Some(process) = &mut self.process {
+            let _ = process.kill();
+        }
+    }
+}
+
+fn start_node() -> Node {
+    if let Ok(url) = std::env::var("ANVIL_URL") {
+        return Node { url, process: None };
+    }
+
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let process = Command::new("anvil")
+        .args(["--port", &port.to_string(), "--chain-id", &CHAIN_ID.to_string(), "--mnemonic", FIXTURE_MNEMONIC])
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("anvil must be installed, or ANVIL_URL set");
+    let node = Node { url: format!("http://127.0.0.1:{}", port), process: Some(process) };
+
+    for _ in 0..50 {
+        if ureq::post(&node.url).send_json(ureq::json!({ "jsonrpc": "2.0", "id": 1, "method": "eth_chainId", "params": [] })).is_ok() {
+            return node;
+        }
+        sleep(Duration::from_millis(100));
+    }
+    panic!("anvil did not start");
+}
+
+fn rpc(node: &Node, method: &str, params: Value) -> Value {
+    let resp: Value = ureq::post(&node.url)
+        .send_json(ureq::json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }))
+        .unwrap()
+        .into_json()
+        .unwrap();
+    assert!(resp["error"].is_null(), "{} failed: {}", method, resp["error"]);
+    resp["result"].clone()
+}
+
+fn hex_quantity(value: &Value) -> u128 {
+    u128::from_str_radix(value.as_str().unwrap().trim_start_matches("0x"), 16).unwrap()
+}
+
+fn balance(node: &Node, address: &str) -> u128 {
+    hex_quantity(&rpc(node, "eth_getBalance", ureq::json!([address, "latest"])))
+}
+
+fn transaction_count(node: &Node, address: &str) -> u64 {
+    hex_quantity(&rpc(node, "eth_getTransactionCount", ureq::json!([address, "latest"]))) as u64
+}
+
+fn wait_for_receipt(node: &Node, hash: &str) -> Value {
+    for _ in 0..50 {
+        let receipt = rpc(node, "eth_getTransactionReceipt", ureq::json!([hash]));
+        if !receipt.is_null() {
+            return receipt;
+        }
+        sleep(Duration::from_millis(100));
+    }
+    panic!("transaction {} was never mined", hash);
+}
+
+#[test]
+fn test_create_fund_send_confirm() {
+    let node = start_node();
+    assert_eq!(hex_quantity(&rpc(&node, "eth_chainId", ureq::json!([]))), CHAIN_ID as u128);
+
+    // the wallet reads its node and stores its file relative to the working directory
+    let dir = std::env::temp_dir().join(format!("basic-eth-anvil-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::env::set_current_dir(&dir).unwrap();
+    std::fs::write("settings.json", ureq::json!({ "network": { "rpc_url": node.url, "chain_id": CHAIN_ID } }).to_string()).unwrap();
+
+    // create: the fixed mnemonic derives anvil's funded first account
+    let mnemonic = Mnemonic::from_phrase(FIXTURE_MNEMONIC, Language::English).unwrap();
+    let mut wallet = Wallet::from(String::from(FIXTURE_PASSWORD), mnemonic, "");
+    wallet.set_location("anvil", String::from("userdata.txt"));
+    let sender = wallet.addresses()[0].clone();
+    let funded = rpc(&node, "eth_accounts", ureq::json!([]));
+    assert_eq!(funded[0].as_str().unwrap().to_lowercase(), sender);
+    let recipient = wallet.add_account();
+
+    let (sender_before, recipient_before) = (balance(&node, &sender), balance(&node, &recipient));
+    let (sender_nonce, recipient_nonce) = (transaction_count(&node, &sender), transaction_count(&node, &recipient));
+    // the wallet doesn't sync nonces from the chain yet
+    assert_eq!(sender_nonce, 0, "ANVIL_URL must point at a fresh node");
+
+    // send and confirm
+    let hash = wallet.send(&HttpRpc::active(), 0, &recipient, WEI_PER_ETH).unwrap();
+    let receipt = wait_for_receipt(&node, &hash);
+    assert_eq!(receipt["status"], "0x1");
+
+    let fee = hex_quantity(&receipt["gasUsed"]) * hex_quantity(&receipt["effectiveGasPrice"]);
+    assert_eq!(balance(&node, &sender), sender_before - WEI_PER_ETH - fee);
+    assert_eq!(balance(&node, &recipient), recipient_before + WEI_PER_ETH);
+    assert_eq!(transaction_count(&node, &sender), sender_nonce + 1);
+    assert_eq!(transaction_count(&node, &recipient), recipient_nonce);
+    assert_eq!(wallet.nonce(0), Some(sender_nonce + 1));
+
+    // restart from the persisted file
+    wallet.store().unwrap();
+    let mut restored = Wallet::load("userdata.txt").unwrap();
+    restored.verify_password(String::from(FIXTURE_PASSWORD)).unwrap();
+    assert_eq!(restored.addresses(), [sender.clone(), recipient.clone()]);
+    assert_eq!(restored.nonce(0), Some(sender_nonce + 1));
+    assert_eq!(restored.nonce(1), Some(0));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
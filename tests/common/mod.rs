@@ -0,0 +1,27 @@
+use basic_eth::{data_dir, migrations};
+use basic_eth::fixtures::{self, FixtureOptions};
+use basic_eth::storage::Wallet;
+
+/// Points the wallet at a temporary data directory, so the audit log and anything else the
+/// tests write stay out of the crate root
+pub fn use_temp_data_dir() {
+    let dir = std::env::temp_dir().join(format!("basic-eth-tests-{}", std::process::id()));
+    data_dir::init(dir.to_str()).unwrap();
+}
+
+/// Returns the on-disk JSON of a fixture wallet
+pub fn fixture_json(seed: u64, accounts: usize) -> String {
+    versioned_fixture_json(seed, accounts, migrations::CURRENT_VERSION)
+}
+
+/// Returns the JSON of a fixture wallet as the app wrote it in file version `version`
+pub fn versioned_fixture_json(seed: u64, accounts: usize, version: u32) -> String {
+    use_temp_data_dir();
+    fixtures::generate_json(&FixtureOptions { seed, accounts, version })
+}
+
+/// Loads a wallet from its on-disk JSON, the way login does
+pub fn load_wallet(json: &str) -> Wallet {
+    use_temp_data_dir();
+    serde_json::from_str(json).unwrap()
+}
@@ -0,0 +1,36 @@
+mod common;
+
+use basic_eth::fixtures::{FIXTURE_PASSWORD, FIXTURE_VERSIONS};
+use basic_eth::migrations;
+use basic_eth::storage::UnlockError;
+
+#[test]
+fn test_fixture_json_is_byte_identical() {
+    assert_eq!(common::fixture_json(7, 5), common::fixture_json(7, 5));
+}
+
+#[test]
+fn test_fixture_unlocks_with_fixture_password() {
+    let mut wallet = common::load_wallet(&common::fixture_json(7, 5));
+    assert_eq!(wallet.verify_password(String::from("wrong password")), Err(UnlockError::IncorrectPassword));
+    wallet.verify_password(String::from(FIXTURE_PASSWORD)).unwrap();
+}
+
+#[test]
+fn test_fixture_survives_reserialization() {
+    let json = common::fixture_json(1, 3);
+    let mut wallet = common::load_wallet(&json);
+    assert_eq!(String::from_utf8(wallet.to_bytes()).unwrap(), json);
+}
+
+#[test]
+fn test_every_version_unlocks() {
+    for version in FIXTURE_VERSIONS {
+        let json = common::versioned_fixture_json(2, 3, version);
+        assert_eq!(json, common::versioned_fixture_json(2, 3, version));
+        assert_eq!(migrations::version(json.as_bytes()), Some(version));
+        let mut wallet = common::load_wallet(&json);
+        wallet.verify_password(String::from(FIXTURE_PASSWORD)).unwrap();
+        assert_eq!(wallet.addresses().len(), 3);
+    }
+}